@@ -0,0 +1,604 @@
+//! Pseudonymisation of compose files, so that models of them can be shared outside the
+//! organisation without its service names, hostnames, registries, paths or configuration
+//! values. Ports and the relationships between services are kept, and each pseudonym is
+//! recorded in a map from which answers given in terms of pseudonyms can be translated back.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use serde::{Deserialize, Serialize};
+use crate::{
+    parse_compose::{Compose, DependsOn, RuntimeExtras, Service},
+    parse_dockerfile::CommandLine,
+    util::{parse_endpoint, parse_volume_spec, sha256_hex, VolumeSource},
+};
+
+/// Directories at the root of a filesystem whose names say nothing about the system they're
+/// in, which are kept when masking an absolute path
+const STANDARD_DIRECTORIES: &[&str] = &["bin", "data", "dev", "etc", "home", "lib", "mnt", "opt", "root", "run", "srv", "tmp", "usr", "var"];
+
+/// Namespaces of reverse-DNS label keys which are public conventions, rather than naming
+/// the organisation which wrote them, and are kept
+const PUBLIC_LABEL_NAMESPACES: &[&str] = &["com.docker.", "org.opencontainers.", "org.label-schema."];
+
+/// The kinds of identifier pseudonymised, each numbered separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Service,
+    Network,
+    Container,
+    Host,
+    Registry,
+    Image,
+    Volume,
+    Secret,
+    Label,
+    Path,
+    Value,
+}
+
+impl Kind {
+    /// The pseudonym of the nth identifier of the kind, counting from 1, such as `svc-01`
+    /// or `registry-a`
+    fn pseudonym(self, n: usize) -> String {
+        let prefix = match self {
+            Kind::Service => "svc",
+            Kind::Network => "net",
+            Kind::Container => "ctr",
+            Kind::Host => "host",
+            Kind::Registry => return format!("registry-{}", letters(n)),
+            Kind::Image => "image",
+            Kind::Volume => "vol",
+            Kind::Secret => "secret",
+            Kind::Label => "label",
+            Kind::Path => "path",
+            Kind::Value => "value",
+        };
+        format!("{}-{:02}", prefix, n)
+    }
+}
+
+/// A number counting from 1 in letters, as spreadsheet columns are: a to z, then aa, ab, ...
+fn letters(mut n: usize) -> String {
+    let mut letters = Vec::new();
+    while n > 0 {
+        n -= 1;
+        letters.push(b'a' + (n % 26) as u8);
+        n /= 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).unwrap_or_default()
+}
+
+/// The originals of the pseudonyms given by `Compose::anonymize`, by kind and then
+/// pseudonym, with the seed which ordered them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnonymizationMap {
+    pub seed: String,
+    pub services: BTreeMap<String, String>,
+    pub networks: BTreeMap<String, String>,
+    pub containers: BTreeMap<String, String>,
+    pub hosts: BTreeMap<String, String>,
+    pub registries: BTreeMap<String, String>,
+    pub images: BTreeMap<String, String>,
+    pub volumes: BTreeMap<String, String>,
+    pub secrets: BTreeMap<String, String>,
+    /// Label keys in reverse-DNS notation, such as `com.example.team`
+    pub labels: BTreeMap<String, String>,
+    /// The masked parts of paths, which are those after a standard root directory
+    pub paths: BTreeMap<String, String>,
+    /// Environment, label and build argument values, commands and other free text
+    pub values: BTreeMap<String, String>,
+}
+
+impl AnonymizationMap {
+    fn table(&self, kind: Kind) -> &BTreeMap<String, String> {
+        match kind {
+            Kind::Service => &self.services,
+            Kind::Network => &self.networks,
+            Kind::Container => &self.containers,
+            Kind::Host => &self.hosts,
+            Kind::Registry => &self.registries,
+            Kind::Image => &self.images,
+            Kind::Volume => &self.volumes,
+            Kind::Secret => &self.secrets,
+            Kind::Label => &self.labels,
+            Kind::Path => &self.paths,
+            Kind::Value => &self.values,
+        }
+    }
+
+    fn table_mut(&mut self, kind: Kind) -> &mut BTreeMap<String, String> {
+        match kind {
+            Kind::Service => &mut self.services,
+            Kind::Network => &mut self.networks,
+            Kind::Container => &mut self.containers,
+            Kind::Host => &mut self.hosts,
+            Kind::Registry => &mut self.registries,
+            Kind::Image => &mut self.images,
+            Kind::Volume => &mut self.volumes,
+            Kind::Secret => &mut self.secrets,
+            Kind::Label => &mut self.labels,
+            Kind::Path => &mut self.paths,
+            Kind::Value => &mut self.values,
+        }
+    }
+
+    fn tables(&self) -> impl Iterator<Item = &BTreeMap<String, String>> {
+        [Kind::Service, Kind::Network, Kind::Container, Kind::Host, Kind::Registry, Kind::Image, Kind::Volume, Kind::Secret, Kind::Label, Kind::Path, Kind::Value]
+            .into_iter()
+            .map(|kind| self.table(kind))
+    }
+
+    /// The original of a pseudonym of any kind
+    pub fn original(&self, pseudonym: &str) -> Option<&str> {
+        self.tables().find_map(|table| table.get(pseudonym)).map(String::as_str)
+    }
+
+    /// Replace the pseudonyms in some text, such as a model generated from the anonymized
+    /// file or a reply about it, with their originals. Pseudonyms are only replaced whole,
+    /// so `svc-01` isn't replaced within `svc-012`, but may run into other text, as
+    /// `net-01` does in the SysML name `net-01Network`.
+    pub fn deanonymize(&self, text: &str) -> String {
+        let boundary = |c: Option<char>| c.is_none_or(|c| !(c.is_ascii_alphanumeric() || c == '-'));
+        let ends = |pseudonym: &str, next: Option<char>| match (pseudonym.chars().last(), next) {
+            (Some(last), Some(next)) if last.is_ascii_digit() => !next.is_ascii_digit(),
+            (_, Some(next)) => !next.is_ascii_lowercase(),
+            _ => true,
+        };
+        let mut pseudonyms: Vec<(&String, &String)> = self.tables().flatten().collect();
+        pseudonyms.sort_by_key(|(pseudonym, _)| std::cmp::Reverse(pseudonym.len()));
+        let mut deanonymized = String::new();
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let start = boundary(text[..text.len() - rest.len()].chars().last());
+            let found = pseudonyms.iter().find(|(pseudonym, _)| {
+                start && rest.starts_with(pseudonym.as_str()) && ends(pseudonym, rest[pseudonym.len()..].chars().next())
+            });
+            match found {
+                Some((pseudonym, original)) => {
+                    deanonymized.push_str(original);
+                    rest = &rest[pseudonym.len()..];
+                }
+                None => {
+                    deanonymized.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        deanonymized
+    }
+}
+
+/// Gives each identifier a pseudonym the first time it's met, and the same one after
+struct Anonymizer {
+    map: AnonymizationMap,
+    pseudonyms: HashMap<(Kind, String), String>,
+}
+
+impl Anonymizer {
+    fn new(seed: &str) -> Self {
+        Anonymizer { map: AnonymizationMap { seed: seed.to_string(), ..Default::default() }, pseudonyms: HashMap::new() }
+    }
+
+    fn pseudonym(&mut self, kind: Kind, original: &str) -> String {
+        if let Some(pseudonym) = self.pseudonyms.get(&(kind, original.to_string())) {
+            return pseudonym.clone();
+        }
+        let table = self.map.table_mut(kind);
+        let pseudonym = kind.pseudonym(table.len() + 1);
+        table.insert(pseudonym.clone(), original.to_string());
+        self.pseudonyms.insert((kind, original.to_string()), pseudonym.clone());
+        pseudonym
+    }
+
+    /// Names in an order set by the seed, so that pseudonyms don't give away the
+    /// alphabetical order of the originals, yet are the same every time for the same seed
+    fn seeded<'a>(&self, names: impl IntoIterator<Item = &'a String>) -> Vec<&'a String> {
+        let mut names: Vec<&String> = names.into_iter().collect::<BTreeSet<_>>().into_iter().collect();
+        names.sort_by_cached_key(|name| sha256_hex(format!("{}\0{}", self.map.seed, name).as_bytes()));
+        names
+    }
+
+    /// The pseudonym of a network. The `default` network compose creates is kept, as it
+    /// names nothing and is what services without networks are attached to.
+    fn network(&mut self, name: &str) -> String {
+        match name {
+            "default" => name.to_string(),
+            _ => self.pseudonym(Kind::Network, name),
+        }
+    }
+
+    /// The pseudonym of a name other containers may reach a service by: the service's own,
+    /// its container's, or that of a hostname or alias
+    fn host(&mut self, host: &str) -> String {
+        [Kind::Service, Kind::Container]
+            .into_iter()
+            .find_map(|kind| self.pseudonyms.get(&(kind, host.to_string())).cloned())
+            .unwrap_or_else(|| self.pseudonym(Kind::Host, host))
+    }
+
+    fn is_known_host(&self, host: &str) -> bool {
+        [Kind::Service, Kind::Container, Kind::Host].into_iter().any(|kind| self.pseudonyms.contains_key(&(kind, host.to_string())))
+    }
+
+    /// An image reference with its registry and repository pseudonymised, keeping its tag
+    /// or digest, such as `registry-a/image-01:1.2` for `registry.example.com/shop/web:1.2`
+    fn image(&mut self, image: &str) -> String {
+        let (name, digest) = match image.split_once('@') {
+            Some((name, digest)) => (name, Some(digest)),
+            None => (image, None),
+        };
+        let (name, tag) = match name.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => (repository, Some(tag)),
+            _ => (name, None),
+        };
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, repository)) if host.contains(['.', ':']) || host == "localhost" => (Some(host), repository),
+            _ => (None, name),
+        };
+        let mut anonymized = registry.map(|registry| format!("{}/", self.pseudonym(Kind::Registry, registry))).unwrap_or_default();
+        anonymized.push_str(&self.pseudonym(Kind::Image, repository));
+        anonymized.extend(tag.map(|tag| format!(":{}", tag)));
+        anonymized.extend(digest.map(|digest| format!("@{}", digest)));
+        anonymized
+    }
+
+    /// A path with all but its anchor and any standard root directory masked, such as
+    /// `/usr/path-01` for `/usr/share/app/config.yml` or `./path-02` for `./config`
+    fn path(&mut self, path: &str) -> String {
+        if matches!(path, "" | "." | ".." | "/" | "~") {
+            return path.to_string();
+        }
+        let anchor = ["./", "../", "~/", "/"].into_iter().find(|anchor| path.starts_with(anchor)).unwrap_or("");
+        let rest = &path[anchor.len()..];
+        let (rest, trailing) = match rest.strip_suffix('/') {
+            Some(rest) => (rest, "/"),
+            None => (rest, ""),
+        };
+        let (kept, masked) = match rest.split_once('/') {
+            Some((root, masked)) if anchor == "/" && STANDARD_DIRECTORIES.contains(&root) => (format!("{}/", root), masked),
+            _ => (String::new(), rest),
+        };
+        if masked.is_empty() {
+            return path.to_string();
+        }
+        format!("{}{}{}{}", anchor, kept, self.pseudonym(Kind::Path, masked), trailing)
+    }
+
+    /// A short-syntax volume with its source and target masked, keeping its mode. Volumes
+    /// which don't parse are masked whole.
+    fn volume(&mut self, volume: &str) -> String {
+        let Ok(spec) = parse_volume_spec(volume) else {
+            return self.pseudonym(Kind::Value, volume);
+        };
+        let source = match &spec.source {
+            VolumeSource::Anonymous => None,
+            VolumeSource::Named(name) => Some(self.pseudonym(Kind::Volume, name)),
+            // Bind sources are normalised without a leading ./, which they need to stay binds
+            VolumeSource::Bind(path) => Some(self.path(path)).map(|path| if path.starts_with(['/', '.', '~']) { path } else { format!("./{}", path) }),
+        };
+        let target = format!("{}{}", self.path(&spec.target), if spec.directory_target { "/" } else { "" });
+        let mode: Vec<&str> = spec.read_only.then_some("ro").into_iter().chain(spec.options.iter().map(String::as_str)).collect();
+        let fields: Vec<String> = source.into_iter().chain([target]).chain((!mode.is_empty()).then(|| mode.join(","))).collect();
+        fields.join(":")
+    }
+
+    /// A configuration value, each of whose comma or space separated parts is either a
+    /// reference to an endpoint, kept as one with its host pseudonymised, a name of a
+    /// service, or masked. Endpoints keep their scheme and port, so flows can still be
+    /// inferred from them, but lose any credentials and path.
+    fn value(&mut self, value: &str) -> String {
+        let mut anonymized = String::new();
+        for part in value.split_inclusive([',', ' ']) {
+            let token = part.trim_end_matches([',', ' ']);
+            let anonymized_token = match parse_endpoint(token) {
+                _ if token.is_empty() => String::new(),
+                Some(endpoint) => {
+                    let scheme = endpoint.scheme.map(|scheme| format!("{}://", scheme)).unwrap_or_default();
+                    let port = endpoint.port.map(|port| format!(":{}", port)).unwrap_or_default();
+                    format!("{}{}{}", scheme, self.host(endpoint.host), port)
+                }
+                None if self.is_known_host(token) => self.host(token),
+                None => self.pseudonym(Kind::Value, token),
+            };
+            anonymized.push_str(&anonymized_token);
+            anonymized.push_str(&part[token.len()..]);
+        }
+        anonymized
+    }
+
+    /// A command masked argument by argument, or whole when run by the shell
+    fn command(&mut self, command: &CommandLine) -> CommandLine {
+        match command {
+            CommandLine::Shell(shell) => CommandLine::Shell(self.pseudonym(Kind::Value, shell)),
+            CommandLine::Exec(arguments) => CommandLine::Exec(arguments.iter().map(|argument| self.pseudonym(Kind::Value, argument)).collect()),
+        }
+    }
+
+    /// A reference to another service's or container's namespace, as `network_mode`, `pid`
+    /// and `ipc` may be, such as `service:db`
+    fn namespace(&mut self, namespace: &str) -> String {
+        match namespace.split_once(':') {
+            Some(("service", name)) => format!("service:{}", self.pseudonym(Kind::Service, name)),
+            Some(("container", name)) => format!("container:{}", self.host(name)),
+            _ => namespace.to_string(),
+        }
+    }
+
+    /// Mask the values of a map, such as a service's environment, keeping their keys
+    fn values(&mut self, values: &mut HashMap<String, String>) {
+        let mut keys: Vec<String> = values.keys().cloned().collect();
+        keys.sort();
+        for key in keys {
+            let value = self.value(&values[&key]);
+            values.insert(key, value);
+        }
+    }
+
+    /// Mask the keys and values of labels. Keys in reverse-DNS notation name whoever wrote
+    /// them, so are given pseudonyms unless in a public namespace, while others are kept.
+    fn labels(&mut self, labels: &mut HashMap<String, String>) {
+        self.values(labels);
+        let mut keys: Vec<String> = labels.keys().cloned().collect();
+        keys.sort();
+        for key in keys {
+            if key.contains('.') && !PUBLIC_LABEL_NAMESPACES.iter().any(|namespace| key.starts_with(namespace)) {
+                let value = labels.remove(&key).unwrap_or_default();
+                labels.insert(self.pseudonym(Kind::Label, &key), value);
+            }
+        }
+    }
+
+    /// Mask runtime tuning values which may name the host's devices or groups. CPU
+    /// numbers, limits and the modes Docker defines are kept.
+    fn runtime_extras(&mut self, extras: &mut RuntimeExtras) {
+        if let Some(blkio_config) = &mut extras.blkio_config {
+            self.yaml_values(blkio_config);
+        }
+        extras.group_add = extras.group_add.as_ref().map(|groups| {
+            groups
+                .iter()
+                .map(|group| match group.parse::<u32>() {
+                    Ok(_) => group.clone(),
+                    Err(_) => self.pseudonym(Kind::Value, group),
+                })
+                .collect()
+        });
+    }
+
+    /// Mask the strings within a value kept as written, keeping its structure and numbers.
+    /// Those under a `path` key, as devices are given, are masked as paths.
+    fn yaml_values(&mut self, value: &mut serde_yaml::Value) {
+        match value {
+            serde_yaml::Value::String(string) => *string = self.value(string),
+            serde_yaml::Value::Sequence(items) => items.iter_mut().for_each(|item| self.yaml_values(item)),
+            serde_yaml::Value::Mapping(mapping) => {
+                for (key, value) in mapping.iter_mut() {
+                    match (key.as_str(), value) {
+                        (Some("path"), serde_yaml::Value::String(path)) => *path = self.path(path),
+                        (_, value) => self.yaml_values(value),
+                    }
+                }
+            }
+            serde_yaml::Value::Tagged(tagged) => self.yaml_values(&mut tagged.value),
+            serde_yaml::Value::Null | serde_yaml::Value::Bool(_) | serde_yaml::Value::Number(_) => (),
+        }
+    }
+
+    fn service(&mut self, service: &mut Service) {
+        service.image = service.image.as_deref().map(|image| self.image(image));
+        if let Some(build) = &mut service.build {
+            build.context = build.context.as_deref().map(|context| self.path(context));
+            build.dockerfile = build.dockerfile.as_deref().map(|dockerfile| self.path(dockerfile));
+            build.args.iter_mut().for_each(|args| self.values(args));
+        }
+        service.container_name = service.container_name.as_deref().map(|name| self.host(name));
+        service.hostname = service.hostname.as_deref().map(|hostname| self.host(hostname));
+        service.command = service.command.as_ref().map(|command| self.command(command));
+        service.entrypoint = service.entrypoint.as_ref().map(|entrypoint| self.command(entrypoint));
+        service.env_file = service.env_file.as_ref().map(|env_files| env_files.iter().map(|env_file| self.path(env_file)).collect());
+        if let Some(logging) = &mut service.logging {
+            logging.options.iter_mut().for_each(|options| self.values(options));
+        }
+        if let Some(mut networks) = service.networks.take() {
+            let names: Vec<String> = self.seeded(networks.keys()).into_iter().cloned().collect();
+            let mut renamed = HashMap::new();
+            for name in names {
+                let Some(mut options) = networks.remove(&name) else { continue };
+                for aliases in options.iter_mut().flat_map(|options| options.aliases.as_mut()) {
+                    *aliases = aliases.iter().map(|alias| self.host(alias)).collect();
+                }
+                renamed.insert(self.network(&name), options);
+            }
+            service.networks = Some(renamed);
+        }
+        service.volumes = service.volumes.as_ref().map(|volumes| volumes.iter().map(|volume| self.volume(volume)).collect());
+        service.tmpfs = service.tmpfs.as_ref().map(|tmpfs| tmpfs.iter().map(|path| self.path(path)).collect());
+        service.depends_on = match service.depends_on.take() {
+            Some(DependsOn::List(names)) => Some(DependsOn::List(names.iter().map(|name| self.pseudonym(Kind::Service, name)).collect())),
+            Some(DependsOn::Map(conditions)) => {
+                Some(DependsOn::Map(conditions.into_iter().map(|(name, condition)| (self.pseudonym(Kind::Service, &name), condition)).collect()))
+            }
+            None => None,
+        };
+        service.environment.iter_mut().for_each(|environment| self.values(environment));
+        service.labels.iter_mut().for_each(|labels| self.labels(labels));
+        service.extra_hosts = service.extra_hosts.as_ref().map(|hosts| {
+            hosts
+                .iter()
+                .map(|entry| match entry.split_once([':', '=']) {
+                    Some((host, address)) => format!("{}{}{}", self.host(host), &entry[host.len()..host.len() + 1], address),
+                    None => self.pseudonym(Kind::Value, entry),
+                })
+                .collect()
+        });
+        if let Some(healthcheck) = &mut service.healthcheck {
+            healthcheck.test = healthcheck
+                .test
+                .iter()
+                .enumerate()
+                .map(|(index, argument)| match argument.as_str() {
+                    "CMD" | "CMD-SHELL" | "NONE" if index == 0 => argument.clone(),
+                    _ => self.pseudonym(Kind::Value, argument),
+                })
+                .collect();
+        }
+        service.working_dir = service.working_dir.as_deref().map(|working_dir| self.path(working_dir));
+        service.network_mode = service.network_mode.as_deref().map(|mode| self.namespace(mode));
+        service.pid = service.pid.as_deref().map(|pid| self.namespace(pid));
+        service.ipc = service.ipc.as_deref().map(|ipc| self.namespace(ipc));
+        for rule in service.develop.iter_mut().flat_map(|develop| develop.watch.iter_mut().flatten()) {
+            rule.path = self.path(&rule.path);
+            rule.target = rule.target.as_deref().map(|target| self.path(target));
+            rule.ignore = rule.ignore.as_ref().map(|ignore| ignore.iter().map(|pattern| self.pseudonym(Kind::Value, pattern)).collect());
+        }
+        for secret in service.secrets.iter_mut().flatten() {
+            secret.source = self.pseudonym(Kind::Secret, &secret.source);
+            secret.target = secret.target.as_deref().map(|target| match target.starts_with('/') {
+                true => self.path(target),
+                false => self.pseudonym(Kind::Secret, target),
+            });
+        }
+        service.runtime_extras.iter_mut().for_each(|extras| self.runtime_extras(extras));
+        service.derived_from = service.derived_from.as_deref().map(|name| self.pseudonym(Kind::Service, name));
+        service.extensions.clear();
+    }
+}
+
+impl Compose {
+    /// Pseudonymise the compose file for sharing: services become `svc-01` and so on,
+    /// networks `net-01`, container names `ctr-01`, other hostnames `host-01`, registries
+    /// `registry-a`, repositories `image-01` and reverse-DNS label keys `label-01`. Paths are masked after any standard root
+    /// directory, and configuration values and commands are masked whole, except for
+    /// references to endpoints. Ports, tags and the relationships between services are kept.
+    ///
+    /// Pseudonyms are numbered in an order set by the seed, so the same file and seed
+    /// always give the same pseudonyms. Extension fields, and the record of the document
+    /// and files parsed, are dropped.
+    pub fn anonymize(&mut self, seed: &str) -> AnonymizationMap {
+        let mut anonymizer = Anonymizer::new(seed);
+        // Names other services may refer to are given pseudonyms first, so that references
+        // to them are recognised wherever they're met
+        let services = anonymizer.seeded(self.services.keys());
+        for name in &services {
+            anonymizer.pseudonym(Kind::Service, name);
+        }
+        let network_names: Vec<&String> = self.networks.iter().flat_map(|networks| networks.keys()).collect();
+        for name in anonymizer.seeded(network_names) {
+            anonymizer.network(name);
+        }
+        for name in &services {
+            let service = &self.services[*name];
+            if let Some(container_name) = &service.container_name {
+                anonymizer.pseudonym(Kind::Container, container_name);
+            }
+            let mut hosts: Vec<&String> = service.hostname.iter().collect();
+            hosts.extend(service.networks.iter().flatten().filter_map(|(_, options)| options.as_ref()).flat_map(|options| options.aliases.iter().flatten()));
+            for host in anonymizer.seeded(hosts) {
+                anonymizer.host(host);
+            }
+        }
+
+        let services: Vec<String> = services.into_iter().cloned().collect();
+        let mut anonymized = HashMap::new();
+        for name in services {
+            let Some(mut service) = self.services.remove(&name) else { continue };
+            anonymizer.service(&mut service);
+            anonymized.insert(anonymizer.pseudonym(Kind::Service, &name), service);
+        }
+        self.services = anonymized;
+        self.networks = self.networks.take().map(|networks| {
+            networks
+                .into_iter()
+                .map(|(name, mut network)| {
+                    network.extensions.clear();
+                    (anonymizer.network(&name), network)
+                })
+                .collect()
+        });
+        self.included_files.clear();
+        self.extensions.clear();
+        self.ignored_fields.clear();
+        self.document = serde_yaml::Value::Null;
+        self.sources = Default::default();
+        anonymizer.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_compose::parse_composefile;
+
+    const ELK: &str = include_str!("../tests/fixtures/elk.yml");
+
+    fn elk() -> Compose {
+        parse_composefile(Box::new(ELK.as_bytes())).unwrap().value
+    }
+
+    #[test]
+    fn test_anonymize_elk() {
+        let mut compose = elk();
+        let map = compose.anonymize("seed");
+        let anonymized = format!("{}\n{}", compose, serde_json::to_string(&compose).unwrap());
+        for original in ["elasticsearch", "logstash", "kibana", "filebeat", "nginx", "elastic", "frontend", "kib", "proxy", "single-node", "Xms512m", "share"] {
+            assert!(!anonymized.contains(original), "{} in {}", original, anonymized);
+        }
+        let mut services: Vec<&str> = compose.services.keys().map(String::as_str).collect();
+        services.sort();
+        assert_eq!(services, ["svc-01", "svc-02", "svc-03", "svc-04", "svc-05"]);
+        // Ports, tags and relationships are kept
+        let logstash = &compose.services[&map.services.iter().find(|(_, original)| *original == "logstash").unwrap().0.clone()];
+        assert_eq!(logstash.ports.as_ref().unwrap().len(), 4);
+        assert!(logstash.image.as_ref().unwrap().ends_with(":7.16.1"));
+        assert_eq!(map.original(logstash.depends_on_names()[0]), Some("elasticsearch"));
+        assert_eq!(logstash.env("discovery.seed_hosts").and_then(|host| map.original(host)), Some("logstash"));
+        let volume = &logstash.volumes.as_ref().unwrap()[0];
+        assert!(volume.starts_with("./path-") && volume.contains(":/usr/path-"), "{}", volume);
+        assert_eq!(map.deanonymize(volume), "./logstash/pipeline/logstash-nginx.config:/usr/share/logstash/pipeline/logstash-nginx.config");
+        // The same seed gives the same pseudonyms, and another seed others
+        let mut again = elk();
+        assert_eq!(again.anonymize("seed"), map);
+        assert_ne!(elk().anonymize("other").services, map.services);
+    }
+
+    #[test]
+    fn test_anonymize_labels_and_runtime_extras() {
+        let yaml = "services:\n  db:\n    image: postgres\n    labels:\n      com.corp.team: payments\n      com.docker.compose.project: shop\n      tier: backend\n    group_add: [corp-dba, 1001]\n    cpuset: 0-3\n    blkio_config:\n      weight: 300\n      device_read_bps:\n        - path: /dev/corp-secret-disk\n          rate: 12mb\n";
+        let mut compose = parse_composefile(Box::new(yaml.as_bytes())).unwrap().value;
+        let map = compose.anonymize("");
+        let anonymized = serde_json::to_string(&compose).unwrap();
+        for original in ["corp", "payments", "shop", "backend"] {
+            assert!(!anonymized.contains(original), "{} in {}", original, anonymized);
+        }
+        let db = &compose.services["svc-01"];
+        let labels = db.labels.as_ref().unwrap();
+        assert_eq!(map.original("label-01"), Some("com.corp.team"));
+        assert!(labels.contains_key("label-01") && labels.contains_key("com.docker.compose.project") && labels.contains_key("tier"));
+        let extras = db.runtime_extras.as_ref().unwrap();
+        assert_eq!(extras.cpuset.as_deref(), Some("0-3"));
+        assert_eq!(extras.group_add.as_ref().unwrap()[1], "1001");
+        let blkio_config = extras.blkio_config.as_ref().unwrap();
+        assert_eq!(blkio_config["weight"], 300);
+        let device = blkio_config["device_read_bps"][0]["path"].as_str().unwrap();
+        assert!(device.starts_with("/dev/path-"), "{}", device);
+        assert_eq!(map.deanonymize(device), "/dev/corp-secret-disk");
+    }
+
+    #[test]
+    fn test_anonymization_map_inverts() {
+        let original = elk();
+        let mut compose = elk();
+        let map = compose.anonymize("");
+        let map: AnonymizationMap = serde_json::from_str(&serde_json::to_string(&map).unwrap()).unwrap();
+        for (pseudonym, service) in compose.services() {
+            let name = map.original(pseudonym).unwrap();
+            let dependencies: BTreeSet<&str> = service.depends_on_names().into_iter().filter_map(|dependency| map.original(dependency)).collect();
+            assert_eq!(dependencies, original.services[name].depends_on_names().into_iter().collect(), "{}", name);
+            let networks: BTreeSet<&str> = service.attached_networks().into_iter().map(|network| map.original(network).unwrap_or(network)).collect();
+            assert_eq!(networks, original.services[name].attached_networks().into_iter().collect(), "{}", name);
+        }
+        assert_eq!(map.deanonymize("svc-01 depends on svc-012"), format!("{} depends on svc-012", map.services["svc-01"]));
+        assert_eq!(map.deanonymize("part 'net-01Network'"), format!("part '{}Network'", map.networks["net-01"]));
+        assert_eq!(letters(1), "a");
+        assert_eq!(letters(27), "aa");
+    }
+}
@@ -0,0 +1,673 @@
+//! A format-independent model of an architecture, holding just the facts worth comparing
+//! between two versions of it, and the diff of two such models
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
+use anyhow::{anyhow, Result};
+use crate::{
+    parse_compose::Compose,
+    parse_dockerfile::{ParsedContainer, Protocol},
+    registry::{image_key, same_image},
+    util::format_number,
+};
+#[cfg(feature = "pod")]
+use crate::{
+    parse_manifests::ParsedManifests,
+    util::{parse_cpus, parse_quantity},
+};
+
+/// A port of a component
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ModelPort {
+    pub number: u16,
+    pub protocol: Protocol,
+    pub published: bool,
+    pub name: Option<String>,
+}
+
+impl fmt::Display for ModelPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.protocol {
+            Protocol::Tcp => write!(f, "{}", self.number)?,
+            Protocol::Udp => write!(f, "{}/udp", self.number)?,
+            Protocol::Sctp => write!(f, "{}/sctp", self.number)?,
+        }
+        if let Some(name) = &self.name {
+            write!(f, " '{}'", name)?;
+        }
+        if !self.published {
+            write!(f, " (exposed)")?;
+        }
+        Ok(())
+    }
+}
+
+/// A deployable component, such as a compose service
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Component {
+    pub image: Option<String>,
+    pub ports: BTreeSet<ModelPort>,
+    pub networks: BTreeSet<String>,
+    pub dependencies: BTreeSet<String>,
+    /// The names of the environment variables set. This and the limits are compared only
+    /// when reconciling, as models read back from SysML don't have them.
+    pub environment: BTreeSet<String>,
+    /// The CPU limit, in thousandths of a CPU
+    pub cpu_limit: Option<u64>,
+    /// The memory limit, in bytes
+    pub memory_limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArchModel {
+    pub components: BTreeMap<String, Component>,
+}
+
+/// A difference between two versions of a model
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelChange {
+    ComponentAdded(String),
+    ComponentRemoved(String),
+    ImageChanged { component: String, old: Option<String>, new: Option<String> },
+    PortAdded { component: String, port: ModelPort },
+    PortRemoved { component: String, port: ModelPort },
+    NetworkJoined { component: String, network: String },
+    NetworkLeft { component: String, network: String },
+    DependencyAdded { component: String, dependency: String },
+    DependencyRemoved { component: String, dependency: String },
+}
+
+impl ModelChange {
+    /// The component the change applies to
+    pub fn component(&self) -> &str {
+        match self {
+            ModelChange::ComponentAdded(component) | ModelChange::ComponentRemoved(component) => component,
+            ModelChange::ImageChanged { component, .. }
+            | ModelChange::PortAdded { component, .. }
+            | ModelChange::PortRemoved { component, .. }
+            | ModelChange::NetworkJoined { component, .. }
+            | ModelChange::NetworkLeft { component, .. }
+            | ModelChange::DependencyAdded { component, .. }
+            | ModelChange::DependencyRemoved { component, .. } => component,
+        }
+    }
+
+    /// The change without the component it applies to, e.g. `port 5601 removed`
+    fn description(&self) -> String {
+        let image = |image: &Option<String>| image.clone().unwrap_or_else(|| "none".to_string());
+        match self {
+            ModelChange::ComponentAdded(_) => "added".to_string(),
+            ModelChange::ComponentRemoved(_) => "removed".to_string(),
+            // References differing only in how they're written, such as `nginx` and
+            // `nginx:latest`, are only a change when compared as written, so say so
+            ModelChange::ImageChanged { old: Some(old), new: Some(new), .. } if same_image(old, new, true) => {
+                format!("image changed {} \u{2192} {} (both {})", old, new, image_key(new, true))
+            }
+            ModelChange::ImageChanged { old, new, .. } => {
+                format!("image changed {} \u{2192} {}", image(old), image(new))
+            }
+            ModelChange::PortAdded { port, .. } => format!("port {} added", port),
+            ModelChange::PortRemoved { port, .. } => format!("port {} removed", port),
+            ModelChange::NetworkJoined { network, .. } => format!("joined network {}", network),
+            ModelChange::NetworkLeft { network, .. } => format!("left network {}", network),
+            ModelChange::DependencyAdded { dependency, .. } => format!("now depends on {}", dependency),
+            ModelChange::DependencyRemoved { dependency, .. } => format!("no longer depends on {}", dependency),
+        }
+    }
+}
+
+impl fmt::Display for ModelChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "service {}: {}", self.component(), self.description())
+    }
+}
+
+/// Summarise changes one line per component, e.g.
+/// `service kibana: image changed 7.16.1 → 7.17.0; port 5601 removed`
+pub fn change_summary(changes: &[ModelChange]) -> String {
+    let mut by_component: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for change in changes {
+        by_component.entry(change.component()).or_default().push(change.description());
+    }
+    by_component
+        .iter()
+        .map(|(component, descriptions)| format!("service {}: {}\n", component, descriptions.join("; ")))
+        .collect()
+}
+
+impl ArchModel {
+    pub fn from_compose(compose: &Compose) -> Self {
+        Self::from_compose_with_containers(compose, &HashMap::new())
+    }
+
+    /// The model of a compose file, including the ports of the containers built for its
+    /// services, keyed by service name
+    pub fn from_compose_with_containers(compose: &Compose, containers: &HashMap<String, ParsedContainer>) -> Self {
+        let mut components = BTreeMap::new();
+        for (name, service) in &compose.services {
+            let dependencies = service.depends_on_names().into_iter().map(String::from).collect();
+            let component = Component {
+                image: service.image.clone(),
+                ports: service
+                    .exposed_ports(containers.get(name))
+                    .iter()
+                    .map(|port| ModelPort {
+                        number: port.container_port,
+                        protocol: port.protocol,
+                        published: port.published,
+                        name: port.name.clone(),
+                    })
+                    .collect(),
+                networks: service.network_names().into_iter().map(String::from).collect(),
+                dependencies,
+                environment: service.environment.iter().flatten().map(|(key, _)| key.clone()).collect(),
+                cpu_limit: service.cpu_limit(),
+                memory_limit: service.memory_limit(),
+            };
+            components.insert(name.clone(), component);
+        }
+        ArchModel { components }
+    }
+
+    /// The model of Kubernetes manifests, with a component for each container of each pod
+    /// other than init containers, named as its pod when it's the pod's only one and as
+    /// `pod/container` otherwise. The pod of a workload, such as a Deployment, has its name.
+    #[cfg(feature = "pod")]
+    pub fn from_manifests(manifests: &ParsedManifests) -> Self {
+        let mut components = BTreeMap::new();
+        for pod in &manifests.pods {
+            let containers: Vec<_> = pod.containers.iter().filter(|container| !container.init).collect();
+            for container in &containers {
+                let name = match containers.len() {
+                    1 => pod.name.clone(),
+                    _ => format!("{}/{}", pod.name, container.name),
+                };
+                let limit = |resource: &str| container.resources.limits.get(resource).map(String::as_str);
+                let component = Component {
+                    image: container.image.clone(),
+                    ports: container
+                        .ports
+                        .iter()
+                        .map(|port| ModelPort {
+                            number: port.container_port,
+                            protocol: match port.protocol() {
+                                "UDP" => Protocol::Udp,
+                                "SCTP" => Protocol::Sctp,
+                                _ => Protocol::Tcp,
+                            },
+                            published: port.host_port.is_some(),
+                            name: port.name.clone(),
+                        })
+                        .collect(),
+                    environment: container.env.iter().map(|variable| variable.name.clone()).collect(),
+                    cpu_limit: limit("cpu").and_then(parse_cpus),
+                    memory_limit: limit("memory").and_then(parse_quantity),
+                    ..Default::default()
+                };
+                components.insert(name, component);
+            }
+        }
+        ArchModel { components }
+    }
+
+    /// Read the model back from SysML as generated for a compose file, e.g. a committed
+    /// model being checked for staleness. Anything not generated by cargotecture is ignored.
+    pub fn from_sysml(sysml: &str) -> Self {
+        let mut components: BTreeMap<String, Component> = BTreeMap::new();
+        let mut current: Option<String> = None;
+        let mut depth = 0;
+        let mut component_depth = 0;
+        let mut port: Option<ModelPort> = None;
+
+        for line in sysml.lines().map(str::trim) {
+            if let Some(declaration) = line.strip_prefix("part ") {
+                if let Some((name, rest)) = declaration.split_once(": Container") {
+                    let rest = rest.trim();
+                    if rest.starts_with('[') || rest == "{" || rest == ";" {
+                        let name = unquote_name(name);
+                        components.entry(name.clone()).or_default();
+                        if rest.ends_with('{') {
+                            current = Some(name);
+                            component_depth = depth + 1;
+                        }
+                    }
+                }
+            } else if let Some(declaration) = line.strip_prefix("connection ") {
+                if let Some((kind, ends)) = declaration.split_once(" connect ") {
+                    let ends = ends.trim_end_matches(['{', ';']).trim();
+                    if let Some((source, target)) = ends.split_once(" to ") {
+                        let source = unquote_name(last_segment(source));
+                        let target = unquote_name(last_segment(target));
+                        let component = components.entry(source).or_default();
+                        if kind.ends_with(": NetworkMembership") {
+                            component.networks.insert(target.trim_end_matches("Network").to_string());
+                        } else if kind.ends_with(": Dependency") {
+                            component.dependencies.insert(target);
+                        }
+                    }
+                }
+            } else if let Some(component) = current.as_ref().and_then(|name| components.get_mut(name)) {
+                if let Some(value) = redefined_value(line, "image") {
+                    component.image = Some(unquote_string(value));
+                } else if line.starts_with("port ") && line.contains(": NetworkPort") {
+                    port = Some(ModelPort { number: 0, protocol: Protocol::Tcp, published: true, name: None });
+                } else if let (Some(port), Some(value)) = (port.as_mut(), redefined_value(line, "protocol")) {
+                    port.protocol = match value.to_ascii_lowercase().as_str() {
+                        "protocol::udp" => Protocol::Udp,
+                        "protocol::sctp" => Protocol::Sctp,
+                        _ => Protocol::Tcp,
+                    };
+                } else if let (Some(port), Some(value)) = (port.as_mut(), redefined_value(line, "portNumber")) {
+                    port.number = value.parse().unwrap_or(0);
+                } else if let (Some(port), Some(value)) = (port.as_mut(), redefined_value(line, "published")) {
+                    port.published = value == "true";
+                } else if let (Some(port), Some(value)) = (port.as_mut(), redefined_value(line, "portName")) {
+                    port.name = Some(unquote_string(value));
+                }
+            }
+
+            depth += line.matches('{').count();
+            depth = depth.saturating_sub(line.matches('}').count());
+            if let Some(name) = current.as_ref().filter(|_| port.is_some() && depth == component_depth) {
+                components.entry(name.clone()).or_default().ports.extend(port.take());
+            }
+            if current.is_some() && depth < component_depth {
+                current = None;
+            }
+        }
+        ArchModel { components }
+    }
+
+    /// The changes made going from this model to `new`, ordered by component, with images
+    /// compared as written so that pinning a tag is a change
+    pub fn diff(&self, new: &ArchModel) -> Vec<ModelChange> {
+        self.diff_with(new, false)
+    }
+
+    /// The changes made going from this model to `new`, as `diff` finds them, but with image
+    /// references normalised before they're compared when `normalize_refs` is on, so that
+    /// `nginx` becoming `docker.io/library/nginx:latest` isn't a change
+    pub fn diff_with(&self, new: &ArchModel, normalize_refs: bool) -> Vec<ModelChange> {
+        let mut changes = Vec::new();
+        let names: BTreeSet<&String> = self.components.keys().chain(new.components.keys()).collect();
+        for name in names {
+            let (old_component, new_component) = match (self.components.get(name), new.components.get(name)) {
+                (Some(old), Some(new)) => (old, new),
+                (None, _) => {
+                    changes.push(ModelChange::ComponentAdded(name.clone()));
+                    continue;
+                }
+                (_, None) => {
+                    changes.push(ModelChange::ComponentRemoved(name.clone()));
+                    continue;
+                }
+            };
+            let component = || name.clone();
+            let image = |component: &Component| component.image.as_deref().map(|image| image_key(image, normalize_refs));
+            if image(old_component) != image(new_component) {
+                changes.push(ModelChange::ImageChanged {
+                    component: component(),
+                    old: old_component.image.clone(),
+                    new: new_component.image.clone(),
+                });
+            }
+            for port in new_component.ports.difference(&old_component.ports) {
+                changes.push(ModelChange::PortAdded { component: component(), port: port.clone() });
+            }
+            for port in old_component.ports.difference(&new_component.ports) {
+                changes.push(ModelChange::PortRemoved { component: component(), port: port.clone() });
+            }
+            for network in new_component.networks.difference(&old_component.networks) {
+                changes.push(ModelChange::NetworkJoined { component: component(), network: network.clone() });
+            }
+            for network in old_component.networks.difference(&new_component.networks) {
+                changes.push(ModelChange::NetworkLeft { component: component(), network: network.clone() });
+            }
+            for dependency in new_component.dependencies.difference(&old_component.dependencies) {
+                changes.push(ModelChange::DependencyAdded { component: component(), dependency: dependency.clone() });
+            }
+            for dependency in old_component.dependencies.difference(&new_component.dependencies) {
+                changes.push(ModelChange::DependencyRemoved { component: component(), dependency: dependency.clone() });
+            }
+        }
+        changes
+    }
+}
+
+/// Which of the definitions of an application being reconciled something comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Environment {
+    Compose,
+    Kubernetes,
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Environment::Compose => write!(f, "compose"),
+            Environment::Kubernetes => write!(f, "kubernetes"),
+        }
+    }
+}
+
+/// A way a component differs between its compose and Kubernetes definitions
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    Image { compose: Option<String>, kubernetes: Option<String> },
+    /// A port, compared by number and protocol, that only one definition has
+    Port { only_in: Environment, port: ModelPort },
+    EnvironmentVariable { only_in: Environment, name: String },
+    CpuLimit { compose: Option<u64>, kubernetes: Option<u64> },
+    MemoryLimit { compose: Option<u64>, kubernetes: Option<u64> },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let none = || "none".to_string();
+        let cpus = |limit: &Option<u64>| {
+            limit.map_or_else(none, |limit| format!("{} CPU{}", format_number(limit as f64 / 1000.0), if limit == 1000 { "" } else { "s" }))
+        };
+        let bytes = |limit: &Option<u64>| limit.map_or_else(none, |limit| format!("{} bytes", limit));
+        match self {
+            Divergence::Image { compose, kubernetes } => write!(
+                f,
+                "image {} in compose, {} in kubernetes",
+                compose.clone().unwrap_or_else(none),
+                kubernetes.clone().unwrap_or_else(none)
+            ),
+            Divergence::Port { only_in, port } => write!(f, "port {} only in {}", port, only_in),
+            Divergence::EnvironmentVariable { only_in, name } => write!(f, "environment variable {} only in {}", name, only_in),
+            Divergence::CpuLimit { compose, kubernetes } => write!(f, "CPU limit {} in compose, {} in kubernetes", cpus(compose), cpus(kubernetes)),
+            Divergence::MemoryLimit { compose, kubernetes } => {
+                write!(f, "memory limit {} in compose, {} in kubernetes", bytes(compose), bytes(kubernetes))
+            }
+        }
+    }
+}
+
+/// A compose service and the Kubernetes workload deploying the same component
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentMatch {
+    pub compose: String,
+    pub kubernetes: String,
+    pub compose_component: Component,
+    pub kubernetes_component: Component,
+    pub divergences: Vec<Divergence>,
+}
+
+/// The components of an application's compose and Kubernetes definitions, matched up
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reconciliation {
+    /// In compose service name order
+    pub matches: Vec<ComponentMatch>,
+    pub unmatched_compose: Vec<String>,
+    pub unmatched_kubernetes: Vec<String>,
+}
+
+impl ArchModel {
+    /// Match the components of this model of a compose file with those of a model of
+    /// Kubernetes manifests: first the `(service, workload)` pairs given, then each other
+    /// service with the first unmatched workload by name running the same image, compared
+    /// normalised. Fails when a pair names a component that isn't in its model, or one
+    /// already paired.
+    pub fn reconcile(&self, kubernetes: &ArchModel, pairs: &[(String, String)]) -> Result<Reconciliation> {
+        let mut matched: BTreeMap<&str, &str> = BTreeMap::new();
+        for (service, workload) in pairs {
+            if !self.components.contains_key(service) {
+                return Err(anyhow!("Service '{}' isn't defined in the compose file", service));
+            }
+            if !kubernetes.components.contains_key(workload) {
+                return Err(anyhow!("Workload '{}' isn't defined in the manifests", workload));
+            }
+            if matched.contains_key(service.as_str()) || matched.values().any(|matched| matched == workload) {
+                return Err(anyhow!("'{}={}' pairs a component that's already paired", service, workload));
+            }
+            matched.insert(service, workload);
+        }
+        let image = |component: &Component| component.image.as_deref().map(|image| image_key(image, true));
+        for (service, component) in &self.components {
+            if matched.contains_key(service.as_str()) || component.image.is_none() {
+                continue;
+            }
+            let workload = kubernetes
+                .components
+                .iter()
+                .find(|(workload, candidate)| image(candidate) == image(component) && !matched.values().any(|matched| matched == workload));
+            if let Some((workload, _)) = workload {
+                matched.insert(service, workload);
+            }
+        }
+        let matches = matched
+            .iter()
+            .map(|(service, workload)| {
+                let (compose_component, kubernetes_component) = (&self.components[*service], &kubernetes.components[*workload]);
+                ComponentMatch {
+                    compose: service.to_string(),
+                    kubernetes: workload.to_string(),
+                    compose_component: compose_component.clone(),
+                    kubernetes_component: kubernetes_component.clone(),
+                    divergences: divergences(compose_component, kubernetes_component),
+                }
+            })
+            .collect();
+        Ok(Reconciliation {
+            matches,
+            unmatched_compose: self.components.keys().filter(|service| !matched.contains_key(service.as_str())).cloned().collect(),
+            unmatched_kubernetes: kubernetes.components.keys().filter(|workload| !matched.values().any(|matched| matched == workload)).cloned().collect(),
+        })
+    }
+}
+
+/// How a component differs between its definitions. Images and ports are compared as the
+/// diff of the two, ports by number and protocol alone, since a compose service publishes
+/// on the host what a pod's Service would.
+fn divergences(compose: &Component, kubernetes: &Component) -> Vec<Divergence> {
+    let model = |component: &Component| {
+        let ports = component.ports.iter().map(|port| ModelPort { published: true, name: None, ..port.clone() }).collect();
+        let component = Component { image: component.image.clone(), ports, ..Default::default() };
+        ArchModel { components: BTreeMap::from([(String::new(), component)]) }
+    };
+    let mut divergences: Vec<Divergence> = model(compose)
+        .diff_with(&model(kubernetes), true)
+        .into_iter()
+        .filter_map(|change| match change {
+            ModelChange::ImageChanged { old, new, .. } => Some(Divergence::Image { compose: old, kubernetes: new }),
+            ModelChange::PortRemoved { port, .. } => Some(Divergence::Port { only_in: Environment::Compose, port }),
+            ModelChange::PortAdded { port, .. } => Some(Divergence::Port { only_in: Environment::Kubernetes, port }),
+            _ => None,
+        })
+        .collect();
+    for (only_in, names, other) in [
+        (Environment::Compose, &compose.environment, &kubernetes.environment),
+        (Environment::Kubernetes, &kubernetes.environment, &compose.environment),
+    ] {
+        divergences.extend(names.difference(other).map(|name| Divergence::EnvironmentVariable { only_in, name: name.clone() }));
+    }
+    if compose.cpu_limit != kubernetes.cpu_limit {
+        divergences.push(Divergence::CpuLimit { compose: compose.cpu_limit, kubernetes: kubernetes.cpu_limit });
+    }
+    if compose.memory_limit != kubernetes.memory_limit {
+        divergences.push(Divergence::MemoryLimit { compose: compose.memory_limit, kubernetes: kubernetes.memory_limit });
+    }
+    divergences
+}
+
+/// Summarise a reconciliation one line per component, e.g.
+/// `web = shop/web: port 8080 only in compose` or `compose service worker: unmatched`
+pub fn reconciliation_summary(reconciliation: &Reconciliation) -> String {
+    let mut summary = String::new();
+    for matched in &reconciliation.matches {
+        let divergences: Vec<String> = matched.divergences.iter().map(ToString::to_string).collect();
+        let divergences = if divergences.is_empty() { "no divergences".to_string() } else { divergences.join("; ") };
+        summary.push_str(&format!("{} = {}: {}\n", matched.compose, matched.kubernetes, divergences));
+    }
+    for service in &reconciliation.unmatched_compose {
+        summary.push_str(&format!("compose service {}: unmatched\n", service));
+    }
+    for workload in &reconciliation.unmatched_kubernetes {
+        summary.push_str(&format!("kubernetes workload {}: unmatched\n", workload));
+    }
+    summary
+}
+
+/// The value of a line such as `attribute image redefines image = "nginx";`
+fn redefined_value<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let (_, value) = line.split_once(&format!("{} redefines {} = ", name, name))?;
+    Some(value.trim_end_matches(';'))
+}
+
+/// The last segment of a qualified name such as `elasticServices::kibana`
+fn last_segment(name: &str) -> &str {
+    name.trim().rsplit("::").next().unwrap_or(name)
+}
+
+fn unquote_name(name: &str) -> String {
+    let name = name.trim();
+    match name.strip_prefix('\'').and_then(|n| n.strip_suffix('\'')) {
+        Some(quoted) => quoted.replace("\\'", "'").replace("\\\\", "\\"),
+        None => name.to_string(),
+    }
+}
+
+fn unquote_string(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen_sysml::{sysml_compose_package, GroupBy, SysmlOptions};
+
+    fn compose(yaml: &str) -> Compose {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    const OLD: &str = r#"
+services:
+  kibana:
+    image: kibana:7.16.1
+    ports: ["5601:5601"]
+    depends_on: [elasticsearch]
+    networks: [elastic]
+  elasticsearch:
+    image: elasticsearch:7.16.1
+    expose: ["9200"]
+    networks: [elastic]
+"#;
+
+    const NEW: &str = r#"
+services:
+  kibana:
+    image: kibana:7.17.0
+    networks: [elastic]
+  elasticsearch:
+    image: elasticsearch:7.16.1
+    expose: ["9200", "9300"]
+    networks: [elastic]
+  logstash:
+    image: logstash:7.17.0
+"#;
+
+    #[test]
+    fn test_diff_summary() {
+        let old = ArchModel::from_compose(&compose(OLD));
+        let new = ArchModel::from_compose(&compose(NEW));
+        assert!(old.diff(&old).is_empty());
+        let changes = old.diff(&new);
+        assert_eq!(
+            change_summary(&changes),
+            "service elasticsearch: port 9300 (exposed) added\n\
+             service kibana: image changed kibana:7.16.1 \u{2192} kibana:7.17.0; port 5601 removed; no longer depends on elasticsearch\n\
+             service logstash: added\n"
+        );
+        assert_eq!(changes[0].to_string(), "service elasticsearch: port 9300 (exposed) added");
+    }
+
+    #[test]
+    fn test_diff_normalized_refs() {
+        let model = |image: &str| ArchModel::from_compose(&compose(&format!("services:\n  web:\n    image: {}\n", image)));
+        let forms = ["nginx", "nginx:latest", "docker.io/library/nginx:latest"];
+        for old in forms {
+            for new in forms {
+                let (old_model, new_model) = (model(old), model(new));
+                assert!(old_model.diff_with(&new_model, true).is_empty(), "{} \u{2192} {}", old, new);
+                let changes = old_model.diff_with(&new_model, false);
+                assert_eq!(changes, old_model.diff(&new_model));
+                if old == new {
+                    assert!(changes.is_empty());
+                } else {
+                    assert_eq!(
+                        change_summary(&changes),
+                        format!("service web: image changed {} \u{2192} {} (both docker.io/library/nginx:latest)\n", old, new)
+                    );
+                }
+            }
+        }
+
+        // A different tag is a change either way, shown as written
+        let changes = model("nginx").diff_with(&model("nginx:1.25"), true);
+        assert_eq!(change_summary(&changes), "service web: image changed nginx \u{2192} nginx:1.25\n");
+    }
+
+    #[test]
+    fn test_from_sysml_round_trip() {
+        let compose = compose(OLD);
+        let model = ArchModel::from_compose(&compose);
+        for group_by in [GroupBy::None, GroupBy::Network, GroupBy::Dependency] {
+            for per_service_packages in [false, true] {
+                let options = SysmlOptions { group_by, per_service_packages, ..Default::default() };
+                let sysml = sysml_compose_package(&compose, "elk", &options);
+                assert_eq!(ArchModel::from_sysml(&sysml), model, "{:?} {}", group_by, per_service_packages);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pod")]
+    fn test_reconcile() {
+        let compose = compose(
+            "services:\n  web:\n    image: nginx\n    ports: ['8080:80', '8443:443']\n    environment:\n      DEBUG: '1'\n      UPSTREAM: api\n    deploy:\n      resources:\n        limits: {cpus: '0.5', memory: 256m}\n  worker:\n    image: shop/worker:2\n  db:\n    image: postgres:16\n",
+        );
+        let manifests = "kind: Pod\nmetadata:\n  name: storefront\nspec:\n  containers:\n    - name: nginx\n      image: docker.io/library/nginx:latest\n      ports: [{containerPort: 80}]\n      env: [{name: UPSTREAM, value: api}, {name: TLS_SECRET, valueFrom: {secretKeyRef: {name: tls, key: key}}}]\n      resources:\n        limits: {cpu: 500m, memory: 512Mi}\n---\nkind: Pod\nmetadata:\n  name: database\nspec:\n  containers:\n    - {name: postgres, image: postgres:15}\n";
+        let manifests = crate::parse_manifests::parse_manifests(Box::new(manifests.as_bytes())).unwrap();
+        let (compose, kubernetes) = (ArchModel::from_compose(&compose), ArchModel::from_manifests(&manifests));
+
+        // The images match once normalised; different tags don't
+        let reconciliation = compose.reconcile(&kubernetes, &[]).unwrap();
+        assert_eq!(
+            reconciliation_summary(&reconciliation),
+            "web = storefront: port 443 only in compose; environment variable DEBUG only in compose; environment variable TLS_SECRET only in kubernetes; memory limit 268435456 bytes in compose, 536870912 bytes in kubernetes\n\
+             compose service db: unmatched\n\
+             compose service worker: unmatched\n\
+             kubernetes workload database: unmatched\n"
+        );
+        assert_eq!(reconciliation.matches[0].kubernetes_component.cpu_limit, Some(500));
+
+        // Pairs given match whatever the images
+        let pairs = [("db".to_string(), "database".to_string())];
+        let reconciliation = compose.reconcile(&kubernetes, &pairs).unwrap();
+        assert_eq!(reconciliation.matches.iter().map(|m| m.compose.as_str()).collect::<Vec<_>>(), vec!["db", "web"]);
+        assert_eq!(reconciliation.matches[0].divergences, vec![Divergence::Image { compose: Some("postgres:16".to_string()), kubernetes: Some("postgres:15".to_string()) }]);
+        assert_eq!(reconciliation.unmatched_compose, vec!["worker"]);
+        assert!(reconciliation.unmatched_kubernetes.is_empty());
+
+        let pairs = [("cache".to_string(), "database".to_string())];
+        assert_eq!(compose.reconcile(&kubernetes, &pairs).unwrap_err().to_string(), "Service 'cache' isn't defined in the compose file");
+    }
+
+    #[test]
+    fn test_named_ports_round_trip() {
+        let yaml = "services:\n  api:\n    image: api:1\n    ports:\n      - name: metrics-2\n        target: 9090\n";
+        let compose = crate::parse_compose::parse_composefile(Box::new(yaml.as_bytes())).unwrap().value;
+        let model = ArchModel::from_compose(&compose);
+        let port = model.components["api"].ports.iter().next().unwrap();
+        assert_eq!(port.to_string(), "9090 'metrics-2'");
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert_eq!(ArchModel::from_sysml(&sysml), model);
+    }
+}
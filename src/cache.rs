@@ -0,0 +1,190 @@
+//! An on-disk cache of the packages generated by scans, so that scanning a large tree again
+//! only parses the files that changed
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+use serde::{Deserialize, Serialize};
+use crate::{
+    gen_sysml::SysmlOptions,
+    provenance::{InputHash, Provenance},
+    sysml_ast::{BraceStyle, RenderStyle},
+    util::sha256_hex,
+};
+
+/// The version of the cache entry format. Entries written with another version are ignored.
+pub const CACHE_FORMAT_VERSION: u32 = 1;
+
+/// What scanning a file produced
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub version: u32,
+    /// The generated package without provenance, None when the file failed to parse
+    pub sysml: Option<String>,
+    /// The warnings and errors reported while parsing, as printed
+    pub diagnostics: Vec<String>,
+    /// Other files read while parsing, such as those named by `extends.file`, as they were
+    pub included: Vec<InputHash>,
+}
+
+impl CacheEntry {
+    pub fn new(sysml: Option<String>, diagnostics: Vec<String>, included_files: &[String]) -> Self {
+        let included = included_files.iter().filter_map(|file| InputHash::of_file(Path::new(file)).ok()).collect();
+        CacheEntry { version: CACHE_FORMAT_VERSION, sysml, diagnostics, included }
+    }
+
+    /// Whether the entry was written in this format and the files it included are unchanged
+    fn is_current(&self) -> bool {
+        self.version == CACHE_FORMAT_VERSION
+            && self.included.iter().all(|input| InputHash::of_file(Path::new(&input.path)).is_ok_and(|now| now == *input))
+    }
+}
+
+/// A directory of cache entries, one JSON file per entry named by its key
+#[derive(Debug)]
+pub struct ScanCache {
+    dir: PathBuf,
+    /// Ignore the cached entries, replacing them with fresh ones
+    refresh: bool,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl ScanCache {
+    pub fn new(dir: &Path, refresh: bool) -> Self {
+        ScanCache { dir: dir.to_path_buf(), refresh, hits: 0, misses: 0 }
+    }
+
+    /// The key of the entry for a source file: the SHA-256 digest of its content, together
+    /// with everything else the generated package depends on
+    pub fn key(path: &Path, name: &str, options: &SysmlOptions, content: &[u8]) -> String {
+        let mut keyed = format!("{}\n{}\n{}\n{}\n{:?}\n", env!("CARGO_PKG_VERSION"), CACHE_FORMAT_VERSION, path.display(), name, options.effective())
+            .into_bytes();
+        keyed.extend_from_slice(content);
+        sha256_hex(&keyed)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    fn load(&self, key: &str) -> Option<CacheEntry> {
+        if self.refresh {
+            return None;
+        }
+        let content = fs::read_to_string(self.entry_path(key)).ok()?;
+        serde_json::from_str::<CacheEntry>(&content).ok().filter(CacheEntry::is_current)
+    }
+
+    fn store(&self, key: &str, entry: &CacheEntry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.entry_path(key), serde_json::to_string(entry)?)
+    }
+
+    /// The cached entry for a key, else the one `generate` makes, which is cached. Failing
+    /// to write the cache is reported but otherwise harmless.
+    pub fn get_or_insert_with(&mut self, key: &str, generate: impl FnOnce() -> CacheEntry) -> CacheEntry {
+        if let Some(entry) = self.load(key) {
+            self.hits += 1;
+            return entry;
+        }
+        self.misses += 1;
+        let entry = generate();
+        if let Err(err) = self.store(key, &entry) {
+            eprintln!("warning: failed to write to the cache in {}: {}", self.dir.display(), err);
+        }
+        entry
+    }
+
+    /// Remove every cache entry in a directory, returning how many there were. A missing
+    /// directory is an empty cache.
+    pub fn clear(dir: &Path) -> io::Result<usize> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Add a provenance comment to a package generated in `style`, where generating it with
+/// provenance would have put it
+pub fn with_provenance(sysml: &str, provenance: &Provenance, style: &RenderStyle) -> String {
+    // The package's body opens on its first line, or on the next with braces on their own
+    let opening = match style.braces {
+        BraceStyle::SameLine => 0,
+        BraceStyle::NextLine => 1,
+    };
+    let Some((end, _)) = sysml.match_indices('\n').nth(opening) else {
+        return sysml.to_string();
+    };
+    let (head, rest) = sysml.split_at(end + 1);
+    let mut out = head.to_string();
+    let indentation = style.indentation(1);
+    for line in provenance.sysml_comment().lines() {
+        if line.trim().is_empty() {
+            out.push('\n');
+        } else {
+            out.push_str(&format!("{}{}\n", indentation, line));
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let included = dir.path().join("base.yaml");
+        fs::write(&included, "services: {}\n").unwrap();
+        let included_files = vec![included.to_string_lossy().to_string()];
+
+        let mut cache = ScanCache::new(&dir.path().join("cache"), false);
+        let key = ScanCache::key(Path::new("compose.yaml"), "app", &SysmlOptions::default(), b"services: {}\n");
+        let entry = CacheEntry::new(Some("package appModel {\n}\n".to_string()), vec!["warning: w".to_string()], &included_files);
+        assert_eq!(cache.get_or_insert_with(&key, || entry.clone()), entry);
+        assert_eq!(cache.get_or_insert_with(&key, || unreachable!()), entry);
+        assert_eq!((cache.hits, cache.misses), (1, 1));
+
+        // Changing an included file invalidates the entry
+        fs::write(&included, "services:\n  web: {}\n").unwrap();
+        cache.get_or_insert_with(&key, || entry.clone());
+        assert_eq!(cache.misses, 2);
+
+        // As does any other content or option
+        let other = ScanCache::key(Path::new("compose.yaml"), "app", &SysmlOptions { infer_flows: true, ..Default::default() }, b"services: {}\n");
+        assert_ne!(key, other);
+
+        assert_eq!(ScanCache::clear(&dir.path().join("cache")).unwrap(), 1);
+        assert_eq!(ScanCache::clear(&dir.path().join("missing")).unwrap(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "compose")]
+    fn test_with_provenance() {
+        let mut provenance = Provenance::new("0.1", true);
+        provenance.inputs.push(InputHash::new("compose.yml", b""));
+        let compose = crate::parse_compose::parse_composefile(Box::new("services:\n  web:\n    image: nginx:1.25\n".as_bytes())).unwrap().value;
+        let next_line = RenderStyle { indent: crate::sysml_ast::Indent::Tab, braces: BraceStyle::NextLine, ..Default::default() };
+        for style in [RenderStyle::default(), next_line] {
+            let options = SysmlOptions { provenance: Some(provenance.clone()), style, ..Default::default() };
+            let expected = crate::gen_sysml::sysml_compose_package(&compose, "app", &options);
+            let plain = crate::gen_sysml::sysml_compose_package(&compose, "app", &SysmlOptions { style, ..Default::default() });
+            assert_eq!(with_provenance(&plain, &provenance, &style), expected, "{}", style);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Canonical formatting of compose files, so that equivalent files are written the same way
+//! and can be tidied, or checked to be tidy, like source code
+use anyhow::Result;
+use serde_yaml::{Mapping, Value};
+use crate::{
+    parse_compose::{number_string, yaml_bool, Compose},
+    parse_dockerfile::Protocol,
+    util::{format_file_mode, parse_file_mode, parse_port_mapping, parse_volume_spec, VolumeSource},
+};
+
+/// Propagation modes of bind mounts, written under `bind.propagation` in the long syntax
+const PROPAGATIONS: &[&str] = &["shared", "rshared", "slave", "rslave", "private", "rprivate"];
+
+/// Consistency requirements of mounts, written as `consistency` in the long syntax
+const CONSISTENCIES: &[&str] = &["cached", "delegated", "consistent"];
+
+/// The text a mapping key sorts by
+fn key_text(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other).unwrap_or_default(),
+    }
+}
+
+/// Sort the keys of every mapping in a value, leaving sequences in order
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Mapping(mapping) => {
+            let mut entries: Vec<(Value, Value)> = mapping.into_iter().collect();
+            entries.sort_by_key(|(key, _)| key_text(key));
+            Value::Mapping(entries.into_iter().map(|(key, value)| (key, sort_keys(value))).collect())
+        }
+        Value::Sequence(sequence) => Value::Sequence(sequence.into_iter().map(sort_keys).collect()),
+        other => other,
+    }
+}
+
+fn mapping<const N: usize>(entries: [(&str, Option<Value>); N]) -> Value {
+    Value::Mapping(entries.into_iter().filter_map(|(key, value)| Some((Value::from(key), value?))).collect())
+}
+
+/// A short-syntax port in the long syntax. Ports which don't parse, such as ranges, are
+/// kept as written.
+fn long_port(port: Value) -> Value {
+    let Some(binding) = scalar(&port).and_then(|short| parse_port_mapping(&short).ok()) else {
+        return port;
+    };
+    let protocol = match binding.protocol {
+        Protocol::Tcp => "tcp",
+        Protocol::Udp => "udp",
+        Protocol::Sctp => "sctp",
+    };
+    mapping([
+        ("target", Some(Value::from(binding.container_port))),
+        ("published", binding.host_port.map(Value::from)),
+        ("host_ip", binding.host_ip.map(|ip| Value::from(ip.to_string()))),
+        ("protocol", Some(Value::from(protocol))),
+    ])
+}
+
+/// A short-syntax volume in the long syntax. Volumes which don't parse are kept as written.
+fn long_volume(volume: Value) -> Value {
+    let Some(spec) = volume.as_str().and_then(|short| parse_volume_spec(short).ok()) else {
+        return volume;
+    };
+    let option = |options: &[&str]| spec.options.iter().find(|option| options.contains(&option.as_str())).map(|option| Value::from(option.as_str()));
+    let (volume_type, source) = match &spec.source {
+        VolumeSource::Anonymous => ("volume", None),
+        VolumeSource::Named(name) => ("volume", Some(Value::from(name.as_str()))),
+        VolumeSource::Bind(path) => ("bind", Some(Value::from(path.as_str()))),
+    };
+    let bind = mapping([("propagation", option(PROPAGATIONS)), ("selinux", option(&["z", "Z"]))]);
+    let nocopy = spec.options.iter().any(|option| option == "nocopy");
+    mapping([
+        ("type", Some(Value::from(volume_type))),
+        ("source", source),
+        ("target", Some(Value::from(spec.target.as_str()))),
+        ("read_only", spec.read_only.then_some(Value::from(true))),
+        ("bind", bind.as_mapping().filter(|bind| !bind.is_empty()).map(|_| bind.clone())),
+        ("volume", nocopy.then(|| mapping([("nocopy", Some(Value::from(true)))]))),
+        ("consistency", option(CONSISTENCIES)),
+    ])
+}
+
+/// A scalar as compose reads it in a string context
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(number_string(n)),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Environment variables as a sorted list of `KEY=value`, with `KEY` alone for a variable
+/// taken from the environment compose runs in
+fn environment_list(environment: Value) -> Value {
+    let mut entries: Vec<String> = match &environment {
+        Value::Mapping(mapping) => mapping
+            .iter()
+            .map(|(key, value)| match (key_text(key), scalar(value)) {
+                (key, Some(value)) => format!("{}={}", key, value),
+                (key, None) => key,
+            })
+            .collect(),
+        Value::Sequence(sequence) => sequence.iter().filter_map(scalar).collect(),
+        _ => return environment,
+    };
+    entries.sort();
+    Value::Sequence(entries.into_iter().map(Value::from).collect())
+}
+
+/// Service fields which are booleans, written `true` or `false` whichever form of YAML 1.1
+/// they're written in
+const BOOLEAN_FIELDS: &[&str] = &["privileged", "read_only", "attach", "init", "tty", "stdin_open", "oom_kill_disable"];
+
+/// A boolean written `true` or `false`. Values which aren't booleans are kept as written.
+fn canonical_bool(value: Value) -> Value {
+    yaml_bool(&value).map_or(value, Value::from)
+}
+
+/// A long-syntax secret with its mode as four octal digits, such as `"0440"`, since an
+/// unquoted `0440` is read as a string by some tools and an octal number by others
+fn canonical_secret(mut secret: Value) -> Value {
+    let mode = secret.get("mode").and_then(|mode| match mode {
+        Value::Number(n) => n.as_u64().and_then(|mode| u32::try_from(mode).ok()),
+        Value::String(s) => parse_file_mode(s),
+        _ => None,
+    });
+    if let (Some(mode), Some(mapping)) = (mode, secret.as_mapping_mut()) {
+        mapping.insert(Value::from("mode"), Value::from(format_file_mode(mode)));
+    }
+    secret
+}
+
+fn canonical_service(service: &mut Mapping) {
+    for field in BOOLEAN_FIELDS {
+        if let Some(value) = service.get_mut(*field) {
+            *value = canonical_bool(std::mem::take(value));
+        }
+    }
+    if let Some(Value::Sequence(secrets)) = service.get_mut("secrets") {
+        *secrets = std::mem::take(secrets).into_iter().map(canonical_secret).collect();
+    }
+    for (field, normalise) in [("ports", long_port as fn(Value) -> Value), ("volumes", long_volume)] {
+        if let Some(Value::Sequence(entries)) = service.get_mut(field) {
+            *entries = std::mem::take(entries).into_iter().map(normalise).collect();
+        }
+    }
+    if let Some(environment) = service.get_mut("environment") {
+        *environment = environment_list(std::mem::take(environment));
+    }
+}
+
+/// Remove the null fields of a mapping, which are the same as absent ones for the fields
+/// of the model
+fn remove_nulls(value: &mut Value) {
+    if let Some(mapping) = value.as_mapping_mut() {
+        mapping.retain(|_, value| !value.is_null());
+    }
+}
+
+/// A compose document written canonically: keys sorted, ports and volumes in the long
+/// syntax and environment variables as a sorted list
+pub fn canonical_document(document: &Value) -> Value {
+    let mut document = document.clone();
+    if let Some(services) = document.get_mut("services").and_then(Value::as_mapping_mut) {
+        for service in services.values_mut().filter_map(Value::as_mapping_mut) {
+            canonical_service(service);
+        }
+    }
+    sort_keys(document)
+}
+
+impl Compose {
+    /// The compose file written canonically, as `canonical_document` does. Fields the model
+    /// doesn't know are kept from the document parsed. A model deserialized directly has no
+    /// document, so it's written from the model, which lacks those fields and `x-` extensions.
+    pub fn to_yaml_canonical(&self) -> Result<String> {
+        let document = match &self.document {
+            Value::Null => {
+                let mut document = serde_yaml::to_value(self)?;
+                remove_nulls(&mut document);
+                for section in ["services", "networks"] {
+                    for entry in document.get_mut(section).and_then(Value::as_mapping_mut).into_iter().flat_map(|m| m.values_mut()) {
+                        remove_nulls(entry);
+                    }
+                }
+                document
+            }
+            document => document.clone(),
+        };
+        Ok(serde_yaml::to_string(&canonical_document(&document))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_compose::parse_composefiles_unvalidated;
+
+    fn parse(yaml: &str) -> Compose {
+        parse_composefiles_unvalidated(vec![Box::new(std::io::Cursor::new(yaml.to_string()))], &Default::default()).unwrap()
+    }
+
+    /// The model of a compose file, less the document it was parsed from and what's
+    /// derived from its anchors
+    fn model(compose: &Compose) -> (Value, Vec<Value>) {
+        let mut names: Vec<&String> = compose.services.keys().collect();
+        names.sort();
+        let extensions = names.iter().map(|name| serde_yaml::to_value(&compose.services[*name].extensions).unwrap()).collect();
+        (serde_yaml::to_value(compose).unwrap(), extensions)
+    }
+
+    const COMPOSE: &str = r#"
+x-defaults: &defaults
+  restart: unless-stopped
+  environment:
+    LOG_LEVEL: info
+services:
+  web:
+    <<: *defaults
+    image: nginx:1.25
+    ports:
+      - "8080:80"
+      - "127.0.0.1:9443:443"
+      - "[::1]::8081"
+      - "53:53/udp"
+      - "9000-9001:9000-9001"
+    volumes:
+      - ./html:/usr/share/nginx/html:ro
+      - cache:/var/cache/nginx:nocopy
+      - /var/run
+      - ./certs:/certs:rshared,z,cached
+    environment:
+      - ZONE=eu
+      - DEBUG
+    x-team: web
+    secrets: [tls]
+  db:
+    image: postgres:16
+    environment:
+      POSTGRES_DB: app
+      PGPORT: 5432
+      PGPASSFILE:
+secrets:
+  tls:
+    file: ./tls.pem
+volumes:
+  cache: {}
+"#;
+
+    #[test]
+    fn test_canonical_round_trip() {
+        let compose = parse(COMPOSE);
+        let canonical = compose.to_yaml_canonical().unwrap();
+        let reparsed = parse(&canonical);
+        assert_eq!(model(&reparsed), model(&compose));
+        assert_eq!(reparsed.extensions, compose.extensions);
+
+        // Formatting is idempotent
+        assert_eq!(reparsed.to_yaml_canonical().unwrap(), canonical);
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        let canonical = parse(COMPOSE).to_yaml_canonical().unwrap();
+        let document: Value = serde_yaml::from_str(&canonical).unwrap();
+        let web = &document["services"]["web"];
+        assert_eq!(serde_yaml::to_string(&web["ports"][1]).unwrap(), "host_ip: 127.0.0.1\nprotocol: tcp\npublished: 9443\ntarget: 443\n");
+        assert_eq!(serde_yaml::to_string(&web["ports"][2]).unwrap(), "host_ip: ::1\nprotocol: tcp\ntarget: 8081\n");
+        // Ranges have no long syntax here, so are kept as written
+        assert_eq!(web["ports"][4].as_str(), Some("9000-9001:9000-9001"));
+        assert_eq!(serde_yaml::to_string(&web["volumes"][1]).unwrap(), "source: cache\ntarget: /var/cache/nginx\ntype: volume\nvolume:\n  nocopy: true\n");
+        assert_eq!(serde_yaml::to_string(&web["volumes"][2]).unwrap(), "target: /var/run\ntype: volume\n");
+        assert_eq!(
+            serde_yaml::to_string(&web["volumes"][3]).unwrap(),
+            "bind:\n  propagation: rshared\n  selinux: z\nconsistency: cached\nsource: ./certs\ntarget: /certs\ntype: bind\n"
+        );
+        // Anchors are resolved, and fields which aren't modelled kept
+        assert_eq!(web["restart"].as_str(), Some("unless-stopped"));
+        assert_eq!(web["secrets"][0].as_str(), Some("tls"));
+        assert_eq!(document["secrets"]["tls"]["file"].as_str(), Some("./tls.pem"));
+        assert_eq!(serde_yaml::to_string(&web["environment"]).unwrap(), "- DEBUG\n- ZONE=eu\n");
+        assert_eq!(
+            serde_yaml::to_string(&document["services"]["db"]["environment"]).unwrap(),
+            "- PGPASSFILE\n- PGPORT=5432\n- POSTGRES_DB=app\n"
+        );
+        let keys: Vec<&str> = document.as_mapping().unwrap().keys().filter_map(Value::as_str).collect();
+        assert_eq!(keys, vec!["secrets", "services", "volumes", "x-defaults"]);
+    }
+
+    #[test]
+    fn test_canonical_scalars() {
+        let yaml = "services:\n  web:\n    image: nginx\n    privileged: yes\n    read_only: Off\n    secrets:\n      - source: a\n        mode: 0440\n      - source: b\n        mode: \"0440\"\n      - source: c\n        mode: 0o440\n    environment:\n      LIMIT: 1e21\n";
+        let canonical = parse(yaml).to_yaml_canonical().unwrap();
+        let document: Value = serde_yaml::from_str(&canonical).unwrap();
+        let web = &document["services"]["web"];
+        assert_eq!((&web["privileged"], &web["read_only"]), (&Value::Bool(true), &Value::Bool(false)));
+        for secret in web["secrets"].as_sequence().unwrap() {
+            assert_eq!(secret["mode"].as_str(), Some("0440"));
+        }
+        assert_eq!(web["environment"][0].as_str(), Some("LIMIT=1000000000000000000000"));
+        assert_eq!(parse(&canonical).to_yaml_canonical().unwrap(), canonical);
+    }
+
+    #[test]
+    fn test_canonical_without_document() {
+        let compose: Compose = serde_yaml::from_str("services:\n  web:\n    image: nginx\n    ports: [\"8080:80\"]\n").unwrap();
+        assert_eq!(
+            compose.to_yaml_canonical().unwrap(),
+            "services:\n  web:\n    image: nginx\n    ports:\n    - protocol: tcp\n      published: 8080\n      target: 80\n"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Errors raised by cargotecture itself, rather than passed through from the parsers it uses
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CargotectureError {
+    /// A port or port mapping which couldn't be parsed
+    InvalidPort { value: String, reason: String },
+    /// A signal name or number which isn't a known POSIX signal
+    InvalidSignal { value: String, reason: String },
+    /// A compose short-syntax volume which couldn't be parsed
+    InvalidVolume { value: String, reason: String },
+    /// A subnet which isn't an IPv4 network in CIDR notation
+    InvalidSubnet { value: String, reason: String },
+    /// A label selector which couldn't be parsed
+    InvalidSelector { value: String, reason: String },
+    /// A build target naming none of the stages of a Dockerfile
+    UnknownStage { target: String, available: Vec<String> },
+    /// A diagnostic code which isn't one of those reported, with the known codes like it
+    UnknownCode { code: String, similar: Vec<String> },
+    /// A compose file which can't be resolved into a valid model, such as one with cyclic `extends`
+    Validation(String),
+}
+
+impl CargotectureError {
+    pub fn invalid_port(value: &str, reason: &str) -> Self {
+        CargotectureError::InvalidPort { value: value.to_string(), reason: reason.to_string() }
+    }
+
+    pub fn invalid_signal(value: &str, reason: &str) -> Self {
+        CargotectureError::InvalidSignal { value: value.to_string(), reason: reason.to_string() }
+    }
+
+    pub fn invalid_volume(value: &str, reason: &str) -> Self {
+        CargotectureError::InvalidVolume { value: value.to_string(), reason: reason.to_string() }
+    }
+
+    pub fn invalid_subnet(value: &str, reason: &str) -> Self {
+        CargotectureError::InvalidSubnet { value: value.to_string(), reason: reason.to_string() }
+    }
+
+    pub fn invalid_selector(value: &str, reason: &str) -> Self {
+        CargotectureError::InvalidSelector { value: value.to_string(), reason: reason.to_string() }
+    }
+}
+
+impl fmt::Display for CargotectureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CargotectureError::InvalidPort { value, reason } => write!(f, "Invalid port '{}': {}", value, reason),
+            CargotectureError::InvalidSignal { value, reason } => write!(f, "Invalid signal '{}': {}", value, reason),
+            CargotectureError::InvalidVolume { value, reason } => write!(f, "Invalid volume '{}': {}", value, reason),
+            CargotectureError::InvalidSubnet { value, reason } => write!(f, "Invalid subnet '{}': {}", value, reason),
+            CargotectureError::InvalidSelector { value, reason } => write!(f, "Invalid label selector '{}': {}", value, reason),
+            CargotectureError::UnknownStage { target, available } => {
+                write!(f, "Build target '{}' not found, the stages are: {}", target, available.join(", "))
+            }
+            CargotectureError::UnknownCode { code, similar } if similar.is_empty() => write!(f, "Unknown diagnostic code '{}'", code),
+            CargotectureError::UnknownCode { code, similar } => {
+                write!(f, "Unknown diagnostic code '{}', did you mean: {}", code, similar.join(", "))
+            }
+            CargotectureError::Validation(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for CargotectureError {}
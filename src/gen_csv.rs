@@ -0,0 +1,131 @@
+//! CSV summaries of container architectures, one row per service, for aggregating many
+//! compose files in spreadsheets
+use crate::{parse_dockerfile::ParsedContainer, util::image_tag};
+#[cfg(feature = "compose")]
+use crate::parse_compose::{Compose, Service};
+
+/// The header row of the CSV, shared by compose files and containerfiles so that their rows
+/// can be concatenated
+pub const CSV_HEADER: &str = "service,image,tag,ports_published,volumes_count,has_healthcheck,restart_policy,network_count,extras";
+
+/// Quote a CSV field when it contains a separator, quote or line break, doubling its quotes
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    format!("{}\n", fields.join(","))
+}
+
+/// The row of a service, with the values of the extension fields named by `keys` last
+#[cfg(feature = "compose")]
+fn service_row(name: &str, service: &Service, keys: &[String]) -> String {
+    let promoted = service.promoted_extensions(keys);
+    let extensions = keys.iter().map(|key| promoted.iter().find(|(promoted, _)| promoted == key).map_or_else(String::new, |(_, value)| value.clone()));
+    let mut fields = vec![
+        name.to_string(),
+        service.image.clone().unwrap_or_default(),
+        service.image.as_deref().map(image_tag).unwrap_or_default(),
+        service.port_infos().iter().filter(|port| port.published).count().to_string(),
+        service.volumes.iter().flatten().count().to_string(),
+        service.healthcheck.is_some().to_string(),
+        service.restart.clone().unwrap_or_default(),
+        service.network_names().len().to_string(),
+        service.runtime_extras.as_ref().map(|extras| extras.to_json()).unwrap_or_default(),
+    ];
+    fields.extend(extensions);
+    csv_row(&fields)
+}
+
+/// Generate a CSV of the services of a compose file, sorted by name, with a header row
+#[cfg(feature = "compose")]
+pub fn compose_to_csv(compose: &Compose) -> String {
+    compose_to_csv_with_extensions(compose, &[])
+}
+
+/// Generate a CSV as `compose_to_csv` does, with a column after the others for each of the
+/// `x-` extension fields named by `keys`, empty for services which don't set it
+#[cfg(feature = "compose")]
+pub fn compose_to_csv_with_extensions(compose: &Compose, keys: &[String]) -> String {
+    let mut header = vec![CSV_HEADER.to_string()];
+    header.extend(keys.iter().map(|key| csv_field(key)));
+    let mut out = format!("{}\n", header.join(","));
+    for (name, service) in compose.services() {
+        out.push_str(&service_row(name, service, keys));
+    }
+    out
+}
+
+impl ParsedContainer {
+    /// A CSV row describing the container, with the columns of `CSV_HEADER`. The image is
+    /// the base image, and a Dockerfile publishes no ports, has no restart policy, joins no
+    /// networks and sets no runtime extras.
+    pub fn to_csv_row(&self) -> String {
+        csv_row(&[
+            self.name.clone(),
+            self.base_image.clone(),
+            image_tag(&self.base_image),
+            "0".to_string(),
+            self.volumes.len().to_string(),
+            "false".to_string(),
+            String::new(),
+            "0".to_string(),
+            String::new(),
+        ])
+    }
+}
+
+#[cfg(all(test, feature = "compose"))]
+mod tests {
+    use super::*;
+    use crate::parse_compose::parse_composefile;
+
+    #[test]
+    fn test_compose_to_csv() {
+        let compose = parse_composefile(Box::new(include_str!("../tests/fixtures/elk.yml").as_bytes())).unwrap().value;
+        let csv = compose_to_csv(&compose);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        // One data row per service
+        assert_eq!(lines.len(), 1 + compose.services.len());
+        assert_eq!(lines[1], "elasticsearch,elasticsearch:7.16.1,7.16.1,2,0,true,,1,");
+        assert_eq!(lines[2], "filebeat,elastic/filebeat:7.16.1,7.16.1,0,1,false,,1,");
+        assert_eq!(lines[4], "logstash,logstash:7.16.1,7.16.1,4,2,false,,1,");
+        assert_eq!(lines[5], "nginx,nginx:1.25,1.25,2,0,false,,2,");
+    }
+
+    #[test]
+    fn test_csv_promoted_extensions() {
+        let compose = parse_composefile(Box::new(include_str!("../tests/fixtures/extensions.yml").as_bytes())).unwrap().value;
+        let csv = compose_to_csv_with_extensions(&compose, &["x-owner".to_string(), "x-oncall".to_string()]);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], format!("{},x-owner,x-oncall", CSV_HEADER));
+        assert_eq!(lines[1], "ledger,ledger:1.4,1.4,0,0,false,,0,,\"ledger, core\",");
+        assert_eq!(lines[2], "payments,payments:2.1,2.1,0,0,false,,0,,payments-team,\"{\"\"primary\"\":\"\"alice\"\",\"\"rota\"\":\"\"weekly\"\"}\"");
+        assert_eq!(compose_to_csv_with_extensions(&compose, &[]), compose_to_csv(&compose));
+    }
+
+    #[test]
+    fn test_csv_runtime_extras() {
+        let compose = parse_composefile(Box::new(include_str!("../tests/fixtures/tuning.yml").as_bytes())).unwrap().value;
+        let csv = compose_to_csv(&compose);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[1], "cache,redis:7,7,0,0,false,,0,");
+        assert!(lines[2].starts_with("db,postgres:16,16,0,0,false,,0,\"{\"\"blkio_config\"\":{\"\"weight\"\":300,"));
+        assert_eq!(lines[3], "web,mcr.microsoft.com/windows/servercore/iis,latest,0,0,false,,0,\"{\"\"userns_mode\"\":\"\"host\"\",\"\"isolation\"\":\"\"process\"\"}\"");
+    }
+
+    #[test]
+    fn test_csv_escaping() {
+        let compose = parse_composefile(Box::new("services:\n  \"a,b\":\n    image: 'reg.io/x\"y'\n    restart: always\n".as_bytes())).unwrap().value;
+        assert_eq!(compose_to_csv(&compose).lines().nth(1), Some("\"a,b\",\"reg.io/x\"\"y\",latest,0,0,false,always,0,"));
+
+        let container = crate::parse_dockerfile::parse_containerfile(Box::new("FROM rust:1.75\nVOLUME /data\n".as_bytes()), "app").unwrap();
+        assert_eq!(container.to_csv_row(), "app,rust:1.75,1.75,0,1,false,,0,\n");
+    }
+}
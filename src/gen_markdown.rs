@@ -0,0 +1,314 @@
+//! Markdown documentation of container architectures, for documentation sites and READMEs
+//! rather than modelling tools
+use crate::parse_dockerfile::{ParsedContainer, Protocol};
+#[cfg(feature = "compose")]
+use {
+    std::collections::HashMap,
+    crate::{
+        parse_compose::{Compose, Service},
+        util::image_tag,
+    },
+};
+
+/// Escape a value for use in a table cell
+fn cell(value: &str) -> String {
+    value.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Render a table, or `None.` when it has no rows
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    if rows.is_empty() {
+        return "None.\n".to_string();
+    }
+    let mut out = format!("| {} |\n", headers.join(" | "));
+    out.push_str(&format!("|{}\n", " --- |".repeat(headers.len())));
+    for row in rows {
+        let cells: Vec<String> = row.iter().map(|value| cell(value)).collect();
+        out.push_str(&format!("| {} |\n", cells.join(" | ")));
+    }
+    out
+}
+
+fn protocol_name(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Tcp => "tcp",
+        Protocol::Udp => "udp",
+        Protocol::Sctp => "sctp",
+    }
+}
+
+/// Generate a Markdown document describing a container
+pub fn container_to_markdown(container: &ParsedContainer) -> String {
+    let mut out = format!("# {}\n\n", container.name);
+
+    out.push_str("## Base Image\n\n");
+    out.push_str(&format!("`{}`\n\n", container.base_image));
+
+    out.push_str("## Labels\n\n");
+    let rows: Vec<Vec<String>> = container.labels_sorted().into_iter().map(|(key, value)| vec![key.to_string(), value.to_string()]).collect();
+    out.push_str(&table(&["Label", "Value"], &rows));
+
+    out.push_str("\n## Exposed Ports\n\n");
+    let rows: Vec<Vec<String>> = container
+        .exposed_ports
+        .iter()
+        .map(|port| vec![port.port_number.to_string(), protocol_name(port.protocol).to_string()])
+        .collect();
+    out.push_str(&table(&["Port", "Protocol"], &rows));
+
+    out.push_str("\n## Volumes\n\n");
+    let rows: Vec<Vec<String>> = container.volumes.iter().map(|volume| vec![volume.mount_point.clone()]).collect();
+    out.push_str(&table(&["Mount Point"], &rows));
+
+    out.push_str("\n## Build Metrics\n\n");
+    let rows: Vec<Vec<String>> = container
+        .build_metrics
+        .iter()
+        .map(|metrics| {
+            vec![
+                metrics.stage.clone(),
+                metrics.layer_instructions.to_string(),
+                metrics.mergeable_runs.to_string(),
+                metrics.cache_cleaned.map_or_else(String::new, |cleaned| if cleaned { "yes" } else { "no" }.to_string()),
+            ]
+        })
+        .collect();
+    out.push_str(&table(&["Stage", "Layer Instructions", "Mergeable RUNs", "Package Cache Cleaned"], &rows));
+    out
+}
+
+/// The services a service depends on with their conditions, sorted
+#[cfg(feature = "compose")]
+fn dependencies(service: &Service) -> Vec<(&str, &str)> {
+    let mut dependencies: Vec<(&str, &str)> = service
+        .depends_on_names()
+        .into_iter()
+        .map(|dependency| (dependency, service.depends_on_condition(dependency).unwrap_or("service_started")))
+        .collect();
+    dependencies.sort();
+    dependencies
+}
+
+/// A Mermaid node ID for a service, which can't contain most punctuation
+#[cfg(feature = "compose")]
+fn mermaid_id(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+/// Generate a Mermaid flowchart of the connections between services: solid arrows to the
+/// services each depends on, and dashed grey lines to the networks it's attached to, so the
+/// two kinds of connection can be told apart
+#[cfg(feature = "compose")]
+fn mermaid_connections(services: &[(&str, &Service)]) -> String {
+    let mut out = "```mermaid\ngraph TD\n".to_string();
+    let ids: HashMap<&str, String> = services.iter().map(|(name, _)| (*name, mermaid_id(name))).collect();
+    for (name, _) in services {
+        out.push_str(&format!("    {}[\"{}\"]\n", ids[name], name.replace('"', "#quot;")));
+    }
+    let mut networks: Vec<&str> = services.iter().flat_map(|(_, service)| service.network_names()).collect();
+    networks.sort();
+    networks.dedup();
+    for network in &networks {
+        out.push_str(&format!("    network_{}{{{{\"{}\"}}}}\n", mermaid_id(network), network.replace('"', "#quot;")));
+    }
+    let mut edges = 0;
+    for (name, service) in services {
+        for (dependency, condition) in dependencies(service) {
+            let target = ids.get(dependency).cloned().unwrap_or_else(|| mermaid_id(dependency));
+            match condition {
+                "service_started" => out.push_str(&format!("    {} --> {}\n", ids[name], target)),
+                _ => out.push_str(&format!("    {} -->|{}| {}\n", ids[name], condition, target)),
+            }
+            edges += 1;
+        }
+    }
+    let mut memberships = Vec::new();
+    for (name, service) in services {
+        for network in service.network_names() {
+            out.push_str(&format!("    {} -.- network_{}\n", ids[name], mermaid_id(network)));
+            memberships.push(edges.to_string());
+            edges += 1;
+        }
+    }
+    if !memberships.is_empty() {
+        out.push_str(&format!("    linkStyle {} stroke:#999\n", memberships.join(",")));
+    }
+    out.push_str("```\n");
+    out
+}
+
+/// Generate the section describing one service
+#[cfg(feature = "compose")]
+fn service_section(name: &str, service: &Service) -> String {
+    let mut out = format!("## {}\n\n", name);
+    if let Some(image) = &service.image {
+        out.push_str(&format!("- **Image:** `{}`\n", image));
+    }
+    if let Some(command) = &service.command {
+        out.push_str(&format!("- **Command:** `{}`\n", command.display()));
+    }
+    let networks = service.network_names();
+    if !networks.is_empty() {
+        out.push_str(&format!("- **Networks:** {}\n", networks.join(", ")));
+    }
+    if let Some(replicas) = service.replicas() {
+        out.push_str(&format!("- **Replicas:** {}\n", replicas));
+    }
+    if service.healthcheck.is_some() {
+        out.push_str("- **Healthcheck:** yes\n");
+    }
+    let interactive = service.interactive_flags();
+    if !interactive.is_empty() {
+        out.push_str(&format!("- **Interactive:** {}\n", interactive.join(", ")));
+    }
+    if let Some(pull_policy) = service.pull_policy {
+        out.push_str(&format!("- **Pull Policy:** {}\n", pull_policy));
+    }
+
+    out.push_str("\n### Ports\n\n");
+    let rows: Vec<Vec<String>> = service
+        .port_infos()
+        .iter()
+        .map(|port| {
+            vec![
+                port.container_port.to_string(),
+                protocol_name(port.protocol).to_string(),
+                port.host_port.map_or_else(String::new, |port| port.to_string()),
+                if port.published { "published" } else { "exposed" }.to_string(),
+            ]
+        })
+        .collect();
+    out.push_str(&table(&["Port", "Protocol", "Host Port", "Visibility"], &rows));
+
+    out.push_str("\n### Volumes\n\n");
+    let rows: Vec<Vec<String>> = service.volumes.iter().flatten().map(|volume| vec![volume.clone()]).collect();
+    out.push_str(&table(&["Volume"], &rows));
+
+    // Watch rules describe how files flow into the container during development
+    let rules: Vec<Vec<String>> = service
+        .develop
+        .iter()
+        .flat_map(|develop| develop.watch.iter().flatten())
+        .map(|rule| {
+            vec![
+                rule.path.clone(),
+                rule.action.clone(),
+                rule.target.clone().unwrap_or_default(),
+                rule.ignore.as_deref().unwrap_or_default().join(", "),
+            ]
+        })
+        .collect();
+    if !rules.is_empty() {
+        out.push_str("\n### Watch\n\n");
+        out.push_str(&table(&["Path", "Action", "Target", "Ignore"], &rules));
+    }
+    out
+}
+
+/// Generate a Markdown document describing a compose file: a summary table of its services,
+/// a Mermaid diagram of their dependencies and a section per service
+#[cfg(feature = "compose")]
+pub fn compose_to_markdown(compose: &Compose) -> String {
+    let services: Vec<(&str, &Service)> = compose.services().collect();
+
+    let mut out = "# Services\n\n".to_string();
+    let rows: Vec<Vec<String>> = services
+        .iter()
+        .map(|(name, service)| {
+            let depends_on: Vec<&str> = dependencies(service).into_iter().map(|(dependency, _)| dependency).collect();
+            vec![
+                name.to_string(),
+                service.image.clone().unwrap_or_default(),
+                service.image.as_deref().map(image_tag).unwrap_or_default(),
+                service.port_infos().len().to_string(),
+                depends_on.join(", "),
+            ]
+        })
+        .collect();
+    out.push_str(&table(&["Service", "Image", "Tag", "Ports", "Depends On"], &rows));
+
+    out.push_str("\n## Images\n\n");
+    let rows: Vec<Vec<String>> = compose
+        .image_consumers(&HashMap::new(), true)
+        .into_iter()
+        .map(|(image, consumers)| vec![image, consumers.len().to_string(), consumers.join(", ")])
+        .collect();
+    out.push_str(&table(&["Image", "Consumers", "Services"], &rows));
+
+    out.push_str("\n## Dependencies\n\n");
+    out.push_str(&mermaid_connections(&services));
+
+    for (name, service) in services {
+        out.push('\n');
+        out.push_str(&service_section(name, service));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "compose")]
+    use crate::parse_compose::parse_composefile;
+
+    #[test]
+    fn test_container_to_markdown() {
+        let dockerfile = "FROM rust:1.75\nLABEL version=\"1.0\"\nEXPOSE 8080/tcp\nEXPOSE 53/udp\nVOLUME /data\n";
+        let container = crate::parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "app").unwrap();
+        let markdown = container_to_markdown(&container);
+        assert!(markdown.starts_with("# app\n\n## Base Image\n\n`rust:1.75`\n\n## Labels\n\n| Label | Value |\n| --- | --- |\n| version | 1.0 |\n"));
+        assert!(markdown.contains("## Exposed Ports\n\n| Port | Protocol |\n| --- | --- |\n| 8080 | tcp |\n| 53 | udp |\n"));
+        assert!(markdown.contains("## Volumes\n\n| Mount Point |\n| --- |\n| /data |\n"));
+        assert!(markdown.contains("## Build Metrics\n\n| Stage | Layer Instructions | Mergeable RUNs | Package Cache Cleaned |\n| --- | --- | --- | --- |\n| 0 | 0 | 0 |  |\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "compose")]
+    fn test_compose_to_markdown() {
+        let compose = parse_composefile(Box::new(include_str!("../tests/fixtures/elk.yml").as_bytes())).unwrap().value;
+        let markdown = compose_to_markdown(&compose);
+        assert!(markdown.contains("| Service | Image | Tag | Ports | Depends On |\n"));
+        assert!(markdown.contains("| elasticsearch | elasticsearch:7.16.1 | 7.16.1 | 2 |  |\n"));
+        assert!(markdown.contains("| logstash | logstash:7.16.1 | 7.16.1 | 4 | elasticsearch |\n"));
+        assert!(markdown.contains("```mermaid\ngraph TD\n    elasticsearch[\"elasticsearch\"]\n"));
+        assert!(markdown.contains("## Images\n\n| Image | Consumers | Services |\n| --- | --- | --- |\n| docker.io/elastic/filebeat:7.16.1 | 1 | filebeat |\n"));
+        assert!(markdown.contains("    logstash -->|service_healthy| elasticsearch\n"));
+        assert!(markdown.contains("    kibana --> elasticsearch\n"));
+        // Network memberships are dashed and grey, unlike dependencies
+        assert!(markdown.contains("    network_elastic{{\"elastic\"}}\n"));
+        assert!(markdown.contains("    kibana -.- network_elastic\n"));
+        let edges: Vec<&str> = markdown.lines().filter(|line| line.contains(" --> ") || line.contains(" -->|") || line.contains(" -.- ")).collect();
+        let dashed: Vec<String> = edges.iter().enumerate().filter(|(_, edge)| edge.contains(" -.- ")).map(|(index, _)| index.to_string()).collect();
+        assert!(!dashed.is_empty() && dashed.len() < edges.len());
+        assert!(markdown.contains(&format!("    linkStyle {} stroke:#999\n", dashed.join(","))));
+        assert!(markdown.contains("## nginx\n\n- **Image:** `nginx:1.25`\n- **Networks:** elastic, frontend\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "compose")]
+    fn test_develop_watch() {
+        let compose = parse_composefile(Box::new(include_str!("../tests/fixtures/develop.yml").as_bytes())).unwrap().value;
+        let markdown = compose_to_markdown(&compose);
+        assert!(markdown.contains("## web\n\n- **Image:** `web:dev`\n- **Pull Policy:** build\n"));
+        assert!(markdown.contains(
+            "### Watch\n\n| Path | Action | Target | Ignore |\n| --- | --- | --- | --- |\n| ./src | sync | /app/src | node_modules/, *.tmp |\n| package.json | rebuild |  |  |\n"
+        ));
+        // Services without watch rules have no Watch section
+        assert_eq!(markdown.matches("### Watch").count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "compose")]
+    fn test_interactive_flags() {
+        let yaml = "services:\n  shell:\n    image: alpine\n    stdin_open: true\n    tty: true\n  web:\n    image: nginx\n    tty: false\n";
+        let markdown = compose_to_markdown(&parse_composefile(Box::new(yaml.as_bytes())).unwrap().value);
+        assert!(markdown.contains("## shell\n\n- **Image:** `alpine`\n- **Interactive:** stdin_open, tty\n"));
+        assert_eq!(markdown.matches("**Interactive:**").count(), 1);
+    }
+
+    #[test]
+    fn test_cells_are_escaped() {
+        assert_eq!(table(&["A"], &[vec!["a|b".to_string()]]), "| A |\n| --- |\n| a\\|b |\n");
+        assert_eq!(table(&["A"], &[]), "None.\n");
+    }
+}
@@ -1,76 +1,2779 @@
-use crate::parse_dockerfile;
+use std::{collections::{BTreeSet, HashMap}, fmt, str::FromStr};
+use crate::{
+    parse_dockerfile,
+    provenance::Provenance,
+    sysml_ast::{attribute_redefinition, render_with, Element, RenderStyle, Usage},
+    util::image_tag,
+};
+#[cfg(feature = "compose")]
+use {
+    std::collections::BTreeMap,
+    crate::{parse_compose, util::{format_file_mode, format_number, parse_duration}},
+};
+use crate::sysml_ast::ConnectionKind;
+#[cfg(feature = "pod")]
+use {
+    std::collections::HashSet,
+    crate::{parse_manifests::ParsedManifests, parse_podfile::{self, ParsedPod}, util::dedup_name},
+};
+#[cfg(all(feature = "compose", feature = "pod"))]
+use crate::arch_model::{Component, Reconciliation};
 
-static PACKAGE_HEADER:&str = r#" {
-    import ScalarValues::*;
-    
-    attribute def image;
-    attribute def label;
-    attribute def maintainer;
-    attribute def mountPoint;
+static PACKAGE_HEADER:&str = r#"import ScalarValues::*;
 
-    // Part Definition: Container
-    part def Container {
-        attribute image: String;
-        attribute label: String[0..*];
-        attribute maintainer: String[0..*];
+attribute def image;
+attribute def label;
+attribute def maintainer;
+attribute def mountPoint;
 
-        port networkPorts: NetworkPort[0..*];
-        port volumePorts: VolumePort[0..*];
+// Part Definition: Container
+part def Container {
+    attribute image: String;
+    attribute label: String[0..*];
+    attribute maintainer: String[0..*];
+
+    port networkPorts: NetworkPort[0..*];
+    port volumePorts: VolumePort[0..*];
+}
+
+part def BaseImage {
+    attribute imageName: String;
+}
+
+// Port Definition: NetworkPort
+port def NetworkPort {
+    enum def Protocol {
+        enum UDP;
+        enum TCP;
+        enum SCTP;
     }
 
-    part def BaseImage {
-        attribute imageName: String;
+    attribute protocol: Protocol;
+    attribute portNumber: Integer;
+    // Published on the host, rather than only exposed to other containers
+    attribute published: Boolean[0..1];
+    // The name given to the port, such as http, as written
+    attribute portName: String[0..1];
+}
+
+// Port Definition: VolumePort
+port def VolumePort {
+    attribute mountPoint: String;
+}
+
+"#;
+
+static CONNECTION_DEFS:&str = r#"// Enumeration Definition: ConnectionKind
+// The relationship a connection models, which every connection carries as its kind
+enum def ConnectionKind {
+    enum dependsOn;
+    enum networkMembership;
+    enum networkFlow;
+    enum volumeSharing;
+    enum inferredFlow;
+    enum ingressRoute;
+    enum externalFetch;
+}
+
+// Connection Definition: NetworkConnection
+// Traffic between the network ports of two containers
+connection def NetworkConnection {
+    end source: NetworkPort;
+    end target: NetworkPort;
+    attribute kind: ConnectionKind;
+}
+
+// Connection Definition: VolumeConnection
+// A volume mounted by two containers, shared through their volume ports
+connection def VolumeConnection {
+    end source: VolumePort;
+    end target: VolumePort;
+    attribute kind: ConnectionKind;
+}
+
+"#;
+
+/// The definitions of the connections between the ports of containers
+pub fn sysml_connection_definitions() -> &'static str {
+    CONNECTION_DEFS
+}
+
+/// The definitions every package starts with: the parts and ports of containers, and the
+/// connections between their ports
+fn package_header() -> String {
+    format!("{}{}", PACKAGE_HEADER, sysml_connection_definitions())
+}
+
+static COMPOSE_DEFS:&str = r#"// Part Definition: Healthcheck
+// The command run to check a container is healthy, and how often it's run
+part def Healthcheck {
+    // Whether the command is run directly (CMD) or by the container's shell (CMD_SHELL),
+    // or the image's healthcheck is disabled (NONE)
+    enum def Kind {
+        enum CMD;
+        enum CMD_SHELL;
+        enum NONE;
     }
 
-    // Port Definition: NetworkPort
-    port def NetworkPort {
-        enum def Protocol {
-            enum UDP;
-            enum TCP;
+    attribute kind: Kind[0..1];
+    attribute command: String[0..1];
+    attribute interval: ISQ::DurationValue[0..1];
+    attribute timeout: ISQ::DurationValue[0..1];
+    attribute retries: Integer[0..1];
+    attribute startPeriod: ISQ::DurationValue[0..1];
+}
+
+// Attribute Definition: DeviceRequest
+// Devices such as GPUs the container needs reserved for it
+attribute def DeviceRequest {
+    attribute driver: String[0..1];
+    attribute count: Integer[0..1];
+    attribute allDevices: Boolean;
+    attribute deviceIds: String[0..*];
+    attribute capabilities: String[0..*];
+}
+
+// Attribute Definition: Secret
+// A secret mounted into the container as a file, with its mode in octal
+attribute def Secret {
+    attribute source: String;
+    attribute target: String[0..1];
+    attribute uid: String[0..1];
+    attribute gid: String[0..1];
+    attribute mode: String[0..1];
+}
+
+// Constraint Definition: Healthy
+// Satisfied once the referenced healthcheck passes
+constraint def Healthy {
+    in healthcheck: Healthcheck;
+}
+
+// Part Definition: Network
+part def Network {
+    attribute driver: String[0..1];
+    attribute internal: Boolean[0..1];
+}
+
+// Connection Definition: NetworkMembership
+connection def NetworkMembership {
+    end member: Container;
+    end network: Network;
+    attribute kind: ConnectionKind;
+    attribute aliases: String[0..*];
+    attribute ipv4Address: String[0..1];
+    attribute ipv6Address: String[0..1];
+}
+
+// Connection Definition: Dependency
+connection def Dependency {
+    end dependent: Container;
+    end dependency: Container;
+    attribute kind: ConnectionKind;
+    attribute condition: String;
+}
+
+"#;
+
+static INFERRED_FLOW_DEF:&str = r#"// Connection Definition: InferredFlow
+// Communication inferred from configuration values rather than declared
+connection def InferredFlow {
+    end source: Container;
+    end target: Container;
+    attribute kind: ConnectionKind;
+    attribute inferred: Boolean = true;
+    attribute evidence: String;
+    attribute portNumber: Integer;
+}
+
+"#;
+
+#[cfg(feature = "pod")]
+static MANIFEST_DEFS:&str = r#"// Part Definition: Ingress
+// Routes traffic from outside the cluster to the pods behind Services
+part def Ingress;
+
+// Connection Definition: IngressRoute
+// A route of an Ingress, through a Service, to a container of a pod the Service selects
+connection def IngressRoute {
+    end source: Ingress;
+    end target: Container;
+    attribute kind: ConnectionKind;
+    attribute host: String[0..1];
+    attribute path: String[0..1];
+    attribute service: String;
+    attribute servicePort: Integer;
+    attribute portNumber: Integer;
+    // Whether the NetworkPolicies selecting the pod let traffic from outside the cluster in
+    attribute allowed: Boolean;
+}
+
+"#;
+
+#[cfg(all(feature = "compose", feature = "pod"))]
+static RECONCILE_DEFS:&str = r#"// Part Definition: Deployment
+// How one definition of an application deploys a component
+part def Deployment {
+    // The compose service or Kubernetes workload
+    attribute name: String;
+    attribute image: String[0..1];
+    attribute ports: String[0..*];
+    attribute environmentVariables: String[0..*];
+    // The number of CPUs
+    attribute cpuLimit: Real[0..1];
+    // In bytes
+    attribute memoryLimit: Integer[0..1];
+}
+
+// Part Definition: ReconciledComponent
+// A component as deployed by compose and by Kubernetes, parts named for each, and how the
+// two differ
+part def ReconciledComponent {
+    attribute divergences: String[0..*];
+}
+
+"#;
+
+static BUILD_METRICS_DEF:&str = r#"// Attribute Definition: BuildMetrics
+// How a build stage creates the layers of the image
+attribute def BuildMetrics {
+    attribute stage: String;
+    attribute layerInstructions: Integer;
+    attribute mergeableRuns: Integer;
+    attribute cacheCleaned: Boolean[0..1];
+}
+
+"#;
+
+static EXTERNAL_SOURCE_DEFS:&str = r#"// Part Definition: ExternalSource
+// Content an ADD instruction fetches from outside the build context
+part def ExternalSource {
+    attribute location: String;
+    // url or git
+    attribute sourceKind: String;
+    // The digest given by --checksum, which the build verifies the content against
+    attribute checksum: String[0..1];
+}
+
+// Connection Definition: ExternalFetch
+// Content fetched into the image of a container when it's built
+connection def ExternalFetch {
+    end source: ExternalSource;
+    end target: Container;
+    attribute kind: ConnectionKind;
+    attribute destination: String;
+}
+
+"#;
+
+static METADATA_DEFS:&str = r#"// Metadata Definitions: trace model elements back to their source
+metadata def Dockerfile {
+    attribute line: String;
+}
+metadata def Compose {
+    attribute version: String;
+}
+metadata def Docker {
+    attribute imageTag: String;
+}
+
+"#;
+
+/// How the service parts of a compose model are organised
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Flat, in alphabetical order
+    #[default]
+    None,
+    /// In a package per network, named after the service's first network
+    Network,
+    /// Flat, in topological order with every service after its dependencies
+    Dependency,
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(GroupBy::None),
+            "network" => Ok(GroupBy::Network),
+            "dependency" => Ok(GroupBy::Dependency),
+            _ => Err(format!("unknown grouping '{}', expected network, dependency or none", s)),
         }
+    }
+}
 
-        attribute protocol: Protocol;
-        attribute portNumber: Integer;
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GroupBy::None => write!(f, "none"),
+            GroupBy::Network => write!(f, "network"),
+            GroupBy::Dependency => write!(f, "dependency"),
+        }
     }
+}
 
-    // Port Definition: VolumePort
-    port def VolumePort {
-        attribute mountPoint: String;
+/// Which parts of a model to generate
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    /// Only the shared definitions, as a package to maintain as a library
+    Definitions,
+    /// Only the usages, importing their definitions from a library package
+    Usages,
+    /// The definitions followed by the usages, in one self-contained package
+    #[default]
+    Both,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "definitions" => Ok(Emit::Definitions),
+            "usages" => Ok(Emit::Usages),
+            "both" => Ok(Emit::Both),
+            _ => Err(format!("unknown emit mode '{}', expected definitions, usages or both", s)),
+        }
     }
-    "#;
+}
+
+impl fmt::Display for Emit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Emit::Definitions => write!(f, "definitions"),
+            Emit::Usages => write!(f, "usages"),
+            Emit::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// The package holding the shared definitions when no other is named
+pub const DEFAULT_DEFINITIONS_PACKAGE: &str = "CargotectureDefinitions";
+
+/// The version of the SysML v2 specification generated models conform to when no other
+/// is targeted
+pub const SYSML_VERSION: &str = "2.0";
+
+/// The version of the definitions generated models are built on, changed whenever they change
+pub const METAMODEL_VERSION: &str = "0.1.0";
+
+/// Options controlling SysML generation
+#[derive(Debug, Default, Clone)]
+pub struct SysmlOptions {
+    /// Emit connections for service-to-service flows inferred from environment values
+    pub infer_flows: bool,
+    /// Annotate generated elements with metadata tracing them back to their source
+    pub include_metadata: bool,
+    /// How service parts are packaged and ordered. Never changes the elements generated
+    pub group_by: GroupBy,
+    /// Emit the ports of each part in port number order rather than declaration order
+    pub ports_sorted: bool,
+    /// Put each service part in a package of its own
+    pub per_service_packages: bool,
+    /// Whether to generate the definitions, the usages or both
+    pub emit: Emit,
+    /// The qualified name of the definitions package, such as `Library::Containers`,
+    /// defaulting to `DEFAULT_DEFINITIONS_PACKAGE`
+    pub definitions_package: Option<String>,
+    /// Record where the model came from in a comment at the top of its package
+    pub provenance: Option<Provenance>,
+    /// Emit the environment variables of compose services
+    pub include_environment: bool,
+    /// Fold attribute lists longer than this, such as labels or environment variables,
+    /// into nested groups of at most this many attributes
+    pub max_attrs_per_group: Option<usize>,
+    /// Emit the environment of a service as one multi-line string of `NAME=value` lines
+    /// rather than an attribute per variable
+    pub env_as_table: bool,
+    /// Emit the paths a container's Dockerfile copies files to, works in and declares as
+    /// volumes
+    pub include_filesystem: bool,
+    /// Emit the RUN instructions of a Dockerfile as a sequence of build actions
+    pub include_build_actions: bool,
+    /// Emit a BaseImage part for each distinct image of the compose services, referenced by
+    /// every service using it
+    pub shared_base_images: bool,
+    /// Tell the images of shared BaseImage parts apart by their references as written, so
+    /// that `nginx` and `nginx:latest` get a part each, rather than normalising them
+    pub raw_image_refs: bool,
+    /// Emit the build metrics of each stage of a Dockerfile
+    pub include_metrics: bool,
+    /// The `x-` extension fields of compose services to emit as attributes of their parts
+    pub promote_extensions: Vec<String>,
+    /// The version of the SysML v2 specification to target, defaulting to `SYSML_VERSION`
+    pub sysml_spec_version: Option<String>,
+    /// How the text is laid out, which never changes the model
+    pub style: RenderStyle,
+}
+
+impl SysmlOptions {
+    /// The options affecting the generated model, as `(name, value)` pairs for provenance
+    pub fn effective(&self) -> Vec<(String, String)> {
+        [
+            ("infer_flows", self.infer_flows.to_string()),
+            ("include_metadata", self.include_metadata.to_string()),
+            ("group_by", self.group_by.to_string()),
+            ("ports_sorted", self.ports_sorted.to_string()),
+            ("per_service_packages", self.per_service_packages.to_string()),
+            ("emit", self.emit.to_string()),
+            ("definitions_package", self.definitions_package()),
+            ("include_environment", self.include_environment.to_string()),
+            ("max_attrs_per_group", self.max_attrs_per_group.map_or_else(|| "none".to_string(), |max| max.to_string())),
+            ("env_as_table", self.env_as_table.to_string()),
+            ("include_filesystem", self.include_filesystem.to_string()),
+            ("include_build_actions", self.include_build_actions.to_string()),
+            ("shared_base_images", self.shared_base_images.to_string()),
+            ("raw_image_refs", self.raw_image_refs.to_string()),
+            ("include_metrics", self.include_metrics.to_string()),
+            ("sysml_spec_version", self.sysml_spec_version().to_string()),
+            ("promote_extensions", if self.promote_extensions.is_empty() { "none".to_string() } else { self.promote_extensions.join(",") }),
+            ("style", self.style.to_string()),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+    }
+
+    /// The version of the SysML v2 specification targeted
+    pub fn sysml_spec_version(&self) -> &str {
+        self.sysml_spec_version.as_deref().unwrap_or(SYSML_VERSION)
+    }
+
+    /// The provenance comment, if any, that generated SysML starts with
+    fn provenance_members(&self) -> Vec<Element> {
+        self.provenance.iter().map(|provenance| Element::Block(provenance.sysml_comment())).collect()
+    }
+
+    /// The members a generated package starts with: any provenance comment, then the
+    /// versions of SysML and of the definitions it was generated for
+    fn preamble_members(&self) -> Vec<Element> {
+        let mut members = self.provenance_members();
+        members.push(Element::Block(format!(
+            "/* SysML v{} generated by cargotecture */\nattribute cargotectureMetamodelVersion = \"{}\";\n\n",
+            self.sysml_spec_version().replace("*/", "* /"),
+            METAMODEL_VERSION
+        )));
+        members
+    }
+
+    /// The qualified name of the definitions package, with each segment a SysML name
+    fn definitions_package(&self) -> String {
+        self.definitions_package
+            .as_deref()
+            .unwrap_or(DEFAULT_DEFINITIONS_PACKAGE)
+            .split("::")
+            .map(|segment| sysml_name(segment.trim()))
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+}
+
+/// Generate a metadata annotation such as `@Dockerfile { line = "3"; }` from a
+/// `Metadata.attribute` key
+pub fn sysml_metadata_annotation(key: &str, value: &str) -> String {
+    let (metadata, attribute) = key.split_once('.').unwrap_or((key, "value"));
+    format!("@{} {{ {} = \"{}\"; }}", sysml_name(metadata), sysml_name(attribute), sysml_string(value))
+}
+
+/// Insert an annotation as the first member of every part def in the definitions header
+#[cfg(feature = "compose")]
+fn annotate_part_defs(header: &str, annotation: &str) -> String {
+    let mut annotated = String::new();
+    for line in header.split_inclusive('\n') {
+        annotated.push_str(line);
+        if line.starts_with("part def ") && line.trim_end().ends_with('{') {
+            annotated.push_str(&format!("    {}\n", annotation));
+        }
+    }
+    annotated
+}
+
+/// Render a name as a SysML identifier, quoting it when it isn't a basic name
+pub fn sysml_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let basic = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if basic {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+}
+
+/// Escape a value for use inside a SysML string literal
+pub fn sysml_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Render a value as a SysML string literal
+fn sysml_quoted(value: &str) -> String {
+    format!("\"{}\"", sysml_string(value))
+}
+
+/// Fold a list of attributes into groups named `<prefix>Group1`, `<prefix>Group2`, ... when
+/// it's longer than `SysmlOptions::max_attrs_per_group`, keeping every attribute
+fn attribute_groups(attributes: Vec<Element>, prefix: &str, options: &SysmlOptions) -> Vec<Element> {
+    match options.max_attrs_per_group {
+        Some(max) if max > 0 && attributes.len() > max => attributes
+            .chunks(max)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut group = Usage::new("attribute", &format!("{}Group{}", prefix, index + 1));
+                group.members = chunk.to_vec();
+                group.into()
+            })
+            .collect(),
+        _ => attributes,
+    }
+}
+
+/// Generate the label attributes of a part, sorted by key. Labels folded into groups are
+/// typed as strings, as a group has no `label` of its own to redefine.
+fn sysml_labels(labels: &HashMap<String, String>, options: &SysmlOptions) -> Vec<Element> {
+    let mut labels: Vec<(&String, &String)> = labels.iter().collect();
+    labels.sort();
+    let grouped = options.max_attrs_per_group.is_some_and(|max| max > 0 && labels.len() > max);
+    let attributes = labels
+        .into_iter()
+        .map(|(key, value)| {
+            let attribute = Usage::new("attribute", &sysml_name(key));
+            let attribute = if grouped { attribute.typed("String") } else { attribute.redefining("label") };
+            attribute.valued(&sysml_quoted(value)).into()
+        })
+        .collect();
+    attribute_groups(attributes, "label", options)
+}
+
+/// Generate the environment attributes of a service part, sorted by name
+#[cfg(feature = "compose")]
+fn sysml_environment(environment: &HashMap<String, String>, options: &SysmlOptions) -> Vec<Element> {
+    let mut variables: Vec<(&String, &String)> = environment.iter().collect();
+    variables.sort();
+    if options.env_as_table {
+        let table: Vec<String> = variables.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        return vec![Usage::new("attribute", "environment").typed("String").valued(&sysml_quoted(&table.join("\n"))).into()];
+    }
+    let attributes = variables
+        .into_iter()
+        .map(|(name, value)| Usage::new("attribute", &sysml_name(name)).typed("String").valued(&sysml_quoted(value)).into())
+        .collect();
+    attribute_groups(attributes, "env", options)
+}
+
+/// Generate an attribute group of the paths in a container's filesystem layout, each valued
+/// with the instruction that put it there
+fn sysml_filesystem(container: &parse_dockerfile::ParsedContainer, options: &SysmlOptions) -> Option<Element> {
+    if !options.include_filesystem || container.filesystem.is_empty() {
+        return None;
+    }
+    let mut filesystem = Usage::new("attribute", "filesystem");
+    for entry in &container.filesystem {
+        filesystem.push(Usage::new("attribute", &sysml_name(&entry.path)).typed("String").valued(&sysml_quoted(&entry.instruction)).into());
+    }
+    Some(filesystem.into())
+}
+
+/// A port to be generated as a NetworkPort usage
+struct PortSpec {
+    port_number: u16,
+    protocol: parse_dockerfile::Protocol,
+    /// The Dockerfile line to trace the port to
+    line: Option<usize>,
+    /// Whether a compose port is published on the host, or only exposed to other services
+    published: Option<bool>,
+    /// The name given to the port, such as `http`
+    name: Option<String>,
+}
+
+/// Members of a Container part which a port named after them would clash with
+const RESERVED_PORT_NAMES: &[&str] =
+    &["image", "label", "maintainer", "networkPorts", "volumePorts", "baseImage", "replicaCount", "derivedFrom", "healthcheck", "environment", "filesystem"];
+
+/// Whether a name is one generated for an unnamed member, such as `port3`
+fn is_generated_name(name: &str, prefix: &str) -> bool {
+    name.strip_prefix(prefix).is_some_and(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The identifiers of a list of ports. A named port is identified by its name with the
+/// characters a basic SysML name can't contain replaced by `_`, as `metrics-2` becomes
+/// `metrics_2`. Ports without names, and those whose names can't be made identifiers or
+/// clash with another member, are identified by their index, as `port3`.
+fn port_identifiers<'a>(names: impl IntoIterator<Item = Option<&'a str>>) -> Vec<String> {
+    let mut used = BTreeSet::new();
+    names
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let identifier = name
+                .map(|name| name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect::<String>())
+                .filter(|identifier| identifier.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_'))
+                .filter(|identifier| !RESERVED_PORT_NAMES.contains(&identifier.as_str()))
+                .filter(|identifier| !is_generated_name(identifier, "port") && !is_generated_name(identifier, "volume"))
+                .filter(|identifier| used.insert(identifier.clone()));
+            identifier.unwrap_or_else(|| format!("port{}", index))
+        })
+        .collect()
+}
+
+/// Generate the NetworkPort usages of a part
+fn sysml_ports(mut ports: Vec<PortSpec>, options: &SysmlOptions) -> Vec<Element> {
+    if options.ports_sorted {
+        ports.sort_by_key(|port| (port.port_number, port.protocol));
+    }
+    let identifiers = port_identifiers(ports.iter().map(|port| port.name.as_deref()));
+    ports
+        .iter()
+        .zip(identifiers)
+        .map(|(port, identifier)| {
+            let mut usage = Usage::new("port", &identifier).typed("NetworkPort");
+            if let Some(line) = port.line {
+                usage.push(Element::Line(sysml_metadata_annotation("Dockerfile.line", &line.to_string())));
+            }
+            usage.push(Usage::new("", "protocol").redefining("protocol").valued(&format!("Protocol::{}", port.protocol)).into());
+            usage.push(Usage::new("", "portNumber").redefining("portNumber").valued(&port.port_number.to_string()).into());
+            if let Some(published) = port.published {
+                usage.push(Usage::new("", "published").redefining("published").valued(&published.to_string()).into());
+            }
+            if let Some(name) = &port.name {
+                usage.push(Usage::new("", "portName").redefining("portName").valued(&sysml_quoted(name)).into());
+            }
+            usage.into()
+        })
+        .collect()
+}
+
+/// Generate the VolumePort usages of a part
+fn sysml_volumes(volumes: &[parse_dockerfile::VolumeMount]) -> Vec<Element> {
+    volumes
+        .iter()
+        .enumerate()
+        .map(|(index, volume)| {
+            let mut usage = Usage::new("port", &format!("volume{}", index)).typed("VolumePort");
+            usage.push(Usage::new("", "mountPoint").redefining("mountPoint").valued(&sysml_quoted(&volume.mount_point)).into());
+            usage.into()
+        })
+        .collect()
+}
+
+/// Generate the library package of every definition generated models use, nested in
+/// packages as needed for its qualified name
+pub fn sysml_definitions_package(options: &SysmlOptions) -> String {
+    let qualified = options.definitions_package();
+    let mut segments: Vec<&str> = qualified.split("::").collect();
+    let name = segments.pop().unwrap_or(DEFAULT_DEFINITIONS_PACKAGE);
+    let mut members = options.preamble_members();
+    members.extend(
+        [package_header().as_str(), COMPOSE_DEFS, INFERRED_FLOW_DEF, BUILD_METRICS_DEF, EXTERNAL_SOURCE_DEFS, METADATA_DEFS]
+            .iter()
+            .map(|defs| Element::Block(defs.to_string())),
+    );
+    let mut package = Element::Package { name: name.to_string(), members };
+    for segment in segments.into_iter().rev() {
+        package = Element::Package { name: segment.to_string(), members: vec![package] };
+    }
+    render_with(&[package], &options.style)
+}
+
+/// The members a model package starts with: any provenance, then its definitions or an
+/// import of them from the definitions package when only usages are generated
+fn definition_members(definitions: Vec<Element>, options: &SysmlOptions) -> Vec<Element> {
+    let mut members = options.preamble_members();
+    match options.emit {
+        Emit::Usages => members.push(Element::Block(format!("import {}::*;\n\n", options.definitions_package()))),
+        Emit::Definitions | Emit::Both => members.extend(definitions),
+    }
+    members
+}
+
 /// Generate a SysMLv2 Package for the parsed dockerfile
-pub fn sysml_cargotecture_package(container: &parse_dockerfile::ParsedContainer) -> String {
+pub fn sysml_cargotecture_package(container: &parse_dockerfile::ParsedContainer, options: &SysmlOptions) -> String {
+    if options.emit == Emit::Definitions {
+        return sysml_definitions_package(options);
+    }
+    let mut definitions = vec![Element::Block(package_header())];
+    if options.include_metrics {
+        definitions.push(Element::Block(BUILD_METRICS_DEF.to_string()));
+    }
+    if has_external_sources(container) {
+        definitions.push(Element::Block(EXTERNAL_SOURCE_DEFS.to_string()));
+    }
+    if options.include_metadata {
+        definitions.push(Element::Block(METADATA_DEFS.to_string()));
+    }
+    let mut members = definition_members(definitions, options);
+
+    let mut base = Usage::new("part", &sysml_name(&format!("{}Base", container.name))).typed("BaseImage");
+    if options.include_metadata {
+        base.push(Element::Line(sysml_metadata_annotation("Docker.imageTag", &image_tag(&container.base_image))));
+    }
+    base.push(attribute_redefinition("imageName", &sysml_quoted(&container.base_image)));
+
+    let mut part = Usage::new("part", &sysml_name(&container.name)).typed("Container");
+    part.members.extend(sysml_labels(&container.labels, options));
+    let ports = container
+        .exposed_ports
+        .iter()
+        .map(|port| PortSpec {
+            port_number: port.port_number,
+            protocol: port.protocol,
+            line: port.line.filter(|_| options.include_metadata),
+            published: None,
+            name: None,
+        })
+        .collect();
+    part.members.extend(sysml_ports(ports, options));
+    part.members.extend(sysml_volumes(&container.volumes));
+    part.members.extend(sysml_filesystem(container, options));
+    part.members.extend(sysml_extracted_archives(container));
+    if options.include_metrics {
+        part.members.extend(sysml_build_metrics(&container.build_metrics));
+    }
+
+    let mut system = Usage::new("part", &sysml_name(&format!("{}System", container.name)));
+    system.push(base.into());
+    system.push(part.into());
+    system.members.extend(sysml_external_sources(&container.name, container, &sysml_name(&container.name)));
+    members.push(system.into());
+    if options.include_build_actions {
+        members.extend(build_action_elements(container));
+    }
+
+    render_with(&[Element::Package { name: sysml_name(&format!("{}Model", container.name)), members }], &options.style)
+}
+
+/// Whether any ADD of a container fetches from outside the build
+fn has_external_sources(container: &parse_dockerfile::ParsedContainer) -> bool {
+    container.copies.iter().any(|copy| !copy.remote_sources().is_empty())
+}
+
+/// The ExternalSource parts of what the ADDs of a container fetch from outside the build,
+/// named after `name`, each connected to the container's part `target`
+fn sysml_external_sources(name: &str, container: &parse_dockerfile::ParsedContainer, target: &str) -> Vec<Element> {
+    let mut elements = Vec::new();
+    let sources = container.copies.iter().flat_map(|copy| copy.remote_sources().into_iter().map(move |(source, kind)| (copy, source, kind)));
+    for (index, (copy, source, kind)) in sources.enumerate() {
+        let part_name = sysml_name(&format!("{}Source{}", name, index));
+        let mut part = Usage::new("part", &part_name).typed("ExternalSource");
+        part.push(attribute_redefinition("location", &sysml_quoted(source)));
+        part.push(attribute_redefinition("sourceKind", &sysml_quoted(&kind.to_string())));
+        if let Some(checksum) = &copy.checksum {
+            part.push(attribute_redefinition("checksum", &sysml_quoted(checksum)));
+        }
+        elements.push(part.into());
+        let mut connection = Usage::connection(&sysml_name(&format!("{}Fetch{}", name, index)), ConnectionKind::ExternalFetch, &part_name, target);
+        connection.push(attribute_redefinition("destination", &sysml_quoted(&copy.destination)));
+        elements.push(connection.into());
+    }
+    elements
+}
+
+/// A note on a container of each archive its ADDs likely extract into the image
+fn sysml_extracted_archives(container: &parse_dockerfile::ParsedContainer) -> Vec<Element> {
+    container
+        .copies
+        .iter()
+        .flat_map(|copy| copy.extracted_archives().into_iter().map(move |archive| (copy, archive)))
+        .map(|(copy, archive)| {
+            let note = format!("ADD at line {} likely extracts the archive {} into {}", copy.line, archive, copy.destination);
+            Element::Line(format!("doc /* {} */", note.replace("*/", "* /")))
+        })
+        .collect()
+}
+
+/// The BuildMetrics attributes of the stages of a container, in the order they're built
+fn sysml_build_metrics(build_metrics: &[parse_dockerfile::BuildMetrics]) -> Vec<Element> {
+    build_metrics
+        .iter()
+        .enumerate()
+        .map(|(index, metrics)| {
+            let mut attribute = Usage::new("attribute", &format!("buildMetrics{}", index)).typed("BuildMetrics");
+            attribute.push(attribute_redefinition("stage", &sysml_quoted(&metrics.stage)));
+            attribute.push(attribute_redefinition("layerInstructions", &metrics.layer_instructions.to_string()));
+            attribute.push(attribute_redefinition("mergeableRuns", &metrics.mergeable_runs.to_string()));
+            if let Some(cleaned) = metrics.cache_cleaned {
+                attribute.push(attribute_redefinition("cacheCleaned", &cleaned.to_string()));
+            }
+            attribute.into()
+        })
+        .collect()
+}
+
+/// The action definitions of the build steps of a container, one per RUN instruction, and
+/// of a Build action performing them in order
+fn build_action_elements(container: &parse_dockerfile::ParsedContainer) -> Vec<Element> {
+    if container.run_commands.is_empty() {
+        return Vec::new();
+    }
+    let mut elements = vec![Element::Line("// Action Definitions: Build".to_string())];
+    let mut build = Usage::new("action def", "Build");
+    for (index, command) in container.run_commands.iter().enumerate() {
+        let step = index + 1;
+        let mut definition = Usage::new("action def", &format!("BuildStep{}", step));
+        definition.push(Element::Line(format!("doc /* RUN {} */", command.display().replace("*/", "* /"))));
+        elements.push(definition.into());
+        build.push(Usage::new("action", &format!("step{}", step)).typed(&format!("BuildStep{}", step)).into());
+    }
+    for step in 1..container.run_commands.len() {
+        build.push(Element::Line(format!("first step{} then step{};", step, step + 1)));
+    }
+    elements.push(build.into());
+    elements
+}
+
+/// Generate the build actions of a container: an action definition per RUN instruction,
+/// and a Build action performing them in succession. Empty when there are no RUN instructions.
+pub fn sysml_build_actions(container: &parse_dockerfile::ParsedContainer, style: &RenderStyle) -> String {
+    render_with(&build_action_elements(container), style)
+}
+
+static REQUIREMENT_DEFS:&str = r#"// Requirement Definition: NetworkAccessRequirement
+// A port which must be reachable for the container to do its job
+requirement def NetworkAccessRequirement {
+    attribute requiredPort: Integer;
+    attribute requiredProtocol: NetworkPort::Protocol;
+}
+
+"#;
+
+/// Generate a package of NetworkAccessRequirements, one for each port the container
+/// exposes, each satisfied by the corresponding NetworkPort of the container's part in
+/// the package generated by `sysml_cargotecture_package` with default options, laid out
+/// in the style of `options`
+pub fn sysml_requirement_definitions(container: &parse_dockerfile::ParsedContainer, options: &SysmlOptions) -> String {
+    let model = sysml_name(&format!("{}Model", container.name));
+    let part = format!("{}.{}", sysml_name(&format!("{}System", container.name)), sysml_name(&container.name));
+    let mut members = vec![
+        Element::Block(format!("import {}::*;\n\n", model)),
+        Element::Block(REQUIREMENT_DEFS.to_string()),
+    ];
+    let mut satisfactions = Vec::new();
+    for (index, port) in container.exposed_ports.iter().enumerate() {
+        let name = format!("networkAccess{}", index);
+        let mut requirement = Usage::new("requirement", &name).typed("NetworkAccessRequirement");
+        requirement.push(attribute_redefinition("requiredPort", &port.port_number.to_string()));
+        requirement.push(attribute_redefinition("requiredProtocol", &format!("NetworkPort::Protocol::{}", port.protocol)));
+        members.push(requirement.into());
+        satisfactions.push(Element::Line(format!("satisfy {} by {}.port{};", name, part, index)));
+    }
+    members.extend(satisfactions);
+    render_with(&[Element::Package { name: sysml_name(&format!("{}Requirements", container.name)), members }], &options.style)
+}
+
+/// Generate a part definition of a pod, `<name>Pod`, with a Container part for each of its
+/// containers. Init containers are marked `#init`, and the volume ports of containers mounting
+/// the same volume are bound together. Volume ports are named after the volumes they mount.
+/// Only the provenance and style of `options` apply.
+#[cfg(feature = "pod")]
+pub fn sysml_part_hierarchy(pod: &ParsedPod, options: &SysmlOptions) -> String {
+    let mut elements = options.provenance_members();
+    if pod.containers.iter().any(|container| container.init) {
+        elements.extend(init_metadata_definition());
+    }
+    elements.push(sysml_pod_definition(pod));
+    render_with(&elements, &options.style)
+}
+
+/// The definition of the metadata marking init containers
+#[cfg(feature = "pod")]
+fn init_metadata_definition() -> Vec<Element> {
+    vec![
+        Element::Line("// Metadata Definition: init".to_string()),
+        Element::Line("// Marks a container run to completion before the others in its pod start".to_string()),
+        Element::Block("metadata def init;\n\n".to_string()),
+    ]
+}
+
+#[cfg(feature = "pod")]
+fn sysml_pod_definition(pod: &ParsedPod) -> Element {
+    let mut definition = Usage::new("part def", &sysml_name(&format!("{}Pod", pod.name)));
+    // The port of each container mounting each volume, by container and volume name
+    let mut ports: HashMap<(&str, &str), String> = HashMap::new();
+    for container in &pod.containers {
+        let keyword = if container.init { "#init part" } else { "part" };
+        let mut part = Usage::new(keyword, &sysml_name(&container.name)).typed("Container");
+        part.multiplicity = Some("1".to_string());
+        if let Some(image) = &container.image {
+            part.push(attribute_redefinition("image", &sysml_quoted(image)));
+        }
+        let mut used: HashSet<String> = RESERVED_PORT_NAMES.iter().map(|name| name.to_lowercase()).collect();
+        for mount in &container.volume_mounts {
+            let identifier = sysml_name(&dedup_name(&mut used, &mount.name));
+            let mut port = Usage::new("port", &identifier).typed("VolumePort");
+            port.push(Usage::new("", "mountPoint").redefining("mountPoint").valued(&sysml_quoted(&mount.mount_path)).into());
+            part.push(port.into());
+            ports.entry((&container.name, &mount.name)).or_insert(format!("{}.{}", sysml_name(&container.name), identifier));
+        }
+        definition.push(part.into());
+    }
+    for (volume, containers) in pod.shared_volumes() {
+        let port = |container: &parse_podfile::PodContainer| ports[&(container.name.as_str(), volume.name.as_str())].clone();
+        for other in &containers[1..] {
+            definition.push(Element::Line(format!("bind {} = {};", port(containers[0]), port(other))));
+        }
+    }
+    definition.into()
+}
+
+/// Generate a SysMLv2 package for Kubernetes manifests: the part definition of each pod, as
+/// `sysml_part_hierarchy` generates, and a `<name>Cluster` part with a part for each pod and
+/// Ingress. Each route of an Ingress is connected to the container of each pod its Service
+/// selects, saying whether NetworkPolicies let the traffic in, and the pods NetworkPolicies
+/// select have attributes naming them and saying which way they isolate the pod. Only the
+/// provenance and style of `options` apply.
+#[cfg(feature = "pod")]
+pub fn sysml_manifests_package(manifests: &ParsedManifests, name: &str, options: &SysmlOptions) -> String {
+    let mut members = options.provenance_members();
+    members.extend([Element::Block(package_header()), Element::Block(MANIFEST_DEFS.to_string())]);
+    if manifests.pods.iter().flat_map(|pod| &pod.containers).any(|container| container.init) {
+        members.extend(init_metadata_definition());
+    }
+    members.extend(manifests.pods.iter().map(sysml_pod_definition));
+
+    let mut cluster = Usage::new("part", &sysml_name(&format!("{}Cluster", name)));
+    let mut used = HashSet::new();
+    let mut pods: HashMap<&str, String> = HashMap::new();
+    for pod in &manifests.pods {
+        let identifier = sysml_name(&dedup_name(&mut used, &pod.name));
+        let mut part = Usage::new("part", &identifier).typed(&sysml_name(&format!("{}Pod", pod.name)));
+        let policies = manifests.policies_selecting(pod);
+        if !policies.is_empty() {
+            let names: Vec<String> = policies.iter().map(|policy| policy.name.clone()).collect();
+            let mut attribute = Usage::new("attribute", "networkPolicies").typed("String").valued(&sysml_string_sequence(&names));
+            attribute.multiplicity = Some("0..*".to_string());
+            part.push(attribute.into());
+            for (attribute, isolates) in [
+                ("ingressIsolated", policies.iter().any(|policy| policy.isolates_ingress)),
+                ("egressIsolated", policies.iter().any(|policy| policy.isolates_egress)),
+            ] {
+                part.push(Usage::new("attribute", attribute).typed("Boolean").valued(&isolates.to_string()).into());
+            }
+        }
+        cluster.push(part.into());
+        pods.insert(&pod.name, identifier);
+    }
+    let mut ingresses: HashMap<&str, String> = HashMap::new();
+    for ingress in &manifests.ingresses {
+        let identifier = sysml_name(&dedup_name(&mut used, &ingress.name));
+        cluster.push(Usage::new("part", &identifier).typed("Ingress").into());
+        ingresses.insert(&ingress.name, identifier);
+    }
+    for (index, resolved) in manifests.resolved_routes().iter().enumerate() {
+        let target = format!("{}.{}", pods[resolved.pod.name.as_str()], sysml_name(&resolved.container.name));
+        let mut connection = Usage::connection(&format!("ingressRoute{}", index), ConnectionKind::IngressRoute, &ingresses[resolved.ingress.name.as_str()], &target);
+        for (attribute, value) in [("host", &resolved.route.host), ("path", &resolved.route.path)] {
+            if let Some(value) = value {
+                connection.push(attribute_redefinition(attribute, &sysml_quoted(value)));
+            }
+        }
+        connection.push(attribute_redefinition("service", &sysml_quoted(&resolved.service.name)));
+        connection.push(attribute_redefinition("servicePort", &resolved.service_port.port.to_string()));
+        connection.push(attribute_redefinition("portNumber", &resolved.port.to_string()));
+        connection.push(attribute_redefinition("allowed", &resolved.allowed.to_string()));
+        cluster.push(connection.into());
+    }
+    members.push(cluster.into());
+    render_with(&[Element::Package { name: sysml_name(name), members }], &options.style)
+}
+
+/// Generate SysML for Kubernetes manifests: the part definition of a pod when they're of a
+/// single pod and nothing else, or otherwise a package of them all
+#[cfg(feature = "pod")]
+pub fn sysml_kubernetes(manifests: &ParsedManifests, name: &str, options: &SysmlOptions) -> String {
+    match manifests.single_pod() {
+        Some(pod) => sysml_part_hierarchy(pod, options),
+        None => sysml_manifests_package(manifests, name, options),
+    }
+}
+
+/// Generate the deployment part, named for its environment, of a reconciled component
+#[cfg(all(feature = "compose", feature = "pod"))]
+fn sysml_deployment(environment: &str, name: &str, component: &Component) -> Element {
+    let mut part = Usage::new("part", environment).typed("Deployment");
+    part.push(attribute_redefinition("name", &sysml_quoted(name)));
+    if let Some(image) = &component.image {
+        part.push(attribute_redefinition("image", &sysml_quoted(image)));
+    }
+    let ports: Vec<String> = component.ports.iter().map(|port| port.to_string()).collect();
+    part.push(attribute_redefinition("ports", &sysml_string_sequence(&ports)));
+    let variables: Vec<String> = component.environment.iter().cloned().collect();
+    part.push(attribute_redefinition("environmentVariables", &sysml_string_sequence(&variables)));
+    if let Some(limit) = component.cpu_limit {
+        part.push(attribute_redefinition("cpuLimit", &format_number(limit as f64 / 1000.0)));
+    }
+    if let Some(limit) = component.memory_limit {
+        part.push(attribute_redefinition("memoryLimit", &limit.to_string()));
+    }
+    part.into()
+}
+
+/// Generate a package modelling an application as both compose and Kubernetes deploy it:
+/// a part for each component they both deploy, with each one's attributes and how they
+/// diverge, and the components only one of them deploys. Only the provenance and style of
+/// `options` apply.
+#[cfg(all(feature = "compose", feature = "pod"))]
+pub fn sysml_reconciliation(reconciliation: &Reconciliation, name: &str, options: &SysmlOptions) -> String {
+    let mut members = options.provenance_members();
+    members.extend([Element::Block(PACKAGE_HEADER.to_string()), Element::Block(RECONCILE_DEFS.to_string())]);
+    let mut application = Usage::new("part", &sysml_name(&format!("{}Application", name)));
+    for matched in &reconciliation.matches {
+        let mut part = Usage::new("part", &sysml_name(&matched.compose)).typed("ReconciledComponent");
+        part.push(sysml_deployment("compose", &matched.compose, &matched.compose_component));
+        part.push(sysml_deployment("kubernetes", &matched.kubernetes, &matched.kubernetes_component));
+        let divergences: Vec<String> = matched.divergences.iter().map(ToString::to_string).collect();
+        part.push(attribute_redefinition("divergences", &sysml_string_sequence(&divergences)));
+        application.push(part.into());
+    }
+    for (attribute, unmatched) in [("unmatchedCompose", &reconciliation.unmatched_compose), ("unmatchedKubernetes", &reconciliation.unmatched_kubernetes)] {
+        let mut attribute = Usage::new("attribute", attribute).typed("String").valued(&sysml_string_sequence(unmatched));
+        attribute.multiplicity = Some("0..*".to_string());
+        application.push(attribute.into());
+    }
+    members.push(application.into());
+    render_with(&[Element::Package { name: sysml_name(&format!("{}Reconciled", name)), members }], &options.style)
+}
+
+/// Generate a package importing each of the packages emitted to separate files, given as
+/// `(package, file name)` pairs
+pub fn sysml_index_package(name: &str, packages: &[(String, String)], style: &RenderStyle) -> String {
+    let members = packages
+        .iter()
+        .map(|(package, file_name)| Element::Line(format!("import {}::*; // {}", package, file_name)))
+        .collect();
+    render_with(&[Element::Package { name: sysml_name(&format!("{}Index", name)), members }], style)
+}
+
+/// A compose duration as a SysML duration value in seconds, such as `3 [SI::s]`
+#[cfg(feature = "compose")]
+fn sysml_duration(value: &str) -> Option<String> {
+    let duration = parse_duration(value)?;
+    Some(format!("{} [SI::s]", format_number(duration.as_secs_f64())))
+}
+
+/// Generate the healthcheck part of a service part. Durations which don't parse are left
+/// out, as validation reports them.
+#[cfg(feature = "compose")]
+fn sysml_healthcheck(healthcheck: &parse_compose::Healthcheck) -> Element {
+    let mut part = Usage::new("part", "healthcheck").typed("Healthcheck");
+    let (kind, command) = match healthcheck.test.split_first() {
+        Some((kind, command)) if ["CMD", "CMD-SHELL", "NONE"].contains(&kind.as_str()) => (Some(kind.replace('-', "_")), command),
+        _ => (None, healthcheck.test.as_slice()),
+    };
+    if let Some(kind) = kind {
+        part.push(attribute_redefinition("kind", &format!("Healthcheck::Kind::{}", kind)));
+    }
+    if !command.is_empty() {
+        part.push(attribute_redefinition("command", &sysml_quoted(&command.join(" "))));
+    }
+    for (name, value) in [("interval", &healthcheck.interval), ("timeout", &healthcheck.timeout)] {
+        if let Some(duration) = value.as_deref().and_then(sysml_duration) {
+            part.push(attribute_redefinition(name, &duration));
+        }
+    }
+    if let Some(retries) = healthcheck.retries {
+        part.push(attribute_redefinition("retries", &retries.to_string()));
+    }
+    if let Some(duration) = healthcheck.start_period.as_deref().and_then(sysml_duration) {
+        part.push(attribute_redefinition("startPeriod", &duration));
+    }
+    part.into()
+}
+
+/// Generate the DeviceRequest attributes of a service part, leaving out requests which
+/// repeat an earlier one in another form
+#[cfg(feature = "compose")]
+fn sysml_device_requests(service: &parse_compose::Service) -> Vec<Element> {
+    let mut requests: Vec<parse_compose::DeviceRequest> = Vec::new();
+    for request in service.device_requests() {
+        if !requests.iter().any(|r| parse_compose::DeviceRequest { source: r.source, ..request.clone() } == *r) {
+            requests.push(request);
+        }
+    }
+    requests
+        .iter()
+        .enumerate()
+        .map(|(index, request)| {
+            let mut attribute = Usage::new("attribute", &format!("deviceRequest{}", index)).typed("DeviceRequest");
+            if let Some(driver) = &request.driver {
+                attribute.push(attribute_redefinition("driver", &sysml_quoted(driver)));
+            }
+            if let (Some(parse_compose::DeviceCount::Count(count)), false) = (&request.count, request.all_devices()) {
+                attribute.push(attribute_redefinition("count", &count.to_string()));
+            }
+            attribute.push(attribute_redefinition("allDevices", &request.all_devices().to_string()));
+            if !request.device_ids.is_empty() {
+                attribute.push(attribute_redefinition("deviceIds", &sysml_string_sequence(&request.device_ids)));
+            }
+            if !request.capabilities.is_empty() {
+                attribute.push(attribute_redefinition("capabilities", &sysml_string_sequence(&request.capabilities)));
+            }
+            attribute.into()
+        })
+        .collect()
+}
+
+/// Generate the Secret attributes of a service part
+#[cfg(feature = "compose")]
+fn sysml_secrets(service: &parse_compose::Service) -> Vec<Element> {
+    service
+        .secrets
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(index, secret)| {
+            let mut attribute = Usage::new("attribute", &format!("secret{}", index)).typed("Secret");
+            attribute.push(attribute_redefinition("source", &sysml_quoted(&secret.source)));
+            for (name, value) in [("target", &secret.target), ("uid", &secret.uid), ("gid", &secret.gid)] {
+                if let Some(value) = value {
+                    attribute.push(attribute_redefinition(name, &sysml_quoted(value)));
+                }
+            }
+            if let Some(mode) = secret.mode {
+                attribute.push(attribute_redefinition("mode", &sysml_quoted(&format_file_mode(mode))));
+            }
+            attribute.into()
+        })
+        .collect()
+}
+
+/// The marker of an image compose builds rather than pulls, naming the Dockerfile it's built from
+#[cfg(feature = "compose")]
+fn to_be_built(build: &parse_compose::Build) -> Element {
+    let dockerfile = build.dockerfile_path(None).display().to_string();
+    Element::Line(format!("doc /* «to-be-built» from {} */", dockerfile.replace("*/", "* /")))
+}
+
+/// Generate the Container part of a compose service, including the ports and volumes of
+/// the container built for it if known
+#[cfg(feature = "compose")]
+fn sysml_service(
+    name: &str,
+    service: &parse_compose::Service,
+    container: Option<&parse_dockerfile::ParsedContainer>,
+    base_image: Option<&str>,
+    options: &SysmlOptions,
+) -> Element {
+    let mut part = Usage::new("part", &sysml_name(name)).typed("Container");
+    part.multiplicity = service.replicas().map(|replicas| replicas.to_string());
+    if let (true, Some(image)) = (options.include_metadata, &service.image) {
+        part.push(Element::Line(sysml_metadata_annotation("Docker.imageTag", &image_tag(image))));
+    }
+    match (&service.image, &service.build) {
+        (Some(image), _) => part.push(attribute_redefinition("image", &sysml_quoted(image))),
+        (None, Some(build)) => {
+            let marker = to_be_built(build);
+            match base_image {
+                Some(_) => part.push(marker),
+                None => {
+                    let mut base = Usage::new("part", "baseImage").typed("BaseImage");
+                    base.push(marker);
+                    if let Some(container) = container {
+                        base.push(attribute_redefinition("imageName", &sysml_quoted(&container.base_image)));
+                    }
+                    part.push(base.into());
+                }
+            }
+        }
+        // Only generated when the validation error for it is ignored, so say why it's empty
+        (None, None) => part.push(Element::Line("doc /* No image or build is configured, so the image is unknown */".to_string())),
+    }
+    if let Some(base_image) = base_image {
+        part.push(Usage::new("ref part", "baseImage").typed("BaseImage").valued(base_image).into());
+    }
+    if let Some(replicas) = service.replicas() {
+        part.push(Usage::new("attribute", "replicaCount").typed("Integer").valued(&replicas.to_string()).into());
+    }
+    if let Some(source) = &service.derived_from {
+        part.push(Usage::new("attribute", "derivedFrom").typed("String").valued(&sysml_quoted(source)).into());
+    }
+    for (name, flag) in [("stdinOpen", service.stdin_open), ("tty", service.tty)] {
+        if let Some(flag) = flag {
+            part.push(Usage::new("attribute", name).typed("Boolean").valued(&flag.to_string()).into());
+        }
+    }
+    for (name, bytes) in [("shmSizeBytes", service.shm_size), ("memLimitBytes", service.mem_limit)] {
+        if let Some(bytes) = bytes {
+            part.push(Usage::new("attribute", name).typed("Integer").valued(&bytes.to_string()).into());
+        }
+    }
+    for (key, value) in service.promoted_extensions(&options.promote_extensions) {
+        part.push(Usage::new("attribute", &sysml_name(key)).typed("String").valued(&sysml_quoted(&value)).into());
+    }
+    if let Some(labels) = &service.labels {
+        part.members.extend(sysml_labels(labels, options));
+    }
+    if let (true, Some(environment)) = (options.include_environment, &service.environment) {
+        part.members.extend(sysml_environment(environment, options));
+    }
+    let ports = service
+        .exposed_ports(container)
+        .iter()
+        .map(|port| PortSpec {
+            port_number: port.container_port,
+            protocol: port.protocol,
+            line: None,
+            published: Some(port.published),
+            name: port.name.clone(),
+        })
+        .collect();
+    part.members.extend(sysml_ports(ports, options));
+    let mut volumes = service.mount_points();
+    for volume in container.iter().flat_map(|c| c.volumes.iter()) {
+        if !volumes.iter().any(|v| v.mount_point == volume.mount_point) {
+            volumes.push(parse_dockerfile::VolumeMount { mount_point: volume.mount_point.clone() });
+        }
+    }
+    part.members.extend(sysml_volumes(&volumes));
+    part.members.extend(container.and_then(|container| sysml_filesystem(container, options)));
+    part.members.extend(container.iter().flat_map(|container| sysml_extracted_archives(container)));
+    if let Some(healthcheck) = &service.healthcheck {
+        part.push(sysml_healthcheck(healthcheck));
+    }
+    part.members.extend(sysml_device_requests(service));
+    part.members.extend(sysml_secrets(service));
+    part.into()
+}
+
+/// Generate a package holding the Container part of a compose service, importing the
+/// shared definitions from the enclosing model package
+#[cfg(feature = "compose")]
+fn service_package(
+    name: &str,
+    service: &parse_compose::Service,
+    container: Option<&parse_dockerfile::ParsedContainer>,
+    base_image: Option<&str>,
+    model: &str,
+    options: &SysmlOptions,
+) -> Element {
+    let mut members = vec![Element::Line(format!("import {}::*;", model))];
+    // Imports aren't re-exported, so the definitions have to be imported directly too
+    if options.emit == Emit::Usages {
+        members.push(Element::Line(format!("import {}::*;", options.definitions_package())));
+    }
+    members.push(sysml_service(name, service, container, base_image, options));
+    Element::Package { name: sysml_name(name), members }
+}
+
+/// Generate the package of a single compose service, as generated with
+/// `SysmlOptions::per_service_packages` inside the package named `model`
+#[cfg(feature = "compose")]
+pub fn sysml_package_for_service(name: &str, service: &parse_compose::Service, model: &str, options: &SysmlOptions) -> String {
+    render_with(&[service_package(name, service, None, None, &sysml_name(model), options)], &options.style)
+}
 
-    let mut package=format!("package {}Model",container.name);
-    package.push_str(PACKAGE_HEADER);
-    package.push_str(&format!("part {}System {{\n", container.name));
-    package.push_str(&format!("        part {}Base: BaseImage {{\n",container.name));
-    package.push_str(&format!("                attribute imageName redefines imageName = \"{}\";\n", container.base_image));
-    package.push_str("            }\n");
+/// The SysML name of the part representing a compose network
+#[cfg(feature = "compose")]
+fn network_part_name(network: &str) -> String {
+    sysml_name(&format!("{}Network", network))
+}
 
-    package.push_str(&format!("        part {}: Container {{\n", container.name));
-    
-    for (key, value) in &container.labels {
-        package.push_str(&format!("            attribute {} redefines label = \"{}\";\n", key, value));
+/// The SysML name of the package holding the services grouped under a network
+#[cfg(feature = "compose")]
+fn network_package_name(network: &str) -> String {
+    sysml_name(&format!("{}Services", network))
+}
+
+/// Render a list of strings as a SysML sequence literal
+#[cfg(any(feature = "compose", feature = "pod"))]
+fn sysml_string_sequence(values: &[String]) -> String {
+    let quoted: Vec<String> = values.iter().map(|v| sysml_quoted(v)).collect();
+    format!("({})", quoted.join(", "))
+}
+
+/// Generate the names resolvable on a network, each valued with the services claiming it
+#[cfg(feature = "compose")]
+fn sysml_network_names(names: &BTreeMap<String, BTreeSet<String>>) -> Element {
+    let mut group = Usage::new("attribute", "names");
+    for (name, services) in names {
+        let services: Vec<String> = services.iter().cloned().collect();
+        let mut attribute = Usage::new("attribute", &sysml_name(name)).typed("String");
+        if services.len() > 1 {
+            attribute.multiplicity = Some(services.len().to_string());
+            attribute = attribute.valued(&sysml_string_sequence(&services));
+        } else {
+            attribute = attribute.valued(&sysml_quoted(&services[0]));
+        }
+        group.push(attribute.into());
+    }
+    group.into()
+}
+
+/// Generate the Network parts of a compose file and the memberships of its services
+#[cfg(feature = "compose")]
+fn sysml_networks(compose: &parse_compose::Compose, service_names: &[&String], refs: &HashMap<&str, String>) -> Vec<Element> {
+    let mut elements = Vec::new();
+    let declared = compose.networks.as_ref();
+    let mut network_names: Vec<&str> = declared.iter().flat_map(|n| n.keys()).map(String::as_str).collect();
+    for service_name in service_names {
+        network_names.extend(compose.services[*service_name].network_names());
+    }
+    network_names.sort();
+    network_names.dedup();
+
+    let name_table = compose.name_table();
+    for network_name in &network_names {
+        let mut part = Usage::new("part", &network_part_name(network_name)).typed("Network");
+        if let Some(network) = declared.and_then(|n| n.get(*network_name)) {
+            if let Some(driver) = &network.driver {
+                part.push(attribute_redefinition("driver", &sysml_quoted(driver)));
+            }
+            if let Some(internal) = network.internal {
+                part.push(attribute_redefinition("internal", &internal.to_string()));
+            }
+        }
+        part.members.extend(name_table.get(*network_name).map(sysml_network_names));
+        elements.push(part.into());
+    }
+
+    let mut membership_index = 0;
+    for service_name in service_names {
+        let service = &compose.services[*service_name];
+        for network_name in service.network_names() {
+            let mut connection = Usage::connection(
+                &format!("membership{}", membership_index),
+                ConnectionKind::NetworkMembership,
+                &refs[service_name.as_str()],
+                &network_part_name(network_name),
+            );
+            let options = service.networks.as_ref().and_then(|n| n.get(network_name)).and_then(Option::as_ref);
+            if let Some(options) = options {
+                if let Some(aliases) = &options.aliases {
+                    connection.push(attribute_redefinition("aliases", &sysml_string_sequence(aliases)));
+                }
+                if let Some(address) = options.ipv4_address {
+                    connection.push(attribute_redefinition("ipv4Address", &sysml_quoted(&address.to_string())));
+                }
+                if let Some(address) = options.ipv6_address {
+                    connection.push(attribute_redefinition("ipv6Address", &sysml_quoted(&address.to_string())));
+                }
+            }
+            elements.push(connection.into());
+            membership_index += 1;
+        }
+    }
+    elements
+}
+
+/// Generate the Dependency connections between services
+#[cfg(feature = "compose")]
+fn sysml_dependencies(compose: &parse_compose::Compose, service_names: &[&String], refs: &HashMap<&str, String>) -> Vec<Element> {
+    let mut elements = Vec::new();
+    let mut dependency_index = 0;
+    for service_name in service_names {
+        let service = &compose.services[*service_name];
+        let mut dependencies = service.depends_on_names();
+        dependencies.sort();
+        for dependency in dependencies {
+            let condition = service.depends_on_condition(dependency).unwrap_or("service_started");
+            let target = refs.get(dependency).cloned().unwrap_or_else(|| sysml_name(dependency));
+            let mut connection =
+                Usage::connection(&format!("dependency{}", dependency_index), ConnectionKind::DependsOn, &refs[service_name.as_str()], &target);
+            connection.push(attribute_redefinition("condition", &sysml_quoted(condition)));
+            let target_healthy = compose.service(dependency).is_some_and(|s| s.healthcheck.is_some());
+            if condition == "service_healthy" && target_healthy {
+                connection.push(Element::Line(format!(
+                    "assert constraint healthy: Healthy {{ in healthcheck = {}.healthcheck; }}",
+                    target
+                )));
+            }
+            elements.push(connection.into());
+            dependency_index += 1;
+        }
+    }
+    elements
+}
+
+/// Generate the InferredFlow connections between services
+#[cfg(feature = "compose")]
+fn sysml_inferred_flows(compose: &parse_compose::Compose, refs: &HashMap<&str, String>) -> Vec<Element> {
+    compose
+        .infer_flows()
+        .iter()
+        .enumerate()
+        .map(|(index, flow)| {
+            let mut connection = Usage::connection(
+                &format!("inferredFlow{}", index),
+                ConnectionKind::InferredFlow,
+                &refs[flow.source.as_str()],
+                &refs[flow.target.as_str()],
+            );
+            connection.push(attribute_redefinition("evidence", &sysml_quoted(&flow.evidence)));
+            connection.push(attribute_redefinition("portNumber", &flow.port.to_string()));
+            connection.into()
+        })
+        .collect()
+}
+
+/// Generate NetworkConnections from each service to the services it depends on over a shared
+/// network, where a port the dependent maps matches a port its dependency exposes. Ports are
+/// named as in the parts `sysml_compose_package` generates with the default options.
+#[cfg(feature = "compose")]
+pub fn sysml_flow_connections(compose: &parse_compose::Compose, style: &RenderStyle) -> String {
+    let mut flows = Vec::new();
+    for (service_name, service) in compose.services() {
+        let networks = service.attached_networks();
+        let ports = service.exposed_ports(None);
+        let identifiers = port_identifiers(ports.iter().map(|port| port.name.as_deref()));
+        let mut dependencies = service.depends_on_names();
+        dependencies.sort();
+        for dependency in dependencies {
+            let Some(provider) = compose.service(dependency) else {
+                continue;
+            };
+            if !provider.attached_networks().iter().any(|network| networks.contains(network)) {
+                continue;
+            }
+            let provided_ports = provider.exposed_ports(None);
+            let provided_identifiers = port_identifiers(provided_ports.iter().map(|port| port.name.as_deref()));
+            for (target, provided) in provided_ports.iter().enumerate() {
+                let source = ports.iter().position(|port| {
+                    port.protocol == provided.protocol
+                        && (port.container_port == provided.container_port || port.host_port == Some(provided.container_port))
+                });
+                if let Some(source) = source {
+                    flows.push(
+                        Usage::connection(
+                            &format!("networkConnection{}", flows.len()),
+                            ConnectionKind::NetworkFlow,
+                            &format!("{}.{}", sysml_name(service_name), identifiers[source]),
+                            &format!("{}.{}", sysml_name(dependency), provided_identifiers[target]),
+                        )
+                        .into(),
+                    );
+                }
+            }
+        }
+    }
+    render_with(&flows, style)
+}
+
+/// Generate a SysMLv2 Package for a parsed compose file, with one Container part per service
+#[cfg(feature = "compose")]
+pub fn sysml_compose_package(compose: &parse_compose::Compose, name: &str, options: &SysmlOptions) -> String {
+    sysml_compose_package_with_containers(compose, name, &HashMap::new(), options)
+}
+
+/// Generate a SysMLv2 Package for a parsed compose file as `sysml_compose_package` does,
+/// adding the ports and volumes of the containers built for services, keyed by service name
+#[cfg(feature = "compose")]
+pub fn sysml_compose_package_with_containers(
+    compose: &parse_compose::Compose,
+    name: &str,
+    containers: &HashMap<String, parse_dockerfile::ParsedContainer>,
+    options: &SysmlOptions,
+) -> String {
+    if options.emit == Emit::Definitions {
+        return sysml_definitions_package(options);
+    }
+    let mut definitions = Vec::new();
+    match (options.include_metadata, &compose.version) {
+        (true, Some(version)) => definitions.push(Element::Block(annotate_part_defs(
+            &package_header(),
+            &sysml_metadata_annotation("Compose.version", version),
+        ))),
+        _ => definitions.push(Element::Block(package_header())),
+    }
+    definitions.push(Element::Block(COMPOSE_DEFS.to_string()));
+    if options.include_metadata {
+        definitions.push(Element::Block(METADATA_DEFS.to_string()));
+    }
+    if options.infer_flows {
+        definitions.push(Element::Block(INFERRED_FLOW_DEF.to_string()));
+    }
+    if containers.iter().any(|(service, container)| compose.services.contains_key(service) && has_external_sources(container)) {
+        definitions.push(Element::Block(EXTERNAL_SOURCE_DEFS.to_string()));
+    }
+    let mut members = definition_members(definitions, options);
+
+    // Connections are always enumerated alphabetically, so that grouping never changes them
+    let mut service_names: Vec<&String> = compose.services.keys().collect();
+    service_names.sort();
+
+    let model = sysml_name(&format!("{}Model", name));
+    let order: Vec<&str> = match options.group_by {
+        // A dependency cycle is reported by validation, so fall back to alphabetical order
+        GroupBy::Dependency => compose
+            .dependency_order()
+            .unwrap_or_else(|_| service_names.iter().map(|n| n.as_str()).collect()),
+        GroupBy::None | GroupBy::Network => service_names.iter().map(|n| n.as_str()).collect(),
+    };
+
+    let system_name = sysml_name(&format!("{}System", name));
+    let mut system = Usage::new("part", &system_name);
+    // The shared BaseImage parts, and the qualified name of the part each service uses,
+    // which resolves from the service packages and network packages too
+    let mut base_images: HashMap<&str, String> = HashMap::new();
+    if options.shared_base_images {
+        for (index, (image, consumers)) in compose.image_consumers(containers, !options.raw_image_refs).into_iter().enumerate() {
+            let part_name = format!("baseImage{}", index);
+            let mut base = Usage::new("part", &part_name).typed("BaseImage");
+            base.push(attribute_redefinition("imageName", &sysml_quoted(&image)));
+            base.push(Usage::new("attribute", "consumerCount").typed("Integer").valued(&consumers.len().to_string()).into());
+            system.push(base.into());
+            for consumer in consumers {
+                base_images.insert(consumer, format!("{}::{}", system_name, part_name));
+            }
+        }
+    }
+    let mut refs: HashMap<&str, String> = HashMap::new();
+    let mut network_packages: Vec<(&str, Vec<Element>)> = Vec::new();
+    for service_name in order {
+        let service = &compose.services[service_name];
+        let container = containers.get(service_name);
+        let base_image = base_images.get(service_name).map(String::as_str);
+        let (element, mut reference) = if options.per_service_packages {
+            let reference = format!("{}::{}", sysml_name(service_name), sysml_name(service_name));
+            (service_package(service_name, service, container, base_image, &model, options), reference)
+        } else {
+            (sysml_service(service_name, service, container, base_image, options), sysml_name(service_name))
+        };
+        match options.group_by {
+            GroupBy::Network => {
+                let network = service.network_names().first().copied().unwrap_or("default");
+                reference = format!("{}::{}", network_package_name(network), reference);
+                match network_packages.iter_mut().find(|(n, _)| *n == network) {
+                    Some((_, elements)) => elements.push(element),
+                    None => network_packages.push((network, vec![element])),
+                }
+            }
+            // Packages can't be owned by a part, so service packages sit beside the system part
+            _ if options.per_service_packages => members.push(element),
+            _ => system.push(element),
+        }
+        refs.insert(service_name, reference);
+    }
+    network_packages.sort_by_key(|(network, _)| *network);
+    for (network, elements) in network_packages {
+        members.push(Element::Package { name: network_package_name(network), members: elements });
     }
 
-    for (index, exposed_port) in container.exposed_ports.iter().enumerate() {
-        package.push_str(&format!("            port port{}: NetworkPort {{\n", index));
-        package.push_str(&format!("                protocol redefines protocol = Protocol::{};\n", exposed_port.protocol));
-        package.push_str(&format!("                portNumber redefines portNumber = {};\n", exposed_port.port_number));
-        package.push_str("            }\n");
+    system.members.extend(sysml_networks(compose, &service_names, &refs));
+    system.members.extend(sysml_dependencies(compose, &service_names, &refs));
+    if options.infer_flows {
+        system.members.extend(sysml_inferred_flows(compose, &refs));
     }
+    for service_name in &service_names {
+        if let Some(container) = containers.get(service_name.as_str()) {
+            system.members.extend(sysml_external_sources(service_name, container, &refs[service_name.as_str()]));
+        }
+    }
+    members.push(system.into());
+
+    render_with(&[Element::Package { name: model, members }], &options.style)
+}
 
-    for (index, volume) in container.volumes.iter().enumerate() {
-        package.push_str(&format!("            port volume{}: VolumePort {{\n", index));
-        package.push_str(&format!("                mountPoint redefines mountPoint = \"{}\";\n", volume.mount_point));
-        package.push_str("            }\n");
+/// A structural problem `check_sysml` found in SysML text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysmlIssue {
+    /// The line the problem is on, counting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SysmlIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
     }
+}
+
+/// A token of SysML text, as far as `check_sysml` needs to tell them apart
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A basic name such as `web`, or an unrestricted name such as `'my-service'` without
+    /// its quotes
+    Name(String),
+    /// A string or number
+    Literal,
+    /// A block comment, such as the body of a `doc`
+    Comment,
+    Symbol(&'static str),
+}
+
+/// The symbols the generated SysML uses, longest first so that `:>>` isn't read as `:`
+const SYSML_SYMBOLS: &[&str] = &[":>>", ":>", "::", "..", "{", "}", "(", ")", "[", "]", ";", ":", ",", ".", "=", "*", "@", "#", "~", "+"];
 
-    package.push_str("        }\n"); // Close Container part
-    package.push_str("    }\n"); // Close System Part
-    package.push_str("}\n");// Close Package
+/// The keywords which may come before the name a statement declares
+const SYSML_DECLARATION_KEYWORDS: &[&str] = &[
+    "abstract", "action", "assert", "attribute", "comment", "connection", "constraint", "def", "doc", "end", "enum",
+    "in", "inout", "item", "metadata", "out", "part", "port", "ref", "requirement", "satisfy", "state",
+];
 
-    package
+/// Scan the string literal or unrestricted name at the start of `rest`, returning its length
+/// with its quotes, or None when it's never closed, its content and any escapes SysML lacks
+fn scan_quoted(rest: &str, quote: char) -> (Option<usize>, String, Vec<char>) {
+    let mut content = String::new();
+    let mut invalid = Vec::new();
+    let mut chars = rest.char_indices().skip(1);
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped)) => {
+                    if !"\"'\\btnfr".contains(escaped) {
+                        invalid.push(escaped);
+                    }
+                    content.push(escaped);
+                }
+                None => break,
+            },
+            c if c == quote => return (Some(index + 1), content, invalid),
+            c => content.push(c),
+        }
+    }
+    (None, content, invalid)
+}
+
+/// Split SysML text into tokens with their lines, reporting unterminated literals, comments
+/// and names, and characters outside the grammar of names, numbers and symbols. None when
+/// something is never closed, which leaves the rest of the text unreadable.
+fn sysml_tokens(text: &str, issues: &mut Vec<SysmlIssue>) -> Option<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut issue = |line: usize, message: String| issues.push(SysmlIssue { line, message });
+    let (mut pos, mut line) = (0, 1);
+    while let Some(c) = text[pos..].chars().next() {
+        let rest = &text[pos..];
+        let start = line;
+        let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let len = if c.is_whitespace() {
+            c.len_utf8()
+        } else if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            let Some(end) = comment.find("*/") else {
+                issue(start, "block comment is never closed".to_string());
+                return None;
+            };
+            tokens.push((start, Token::Comment));
+            end + 4
+        } else if c == '"' || c == '\'' {
+            let kind = if c == '"' { "string literal" } else { "name" };
+            let (len, content, invalid) = scan_quoted(rest, c);
+            for escaped in invalid {
+                issue(start, format!("'\\{}' isn't an escape sequence", escaped));
+            }
+            let Some(len) = len else {
+                issue(start, format!("{} is never closed", kind));
+                return None;
+            };
+            match c {
+                '"' => tokens.push((start, Token::Literal)),
+                _ if content.is_empty() => issue(start, "a name can't be empty".to_string()),
+                _ => tokens.push((start, Token::Name(content))),
+            }
+            len
+        } else if c.is_ascii_digit() {
+            let mut len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            if rest[len..].starts_with('.') && rest[len + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+                len += 1 + rest[len + 1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - len - 1);
+            }
+            // A name can't start with a digit, and numbers are written without exponents
+            if rest[len..].starts_with(is_name_char) {
+                len += rest[len..].find(|c: char| !is_name_char(c)).unwrap_or(rest.len() - len);
+                issue(start, format!("'{}' is neither a name nor a number", &rest[..len]));
+            } else {
+                tokens.push((start, Token::Literal));
+            }
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+            tokens.push((start, Token::Name(rest[..len].to_string())));
+            len
+        } else if let Some(symbol) = SYSML_SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push((start, Token::Symbol(symbol)));
+            symbol.len()
+        } else {
+            issue(start, format!("unexpected character '{}'", c));
+            c.len_utf8()
+        };
+        line += rest[..len].matches('\n').count();
+        pos += len;
     }
+    Some(tokens)
+}
 
+/// What a statement declares, as far as checking redefinitions needs
+#[derive(Debug, Default)]
+struct SysmlStatement {
+    /// The name declared, if any
+    name: Option<String>,
+    /// Whether the statement declares a definition rather than a usage
+    definition: bool,
+    /// The definitions a definition specializes, or the type of a usage, without qualifiers
+    types: Vec<String>,
+    /// The features the statement redefines
+    redefined: Vec<String>,
+}
+
+/// The last segment of the qualified name starting at `tokens[index]`, such as `Kind` for
+/// `Healthcheck::Kind`
+fn qualified_name_end(tokens: &[&Token], mut index: usize) -> Option<String> {
+    let mut name = None;
+    while let Some(Token::Name(segment)) = tokens.get(index) {
+        name = Some(segment.clone());
+        if tokens.get(index + 1) != Some(&&Token::Symbol("::")) {
+            break;
+        }
+        index += 2;
+    }
+    name
+}
+
+fn sysml_statement(tokens: &[&Token]) -> SysmlStatement {
+    let mut statement = SysmlStatement::default();
+    if tokens.first() == Some(&&Token::Symbol("@")) {
+        statement.types.extend(qualified_name_end(tokens, 1));
+        return statement;
+    }
+    let mut index = 0;
+    loop {
+        match tokens.get(index) {
+            Some(Token::Symbol("#")) => index += 2,
+            Some(Token::Name(keyword)) if SYSML_DECLARATION_KEYWORDS.contains(&keyword.as_str()) => {
+                statement.definition |= keyword == "def";
+                index += 1;
+            }
+            _ => break,
+        }
+    }
+    if let (Some(Token::Name(name)), false) = (tokens.get(index), tokens.get(index + 1) == Some(&&Token::Symbol("::"))) {
+        statement.name = Some(name.clone());
+    }
+    for (position, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Symbol(":") if !statement.definition && statement.types.is_empty() => statement.types.extend(qualified_name_end(tokens, position + 1)),
+            Token::Symbol(":>") if statement.definition => statement.types.extend(qualified_name_end(tokens, position + 1)),
+            Token::Name(keyword) if keyword == "specializes" && statement.definition => statement.types.extend(qualified_name_end(tokens, position + 1)),
+            Token::Symbol(":>>") => statement.redefined.extend(qualified_name_end(tokens, position + 1)),
+            Token::Name(keyword) if keyword == "redefines" => statement.redefined.extend(qualified_name_end(tokens, position + 1)),
+            _ => (),
+        }
+    }
+    statement
+}
+
+/// The features of a definition, with those of the definitions it specializes, or None when
+/// it or one of them isn't in the text, so its features can't be known
+fn definition_features<'a>(definitions: &'a HashMap<String, (BTreeSet<String>, Vec<String>)>, name: &str, seen: &mut BTreeSet<String>) -> Option<BTreeSet<&'a String>> {
+    let (features, supertypes) = definitions.get(name)?;
+    let mut all: BTreeSet<&String> = features.iter().collect();
+    if seen.insert(name.to_string()) {
+        for supertype in supertypes {
+            all.extend(definition_features(definitions, supertype, seen)?);
+        }
+    }
+    Some(all)
+}
+
+/// Check SysML text for structural problems, without a SysML toolchain: braces which don't
+/// balance, string literals, names and comments which aren't closed, characters outside the
+/// grammar of names, numbers and symbols, and redefinitions of features which the type of
+/// the usage doesn't declare. Types which aren't defined in the text, such as those of the
+/// standard library, are taken on trust. Generated models are checked with this by the tests
+/// and by `--self-check`.
+pub fn check_sysml(text: &str) -> Vec<SysmlIssue> {
+    let mut issues = Vec::new();
+    let Some(tokens) = sysml_tokens(text, &mut issues) else {
+        return issues;
+    };
+    // Each open body: the line it opened on, the definition it's the body of, and the types
+    // whose features it may redefine
+    let mut scopes: Vec<(usize, Option<String>, Vec<String>)> = vec![(0, None, Vec::new())];
+    let mut definitions: HashMap<String, (BTreeSet<String>, Vec<String>)> = HashMap::new();
+    let mut redefinitions: Vec<(usize, String, Vec<String>)> = Vec::new();
+    let mut statement: Vec<&Token> = Vec::new();
+    let mut statement_line = 0;
+    for (line, token) in &tokens {
+        match token {
+            Token::Symbol(end @ (";" | "{")) => {
+                let declared = sysml_statement(&statement);
+                let (_, definition, types) = scopes.last().expect("the document scope is never closed");
+                for redefined in &declared.redefined {
+                    redefinitions.push((statement_line, redefined.clone(), types.clone()));
+                }
+                if let (Some(definition), Some(name)) = (definition, &declared.name) {
+                    definitions.entry(definition.clone()).or_default().0.insert(name.clone());
+                }
+                let name = declared.name.filter(|_| declared.definition);
+                if let Some(name) = &name {
+                    definitions.entry(name.clone()).or_default().1.extend(declared.types.iter().cloned());
+                }
+                if *end == "{" {
+                    scopes.push((*line, name, declared.types));
+                }
+                statement.clear();
+            }
+            Token::Symbol("}") => {
+                if !statement.is_empty() {
+                    issues.push(SysmlIssue { line: statement_line, message: "statement isn't ended with ';'".to_string() });
+                    statement.clear();
+                }
+                if scopes.len() == 1 {
+                    issues.push(SysmlIssue { line: *line, message: "'}' doesn't close a '{'".to_string() });
+                } else {
+                    scopes.pop();
+                }
+            }
+            // The comment of a `doc` ends it
+            Token::Comment => {
+                if matches!(statement.first(), Some(Token::Name(keyword)) if keyword == "doc" || keyword == "comment") {
+                    statement.clear();
+                }
+            }
+            token => {
+                if statement.is_empty() {
+                    statement_line = *line;
+                }
+                statement.push(token);
+            }
+        }
+    }
+    if !statement.is_empty() {
+        issues.push(SysmlIssue { line: statement_line, message: "statement isn't ended with ';'".to_string() });
+    }
+    for (line, _, _) in scopes.iter().skip(1) {
+        issues.push(SysmlIssue { line: *line, message: "'{' is never closed".to_string() });
+    }
+    for (line, redefined, types) in redefinitions {
+        if types.is_empty() {
+            issues.push(SysmlIssue { line, message: format!("'{}' is redefined outside a typed usage or specialized definition", redefined) });
+            continue;
+        }
+        let features: Option<Vec<BTreeSet<&String>>> = types.iter().map(|name| definition_features(&definitions, name, &mut BTreeSet::new())).collect();
+        if let Some(features) = features {
+            if !features.iter().any(|features| features.contains(&redefined)) {
+                issues.push(SysmlIssue { line, message: format!("'{}' is redefined, but {} doesn't declare it", redefined, types.join(" or ")) });
+            }
+        }
+    }
+    issues.sort_by_key(|issue| issue.line);
+    issues
+}
+
+#[cfg(all(test, feature = "compose"))]
+mod tests {
+    use super::*;
+
+    fn compose_from_str(yaml: &str) -> parse_compose::Compose {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_check_sysml() {
+        let valid = "package app {\n    part def Container {\n        attribute image: String;\n    }\n    part web: Container {\n        doc /* built «locally» */\n        attribute image redefines image = \"nginx\";\n        attribute 'shm-size': Integer = 1.5;\n    }\n}\n";
+        assert_eq!(check_sysml(valid), vec![]);
+
+        let broken = [
+            ("package app {\n    part web;\n", "line 1: '{' is never closed"),
+            ("package app {\n}\n}\n", "line 3: '}' doesn't close a '{'"),
+            ("part web: Container {\n    attribute image redefines image = \"nginx;\n}\n", "line 2: string literal is never closed"),
+            ("part def A;\npart 'web: A;\n", "line 2: name is never closed"),
+            ("part '': Container;\n", "line 1: a name can't be empty"),
+            ("part my-service: Container;\n", "line 1: unexpected character '-'"),
+            ("part 9lives: Container;\n", "line 1: '9lives' is neither a name nor a number"),
+            ("attribute size: Integer = 1e21;\n", "line 1: '1e21' is neither a name nor a number"),
+            ("attribute s: String = \"a\\qb\";\n", "line 1: '\\q' isn't an escape sequence"),
+            ("/* never closed\n", "line 1: block comment is never closed"),
+            ("package app {\n    part web\n}\n", "line 2: statement isn't ended with ';'"),
+            (
+                "part def Container {\n    attribute image: String;\n}\npart web: Container {\n    attribute tag redefines tag = \"1\";\n}\n",
+                "line 5: 'tag' is redefined, but Container doesn't declare it",
+            ),
+            ("package app {\n    attribute image redefines image = \"nginx\";\n}\n", "line 2: 'image' is redefined outside a typed usage or specialized definition"),
+        ];
+        for (text, expected) in broken {
+            let issues: Vec<String> = check_sysml(text).iter().map(ToString::to_string).collect();
+            assert_eq!(issues, vec![expected.to_string()], "{}", text);
+        }
+
+        // Features are inherited through specialization, and types which aren't in the text
+        // are taken on trust
+        let inherited = "part def Base {\n    attribute image: String;\n}\npart def Web :> Base;\npart web: Web {\n    attribute image redefines image = \"nginx\";\n}\npart d: ISQ::DurationValue {\n    attribute x redefines x = 1;\n}\n";
+        assert_eq!(check_sysml(inherited), vec![]);
+    }
+
+    /// Every fixture generates SysML which passes `check_sysml`, whatever the options
+    #[test]
+    fn test_generated_sysml_self_check() {
+        let option_sets = [
+            SysmlOptions::default(),
+            SysmlOptions { infer_flows: true, include_metadata: true, shared_base_images: true, ports_sorted: true, ..Default::default() },
+            SysmlOptions { group_by: GroupBy::Network, per_service_packages: true, include_environment: true, env_as_table: true, ..Default::default() },
+            SysmlOptions { group_by: GroupBy::Dependency, include_environment: true, max_attrs_per_group: Some(2), ..Default::default() },
+            SysmlOptions { emit: Emit::Usages, ..Default::default() },
+            SysmlOptions { emit: Emit::Definitions, ..Default::default() },
+        ];
+        let fixtures = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures")).unwrap();
+        let mut checked = 0;
+        for fixture in fixtures.map(|entry| entry.unwrap().path()) {
+            let compose = parse_compose::parse_composefile(Box::new(std::fs::File::open(&fixture).unwrap())).unwrap().value;
+            for options in &option_sets {
+                let sysml = sysml_compose_package(&compose, "fixture", options);
+                assert_eq!(check_sysml(&sysml), vec![], "{} with {:?}\n{}", fixture.display(), options, sysml);
+                checked += 1;
+            }
+        }
+        assert!(checked > 0);
+
+        let dockerfile = "FROM rust:1.75 AS build\nWORKDIR /src\nCOPY . .\nRUN cargo build --release && rm -rf target/debug\nFROM debian:bookworm-slim\nLABEL maintainer=\"ops@example.com\" org.example.team=\"web\"\nCOPY --from=build /src/target/release/app /usr/local/bin/app\nEXPOSE 8080/tcp 53/udp\nVOLUME /data\nUSER app\n";
+        let container = parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "my-app").unwrap();
+        let options = SysmlOptions { include_metadata: true, include_filesystem: true, include_build_actions: true, include_metrics: true, ..Default::default() };
+        for options in [SysmlOptions::default(), options] {
+            let sysml = sysml_cargotecture_package(&container, &options);
+            assert_eq!(check_sysml(&sysml), vec![], "{}", sysml);
+        }
+        assert_eq!(check_sysml(&sysml_requirement_definitions(&container, &SysmlOptions::default())), vec![]);
+
+        #[cfg(feature = "pod")]
+        {
+            let manifest = "kind: Pod\nmetadata:\n  name: web\nspec:\n  initContainers:\n    - name: migrate\n      image: app:1.0\n  containers:\n    - name: nginx\n      image: nginx:1.25\n      ports:\n        - containerPort: 80\n      volumeMounts:\n        - name: html\n          mountPath: /usr/share/nginx/html\n";
+            let pod = parse_podfile::parse_podfile(Box::new(manifest.as_bytes())).unwrap();
+            let sysml = sysml_part_hierarchy(&pod, &SysmlOptions::default());
+            assert_eq!(check_sysml(&sysml), vec![], "{}", sysml);
+        }
+    }
+
+    #[test]
+    fn test_sysml_name() {
+        assert_eq!(sysml_name("backend"), "backend");
+        assert_eq!(sysml_name("my-service"), "'my-service'");
+        assert_eq!(sysml_name("9lives"), "'9lives'");
+    }
+
+    #[test]
+    fn test_sysml_shared_base_images() {
+        let compose = compose_from_str(
+            "services:\n  web:\n    image: nginx\n  proxy:\n    image: docker.io/library/nginx:latest\n  cache:\n    image: redis:7\n",
+        );
+        let options = SysmlOptions { shared_base_images: true, ..Default::default() };
+        let sysml = sysml_compose_package(&compose, "app", &options);
+        assert_eq!(sysml.matches(": BaseImage {").count(), 2);
+        assert!(sysml.contains(
+            "        part baseImage0: BaseImage {\n            attribute imageName redefines imageName = \"docker.io/library/nginx:latest\";\n            attribute consumerCount: Integer = 2;\n"
+        ));
+        assert!(sysml.contains("        part baseImage1: BaseImage {\n            attribute imageName redefines imageName = \"docker.io/library/redis:7\";\n            attribute consumerCount: Integer = 1;\n"));
+        assert!(sysml.contains("        part web: Container {\n            attribute image redefines image = \"nginx\";\n            ref part baseImage: BaseImage = appSystem::baseImage0;\n"));
+        assert!(sysml.contains("            ref part baseImage: BaseImage = appSystem::baseImage1;\n"));
+        assert!(!sysml_compose_package(&compose, "app", &SysmlOptions::default()).contains("baseImage"));
+
+        // Compared as written, each form of a reference gets a part of its own
+        let options = SysmlOptions { raw_image_refs: true, ..options };
+        let sysml = sysml_compose_package(&compose, "app", &options);
+        assert_eq!(sysml.matches(": BaseImage {").count(), 3);
+        assert!(sysml.contains("        part baseImage0: BaseImage {\n            attribute imageName redefines imageName = \"docker.io/library/nginx:latest\";\n            attribute consumerCount: Integer = 1;\n"));
+        assert!(sysml.contains("        part baseImage1: BaseImage {\n            attribute imageName redefines imageName = \"nginx\";\n            attribute consumerCount: Integer = 1;\n"));
+    }
+
+    #[test]
+    fn test_sysml_connection_kinds() {
+        let elk = include_str!("../tests/fixtures/elk.yml").replace("      - \"9600:9600\"\n", "      - \"9600:9600\"\n      - \"19200:9200\"\n");
+        let compose = compose_from_str(&elk);
+        let sysml = sysml_compose_package(&compose, "elk", &SysmlOptions::default());
+        let sysml = format!("{}{}", sysml, sysml_flow_connections(&compose, &RenderStyle::default()));
+        // Every connection carries the kind of its definition as its first member
+        let lines: Vec<&str> = sysml.lines().map(str::trim).collect();
+        let mut kinds = std::collections::HashSet::new();
+        for (index, line) in lines.iter().enumerate().filter(|(_, line)| line.starts_with("connection ") && !line.starts_with("connection def ")) {
+            let kind = ConnectionKind::ALL.into_iter().find(|kind| line.contains(&format!(": {} connect ", kind.definition()))).unwrap();
+            assert_eq!(lines[index + 1], format!("attribute kind redefines kind = ConnectionKind::{};", kind.literal()), "{}", line);
+            kinds.insert(kind);
+        }
+        assert_eq!(kinds, [ConnectionKind::DependsOn, ConnectionKind::NetworkMembership, ConnectionKind::NetworkFlow].into());
+        // The header defines each kind, and the definition of each kind has the attribute
+        for kind in ConnectionKind::ALL {
+            assert!(sysml.contains(&format!("        enum {};\n", kind.literal())));
+        }
+        for kind in kinds {
+            let definition = &sysml[sysml.find(&format!("    connection def {} {{\n", kind.definition())).unwrap()..];
+            assert!(definition[..definition.find("\n    }\n").unwrap()].contains("attribute kind: ConnectionKind;"));
+        }
+    }
+
+    #[test]
+    fn test_sysml_flow_connections() {
+        let elk = include_str!("../tests/fixtures/elk.yml").replace("      - \"9600:9600\"\n", "      - \"9600:9600\"\n      - \"19200:9200\"\n");
+        let flows = sysml_flow_connections(&compose_from_str(&elk), &RenderStyle::default());
+        assert_eq!(
+            flows,
+            "connection networkConnection0: NetworkConnection connect logstash.port1 to elasticsearch.port1 {\n    attribute kind redefines kind = ConnectionKind::networkFlow;\n}\n"
+        );
+        // The connections are typed by the definition every package starts with
+        let sysml = sysml_compose_package(&compose_from_str(&elk), "elk", &SysmlOptions::default());
+        assert!(sysml.contains("    connection def NetworkConnection {\n        end source: NetworkPort;\n        end target: NetworkPort;\n        attribute kind: ConnectionKind;\n    }\n"));
+        assert!(sysml_connection_definitions().contains("connection def NetworkConnection {\n    end source: NetworkPort;\n    end target: NetworkPort;\n    attribute kind: ConnectionKind;\n}\n"));
+        assert!(sysml_connection_definitions().contains("connection def VolumeConnection {\n    end source: VolumePort;\n    end target: VolumePort;\n    attribute kind: ConnectionKind;\n}\n"));
+
+        // Services on different networks can't reach each other
+        let compose = compose_from_str(
+            "services:\n  api:\n    ports: [\"8080\"]\n    depends_on: [db]\n    networks: [front]\n  db:\n    expose: [\"8080\"]\n    networks: [back]\n",
+        );
+        assert_eq!(sysml_flow_connections(&compose, &RenderStyle::default()), "");
+    }
+
+    #[test]
+    fn test_sysml_compose_package_inferred_flows() {
+        let compose = compose_from_str(r#"
+services:
+  db:
+    image: postgres:15
+  backend:
+    image: backend:latest
+    environment:
+      DATABASE_URL: postgres://db:5432/app
+        "#);
+
+        let plain = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(plain.starts_with("package appModel {"));
+        assert!(plain.contains("        part backend: Container {\n"));
+        assert!(plain.contains("attribute image redefines image = \"postgres:15\";"));
+        assert!(!plain.contains("InferredFlow"));
+
+        let options = SysmlOptions { infer_flows: true, ..Default::default() };
+        let inferred = sysml_compose_package(&compose, "app", &options);
+        assert!(inferred.contains("connection def InferredFlow {"));
+        assert!(inferred.contains("connection inferredFlow0: InferredFlow connect backend to db {\n            attribute kind redefines kind = ConnectionKind::inferredFlow;\n"));
+        assert!(inferred.contains("attribute evidence redefines evidence = \"DATABASE_URL=postgres://db:5432/app\";"));
+        assert!(inferred.contains("attribute portNumber redefines portNumber = 5432;"));
+    }
+
+    #[test]
+    fn test_sysml_healthcheck() {
+        let compose = compose_from_str(r#"
+services:
+  db:
+    image: mysql:8
+    healthcheck:
+      test: ['CMD-SHELL', 'mysqladmin ping -h 127.0.0.1 --password="$$(cat /run/secrets/db-password)" --silent']
+      interval: 3s
+  cache:
+    image: redis:7
+    healthcheck:
+      test: [CMD, redis-cli, ping]
+      timeout: 1m30s
+      retries: 5
+      start_period: 500ms
+  legacy:
+    image: legacy:1.0
+    healthcheck:
+      test: [NONE]
+        "#);
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(sysml.contains("    part def Healthcheck {\n"));
+        assert!(sysml.contains("        attribute interval: ISQ::DurationValue[0..1];\n"));
+        assert!(sysml.contains(concat!(
+            "            part healthcheck: Healthcheck {\n",
+            "                attribute kind redefines kind = Healthcheck::Kind::CMD_SHELL;\n",
+            "                attribute command redefines command = \"mysqladmin ping -h 127.0.0.1 --password=\\\"$$(cat /run/secrets/db-password)\\\" --silent\";\n",
+            "                attribute interval redefines interval = 3 [SI::s];\n",
+            "            }\n",
+        )));
+        assert!(sysml.contains(concat!(
+            "            part healthcheck: Healthcheck {\n",
+            "                attribute kind redefines kind = Healthcheck::Kind::CMD;\n",
+            "                attribute command redefines command = \"redis-cli ping\";\n",
+            "                attribute timeout redefines timeout = 90 [SI::s];\n",
+            "                attribute retries redefines retries = 5;\n",
+            "                attribute startPeriod redefines startPeriod = 0.5 [SI::s];\n",
+            "            }\n",
+        )));
+        assert!(sysml.contains("            part healthcheck: Healthcheck {\n                attribute kind redefines kind = Healthcheck::Kind::NONE;\n            }\n"));
+    }
+
+    #[test]
+    fn test_sysml_canonical_scalars() {
+        let compose = compose_from_str(r#"
+services:
+  db:
+    image: postgres:16
+    privileged: yes
+    read_only: On
+    attach: off
+    tty: y
+    shm_size: 1_099_511_627_776
+    mem_limit: 2g
+    healthcheck:
+      test: [CMD, pg_isready]
+      interval: 1ns
+      timeout: 100000h
+    secrets:
+      - db-password
+      - source: db-cert
+        mode: 0440
+      - source: db-key
+        uid: 999
+        mode: "0440"
+      - source: db-ca
+        mode: 0o444
+networks:
+  backend:
+    internal: yes
+        "#);
+        let db = &compose.services["db"];
+        assert_eq!((db.privileged, db.read_only, db.attach), (Some(true), Some(true), Some(false)));
+        let modes: Vec<Option<u32>> = db.secrets.iter().flatten().map(|secret| secret.mode).collect();
+        assert_eq!(modes, vec![None, Some(0o440), Some(0o440), Some(0o444)]);
+
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(sysml.contains("            attribute shmSizeBytes: Integer = 1099511627776;\n"));
+        assert!(sysml.contains("            attribute memLimitBytes: Integer = 2147483648;\n"));
+        assert!(sysml.contains("            attribute tty: Boolean = true;\n"));
+        assert!(!sysml.contains("stdinOpen"));
+        assert!(sysml.contains("                attribute interval redefines interval = 0.000000001 [SI::s];\n"));
+        assert!(sysml.contains("                attribute timeout redefines timeout = 360000000 [SI::s];\n"));
+        assert!(sysml.contains("            attribute secret0: Secret {\n                attribute source redefines source = \"db-password\";\n            }\n"));
+        assert!(sysml.contains(concat!(
+            "            attribute secret1: Secret {\n",
+            "                attribute source redefines source = \"db-cert\";\n",
+            "                attribute mode redefines mode = \"0440\";\n",
+            "            }\n",
+            "            attribute secret2: Secret {\n",
+            "                attribute source redefines source = \"db-key\";\n",
+            "                attribute uid redefines uid = \"999\";\n",
+            "                attribute mode redefines mode = \"0440\";\n",
+            "            }\n",
+        )));
+        assert!(sysml.contains("                attribute mode redefines mode = \"0444\";\n"));
+        assert!(sysml.contains("attribute internal redefines internal = true;"));
+
+        let json = serde_json::to_value(&compose).unwrap();
+        let db = &json["services"]["db"];
+        assert_eq!((&db["privileged"], &db["read_only"], &db["attach"]), (&serde_json::json!(true), &serde_json::json!(true), &serde_json::json!(false)));
+        assert_eq!(db["shm_size"], serde_json::json!(1_099_511_627_776u64));
+        assert_eq!(db["secrets"][1]["mode"], "0440");
+        assert_eq!(db["secrets"][2]["mode"], "0440");
+        assert_eq!(db["secrets"][3]["mode"], "0444");
+        assert_eq!(json["networks"]["backend"]["internal"], true);
+        let reparsed: parse_compose::Compose = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), json);
+    }
+
+    #[test]
+    fn test_sysml_compose_package_healthcheck_dependency() {
+        let compose = compose_from_str(r#"
+services:
+  db:
+    healthcheck:
+      test: ['CMD-SHELL', 'mysqladmin ping']
+      interval: 3s
+  backend:
+    depends_on:
+      db:
+        condition: service_healthy
+  proxy:
+    depends_on:
+      - backend
+        "#);
+        let sysml = sysml_compose_package(&compose, "sample", &SysmlOptions::default());
+        assert!(sysml.contains("constraint def Healthy {"));
+        assert!(sysml.contains("            part healthcheck: Healthcheck {\n                attribute kind redefines kind = Healthcheck::Kind::CMD_SHELL;\n                attribute command redefines command = \"mysqladmin ping\";\n                attribute interval redefines interval = 3 [SI::s];\n            }\n"));
+        assert!(sysml.contains("        connection dependency0: Dependency connect backend to db {\n            attribute kind redefines kind = ConnectionKind::dependsOn;\n            attribute condition redefines condition = \"service_healthy\";\n            assert constraint healthy: Healthy { in healthcheck = db.healthcheck; }\n        }\n"));
+        assert!(sysml.contains("        connection dependency1: Dependency connect proxy to backend {\n            attribute kind redefines kind = ConnectionKind::dependsOn;\n            attribute condition redefines condition = \"service_started\";\n        }\n"));
+    }
+
+    #[test]
+    fn test_sysml_metadata_annotation() {
+        assert_eq!(sysml_metadata_annotation("Dockerfile.line", "3"), "@Dockerfile { line = \"3\"; }");
+        assert_eq!(sysml_metadata_annotation("Docker.imageTag", "7.16.1"), "@Docker { imageTag = \"7.16.1\"; }");
+    }
+
+    #[test]
+    fn test_sysml_container_metadata() {
+        let dockerfile = "FROM rust:1.55\nEXPOSE 8080/tcp\n";
+        let container = parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "app").unwrap();
+
+        let plain = sysml_cargotecture_package(&container, &SysmlOptions::default());
+        assert!(!plain.contains('@'));
+        assert!(!plain.contains("metadata def"));
+
+        let options = SysmlOptions { include_metadata: true, ..Default::default() };
+        let sysml = sysml_cargotecture_package(&container, &options);
+        assert!(sysml.contains("metadata def Dockerfile {"));
+        assert!(sysml.contains("        part appBase: BaseImage {\n            @Docker { imageTag = \"1.55\"; }\n"));
+        assert!(sysml.contains("            port port0: NetworkPort {\n                @Dockerfile { line = \"2\"; }\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "pod")]
+    fn test_sysml_part_hierarchy() {
+        let manifest = r#"
+kind: Pod
+metadata:
+  name: web
+spec:
+  initContainers:
+    - name: fetch-content
+      image: busybox:1.36
+  containers:
+    - name: nginx
+      image: nginx:1.25
+      volumeMounts:
+        - name: content
+          mountPath: /usr/share/nginx/html
+    - name: sync
+      image: git-sync:4
+      volumeMounts:
+        - name: content
+          mountPath: /git
+        - name: content
+          mountPath: /cache
+  volumes:
+    - name: content
+      emptyDir: {}
+"#;
+        let pod = crate::parse_podfile::parse_podfile(Box::new(manifest.as_bytes())).unwrap();
+        assert_eq!(
+            sysml_part_hierarchy(&pod, &SysmlOptions::default()),
+            concat!(
+                "// Metadata Definition: init\n",
+                "// Marks a container run to completion before the others in its pod start\n",
+                "metadata def init;\n",
+                "\n",
+                "part def webPod {\n",
+                "    #init part 'fetch-content': Container[1] {\n",
+                "        attribute image redefines image = \"busybox:1.36\";\n",
+                "    }\n",
+                "    part nginx: Container[1] {\n",
+                "        attribute image redefines image = \"nginx:1.25\";\n",
+                "        port content: VolumePort {\n",
+                "            mountPoint redefines mountPoint = \"/usr/share/nginx/html\";\n",
+                "        }\n",
+                "    }\n",
+                "    part sync: Container[1] {\n",
+                "        attribute image redefines image = \"git-sync:4\";\n",
+                "        port content: VolumePort {\n",
+                "            mountPoint redefines mountPoint = \"/git\";\n",
+                "        }\n",
+                "        port content_2: VolumePort {\n",
+                "            mountPoint redefines mountPoint = \"/cache\";\n",
+                "        }\n",
+                "    }\n",
+                "    bind nginx.content = sync.content;\n",
+                "}\n",
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "pod")]
+    fn test_sysml_manifests_package() {
+        let manifests = r#"
+kind: Ingress
+metadata:
+  name: web
+spec:
+  rules:
+    - host: shop.example.com
+      http:
+        paths:
+          - path: /
+            backend:
+              service: {name: storefront, port: {number: 80}}
+          - path: /api
+            backend:
+              service: {name: api, port: {number: 8000}}
+---
+kind: Service
+metadata:
+  name: storefront
+spec:
+  selector: {app: storefront}
+  ports: [{port: 80, targetPort: 8080}]
+---
+kind: Service
+metadata:
+  name: api
+spec:
+  selector: {app: api}
+  ports: [{port: 8000}]
+---
+kind: Pod
+metadata:
+  name: web
+  labels: {app: storefront}
+spec:
+  containers:
+    - name: nginx
+      ports: [{containerPort: 8080}]
+---
+kind: Pod
+metadata:
+  name: api
+  labels: {app: api}
+spec:
+  containers:
+    - name: server
+      ports: [{containerPort: 8000}]
+---
+kind: NetworkPolicy
+metadata:
+  name: default-deny
+spec:
+  podSelector: {}
+---
+kind: NetworkPolicy
+metadata:
+  name: allow-api
+spec:
+  podSelector: {matchLabels: {app: api}}
+  ingress:
+    - from: [{namespaceSelector: {}}]
+"#;
+        let manifests = crate::parse_manifests::parse_manifests(Box::new(manifests.as_bytes())).unwrap();
+        let sysml = sysml_kubernetes(&manifests, "shop", &SysmlOptions::default());
+        assert_eq!(check_sysml(&sysml), vec![], "{}", sysml);
+        assert!(sysml.starts_with("package shop {\n"));
+        assert!(sysml.contains("    connection def IngressRoute {\n        end source: Ingress;\n        end target: Container;\n"));
+        assert!(sysml.contains("    part def webPod {\n") && sysml.contains("    part def apiPod {\n"));
+        // The Ingress is named as a pod is, so its part is renamed
+        assert!(sysml.contains(concat!(
+            "    part shopCluster {\n",
+            "        part web: webPod {\n",
+            "            attribute networkPolicies: String[0..*] = (\"default-deny\");\n",
+            "            attribute ingressIsolated: Boolean = true;\n",
+            "            attribute egressIsolated: Boolean = false;\n",
+            "        }\n",
+            "        part api: apiPod {\n",
+            "            attribute networkPolicies: String[0..*] = (\"default-deny\", \"allow-api\");\n",
+        )));
+        assert!(sysml.contains("        part web_2: Ingress;\n"));
+        assert!(sysml.contains(concat!(
+            "        connection ingressRoute0: IngressRoute connect web_2 to web.nginx {\n",
+            "            attribute kind redefines kind = ConnectionKind::ingressRoute;\n",
+            "            attribute host redefines host = \"shop.example.com\";\n",
+            "            attribute path redefines path = \"/\";\n",
+            "            attribute service redefines service = \"storefront\";\n",
+            "            attribute servicePort redefines servicePort = 80;\n",
+            "            attribute portNumber redefines portNumber = 8080;\n",
+            "            attribute allowed redefines allowed = false;\n",
+            "        }\n",
+        )));
+        assert!(sysml.contains("        connection ingressRoute1: IngressRoute connect web_2 to api.server {\n"));
+        assert!(sysml.contains("            attribute allowed redefines allowed = true;\n        }\n    }\n}\n"));
+
+        // A single pod is modelled as before
+        let pod = "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: nginx\n";
+        let manifests = crate::parse_manifests::parse_manifests(Box::new(pod.as_bytes())).unwrap();
+        assert_eq!(sysml_kubernetes(&manifests, "web", &SysmlOptions::default()), sysml_part_hierarchy(&manifests.pods[0], &SysmlOptions::default()));
+    }
+
+    #[test]
+    #[cfg(all(feature = "compose", feature = "pod"))]
+    fn test_sysml_reconciliation() {
+        use crate::arch_model::ArchModel;
+        let compose: parse_compose::Compose = serde_yaml::from_str("services:\n  web:\n    image: nginx\n    mem_limit: 64m\n  worker:\n    image: worker\n").unwrap();
+        let pod = "kind: Pod\nmetadata:\n  name: storefront\nspec:\n  containers:\n    - {name: nginx, image: nginx, resources: {limits: {memory: 64Mi}}}\n";
+        let manifests = crate::parse_manifests::parse_manifests(Box::new(pod.as_bytes())).unwrap();
+        let reconciliation = ArchModel::from_compose(&compose).reconcile(&ArchModel::from_manifests(&manifests), &[]).unwrap();
+        let sysml = sysml_reconciliation(&reconciliation, "shop", &SysmlOptions::default());
+        assert!(sysml.contains(
+            "        part web: ReconciledComponent {\n            part compose: Deployment {\n                attribute name redefines name = \"web\";\n                attribute image redefines image = \"nginx\";\n                attribute ports redefines ports = ();\n                attribute environmentVariables redefines environmentVariables = ();\n                attribute memoryLimit redefines memoryLimit = 67108864;\n            }\n"
+        ));
+        assert!(sysml.contains("            attribute divergences redefines divergences = ();\n"));
+        assert!(sysml.contains("        attribute unmatchedCompose: String[0..*] = (\"worker\");\n"));
+        assert!(check_sysml(&sysml).is_empty());
+    }
+
+    #[test]
+    fn test_sysml_requirement_definitions() {
+        let dockerfile = "FROM nginx:1.25\nEXPOSE 80\nEXPOSE 443/tcp\n";
+        let container = parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "web").unwrap();
+        let sysml = sysml_requirement_definitions(&container, &SysmlOptions::default());
+        assert!(sysml.starts_with("package webRequirements {\n    import webModel::*;\n\n    // Requirement Definition: NetworkAccessRequirement\n"));
+        assert!(sysml.contains("    requirement def NetworkAccessRequirement {\n        attribute requiredPort: Integer;\n"));
+        assert!(sysml.contains("    requirement networkAccess0: NetworkAccessRequirement {\n        attribute requiredPort redefines requiredPort = 80;\n        attribute requiredProtocol redefines requiredProtocol = NetworkPort::Protocol::TCP;\n    }\n"));
+        assert!(sysml.contains("        attribute requiredPort redefines requiredPort = 443;\n"));
+        assert!(sysml.contains("    satisfy networkAccess0 by webSystem.web.port0;\n    satisfy networkAccess1 by webSystem.web.port1;\n"));
+        assert_eq!(sysml.matches("requirement networkAccess").count(), 2);
+    }
+
+    #[test]
+    fn test_sysml_network_name_collisions() {
+        let compose = compose_from_str("services:\n  a:\n    networks:\n      back:\n        aliases: [api]\n  b:\n    networks:\n      back:\n        aliases: [api]\n");
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(sysml.contains("                attribute api: String[2] = (\"a\", \"b\");\n"));
+    }
+
+    #[test]
+    fn test_sysml_compose_metadata() {
+        let compose = compose_from_str(r#"
+version: "3.8"
+services:
+  web:
+    image: nginx:1.25
+        "#);
+        let options = SysmlOptions { include_metadata: true, ..Default::default() };
+        let sysml = sysml_compose_package(&compose, "app", &options);
+        assert!(sysml.contains("    part def Container {\n        @Compose { version = \"3.8\"; }\n"));
+        assert!(sysml.contains("    part def BaseImage {\n        @Compose { version = \"3.8\"; }\n"));
+        assert!(sysml.contains("        part web: Container {\n            @Docker { imageTag = \"1.25\"; }\n"));
+    }
+
+    #[test]
+    fn test_sysml_compose_networks() {
+        let compose = compose_from_str(r#"
+services:
+  elasticsearch:
+    networks:
+      elastic:
+        aliases: [es]
+        ipv4_address: 172.16.238.10
+  kibana:
+    networks:
+      - elastic
+networks:
+  elastic:
+    driver: bridge
+        "#);
+        let sysml = sysml_compose_package(&compose, "elk", &SysmlOptions::default());
+        assert!(sysml.contains("        part elasticNetwork: Network {\n            attribute driver redefines driver = \"bridge\";\n            attribute names {\n                attribute elasticsearch: String = \"elasticsearch\";\n                attribute es: String = \"elasticsearch\";\n                attribute kibana: String = \"kibana\";\n            }\n        }\n"));
+        assert!(sysml.contains("        connection membership0: NetworkMembership connect elasticsearch to elasticNetwork {\n            attribute kind redefines kind = ConnectionKind::networkMembership;\n            attribute aliases redefines aliases = (\"es\");\n            attribute ipv4Address redefines ipv4Address = \"172.16.238.10\";\n        }\n"));
+        assert!(sysml.contains("        connection membership1: NetworkMembership connect kibana to elasticNetwork {\n            attribute kind redefines kind = ConnectionKind::networkMembership;\n        }\n"));
+    }
+
+    #[test]
+    fn test_sysml_compose_replicas() {
+        let compose = compose_from_str(r#"
+services:
+  web:
+    image: nginx:1.25
+    deploy:
+      replicas: 3
+    ports:
+      - "80"
+  db:
+    image: postgres:15
+        "#);
+        assert!(compose.validate().warnings.is_empty());
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(sysml.contains("        part web: Container[3] {\n            attribute image redefines image = \"nginx:1.25\";\n            attribute replicaCount: Integer = 3;\n"));
+        assert!(sysml.contains("        part db: Container {\n"));
+    }
+
+    #[test]
+    fn test_sysml_services_without_image() {
+        let compose = compose_from_str("services:\n  api:\n    build:\n      context: api\n      dockerfile: Containerfile\n  bare:\n    restart: always\n");
+        let container = parse_dockerfile::parse_containerfile(Box::new("FROM rust:1.75\n".as_bytes()), "api").unwrap();
+        let containers = HashMap::from([("api".to_string(), container)]);
+        let sysml = sysml_compose_package_with_containers(&compose, "app", &containers, &SysmlOptions::default());
+        assert!(sysml.contains(
+            "        part api: Container {\n            part baseImage: BaseImage {\n                doc /* «to-be-built» from api/Containerfile */\n                attribute imageName redefines imageName = \"rust:1.75\";\n            }\n"
+        ));
+        assert!(sysml.contains("        part bare: Container {\n            doc /* No image or build is configured, so the image is unknown */\n"));
+        assert!(!sysml.contains("image = \"\""));
+
+        // Without the Dockerfile the base image is only marked
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(sysml.contains("            part baseImage: BaseImage {\n                doc /* «to-be-built» from api/Containerfile */\n            }\n"));
+
+        // A shared base image is referenced, with the marker on the container
+        let options = SysmlOptions { shared_base_images: true, ..Default::default() };
+        let sysml = sysml_compose_package_with_containers(&compose, "app", &containers, &options);
+        assert!(sysml.contains(
+            "        part api: Container {\n            doc /* «to-be-built» from api/Containerfile */\n            ref part baseImage: BaseImage = appSystem::baseImage0;\n"
+        ));
+    }
+
+    #[test]
+    fn test_sysml_compose_derived_from() {
+        let yaml = "services:\n  worker1: &worker1\n    image: worker:1.0\n  worker2: *worker1\n";
+        let compose = parse_compose::parse_composefile(Box::new(yaml.as_bytes())).unwrap().value;
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(sysml.contains("        part worker2: Container {\n            attribute image redefines image = \"worker:1.0\";\n            attribute derivedFrom: String = \"worker1\";\n"));
+        assert_eq!(sysml.matches("derivedFrom").count(), 1);
+    }
+
+    #[test]
+    fn test_sysml_per_service_packages() {
+        let compose = compose_from_str(r#"
+services:
+  elasticsearch:
+    image: elasticsearch:7.16.1
+    networks: [elastic]
+  logstash:
+    image: logstash:7.16.1
+    depends_on: [elasticsearch]
+    networks: [elastic]
+        "#);
+        let options = SysmlOptions { per_service_packages: true, ..Default::default() };
+        let sysml = sysml_compose_package(&compose, "elk", &options);
+        assert!(sysml.contains("    package elasticsearch {\n        import elkModel::*;\n        part elasticsearch: Container {\n"));
+        assert!(sysml.contains("    package logstash {\n        import elkModel::*;\n        part logstash: Container {\n"));
+        assert!(sysml.contains("connection membership1: NetworkMembership connect logstash::logstash to elasticNetwork {"));
+        assert!(sysml.contains("connection dependency0: Dependency connect logstash::logstash to elasticsearch::elasticsearch {"));
+
+        let options = SysmlOptions { per_service_packages: true, group_by: GroupBy::Network, ..Default::default() };
+        let sysml = sysml_compose_package(&compose, "elk", &options);
+        assert!(sysml.contains("    package elasticServices {\n        package elasticsearch {\n"));
+        assert!(sysml.contains("connect elasticServices::logstash::logstash to elasticServices::elasticsearch::elasticsearch {"));
+
+        let package = sysml_package_for_service("logstash", &compose.services["logstash"], "elkModel", &SysmlOptions::default());
+        assert!(package.starts_with("package logstash {\n    import elkModel::*;\n    part logstash: Container {\n"));
+    }
+
+    #[test]
+    fn test_sysml_compose_device_requests() {
+        let compose = compose_from_str(r#"
+services:
+  trainer:
+    image: pytorch/pytorch
+    runtime: nvidia
+    gpus: all
+    deploy:
+      resources:
+        reservations:
+          devices:
+            - driver: nvidia
+              count: 2
+              capabilities: [gpu, compute]
+        "#);
+        let sysml = sysml_compose_package(&compose, "ml", &SysmlOptions::default());
+        assert!(sysml.contains("            attribute deviceRequest0: DeviceRequest {\n                attribute driver redefines driver = \"nvidia\";\n                attribute count redefines count = 2;\n                attribute allDevices redefines allDevices = false;\n                attribute capabilities redefines capabilities = (\"gpu\", \"compute\");\n            }\n"));
+        // runtime: nvidia repeats gpus: all, so is only emitted once
+        assert!(sysml.contains("            attribute deviceRequest1: DeviceRequest {\n                attribute driver redefines driver = \"nvidia\";\n                attribute allDevices redefines allDevices = true;\n"));
+        assert!(!sysml.contains("deviceRequest2"));
+    }
+
+    #[test]
+    fn test_sysml_build_actions() {
+        let dockerfile = "FROM debian\nRUN apt-get update\nRUN [\"apt-get\", \"install\", \"-y\", \"curl\"]\n";
+        let container = parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "app").unwrap();
+        assert_eq!(sysml_build_actions(&container, &RenderStyle::default()), "\
+// Action Definitions: Build
+action def BuildStep1 {
+    doc /* RUN apt-get update */
+}
+action def BuildStep2 {
+    doc /* RUN apt-get install -y curl */
+}
+action def Build {
+    action step1: BuildStep1;
+    action step2: BuildStep2;
+    first step1 then step2;
+}
+");
+        assert!(!sysml_cargotecture_package(&container, &SysmlOptions::default()).contains("action def"));
+        let options = SysmlOptions { include_build_actions: true, ..Default::default() };
+        let sysml = sysml_cargotecture_package(&container, &options);
+        assert!(sysml.contains("    action def Build {\n        action step1: BuildStep1;\n        action step2: BuildStep2;\n        first step1 then step2;\n    }\n}\n"));
+    }
+
+    #[test]
+    fn test_sysml_build_metrics() {
+        let dockerfile = "FROM rust:1.75 AS build\nRUN cargo build\nFROM debian:12\nRUN apt-get update\nRUN apt-get install -y curl\nCOPY --from=build /app /app\n";
+        let container = parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "app").unwrap();
+        assert!(!sysml_cargotecture_package(&container, &SysmlOptions::default()).contains("BuildMetrics"));
+        let options = SysmlOptions { include_metrics: true, ..Default::default() };
+        let sysml = sysml_cargotecture_package(&container, &options);
+        assert!(sysml.contains("    attribute def BuildMetrics {\n"));
+        // Only the final stage is modelled, as the build stage isn't built FROM
+        assert!(sysml.contains(
+            "            attribute buildMetrics0: BuildMetrics {\n                attribute stage redefines stage = \"1\";\n                attribute layerInstructions redefines layerInstructions = 3;\n                attribute mergeableRuns redefines mergeableRuns = 1;\n                attribute cacheCleaned redefines cacheCleaned = false;\n            }\n"
+        ));
+        assert!(!sysml.contains("buildMetrics1"));
+    }
+
+    #[test]
+    fn test_sysml_filesystem() {
+        let dockerfile = "FROM alpine\nWORKDIR /srv\nCOPY app bin/\nVOLUME /data\n";
+        let container = parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "app").unwrap();
+        assert!(!sysml_cargotecture_package(&container, &SysmlOptions::default()).contains("filesystem"));
+        let options = SysmlOptions { include_filesystem: true, ..Default::default() };
+        let sysml = sysml_cargotecture_package(&container, &options);
+        assert!(sysml.contains("            attribute filesystem {\n                attribute '/data': String = \"VOLUME\";\n                attribute '/srv': String = \"WORKDIR\";\n                attribute '/srv/bin': String = \"COPY\";\n            }\n"));
+    }
+
+    #[test]
+    fn test_sysml_external_sources() {
+        let dockerfile = "FROM alpine\nADD https://example.com/tool.tar.gz /opt/\nADD --checksum=sha256:24454f830cdb571e2c4ad15481119c43b3cafd48dd869a9b2945d1036d1dc68d https://example.com/data.json /data/\n";
+        let container = parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "app").unwrap();
+        let sysml = sysml_cargotecture_package(&container, &SysmlOptions::default());
+        assert!(sysml.contains("    part def ExternalSource {\n"));
+        assert!(sysml.contains("        part appSource0: ExternalSource {\n            attribute location redefines location = \"https://example.com/tool.tar.gz\";\n            attribute sourceKind redefines sourceKind = \"url\";\n        }\n"));
+        assert!(sysml.contains("        connection appFetch0: ExternalFetch connect appSource0 to app {\n            attribute kind redefines kind = ConnectionKind::externalFetch;\n            attribute destination redefines destination = \"/opt/\";\n        }\n"));
+        assert!(sysml.contains("            attribute checksum redefines checksum = \"sha256:24454f830cdb571e2c4ad15481119c43b3cafd48dd869a9b2945d1036d1dc68d\";\n"));
+        // Downloaded archives aren't extracted
+        assert!(!sysml.contains("extracts"));
+        assert!(check_sysml(&sysml).is_empty(), "{:?}", check_sysml(&sysml));
+
+        let dockerfile = "FROM alpine\nADD vendor.tar.gz /opt/\nADD config.json /etc/app/\n";
+        let container = parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "app").unwrap();
+        let sysml = sysml_cargotecture_package(&container, &SysmlOptions::default());
+        assert!(sysml.contains("            doc /* ADD at line 2 likely extracts the archive vendor.tar.gz into /opt/ */\n"));
+        assert!(!sysml.contains("ExternalSource") && !sysml.contains("config.json"));
+    }
+
+    #[test]
+    fn test_sysml_attribute_groups() {
+        let mut yaml = "services:\n  big:\n    environment:\n".to_string();
+        for i in 0..300 {
+            yaml.push_str(&format!("      VAR_{:03}: \"{}\"\n", i, i));
+        }
+        let compose = compose_from_str(&yaml);
+        assert!(!sysml_compose_package(&compose, "app", &SysmlOptions::default()).contains("VAR_000"));
+
+        let options = SysmlOptions { include_environment: true, ..Default::default() };
+        let flat = sysml_compose_package(&compose, "app", &options);
+        assert_eq!(flat.matches(": String = \"").count(), 300);
+        assert!(flat.contains("            attribute VAR_000: String = \"0\";\n"));
+        assert!(!flat.contains("envGroup"));
+
+        let options = SysmlOptions { include_environment: true, max_attrs_per_group: Some(64), ..Default::default() };
+        let grouped = sysml_compose_package(&compose, "app", &options);
+        assert_eq!(grouped.matches(": String = \"").count(), 300);
+        assert_eq!(grouped.matches("attribute envGroup").count(), 5);
+        // Groups hold 64 variables each, the last the 44 left over
+        assert!(grouped.contains("                attribute VAR_063: String = \"63\";\n            }\n            attribute envGroup2 {\n                attribute VAR_064: String = \"64\";\n"));
+        assert!(grouped.contains("            attribute envGroup5 {\n                attribute VAR_256:"));
+        assert!(grouped.contains("                attribute VAR_299: String = \"299\";\n            }\n"));
+
+        let labelled = compose_from_str(include_str!("../tests/fixtures/labels.yml"));
+        let flat = sysml_compose_package(&labelled, "app", &SysmlOptions::default());
+        assert!(flat.contains("            attribute 'app.kubernetes.io/component' redefines label = \"database\";\n"));
+        // Grouped labels have no label to redefine, so are strings
+        let grouped = sysml_compose_package(&labelled, "app", &SysmlOptions { max_attrs_per_group: Some(2), ..Default::default() });
+        assert!(grouped.contains("            attribute labelGroup2 {\n                attribute tier: String = \"backend\";\n"));
+        // web has no more labels than fit a group, so they aren't grouped
+        assert_eq!(grouped.matches("redefines label").count(), 2);
+
+        let options = SysmlOptions { include_environment: true, env_as_table: true, ..Default::default() };
+        let table = sysml_compose_package(&compose, "app", &options);
+        assert!(table.contains("            attribute environment: String = \"VAR_000=0\nVAR_001=1\n"));
+        assert_eq!(table.matches("VAR_").count(), 300);
+    }
+
+    #[test]
+    fn test_sysml_compose_published_and_exposed_ports() {
+        let compose = compose_from_str(r#"
+services:
+  web:
+    expose: ["9000", 8080]
+    ports:
+      - "8000:8080"
+        "#);
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(sysml.contains("            port port0: NetworkPort {\n                protocol redefines protocol = Protocol::TCP;\n                portNumber redefines portNumber = 8080;\n                published redefines published = true;\n"));
+        assert!(sysml.contains("            port port1: NetworkPort {\n                protocol redefines protocol = Protocol::TCP;\n                portNumber redefines portNumber = 9000;\n                published redefines published = false;\n"));
+        assert!(!sysml.contains("port port2"));
+    }
+
+    #[test]
+    fn test_sysml_named_ports() {
+        let yaml = r#"
+services:
+  api:
+    image: api:1
+    ports:
+      - name: http
+        target: 8080
+        published: "80"
+      - name: metrics-2
+        target: 9090
+      - name: image
+        target: 9100
+    networks: [backend]
+  web:
+    image: web:1
+    depends_on: [api]
+    ports:
+      - name: http
+        target: 80
+        published: "8080"
+    networks: [backend]
+networks:
+  backend: {}
+"#;
+        let compose = parse_compose::parse_composefile(Box::new(yaml.as_bytes())).unwrap().value;
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(sysml.contains("            port http: NetworkPort {\n                protocol redefines protocol = Protocol::TCP;\n                portNumber redefines portNumber = 8080;\n                published redefines published = true;\n                portName redefines portName = \"http\";\n"));
+        assert!(sysml.contains("port metrics_2: NetworkPort {"));
+        assert!(sysml.contains("portName redefines portName = \"metrics-2\";"));
+        // A name which clashes with a member of Container is identified by its index
+        assert!(sysml.contains("port port2: NetworkPort {"));
+        assert!(sysml_flow_connections(&compose, &RenderStyle::default()).contains("connect web.http to api.http {"));
+    }
+
+    #[test]
+    fn test_sysml_promote_extensions() {
+        let compose = parse_compose::parse_composefile(Box::new(include_str!("../tests/fixtures/extensions.yml").as_bytes())).unwrap().value;
+        let options = SysmlOptions { promote_extensions: vec!["x-owner".to_string(), "x-oncall".to_string()], ..Default::default() };
+        let sysml = sysml_compose_package(&compose, "app", &options);
+        assert!(sysml.contains(
+            "        part payments: Container {\n            attribute image redefines image = \"payments:2.1\";\n            \
+             attribute 'x-owner': String = \"payments-team\";\n            attribute 'x-oncall': String = \"{\\\"primary\\\":\\\"alice\\\",\\\"rota\\\":\\\"weekly\\\"}\";\n"
+        ));
+        assert!(sysml.contains("attribute 'x-owner': String = \"ledger, core\";"));
+        // Keys not promoted are left alone
+        assert!(!sysml.contains("x-tier"));
+        assert_eq!(compose.services["payments"].extensions["x-tier"].as_str(), Some("critical"));
+        assert!(!sysml_compose_package(&compose, "app", &SysmlOptions::default()).contains("x-owner"));
+    }
+
+    #[test]
+    fn test_port_identifiers() {
+        let names = [Some("http"), None, Some("9lives"), Some("http"), Some("port1"), Some("a.b")];
+        assert_eq!(port_identifiers(names), vec!["http", "port1", "port2", "port3", "port4", "a_b"]);
+    }
+
+    #[test]
+    fn test_sysml_emit_usages() {
+        let dockerfile = "FROM rust:1.75\nEXPOSE 8080\nVOLUME /data\n";
+        let container = parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "app").unwrap();
+        let options = SysmlOptions {
+            emit: Emit::Usages,
+            definitions_package: Some("Library::Containers".to_string()),
+            ..Default::default()
+        };
+        let sysml = sysml_cargotecture_package(&container, &options);
+        assert!(!sysml.contains("part def"));
+        assert!(!sysml.contains("port def"));
+        assert!(sysml.starts_with("package appModel {\n    /* SysML v2.0 generated by cargotecture */\n    attribute cargotectureMetamodelVersion = \"0.1.0\";\n\n    import Library::Containers::*;\n\n"));
+        assert!(sysml.contains("        part appBase: BaseImage {\n"));
+        assert!(sysml.contains("        part app: Container {\n"));
+        assert!(sysml.contains("            port port0: NetworkPort {\n"));
+        assert!(sysml.contains("            port volume0: VolumePort {\n"));
+    }
+
+    #[test]
+    fn test_sysml_emit_definitions() {
+        let compose = compose_from_str("services:\n  web:\n    image: nginx:1.25\n");
+        let options = SysmlOptions { emit: Emit::Definitions, ..Default::default() };
+        let sysml = sysml_compose_package(&compose, "app", &options);
+        assert!(sysml.starts_with("package CargotectureDefinitions {\n    /* SysML v2.0 generated by cargotecture */\n    attribute cargotectureMetamodelVersion = \"0.1.0\";\n\n    import ScalarValues::*;\n"));
+        for definition in ["part def Container {", "port def NetworkPort {", "part def Network {", "connection def InferredFlow {", "metadata def Docker {"] {
+            assert!(sysml.contains(definition), "{}", definition);
+        }
+        assert!(!sysml.contains("part web"));
+
+        let options = SysmlOptions { definitions_package: Some("Library::Containers".to_string()), ..options };
+        assert!(sysml_definitions_package(&options).starts_with("package Library {\n    package Containers {\n        /* SysML v2.0 generated by cargotecture */\n"));
+        assert_eq!("usages".parse::<Emit>().unwrap(), Emit::Usages);
+        assert!("all".parse::<Emit>().is_err());
+    }
+
+    #[test]
+    fn test_sysml_spec_version() {
+        let compose = compose_from_str("services:\n  web:\n    image: nginx:1.25\n");
+        let sysml = sysml_compose_package(&compose, "app", &SysmlOptions::default());
+        assert!(sysml.starts_with("package appModel {\n    /* SysML v2.0 generated by cargotecture */\n    attribute cargotectureMetamodelVersion = \"0.1.0\";\n"));
+
+        let options = SysmlOptions { sysml_spec_version: Some("2.1".to_string()), ..Default::default() };
+        let sysml = sysml_compose_package(&compose, "app", &options);
+        assert!(sysml.starts_with("package appModel {\n    /* SysML v2.1 generated by cargotecture */\n"));
+        assert!(options.effective().contains(&("sysml_spec_version".to_string(), "2.1".to_string())));
+    }
+
+    #[test]
+    fn test_sysml_provenance() {
+        let compose = compose_from_str("services:\n  web:\n    image: nginx:1.25\n");
+        let mut provenance = Provenance::new("0.1", true);
+        provenance.inputs.push(crate::provenance::InputHash::new("compose.yml", b""));
+        let options = SysmlOptions { provenance: Some(provenance), ..Default::default() };
+        let sysml = sysml_compose_package(&compose, "app", &options);
+        assert!(sysml.starts_with("package appModel {\n    /* Provenance\n     * tool: cargotecture 0.1\n     * input: compose.yml sha256:e3b0c442"));
+        assert_eq!(
+            crate::provenance::strip_sysml_provenance(&sysml),
+            sysml_compose_package(&compose, "app", &SysmlOptions::default())
+        );
+        let definitions = sysml_definitions_package(&SysmlOptions { emit: Emit::Definitions, ..options });
+        assert!(definitions.starts_with("package CargotectureDefinitions {\n    /* Provenance\n"));
+    }
+}
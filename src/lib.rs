@@ -0,0 +1,42 @@
+pub mod parse_dockerfile;
+#[cfg(feature = "pod")]
+pub mod parse_podfile;
+#[cfg(feature = "pod")]
+pub mod parse_manifests;
+#[cfg(feature = "compose")]
+pub mod parse_compose;
+pub mod error;
+pub mod util;
+#[cfg(feature = "sysml")]
+pub mod gen_sysml;
+#[cfg(feature = "diagrams")]
+pub mod gen_markdown;
+#[cfg(feature = "diagrams")]
+pub mod gen_csv;
+pub mod lint;
+pub mod output;
+#[cfg(feature = "sysml")]
+pub mod sysml_ast;
+#[cfg(all(feature = "compose", feature = "sysml"))]
+pub mod scan;
+#[cfg(all(feature = "compose", feature = "sysml"))]
+pub mod arch_model;
+#[cfg(feature = "sysml")]
+pub mod cache;
+#[cfg(all(feature = "compose", feature = "sysml"))]
+pub mod watch;
+pub mod provenance;
+#[cfg(feature = "compose")]
+pub mod registry;
+#[cfg(feature = "compose")]
+pub mod security;
+#[cfg(feature = "compose")]
+pub mod canonical;
+#[cfg(feature = "compose")]
+pub mod anonymize;
+#[cfg(feature = "compose")]
+pub mod env_report;
+pub mod validation;
+pub mod selector;
+#[cfg(all(feature = "compose", feature = "sysml"))]
+pub mod process;
@@ -0,0 +1,315 @@
+//! Lint rules for Dockerfiles, flagging instructions that build but likely don't do what
+//! their author intended. Each rule has an ID by which it can be suppressed.
+use std::fmt::{self, Display, Formatter};
+use dockerfile_parser::{Dockerfile, Instruction};
+use serde::Serialize;
+use crate::{
+    parse_dockerfile::{line_number, package_install, parse_add_instruction, resolve_path, volume_mount_points, CommandLine},
+    util::{is_within, split_shell_words},
+    validation::{DiagnosticCategory, DiagnosticInfo},
+};
+
+/// Files put under a directory already declared as a VOLUME
+pub const VOLUME_SHADOW: &str = "volume-shadow";
+
+/// A RUN directly after another RUN, which could share its layer
+pub const CONSECUTIVE_RUN: &str = "consecutive-run";
+
+/// A RUN installing packages which leaves the package manager's cache in its layer
+pub const PACKAGE_CACHE: &str = "package-cache";
+
+/// An ADD fetching a URL or git repository without a `--checksum` to verify it against
+pub const UNVERIFIED_REMOTE_ADD: &str = "unverified-remote-add";
+
+/// The IDs of all the lint rules
+pub const RULES: &[&str] = &[VOLUME_SHADOW, CONSECUTIVE_RUN, PACKAGE_CACHE, UNVERIFIED_REMOTE_ADD];
+
+/// The explanations of the lint rules, in the order of `RULES`
+pub const EXPLANATIONS: &[DiagnosticInfo] = &[
+    DiagnosticInfo {
+        code: VOLUME_SHADOW,
+        category: DiagnosticCategory::Containerfiles,
+        summary: "An instruction puts files under a directory declared as a VOLUME earlier in the stage.",
+        rationale: "Docker discards changes later build steps make under a volume, and other engines differ, so the files likely aren't there at runtime.",
+        failing: "FROM alpine\nVOLUME /data\nCOPY seed.db /data/\n",
+        passing: "FROM alpine\nCOPY seed.db /data/\nVOLUME /data\n",
+        remediation: "declare the VOLUME after the instructions which fill the directory.",
+    },
+    DiagnosticInfo {
+        code: CONSECUTIVE_RUN,
+        category: DiagnosticCategory::Containerfiles,
+        summary: "A RUN directly follows another RUN.",
+        rationale: "Each RUN makes a layer of its own, so the image is larger and slower to pull than it need be, and files deleted by the second remain in the first.",
+        failing: "FROM alpine\nRUN apk add curl\nRUN curl --version\n",
+        passing: "FROM alpine\nRUN apk add curl && curl --version\n",
+        remediation: "join the commands into one RUN with &&.",
+    },
+    DiagnosticInfo {
+        code: PACKAGE_CACHE,
+        category: DiagnosticCategory::Containerfiles,
+        summary: "A RUN installing packages leaves the package manager's cache in its layer.",
+        rationale: "The cache is never used at runtime but is shipped with every copy of the image.",
+        failing: "FROM debian\nRUN apt-get update && apt-get install -y curl\n",
+        passing: "FROM debian\nRUN apt-get update && apt-get install -y curl && rm -rf /var/lib/apt/lists/*\n",
+        remediation: "remove the cache in the same RUN, or use apk add --no-cache, or a cache mount.",
+    },
+    DiagnosticInfo {
+        code: UNVERIFIED_REMOTE_ADD,
+        category: DiagnosticCategory::Containerfiles,
+        summary: "An ADD fetches a URL or git repository without a --checksum.",
+        rationale: "Whatever the server returns at build time goes into the image, so a compromised or changed source changes the image without any change to the Dockerfile.",
+        failing: "FROM alpine\nADD https://example.com/tool.tar.gz /opt/\n",
+        passing: "FROM alpine\nADD --checksum=sha256:24454f830cdb571e2c4ad15481119c43b3cafd48dd869a9b2945d1036d1dc68d https://example.com/tool.tar.gz /opt/\n",
+        remediation: "give the digest of the expected content with --checksum, or download and verify it in a RUN.",
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Severity {
+    /// Worth knowing but not a problem, reported only by validation
+    Note,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Severity::Note => write!(f, "note"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// An instruction flagged by a lint rule
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LintFinding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    /// The line of the flagged instruction
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for LintFinding {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: line {}: {}", self.severity, self.rule, self.line, self.message)
+    }
+}
+
+/// Flag COPY and ADD instructions whose destination, and RUN instructions with an absolute
+/// path argument, under a directory declared as a VOLUME earlier in the same stage. Docker
+/// discards changes made under a volume by later build steps, and other engines differ, so
+/// the files likely aren't where the author expects at runtime. Paths in RUN commands are
+/// a best effort guess at what they write to.
+fn lint_volume_shadow(dockerfile: &Dockerfile, findings: &mut Vec<LintFinding>) {
+    for stage in dockerfile.iter_stages() {
+        let mut working_dir = "/".to_string();
+        let mut volumes: Vec<(String, usize)> = Vec::new();
+        for ins in stage.instructions {
+            let line = line_number(&dockerfile.content, ins.span().start);
+            let (instruction, paths) = match ins {
+                Instruction::Copy(copy) => ("COPY", vec![resolve_path(&working_dir, &copy.destination.content)]),
+                Instruction::Run(run) => {
+                    let words = match run.as_exec() {
+                        Some(exec) => exec.as_str_vec().iter().map(|s| s.to_string()).collect(),
+                        None => run.as_shell().and_then(|shell| split_shell_words(&shell.to_string())).unwrap_or_default(),
+                    };
+                    let paths = words
+                        .iter()
+                        .map(|word| word.trim_start_matches(['>', '<']))
+                        .filter(|word| word.starts_with('/'))
+                        .map(|word| resolve_path("/", word))
+                        .collect();
+                    ("RUN", paths)
+                }
+                Instruction::Misc(misc) => {
+                    let arguments = misc.arguments.to_string();
+                    match misc.instruction.content.to_ascii_uppercase().as_str() {
+                        "ADD" => match parse_add_instruction(&arguments, line) {
+                            Some(add) => ("ADD", vec![resolve_path(&working_dir, &add.destination)]),
+                            None => continue,
+                        },
+                        "WORKDIR" => {
+                            working_dir = resolve_path(&working_dir, arguments.trim());
+                            continue;
+                        }
+                        "VOLUME" => {
+                            volumes.extend(volume_mount_points(&arguments).into_iter().map(|path| (resolve_path("/", &path), line)));
+                            continue;
+                        }
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+            for path in paths {
+                if let Some((volume, volume_line)) = volumes.iter().find(|(volume, _)| is_within(&path, volume)) {
+                    findings.push(LintFinding {
+                        rule: VOLUME_SHADOW,
+                        severity: Severity::Warning,
+                        line,
+                        message: format!(
+                            "{} writes to {}, under the VOLUME {} declared at line {}, so the files may be hidden at runtime",
+                            instruction, path, volume, volume_line
+                        ),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Flag RUN instructions directly after another RUN in the same stage. Each creates a layer,
+/// so joining the commands with `&&` makes the image smaller unless the layers are meant to
+/// be cached separately.
+fn lint_consecutive_runs(dockerfile: &Dockerfile, findings: &mut Vec<LintFinding>) {
+    for stage in dockerfile.iter_stages() {
+        let mut previous_run = None;
+        for ins in stage.instructions {
+            let line = line_number(&dockerfile.content, ins.span().start);
+            if !matches!(ins, Instruction::Run(_)) {
+                previous_run = None;
+                continue;
+            }
+            if let Some(previous) = previous_run {
+                findings.push(LintFinding {
+                    rule: CONSECUTIVE_RUN,
+                    severity: Severity::Warning,
+                    line,
+                    message: format!("RUN directly follows the RUN at line {}, and could be merged into it to save a layer", previous),
+                });
+            }
+            previous_run = Some(line);
+        }
+    }
+}
+
+/// Flag RUN instructions installing packages without cleaning the package manager's cache
+/// in the same RUN. Cleaning it in a later RUN doesn't shrink the image, as the cache is
+/// already in the installing layer.
+fn lint_package_cache(dockerfile: &Dockerfile, findings: &mut Vec<LintFinding>) {
+    for ins in dockerfile.iter_stages().flat_map(|stage| stage.instructions) {
+        let Instruction::Run(run) = ins else {
+            continue;
+        };
+        if let Some((manager, false)) = package_install(&CommandLine::from_expr(&run.expr).display()) {
+            findings.push(LintFinding {
+                rule: PACKAGE_CACHE,
+                severity: Severity::Warning,
+                line: line_number(&dockerfile.content, ins.span().start),
+                message: format!("RUN installs packages with {} without cleaning its cache in the same RUN, so the cache stays in the layer", manager),
+            });
+        }
+    }
+}
+
+/// Flag ADD instructions fetching a URL or git repository without `--checksum`, whose
+/// content is whatever the source serves at build time
+fn lint_remote_adds(dockerfile: &Dockerfile, findings: &mut Vec<LintFinding>) {
+    for ins in dockerfile.iter_stages().flat_map(|stage| stage.instructions) {
+        let Instruction::Misc(misc) = ins else {
+            continue;
+        };
+        if !misc.instruction.content.eq_ignore_ascii_case("ADD") {
+            continue;
+        }
+        let line = line_number(&dockerfile.content, ins.span().start);
+        let Some(add) = parse_add_instruction(&misc.arguments.to_string(), line).filter(|add| add.checksum.is_none()) else {
+            continue;
+        };
+        for (source, kind) in add.remote_sources() {
+            findings.push(LintFinding {
+                rule: UNVERIFIED_REMOTE_ADD,
+                severity: Severity::Warning,
+                line,
+                message: format!("ADD fetches the {} source {} without a --checksum, so its content isn't verified", kind, source),
+            });
+        }
+    }
+}
+
+/// Lint a Dockerfile, skipping the rules whose IDs are in `allowed`. Findings are in line order.
+pub fn lint_containerfile(content: &str, allowed: &[String]) -> anyhow::Result<Vec<LintFinding>> {
+    let dockerfile = Dockerfile::parse(content)?;
+    let mut findings = Vec::new();
+    lint_volume_shadow(&dockerfile, &mut findings);
+    lint_consecutive_runs(&dockerfile, &mut findings);
+    lint_package_cache(&dockerfile, &mut findings);
+    lint_remote_adds(&dockerfile, &mut findings);
+    findings.retain(|finding| !allowed.iter().any(|rule| rule == finding.rule));
+    findings.sort_by_key(|finding| finding.line);
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(content: &str) -> Vec<usize> {
+        lint_containerfile(content, &[]).unwrap().iter().map(|finding| finding.line).collect()
+    }
+
+    #[test]
+    fn test_volume_shadow() {
+        let content = "FROM alpine\nVOLUME /data\nCOPY seed/ /data/\n";
+        let findings = lint_containerfile(content, &[]).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].to_string(),
+            "warning[volume-shadow]: line 3: COPY writes to /data, under the VOLUME /data declared at line 2, so the files may be hidden at runtime"
+        );
+
+        // Populating the directory before declaring the volume is fine
+        assert!(lines("FROM alpine\nCOPY seed/ /data/\nVOLUME /data\n").is_empty());
+        // Subdirectories of the volume are shadowed too, relative destinations included
+        assert_eq!(lines("FROM alpine\nVOLUME [\"/data\"]\nWORKDIR /data\nADD seed.tar.gz db/\nCOPY app /app\n"), vec![4]);
+        assert!(lines("FROM alpine\nVOLUME /data\nCOPY app /database\n").is_empty());
+        // RUN commands are checked for absolute paths under the volume
+        assert_eq!(lines("FROM alpine\nVOLUME /var/lib/db\nRUN mkdir -p /var/lib/db/init && echo ok >/var/lib/db/ready\n"), vec![3, 3]);
+        // Volumes are scoped to their stage
+        assert!(lines("FROM alpine AS build\nVOLUME /data\nFROM alpine\nCOPY seed /data\n").is_empty());
+    }
+
+    #[test]
+    fn test_build_layers() {
+        let content = "FROM debian:12\nRUN apt-get update\nRUN apt-get install -y curl\nRUN useradd app\nUSER app\nRUN echo done\n";
+        let findings: Vec<String> = lint_containerfile(content, &[]).unwrap().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            findings,
+            vec![
+                "warning[consecutive-run]: line 3: RUN directly follows the RUN at line 2, and could be merged into it to save a layer",
+                "warning[package-cache]: line 3: RUN installs packages with apt-get without cleaning its cache in the same RUN, so the cache stays in the layer",
+                "warning[consecutive-run]: line 4: RUN directly follows the RUN at line 3, and could be merged into it to save a layer",
+            ]
+        );
+
+        // Installing and cleaning in one RUN is fine, as is apk's --no-cache
+        assert!(lines("FROM debian:12\nRUN apt-get update && apt-get install -y curl \\\n    && rm -rf /var/lib/apt/lists/*\n").is_empty());
+        assert!(lines("FROM alpine\nRUN apk add --no-cache curl\n").is_empty());
+        assert_eq!(lines("FROM fedora\nRUN dnf install -y curl\n"), vec![2]);
+        // RUNs in different stages can't be merged
+        assert!(lines("FROM alpine AS build\nRUN make\nFROM alpine\nRUN true\n").is_empty());
+    }
+
+    #[test]
+    fn test_remote_adds() {
+        let findings = lint_containerfile("FROM alpine\nADD https://example.com/tool.tar.gz /opt/\n", &[]).unwrap();
+        assert_eq!(
+            findings.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["warning[unverified-remote-add]: line 2: ADD fetches the url source https://example.com/tool.tar.gz without a --checksum, so its content isn't verified"]
+        );
+        assert_eq!(lines("FROM alpine\nADD git@github.com:moby/buildkit.git#v0.12.0 /src\n"), vec![2]);
+
+        // Verified downloads and local files are fine
+        assert!(lines("FROM alpine\nADD --checksum=sha256:24454f830cdb571e2c4ad15481119c43b3cafd48dd869a9b2945d1036d1dc68d https://example.com/tool.tar.gz /opt/\n").is_empty());
+        assert!(lines("FROM alpine\nADD tool.tar.gz /opt/\n").is_empty());
+    }
+
+    #[test]
+    fn test_suppressed_rules() {
+        let content = "FROM alpine\nVOLUME /data\nCOPY seed/ /data/\n";
+        assert!(lint_containerfile(content, &[VOLUME_SHADOW.to_string()]).unwrap().is_empty());
+    }
+}
@@ -1,21 +1,96 @@
-mod parse_dockerfile;
-mod parse_podfile;
-mod parse_compose;
-mod util;
-mod gen_sysml;
-
 use std::fs;
-use crate::{
-    parse_dockerfile::{parse_dockerfile,parse_containerfile},
-    parse_compose::parse_composefile,
+use cargotecture::{
+    parse_dockerfile::{self,parse_dockerfile,parse_containerfile_with_args,parse_containerfile_lenient,parse_dockerignore,ParsedContainer},
+    parse_compose::{parse_composefiles_unvalidated,load_build_containers,Compose,resolve_compose_files,ComposeFileSource,ParseOptions,DEFAULT_MAX_EXTENDS_DEPTH},
+    parse_manifests::parse_manifests,
     parse_podfile::parse_podfile,
-    util::get_basename,
+    gen_sysml::{self, Emit, GroupBy, SysmlOptions},
+    gen_markdown::{compose_to_markdown, container_to_markdown},
+    gen_csv::{compose_to_csv_with_extensions, CSV_HEADER},
+    lint,
+    scan::{generate_packages_cached, scan_directory, source_kind, write_split_output, SourceKind},
+    cache::ScanCache,
+    arch_model::{change_summary, reconciliation_summary, ArchModel},
+    watch::ComposeWatcher,
+    output,
+    provenance::{strip_sysml_provenance, InputHash, Provenance},
+    selector::LabelSelector,
+    sysml_ast::{BraceStyle, Indent, RenderStyle},
+    util::{get_basename,get_stem,glob_match,unified_diff},
+    validation::{all_codes, explain, explanations, validate_compose_readers, validate_containerfile_reader, DiagnosticInfo, Level, ValidateOptions, ValidationReport},
 };
 
 use anyhow::{Result,anyhow};
-use clap::{Parser, Subcommand};
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::io::{self, BufReader, Cursor, IsTerminal, Read};
+use std::{fmt, str::FromStr};
+
+/// The format of the generated output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    #[default]
+    Sysml,
+    Markdown,
+    /// The waves services can be started in, for compose files only
+    StartOrder,
+    StartOrderJson,
+    /// The parsed container as JSON, for containerfiles only
+    Json,
+    Csv,
+    /// A report of security-relevant configuration, for compose files only
+    Security,
+    /// A table of the security posture of each service, riskiest first, for compose files only
+    SecurityReport,
+    SecurityReportJson,
+    /// The effective environment of each service, for compose files only
+    EnvReport,
+    EnvReportJson,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sysml" => Ok(Format::Sysml),
+            "markdown" => Ok(Format::Markdown),
+            "start-order" => Ok(Format::StartOrder),
+            "start-order-json" => Ok(Format::StartOrderJson),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "security" => Ok(Format::Security),
+            "security-report" => Ok(Format::SecurityReport),
+            "security-report-json" => Ok(Format::SecurityReportJson),
+            "env-report" => Ok(Format::EnvReport),
+            "env-report-json" => Ok(Format::EnvReportJson),
+            _ => Err(format!(
+                "unknown format '{}', expected sysml, markdown, start-order, start-order-json, json, csv, security, security-report, security-report-json, env-report or env-report-json",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Sysml => write!(f, "sysml"),
+            Format::Markdown => write!(f, "markdown"),
+            Format::StartOrder => write!(f, "start-order"),
+            Format::StartOrderJson => write!(f, "start-order-json"),
+            Format::Json => write!(f, "json"),
+            Format::Csv => write!(f, "csv"),
+            Format::Security => write!(f, "security"),
+            Format::SecurityReport => write!(f, "security-report"),
+            Format::SecurityReportJson => write!(f, "security-report-json"),
+            Format::EnvReport => write!(f, "env-report"),
+            Format::EnvReportJson => write!(f, "env-report-json"),
+        }
+    }
+}
 
 #[allow(dead_code)]
 fn debug_dump_dockerfile_struct(block: &parse_dockerfile::ParsedContainer) {
@@ -25,7 +100,7 @@ fn debug_dump_dockerfile_struct(block: &parse_dockerfile::ParsedContainer) {
 
 pub fn demo(path: &str) -> Result<()> {
     let container = parse_dockerfile(path)?;
-    let parts=gen_sysml::sysml_cargotecture_package(&container);
+    let parts=gen_sysml::sysml_cargotecture_package(&container, &SysmlOptions::default());
     print!("{}",parts);
     Ok(())
 }
@@ -61,62 +136,949 @@ fn create_reader(filename: Option<&str>) -> Box<dyn Read> {
     }
 }
 
+/// Read an input in full, recording its hash for provenance, and return a reader of it
+fn hashed_reader(filename: Option<&str>, inputs: &mut Vec<InputHash>) -> Box<dyn Read> {
+    let mut content = Vec::new();
+    create_reader(filename).read_to_end(&mut content).expect("Unable to read the input");
+    inputs.push(InputHash::new(filename.unwrap_or("<stdin>"), &content));
+    Box::new(Cursor::new(content))
+}
+
+/// Parse a `--build-arg` as Docker does: `KEY=VALUE`, or `KEY` to take the value from the environment
+fn parse_build_arg(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) => Ok((key.to_string(), value.to_string())),
+        None => std::env::var(arg)
+            .map(|value| (arg.to_string(), value))
+            .map_err(|_| format!("no value for build argument '{}' and it is not set in the environment", arg)),
+    }
+}
+
+/// Parse a `--map` pair of a compose service and the Kubernetes workload deploying it
+fn parse_component_pair(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once('=') {
+        Some((service, workload)) if !service.is_empty() && !workload.is_empty() => Ok((service.to_string(), workload.to_string())),
+        _ => Err(format!("expected SERVICE=WORKLOAD, found '{}'", pair)),
+    }
+}
+
+/// A glob of the codes of problems, which must match at least one
+fn parse_code_pattern(pattern: &str) -> Result<String, String> {
+    match all_codes().iter().any(|(code, _)| glob_match(pattern, code)) {
+        true => Ok(pattern.to_string()),
+        false => Err(format!("'{}' matches no problem code", pattern)),
+    }
+}
+
+/// The levels set by the `--allow` and `--deny` arguments of a command, in the order given
+fn code_levels(matches: Option<&ArgMatches>) -> Vec<(String, Level)> {
+    let mut levels: Vec<(usize, String, Level)> = Vec::new();
+    for (id, level) in [("allow", Level::Allow), ("deny", Level::Deny)] {
+        let Some(matches) = matches else { continue };
+        let (Some(indices), Some(patterns)) = (matches.indices_of(id), matches.get_many::<String>(id)) else { continue };
+        levels.extend(indices.zip(patterns).map(|(index, pattern)| (index, pattern.clone(), level)));
+    }
+    levels.sort_by_key(|(index, _, _)| *index);
+    levels.into_iter().map(|(_, pattern, level)| (pattern, level)).collect()
+}
+
+/// An extension field to promote, which must be named as `x-` extensions are
+fn parse_extension_key(key: &str) -> Result<String, String> {
+    match key.strip_prefix("x-") {
+        Some(rest) if !rest.is_empty() => Ok(key.to_string()),
+        _ => Err(format!("'{}' is not an extension field: extension fields start with 'x-'", key)),
+    }
+}
+
+/// Warn when an emitted file is too large for modelling tools to handle comfortably
+fn warn_if_oversized(name: &Path, size: usize, threshold: usize) {
+    if size > threshold {
+        eprintln!(
+            "warning: {} is {} bytes, larger than the {} byte threshold. Modelling tools may struggle with it; consider --split-output",
+            name.display(), size, threshold
+        );
+    }
+}
+
+/// The provenance of a compose model: the files read as given, followed by any they
+/// included such as by extends
+fn compose_provenance(provenance: &Provenance, mut inputs: Vec<InputHash>, compose: &Compose) -> Provenance {
+    inputs.extend(compose.included_files.iter().filter_map(|file| InputHash::of_file(Path::new(file)).ok()));
+    provenance.with_inputs(inputs)
+}
+
+/// With `self_check` on, check generated SysML with `check_sysml`, exiting if it has
+/// problems, which are regressions in the generator rather than in its input. `file` names
+/// the file it was generated from or written to, if it isn't printed.
+fn self_check_sysml(sysml: &str, file: Option<&Path>, self_check: bool) {
+    let issues = if self_check { gen_sysml::check_sysml(sysml) } else { Vec::new() };
+    if !issues.is_empty() {
+        for issue in issues {
+            match file {
+                Some(path) => eprintln!("error: generated SysML for {} failed its self-check at {}", path.display(), issue),
+                None => eprintln!("error: generated SysML failed its self-check at {}", issue),
+            }
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Print generated SysML, first checking it with `self_check_sysml`
+fn print_sysml(sysml: &str, self_check: bool) {
+    self_check_sysml(sysml, None, self_check);
+    print!("{}", sysml);
+}
+
+/// Regenerate the model of compose files each time they change, never returning. The
+/// changes from the previous model are reported on stderr.
+fn watch_compose(
+    files: &[String],
+    parse_options: ParseOptions,
+    name: &str,
+    mut options: SysmlOptions,
+    provenance: Option<Provenance>,
+    poll_interval: u64,
+    self_check: bool,
+) -> ! {
+    let mut watcher = ComposeWatcher::new(files.iter().map(PathBuf::from).collect(), parse_options);
+    loop {
+        match watcher.poll() {
+            Ok(Some(regeneration)) => {
+                options.provenance = provenance.as_ref().map(|provenance| {
+                    let inputs = files.iter().filter_map(|file| InputHash::of_file(Path::new(file)).ok()).collect();
+                    compose_provenance(provenance, inputs, &regeneration.compose)
+                });
+                print_sysml(&gen_sysml::sysml_compose_package(&regeneration.compose, name, &options), self_check);
+                eprint!("{}", change_summary(&regeneration.changes));
+            }
+            Ok(None) => (),
+            Err(err) => eprintln!("Parse failed: {}", err),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(poll_interval));
+    }
+}
+
+/// Check that a committed model matches the one generated from compose files, printing a
+/// unified diff and a summary of the changes to the model if not
+fn check_compose(compose: &Compose, containers: &HashMap<String, ParsedContainer>, name: &str, options: &SysmlOptions, check: &Path, normalize_refs: bool) -> bool {
+    let expected = match fs::read_to_string(check) {
+        Ok(expected) => expected,
+        Err(err) => {
+            eprintln!("Failed to read {}: {}", check.display(), err);
+            return false;
+        }
+    };
+    let generated = gen_sysml::sysml_compose_package_with_containers(compose, name, containers, options);
+    let (expected, generated) = (strip_sysml_provenance(&expected), strip_sysml_provenance(&generated));
+    if expected == generated {
+        return true;
+    }
+    print!("{}", unified_diff(&check.display().to_string(), "generated", &expected, &generated));
+    let changes = ArchModel::from_sysml(&expected).diff_with(&ArchModel::from_compose_with_containers(compose, containers), normalize_refs);
+    eprint!("{}", change_summary(&changes));
+    eprintln!("{} is out of date", check.display());
+    false
+}
+
+#[derive(Args)]
+struct ProvenanceArgs {
+    #[clap(long, help = "Omit the generation time from the provenance comment, so the same inputs always give the same output")]
+    reproducible: bool,
+    #[clap(long, conflicts_with = "reproducible", help = "Don't record the tool version, input hashes and options used in the output")]
+    no_provenance: bool,
+}
+
+impl ProvenanceArgs {
+    /// The provenance to record for the given generation options, without its inputs
+    fn provenance(&self, options: &SysmlOptions) -> Option<Provenance> {
+        if self.no_provenance {
+            return None;
+        }
+        let version = Cli::command().get_version().unwrap_or(env!("CARGO_PKG_VERSION")).to_string();
+        let mut provenance = Provenance::new(&version, self.reproducible);
+        provenance.options = options.effective();
+        Some(provenance)
+    }
+}
+
+#[derive(Args)]
+struct StyleArgs {
+    #[clap(long, value_name = "INDENT", default_value_t = Indent::default(), help = "Indent each level of the SysML by this many spaces, or a tab (1-8|tab)")]
+    indent: Indent,
+    #[clap(long, value_name = "COLUMNS", help = "Wrap string values onto further lines to keep the SysML within this width where possible [default: no wrapping]")]
+    max_width: Option<usize>,
+    #[clap(long, default_value_t = BraceStyle::default(), help = "Open bodies on the line of their declaration or on the next (same-line|next-line)")]
+    brace_style: BraceStyle,
+}
+
+impl StyleArgs {
+    fn style(&self) -> RenderStyle {
+        RenderStyle { indent: self.indent, max_width: self.max_width, braces: self.brace_style }
+    }
+}
+
 #[derive(Parser)]
 #[clap(version = "0.1", author = "Andrew Mobbs <andrew.mobbs@gmail.com>", about = "Generate SysML version 2 representations of container files")]
 #[command(propagate_version = true)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    #[clap(long, global = true, hide = true, help = "Check the structure of generated SysML before printing it, failing if it has problems. Always on in debug builds")]
+    self_check: bool,
 }
 
+const COMPOSE_FILE_PRECEDENCE: &str = "\
+Input files are chosen in this order of precedence:
+  1. filenames given as arguments
+  2. with --use-env, the COMPOSE_FILE variable, split on COMPOSE_PATH_SEPARATOR
+  3. compose.yaml, compose.yml, docker-compose.yaml or docker-compose.yml in the
+     current directory, with its .override file merged on top. This lookup is
+     made when stdin is a terminal or with --use-env
+  4. stdin
+With --use-env, COMPOSE_PROJECT_NAME names the generated model.";
+
 #[derive(Subcommand)]
 enum Commands {
     #[clap(about = "Parses container files", alias = "cf")]
     Containerfile {
         #[clap(help = "The input file. If not provided, stdin will be used")]
         filename: Option<String>,
+        #[clap(long, default_value_t = Format::Sysml, help = "Generate a SysML model, Markdown documentation, the parsed container as JSON, or a CSV summary (sysml|markdown|json|csv)")]
+        format: Format,
+        #[clap(long, help = "Annotate generated elements with metadata tracing them to their source")]
+        include_metadata: bool,
+        #[clap(long, help = "Emit ports in port number order rather than declaration order")]
+        ports_sorted: bool,
+        #[clap(long, conflicts_with = "ports_sorted", help = "Also emit a package of network access requirements satisfied by the exposed ports")]
+        requirements: bool,
+        #[clap(long, help = "Emit the paths the image copies files to, works in and declares as volumes")]
+        include_filesystem: bool,
+        #[clap(long, help = "Emit the RUN instructions as a sequence of build actions")]
+        include_build_actions: bool,
+        #[clap(long, help = "Emit the layer-creating instructions, mergeable RUNs and package cache cleaning of each stage")]
+        include_metrics: bool,
+        #[clap(long, value_name = "N", help = "Fold label lists longer than N into nested groups of at most N")]
+        max_attrs_per_group: Option<usize>,
+        #[clap(long = "build-arg", value_name = "KEY=VALUE", value_parser = parse_build_arg, help = "Override the value of an ARG, as docker build does")]
+        build_args: Vec<(String, String)>,
+        #[clap(long, requires = "filename", help = "Warn about COPY and ADD sources excluded by the .dockerignore next to the input file")]
+        check_dockerignore: bool,
+        #[clap(long, help = "Report likely mistakes in the containerfile on stderr")]
+        lint: bool,
+        #[clap(long, value_name = "RULE", requires = "lint", value_parser = clap::builder::PossibleValuesParser::new(lint::RULES), help = "Don't report findings of a lint rule")]
+        allow: Vec<String>,
+        #[clap(long, help = "When the containerfile can't be parsed, read what it can line by line rather than failing")]
+        lenient: bool,
+        #[command(flatten)]
+        style: StyleArgs,
+        #[command(flatten)]
+        provenance: ProvenanceArgs,
+        #[clap(long, default_value_t = Emit::Both, help = "Generate only the shared definitions, only the usages importing them, or both (definitions|usages|both)")]
+        emit: Emit,
+        #[clap(long, value_name = "QUALIFIED_NAME", help = "The package holding the shared definitions [default: CargotectureDefinitions]")]
+        definitions_package: Option<String>,
     },
-    #[clap(about = "Parses compose files", alias = "cmp")]
+    #[clap(about = "Parses compose files", alias = "cmp", after_help = COMPOSE_FILE_PRECEDENCE)]
     Compose {
-        #[clap(help = "The input file. If not provided, stdin will be used")]
-        filename: Option<String>,
+        #[clap(help = "The input files, later files merged over earlier ones. If not provided, stdin will be used")]
+        filenames: Vec<String>,
+        #[clap(long, help = "Use COMPOSE_FILE, COMPOSE_PATH_SEPARATOR and COMPOSE_PROJECT_NAME from the environment")]
+        use_env: bool,
+        #[clap(long, default_value_t = Format::Sysml, help = "Generate a SysML model, Markdown documentation, a CSV summary of the services, the waves services start in as text or JSON, a report of security findings, or a table or JSON of each service's security posture, which takes Dockerfile users from --follow-builds, or the effective environment of each service after reading its env files and interpolating variables, as text or JSON (sysml|markdown|csv|start-order|start-order-json|security|security-report|security-report-json|env-report|env-report-json)")]
+        format: Format,
+        #[clap(long, help = "Emit connections for service-to-service flows inferred from environment values")]
+        infer_flows: bool,
+        #[clap(long, help = "Annotate generated elements with metadata tracing them to their source")]
+        include_metadata: bool,
+        #[clap(long, default_value_t = GroupBy::None, help = "Organise service parts in network packages, in dependency order, or flat and alphabetical (network|dependency|none)")]
+        group_by: GroupBy,
+        #[clap(long, help = "Emit ports in port number order rather than declaration order")]
+        ports_sorted: bool,
+        #[clap(long, help = "Put each service in a package of its own")]
+        per_service_packages: bool,
+        #[clap(long, help = "Emit the environment variables of services")]
+        include_environment: bool,
+        #[clap(long, value_name = "N", help = "Fold label and environment lists longer than N into nested groups of at most N")]
+        max_attrs_per_group: Option<usize>,
+        #[clap(long, requires = "include_environment", help = "Emit the environment of each service as a single multi-line string attribute")]
+        env_as_table: bool,
+        #[clap(long, default_value_t = Emit::Both, help = "Generate only the shared definitions, only the usages importing them, or both (definitions|usages|both)")]
+        emit: Emit,
+        #[clap(long, value_name = "QUALIFIED_NAME", help = "The package holding the shared definitions [default: CargotectureDefinitions]")]
+        definitions_package: Option<String>,
+        #[clap(long, value_name = "DEPTH", default_value_t = DEFAULT_MAX_EXTENDS_DEPTH, help = "The most services an extends chain may pass through")]
+        max_extends_depth: usize,
+        #[clap(long, conflicts_with = "watch", help = "Model the containers built for services from their Dockerfiles, at their build.target stage")]
+        follow_builds: bool,
+        #[clap(long, requires = "follow_builds", help = "Emit the paths the images built for services copy files to, work in and declare as volumes")]
+        include_filesystem: bool,
+        #[clap(long, help = "Emit one BaseImage part per distinct image, referenced by every service using it, with its number of consumers")]
+        shared_base_images: bool,
+        #[clap(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true", help = "Compare image references normalised, so that nginx, nginx:latest and docker.io/library/nginx:latest are the same image. On by default for --shared-base-images, and off by default for the summary of --check, so that pinning a tag is a change")]
+        normalize_refs: Option<bool>,
+        #[clap(long, conflicts_with = "watch", help = "Warn about service images missing from their registries, such as Docker Hub and GHCR. Private images can't be checked")]
+        check_images: bool,
+        #[clap(long, value_name = "MS", default_value_t = 5000, help = "How long --check-images waits for each registry request")]
+        image_timeout: u64,
+        #[clap(long, conflicts_with = "check", help = "Regenerate the model whenever the input files change, reporting what changed on stderr")]
+        watch: bool,
+        #[clap(long, value_name = "MS", default_value_t = 500, help = "How often --watch polls the input files")]
+        poll_interval: u64,
+        #[clap(long, value_name = "FILE", help = "Compare the generated model with FILE, printing a diff and a summary of the changes and failing if they differ. Provenance comments are ignored")]
+        check: Option<PathBuf>,
+        #[clap(long, help = "Also warn about deprecated options found while parsing")]
+        verbose: bool,
+        #[clap(long, help = "Read the input files as JSON. Without it, files that are a single JSON object are read as JSON and others as YAML")]
+        json: bool,
+        #[clap(long, value_name = "KEY", value_delimiter = ',', value_parser = parse_extension_key, help = "Emit the x- extension fields of services with these keys as attributes of their parts and, with --format csv, as extra columns. Values other than scalars are given as JSON")]
+        promote_extensions: Vec<String>,
+        #[clap(long, help = "Print tables at full width, rather than truncating cells to fit the terminal")]
+        wide: bool,
+        #[clap(long, value_name = "SELECTOR", conflicts_with = "watch", help = "Model only the services whose labels match a selector such as 'tier=backend,env in (staging,prod)', dropping dependencies on the others")]
+        select_labels: Option<LabelSelector>,
+        #[clap(long = "profile", value_name = "PROFILE", value_delimiter = ',', conflicts_with = "watch", help = "Model only the services compose starts with these profiles active: those with no profiles or an active one, and the services they require, whose activation is noted on stderr [default: every service]")]
+        profiles: Vec<String>,
+        #[clap(long, requires = "profiles", help = "Fail when an active service requires a service outside the active profiles, rather than activating it as compose does")]
+        no_auto_activate: bool,
+        #[clap(long, conflicts_with_all = ["watch", "follow_builds"], help = "Pseudonymise service, network and host names, registries, images, paths and configuration values so the output can be shared, keeping ports and the relationships between services. The provenance comment, which names the input files, is left out")]
+        anonymize: bool,
+        #[clap(long, value_name = "FILE", requires = "anonymize", help = "Write the originals of the pseudonyms --anonymize gave to FILE as JSON, to translate answers about the anonymized model back")]
+        anonymize_map: Option<PathBuf>,
+        #[clap(long, value_name = "SEED", requires = "anonymize", help = "Order the pseudonyms --anonymize gives by SEED, so that the same inputs and seed always give the same pseudonyms [default: none]")]
+        anonymize_seed: Option<String>,
+        #[command(flatten)]
+        style: StyleArgs,
+        #[command(flatten)]
+        provenance: ProvenanceArgs,
     },
-    #[clap(about = "Parses pod files")]
+    #[clap(about = "Generates a model of every containerfile and compose file under a directory")]
+    Scan {
+        #[clap(default_value = ".", help = "The directory to scan")]
+        dir: PathBuf,
+        #[clap(long, value_name = "DIR", help = "Write each package to a file of its own in DIR, with an index package importing them all")]
+        split_output: Option<PathBuf>,
+        #[clap(long, value_name = "BYTES", default_value_t = 1_048_576, help = "Warn when an emitted file is larger than this")]
+        size_warning: usize,
+        #[clap(long, help = "Annotate generated elements with metadata tracing them to their source")]
+        include_metadata: bool,
+        #[clap(long, value_name = "DIR", help = "Cache what each file generates in DIR, so later scans only parse the files that changed")]
+        cache_dir: Option<PathBuf>,
+        #[clap(long, requires = "cache_dir", help = "Parse every file rather than using the cache, refreshing it")]
+        no_cache: bool,
+        #[command(flatten)]
+        style: StyleArgs,
+        #[command(flatten)]
+        provenance: ProvenanceArgs,
+    },
+    #[clap(about = "Manages the cache of scan results")]
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    #[clap(about = "Queries compose files and pod manifests without generating anything")]
+    Query {
+        #[command(subcommand)]
+        query: QueryAction,
+    },
+    #[clap(about = "Validates compose files and containerfiles without generating anything, reporting problems on stderr and failing on errors")]
+    Validate {
+        #[clap(required = true, help = "The input files, told apart by name as scan does. Compose files are merged, later over earlier, and containerfiles validated one by one")]
+        filenames: Vec<String>,
+        #[clap(long, help = "Treat warnings as errors")]
+        strict: bool,
+        #[clap(long, help = "Warn about unknown fields in compose files rather than failing")]
+        lenient: bool,
+        #[clap(long, help = "Also report likely mistakes in containerfiles")]
+        lint: bool,
+        #[clap(long, value_name = "CODE", value_parser = parse_code_pattern, help = "Don't report problems with a code, or with codes matching a glob such as 'invalid-*'. Where --allow and --deny both match a code, the last given wins")]
+        allow: Vec<String>,
+        #[clap(long, value_name = "CODE", value_parser = parse_code_pattern, help = "Report problems with a code, or with codes matching a glob, as errors")]
+        deny: Vec<String>,
+        #[clap(long = "profile", value_name = "PROFILE", value_delimiter = ',', help = "Validate compose files as started with these profiles active, also reporting the services they activate as required dependencies and the optional dependencies they leave out")]
+        profiles: Vec<String>,
+        #[clap(long, short, help = "Print the level each problem code is reported at, and where each problem's value was defined and overridden in the files")]
+        verbose: bool,
+    },
+    #[clap(about = "Explains what a problem code reported by validate means and how to fix it")]
+    Explain {
+        #[clap(required_unless_present = "list", help = "The code, such as dependency-cycle")]
+        code: Option<String>,
+        #[clap(long, conflicts_with = "code", help = "List every code, grouped by category, with a summary of each")]
+        list: bool,
+    },
+    #[clap(about = "Writes compose files canonically: keys sorted, anchors resolved, ports and volumes in the long syntax and environment variables as a sorted list")]
+    Fmt {
+        #[clap(help = "The compose files, each formatted on its own. If not provided, stdin will be formatted")]
+        filenames: Vec<String>,
+        #[clap(long, requires = "filenames", conflicts_with = "check", help = "Rewrite the files in place rather than printing them")]
+        write: bool,
+        #[clap(long, help = "Print nothing, but list the files which aren't formatted canonically on stderr and fail if there are any")]
+        check: bool,
+    },
+    #[clap(about = "Generates a SysML part definition of a Kubernetes pod and its containers, or with several manifests a package of their pods and Ingresses, connected by the routes of the Ingresses and saying whether NetworkPolicies allow them")]
     Pod {
         #[clap(help = "The input file. If not provided, stdin will be used")]
         filename: Option<String>,
+        #[clap(long, value_name = "SELECTOR", help = "Model only the pods whose labels match a selector such as 'tier=backend'")]
+        select_labels: Option<LabelSelector>,
+        #[command(flatten)]
+        style: StyleArgs,
+        #[command(flatten)]
+        provenance: ProvenanceArgs,
+    },
+    #[clap(about = "Generates a single SysML model of an application deployed by both a compose file and Kubernetes manifests, matching their components by image, with how each matched component diverges and those only one deploys. A summary of the divergences is printed on stderr")]
+    Reconcile {
+        #[clap(help = "The compose file")]
+        compose: String,
+        #[clap(help = "The Kubernetes manifests")]
+        manifests: String,
+        #[clap(long = "map", value_name = "SERVICE=WORKLOAD", value_parser = parse_component_pair, help = "Match a compose service with a Kubernetes workload, named as its pod, Deployment, StatefulSet, DaemonSet, ReplicaSet or Job, or as workload/container for pods of several containers, whatever their images")]
+        map: Vec<(String, String)>,
+        #[clap(long, help = "The name of the model, by default the compose file's")]
+        name: Option<String>,
+        #[command(flatten)]
+        style: StyleArgs,
+        #[command(flatten)]
+        provenance: ProvenanceArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    #[clap(about = "Removes every cached scan result")]
+    Clear {
+        #[clap(long, value_name = "DIR", help = "The cache directory given to scan")]
+        cache_dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum QueryAction {
+    #[clap(about = "Lists the compose services, or the pods, whose labels match a Kubernetes-style selector, one per line")]
+    Labels {
+        #[clap(help = "The selector, requirements separated by commas: key=value, key==value, key!=value, 'key in (a,b)', 'key notin (a,b)', key or !key")]
+        selector: LabelSelector,
+        #[clap(help = "The input files. Compose files are merged, later over earlier. If not provided, stdin will be used")]
+        filenames: Vec<String>,
+        #[clap(long, help = "Read the input files as pod manifests rather than compose files")]
+        pod: bool,
     },
 }
 
+/// Every code a problem may be reported with and a summary of each, grouped by category
+fn list_explanations() -> String {
+    let mut explanations: Vec<&DiagnosticInfo> = explanations().collect();
+    explanations.sort_by_key(|info| (info.category, info.code));
+    let width = explanations.iter().map(|info| info.code.len()).max().unwrap_or(0);
+    let mut list = String::new();
+    for (index, info) in explanations.iter().enumerate() {
+        if index == 0 || explanations[index - 1].category != info.category {
+            list.push_str(&format!("{}{}:\n", if index == 0 { "" } else { "\n" }, info.category));
+        }
+        list.push_str(&format!("  {:width$}  {}\n", info.code, info.summary, width = width));
+    }
+    list
+}
+
+/// The names of the compose services, or with `pod` the pods, in `filenames` whose labels
+/// `selector` matches
+fn query_labels(selector: &LabelSelector, filenames: &[String], pod: bool) -> Result<Vec<String>> {
+    let filenames: Vec<Option<&str>> = if filenames.is_empty() { vec![None] } else { filenames.iter().map(|file| Some(file.as_str())).collect() };
+    if pod {
+        let mut names = Vec::new();
+        for filename in filenames {
+            let pod = parse_podfile(create_reader(filename))?;
+            if selector.matches(&pod.labels) {
+                names.push(pod.name);
+            }
+        }
+        return Ok(names);
+    }
+    let readers = filenames.into_iter().map(create_reader).collect();
+    let compose = parse_composefiles_unvalidated(readers, &ParseOptions::default())?;
+    Ok(compose.services_matching(selector).into_iter().map(str::to_string).collect())
+}
+
+/// Warn on stderr about the COPY and ADD sources of a container that the `.dockerignore`
+/// next to its Dockerfile excludes from the build context
+fn warn_dockerignored_copies(filename: &str, container: &ParsedContainer) {
+    let dockerignore = Path::new(filename).with_file_name(".dockerignore");
+    if !dockerignore.exists() {
+        return;
+    }
+    match parse_dockerignore(&dockerignore) {
+        Ok(patterns) => {
+            for (copy, source) in container.ignored_sources(&patterns) {
+                eprintln!("Warning: {} source '{}' at line {} is excluded by {}", copy.instruction, source, copy.line, dockerignore.display());
+            }
+        }
+        Err(err) => eprintln!("Failed to read {}: {}", dockerignore.display(), err),
+    }
+}
+
+/// Validate compose files and containerfiles, printing their problems and returning
+/// whether they're all valid. Files of unknown kinds are an Err.
+fn validate_files(filenames: &[String], options: &ValidateOptions, verbose: bool) -> Result<bool> {
+    let mut compose_files = Vec::new();
+    let mut reports: Vec<(String, ValidationReport)> = Vec::new();
+    for filename in filenames {
+        match source_kind(&get_basename(filename)) {
+            Some(SourceKind::Compose) => compose_files.push(filename),
+            Some(SourceKind::Containerfile) => {
+                let mut report = validate_containerfile_reader(Box::new(File::open(filename)?), options)?;
+                report.in_file(filename);
+                reports.push((filename.clone(), report));
+            }
+            None => return Err(anyhow!("{} is neither a compose file nor a containerfile", filename)),
+        }
+    }
+    if !compose_files.is_empty() {
+        let readers = compose_files.iter().map(|file| File::open(file).map(|f| Box::new(f) as Box<dyn Read>)).collect::<io::Result<_>>()?;
+        let names: Vec<&str> = compose_files.iter().map(|file| file.as_str()).collect();
+        let parse = ParseOptions { file_names: names.iter().map(|name| name.to_string()).collect(), ..options.parse.clone() };
+        let options = ValidateOptions { parse, ..options.clone() };
+        reports.insert(0, (names.join(", "), validate_compose_readers(readers, &options)?));
+    }
+    let mut valid = true;
+    for (name, report) in reports {
+        if !report.errors.is_empty() || !report.warnings.is_empty() || !report.notes.is_empty() {
+            eprintln!("{}:", name);
+            if verbose {
+                report.print_located();
+            } else {
+                report.print();
+            }
+        }
+        valid &= report.is_valid();
+    }
+    Ok(valid)
+}
+
+/// Reconcile a compose file with Kubernetes manifests, returning the SysML model and the
+/// summary of the reconciliation. The provenance of `sysml_options`, if any, records both files.
+fn reconcile_files(compose_file: &str, manifests_file: &str, map: &[(String, String)], name: &str, sysml_options: &SysmlOptions) -> Result<(String, String)> {
+    let options = ParseOptions {
+        base_dir: Path::new(compose_file).parent().map(Path::to_path_buf),
+        file_names: vec![compose_file.to_string()],
+        ..Default::default()
+    };
+    let compose = File::open(compose_file).map_err(|err| anyhow!("{}: {}", compose_file, err))?;
+    let compose = parse_composefiles_unvalidated(vec![Box::new(compose)], &options)?;
+    let manifests = parse_manifests(Box::new(File::open(manifests_file).map_err(|err| anyhow!("{}: {}", manifests_file, err))?))?;
+    let reconciliation = ArchModel::from_compose(&compose).reconcile(&ArchModel::from_manifests(&manifests), map)?;
+    let mut sysml_options = sysml_options.clone();
+    if let Some(provenance) = &sysml_options.provenance {
+        let inputs = [compose_file, manifests_file].iter().map(|file| InputHash::of_file(Path::new(file))).collect::<std::io::Result<_>>()?;
+        sysml_options.provenance = Some(provenance.with_inputs(inputs));
+    }
+    Ok((gen_sysml::sysml_reconciliation(&reconciliation, name, &sysml_options), reconciliation_summary(&reconciliation)))
+}
+
+/// A compose file written canonically. `extends.file` paths are relative to `filename`.
+fn format_compose(content: &str, filename: Option<&str>) -> Result<String> {
+    let options = ParseOptions {
+        base_dir: filename.and_then(|file| Path::new(file).parent()).map(Path::to_path_buf),
+        ..Default::default()
+    };
+    parse_composefiles_unvalidated(vec![Box::new(Cursor::new(content.to_string()))], &options)?.to_yaml_canonical()
+}
+
+/// Format compose files, printing them, rewriting those which change or, when checking,
+/// listing them. Returns whether every file was already formatted.
+fn format_compose_files(filenames: &[String], write: bool, check: bool) -> Result<bool> {
+    let inputs: Vec<Option<&str>> = match filenames.is_empty() {
+        true => vec![None],
+        false => filenames.iter().map(|file| Some(file.as_str())).collect(),
+    };
+    let mut formatted = true;
+    for filename in inputs {
+        let content = match filename {
+            Some(file) => fs::read_to_string(file).map_err(|err| anyhow!("{}: {}", file, err))?,
+            None => io::read_to_string(io::stdin())?,
+        };
+        let canonical = format_compose(&content, filename).map_err(|err| anyhow!("{}: {}", filename.unwrap_or("<stdin>"), err))?;
+        let unchanged = canonical == content;
+        formatted &= unchanged;
+        match (filename, write, check) {
+            (_, _, true) if !unchanged => eprintln!("{} isn't formatted", filename.unwrap_or("<stdin>")),
+            (_, _, true) => (),
+            (Some(filename), true, _) if !unchanged => fs::write(filename, canonical)?,
+            (_, true, _) => (),
+            _ => print!("{}", canonical),
+        }
+    }
+    Ok(formatted)
+}
+
 fn main() {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let self_check = cli.self_check || cfg!(debug_assertions);
 
     match &cli.command {
-        Some(Commands::Containerfile{ filename }) => {
-            let reader = create_reader(filename.as_deref());
+        Some(Commands::Containerfile{ filename, format, include_metadata, ports_sorted, requirements, include_filesystem, include_build_actions, include_metrics, max_attrs_per_group, build_args, check_dockerignore, lint, allow, lenient, emit, definitions_package, style, provenance }) => {
+            let mut options = SysmlOptions {
+                include_metadata: *include_metadata,
+                ports_sorted: *ports_sorted,
+                emit: *emit,
+                definitions_package: definitions_package.clone(),
+                max_attrs_per_group: *max_attrs_per_group,
+                include_filesystem: *include_filesystem,
+                include_build_actions: *include_build_actions,
+                include_metrics: *include_metrics,
+                style: style.style(),
+                ..Default::default()
+            };
+            let mut inputs = Vec::new();
+            let mut reader = hashed_reader(filename.as_deref(), &mut inputs);
+            options.provenance = provenance.provenance(&options).map(|provenance| provenance.with_inputs(inputs));
+            if *lint {
+                let mut content = String::new();
+                reader.read_to_string(&mut content).expect("Unable to read the input");
+                if let Ok(findings) = lint::lint_containerfile(&content, allow) {
+                    findings.iter().for_each(|finding| eprintln!("{}", finding));
+                }
+                reader = Box::new(Cursor::new(content));
+            }
             let basename = get_basename(filename.as_deref().unwrap_or("Unknown"));
-            let block=parse_containerfile(reader, &basename);
+            if matches!(format, Format::StartOrder | Format::StartOrderJson | Format::Security | Format::SecurityReport | Format::SecurityReportJson | Format::EnvReport | Format::EnvReportJson) {
+                println!("--format {} only applies to compose files", format);
+                std::process::exit(2);
+            }
+            let build_args = build_args.iter().cloned().collect();
+            let block = if *lenient {
+                parse_containerfile_lenient(reader, &basename, &build_args)
+            } else {
+                parse_containerfile_with_args(reader, &basename, &build_args).map_err(anyhow::Error::from)
+            };
+            if let Ok(ParsedContainer { lenient_parse: Some(diagnostic), raw_lines, .. }) = &block {
+                eprintln!("warning: {}", diagnostic);
+                for line in raw_lines {
+                    eprintln!("warning: line {} was not recognised and is kept as raw text", line);
+                }
+            }
+            if let (true, Some(filename), Ok(container)) = (*check_dockerignore, filename, &block) {
+                warn_dockerignored_copies(filename, container);
+            }
             match block {
-                Ok(_)=> println!("Parse successful"),
+                Ok(container) if *format == Format::Markdown => print!("{}", container_to_markdown(&container)),
+                Ok(container) if *format == Format::Json => println!("{}", serde_json::to_string_pretty(&container).unwrap()),
+                Ok(container) if *format == Format::Csv => print!("{}\n{}", CSV_HEADER, container.to_csv_row()),
+                Ok(container)=> {
+                    print_sysml(&gen_sysml::sysml_cargotecture_package(&container, &options), self_check);
+                    if *requirements {
+                        print_sysml(&gen_sysml::sysml_requirement_definitions(&container, &options), self_check);
+                    }
+                }
                 Err(err)=> println!("Parse failed: {}", err),
             };
         }
-        Some(Commands::Compose{ filename }) => {
-            let reader = create_reader(filename.as_deref());
-            let block=parse_composefile(reader);
-            match block{
-                Ok(block) => println!("Parse successful"),
-                Err(err) => println!("Parse failed: {}", err),
+        Some(Commands::Compose{ filenames, use_env, format, infer_flows, include_metadata, group_by, ports_sorted, per_service_packages, include_environment, max_attrs_per_group, env_as_table, emit, definitions_package, max_extends_depth, follow_builds, include_filesystem, shared_base_images, normalize_refs, check_images, image_timeout, watch, poll_interval, check, verbose, json, promote_extensions, wide, select_labels, profiles, no_auto_activate, anonymize, anonymize_map, anonymize_seed, style, provenance }) => {
+            if *format == Format::Json {
+                println!("--format {} only applies to containerfiles", format);
+                std::process::exit(2);
+            }
+            let cwd = std::env::current_dir().unwrap_or_default();
+            let (files, source) = resolve_compose_files(filenames, *use_env, io::stdin().is_terminal(), &cwd);
+            if source == ComposeFileSource::Discovered {
+                eprintln!("Using compose files: {}", files.join(", "));
+            }
+            let mut inputs = Vec::new();
+            let readers = if files.is_empty() {
+                vec![hashed_reader(None, &mut inputs)]
+            } else {
+                files.iter().map(|file| hashed_reader(Some(file), &mut inputs)).collect()
+            };
+            let project_name = std::env::var("COMPOSE_PROJECT_NAME").ok().filter(|_| *use_env);
+            let name = project_name.unwrap_or_else(|| get_stem(files.first().map_or("Compose", String::as_str)));
+            let mut options = SysmlOptions {
+                infer_flows: *infer_flows,
+                include_metadata: *include_metadata,
+                group_by: *group_by,
+                ports_sorted: *ports_sorted,
+                per_service_packages: *per_service_packages,
+                emit: *emit,
+                definitions_package: definitions_package.clone(),
+                provenance: None,
+                include_environment: *include_environment,
+                max_attrs_per_group: *max_attrs_per_group,
+                env_as_table: *env_as_table,
+                include_filesystem: *include_filesystem,
+                include_build_actions: false,
+                shared_base_images: *shared_base_images,
+                raw_image_refs: !normalize_refs.unwrap_or(true),
+                include_metrics: false,
+                promote_extensions: promote_extensions.clone(),
+                sysml_spec_version: None,
+                style: style.style(),
+            };
+            let provenance = provenance.provenance(&options).map(|mut provenance| {
+                provenance.options.push(("max_extends_depth".to_string(), max_extends_depth.to_string()));
+                provenance
+            });
+            // extends.file paths are relative to the first compose file, as with docker compose
+            let parse_options = ParseOptions {
+                base_dir: files.first().and_then(|file| Path::new(file).parent()).map(Path::to_path_buf),
+                max_extends_depth: *max_extends_depth,
+                lenient: false,
+                json: *json,
+                read_files: true,
+                file_names: files.clone(),
+            };
+            if *watch {
+                if files.is_empty() {
+                    println!("--watch needs compose files to watch, not stdin");
+                    std::process::exit(2);
+                }
+                watch_compose(&files, parse_options, &name, options, provenance, *poll_interval, self_check);
+            }
+            let mut containers = HashMap::new();
+            let mut block=parse_composefiles_unvalidated(readers, &parse_options).and_then(|mut compose| {
+                // Following builds, the file is validated once the containers are loaded, so
+                // the ports they EXPOSE can be checked against those published
+                if !*follow_builds {
+                    compose.validate().print();
+                }
+                if !profiles.is_empty() {
+                    compose.retain_active_services(profiles, !*no_auto_activate)?.report().print();
+                }
+                if let Some(selector) = select_labels {
+                    compose.retain_services_matching(selector);
+                }
+                if *follow_builds {
+                    containers = load_build_containers(&compose, parse_options.base_dir.as_deref())?;
+                    let base_dir = parse_options.base_dir.as_deref();
+                    let mut dockerfiles: Vec<String> = compose.services.values().filter_map(|service| {
+                        service.build.as_ref().map(|build| build.dockerfile_path(base_dir).to_string_lossy().into_owned())
+                    }).collect();
+                    dockerfiles.sort();
+                    dockerfiles.dedup();
+                    compose.included_files.extend(dockerfiles);
+                    compose.validate_with_containers(&containers).print();
+                }
+                if *verbose {
+                    for warning in compose.parse_warnings() {
+                        eprintln!("warning: {}", warning);
+                    }
+                }
+                if *check_images {
+                    for warning in compose.validate_image_availability(std::time::Duration::from_millis(*image_timeout)) {
+                        eprintln!("warning: {}", warning);
+                    }
+                }
+                Ok(compose)
+            });
+            let name = match &mut block {
+                Ok(compose) if *anonymize => {
+                    let map = compose.anonymize(anonymize_seed.as_deref().unwrap_or_default());
+                    if let Some(path) = anonymize_map {
+                        if let Err(err) = std::fs::write(path, serde_json::to_string_pretty(&map).unwrap()) {
+                            println!("Write failed: {}: {}", path.display(), err);
+                            std::process::exit(1);
+                        }
+                    }
+                    "anonymized".to_string()
+                }
+                _ => name,
+            };
+            if let (Ok(compose), false) = (&block, *anonymize) {
+                options.provenance = provenance.map(|provenance| compose_provenance(&provenance, inputs, compose));
+            }
+            match (block, check) {
+                (Ok(compose), Some(check)) => {
+                    if !check_compose(&compose, &containers, &name, &options, check, normalize_refs.unwrap_or(false)) {
+                        std::process::exit(1);
+                    }
+                }
+                (Ok(compose), None) if *format == Format::Markdown => print!("{}", compose_to_markdown(&compose)),
+                (Ok(compose), None) if *format == Format::Csv => print!("{}", compose_to_csv_with_extensions(&compose, promote_extensions)),
+                (Ok(compose), None) if *format == Format::Security => print!("{}", compose.security_report_with_containers(&containers)),
+                (Ok(compose), None) if *format == Format::SecurityReport => {
+                    let max_width = if *wide { None } else { output::terminal_width() };
+                    print!("{}", compose.security_posture(&containers).to_table().max_width(max_width).render());
+                }
+                (Ok(compose), None) if *format == Format::SecurityReportJson => {
+                    println!("{}", serde_json::to_string_pretty(&compose.security_posture(&containers)).unwrap());
+                }
+                (Ok(compose), None) if matches!(format, Format::EnvReport | Format::EnvReportJson) => {
+                    match compose.environment_report(parse_options.base_dir.as_deref()) {
+                        Ok(report) if *format == Format::EnvReportJson => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+                        Ok(report) => print!("{}", report),
+                        Err(err) => {
+                            println!("Environment report failed: {:#}", err);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                (Ok(compose), None) if matches!(format, Format::StartOrder | Format::StartOrderJson) => {
+                    match compose.start_waves() {
+                        Ok(waves) if *format == Format::StartOrderJson => {
+                            println!("{}", serde_json::json!({ "waves": waves }));
+                        }
+                        Ok(waves) => waves.iter().for_each(|wave| print!("{}", wave)),
+                        Err(err) => {
+                            println!("Start order failed: {}", err);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                (Ok(compose), None) => print_sysml(&gen_sysml::sysml_compose_package_with_containers(&compose, &name, &containers, &options), self_check),
+                (Err(err), _) => println!("Parse failed: {}", err),
+            };
+        }
+        Some(Commands::Scan{ dir, split_output, size_warning, include_metadata, cache_dir, no_cache, style, provenance }) => {
+            let mut options = SysmlOptions { include_metadata: *include_metadata, style: style.style(), ..Default::default() };
+            options.provenance = provenance.provenance(&options);
+            let sources = match scan_directory(dir) {
+                Ok(sources) => sources,
+                Err(err) => {
+                    println!("Scan failed: {}", err);
+                    return;
+                }
             };
+            let mut cache = cache_dir.as_deref().map(|cache_dir| ScanCache::new(cache_dir, *no_cache));
+            let packages = generate_packages_cached(dir, &sources, &options, cache.as_mut());
+            if let Some(cache) = cache {
+                eprintln!("cache: {} unchanged, {} parsed", cache.hits, cache.misses);
+            }
+            match split_output {
+                Some(output) => {
+                    let root = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+                    let index_name = get_basename(&root.to_string_lossy());
+                    for package in &packages {
+                        self_check_sysml(&package.sysml, Some(&package.source), self_check);
+                    }
+                    match write_split_output(output, &index_name, &packages, &options.style) {
+                        Ok(written) => {
+                            // The packages were checked before they were written, leaving the index
+                            if let Some(index) = written.last() {
+                                self_check_sysml(&std::fs::read_to_string(index).unwrap_or_default(), Some(index), self_check);
+                            }
+                            for path in written {
+                                let size = std::fs::metadata(&path).map_or(0, |m| m.len() as usize);
+                                warn_if_oversized(&path, size, *size_warning);
+                            }
+                        }
+                        Err(err) => println!("Write failed: {}", err),
+                    }
+                }
+                None => {
+                    let sysml: String = packages.iter().map(|p| p.sysml.as_str()).collect();
+                    warn_if_oversized(Path::new("<stdout>"), sysml.len(), *size_warning);
+                    print_sysml(&sysml, self_check);
+                }
+            }
         }
-        Some(Commands::Pod{ filename }) => {
-            let reader = create_reader(filename.as_deref());
-            let block=parse_podfile(reader);
+        Some(Commands::Cache{ action: CacheAction::Clear{ cache_dir } }) => {
+            match ScanCache::clear(cache_dir) {
+                Ok(removed) => eprintln!("Removed {} cached results from {}", removed, cache_dir.display()),
+                Err(err) => {
+                    println!("Clearing the cache failed: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Validate{ filenames, strict, lenient, lint, profiles, verbose, .. }) => {
+            let options = ValidateOptions {
+                parse: ParseOptions {
+                    // extends.file paths are relative to the first compose file
+                    base_dir: filenames
+                        .iter()
+                        .find(|file| source_kind(&get_basename(file)) == Some(SourceKind::Compose))
+                        .and_then(|file| Path::new(file).parent())
+                        .map(Path::to_path_buf),
+                    lenient: *lenient,
+                    ..Default::default()
+                },
+                profiles: profiles.clone(),
+                strict: *strict,
+                lint: *lint,
+                levels: code_levels(matches.subcommand_matches("validate")),
+            };
+            if *verbose {
+                for (code, severity) in options.effective_levels() {
+                    eprintln!("{}: {}", code, severity.map_or_else(|| Level::Allow.to_string(), |severity| severity.to_string()));
+                }
+            }
+            match validate_files(filenames, &options, *verbose) {
+                Ok(true) => (),
+                Ok(false) => std::process::exit(1),
+                Err(err) => {
+                    eprintln!("Validation failed: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(Commands::Explain{ code, list }) => {
+            if *list {
+                print!("{}", list_explanations());
+            } else if let Some(code) = code {
+                match explain(code) {
+                    Ok(info) => print!("{}", info),
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        std::process::exit(2);
+                    }
+                }
+            }
+        }
+        Some(Commands::Fmt{ filenames, write, check }) => {
+            match format_compose_files(filenames, *write, *check) {
+                Ok(false) if *check => std::process::exit(1),
+                Ok(_) => (),
+                Err(err) => {
+                    eprintln!("Formatting failed: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(Commands::Query{ query: QueryAction::Labels{ selector, filenames, pod } }) => {
+            match query_labels(selector, filenames, *pod) {
+                Ok(names) => names.iter().for_each(|name| println!("{}", name)),
+                Err(err) => {
+                    println!("Parse failed: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(Commands::Pod{ filename, select_labels, style, provenance }) => {
+            let mut options = SysmlOptions { style: style.style(), ..Default::default() };
+            let mut inputs = Vec::new();
+            let reader = hashed_reader(filename.as_deref(), &mut inputs);
+            options.provenance = provenance.provenance(&options).map(|provenance| provenance.with_inputs(inputs));
+            let block=parse_manifests(reader);
             match block{
-                Ok(()) => println!("Parse successful"),
+                Ok(mut manifests) => {
+                    if let Some(selector) = select_labels {
+                        for pod in manifests.pods.iter().filter(|pod| !selector.matches(&pod.labels)) {
+                            eprintln!("note: {} doesn't match --select-labels, so nothing is modelled", pod.description());
+                        }
+                        manifests.pods.retain(|pod| selector.matches(&pod.labels));
+                    }
+                    if !manifests.is_empty() {
+                        manifests.validate().print();
+                        let name = filename.as_deref().map_or_else(|| "manifests".to_string(), get_stem);
+                        print_sysml(&gen_sysml::sysml_kubernetes(&manifests, &name, &options), self_check);
+                    }
+                }
                 Err(err) => println!("Parse failed: {}", err),
             };
         }
+        Some(Commands::Reconcile{ compose, manifests, map, name, style, provenance }) => {
+            let name = name.clone().unwrap_or_else(|| get_stem(compose));
+            let mut options = SysmlOptions { style: style.style(), ..Default::default() };
+            options.provenance = provenance.provenance(&options);
+            match reconcile_files(compose, manifests, map, &name, &options) {
+                Ok((sysml, summary)) => {
+                    eprint!("{}", summary);
+                    print_sysml(&sysml, self_check);
+                }
+                Err(err) => {
+                    eprintln!("Reconcile failed: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
         None => {
             println!("Default subcommand");
         }
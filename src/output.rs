@@ -0,0 +1,191 @@
+//! Aligned text tables for terminals, with columns sized to their content and cells
+//! truncated to fit a maximum width
+
+/// What a truncated cell ends with
+const ELLIPSIS: char = '…';
+
+/// The narrowest a column is truncated to, unless its content is narrower
+const MIN_COLUMN_WIDTH: usize = 4;
+
+/// The space between columns
+const SEPARATOR: &str = "  ";
+
+/// The number of terminal columns a character takes: two for wide characters such as CJK
+/// ideographs and most emoji, none for combining marks and other zero-width characters,
+/// and one for the rest
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x0300..=0x036F | 0x200B..=0x200F | 0x20D0..=0x20FF | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// The number of terminal columns a string takes
+pub fn display_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+/// A string cut to at most `width` terminal columns, ending with an ellipsis when it's cut.
+/// Characters are never split.
+pub fn truncate(s: &str, width: usize) -> String {
+    if display_width(s) <= width {
+        return s.to_string();
+    }
+    let mut out = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        if used + char_width(c) + 1 > width {
+            break;
+        }
+        used += char_width(c);
+        out.push(c);
+    }
+    if width > 0 {
+        out.push(ELLIPSIS);
+    }
+    out
+}
+
+/// The width of the terminal standard output is written to, as the terminal reports it or
+/// else from `COLUMNS`, or None when it isn't a terminal or the width isn't known
+pub fn terminal_width() -> Option<usize> {
+    use std::io::IsTerminal;
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    match terminal_size::terminal_size_of(std::io::stdout()) {
+        Some((terminal_size::Width(width), _)) if width > 0 => Some(usize::from(width)),
+        _ => std::env::var("COLUMNS").ok()?.parse().ok().filter(|width| *width > 0),
+    }
+}
+
+/// A table with a header row, rendered with its columns lined up
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// The widest a rendered line may be, None for no limit
+    pub max_width: Option<usize>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Table { headers: headers.iter().map(|header| header.to_string()).collect(), ..Default::default() }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn max_width(mut self, max_width: Option<usize>) -> Self {
+        self.max_width = max_width;
+        self
+    }
+
+    /// The width of each column: as wide as its widest cell, less what's needed to fit the
+    /// maximum width, taken from the widest columns first
+    pub fn column_widths(&self) -> Vec<usize> {
+        let mut widths: Vec<usize> = (0..self.headers.len())
+            .map(|column| {
+                let cells = std::iter::once(&self.headers[column]).chain(self.rows.iter().filter_map(|row| row.get(column)));
+                cells.map(|cell| display_width(cell)).max().unwrap_or(0)
+            })
+            .collect();
+        let Some(max_width) = self.max_width else {
+            return widths;
+        };
+        let separators = SEPARATOR.len() * widths.len().saturating_sub(1);
+        while widths.iter().sum::<usize>() + separators > max_width {
+            let widest = (0..widths.len()).max_by_key(|&column| (widths[column], std::cmp::Reverse(column)));
+            match widest {
+                Some(column) if widths[column] > MIN_COLUMN_WIDTH => widths[column] -= 1,
+                _ => break,
+            }
+        }
+        widths
+    }
+
+    /// The table as text, a line per row after the header, without trailing spaces
+    pub fn render(&self) -> String {
+        let widths = self.column_widths();
+        let mut out = String::new();
+        for row in std::iter::once(&self.headers).chain(&self.rows) {
+            let cells: Vec<String> = widths
+                .iter()
+                .enumerate()
+                .map(|(column, width)| {
+                    let cell = truncate(row.get(column).map_or("", String::as_str), *width);
+                    let padding = width - display_width(&cell);
+                    format!("{}{}", cell, " ".repeat(padding))
+                })
+                .collect();
+            out.push_str(cells.join(SEPARATOR).trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("nginx"), 5);
+        // Accented letters are one column, however many bytes they take
+        assert_eq!(display_width("café"), 4);
+        assert_eq!("café".len(), 5);
+        assert_eq!(display_width("cafe\u{301}"), 4);
+        assert_eq!(display_width("数据库"), 6);
+        assert_eq!(display_width("🐳 whale"), 8);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("elasticsearch", 20), "elasticsearch");
+        assert_eq!(truncate("elasticsearch", 13), "elasticsearch");
+        assert_eq!(truncate("elasticsearch", 8), "elastic…");
+        assert_eq!(truncate("résumé-service", 7), "résumé…");
+        // Wide characters are never split, so may leave the cell a column short
+        assert_eq!(truncate("数据库服务", 6), "数据…");
+        assert_eq!(display_width(&truncate("数据库服务", 6)), 5);
+        assert_eq!(truncate("abc", 1), "…");
+        assert_eq!(truncate("abc", 0), "");
+    }
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(&["SERVICE", "IMAGE"]);
+        table.push(vec!["数据库".to_string(), "postgres:16".to_string()]);
+        table.push(vec!["web".to_string(), "registry.example.com/team/web:1.2.3".to_string()]);
+        assert_eq!(table.column_widths(), vec![7, 35]);
+        assert_eq!(
+            table.render(),
+            "SERVICE  IMAGE\n数据库   postgres:16\nweb      registry.example.com/team/web:1.2.3\n"
+        );
+
+        // The widest column gives way first
+        let narrow = table.clone().max_width(Some(30));
+        assert_eq!(narrow.column_widths(), vec![7, 21]);
+        assert_eq!(narrow.render(), "SERVICE  IMAGE\n数据库   postgres:16\nweb      registry.example.com…\n");
+        assert!(narrow.render().lines().all(|line| display_width(line) <= 30));
+
+        // Columns aren't truncated below a minimum, even if the table overflows
+        assert_eq!(table.max_width(Some(5)).column_widths(), vec![4, 4]);
+    }
+}
@@ -3,121 +3,1054 @@ use serde::{
     Deserialize, Serialize,
 };
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    env,
+    fs,
     io::{BufReader,Read},
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     fmt,
+    time::Duration,
+};
+use anyhow::{anyhow, Result};
+use crate::{
+    error::CargotectureError,
+    parse_dockerfile::{parse_containerfile_for_target, CommandLine, ExposedPort, ParsedContainer, Protocol, VolumeMount},
+    util::{cidrs_overlap, default_port_for_scheme, detect_format, did_you_mean, format_file_mode, format_number, is_absolute_container_path, is_private_ipv4, looks_like_file, overlapping_mounts, parse_bool, parse_bytes, parse_cpus, parse_duration, parse_endpoint, parse_file_mode, parse_port_mapping, parse_signal, parse_volume_spec, redact_url_credentials, suggestions, validate_dns_label, CidrV4, DocumentFormat, MountOverlap, PortBinding, VolumeSource, VolumeSpec},
+    registry::{image_key, manifest_status, ImageReference},
+    selector::LabelSelector,
+    validation::{SourceLocation, ValidationError, ValidationReport, ValidationWarning},
 };
-use anyhow::Result;
 
+/// Accept DNS servers as a single address or a list, each with an optional port
+/// defaulting to 53
 fn deserialize_socket_addrs<'de, D>(deserializer: D) -> Result<Option<Vec<SocketAddr>>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    struct SocketAddrVisitor;
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
 
-    impl<'de> serde::de::Visitor<'de> for SocketAddrVisitor {
-        type Value = Vec<SocketAddr>;
+    let addresses = match Option::<OneOrMany>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(OneOrMany::One(address)) => vec![address],
+        Some(OneOrMany::Many(addresses)) => addresses,
+    };
+    addresses
+        .iter()
+        .map(|address| {
+            address
+                .parse::<SocketAddr>()
+                .or_else(|_| address.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .map_err(|_| de::Error::custom(format!("invalid DNS server address '{}'", address)))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
 
-        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("a list of socket addresses")
-        }
+/// Accept a single string or a list of them
+fn deserialize_string_or_list<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
 
-        fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
-        where
-            S: serde::de::SeqAccess<'de>,
-        {
-            let mut addresses = vec![];
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        None => None,
+        Some(OneOrMany::One(value)) => Some(vec![value]),
+        Some(OneOrMany::Many(values)) => Some(values),
+    })
+}
+
+/// A YAML number in plain decimal, as `format_number` writes numbers, rather than in the
+/// scientific notation YAML writes large and small floats in
+pub(crate) fn number_string(number: &serde_yaml::Number) -> String {
+    match (number.as_u64(), number.as_i64(), number.as_f64()) {
+        (Some(n), _, _) => n.to_string(),
+        (_, Some(n), _) => n.to_string(),
+        (_, _, Some(n)) => format_number(n),
+        _ => number.to_string(),
+    }
+}
+
+/// A scalar of a long-syntax entry as a string, for numbers written either way
+fn scalar_string(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        serde_yaml::Value::Number(n) => Some(number_string(n)),
+        _ => None,
+    }
+}
+
+/// A YAML boolean, including the `yes`, `on` and similar strings of YAML 1.1, which YAML
+/// 1.2 reads as strings
+pub(crate) fn yaml_bool(value: &serde_yaml::Value) -> Option<bool> {
+    match value {
+        serde_yaml::Value::Bool(b) => Some(*b),
+        serde_yaml::Value::String(s) => parse_bool(s),
+        _ => None,
+    }
+}
+
+/// Accept a boolean written as `true` or in the YAML 1.1 forms, such as `yes` or `off`
+fn deserialize_bool<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(value) => yaml_bool(&value).map(Some).ok_or_else(|| de::Error::custom(format!("expected a boolean, found {:?}", value))),
+    }
+}
+
+/// Accept a restart policy, taking a boolean as the policy meant by it: `false`, which is
+/// what YAML 1.1 makes of an unquoted `no`, as `no` and `true` as `always`. Validation warns
+/// about the quoting.
+fn deserialize_restart<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(serde_yaml::Value::Bool(restart)) => Ok(Some(if restart { "always" } else { "no" }.to_string())),
+        Some(value) => scalar_string(&value).map(Some).ok_or_else(|| de::Error::custom(format!("expected a restart policy, found {:?}", value))),
+    }
+}
+
+/// Accept a byte value written as a number of bytes or with a unit, such as `64m`
+fn deserialize_bytes<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<serde_yaml::Value>::deserialize(deserializer)?;
+    match value.as_ref().map(|value| (value, scalar_string(value))) {
+        None => Ok(None),
+        Some((_, Some(text))) if parse_bytes(&text).is_some() => Ok(parse_bytes(&text)),
+        Some((value, _)) => Err(de::Error::custom(format!("invalid byte value {:?}", value))),
+    }
+}
+
+/// Accept a file mode as octal digits, such as `0440` or `"0440"`, or as the number `0o440`
+/// means
+fn deserialize_file_mode<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(serde_yaml::Value::Number(n)) => n.as_u64().and_then(|mode| u32::try_from(mode).ok()).filter(|mode| *mode <= 0o7777).map(Some).ok_or_else(|| de::Error::custom(format!("invalid file mode {}", n))),
+        Some(serde_yaml::Value::String(s)) => parse_file_mode(&s).map(Some).ok_or_else(|| de::Error::custom(format!("invalid file mode '{}'", s))),
+        Some(value) => Err(de::Error::custom(format!("invalid file mode {:?}", value))),
+    }
+}
+
+/// Write a file mode as a string of four octal digits, such as `"0440"`, so that it reads
+/// back as the same mode wherever it's written
+fn serialize_file_mode<S: serde::Serializer>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+    mode.map(format_file_mode).serialize(serializer)
+}
+
+/// Accept an integer, written as a number or as a string of digits
+fn deserialize_integer<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(value) => scalar_string(&value)
+            .and_then(|text| text.parse().ok())
+            .map(Some)
+            .ok_or_else(|| de::Error::custom(format!("expected an integer, found {:?}", value))),
+    }
+}
+
+/// Accept a list of scalars as strings, for names and IDs written either way
+fn deserialize_scalar_list<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<Vec<serde_yaml::Value>>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(values) => values
+            .iter()
+            .map(|value| scalar_string(value).ok_or_else(|| de::Error::custom(format!("expected a scalar, found {:?}", value))))
+            .collect::<Result<_, _>>()
+            .map(Some),
+    }
+}
+
+/// Accept a scalar as a string, for fields such as user IDs written either as numbers or
+/// strings
+fn deserialize_scalar_string<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(value) => scalar_string(&value).map(Some).ok_or_else(|| de::Error::custom(format!("expected a scalar, found {:?}", value))),
+    }
+}
+
+/// Write a long-syntax port, such as `{target: 80, published: 8080}`, in the short syntax
+/// the rest of the model uses. TCP is left implicit, as it's the default.
+fn long_port_to_short(long: &serde_yaml::Mapping) -> Result<String, String> {
+    let field = |name: &str| long.get(name).and_then(scalar_string);
+    let target = field("target").ok_or("a long-syntax port needs a target")?;
+    let mut short = match (field("host_ip"), field("published")) {
+        (Some(ip), published) if ip.contains(':') => format!("[{}]:{}:", ip, published.unwrap_or_default()),
+        (Some(ip), published) => format!("{}:{}:", ip, published.unwrap_or_default()),
+        (None, Some(published)) => format!("{}:", published),
+        (None, None) => String::new(),
+    };
+    short.push_str(&target);
+    match field("protocol") {
+        Some(protocol) if !protocol.eq_ignore_ascii_case("tcp") => short.push_str(&format!("/{}", protocol)),
+        _ => (),
+    }
+    Ok(short)
+}
 
-            while let Some(addr_str) = seq.next_element::<String>()? {
-                let addr: SocketAddr = addr_str.parse().map_err(serde::de::Error::custom)?;
-                addresses.push(addr);
+/// The `name`s of the long-syntax ports of each service of a compose document, by service
+/// and then by the container port and protocol they name
+fn long_port_names(document: &serde_yaml::Value) -> HashMap<String, HashMap<(u16, Protocol), String>> {
+    let mut names: HashMap<String, HashMap<(u16, Protocol), String>> = HashMap::new();
+    for (service, definition) in document.get("services").and_then(serde_yaml::Value::as_mapping).into_iter().flatten() {
+        let (Some(service), Some(ports)) = (service.as_str(), definition.get("ports").and_then(serde_yaml::Value::as_sequence)) else {
+            continue;
+        };
+        for long in ports.iter().filter_map(serde_yaml::Value::as_mapping) {
+            let name = long.get("name").and_then(serde_yaml::Value::as_str);
+            let binding = long_port_to_short(long).ok().and_then(|short| parse_port_mapping(&short).ok());
+            if let (Some(name), Some(binding)) = (name, binding) {
+                names.entry(service.to_string()).or_default().insert((binding.container_port, binding.protocol), name.to_string());
             }
+        }
+    }
+    names
+}
 
-            Ok(addresses)
+/// Write a long-syntax volume, such as `{type: bind, source: ./data, target: /data}`, in the
+/// short syntax the rest of the model uses. Types other than bind mounts and volumes have
+/// no short syntax, so aren't supported.
+fn long_volume_to_short(long: &serde_yaml::Mapping) -> Result<String, String> {
+    let field = |name: &str| long.get(name).and_then(scalar_string);
+    let nested = |section: &str, name: &str| long.get(section).and_then(|section| section.get(name)).cloned();
+    let volume_type = field("type").ok_or("a long-syntax volume needs a type")?;
+    if volume_type != "bind" && volume_type != "volume" {
+        return Err(format!("long-syntax volumes of type '{}' aren't supported", volume_type));
+    }
+    let target = field("target").ok_or("a long-syntax volume needs a target")?;
+    let mut short = match field("source") {
+        Some(source) => format!("{}:{}", source, target),
+        None => target,
+    };
+    let mut options = Vec::new();
+    if long.get("read_only").and_then(yaml_bool) == Some(true) {
+        options.push("ro".to_string());
+    }
+    for (section, name) in [("bind", "propagation"), ("bind", "selinux")] {
+        if let Some(option) = nested(section, name).as_ref().and_then(scalar_string) {
+            options.push(option);
         }
     }
+    if nested("volume", "nocopy").as_ref().and_then(yaml_bool) == Some(true) {
+        options.push("nocopy".to_string());
+    }
+    options.extend(field("consistency"));
+    if !options.is_empty() {
+        short.push_str(&format!(":{}", options.join(",")));
+    }
+    Ok(short)
+}
 
-    let addresses = deserializer.deserialize_option(SocketAddrVisitor)?;
-    Ok(Some(addresses))
+/// Accept volumes in the short syntax, or the long syntax of bind mounts and volumes
+fn deserialize_volumes<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(entries) = Option::<Vec<serde_yaml::Value>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    entries
+        .iter()
+        .map(|entry| match entry {
+            serde_yaml::Value::String(short) => Ok(short.clone()),
+            serde_yaml::Value::Mapping(long) => long_volume_to_short(long).map_err(de::Error::custom),
+            _ => Err(de::Error::custom("expected a volume string or mapping")),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
 }
 
+/// Accept ports as strings, integers or the long syntax, leaving a null list unset
 fn deserialize_ports<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    struct VecStringVisitor;
+    let Some(entries) = Option::<Vec<serde_yaml::Value>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    entries
+        .iter()
+        .map(|entry| match (entry.as_str(), entry.as_u64(), entry.as_mapping()) {
+            (Some(s), _, _) => Ok(s.to_string()),
+            (_, Some(i), _) => Ok(i.to_string()),
+            (_, _, Some(long)) => long_port_to_short(long).map_err(de::Error::custom),
+            _ => Err(de::Error::custom("unexpected value type")),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Accept service networks as a list of names or a map of names to (possibly null) options
+fn deserialize_service_networks<'de, D>(deserializer: D) -> Result<Option<HashMap<String, Option<NetworkRef>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Form {
+        List(Vec<String>),
+        Map(HashMap<String, Option<NetworkRef>>),
+    }
 
-    impl<'de> de::Visitor<'de> for VecStringVisitor {
-        type Value = Vec<String>;
+    let entries: Vec<ServiceNetwork> = match Option::<Form>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(Form::List(names)) => names.into_iter().map(ServiceNetwork::Simple).collect(),
+        Some(Form::Map(map)) => map
+            .into_iter()
+            .map(|(name, options)| match options {
+                Some(options) => ServiceNetwork::Extended(name, options),
+                None => ServiceNetwork::Simple(name),
+            })
+            .collect(),
+    };
+    Ok(Some(
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                ServiceNetwork::Simple(name) => (name, None),
+                ServiceNetwork::Extended(name, options) => (name, Some(options)),
+            })
+            .collect(),
+    ))
+}
 
-        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("a sequence of strings or integers")
-        }
+/// Accept a build as a context path alone or as a mapping
+fn deserialize_build<'de, D>(deserializer: D) -> Result<Option<Build>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Form {
+        Context(String),
+        Full(Build),
+    }
 
-        fn visit_seq<A>(self, mut seq: A) -> Result<Vec<String>, A::Error>
-        where
-            A: de::SeqAccess<'de>,
-        {
-            let mut vec = Vec::new();
+    Ok(match Option::<Form>::deserialize(deserializer)? {
+        None => None,
+        Some(Form::Context(context)) => Some(Build { context: Some(context), ..Default::default() }),
+        Some(Form::Full(build)) => Some(build),
+    })
+}
 
-            while let Some(value) = seq.next_element::<serde_yaml::Value>()? {
-                let as_string = match (value.as_str(), value.as_u64()) {
-                    (Some(s), _) => s.to_string(),
-                    (_, Some(i)) => i.to_string(),
-                    _ => return Err(de::Error::custom("unexpected value type")),
-                };
-                vec.push(as_string);
+/// Accept either the mapping form (`key: value`) or the list form (`- key=value`)
+fn deserialize_key_value_map<'de, D>(deserializer: D) -> Result<Option<HashMap<String, String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = serde_yaml::Value::deserialize(deserializer)?;
+    let scalar_to_string = |v: &serde_yaml::Value| match v {
+        serde_yaml::Value::String(s) => Ok(s.clone()),
+        serde_yaml::Value::Number(n) => Ok(number_string(n)),
+        serde_yaml::Value::Bool(b) => Ok(b.to_string()),
+        serde_yaml::Value::Null => Ok(String::new()),
+        _ => Err(de::Error::custom("expected a scalar value")),
+    };
+    let mut map = HashMap::new();
+    match value {
+        serde_yaml::Value::Null => return Ok(None),
+        serde_yaml::Value::Mapping(mapping) => {
+            for (k, v) in &mapping {
+                map.insert(scalar_to_string(k)?, scalar_to_string(v)?);
+            }
+        }
+        serde_yaml::Value::Sequence(seq) => {
+            for item in &seq {
+                let entry = scalar_to_string(item)?;
+                let (k, v) = entry.split_once('=').unwrap_or((&entry, ""));
+                map.insert(k.to_string(), v.to_string());
             }
+        }
+        _ => return Err(de::Error::custom("expected a mapping or a list of key=value strings")),
+    }
+    Ok(Some(map))
+}
 
-            Ok(vec)
+/// Take the `x-` extension fields out of a mapping, failing on any other field that isn't
+/// in `fields` or `unmodelled`. Unmodelled fields are valid compose but ignored here.
+fn take_extensions<E: de::Error>(
+    mapping: &mut serde_yaml::Mapping,
+    fields: &'static [&'static str],
+    unmodelled: &[&str],
+) -> Result<HashMap<String, serde_yaml::Value>, E> {
+    let mut extensions = HashMap::new();
+    let keys: Vec<serde_yaml::Value> = mapping.keys().cloned().collect();
+    for key in keys {
+        let Some(name) = key.as_str() else {
+            return Err(E::custom(format!("expected a field name, found {:?}", key)));
+        };
+        if name.starts_with("x-") {
+            let value = mapping.remove(&key).unwrap_or_default();
+            extensions.insert(name.to_string(), value);
+        } else if unmodelled.contains(&name) {
+            mapping.remove(&key);
+        } else if !fields.contains(&name) {
+            return Err(match suggestions(name, fields.iter().chain(unmodelled).copied())[..] {
+                [] => E::unknown_field(name, fields),
+                ref similar => E::custom(format!("unknown field `{}`{}", name, did_you_mean(similar))),
+            });
         }
     }
+    Ok(extensions)
+}
+
+/// Implement Deserialize for a type deriving it with `#[serde(remote = "Self")]`, rejecting
+/// unknown fields other than `x-` extensions, which are kept in its `extensions` field
+macro_rules! deserialize_with_extensions {
+    ($type:ty, $fields:expr, $unmodelled:expr) => {
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let mut mapping = serde_yaml::Mapping::deserialize(deserializer)?;
+                let extensions = take_extensions(&mut mapping, $fields, $unmodelled)?;
+                let mut value = <$type>::deserialize(serde_yaml::Value::Mapping(mapping)).map_err(de::Error::custom)?;
+                value.extensions = extensions;
+                Ok(value)
+            }
+        }
 
-    deserializer.deserialize_seq(VecStringVisitor).map(Some)
+        impl Serialize for $type {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                <$type>::serialize(self, serializer)
+            }
+        }
+    };
 }
 
+const COMPOSE_FIELDS: &[&str] = &["version", "services", "networks"];
+const UNMODELLED_COMPOSE_FIELDS: &[&str] = &["name", "include", "volumes", "secrets", "configs"];
+
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Compose {
-    version: Option<String>,
-    services: HashMap<String, Service>,
-    networks: Option<HashMap<String, Network>>,
+    pub version: Option<String>,
+    pub services: HashMap<String, Service>,
+    pub networks: Option<HashMap<String, Network>>,
+    /// Other files read while resolving the document, such as those named by `extends.file`
+    #[serde(skip)]
+    pub included_files: Vec<String>,
+    /// `x-` extension fields, by name
+    #[serde(skip)]
+    pub extensions: HashMap<String, serde_yaml::Value>,
+    /// The paths of unknown fields dropped when parsing leniently, such as `services.web.foo`
+    #[serde(skip)]
+    pub ignored_fields: Vec<String>,
+    /// The document parsed, after merging its files and resolving anchors but before
+    /// resolving `extends`, with every field including those not modelled. Null when the
+    /// model was deserialized directly.
+    #[serde(skip)]
+    pub document: serde_yaml::Value,
+    /// Where each field of the document was written in the files merged
+    #[serde(skip)]
+    pub sources: SourceMap,
+}
+
+deserialize_with_extensions!(Compose, COMPOSE_FIELDS, UNMODELLED_COMPOSE_FIELDS);
+
+/// A summary of the compose file, with a line per service and then per network, sorted by name
+impl fmt::Display for Compose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let networks = self.networks.as_ref().map_or(0, HashMap::len);
+        writeln!(f, "Compose[version={}, services={}, networks={}]", self.version.as_deref().unwrap_or("none"), self.services.len(), networks)?;
+        for (name, service) in self.services() {
+            writeln!(f, "  - {}: {}", name, service)?;
+        }
+        let mut networks: Vec<(&String, &Network)> = self.networks.iter().flatten().collect();
+        networks.sort_by_key(|(name, _)| *name);
+        for (name, network) in networks {
+            writeln!(f, "  network {}: {}", name, network)?;
+        }
+        Ok(())
+    }
 }
 
+const SERVICE_FIELDS: &[&str] = &[
+    "image", "container_name", "command", "restart", "stop_signal", "env_file", "logging", "ports", "expose",
+    "networks", "volumes", "depends_on", "dns", "hostname", "environment", "extra_hosts", "healthcheck", "user",
+    "working_dir", "entrypoint", "labels", "deploy", "scale", "profiles", "gpus", "runtime", "build", "privileged",
+    "network_mode", "pid", "ipc", "cap_add", "tmpfs", "read_only", "pull_policy", "attach", "develop",
+    "platform", "secrets", "shm_size", "mem_limit", "stdin_open", "tty",
+    // Kept in runtime_extras
+    "blkio_config", "cpuset", "cpu_period", "cpu_quota", "mem_swappiness", "oom_score_adj", "userns_mode", "group_add",
+    "isolation",
+];
+/// Compose specification service fields which aren't modelled, so are accepted and ignored
+const UNMODELLED_SERVICE_FIELDS: &[&str] = &[
+    "annotations", "cap_drop", "cgroup", "cgroup_parent", "configs",
+    "cpu_count", "cpu_percent", "cpu_rt_period", "cpu_rt_runtime", "cpu_shares", "cpus",
+    "credential_spec", "device_cgroup_rules", "devices", "dns_opt", "dns_search", "domainname",
+    "external_links", "init", "links", "mac_address",
+    "mem_reservation", "memswap_limit", "oom_kill_disable",
+    "pids_limit", "post_start", "pre_stop",
+    "security_opt", "stop_grace_period", "storage_opt", "sysctls",
+    "ulimits", "uts", "volumes_from",
+];
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Service {
-    image: Option<String>,
-    container_name: Option<String>,
-    command: Option<String>,
-    restart: Option<String>,
-    env_file: Option<String>,
-    logging: Option<Logging>,
+    pub image: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_build")]
+    pub build: Option<Build>,
+    pub container_name: Option<String>,
+    pub command: Option<CommandLine>,
+    #[serde(default, deserialize_with = "deserialize_restart")]
+    pub restart: Option<String>,
+    pub stop_signal: Option<String>,
+    /// Files of environment variables, relative to the compose file, written as one file
+    /// or a list, later files overriding earlier ones
+    #[serde(default, deserialize_with = "deserialize_string_or_list")]
+    pub env_file: Option<Vec<String>>,
+    pub logging: Option<Logging>,
+    #[serde(default, deserialize_with = "deserialize_ports")]
+    pub ports: Option<Vec<String>>,
+    /// Ports exposed to other services without being published on the host
     #[serde(default, deserialize_with = "deserialize_ports")]
-    ports: Option<Vec<String>>,
-    networks: Option<Vec<String>>,
-    volumes: Option<Vec<String>>,
+    pub expose: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_service_networks")]
+    pub networks: Option<HashMap<String, Option<NetworkRef>>>,
+    #[serde(default, deserialize_with = "deserialize_volumes")]
+    pub volumes: Option<Vec<String>>,
+    /// Container paths mounted as tmpfs, written as one path or a list
+    #[serde(default, deserialize_with = "deserialize_string_or_list")]
+    pub tmpfs: Option<Vec<String>>,
     #[serde(rename = "depends_on")]
-    depends_on: Option<DependsOn>,
+    pub depends_on: Option<DependsOn>,
     #[serde(default,deserialize_with = "deserialize_socket_addrs")]
-    dns: Option<Vec<SocketAddr>>,
-    hostname: Option<String>,
-    environment: Option<HashMap<String,String>>,
-    extra_hosts: Option<Vec<String>>,
-    healthcheck: Option<Healthcheck>,
+    pub dns: Option<Vec<SocketAddr>>,
+    pub hostname: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_key_value_map")]
+    pub environment: Option<HashMap<String,String>>,
+    pub extra_hosts: Option<Vec<String>>,
+    pub healthcheck: Option<Healthcheck>,
+    pub user: Option<String>,
+    pub working_dir: Option<String>,
+    pub entrypoint: Option<CommandLine>,
+    #[serde(default, deserialize_with = "deserialize_key_value_map")]
+    pub labels: Option<HashMap<String, String>>,
+    pub deploy: Option<Deploy>,
+    pub scale: Option<u32>,
+    pub profiles: Option<Vec<String>>,
+    pub gpus: Option<Gpus>,
+    /// The OCI runtime, such as `runc`, or `nvidia` for the legacy GPU runtime
+    pub runtime: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub privileged: Option<bool>,
+    /// The network stack to use instead of the service's networks, such as `host` or
+    /// `service:name`
+    pub network_mode: Option<String>,
+    /// The PID namespace, `host` to share the host's
+    pub pid: Option<String>,
+    /// The IPC namespace, `host` to share the host's
+    pub ipc: Option<String>,
+    /// Linux capabilities added to the container's default set
+    pub cap_add: Option<Vec<String>>,
+    /// Mount the container's root filesystem read-only
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub read_only: Option<bool>,
+    /// When to pull the image rather than use a local one
+    pub pull_policy: Option<PullPolicy>,
+    /// Whether `docker compose up` attaches to the service's output
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub attach: Option<bool>,
+    /// Development settings used by `docker compose watch`
+    pub develop: Option<Develop>,
+    /// The platform the container runs on, such as `linux/arm64` or `windows/amd64`
+    pub platform: Option<String>,
+    /// Secrets mounted into the container as files
+    #[serde(default, deserialize_with = "deserialize_secrets")]
+    pub secrets: Option<Vec<ServiceSecret>>,
+    /// The size of `/dev/shm`, in bytes
+    #[serde(default, deserialize_with = "deserialize_bytes")]
+    pub shm_size: Option<u64>,
+    /// The memory limit of the container, in bytes
+    #[serde(default, deserialize_with = "deserialize_bytes")]
+    pub mem_limit: Option<u64>,
+    /// Keep the container's standard input open, as `docker run -i` does
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub stdin_open: Option<bool>,
+    /// Allocate a pseudo-terminal, as `docker run -t` does
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub tty: Option<bool>,
+    /// Runtime tuning fields which are kept but not modelled, None when none are set.
+    /// They're written among the service's fields, as in the compose file.
+    #[serde(flatten, skip_deserializing)]
+    pub runtime_extras: Option<RuntimeExtras>,
+    /// The service this one reuses the definition of through a YAML alias, either as a
+    /// whole (`worker2: *worker1`) or as a merge key (`<<: *worker1`) it overrides
+    #[serde(skip)]
+    pub derived_from: Option<String>,
+    /// The `name`s of long-syntax `ports` entries, by container port and protocol
+    #[serde(skip)]
+    pub port_names: HashMap<(u16, Protocol), String>,
+    /// `x-` extension fields, by name
+    #[serde(skip)]
+    pub extensions: HashMap<String, serde_yaml::Value>,
+}
+
+impl<'de> Deserialize<'de> for Service {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut mapping = serde_yaml::Mapping::deserialize(deserializer)?;
+        let extensions = take_extensions(&mut mapping, SERVICE_FIELDS, UNMODELLED_SERVICE_FIELDS)?;
+        let runtime_extras = RuntimeExtras::take(&mut mapping).map_err(de::Error::custom)?;
+        let mut service = Service::deserialize(serde_yaml::Value::Mapping(mapping)).map_err(de::Error::custom)?;
+        service.extensions = extensions;
+        service.runtime_extras = runtime_extras;
+        Ok(service)
+    }
+}
+
+impl Serialize for Service {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Service::serialize(self, serializer)
+    }
+}
+
+/// The long tail of runtime tuning fields of a service, which are kept for library users
+/// and JSON output but not modelled. Fields whose shape is simple are parsed, and others
+/// kept as written.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeExtras {
+    /// Block IO weights and limits, as written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blkio_config: Option<serde_yaml::Value>,
+    /// The CPUs the container may run on, such as `0-3` or `0,2`
+    #[serde(default, deserialize_with = "deserialize_scalar_string", skip_serializing_if = "Option::is_none")]
+    pub cpuset: Option<String>,
+    /// The CPU CFS period, in microseconds
+    #[serde(default, deserialize_with = "deserialize_integer", skip_serializing_if = "Option::is_none")]
+    pub cpu_period: Option<i64>,
+    /// The CPU CFS quota, in microseconds per period
+    #[serde(default, deserialize_with = "deserialize_integer", skip_serializing_if = "Option::is_none")]
+    pub cpu_quota: Option<i64>,
+    /// How readily the kernel swaps the container's anonymous pages, from 0 to 100
+    #[serde(default, deserialize_with = "deserialize_integer", skip_serializing_if = "Option::is_none")]
+    pub mem_swappiness: Option<i64>,
+    /// How much more or less likely the container is to be killed when memory runs out,
+    /// from -1000 to 1000
+    #[serde(default, deserialize_with = "deserialize_integer", skip_serializing_if = "Option::is_none")]
+    pub oom_score_adj: Option<i64>,
+    /// The user namespace, `host` to share the host's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userns_mode: Option<String>,
+    /// Groups the container's user is added to, by name or ID
+    #[serde(default, deserialize_with = "deserialize_scalar_list", skip_serializing_if = "Option::is_none")]
+    pub group_add: Option<Vec<String>>,
+    /// The isolation technology of Windows containers, such as `process` or `hyperv`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isolation: Option<String>,
+}
+
+impl RuntimeExtras {
+    const FIELDS: &[&str] = &[
+        "blkio_config", "cpuset", "cpu_period", "cpu_quota", "mem_swappiness", "oom_score_adj", "userns_mode", "group_add", "isolation",
+    ];
+
+    /// Take the runtime extras out of the mapping of a service, None when it sets none
+    fn take(mapping: &mut serde_yaml::Mapping) -> Result<Option<RuntimeExtras>, serde_yaml::Error> {
+        let extras: serde_yaml::Mapping = RuntimeExtras::FIELDS.iter().filter_map(|field| Some(((*field).into(), mapping.remove(*field)?))).collect();
+        if extras.is_empty() {
+            return Ok(None);
+        }
+        serde_yaml::from_value(serde_yaml::Value::Mapping(extras)).map(Some)
+    }
+
+    /// The fields set, as a compact JSON object, for summaries such as the CSV inventory
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("runtime extras are always serialisable")
+    }
+}
+
+/// A secret mounted into a service's container, as a file under `/run/secrets` unless
+/// `target` says otherwise
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceSecret {
+    /// The name of the secret among the top-level `secrets`
+    pub source: String,
+    pub target: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_scalar_string")]
+    pub uid: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_scalar_string")]
+    pub gid: Option<String>,
+    /// The permissions of the file, which compose files write in octal
+    #[serde(default, deserialize_with = "deserialize_file_mode", serialize_with = "serialize_file_mode")]
+    pub mode: Option<u32>,
+}
+
+/// Accept secrets by name, or in the long syntax
+fn deserialize_secrets<'de, D>(deserializer: D) -> Result<Option<Vec<ServiceSecret>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(entries) = Option::<Vec<serde_yaml::Value>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    entries
+        .into_iter()
+        .map(|entry| match entry {
+            serde_yaml::Value::String(source) => Ok(ServiceSecret { source, ..Default::default() }),
+            long => serde_yaml::from_value::<ServiceSecret>(long).map_err(de::Error::custom),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// A one-line summary of the service. Lists are separated by commas without spaces, to tell
+/// them from the fields.
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "image={}, ports={}", self.image.as_deref().unwrap_or("none"), self.port_infos().len())?;
+        match &self.depends_on {
+            Some(depends_on) if !depends_on.all_dependencies().is_empty() => write!(f, ", depends_on={}", depends_on)?,
+            _ => write!(f, ", depends_on=none")?,
+        }
+        let networks = self.network_names();
+        if !networks.is_empty() {
+            write!(f, ", networks={}", networks.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// How the image of a service is built. Fields other than these are accepted and ignored.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Build {
+    pub context: Option<String>,
+    pub dockerfile: Option<String>,
+    /// The stage of a multi-stage Dockerfile to build, by default the last
+    pub target: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_key_value_map")]
+    pub args: Option<HashMap<String, String>>,
+}
+
+impl Build {
+    /// The path of the Dockerfile, relative to `base_dir` if given: `dockerfile` within the
+    /// context, which defaults to `.`, defaulting to `Dockerfile`
+    pub fn dockerfile_path(&self, base_dir: Option<&Path>) -> PathBuf {
+        let context = Path::new(self.context.as_deref().unwrap_or("."));
+        let context = base_dir.map_or_else(|| context.to_path_buf(), |dir| dir.join(context));
+        context.join(self.dockerfile.as_deref().unwrap_or("Dockerfile"))
+    }
+}
+
+/// Parse the Dockerfile of each service built from one, modelling the stage named by its
+/// `build.target`, keyed by service name. Paths are relative to `base_dir`, by default the
+/// current directory. Fails when a Dockerfile can't be read or a target doesn't exist.
+pub fn load_build_containers(compose: &Compose, base_dir: Option<&Path>) -> Result<HashMap<String, ParsedContainer>> {
+    let mut containers = HashMap::new();
+    for (name, service) in compose.services() {
+        let Some(build) = &service.build else {
+            continue;
+        };
+        let path = build.dockerfile_path(base_dir);
+        let file = fs::File::open(&path).map_err(|e| anyhow!("Failed to read {} for service '{}': {}", path.display(), name, e))?;
+        let build_args = build.args.clone().unwrap_or_default();
+        let container = parse_containerfile_for_target(Box::new(BufReader::new(file)), name, &build_args, build.target.as_deref())
+            .map_err(|e| anyhow!("Failed to model the build of service '{}': {}", name, e))?;
+        containers.insert(name.to_string(), container);
+    }
+    Ok(containers)
+}
+
+/// The deployment configuration of a service
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Deploy {
+    pub mode: Option<String>,
+    pub replicas: Option<u32>,
+    pub resources: Option<Resources>,
+}
+
+/// The resource limits and reservations of a service
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Resources {
+    pub limits: Option<ResourceLimits>,
+    pub reservations: Option<Reservations>,
+}
+
+/// The most CPU and memory a service's containers may use
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// The number of CPUs, such as `0.5`
+    #[serde(default, deserialize_with = "deserialize_scalar_string")]
+    pub cpus: Option<String>,
+    /// The memory, in bytes
+    #[serde(default, deserialize_with = "deserialize_bytes")]
+    pub memory: Option<u64>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Reservations {
+    pub devices: Option<Vec<DeviceSpec>>,
+}
+
+/// A device as requested by `deploy.resources.reservations.devices` or a `gpus` list
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceSpec {
+    pub driver: Option<String>,
+    pub count: Option<DeviceCount>,
+    pub device_ids: Option<Vec<String>>,
+    pub capabilities: Option<Vec<String>>,
+    pub options: Option<HashMap<String, String>>,
+}
+
+/// How many devices are requested: a number, or `all`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DeviceCount {
+    Count(i64),
+    All(String),
+}
+
+impl fmt::Display for DeviceCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceCount::Count(count) => write!(f, "{}", count),
+            DeviceCount::All(all) => write!(f, "{}", all),
+        }
+    }
+}
+
+/// The service-level `gpus` field: `all`, or a list of devices
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Gpus {
+    All(String),
+    Devices(Vec<DeviceSpec>),
+}
+
+/// Where a device request was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeviceRequestSource {
+    /// `deploy.resources.reservations.devices`
+    Reservation,
+    /// The `gpus` field
+    Gpus,
+    /// `runtime: nvidia`
+    Runtime,
+}
+
+impl fmt::Display for DeviceRequestSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceRequestSource::Reservation => write!(f, "deploy.resources.reservations.devices"),
+            DeviceRequestSource::Gpus => write!(f, "gpus"),
+            DeviceRequestSource::Runtime => write!(f, "runtime"),
+        }
+    }
+}
+
+/// A request for devices such as GPUs, whichever of the compose forms it was written in
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceRequest {
+    pub source: DeviceRequestSource,
+    pub driver: Option<String>,
+    /// None when neither a count nor device IDs are given, which compose takes as all
+    pub count: Option<DeviceCount>,
+    pub device_ids: Vec<String>,
+    pub capabilities: Vec<String>,
+}
+
+impl DeviceRequest {
+    fn from_spec(source: DeviceRequestSource, spec: &DeviceSpec) -> Self {
+        DeviceRequest {
+            source,
+            driver: spec.driver.clone(),
+            count: spec.count.clone(),
+            device_ids: spec.device_ids.clone().unwrap_or_default(),
+            capabilities: spec.capabilities.clone().unwrap_or_default(),
+        }
+    }
+
+    /// All the GPUs of the default driver, as requested by `gpus: all` or `runtime: nvidia`
+    fn all_gpus(source: DeviceRequestSource) -> Self {
+        DeviceRequest {
+            source,
+            driver: Some("nvidia".to_string()),
+            count: Some(DeviceCount::All("all".to_string())),
+            device_ids: Vec::new(),
+            capabilities: vec!["gpu".to_string()],
+        }
+    }
+
+    /// True when all the devices available are requested, rather than a number of them
+    pub fn all_devices(&self) -> bool {
+        match &self.count {
+            Some(DeviceCount::All(_)) => true,
+            Some(DeviceCount::Count(_)) => false,
+            None => self.device_ids.is_empty(),
+        }
+    }
+}
+
+/// Device capabilities known to Docker's device drivers
+const DEVICE_CAPABILITIES: [&str; 8] = ["gpu", "tpu", "compute", "compat32", "graphics", "utility", "video", "display"];
+
+/// Per-network options of a service attached to a network
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NetworkRef {
+    pub aliases: Option<Vec<String>>,
+    pub ipv4_address: Option<Ipv4Addr>,
+    pub ipv6_address: Option<Ipv6Addr>,
+    pub priority: Option<i32>,
+}
+
+/// A network membership as written in either the list or the map form
+#[derive(Debug, PartialEq)]
+pub enum ServiceNetwork {
+    Simple(String),
+    Extended(String, NetworkRef),
+}
+
+impl ServiceNetwork {
+    pub fn name(&self) -> &str {
+        match self {
+            ServiceNetwork::Simple(name) | ServiceNetwork::Extended(name, _) => name,
+        }
+    }
+}
+
+/// When compose pulls the image of a service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullPolicy {
+    Always,
+    Never,
+    /// Pull only when the image isn't available locally, the default
+    Missing,
+    /// Build the image rather than pulling it
+    Build,
+    /// An alias of `missing`
+    IfNotPresent,
+}
+
+impl fmt::Display for PullPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PullPolicy::Always => write!(f, "always"),
+            PullPolicy::Never => write!(f, "never"),
+            PullPolicy::Missing => write!(f, "missing"),
+            PullPolicy::Build => write!(f, "build"),
+            PullPolicy::IfNotPresent => write!(f, "if_not_present"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PullPolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        match value.as_str() {
+            "always" => Ok(PullPolicy::Always),
+            "never" => Ok(PullPolicy::Never),
+            "missing" => Ok(PullPolicy::Missing),
+            "build" => Ok(PullPolicy::Build),
+            "if_not_present" => Ok(PullPolicy::IfNotPresent),
+            _ => Err(de::Error::custom(format!(
+                "invalid pull_policy '{}', expected always, never, missing, build or if_not_present",
+                value
+            ))),
+        }
+    }
+}
+
+/// The `develop` section of a service. Fields other than `watch` are accepted and ignored.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Develop {
+    pub watch: Option<Vec<WatchRule>>,
+}
+
+/// A rule of `docker compose watch`, acting on the service when files under a host path change
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct WatchRule {
+    /// `sync`, `rebuild`, `restart`, `sync+restart` or `sync+exec`
+    pub action: String,
+    /// The watched path on the host, relative to the compose file
+    pub path: String,
+    /// Where changed files are synced to in the container
+    pub target: Option<String>,
+    /// Patterns of paths under `path` to ignore, as in `.dockerignore`
+    pub ignore: Option<Vec<String>>,
 }
 
+/// The actions `docker compose watch` can take
+const WATCH_ACTIONS: &[&str] = &["sync", "rebuild", "restart", "sync+restart", "sync+exec"];
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Healthcheck {
-    test: Vec<String>,
-    interval: Option<String>,
-    timeout: Option<String>,
-    retries: Option<i32>,
-    start_period: Option<String>,
+    pub test: Vec<String>,
+    pub interval: Option<String>,
+    pub timeout: Option<String>,
+    pub retries: Option<i32>,
+    pub start_period: Option<String>,
+}
+
+impl Healthcheck {
+    /// The longest the healthcheck can take to find the service healthy: every retry failing
+    /// after the start period. Fields not set take Docker's defaults, an interval of 30s, 3
+    /// retries and no start period.
+    pub fn readiness_time(&self) -> Result<Duration, CargotectureError> {
+        let duration = |field: &str, value: &Option<String>, default: Duration| match value {
+            Some(value) => parse_duration(value).ok_or_else(|| {
+                CargotectureError::Validation(format!("Invalid healthcheck.{} value '{}': must be a duration such as '30s'", field, value))
+            }),
+            None => Ok(default),
+        };
+        let interval = duration("interval", &self.interval, Duration::from_secs(30))?;
+        let start_period = duration("start_period", &self.start_period, Duration::ZERO)?;
+        let retries = self.retries.unwrap_or(3).max(0) as u32;
+        Ok(interval * retries + start_period)
+    }
+}
+
+impl fmt::Display for Healthcheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "test={}", self.test.join(" "))?;
+        for (field, value) in [("interval", &self.interval), ("timeout", &self.timeout), ("start_period", &self.start_period)] {
+            if let Some(value) = value {
+                write!(f, ", {}={}", field, value)?;
+            }
+        }
+        if let Some(retries) = self.retries {
+            write!(f, ", retries={}", retries)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Services {
-    services: HashMap<String, Service>,
+    pub services: HashMap<String, Service>,
 }
 
 
@@ -128,46 +1061,160 @@ pub enum DependsOn {
     Map(HashMap<String, Condition>),
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+impl DependsOn {
+    /// The names of the services depended on, in declaration order for the list form and
+    /// sorted for the map form
+    pub fn all_dependencies(&self) -> Vec<&str> {
+        match self {
+            DependsOn::List(list) => list.iter().map(String::as_str).collect(),
+            DependsOn::Map(map) => {
+                let mut names: Vec<&str> = map.keys().map(String::as_str).collect();
+                names.sort();
+                names
+            }
+        }
+    }
+
+    /// The condition on a dependency, such as `service_healthy`. None for the list form,
+    /// which is equivalent to `service_started`, and for services not depended on.
+    pub fn condition_for(&self, service: &str) -> Option<&str> {
+        match self {
+            DependsOn::List(_) => None,
+            DependsOn::Map(map) => map.get(service).map(|condition| condition.condition.as_str()),
+        }
+    }
+}
+
+/// The services depended on separated by commas, each with its condition unless that's
+/// `service_started`, as `db:service_healthy,cache`
+impl fmt::Display for DependsOn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dependencies: Vec<String> = self
+            .all_dependencies()
+            .into_iter()
+            .map(|dependency| match self.condition_for(dependency) {
+                Some(condition) if condition != "service_started" => format!("{}:{}", dependency, condition),
+                _ => dependency.to_string(),
+            })
+            .collect();
+        write!(f, "{}", dependencies.join(","))
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Condition {
-    condition: String,
+    pub condition: String,
+    /// Whether the dependent service fails to start without the dependency. Compose starts
+    /// a required dependency whatever its profiles, and skips an optional one it wouldn't
+    /// otherwise start.
+    #[serde(skip_serializing_if = "is_true")]
+    pub required: bool,
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+/// Accept a dependency with no options, as for `db:` or `db: {}`, as waiting for the service
+/// to start
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Options {
+            condition: Option<String>,
+            required: Option<bool>,
+        }
+
+        let options = Option::<Options>::deserialize(deserializer)?;
+        let (condition, required) = options.map_or((None, None), |options| (options.condition, options.required));
+        Ok(Condition { condition: condition.unwrap_or_else(|| "service_started".to_string()), required: required.unwrap_or(true) })
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Logging {
-    driver: String,
-    options: Option<HashMap<String, String>>,
+    pub driver: String,
+    pub options: Option<HashMap<String, String>>,
 }
 
+const NETWORK_FIELDS: &[&str] = &["enable_ipv6", "driver", "ipam", "internal"];
+const UNMODELLED_NETWORK_FIELDS: &[&str] = &["name", "external", "attachable", "driver_opts", "labels"];
+
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Network {
-    enable_ipv6: Option<bool>,
-    driver: Option<String>,
-    ipam: Option<Ipam>,
-    internal: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub enable_ipv6: Option<bool>,
+    pub driver: Option<String>,
+    pub ipam: Option<Ipam>,
+    #[serde(default, deserialize_with = "deserialize_bool")]
+    pub internal: Option<bool>,
+    /// `x-` extension fields, by name
+    #[serde(skip)]
+    pub extensions: HashMap<String, serde_yaml::Value>,
+}
+
+deserialize_with_extensions!(Network, NETWORK_FIELDS, UNMODELLED_NETWORK_FIELDS);
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "driver={}", self.driver.as_deref().unwrap_or("default"))?;
+        if let Some(internal) = self.internal {
+            write!(f, ", internal={}", internal)?;
+        }
+        if let Some(enable_ipv6) = self.enable_ipv6 {
+            write!(f, ", enable_ipv6={}", enable_ipv6)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Ipam {
-    driver: Option<String>,
-    config: Option<Vec<SubnetConfig>>,
+    pub driver: Option<String>,
+    pub config: Option<Vec<SubnetConfig>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SubnetConfig {
-    subnet: IpNetwork,
+    pub subnet: IpNetwork,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(untagged)]
+/// The subnet of a network in CIDR notation. IPv6 subnets are kept as written.
+#[derive(Debug, Clone, PartialEq)]
 pub enum IpNetwork {
-    V4(Ipv4Addr),
-    V6(Ipv6Addr),
+    V4(CidrV4),
+    V6(String),
 }
 
-// #[derive(Debug, Serialize)]
-// #[serde(untagged)]
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpNetwork::V4(cidr) => write!(f, "{}", cidr),
+            IpNetwork::V6(cidr) => write!(f, "{}", cidr),
+        }
+    }
+}
+
+impl Serialize for IpNetwork {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNetwork {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let subnet = String::deserialize(deserializer)?;
+        match subnet.contains(':') {
+            true => Ok(IpNetwork::V6(subnet)),
+            false => subnet.parse().map(IpNetwork::V4).map_err(de::Error::custom),
+        }
+    }
+}
+
+// #[derive(Debug, Serialize)]
+// #[serde(untagged)]
 // enum SocketAddr {
 //     #[serde(deserialize_with = "deserialize_socket_addr")]
 //     V4(SocketAddr),
@@ -175,344 +1222,3769 @@ pub enum IpNetwork {
 //     V6(SocketAddr),
 // }
 
-impl Compose {
-    pub fn validate(&self) -> Result<(), String> {
-        let service_names: HashSet<&String> = self.services.keys().collect();
-        let networks = &self.networks;
-        let t=&HashMap::new();
-        let network_names: HashSet<&String> = networks.as_ref().unwrap_or(t).keys().collect();
+/// A port of a service, combining Dockerfile `EXPOSE` and compose `ports` data
+#[derive(Debug, PartialEq)]
+pub struct PortInfo {
+    pub container_port: u16,
+    pub host_port: Option<u16>,
+    pub protocol: Protocol,
+    /// True when the port is published on the host by a `ports` entry
+    pub published: bool,
+    /// The name given to the port by a long-syntax `ports` entry, such as `http`
+    pub name: Option<String>,
+}
 
-        for (name, service) in &self.services {
-            // Validate restart values
-            if let Some(ref restart) = service.restart {
-                if !["no", "always", "on-failure", "unless-stopped"]
-                    .contains(&restart.as_str())
-                {
-                    return Err(format!(
-                        "Invalid restart value '{}' for service '{}'",
-                        restart, name
-                    ));
-                }
-            }
+/// Add an exposed port to a list of ports, unless it's already there
+fn add_unpublished_port(ports: &mut Vec<PortInfo>, container_port: u16, protocol: Protocol) {
+    if !ports.iter().any(|p| p.container_port == container_port && p.protocol == protocol) {
+        ports.push(PortInfo { container_port, host_port: None, protocol, published: false, name: None });
+    }
+}
 
-            // Validate referenced networks
-            if let Some(ref networks) = service.networks {
-                for network in networks {
-                    if !network_names.contains(network) {
-                        return Err(format!(
-                            "Referenced network '{}' not found for service '{}'",
-                            network, name
-                        ));
-                    }
-                }
-            }
+/// Add the ports exposed by a container to a list of ports, unless they're already there
+fn add_container_ports(ports: &mut Vec<PortInfo>, container: Option<&ParsedContainer>) {
+    for exposed in container.iter().flat_map(|c| c.exposed_ports.iter()) {
+        add_unpublished_port(ports, exposed.port_number, exposed.protocol);
+    }
+}
 
-            // Validate depends_on services
-            if let Some(ref depends_on) = service.depends_on {
-                let s=match depends_on {
-                    DependsOn::List(l) => l.clone(),
-                    DependsOn::Map(k) => k.keys().cloned().collect(),
-                };
-                for dependency in s {
-                    if !service_names.contains(&dependency) {
-                        return Err(format!(
-                            "Referenced service '{}' in depends_on not found for service '{}'",
-                            dependency, name
-                        ));
-                    }
-                }
-            }
-        }
+/// The kind of storage a service mounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VolumeType {
+    /// A host path, as for `./data:/data`
+    Bind,
+    /// A volume managed by the engine, as for `dbdata:/data`, or an anonymous `/data`
+    Named,
+    /// An in-memory filesystem listed in `tmpfs`
+    Tmpfs,
+    /// A `volumes` entry which doesn't parse
+    Unknown,
+}
+
+/// A service-to-service communication edge inferred from configuration rather than declared
+#[derive(Debug, PartialEq)]
+pub struct InferredFlow {
+    pub source: String,
+    pub target: String,
+    pub port: u16,
+    pub evidence: String,
+}
 
+/// A service in a wave of the start order
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WaveService {
+    pub name: String,
+    /// Whether the service has a healthcheck, which `service_healthy` dependencies wait on
+    pub healthcheck: bool,
+}
+
+/// Services which can be started together once the earlier waves are up
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StartWave {
+    /// The number of the wave, from 1
+    pub wave: usize,
+    pub services: Vec<WaveService>,
+}
+
+impl fmt::Display for StartWave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Wave {}:", self.wave)?;
+        for service in &self.services {
+            let healthcheck = if service.healthcheck { "healthcheck" } else { "no healthcheck" };
+            writeln!(f, "  {} ({})", service.name, healthcheck)?;
+        }
         Ok(())
     }
 }
 
-pub fn parse_composefile(reader: Box<dyn Read>) -> Result<Compose> {
-    let compose: Compose = serde_yaml::from_reader(BufReader::new(reader))?;
-    match compose.validate(){
-        Ok(()) => println!("Validation successful"),
-        Err(err) => println!("Compose validation failed: {}", err),
+/// A service outside the active profiles which compose starts anyway, as an active service
+/// requires it
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AutoActivation {
+    pub service: String,
+    pub profiles: Vec<String>,
+    /// The service whose required dependency it is, itself active or auto-activated
+    pub required_by: String,
+}
+
+impl fmt::Display for AutoActivation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Service '{}' (profiles {}) is activated as '{}' requires it", self.service, self.profiles.join(", "), self.required_by)
     }
-    println!("{:#?}", compose);
-    Ok(compose)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// An optional dependency of an active service on a service which isn't started
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SkippedDependency {
+    pub service: String,
+    pub dependency: String,
+    pub profiles: Vec<String>,
+}
 
-    fn get_yaml_sample() -> String {
-        r#"
-        services:
-          db:
-            healthcheck:
-              test: ['CMD-SHELL', 'mysqladmin ping -h 127.0.0.1 --password="$$(cat /run/secrets/db-password)" --silent']
-              interval: 3s
-          backend:
-            depends_on:
-              db:
-                condition: service_healthy
-          proxy:
-            depends_on: 
-              - backend
-        "#.to_string()
+impl fmt::Display for SkippedDependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Service '{}' depends on '{}', which isn't started as its profiles {} are inactive and the dependency isn't required",
+            self.service, self.dependency, self.profiles.join(", ")
+        )
     }
-    
+}
 
-    fn get_yaml_elk() -> String {
-        // Replace with the second YAML string
-        r#"
-services:
-  elasticsearch:
-    image: elasticsearch:7.16.1
-    container_name: es
-    environment:
-      discovery.type: single-node
-      ES_JAVA_OPTS: "-Xms512m -Xmx512m"
-    ports:
-      - "9200:9200"
-      - "9300:9300"
-    healthcheck:
-      test: ["CMD-SHELL", "curl --silent --fail localhost:9200/_cluster/health || exit 1"]
-      interval: 10s
-      timeout: 10s
-      retries: 3
-    networks:
-      - elastic
-  logstash:
-    image: logstash:7.16.1
-    container_name: log
-    environment:
-      discovery.seed_hosts: logstash
-      LS_JAVA_OPTS: "-Xms512m -Xmx512m"
-    volumes:
-      - ./logstash/pipeline/logstash-nginx.config:/usr/share/logstash/pipeline/logstash-nginx.config
-      - ./logstash/nginx.log:/home/nginx.log
-    ports:
-      - "5000:5000/tcp"
-      - "5000:5000/udp"
-      - "5044:5044"
-      - "9600:9600"
-    depends_on:
-      - elasticsearch
-    networks:
-      - elastic
-    command: logstash -f /usr/share/logstash/pipeline/logstash-nginx.config
-  kibana:
-    image: kibana:7.16.1
-    container_name: kib
-    ports:
-      - "5601:5601"
-    depends_on:
-      - elasticsearch
-    networks:
-      - elastic
-networks:
-  elastic:
-    driver: bridge
-        "#.to_string()
+/// The services compose starts with a set of profiles active
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ProfileActivation {
+    /// The services started, sorted
+    pub services: Vec<String>,
+    /// The services started only as dependencies, in the order they were found
+    pub auto_activated: Vec<AutoActivation>,
+    /// The optional dependencies left out, sorted by service
+    pub skipped: Vec<SkippedDependency>,
+}
 
+impl ProfileActivation {
+    /// Note each service auto-activated and warn about each optional dependency left out,
+    /// so that they can be allowed and denied like other problems
+    pub fn report(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        for activated in &self.auto_activated {
+            let field = format!("services.{}.profiles", activated.service);
+            report.note_at(Some(&activated.service), field, "profile-auto-activated", activated.to_string());
+        }
+        for skipped in &self.skipped {
+            let field = format!("services.{}.depends_on.{}", skipped.service, skipped.dependency);
+            report.warning_at(Some(&skipped.service), field, "inactive-optional-dependency", skipped.to_string());
+        }
+        report
     }
+}
 
-    fn check_elasticsearch_service(service: &Service) {
-        assert_eq!(service.image.as_ref().unwrap(), "elasticsearch:7.16.1");
-        assert_eq!(service.container_name.as_ref().unwrap(), "es");
-        let environment = service.environment.as_ref().unwrap();
-        assert_eq!(environment.get("discovery.type").unwrap(), "single-node");
-        assert_eq!(environment.get("ES_JAVA_OPTS").unwrap(), "-Xms512m -Xmx512m");
-    
-        let ports = service.ports.as_ref().unwrap();
-        assert_eq!(ports.len(), 2);
-        assert_eq!(ports[0], "9200:9200");
-        assert_eq!(ports[1], "9300:9300");
-    
-        let healthcheck = service.healthcheck.as_ref().unwrap();
-        assert_eq!(healthcheck.test, vec!["CMD-SHELL", "curl --silent --fail localhost:9200/_cluster/health || exit 1"]);
-        assert_eq!(healthcheck.interval.as_ref().unwrap(), "10s");
-        assert_eq!(healthcheck.timeout.as_ref().unwrap(), "10s");
-        assert_eq!(healthcheck.retries.unwrap(), 3);
-    
-        let networks = service.networks.as_ref().unwrap();
-        assert_eq!(networks.len(), 1);
-        assert_eq!(networks[0], "elastic");
+impl Service {
+    /// The values of the `x-` extension fields named by `keys` which the service sets, in
+    /// the order of `keys`. Scalars are given as written and other values as JSON.
+    pub fn promoted_extensions<'a>(&self, keys: &'a [String]) -> Vec<(&'a str, String)> {
+        keys.iter()
+            .filter_map(|key| {
+                let text = match self.extensions.get(key)? {
+                    serde_yaml::Value::String(s) => s.clone(),
+                    serde_yaml::Value::Number(n) => number_string(n),
+                    serde_yaml::Value::Bool(b) => b.to_string(),
+                    value => serde_json::to_string(value).ok()?,
+                };
+                Some((key.as_str(), text))
+            })
+            .collect()
     }
 
-    fn check_logstash_service(service: &Service) {
-        assert_eq!(service.image.as_ref().unwrap(), "logstash:7.16.1");
-        assert_eq!(service.container_name.as_ref().unwrap(), "log");
-    
-        let environment = service.environment.as_ref().unwrap();
-        assert_eq!(environment.get("discovery.seed_hosts").unwrap(), "logstash");
-        assert_eq!(environment.get("LS_JAVA_OPTS").unwrap(), "-Xms512m -Xmx512m");
-    
-        let volumes = service.volumes.as_ref().unwrap();
-        assert_eq!(volumes.len(), 2);
-        assert_eq!(volumes[0], "./logstash/pipeline/logstash-nginx.config:/usr/share/logstash/pipeline/logstash-nginx.config");
-        assert_eq!(volumes[1], "./logstash/nginx.log:/home/nginx.log");
-    
-        let ports = service.ports.as_ref().unwrap();
-        assert_eq!(ports.len(), 4);
-        assert_eq!(ports[0], "5000:5000/tcp");
-        assert_eq!(ports[1], "5000:5000/udp");
-        assert_eq!(ports[2], "5044:5044");
-        assert_eq!(ports[3], "9600:9600");
-    
-        let depends_on = service.depends_on.as_ref().unwrap();
-        match depends_on {
-            DependsOn::List(services) => {
-                assert_eq!(services.len(), 1);
-                assert_eq!(services[0], "elasticsearch");
-            }
-            _ => panic!("Unexpected DependsOn variant"),
+    /// Whether no field of the service is set, as for `web:` or `web: {}`
+    fn is_unconfigured(&self) -> bool {
+        self.extensions.is_empty()
+            && serde_yaml::to_value(self).is_ok_and(|value| value.as_mapping().is_some_and(|fields| fields.values().all(serde_yaml::Value::is_null)))
+    }
+
+    /// Names of the services this depends on, in declaration order for the list form of
+    /// `depends_on` and sorted for the map form
+    pub fn depends_on_names(&self) -> Vec<&str> {
+        self.depends_on.as_ref().map(DependsOn::all_dependencies).unwrap_or_default()
+    }
+
+    /// The condition on a dependency, `service_started` for the list form of `depends_on`,
+    /// or None for services not depended on
+    pub fn depends_on_condition(&self, service: &str) -> Option<&str> {
+        match self.depends_on.as_ref()? {
+            DependsOn::List(list) => list.iter().any(|name| name == service).then_some("service_started"),
+            DependsOn::Map(map) => map.get(service).map(|condition| condition.condition.as_str()),
         }
-    
-        let networks = service.networks.as_ref().unwrap();
-        assert_eq!(networks.len(), 1);
-        assert_eq!(networks[0], "elastic");
-    
-        let command = service.command.as_ref().unwrap();
-        assert_eq!(command, "logstash -f /usr/share/logstash/pipeline/logstash-nginx.config");
-    
-        // Since no other properties are defined for the logstash service in the provided YAML,
-        // we'll check that they are set to their default values (i.e., None or empty).
-        assert!(service.restart.is_none());
-        assert!(service.env_file.is_none());
-        assert!(service.logging.is_none());
-        assert!(service.dns.is_none());
-        assert!(service.hostname.is_none());
-        assert!(service.extra_hosts.is_none());
-        assert!(service.healthcheck.is_none());
     }
 
-    fn check_kibana_service(service: &Service) {
-        assert_eq!(service.image.as_ref().unwrap(), "kibana:7.16.1");
-        assert_eq!(service.container_name.as_ref().unwrap(), "kib");
-        let ports = service.ports.as_ref().unwrap();
-        assert_eq!(ports.len(), 1);
-        assert_eq!(ports[0], "5601:5601");
+    /// Whether a dependency is required, as every dependency of the list form of
+    /// `depends_on` is, or None for services not depended on
+    pub fn depends_on_required(&self, service: &str) -> Option<bool> {
+        match self.depends_on.as_ref()? {
+            DependsOn::List(list) => list.iter().any(|name| name == service).then_some(true),
+            DependsOn::Map(map) => map.get(service).map(|condition| condition.required),
+        }
+    }
 
-        let depends_on = service.depends_on.as_ref().unwrap();
-        match depends_on {
-            DependsOn::List(services) => {
-                assert_eq!(services.len(), 1);
-                assert_eq!(services[0], "elasticsearch");
-            }
-            _ => panic!("Unexpected DependsOn variant"),
+    /// Whether the service runs with the profiles `active`: it has none, or one of them
+    pub fn is_active(&self, active: &[String]) -> bool {
+        match self.profiles.as_deref() {
+            None | Some([]) => true,
+            Some(profiles) => profiles.iter().any(|profile| active.contains(profile)),
         }
+    }
 
-        let networks = service.networks.as_ref().unwrap();
-        assert_eq!(networks.len(), 1);
-        assert_eq!(networks[0], "elastic");
+    /// The value of an environment variable, if set
+    pub fn env(&self, key: &str) -> Option<&str> {
+        self.environment.as_ref()?.get(key).map(String::as_str)
+    }
 
-        // Since no other properties are defined for the kibana service in the provided YAML,
-        // we'll check that they are set to their default values (i.e., None or empty).
-        assert!(service.restart.is_none());
-        assert!(service.env_file.is_none());
-        assert!(service.logging.is_none());
-        assert!(service.environment.is_none());
-        assert!(service.extra_hosts.is_none());
-        assert!(service.healthcheck.is_none());
-        assert!(service.dns.is_none());
-        assert!(service.hostname.is_none());
-        assert!(service.volumes.is_none());
+    fn resource_limits(&self) -> Option<&ResourceLimits> {
+        self.deploy.as_ref()?.resources.as_ref()?.limits.as_ref()
     }
 
-    fn check_db_service(service: &Service) {
-        let healthcheck = service.healthcheck.as_ref().unwrap();
-        assert_eq!(healthcheck.test, vec!["CMD-SHELL", "mysqladmin ping -h 127.0.0.1 --password=\"$$(cat /run/secrets/db-password)\" --silent"]);
-        assert_eq!(healthcheck.interval.as_ref().unwrap(), "3s");
-        assert!(healthcheck.timeout.is_none());
-        assert!(healthcheck.retries.is_none());
-        assert!(healthcheck.start_period.is_none());
-    
-        // Since no other properties are defined for the db service in the provided YAML, 
-        // we'll check that they are set to their default values (i.e., None or empty).
-        assert!(service.image.is_none());
-        assert!(service.restart.is_none());
-        assert!(service.env_file.is_none());
-        assert!(service.logging.is_none());
-        assert!(service.ports.is_none());
-        assert!(service.networks.is_none());
-        assert!(service.volumes.is_none());
-        assert!(service.depends_on.is_none());
-        assert!(service.dns.is_none());
-        assert!(service.hostname.is_none());
-        assert!(service.environment.is_none());
-        assert!(service.extra_hosts.is_none());
+    /// The CPU limit of `deploy.resources.limits.cpus`, in thousandths of a CPU
+    pub fn cpu_limit(&self) -> Option<u64> {
+        self.resource_limits()?.cpus.as_deref().and_then(parse_cpus)
     }
 
-    fn check_backend_service(service: &Service) {
-        let depends_on = service.depends_on.as_ref().unwrap();
-        match depends_on {
-            DependsOn::Map(conditions) => {
+    /// The memory limit of `deploy.resources.limits.memory`, or failing that `mem_limit`,
+    /// in bytes
+    pub fn memory_limit(&self) -> Option<u64> {
+        self.resource_limits().and_then(|limits| limits.memory).or(self.mem_limit)
+    }
+
+    /// Container-side ports from the `ports` entries. Port ranges are skipped.
+    pub fn container_ports(&self) -> Vec<ExposedPort> {
+        self.ports
+            .iter()
+            .flatten()
+            .filter_map(|spec| parse_port_mapping(spec).ok())
+            .map(|binding| ExposedPort { port_number: binding.container_port, protocol: binding.protocol, line: None })
+            .collect()
+    }
+
+    /// The ports published on the host by the `ports` entries
+    pub fn published_ports(&self) -> Vec<PortInfo> {
+        self.port_infos().into_iter().filter(|port| port.published).collect()
+    }
+
+    /// The ports published by the `ports` entries, followed by those only listed in `expose`
+    pub fn port_infos(&self) -> Vec<PortInfo> {
+        let mut ports: Vec<PortInfo> = self
+            .ports
+            .iter()
+            .flatten()
+            .filter_map(|spec| parse_port_mapping(spec).ok())
+            .map(|binding| PortInfo {
+                container_port: binding.container_port,
+                host_port: binding.host_port,
+                protocol: binding.protocol,
+                published: true,
+                name: self.port_names.get(&(binding.container_port, binding.protocol)).cloned(),
+            })
+            .collect();
+        for binding in self.expose.iter().flatten().filter_map(|spec| parse_port_mapping(spec).ok()) {
+            add_unpublished_port(&mut ports, binding.container_port, binding.protocol);
+        }
+        ports
+    }
+
+    /// The host port a container port is published on by the first `ports` entry for it,
+    /// or None when it isn't published or Docker chooses an ephemeral host port
+    pub fn get_host_port_for_container_port(&self, container_port: u16, protocol: Protocol) -> Option<u16> {
+        self.ports
+            .iter()
+            .flatten()
+            .filter_map(|spec| parse_port_mapping(spec).ok())
+            .find(|binding| binding.container_port == container_port && binding.protocol == protocol && binding.host_port.is_some())
+            .and_then(|binding| binding.host_port)
+    }
+
+    /// The container port published on a host port by the first `ports` entry for it
+    pub fn get_container_port_for_host_port(&self, host_port: u16, protocol: Protocol) -> Option<u16> {
+        self.ports
+            .iter()
+            .flatten()
+            .filter_map(|spec| parse_port_mapping(spec).ok())
+            .find(|binding| binding.host_port == Some(host_port) && binding.protocol == protocol)
+            .map(|binding| binding.container_port)
+    }
+
+    /// The ports of the service, followed by any further ports exposed by the container
+    /// built from its image
+    pub fn exposed_ports(&self, container: Option<&ParsedContainer>) -> Vec<PortInfo> {
+        let mut ports = self.port_infos();
+        add_container_ports(&mut ports, container);
+        ports
+    }
+
+    /// Container-side mount points from the short-syntax `volumes` entries
+    pub fn mount_points(&self) -> Vec<VolumeMount> {
+        self.volumes
+            .iter()
+            .flatten()
+            .filter_map(|spec| parse_volume_spec(spec).ok())
+            .filter_map(|spec| VolumeMount::new(&spec.target).ok())
+            .collect()
+    }
+
+    /// Whether the service runs in a Windows container, by its `platform`
+    pub fn is_windows(&self) -> bool {
+        self.platform.as_deref().is_some_and(|platform| platform.to_lowercase().starts_with("windows"))
+    }
+
+    /// Which of `stdin_open` and `tty`, the flags for attaching to the container
+    /// interactively, the service sets
+    pub fn interactive_flags(&self) -> Vec<&'static str> {
+        [("stdin_open", self.stdin_open), ("tty", self.tty)]
+            .into_iter()
+            .filter_map(|(flag, set)| (set == Some(true)).then_some(flag))
+            .collect()
+    }
+
+    /// The number of instances of the service, when set by `deploy.replicas` or `scale`
+    pub fn replicas(&self) -> Option<u32> {
+        self.deploy.as_ref().and_then(|d| d.replicas).or(self.scale)
+    }
+
+    /// The devices requested, from `deploy.resources.reservations.devices`, then `gpus`,
+    /// then `runtime: nvidia`. Each form is kept, even where they repeat each other.
+    pub fn device_requests(&self) -> Vec<DeviceRequest> {
+        let mut requests: Vec<DeviceRequest> = self
+            .deploy
+            .iter()
+            .filter_map(|deploy| deploy.resources.as_ref()?.reservations.as_ref()?.devices.as_ref())
+            .flatten()
+            .map(|spec| DeviceRequest::from_spec(DeviceRequestSource::Reservation, spec))
+            .collect();
+        match &self.gpus {
+            Some(Gpus::All(_)) => requests.push(DeviceRequest::all_gpus(DeviceRequestSource::Gpus)),
+            Some(Gpus::Devices(specs)) => requests.extend(specs.iter().map(|spec| {
+                let mut request = DeviceRequest::from_spec(DeviceRequestSource::Gpus, spec);
+                if request.capabilities.is_empty() {
+                    request.capabilities.push("gpu".to_string());
+                }
+                request
+            })),
+            None => (),
+        }
+        if self.runtime.as_deref() == Some("nvidia") {
+            requests.push(DeviceRequest::all_gpus(DeviceRequestSource::Runtime));
+        }
+        requests
+    }
+
+    /// The value of a label, if set
+    pub fn label_value(&self, key: &str) -> Option<&str> {
+        self.labels.as_ref()?.get(key).map(String::as_str)
+    }
+
+    /// The keys of the labels set, in no particular order
+    pub fn label_keys(&self) -> impl Iterator<Item = &str> {
+        self.labels.iter().flat_map(|labels| labels.keys()).map(String::as_str)
+    }
+
+    pub fn has_label(&self, key: &str) -> bool {
+        self.labels.as_ref().is_some_and(|labels| labels.contains_key(key))
+    }
+
+    /// Names of the networks this service is attached to, sorted
+    pub fn network_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.networks.iter().flatten().map(|(name, _)| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// The networks the service is attached to, sorted, or `default` when it lists none
+    pub fn attached_networks(&self) -> Vec<&str> {
+        match self.network_names() {
+            networks if networks.is_empty() => vec!["default"],
+            networks => networks,
+        }
+    }
+
+    /// Names other services on a network can use to reach this one: its service name,
+    /// hostname and container name, and its aliases on that network
+    fn names_on_network<'a>(&'a self, name: &'a str, network: &str) -> Vec<&'a str> {
+        let mut names = vec![name];
+        names.extend(self.hostname.as_deref());
+        names.extend(self.container_name.as_deref());
+        let options = self.networks.as_ref().and_then(|n| n.get(network)).and_then(Option::as_ref);
+        names.extend(options.iter().flat_map(|options| options.aliases.iter().flatten()).map(String::as_str));
+        names
+    }
+}
+
+/// The names resolvable on each network of a compose file, by network and then name, each
+/// with the services claiming it. More than one service claiming a name is a collision.
+pub type NameTable = BTreeMap<String, BTreeMap<String, BTreeSet<String>>>;
+
+impl Compose {
+    /// The service with a name, if defined
+    pub fn service(&self, name: &str) -> Option<&Service> {
+        self.services.get(name)
+    }
+
+    /// The services with their names, sorted by name
+    ///
+    /// ```
+    /// # use cargotecture::parse_compose::parse_composefiles_unvalidated;
+    /// let yaml = "services:\n  web: {image: nginx}\n  db: {image: postgres}\n";
+    /// let compose = parse_composefiles_unvalidated(vec![Box::new(yaml.as_bytes())], &Default::default()).unwrap();
+    /// let names: Vec<&str> = compose.services().map(|(name, _)| name).collect();
+    /// assert_eq!(names, ["db", "web"]);
+    /// ```
+    pub fn services(&self) -> impl Iterator<Item = (&str, &Service)> {
+        let mut services: Vec<(&str, &Service)> = self.services.iter().map(|(name, service)| (name.as_str(), service)).collect();
+        services.sort_by_key(|(name, _)| *name);
+        services.into_iter()
+    }
+
+    /// The ports of a service: those published by its `ports` entries, followed by any
+    /// further ports listed in `expose` or exposed by the container built from its image.
+    /// A service the file doesn't define has no ports, whatever the container exposes.
+    pub fn get_service_exposed_ports(&self, service_name: &str, container: Option<&ParsedContainer>) -> Vec<PortInfo> {
+        let Some(service) = self.services.get(service_name) else {
+            return Vec::new();
+        };
+        let mut ports = service.port_infos();
+        add_container_ports(&mut ports, container);
+        ports
+    }
+
+    /// The services using each image, keyed by the image reference as `image_key` compares
+    /// it, so that with `normalize_refs` on, `nginx` and `docker.io/library/nginx:latest`
+    /// are the same image. A service built rather than pulled uses the base image of the
+    /// container built for it. Services are in name order.
+    pub fn image_consumers(&self, containers: &HashMap<String, ParsedContainer>, normalize_refs: bool) -> BTreeMap<String, Vec<&str>> {
+        let mut consumers: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for (name, service) in self.services() {
+            let image = service
+                .image
+                .as_deref()
+                .or_else(|| containers.get(name).map(|container| container.base_image.as_str()));
+            if let Some(image) = image {
+                consumers.entry(image_key(image, normalize_refs)).or_default().push(name);
+            }
+        }
+        consumers
+    }
+
+    /// The names each service can be reached by on each network it's attached to
+    pub fn name_table(&self) -> NameTable {
+        let mut table = NameTable::new();
+        for (name, service) in &self.services {
+            for network in service.attached_networks() {
+                let names = table.entry(network.to_string()).or_default();
+                for alias in service.names_on_network(name, network) {
+                    names.entry(alias.to_string()).or_default().insert(name.clone());
+                }
+            }
+        }
+        table
+    }
+
+    /// Infer likely communication edges from environment values which reference another
+    /// service by URL or `host:port`. The inference is deliberately conservative: the host
+    /// must resolve to exactly one service in the name table of a network the source is
+    /// attached to, the port must be explicit or the well-known default for the URL scheme,
+    /// and when the target declares ports the port must be one of them.
+    pub fn infer_flows(&self) -> Vec<InferredFlow> {
+        let names = self.name_table();
+        let mut flows = Vec::new();
+        let mut sources: Vec<&String> = self.services.keys().collect();
+        sources.sort();
+
+        for source in sources {
+            let service = &self.services[source];
+            let mut environment: Vec<(&String, &String)> =
+                service.environment.iter().flatten().collect();
+            environment.sort();
+
+            for (key, value) in environment {
+                for candidate in value.split([',', ' ']) {
+                    let Some(endpoint) = parse_endpoint(candidate) else {
+                        continue;
+                    };
+                    let Some(port) = endpoint
+                        .port
+                        .or_else(|| endpoint.scheme.and_then(default_port_for_scheme))
+                    else {
+                        continue;
+                    };
+                    let targets: BTreeSet<&String> = service
+                        .attached_networks()
+                        .into_iter()
+                        .filter_map(|network| names.get(network)?.get(endpoint.host))
+                        .flatten()
+                        .collect();
+                    let [target] = targets.into_iter().collect::<Vec<_>>()[..] else {
+                        continue;
+                    };
+                    let target_service = &self.services[target];
+                    let declared = target_service.port_infos();
+                    if target == source
+                        || (!declared.is_empty() && !declared.iter().any(|p| p.container_port == port))
+                    {
+                        continue;
+                    }
+                    flows.push(InferredFlow {
+                        source: source.clone(),
+                        target: target.clone(),
+                        port,
+                        evidence: format!("{}={}", key, redact_url_credentials(candidate)),
+                    });
+                }
+            }
+        }
+        flows
+    }
+
+    /// All the DNS servers set by any service
+    pub fn get_dns_servers(&self) -> HashSet<SocketAddr> {
+        self.services
+            .values()
+            .flat_map(|service| service.dns.iter().flatten())
+            .copied()
+            .collect()
+    }
+
+    /// Names of the services which set their own DNS servers, sorted
+    pub fn services_with_custom_dns(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .services
+            .iter()
+            .filter(|(_, service)| service.dns.as_ref().is_some_and(|dns| !dns.is_empty()))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// True when a service is attached only to internal networks, so can't reach the internet
+    fn is_internal_only(&self, service: &Service) -> bool {
+        let networks = service.network_names();
+        !networks.is_empty()
+            && networks.iter().all(|name| {
+                self.networks
+                    .as_ref()
+                    .and_then(|n| n.get(*name))
+                    .is_some_and(|network| network.internal == Some(true))
+            })
+    }
+
+    /// The number of containers the services run, counting one for each service without a
+    /// replica count
+    pub fn total_container_count(&self) -> u32 {
+        self.services.values().map(|service| service.replicas().unwrap_or(1)).sum()
+    }
+
+    /// The number of mounts of each type across all services. Anonymous volumes count
+    /// as named, since the engine manages them the same way.
+    pub fn count_volumes_by_type(&self) -> HashMap<VolumeType, usize> {
+        let mut counts = HashMap::new();
+        for service in self.services.values() {
+            for volume in service.volumes.iter().flatten() {
+                let volume_type = match parse_volume_spec(volume).map(|spec| spec.source) {
+                    Ok(VolumeSource::Bind(_)) => VolumeType::Bind,
+                    Ok(VolumeSource::Named(_) | VolumeSource::Anonymous) => VolumeType::Named,
+                    Err(_) => VolumeType::Unknown,
+                };
+                *counts.entry(volume_type).or_insert(0) += 1;
+            }
+            let tmpfs = service.tmpfs.iter().flatten().count();
+            if tmpfs > 0 {
+                *counts.entry(VolumeType::Tmpfs).or_insert(0) += tmpfs;
+            }
+        }
+        counts
+    }
+
+    /// The number of mounts across all services, of every type
+    pub fn total_volume_count(&self) -> usize {
+        self.count_volumes_by_type().values().sum()
+    }
+
+    /// All profile names declared by any service
+    pub fn profiles_used(&self) -> HashSet<&str> {
+        self.services
+            .values()
+            .flat_map(|service| service.profiles.iter().flatten())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Names of the services enabled by a profile, sorted
+    pub fn services_for_profile<'a>(&'a self, profile: &str) -> Vec<&'a str> {
+        let mut names: Vec<&str> = self
+            .services
+            .iter()
+            .filter(|(_, service)| service.profiles.iter().flatten().any(|p| p == profile))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Names of the services whose labels `selector` matches, sorted
+    pub fn services_matching(&self, selector: &LabelSelector) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .services
+            .iter()
+            .filter(|(_, service)| selector.matches(service.labels.as_ref().unwrap_or(&HashMap::new())))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Keep only the services whose labels `selector` matches, for a model of just that
+    /// slice of the stack. Dependencies on the services left out are dropped with them, as
+    /// the model has nothing for them to refer to.
+    pub fn retain_services_matching(&mut self, selector: &LabelSelector) {
+        let selected: HashSet<String> = self.services_matching(selector).into_iter().map(str::to_string).collect();
+        self.retain_services(&selected);
+    }
+
+    /// Keep only the services named in `keep`, dropping dependencies on the others
+    fn retain_services(&mut self, keep: &HashSet<String>) {
+        self.services.retain(|name, _| keep.contains(name));
+        for service in self.services.values_mut() {
+            match &mut service.depends_on {
+                Some(DependsOn::List(dependencies)) => dependencies.retain(|dependency| keep.contains(dependency)),
+                Some(DependsOn::Map(dependencies)) => dependencies.retain(|dependency, _| keep.contains(dependency)),
+                None => (),
+            }
+        }
+    }
+
+    /// The services compose starts with the profiles `active`: those with no profiles or
+    /// an active one, and, transitively, the services they require, whatever their
+    /// profiles. With `auto_activate` off, a required dependency outside the active
+    /// profiles is an error instead. Dependencies on unknown services are ignored.
+    pub fn activate_profiles(&self, active: &[String], auto_activate: bool) -> Result<ProfileActivation> {
+        let mut names: Vec<&String> = self.services.keys().collect();
+        names.sort();
+        let mut started: BTreeSet<&str> = names.iter().filter(|name| self.services[**name].is_active(active)).map(|name| name.as_str()).collect();
+        let mut pending: Vec<&str> = started.iter().copied().collect();
+        let mut activation = ProfileActivation::default();
+        let profiles = |name: &str| self.services[name].profiles.clone().unwrap_or_default();
+        while let Some(name) = pending.pop() {
+            let service = &self.services[name];
+            let mut dependencies = service.depends_on_names();
+            dependencies.sort();
+            for dependency in dependencies.into_iter().filter(|dependency| self.services.contains_key(*dependency)) {
+                if started.contains(dependency) || service.depends_on_required(dependency) == Some(false) {
+                    continue;
+                }
+                if !auto_activate {
+                    return Err(anyhow!(
+                        "Service '{}' requires '{}', which is only in the inactive profiles {}",
+                        name, dependency, profiles(dependency).join(", ")
+                    ));
+                }
+                activation.auto_activated.push(AutoActivation { service: dependency.to_string(), profiles: profiles(dependency), required_by: name.to_string() });
+                started.insert(dependency);
+                pending.push(dependency);
+            }
+        }
+        for name in &started {
+            for dependency in self.services[*name].depends_on_names() {
+                if self.services.contains_key(dependency) && !started.contains(dependency) {
+                    activation.skipped.push(SkippedDependency { service: name.to_string(), dependency: dependency.to_string(), profiles: profiles(dependency) });
+                }
+            }
+        }
+        activation.services = started.into_iter().map(str::to_string).collect();
+        Ok(activation)
+    }
+
+    /// Keep only the services compose starts with the profiles `active`, as found by
+    /// `activate_profiles`, dropping dependencies on the services left out
+    pub fn retain_active_services(&mut self, active: &[String], auto_activate: bool) -> Result<ProfileActivation> {
+        let activation = self.activate_profiles(active, auto_activate)?;
+        let started: HashSet<String> = activation.services.iter().cloned().collect();
+        self.retain_services(&started);
+        Ok(activation)
+    }
+
+    /// Names of the services with no profiles, which are always active, sorted
+    pub fn always_active_services(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .services
+            .iter()
+            .filter(|(_, service)| service.profiles.as_ref().is_none_or(Vec::is_empty))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// The dependencies of each service on other services in the file, by service name
+    fn known_dependencies(&self) -> HashMap<&str, HashSet<&str>> {
+        self.services
+            .iter()
+            .map(|(name, service)| {
+                let known = service.depends_on_names().into_iter().filter(|d| self.services.contains_key(*d)).collect();
+                (name.as_str(), known)
+            })
+            .collect()
+    }
+
+    /// Group the services into waves which can be started together: the first wave depends
+    /// on nothing, and every later wave only on the waves before it. Services are sorted by
+    /// name within a wave. Fails on a dependency cycle, naming the services round it.
+    pub fn start_waves(&self) -> Result<Vec<StartWave>> {
+        let mut pending = self.known_dependencies();
+        let mut waves = Vec::new();
+        while !pending.is_empty() {
+            let mut ready: Vec<&str> = pending
+                .iter()
+                .filter(|(_, dependencies)| dependencies.is_empty())
+                .map(|(name, _)| *name)
+                .collect();
+            if ready.is_empty() {
+                return Err(anyhow!("Dependency cycle: {}", find_cycle(&pending).join(" -> ")));
+            }
+            ready.sort();
+            for name in &ready {
+                pending.remove(name);
+            }
+            for dependencies in pending.values_mut() {
+                dependencies.retain(|dependency| !ready.contains(dependency));
+            }
+            let services = ready
+                .into_iter()
+                .map(|name| WaveService { name: name.to_string(), healthcheck: self.services[name].healthcheck.is_some() })
+                .collect();
+            waves.push(StartWave { wave: waves.len() + 1, services });
+        }
+        Ok(waves)
+    }
+
+    /// Order the services so that every service comes after the services it depends on,
+    /// breaking ties alphabetically. Dependencies on unknown services are ignored.
+    pub fn dependency_order(&self) -> Result<Vec<&str>> {
+        let mut pending = self.known_dependencies();
+        let mut order = Vec::new();
+        while !pending.is_empty() {
+            let mut ready: Vec<&str> = pending
+                .iter()
+                .filter(|(_, dependencies)| dependencies.is_empty())
+                .map(|(name, _)| *name)
+                .collect();
+            if ready.is_empty() {
+                let mut cycle: Vec<&str> = pending.keys().copied().collect();
+                cycle.sort();
+                return Err(anyhow!("Dependency cycle involving services: {}", cycle.join(", ")));
+            }
+            ready.sort();
+            let next = ready[0];
+            pending.remove(next);
+            for dependencies in pending.values_mut() {
+                dependencies.remove(next);
+            }
+            order.push(next);
+        }
+        Ok(order)
+    }
+
+    /// A best-effort estimate of the least time each service waits before it's started, by
+    /// service name: the sum of the readiness times of the healthchecks of every service it
+    /// depends on, directly or not. Services without a healthcheck add nothing. Fails on a
+    /// dependency cycle or a healthcheck duration which can't be parsed.
+    pub fn get_service_startup_wait_time_estimate(&self) -> Result<HashMap<&str, Duration>, CargotectureError> {
+        let order = self.dependency_order().map_err(|err| CargotectureError::Validation(err.to_string()))?;
+        let direct = self.known_dependencies();
+        // Every service a service depends on, built up in dependency order
+        let mut transitive: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for name in &order {
+            let mut all = HashSet::new();
+            for dependency in &direct[name] {
+                all.insert(*dependency);
+                all.extend(transitive[dependency].iter().copied());
+            }
+            transitive.insert(name, all);
+        }
+        let mut readiness = HashMap::new();
+        for name in &order {
+            let time = match &self.services[*name].healthcheck {
+                Some(healthcheck) => healthcheck.readiness_time()?,
+                None => Duration::ZERO,
+            };
+            readiness.insert(*name, time);
+        }
+        Ok(transitive
+            .into_iter()
+            .map(|(name, dependencies)| (name, dependencies.iter().map(|dependency| readiness[dependency]).sum()))
+            .collect())
+    }
+
+    /// The names of the services using each restart policy, sorted, keyed by policy:
+    /// `always`, `on-failure`, `unless-stopped` or `no`, and `unset` for services without
+    /// one. The deprecated `on-failure:N` counts as `on-failure`.
+    pub fn get_services_by_restart_policy(&self) -> HashMap<&str, Vec<&str>> {
+        let mut policies: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (name, service) in &self.services {
+            let policy = match service.restart.as_deref() {
+                Some(restart) => restart.split(':').next().unwrap_or(restart),
+                None => "unset",
+            };
+            policies.entry(policy).or_default().push(name);
+        }
+        policies.values_mut().for_each(|names| names.sort());
+        policies
+    }
+
+    /// The services restarted whenever they stop, sorted by name
+    pub fn always_restart_services(&self) -> Vec<&str> {
+        self.get_services_by_restart_policy().remove("always").unwrap_or_default()
+    }
+
+    /// The services never restarted, as those without a restart policy aren't, sorted by name
+    pub fn never_restart_services(&self) -> Vec<&str> {
+        let mut policies = self.get_services_by_restart_policy();
+        let mut names = policies.remove("no").unwrap_or_default();
+        names.extend(policies.remove("unset").unwrap_or_default());
+        names.sort();
+        names
+    }
+
+    /// Check the devices a service requests, and that the forms it requests them in agree
+    fn validate_device_requests(&self, name: &str, service: &Service, report: &mut ValidationReport) {
+        if let Some(Gpus::All(all)) = &service.gpus {
+            if all != "all" {
+                report.error_at(Some(name), format!("services.{}.gpus", name), "invalid-device-request", format!(
+                    "Invalid gpus value '{}' for service '{}': must be 'all' or a list of devices",
+                    all, name
+                ));
+            }
+        }
+        let requests = service.device_requests();
+        for request in &requests {
+            let field = format!("services.{}.{}", name, request.source);
+            match &request.count {
+                Some(DeviceCount::Count(count)) if *count <= 0 => report.error_at(Some(name), field.clone(), "invalid-device-request", format!(
+                    "Invalid device count {} in {} of service '{}': must be a positive integer or 'all'",
+                    count, request.source, name
+                )),
+                Some(DeviceCount::All(all)) if all != "all" => report.error_at(Some(name), field.clone(), "invalid-device-request", format!(
+                    "Invalid device count '{}' in {} of service '{}': must be a positive integer or 'all'",
+                    all, request.source, name
+                )),
+                _ => (),
+            }
+            for capability in request.capabilities.iter().filter(|c| !DEVICE_CAPABILITIES.contains(&c.as_str())) {
+                report.warning_at(Some(name), field.clone(), "unknown-device-capability", format!(
+                    "Service '{}' requests unknown device capability '{}' in {}",
+                    name, capability, request.source
+                ));
+            }
+        }
+        // Docker applies every form, so ones which disagree are likely a half-finished migration
+        let mut sources: Vec<DeviceRequestSource> = requests.iter().map(|r| r.source).collect();
+        sources.dedup();
+        let requested = |r: &DeviceRequest| {
+            let count = match &r.count {
+                Some(DeviceCount::Count(count)) if !r.all_devices() => Some(*count),
+                _ => None,
+            };
+            (r.driver.clone().unwrap_or_default(), r.all_devices(), count)
+        };
+        let disagree = requests.iter().any(|r| requested(r) != requested(&requests[0]));
+        if sources.len() > 1 && disagree {
+            let sources: Vec<String> = sources.iter().map(ToString::to_string).collect();
+            report.warning(Some(name), "device-request-conflict", format!(
+                "Service '{}' requests devices with {}, which disagree",
+                name, sources.join(" and ")
+            ));
+        }
+    }
+
+    /// The names of the volumes declared under the top-level `volumes`, or None when the
+    /// document isn't known, as for a model deserialized directly
+    fn declared_volumes(&self) -> Option<Vec<&str>> {
+        let volumes = self.document.as_mapping()?.get("volumes").and_then(serde_yaml::Value::as_mapping);
+        Some(volumes.into_iter().flat_map(|volumes| volumes.keys()).filter_map(serde_yaml::Value::as_str).collect())
+    }
+
+    /// Check the volumes a service mounts: that each parses, is mounted at an absolute path
+    /// and doesn't overlap another, that named volumes are declared, and that bind mounts of
+    /// files target files
+    fn validate_volumes(&self, name: &str, service: &Service, report: &mut ValidationReport) {
+        let declared = self.declared_volumes();
+        let field = format!("services.{}.volumes", name);
+        let mut mounts: Vec<(&str, VolumeSpec)> = Vec::new();
+        for volume in service.volumes.iter().flatten() {
+            match parse_volume_spec(volume) {
+                Ok(spec) if !is_absolute_container_path(&spec.target, service.is_windows()) => report.error_at(Some(name), field.clone(), "invalid-volume", format!(
+                    "Invalid volume '{}' in service '{}': the target '{}' is not an absolute path in a Linux container",
+                    volume, name, spec.target
+                )),
+                Ok(spec) => {
+                    if spec.home_relative {
+                        report.warning_at(Some(name), field.clone(), "home-relative-volume", format!(
+                            "Service '{}' mounts '{}', whose host path depends on the home directory of whoever runs compose",
+                            name, volume
+                        ));
+                    }
+                    if let (VolumeSource::Named(volume_name), Some(declared)) = (&spec.source, &declared) {
+                        if !declared.contains(&volume_name.as_str()) {
+                            report.error_at(Some(name), field.clone(), "unknown-volume", format!(
+                                "Referenced volume '{}' not found for service '{}'{}",
+                                volume_name, name, did_you_mean(&suggestions(volume_name, declared.iter().copied()))
+                            ));
+                        }
+                    }
+                    if matches!(&spec.source, VolumeSource::Bind(source) if looks_like_file(source)) && spec.directory_target {
+                        report.note_at(Some(name), field.clone(), "file-on-directory-mount", format!(
+                            "Service '{}' mounts '{}', whose source looks like a file but whose target looks like a directory",
+                            name, volume
+                        ));
+                    }
+                    mounts.push((volume, spec));
+                }
+                Err(err) => report.error_at(Some(name), field.clone(), "invalid-volume", format!("{} in service '{}'", err, name)),
+            }
+        }
+        let targets: Vec<&str> = mounts.iter().map(|(_, spec)| spec.target.as_str()).collect();
+        for (first, second, overlap) in overlapping_mounts(&targets) {
+            let ((outer, _), (inner, spec)) = (&mounts[first], &mounts[second]);
+            match overlap {
+                MountOverlap::Duplicate => report.error_at(Some(name), field.clone(), "duplicate-volume-target", format!(
+                    "Service '{}' mounts both '{}' and '{}' at {}",
+                    name, outer, inner, spec.target
+                )),
+                MountOverlap::Nested => report.warning_at(Some(name), field.clone(), "nested-volume-target", format!(
+                    "Service '{}' mounts '{}' inside '{}', so what's visible at {} depends on the order they're mounted",
+                    name, inner, outer, spec.target
+                )),
+            }
+        }
+    }
+
+    pub fn validate(&self) -> ValidationReport {
+        self.validate_with_containers(&HashMap::new())
+    }
+
+    /// Check that the image of each service exists in its registry, warning about those that
+    /// don't and those that can't be checked, including malformed image references. Each
+    /// registry request may take up to `timeout` to connect and respond.
+    pub fn validate_image_availability(&self, timeout: Duration) -> Vec<ValidationWarning> {
+        let mut report = ValidationReport::default();
+        let mut statuses: HashMap<&str, Result<u16, String>> = HashMap::new();
+        for (name, service) in self.services() {
+            let Some(image) = service.image.as_deref() else { continue };
+            let field = format!("services.{}.image", name);
+            let Some(reference) = ImageReference::parse(image) else {
+                report.warning_at(Some(name), field.clone(), "image-unchecked", format!(
+                    "Can't check image '{}' of service '{}': it isn't a valid image reference",
+                    image, name
+                ));
+                continue;
+            };
+            let status = statuses
+                .entry(image)
+                .or_insert_with(|| manifest_status(&reference, timeout).map_err(|err| err.to_string()));
+            match status {
+                Ok(200..=299) => {}
+                Ok(404) => report.warning_at(Some(name), field.clone(), "image-not-found", format!(
+                    "Image '{}' of service '{}' was not found in registry '{}'",
+                    image, name, reference.registry
+                )),
+                Ok(401 | 403) => report.warning_at(Some(name), field.clone(), "image-unchecked", format!(
+                    "Can't check image '{}' of service '{}': registry '{}' refused access, so it's private or doesn't exist",
+                    image, name, reference.registry
+                )),
+                Ok(status) => report.warning_at(Some(name), field.clone(), "image-unchecked", format!(
+                    "Can't check image '{}' of service '{}': registry '{}' answered with HTTP status {}",
+                    image, name, reference.registry, status
+                )),
+                Err(err) => report.warning_at(Some(name), field.clone(), "image-unchecked", format!(
+                    "Can't check image '{}' of service '{}': {}",
+                    image, name, err
+                )),
+            }
+        }
+        report.warnings
+    }
+
+    /// Check that no two networks have overlapping IPv4 subnets, which conflict when both
+    /// are created on the same host. Each overlapping pair is an error naming both networks.
+    pub fn validate_subnet_overlap(&self) -> Vec<ValidationError> {
+        let mut subnets: Vec<(&str, CidrV4)> = self
+            .networks
+            .iter()
+            .flatten()
+            .flat_map(|(name, network)| {
+                let configs = network.ipam.iter().flat_map(|ipam| ipam.config.iter().flatten());
+                configs.filter_map(move |config| match config.subnet {
+                    IpNetwork::V4(cidr) => Some((name.as_str(), cidr)),
+                    IpNetwork::V6(_) => None,
+                })
+            })
+            .collect();
+        subnets.sort_by_key(|(name, cidr)| (*name, u32::from(cidr.address), cidr.prefix_len));
+        let mut errors = Vec::new();
+        for (index, (first, a)) in subnets.iter().enumerate() {
+            for (second, b) in subnets[index + 1..].iter().filter(|(second, _)| second != first) {
+                if cidrs_overlap(*a, *b) {
+                    errors.push(ValidationError {
+                        service: None,
+                        code: "subnet-overlap",
+                        message: format!("Networks '{}' and '{}' have overlapping subnets {} and {}", first, second, a, b),
+                        field: Some(format!("networks.{}.ipam.config", second)),
+                        locations: Vec::new(),
+                    });
+                }
+            }
+        }
+        errors
+    }
+
+    /// Validate, also cross-checking services against the containers built for them, keyed
+    /// by service name
+    pub fn validate_with_containers(&self, containers: &HashMap<String, ParsedContainer>) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let networks = &self.networks;
+        let t=&HashMap::new();
+        let network_names: HashSet<&String> = networks.as_ref().unwrap_or(t).keys().collect();
+
+        for field in &self.ignored_fields {
+            let service = field.strip_prefix("services.").and_then(|path| path.split_once('.')).map(|(service, _)| service);
+            report.warning_at(service, field.clone(), "unknown-field", format!("Unknown field '{}' was ignored", field));
+        }
+
+        // The first service, in name order, to claim each hostname
+        let mut hostnames: HashMap<&str, &str> = HashMap::new();
+
+        if self.services.is_empty() {
+            report.error(None, "no-services", "No services defined".to_string());
+        }
+
+        for (name, service) in self.services() {
+            let field = |path: &str| format!("services.{}.{}", name, path);
+            // A service written as `web:` with nothing under it is likely unfinished
+            if service.is_unconfigured() {
+                report.warning(Some(name), "unconfigured-service", format!("Service '{}' is defined with no configuration", name));
+            }
+            // Checked after `extends` is resolved, so an image or build from the service extended counts
+            if service.image.is_none() && service.build.is_none() {
+                report.error(Some(name), "no-image", format!("Service '{}' has neither an image nor a build, so compose can't create its container", name));
+            }
+
+            // Hostnames must be valid and unique, or name resolution on the network is ambiguous
+            if let Some(hostname) = &service.hostname {
+                for label in hostname.split('.') {
+                    if let Err(reason) = validate_dns_label(label) {
+                        report.error_at(Some(name), field("hostname"), "invalid-hostname", format!(
+                            "Invalid hostname '{}' for service '{}': {}",
+                            hostname, name, reason
+                        ));
+                    }
+                }
+                match hostnames.get(hostname.as_str()) {
+                    Some(other) => report.error_at(Some(name), field("hostname"), "duplicate-hostname", format!(
+                        "Services '{}' and '{}' both use hostname '{}'",
+                        other, name, hostname
+                    )),
+                    None => {
+                        hostnames.insert(hostname, name);
+                    }
+                }
+            }
+
+            // Validate restart values
+            if let Some(ref restart) = service.restart {
+                // `on-failure:N` is deprecated, so warned about when parsing, but still valid
+                let max_attempts = restart.strip_prefix("on-failure:").map(str::parse::<u32>);
+                if !["no", "always", "on-failure", "unless-stopped"]
+                    .contains(&restart.as_str())
+                    && !matches!(max_attempts, Some(Ok(_)))
+                {
+                    report.error_at(Some(name), field("restart"), "invalid-restart", format!(
+                        "Invalid restart value '{}' for service '{}'",
+                        restart, name
+                    ));
+                }
+                // A policy written as a boolean was taken as the one meant when parsing
+                if let serde_yaml::Value::Bool(written) = self.document["services"][name]["restart"] {
+                    let advice = match written {
+                        false => "quote it, as in restart: \"no\", since YAML 1.1 reads an unquoted no as false",
+                        true => "name the policy, as in restart: always, since yes, on and true aren't restart policies",
+                    };
+                    report.warning_at(Some(name), field("restart"), "unquoted-restart", format!(
+                        "Service '{}' sets restart to the boolean {}, taken as '{}'; {}",
+                        name, written, restart, advice
+                    ));
+                }
+            }
+
+            for rule in service.develop.iter().flat_map(|develop| develop.watch.iter().flatten()) {
+                if !WATCH_ACTIONS.contains(&rule.action.as_str()) {
+                    report.error_at(Some(name), field("develop.watch"), "invalid-watch", format!(
+                        "Invalid develop.watch action '{}' for service '{}'",
+                        rule.action, name
+                    ));
+                } else if rule.action.starts_with("sync") && rule.target.is_none() {
+                    report.error_at(Some(name), field("develop.watch"), "invalid-watch", format!(
+                        "develop.watch rule for '{}' of service '{}' needs a target to {}",
+                        rule.path, name, rule.action
+                    ));
+                }
+            }
+
+            if let Some(stop_signal) = &service.stop_signal {
+                if let Err(CargotectureError::InvalidSignal { reason, .. }) = parse_signal(stop_signal) {
+                    report.error_at(Some(name), field("stop_signal"), "invalid-stop-signal", format!(
+                        "Invalid stop_signal value '{}' for service '{}': {}",
+                        stop_signal, name, reason
+                    ));
+                }
+            }
+
+            self.validate_volumes(name, service, &mut report);
+
+            // Validate healthcheck values, which Docker rejects at runtime with obscure errors
+            if let Some(healthcheck) = &service.healthcheck {
+                if healthcheck.test.is_empty() {
+                    report.error_at(Some(name), field("healthcheck.test"), "invalid-healthcheck", format!("Invalid healthcheck.test for service '{}': must not be empty", name));
+                }
+                if let Some(retries) = healthcheck.retries.filter(|r| *r <= 0) {
+                    report.error_at(Some(name), field("healthcheck.retries"), "invalid-healthcheck", format!(
+                        "Invalid healthcheck.retries value {} for service '{}': must be a positive integer",
+                        retries, name
+                    ));
+                }
+                // Docker's minimum interval and timeout is 1s, while a start period can be 0
+                let durations = [
+                    ("interval", &healthcheck.interval, Duration::from_secs(1)),
+                    ("timeout", &healthcheck.timeout, Duration::from_secs(1)),
+                    ("start_period", &healthcheck.start_period, Duration::ZERO),
+                ];
+                for (key, value, minimum) in durations {
+                    let Some(value) = value else {
+                        continue;
+                    };
+                    match parse_duration(value) {
+                        None => report.error_at(Some(name), field(&format!("healthcheck.{}", key)), "invalid-healthcheck", format!(
+                            "Invalid healthcheck.{} value '{}' for service '{}': must be a duration such as '30s'",
+                            key, value, name
+                        )),
+                        Some(duration) if duration < minimum => report.error_at(Some(name), field(&format!("healthcheck.{}", key)), "invalid-healthcheck", format!(
+                            "Invalid healthcheck.{} value '{}' for service '{}': Docker requires at least {}s",
+                            key, value, name, minimum.as_secs()
+                        )),
+                        Some(_) => (),
+                    }
+                }
+            }
+
+            self.validate_device_requests(name, service, &mut report);
+
+            if service.scale == Some(0) {
+                report.error_at(Some(name), field("scale"), "invalid-scale", format!("Invalid scale value 0 for service '{}': must be at least 1", name));
+            }
+            if let (Some(scale), Some(replicas)) = (service.scale, service.deploy.as_ref().and_then(|d| d.replicas)) {
+                report.warning_at(Some(name), field("scale"), "scale-and-replicas", format!(
+                    "Service '{}' sets both scale ({}) and deploy.replicas ({}); deploy.replicas takes precedence in Swarm mode",
+                    name, scale, replicas
+                ));
+            }
+
+            // A service can only reach a dependency over a network they're both attached to.
+            // Those with a network_mode, such as none or host, aren't attached to networks.
+            if service.network_mode.is_none() {
+                let networks = service.attached_networks();
+                let mut dependencies = service.depends_on_names();
+                dependencies.sort();
+                for dependency in dependencies {
+                    let Some(other) = self.services.get(dependency).filter(|other| other.network_mode.is_none()) else {
+                        continue;
+                    };
+                    if !other.attached_networks().iter().any(|network| networks.contains(network)) {
+                        report.warning_at(Some(name), field("networks"), "no-network", format!(
+                            "Service '{}' depends on '{}' but shares no network with it, so can't reach it",
+                            name, dependency
+                        ));
+                    }
+                }
+            }
+
+            // Only one replica could be attached to, so interactive flags are a mistake
+            let interactive = service.interactive_flags();
+            if let Some(replicas) = service.replicas().filter(|r| *r > 1 && !interactive.is_empty()) {
+                let replicas_field = match service.deploy.as_ref().and_then(|d| d.replicas) {
+                    Some(_) => "deploy.replicas",
+                    None => "scale",
+                };
+                report.warning_at(Some(name), field(replicas_field), "interactive-replicas", format!(
+                    "Service '{}' sets {} but has {} replicas; interactive flags on scaled services are almost always a mistake",
+                    name, interactive.join(" and "), replicas
+                ));
+            }
+
+            // Port ranges are valid, but aren't modelled
+            let port_specs = service.ports.iter().flatten().map(|spec| ("ports", spec));
+            for (key, spec) in port_specs.chain(service.expose.iter().flatten().map(|spec| ("expose", spec))) {
+                match parse_port_mapping(spec) {
+                    Err(CargotectureError::InvalidPort { reason, .. }) if !spec.contains('-') => report.error_at(Some(name), field(key), "invalid-port", format!(
+                        "Invalid {} entry '{}' for service '{}': {}",
+                        key, spec, name, reason
+                    )),
+                    _ => (),
+                }
+            }
+
+            // A fixed host port can only be bound by one replica
+            if let Some(replicas) = service.replicas().filter(|r| *r > 1) {
+                for spec in service.ports.iter().flatten() {
+                    if let Ok(PortBinding { host_port: Some(host_port), .. }) = parse_port_mapping(spec) {
+                        report.warning_at(Some(name), field("ports"), "replica-port-conflict", format!(
+                            "Service '{}' has {} replicas but publishes fixed host port {}, which would conflict at runtime",
+                            name, replicas, host_port
+                        ));
+                    }
+                }
+            }
+
+            // A published port that nothing exposes is often a typo, e.g. 8000 for 8080
+            let container = containers.get(name);
+            if service.expose.is_some() || container.is_some() {
+                let mut exposed: Vec<(u16, Protocol)> = service
+                    .expose
+                    .iter()
+                    .flatten()
+                    .filter_map(|spec| parse_port_mapping(spec).ok())
+                    .map(|binding| (binding.container_port, binding.protocol))
+                    .collect();
+                exposed.extend(container.iter().flat_map(|c| &c.exposed_ports).map(|p| (p.port_number, p.protocol)));
+                for port in service.port_infos().iter().filter(|p| p.published) {
+                    if !exposed.contains(&(port.container_port, port.protocol)) {
+                        let mut listed: Vec<String> = exposed.iter().map(|(number, _)| number.to_string()).collect();
+                        listed.dedup();
+                        report.warning_at(Some(name), field("ports"), "unexposed-port", format!(
+                            "Service '{}' publishes port {} which is neither EXPOSEd by its Dockerfile nor listed in expose ({}); check for a typo",
+                            name, port.container_port, listed.join(", ")
+                        ));
+                    }
+                }
+            }
+
+            // A public resolver can't be reached from internal networks
+            if self.is_internal_only(service) {
+                for server in service.dns.iter().flatten() {
+                    if let IpAddr::V4(ip) = server.ip() {
+                        if !is_private_ipv4(&ip) {
+                            report.warning_at(Some(name), field("dns"), "public-dns", format!(
+                                "Service '{}' uses public DNS server {} but is only attached to internal networks",
+                                name, ip
+                            ));
+                        }
+                    }
+                }
+            }
+
+            // Validate referenced networks
+            // The implicit default network needn't be declared
+            for network in service.network_names() {
+                if network != "default" && !network_names.contains(&network.to_string()) {
+                    let similar = suggestions(network, network_names.iter().map(|network| network.as_str()));
+                    report.error_at(Some(name), field("networks"), "unknown-network", format!(
+                        "Referenced network '{}' not found for service '{}'{}",
+                        network, name, did_you_mean(&similar)
+                    ));
+                }
+            }
+
+            // Validate depends_on services
+            for dependency in service.depends_on_names() {
+                if !self.services.contains_key(dependency) {
+                    let similar = suggestions(dependency, self.services.keys().map(String::as_str));
+                    report.error_at(Some(name), field("depends_on"), "unknown-dependency", format!(
+                        "Referenced service '{}' in depends_on not found for service '{}'{}",
+                        dependency, name, did_you_mean(&similar)
+                    ));
+                }
+            }
+
+            // A service_healthy condition can never be met if the target has no healthcheck
+            for dependency in service.depends_on_names() {
+                let unhealthy = self.service(dependency).is_some_and(|target| target.healthcheck.is_none());
+                if service.depends_on_condition(dependency) == Some("service_healthy") && unhealthy {
+                    report.warning_at(Some(name), field(&format!("depends_on.{}.condition", dependency)), "unhealthy-dependency", format!(
+                        "Service '{}' depends on '{}' being healthy, but '{}' defines no healthcheck",
+                        name, dependency, dependency
+                    ));
+                }
+            }
+        }
+
+        if let Err(err) = self.dependency_order() {
+            report.error(None, "dependency-cycle", err.to_string());
+        }
+        report.errors.extend(self.validate_subnet_overlap());
+
+        // Docker DNS resolves a name claimed by several services on a network to any of them
+        for (network, names) in self.name_table() {
+            for (alias, services) in names.iter().filter(|(_, services)| services.len() > 1) {
+                let services: Vec<String> = services.iter().map(|service| format!("'{}'", service)).collect();
+                report.warning(None, "ambiguous-name", format!(
+                    "Name '{}' on network '{}' is claimed by services {}",
+                    alias, network, services.join(" and ")
+                ));
+            }
+        }
+
+        self.locate_diagnostics(&mut report);
+        report
+    }
+
+    /// Set where each problem in a report was found in the files merged, from the path to
+    /// the value at fault. A problem with a service as a whole is located where the service
+    /// was defined, rather than at every file which sets some field of it.
+    pub fn locate_diagnostics(&self, report: &mut ValidationReport) {
+        report.locate(|service, field| match (service, field) {
+            (_, Some(field)) => self.sources.locate_value(field),
+            (Some(service), None) => self.sources.locate(&format!("services.{}", service)).into_iter().take(1).collect(),
+            (None, None) => Vec::new(),
+        });
+    }
+}
+
+/// Find a cycle in dependencies where every service has at least one, starting from the
+/// first service by name and ending where the cycle closes, e.g. `[a, b, a]`
+fn find_cycle<'a>(dependencies: &HashMap<&'a str, HashSet<&'a str>>) -> Vec<&'a str> {
+    let Some(mut current) = dependencies.keys().min().copied() else {
+        return Vec::new();
+    };
+    let mut path: Vec<&str> = Vec::new();
+    while !path.contains(&current) {
+        path.push(current);
+        current = dependencies[current].iter().min().copied().unwrap_or(current);
+    }
+    let start = path.iter().position(|name| *name == current).unwrap_or(0);
+    let mut cycle = path.split_off(start);
+    cycle.push(current);
+    cycle
+}
+
+/// Apply the compose-level overrides of a service (user, working_dir, entrypoint, command and labels)
+/// to the container built from its image, giving the runtime-effective configuration
+pub fn merge_container_with_service(mut container: ParsedContainer, service: &Service) -> ParsedContainer {
+    if let Some(user) = &service.user {
+        container.user = Some(user.clone());
+    }
+    if let Some(working_dir) = &service.working_dir {
+        container.working_dir = Some(working_dir.clone());
+    }
+    if let Some(entrypoint) = &service.entrypoint {
+        container.entrypoint = Some(entrypoint.clone());
+    }
+    if let Some(command) = &service.command {
+        container.cmd = Some(command.clone());
+    }
+    if let Some(labels) = &service.labels {
+        container.merge_labels(labels);
+    }
+    container
+}
+
+/// File names looked for in the working directory when no compose file is given,
+/// in the order defined by the compose specification
+pub const DEFAULT_COMPOSE_FILES: [&str; 4] = [
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+/// Where the compose files to read were found
+#[derive(Debug, PartialEq)]
+pub enum ComposeFileSource {
+    Explicit,
+    Environment,
+    Discovered,
+    Stdin,
+}
+
+/// Keys whose sequence values replace, rather than extend, those of an earlier file
+const REPLACED_SEQUENCE_KEYS: [&str; 4] = ["command", "entrypoint", "test", "dns"];
+
+/// Merge a later compose document into an earlier one: mappings merge recursively,
+/// most sequences are appended without duplicates, and everything else is replaced
+pub fn merge_compose_values(base: &mut serde_yaml::Value, overlay: serde_yaml::Value) {
+    merge_value(base, overlay, None)
+}
+
+fn merge_value(base: &mut serde_yaml::Value, overlay: serde_yaml::Value, key: Option<&str>) {
+    use serde_yaml::Value;
+    match (base, overlay) {
+        (Value::Mapping(base_map), Value::Mapping(overlay_map)) => {
+            for (k, v) in overlay_map {
+                match base_map.get_mut(&k) {
+                    Some(existing) => merge_value(existing, v, k.as_str()),
+                    None => {
+                        base_map.insert(k, v);
+                    }
+                }
+            }
+        }
+        (Value::Sequence(base_seq), Value::Sequence(overlay_seq))
+            if !key.is_some_and(|k| REPLACED_SEQUENCE_KEYS.contains(&k)) =>
+        {
+            for item in overlay_seq {
+                if !base_seq.contains(&item) {
+                    base_seq.push(item);
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// The compose files named by `COMPOSE_FILE`, split on `COMPOSE_PATH_SEPARATOR`
+/// (by default `:`, or `;` on Windows)
+pub fn compose_files_from_env() -> Vec<String> {
+    let default_separator = if cfg!(windows) { ";" } else { ":" };
+    let separator = env::var("COMPOSE_PATH_SEPARATOR").unwrap_or_else(|_| default_separator.to_string());
+    match env::var("COMPOSE_FILE") {
+        Ok(files) if !separator.is_empty() => files
+            .split(separator.as_str())
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Ok(file) => vec![file],
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Find the default compose file in `dir`, followed by its `.override` file if present
+pub fn discover_compose_files(dir: &Path) -> Vec<String> {
+    let Some(name) = DEFAULT_COMPOSE_FILES.iter().find(|name| dir.join(name).is_file()) else {
+        return Vec::new();
+    };
+    let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+    let override_file = dir.join(format!("{}.override.{}", stem, extension));
+    let mut files = vec![dir.join(name).to_string_lossy().into_owned()];
+    if override_file.is_file() {
+        files.push(override_file.to_string_lossy().into_owned());
+    }
+    files
+}
+
+/// Choose the compose files to read. Explicit files take precedence, then (with
+/// `use_env`) the `COMPOSE_FILE` variable, then the default files in `dir`. Defaults
+/// are only discovered with `use_env` or when stdin is a terminal, so a piped compose
+/// file is always read.
+pub fn resolve_compose_files(explicit: &[String], use_env: bool, stdin_is_tty: bool, dir: &Path) -> (Vec<String>, ComposeFileSource) {
+    if !explicit.is_empty() {
+        return (explicit.to_vec(), ComposeFileSource::Explicit);
+    }
+    if use_env {
+        let from_env = compose_files_from_env();
+        if !from_env.is_empty() {
+            return (from_env, ComposeFileSource::Environment);
+        }
+    }
+    if use_env || stdin_is_tty {
+        let discovered = discover_compose_files(dir);
+        if !discovered.is_empty() {
+            return (discovered, ComposeFileSource::Discovered);
+        }
+    }
+    (Vec::new(), ComposeFileSource::Stdin)
+}
+
+/// The default limit on how many services an `extends` chain may pass through
+pub const DEFAULT_MAX_EXTENDS_DEPTH: usize = 10;
+
+/// Options controlling how compose files are parsed
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// The directory `extends.file` paths in the merged document are relative to,
+    /// by default the current directory
+    pub base_dir: Option<PathBuf>,
+    pub max_extends_depth: usize,
+    /// Drop unknown fields, recording them in `Compose::ignored_fields`, rather than failing
+    pub lenient: bool,
+    /// Read the files as JSON rather than detecting the format of each
+    pub json: bool,
+    /// Whether files other than those given, such as those named by `extends.file`, may be
+    /// read. Without the filesystem, extending a service of another file fails.
+    pub read_files: bool,
+    /// The names of the files, in the order their readers are given, by which problems
+    /// are located. Problems in files without a name are located by line alone.
+    pub file_names: Vec<String>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions { base_dir: None, max_extends_depth: DEFAULT_MAX_EXTENDS_DEPTH, lenient: false, json: false, read_files: true, file_names: Vec::new() }
+    }
+}
+
+/// Remove the fields of a mapping that are neither known nor `x-` extensions, returning
+/// their paths under `path`
+fn drop_unknown_fields(mapping: &mut serde_yaml::Mapping, path: &str, fields: &[&str], unmodelled: &[&str]) -> Vec<String> {
+    let unknown: Vec<serde_yaml::Value> = mapping
+        .keys()
+        .filter(|key| key.as_str().is_none_or(|name| !name.starts_with("x-") && !fields.contains(&name) && !unmodelled.contains(&name)))
+        .cloned()
+        .collect();
+    unknown
+        .into_iter()
+        .map(|key| {
+            mapping.remove(&key);
+            let name = key.as_str().map_or_else(|| format!("{:?}", key), str::to_string);
+            if path.is_empty() { name } else { format!("{}.{}", path, name) }
+        })
+        .collect()
+}
+
+/// Remove the unknown fields of a compose document, its services and its networks,
+/// returning their paths sorted
+fn drop_unknown_document_fields(document: &mut serde_yaml::Value) -> Vec<String> {
+    let Some(mapping) = document.as_mapping_mut() else {
+        return Vec::new();
+    };
+    let mut dropped = drop_unknown_fields(mapping, "", COMPOSE_FIELDS, UNMODELLED_COMPOSE_FIELDS);
+    let sections: [(&str, &[&str], &[&str]); 2] = [
+        ("services", SERVICE_FIELDS, UNMODELLED_SERVICE_FIELDS),
+        ("networks", NETWORK_FIELDS, UNMODELLED_NETWORK_FIELDS),
+    ];
+    for (section, fields, unmodelled) in sections {
+        let Some(entries) = mapping.get_mut(section).and_then(serde_yaml::Value::as_mapping_mut) else {
+            continue;
+        };
+        for (name, entry) in entries.iter_mut() {
+            if let (Some(name), Some(entry)) = (name.as_str(), entry.as_mapping_mut()) {
+                dropped.extend(drop_unknown_fields(entry, &format!("{}.{}", section, name), fields, unmodelled));
+            }
+        }
+    }
+    dropped.sort();
+    dropped
+}
+
+/// A service being resolved: the file it's defined in, None for the document being
+/// parsed, and its name
+type ServiceKey = (Option<String>, String);
+
+/// Resolves `extends`, merging each service over the service it extends
+struct ExtendsResolver<'a> {
+    options: &'a ParseOptions,
+    services: serde_yaml::Mapping,
+    /// The services of other files named by `extends.file`, by path
+    files: HashMap<String, serde_yaml::Mapping>,
+}
+
+impl ExtendsResolver<'_> {
+    fn services_of(&mut self, file: Option<&str>) -> Result<&serde_yaml::Mapping, CargotectureError> {
+        let Some(file) = file else {
+            return Ok(&self.services);
+        };
+        if !self.options.read_files {
+            return Err(CargotectureError::Validation(format!("extends.file {} is unavailable in this mode, which can't read files", file)));
+        }
+        if !self.files.contains_key(file) {
+            let content = fs::read_to_string(file)
+                .map_err(|e| CargotectureError::Validation(format!("Failed to read extended file {}: {}", file, e)))?;
+            let value: serde_yaml::Value = serde_yaml::from_str(&content)
+                .map_err(|e| CargotectureError::Validation(format!("Failed to parse extended file {}: {}", file, e)))?;
+            let services = value.get("services").and_then(|s| s.as_mapping()).cloned().unwrap_or_default();
+            self.files.insert(file.to_string(), services);
+        }
+        Ok(&self.files[file])
+    }
+
+    /// The path of a file named by `extends.file`, relative to the file doing the extending
+    fn locate(&self, extending: Option<&str>, file: &str) -> String {
+        let dir = match extending {
+            Some(extending) => Path::new(extending).parent(),
+            None => self.options.base_dir.as_deref(),
+        };
+        dir.map_or_else(|| PathBuf::from(file), |dir| dir.join(file)).to_string_lossy().into_owned()
+    }
+
+    fn resolve(
+        &mut self,
+        key: ServiceKey,
+        visited: &mut HashSet<ServiceKey>,
+        chain: &mut Vec<ServiceKey>,
+    ) -> Result<serde_yaml::Value, CargotectureError> {
+        let describe = |(file, name): &ServiceKey| match file {
+            Some(file) => format!("{} ({})", name, file),
+            None => name.clone(),
+        };
+        if visited.contains(&key) {
+            let start = chain.iter().position(|k| *k == key).unwrap_or(0);
+            let path: Vec<String> = chain[start..].iter().chain([&key]).map(describe).collect();
+            return Err(CargotectureError::Validation(format!("Cyclic extends: {}", path.join(" -> "))));
+        }
+        let (file, name) = &key;
+        let mut service = match self.services_of(file.as_deref())?.get(name.as_str()) {
+            Some(service) => service.clone(),
+            None => {
+                let extending = chain.last().map_or_else(|| name.clone(), describe);
+                return Err(CargotectureError::Validation(format!(
+                    "Service '{}' extends unknown service {}", extending, describe(&key)
+                )));
+            }
+        };
+        let Some(extends) = service.as_mapping_mut().and_then(|m| m.remove("extends")) else {
+            return Ok(service);
+        };
+        if chain.len() >= self.options.max_extends_depth {
+            let root = chain.first().unwrap_or(&key);
+            return Err(CargotectureError::Validation(format!(
+                "The extends chain of service '{}' is longer than the maximum of {}",
+                describe(root), self.options.max_extends_depth
+            )));
+        }
+        let parent = match &extends {
+            serde_yaml::Value::String(parent) => (file.clone(), parent.clone()),
+            serde_yaml::Value::Mapping(extends) => {
+                let parent = extends.get("service").and_then(|s| s.as_str()).ok_or_else(|| {
+                    CargotectureError::Validation(format!("The extends of service '{}' doesn't name a service", describe(&key)))
+                })?;
+                let parent_file = match extends.get("file").and_then(|f| f.as_str()) {
+                    Some(parent_file) => Some(self.locate(file.as_deref(), parent_file)),
+                    None => file.clone(),
+                };
+                (parent_file, parent.to_string())
+            }
+            _ => {
+                return Err(CargotectureError::Validation(format!(
+                    "The extends of service '{}' must be a service name or a mapping", describe(&key)
+                )))
+            }
+        };
+
+        visited.insert(key.clone());
+        chain.push(key);
+        let mut resolved = self.resolve(parent, visited, chain)?;
+        if let Some(key) = chain.pop() {
+            visited.remove(&key);
+        }
+        merge_compose_values(&mut resolved, service);
+        Ok(resolved)
+    }
+}
+
+/// Replace each service of a compose document that uses `extends` with the service it
+/// extends merged with its own configuration, failing on cycles and overlong chains.
+/// Returns the other files read, in path order.
+pub fn resolve_extends(document: &mut serde_yaml::Value, options: &ParseOptions) -> Result<Vec<String>, CargotectureError> {
+    let Some(services) = document.get("services").and_then(|s| s.as_mapping()).cloned() else {
+        return Ok(Vec::new());
+    };
+    let names: Vec<String> = services.keys().filter_map(|k| k.as_str().map(str::to_string)).collect();
+    let mut resolver = ExtendsResolver { options, services, files: HashMap::new() };
+    let mut resolved = serde_yaml::Mapping::new();
+    for name in names {
+        let service = resolver.resolve((None, name.clone()), &mut HashSet::new(), &mut Vec::new())?;
+        resolved.insert(serde_yaml::Value::String(name), service);
+    }
+    document["services"] = serde_yaml::Value::Mapping(resolved);
+    let mut files: Vec<String> = resolver.files.into_keys().collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Where each field of a merged compose document was written, by its path of keys such
+/// as `services.web.restart`: first where it was defined, then each file which overrode
+/// it, in the order the files were merged
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    pub chains: HashMap<String, Vec<SourceLocation>>,
+}
+
+impl SourceMap {
+    /// Record where each field of a file's document is written, after those of the files
+    /// merged before it
+    fn record(&mut self, value: &serde_yaml::Value, content: &str, file: Option<&str>) {
+        let lines = key_lines(content);
+        let mut paths = Vec::new();
+        key_paths(value, "", &mut paths);
+        for path in paths {
+            let line = lines.get(&path).copied();
+            self.chains.entry(path).or_default().push(SourceLocation { file: file.map(str::to_string), document: None, line });
+        }
+    }
+
+    /// Where the field at a path was defined and then overridden, or failing that the
+    /// nearest field enclosing it, such as the service for a field it doesn't set
+    pub fn locate(&self, path: &str) -> Vec<SourceLocation> {
+        let mut path = path;
+        loop {
+            if let Some(chain) = self.chains.get(path) {
+                return chain.clone();
+            }
+            match path.rsplit_once('.') {
+                Some((parent, _)) => path = parent,
+                None => return Vec::new(),
+            }
+        }
+    }
+
+    /// Where the value at a path was defined and then overridden. One no file sets, such as
+    /// a default, is located where the nearest field enclosing it was defined, since the
+    /// files overriding that field left the value alone.
+    pub fn locate_value(&self, path: &str) -> Vec<SourceLocation> {
+        match self.chains.get(path) {
+            Some(chain) => chain.clone(),
+            None => self.locate(path).into_iter().take(1).collect(),
+        }
+    }
+}
+
+/// The paths of the keys of every mapping in a value, outside sequences
+fn key_paths(value: &serde_yaml::Value, prefix: &str, paths: &mut Vec<String>) {
+    for (key, value) in value.as_mapping().into_iter().flatten() {
+        let Some(key) = key.as_str() else { continue };
+        let path = if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) };
+        key_paths(value, &path, paths);
+        paths.push(path);
+    }
+}
+
+/// The line, counting from 1, of each key of a block style YAML document by its path.
+/// Keys within sequences and block scalars, and those of flow style mappings, aren't found.
+fn key_lines(content: &str) -> HashMap<String, usize> {
+    let mut lines = HashMap::new();
+    // The indentation of each key enclosing a line, or None for sequence items and block
+    // scalars, whose lines aren't keys of the document
+    let mut enclosing: Vec<(usize, Option<&str>)> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let Some((indent, content)) = yaml_line(line) else { continue };
+        while enclosing.last().is_some_and(|(outer, _)| *outer >= indent) {
+            enclosing.pop();
+        }
+        if content == "-" || content.starts_with("- ") {
+            enclosing.push((indent, None));
+            continue;
+        }
+        let Some((key, value)) = yaml_key_value(content) else { continue };
+        if let Some(keys) = enclosing.iter().map(|(_, key)| *key).collect::<Option<Vec<&str>>>() {
+            let path = keys.into_iter().chain([key]).collect::<Vec<_>>().join(".");
+            lines.entry(path).or_insert(number + 1);
+        }
+        let block_scalar = value.starts_with('|') || value.starts_with('>');
+        enclosing.push((indent, (!block_scalar).then_some(key)));
+    }
+    lines
+}
+
+/// The indentation and content of a YAML line, or None for blank and comment lines
+fn yaml_line(line: &str) -> Option<(usize, &str)> {
+    let content = line.trim_start();
+    if content.is_empty() || content.starts_with('#') {
+        return None;
+    }
+    Some((line.len() - content.len(), content.trim_end()))
+}
+
+/// Split a `key: value` line into its key, unquoted, and value
+fn yaml_key_value(content: &str) -> Option<(&str, &str)> {
+    let (key, value) = content.split_once(':')?;
+    let key = key.trim();
+    let key = key.strip_prefix('"').and_then(|k| k.strip_suffix('"')).unwrap_or(key);
+    let key = key.strip_prefix('\'').and_then(|k| k.strip_suffix('\'')).unwrap_or(key);
+    Some((key, value.trim()))
+}
+
+/// The name of a YAML anchor or alias at the start of a value, after its `&` or `*`
+fn yaml_node_name(value: &str, sigil: char) -> Option<&str> {
+    let rest = value.strip_prefix(sigil)?;
+    let end = rest.find(|c: char| c.is_whitespace() || matches!(c, ',' | ']' | '}')).unwrap_or(rest.len());
+    Some(&rest[..end]).filter(|name| !name.is_empty())
+}
+
+/// Find the services of a compose document that reuse another service's definition
+/// through a YAML alias, by name. Aliases are resolved when the YAML is loaded, so this
+/// reads the anchors and aliases from the block style source: a service anchored with
+/// `worker1: &worker1` and reused by `worker2: *worker1` or `<<: *worker1`.
+pub fn service_aliases(content: &str) -> HashMap<String, String> {
+    let mut anchors: HashMap<&str, &str> = HashMap::new();
+    let mut derived = HashMap::new();
+    let mut in_services = false;
+    let mut service_indent = None;
+    let mut current: Option<&str> = None;
+    for (indent, content) in content.lines().filter_map(yaml_line) {
+        if indent == 0 {
+            in_services = yaml_key_value(content).is_some_and(|(key, _)| key == "services");
+            service_indent = None;
+            current = None;
+            continue;
+        }
+        if !in_services || service_indent.is_some_and(|service_indent| indent < service_indent) {
+            continue;
+        }
+        let Some((key, value)) = yaml_key_value(content) else { continue };
+        if *service_indent.get_or_insert(indent) == indent {
+            current = Some(key);
+            let value = match yaml_node_name(value, '&') {
+                Some(anchor) => {
+                    anchors.insert(anchor, key);
+                    value[anchor.len() + 1..].trim()
+                }
+                None => value,
+            };
+            if let Some(source) = yaml_node_name(value, '*').and_then(|alias| anchors.get(alias)) {
+                derived.insert(key.to_string(), source.to_string());
+            }
+        } else if key == "<<" {
+            let value = value.trim_start_matches('[').trim();
+            let source = yaml_node_name(value, '*').and_then(|alias| anchors.get(alias));
+            if let (Some(service), Some(source)) = (current, source) {
+                derived.insert(service.to_string(), source.to_string());
+            }
+        }
+    }
+    derived
+}
+
+/// Parse one or more compose files, merging later files over earlier ones
+pub fn parse_composefiles(readers: Vec<Box<dyn Read>>) -> Result<Compose> {
+    parse_composefiles_with_options(readers, &ParseOptions::default())
+}
+
+/// Parse one or more compose files as `parse_composefiles` does, resolving `extends` as
+/// the options direct
+pub fn parse_composefiles_with_options(readers: Vec<Box<dyn Read>>, options: &ParseOptions) -> Result<Compose> {
+    let compose = parse_composefiles_unvalidated(readers, options)?;
+    compose.validate().print();
+    Ok(compose)
+}
+
+/// Parse one or more compose files as `parse_composefiles_with_options` does, leaving the
+/// caller to validate the result
+pub fn parse_composefiles_unvalidated(readers: Vec<Box<dyn Read>>, options: &ParseOptions) -> Result<Compose> {
+    let mut merged: Option<serde_yaml::Value> = None;
+    let mut aliases = HashMap::new();
+    let mut sources = SourceMap::default();
+    for (index, mut reader) in readers.into_iter().enumerate() {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        let format = if options.json { DocumentFormat::Json } else { detect_format(&content) };
+        let mut value: serde_yaml::Value = match format {
+            DocumentFormat::Json => serde_json::from_str(&content)?,
+            DocumentFormat::Yaml => serde_yaml::from_str(&content)?,
+        };
+        value.apply_merge()?;
+        aliases.extend(service_aliases(&content));
+        sources.record(&value, &content, options.file_names.get(index).map(String::as_str));
+        match merged.as_mut() {
+            Some(base) => merge_compose_values(base, value),
+            None => merged = Some(value),
+        }
+    }
+    let mut merged = merged.unwrap_or_default();
+    let document = merged.clone();
+    let included_files = resolve_extends(&mut merged, options)?;
+    let ignored_fields = if options.lenient { drop_unknown_document_fields(&mut merged) } else { Vec::new() };
+    let port_names = long_port_names(&merged);
+    let mut compose: Compose = serde_yaml::from_value(merged)?;
+    compose.included_files = included_files;
+    compose.ignored_fields = ignored_fields;
+    compose.document = document;
+    compose.sources = sources;
+    for (name, source) in aliases {
+        if let Some(service) = compose.services.get_mut(&name) {
+            service.derived_from = Some(source);
+        }
+    }
+    for (name, names) in port_names {
+        if let Some(service) = compose.services.get_mut(&name) {
+            service.port_names = names;
+        }
+    }
+    Ok(compose)
+}
+
+/// Something worth knowing about a compose file which doesn't stop it being parsed, such as
+/// a deprecated option
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseWarning {
+    pub service: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// A parsed value with the warnings found while parsing it
+#[derive(Debug)]
+pub struct ParseResult<T> {
+    pub value: T,
+    pub warnings: Vec<ParseWarning>,
+}
+
+impl Compose {
+    /// Deprecated options in the compose file, which compose still accepts
+    pub fn parse_warnings(&self) -> Vec<ParseWarning> {
+        let mut warnings = Vec::new();
+        if let Some(version) = &self.version {
+            warnings.push(ParseWarning {
+                service: None,
+                message: format!("The top-level 'version: {}' is obsolete and ignored by compose", version),
+            });
+        }
+        for (name, service) in self.services() {
+            let restart = service.restart.as_deref();
+            if let Some(attempts) = restart.and_then(|restart| restart.strip_prefix("on-failure:")) {
+                warnings.push(ParseWarning {
+                    service: Some(name.to_string()),
+                    message: format!(
+                        "Service '{}' uses the deprecated restart value 'on-failure:{}'; use 'on-failure' with deploy.restart_policy.max_attempts: {}",
+                        name, attempts, attempts
+                    ),
+                });
+            }
+        }
+        warnings
+    }
+}
+
+/// Parse a compose file as `parse_composefiles` does, with the warnings found parsing it
+pub fn parse_composefile(reader: Box<dyn Read>) -> Result<ParseResult<Compose>> {
+    let compose = parse_composefiles(vec![reader])?;
+    let warnings = compose.parse_warnings();
+    Ok(ParseResult { value: compose, warnings })
+}
+
+/// Parse a compose file written as JSON, as `parse_composefile` does
+pub fn parse_composefile_json(reader: Box<dyn Read>) -> Result<ParseResult<Compose>> {
+    let compose = parse_composefiles_with_options(vec![reader], &ParseOptions { json: true, ..Default::default() })?;
+    let warnings = compose.parse_warnings();
+    Ok(ParseResult { value: compose, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_yaml_sample() -> String {
+        r#"
+        services:
+          db:
+            image: mysql:8
+            healthcheck:
+              test: ['CMD-SHELL', 'mysqladmin ping -h 127.0.0.1 --password="$$(cat /run/secrets/db-password)" --silent']
+              interval: 3s
+          backend:
+            build: backend
+            depends_on:
+              db:
+                condition: service_healthy
+          proxy:
+            image: nginx
+            depends_on: 
+              - backend
+        "#.to_string()
+    }
+    
+
+    fn get_yaml_elk() -> String {
+        // Replace with the second YAML string
+        r#"
+services:
+  elasticsearch:
+    image: elasticsearch:7.16.1
+    container_name: es
+    environment:
+      discovery.type: single-node
+      ES_JAVA_OPTS: "-Xms512m -Xmx512m"
+    ports:
+      - "9200:9200"
+      - "9300:9300"
+    healthcheck:
+      test: ["CMD-SHELL", "curl --silent --fail localhost:9200/_cluster/health || exit 1"]
+      interval: 10s
+      timeout: 10s
+      retries: 3
+    networks:
+      - elastic
+  logstash:
+    image: logstash:7.16.1
+    container_name: log
+    environment:
+      discovery.seed_hosts: logstash
+      LS_JAVA_OPTS: "-Xms512m -Xmx512m"
+    volumes:
+      - ./logstash/pipeline/logstash-nginx.config:/usr/share/logstash/pipeline/logstash-nginx.config
+      - ./logstash/nginx.log:/home/nginx.log
+    ports:
+      - "5000:5000/tcp"
+      - "5000:5000/udp"
+      - "5044:5044"
+      - "9600:9600"
+    depends_on:
+      - elasticsearch
+    networks:
+      - elastic
+    command: logstash -f /usr/share/logstash/pipeline/logstash-nginx.config
+  kibana:
+    image: kibana:7.16.1
+    container_name: kib
+    ports:
+      - "5601:5601"
+    depends_on:
+      - elasticsearch
+    networks:
+      - elastic
+networks:
+  elastic:
+    driver: bridge
+        "#.to_string()
+
+    }
+
+    fn check_elasticsearch_service(service: &Service) {
+        assert_eq!(service.image.as_ref().unwrap(), "elasticsearch:7.16.1");
+        assert_eq!(service.container_name.as_ref().unwrap(), "es");
+        let environment = service.environment.as_ref().unwrap();
+        assert_eq!(environment.get("discovery.type").unwrap(), "single-node");
+        assert_eq!(environment.get("ES_JAVA_OPTS").unwrap(), "-Xms512m -Xmx512m");
+    
+        let ports = service.ports.as_ref().unwrap();
+        assert_eq!(ports.len(), 2);
+        assert_eq!(ports[0], "9200:9200");
+        assert_eq!(ports[1], "9300:9300");
+    
+        let healthcheck = service.healthcheck.as_ref().unwrap();
+        assert_eq!(healthcheck.test, vec!["CMD-SHELL", "curl --silent --fail localhost:9200/_cluster/health || exit 1"]);
+        assert_eq!(healthcheck.interval.as_ref().unwrap(), "10s");
+        assert_eq!(healthcheck.timeout.as_ref().unwrap(), "10s");
+        assert_eq!(healthcheck.retries.unwrap(), 3);
+    
+        let networks = service.networks.as_ref().unwrap();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks.get("elastic"), Some(&None));
+    }
+
+    fn check_logstash_service(service: &Service) {
+        assert_eq!(service.image.as_ref().unwrap(), "logstash:7.16.1");
+        assert_eq!(service.container_name.as_ref().unwrap(), "log");
+    
+        let environment = service.environment.as_ref().unwrap();
+        assert_eq!(environment.get("discovery.seed_hosts").unwrap(), "logstash");
+        assert_eq!(environment.get("LS_JAVA_OPTS").unwrap(), "-Xms512m -Xmx512m");
+    
+        let volumes = service.volumes.as_ref().unwrap();
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(volumes[0], "./logstash/pipeline/logstash-nginx.config:/usr/share/logstash/pipeline/logstash-nginx.config");
+        assert_eq!(volumes[1], "./logstash/nginx.log:/home/nginx.log");
+    
+        let ports = service.ports.as_ref().unwrap();
+        assert_eq!(ports.len(), 4);
+        assert_eq!(ports[0], "5000:5000/tcp");
+        assert_eq!(ports[1], "5000:5000/udp");
+        assert_eq!(ports[2], "5044:5044");
+        assert_eq!(ports[3], "9600:9600");
+    
+        let depends_on = service.depends_on.as_ref().unwrap();
+        match depends_on {
+            DependsOn::List(services) => {
+                assert_eq!(services.len(), 1);
+                assert_eq!(services[0], "elasticsearch");
+            }
+            _ => panic!("Unexpected DependsOn variant"),
+        }
+    
+        let networks = service.networks.as_ref().unwrap();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks.get("elastic"), Some(&None));
+    
+        let command = service.command.as_ref().unwrap();
+        assert_eq!(command.display(), "logstash -f /usr/share/logstash/pipeline/logstash-nginx.config");
+        assert_eq!(command.as_argv(), vec!["logstash", "-f", "/usr/share/logstash/pipeline/logstash-nginx.config"]);
+    
+        // Since no other properties are defined for the logstash service in the provided YAML,
+        // we'll check that they are set to their default values (i.e., None or empty).
+        assert!(service.restart.is_none());
+        assert!(service.env_file.is_none());
+        assert!(service.logging.is_none());
+        assert!(service.dns.is_none());
+        assert!(service.hostname.is_none());
+        assert!(service.extra_hosts.is_none());
+        assert!(service.healthcheck.is_none());
+    }
+
+    fn check_kibana_service(service: &Service) {
+        assert_eq!(service.image.as_ref().unwrap(), "kibana:7.16.1");
+        assert_eq!(service.container_name.as_ref().unwrap(), "kib");
+        let ports = service.ports.as_ref().unwrap();
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0], "5601:5601");
+
+        let depends_on = service.depends_on.as_ref().unwrap();
+        match depends_on {
+            DependsOn::List(services) => {
+                assert_eq!(services.len(), 1);
+                assert_eq!(services[0], "elasticsearch");
+            }
+            _ => panic!("Unexpected DependsOn variant"),
+        }
+
+        let networks = service.networks.as_ref().unwrap();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks.get("elastic"), Some(&None));
+
+        // Since no other properties are defined for the kibana service in the provided YAML,
+        // we'll check that they are set to their default values (i.e., None or empty).
+        assert!(service.restart.is_none());
+        assert!(service.env_file.is_none());
+        assert!(service.logging.is_none());
+        assert!(service.environment.is_none());
+        assert!(service.extra_hosts.is_none());
+        assert!(service.healthcheck.is_none());
+        assert!(service.dns.is_none());
+        assert!(service.hostname.is_none());
+        assert!(service.volumes.is_none());
+    }
+
+    fn check_db_service(service: &Service) {
+        let healthcheck = service.healthcheck.as_ref().unwrap();
+        assert_eq!(healthcheck.test, vec!["CMD-SHELL", "mysqladmin ping -h 127.0.0.1 --password=\"$$(cat /run/secrets/db-password)\" --silent"]);
+        assert_eq!(healthcheck.interval.as_ref().unwrap(), "3s");
+        assert!(healthcheck.timeout.is_none());
+        assert!(healthcheck.retries.is_none());
+        assert!(healthcheck.start_period.is_none());
+    
+        assert_eq!(service.image.as_deref(), Some("mysql:8"));
+
+        // Since no other properties are defined for the db service in the provided YAML, 
+        // we'll check that they are set to their default values (i.e., None or empty).
+        assert!(service.restart.is_none());
+        assert!(service.env_file.is_none());
+        assert!(service.logging.is_none());
+        assert!(service.ports.is_none());
+        assert!(service.networks.is_none());
+        assert!(service.volumes.is_none());
+        assert!(service.depends_on.is_none());
+        assert!(service.dns.is_none());
+        assert!(service.hostname.is_none());
+        assert!(service.environment.is_none());
+        assert!(service.extra_hosts.is_none());
+    }
+
+    fn check_backend_service(service: &Service) {
+        let depends_on = service.depends_on.as_ref().unwrap();
+        match depends_on {
+            DependsOn::Map(conditions) => {
                 let db_condition = conditions.get("db").unwrap();
                 assert_eq!(db_condition.condition, "service_healthy");
             }
-            _ => panic!("Unexpected DependsOn variant"),
+            _ => panic!("Unexpected DependsOn variant"),
+        }
+    
+        assert_eq!(service.build.as_ref().unwrap().context.as_deref(), Some("backend"));
+
+        // Since no other properties are defined for the backend service in the provided YAML, 
+        // we'll check that they are set to their default values (i.e., None or empty).
+        assert!(service.image.is_none());
+        assert!(service.restart.is_none());
+        assert!(service.env_file.is_none());
+        assert!(service.logging.is_none());
+        assert!(service.ports.is_none());
+        assert!(service.networks.is_none());
+        assert!(service.volumes.is_none());
+        assert!(service.dns.is_none());
+        assert!(service.hostname.is_none());
+        assert!(service.environment.is_none());
+        assert!(service.extra_hosts.is_none());
+        assert!(service.healthcheck.is_none());
+    }
+    
+    fn check_proxy_service(service: &Service) {
+        let depends_on = service.depends_on.as_ref().unwrap();
+        match depends_on {
+            DependsOn::List(services) => {
+                assert_eq!(services.len(), 1);
+                assert_eq!(services[0], "backend");
+            }
+            _ => panic!("Unexpected DependsOn variant"),
+        }
+    
+        assert_eq!(service.image.as_deref(), Some("nginx"));
+
+        // Since no other properties are defined for the proxy service in the provided YAML,
+        // we'll check that they are set to their default values (i.e., None or empty).
+        assert!(service.restart.is_none());
+        assert!(service.env_file.is_none());
+        assert!(service.logging.is_none());
+        assert!(service.ports.is_none());
+        assert!(service.networks.is_none());
+        assert!(service.volumes.is_none());
+        assert!(service.dns.is_none());
+        assert!(service.hostname.is_none());
+        assert!(service.environment.is_none());
+        assert!(service.extra_hosts.is_none());
+        assert!(service.healthcheck.is_none());
+    }
+
+    #[test]
+    fn test_deserialization_sample() {
+        let yaml_str = get_yaml_sample();
+        let compose: Compose = serde_yaml::from_str(&yaml_str).unwrap();
+        let services = compose.services;
+
+        check_db_service(services.get("db").unwrap());
+        check_backend_service(services.get("backend").unwrap());
+        check_proxy_service(services.get("proxy").unwrap());
+    }
+
+    #[test]
+    fn test_deserialization_elk() {
+        let yaml_str = get_yaml_elk();
+        let compose: Compose = serde_yaml::from_str(&yaml_str).unwrap();
+        let services = compose.services;
+
+        check_elasticsearch_service(services.get("elasticsearch").unwrap());
+        check_logstash_service(services.get("logstash").unwrap());
+        check_kibana_service(services.get("kibana").unwrap());
+    }
+
+    fn get_yaml_flows() -> String {
+        r#"
+services:
+  db:
+    image: postgres:15
+    hostname: database
+  cache:
+    image: redis:7
+    ports:
+      - "6379:6379"
+  backend:
+    image: backend:latest
+    environment:
+      DATABASE_URL: postgres://app:secret@db:5432/app
+      CACHE_ADDR: cache:6380
+      LOG_LEVEL: debug
+    depends_on:
+      - db
+  worker:
+    image: worker:latest
+    environment:
+      DB_HOST_URL: postgres://database/app
+      UPSTREAM: http://db.example.com:5432
+      OTHER: postgres://dbx:5432/app
+        "#.to_string()
+    }
+
+    #[test]
+    fn test_infer_flows() {
+        let compose: Compose = serde_yaml::from_str(&get_yaml_flows()).unwrap();
+        let flows = compose.infer_flows();
+
+        // backend -> db via the URL, with the password redacted from the evidence.
+        // CACHE_ADDR names a port that cache doesn't declare, so no edge is inferred.
+        // worker -> db via its hostname alias and the postgres default port; the
+        // similar-looking external hostnames must not create edges.
+        assert_eq!(
+            flows,
+            vec![
+                InferredFlow {
+                    source: "backend".to_string(),
+                    target: "db".to_string(),
+                    port: 5432,
+                    evidence: "DATABASE_URL=postgres://app:***@db:5432/app".to_string(),
+                },
+                InferredFlow {
+                    source: "worker".to_string(),
+                    target: "db".to_string(),
+                    port: 5432,
+                    evidence: "DB_HOST_URL=postgres://database/app".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_infer_flows_external_host() {
+        let yaml = r#"
+services:
+  db:
+    image: postgres:15
+  backend:
+    environment:
+      DATABASE_URL: postgres://db.internal.example.com:5432/app
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        assert!(compose.infer_flows().is_empty());
+    }
+
+    #[test]
+    fn test_container_ports_and_mount_points() {
+        let compose: Compose = serde_yaml::from_str(&get_yaml_elk()).unwrap();
+        let logstash = compose.services.get("logstash").unwrap();
+
+        let ports = logstash.container_ports();
+        assert_eq!(ports.len(), 4);
+        assert_eq!(ports[0], ExposedPort { port_number: 5000, protocol: Protocol::Tcp, line: None });
+        assert_eq!(ports[1], ExposedPort { port_number: 5000, protocol: Protocol::Udp, line: None });
+
+        let mounts = logstash.mount_points();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[1].mount_point, "/home/nginx.log");
+    }
+
+    #[test]
+    fn test_pull_policy_attach_and_develop() {
+        let compose = parse_str(include_str!("../tests/fixtures/develop.yml")).unwrap();
+        assert!(compose.validate().is_valid());
+        let web = &compose.services["web"];
+        assert_eq!(web.pull_policy, Some(PullPolicy::Build));
+        let watch = web.develop.as_ref().and_then(|develop| develop.watch.as_ref()).unwrap();
+        assert_eq!(
+            watch[0],
+            WatchRule {
+                action: "sync".to_string(),
+                path: "./src".to_string(),
+                target: Some("/app/src".to_string()),
+                ignore: Some(vec!["node_modules/".to_string(), "*.tmp".to_string()]),
+            }
+        );
+        assert_eq!((watch[1].action.as_str(), watch[1].target.as_deref()), ("rebuild", None));
+        assert_eq!(compose.services["worker"].pull_policy, Some(PullPolicy::Always));
+        assert_eq!(compose.services["worker"].attach, Some(false));
+        assert_eq!(compose.services["cache"].pull_policy, Some(PullPolicy::IfNotPresent));
+        assert_eq!(compose.services["cache"].attach, None);
+
+        assert_eq!(
+            parse_str("services:\n  web:\n    image: nginx\n    pull_policy: sometimes\n").unwrap_err().to_string(),
+            "invalid pull_policy 'sometimes', expected always, never, missing, build or if_not_present"
+        );
+
+        let compose = parse_str("services:\n  web:\n    image: nginx\n    develop:\n      watch:\n        - action: copy\n          path: ./src\n        - action: sync\n          path: ./lib\n").unwrap();
+        assert_eq!(
+            compose.validate().errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "Invalid develop.watch action 'copy' for service 'web'",
+                "develop.watch rule for './lib' of service 'web' needs a target to sync",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_isolated_service_with_dependencies() {
+        let warnings = |web: &str| -> Vec<String> {
+            let yaml = format!("services:\n  db:\n    image: postgres:16\n    networks: [back]\n  web:\n    image: nginx\n{}networks:\n  back: {{}}\n", web);
+            let report = parse_str(&yaml).unwrap().validate();
+            report.warnings.into_iter().map(|w| w.message).collect()
+        };
+        assert_eq!(
+            warnings("    depends_on: [db]\n"),
+            vec!["Service 'web' depends on 'db' but shares no network with it, so can't reach it"]
+        );
+        assert_eq!(warnings("    depends_on: [db]\n    networks: [default]\n").len(), 1);
+        // Published ports don't make the dependency reachable
+        assert_eq!(warnings("    depends_on: [db]\n    ports: [\"8080:80\"]\n").len(), 1);
+        // Isolated workers needn't reach anything
+        assert!(warnings("").is_empty());
+        assert!(warnings("    depends_on: [db]\n    networks: [back]\n").is_empty());
+        assert!(warnings("    depends_on: [db]\n    network_mode: bridge\n").is_empty());
+        // Isolation is intended
+        assert!(warnings("    depends_on: [db]\n    network_mode: none\n").is_empty());
+
+        // Services listing no networks share the default network
+        let yaml = "services:\n  db:\n    image: postgres:16\n  web:\n    image: nginx\n    depends_on: [db]\n";
+        assert!(parse_str(yaml).unwrap().validate().warnings.is_empty());
+    }
+
+    #[test]
+    fn test_long_syntax_ports_and_volumes() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx
+    ports:
+      - target: 80
+        published: "8080"
+      - { target: 53, published: 5353, protocol: udp, host_ip: 127.0.0.1 }
+      - { target: 443, host_ip: "::1", mode: host }
+    volumes:
+      - type: bind
+        source: ./html
+        target: /usr/share/nginx/html
+        read_only: true
+        bind: { selinux: z }
+      - { type: volume, source: cache, target: /cache, volume: { nocopy: true } }
+      - { type: volume, target: /tmp/anonymous }
+"#;
+        let compose = parse_str(yaml).unwrap();
+        let web = &compose.services["web"];
+        assert_eq!(web.ports.as_deref().unwrap(), ["8080:80", "127.0.0.1:5353:53/udp", "[::1]::443"]);
+        assert_eq!(web.volumes.as_deref().unwrap(), ["./html:/usr/share/nginx/html:ro,z", "cache:/cache:nocopy", "/tmp/anonymous"]);
+
+        let tmpfs = "services:\n  web:\n    image: nginx\n    volumes:\n      - { type: tmpfs, target: /tmp }\n";
+        assert_eq!(parse_str(tmpfs).unwrap_err().to_string(), "long-syntax volumes of type 'tmpfs' aren't supported");
+        assert!(parse_str("services:\n  web:\n    image: nginx\n    ports:\n      - published: 80\n").is_err());
+    }
+
+    #[test]
+    fn test_null_and_empty_sections() {
+        let web = "services:\n  web:\n    image: nginx\n";
+        const NO_IMAGE: &str = "Service 'web' has neither an image nor a build, so compose can't create its container";
+        // Each document, with the errors and warnings validating it reports
+        let cases: &[(String, &[&str], &[&str])] = &[
+            ("services: {}\n".to_string(), &["No services defined"], &[]),
+            ("services:\n".to_string(), &["No services defined"], &[]),
+            ("services:\n  web:\n".to_string(), &[NO_IMAGE], &["Service 'web' is defined with no configuration"]),
+            ("services:\n  web: {}\n".to_string(), &[NO_IMAGE], &["Service 'web' is defined with no configuration"]),
+            (format!("{}networks:\n", web), &[], &[]),
+            (format!("{}volumes:\n", web), &[], &[]),
+            (format!("{}networks:\n  back:\n", web), &[], &[]),
+            (format!("{}    healthcheck:\n", web), &[], &[]),
+            (format!("{}    depends_on:\n", web), &[], &[]),
+            (format!("{}    networks:\n    volumes:\n    ports:\n    environment:\n    labels:\n", web), &[], &[]),
+            (format!("{}    networks: [back]\n    depends_on:\n      db:\n  db:\n    image: postgres\n    networks: [back]\nnetworks:\n  back:\n", web), &[], &[]),
+        ];
+        for (yaml, errors, warnings) in cases {
+            let compose = parse_str(yaml).unwrap_or_else(|err| panic!("{}: {}", yaml, err));
+            let report = compose.validate();
+            assert_eq!(report.errors.iter().map(|e| e.message.as_str()).collect::<Vec<_>>(), *errors, "{}", yaml);
+            assert_eq!(report.warnings.iter().map(|w| w.message.as_str()).collect::<Vec<_>>(), *warnings, "{}", yaml);
+        }
+
+        // Null sections are absent, and a null dependency waits for the service to start
+        let compose = parse_str(&format!("{}    healthcheck:\n    depends_on:\n      db:\n  db:\n    image: postgres\nnetworks:\n", web)).unwrap();
+        assert!(compose.networks.is_none());
+        let service = &compose.services["web"];
+        assert!(service.healthcheck.is_none());
+        assert_eq!(service.depends_on.as_ref().unwrap().condition_for("db"), Some("service_started"));
+        let empty = &parse_str("services:\n  web:\n").unwrap().services["web"];
+        assert!(empty.image.is_none() && empty.build.is_none() && empty.ports.is_none());
+    }
+
+    #[test]
+    fn test_runtime_extras() {
+        let compose = parse_composefile(Box::new(include_str!("../tests/fixtures/tuning.yml").as_bytes())).unwrap().value;
+        let db = compose.services["db"].runtime_extras.as_ref().unwrap();
+        assert_eq!(db.cpuset.as_deref(), Some("0-3"));
+        assert_eq!((db.cpu_period, db.cpu_quota, db.mem_swappiness, db.oom_score_adj), (Some(100_000), Some(50_000), Some(0), Some(-500)));
+        assert_eq!(db.group_add, Some(vec!["audio".to_string(), "1001".to_string()]));
+        assert_eq!(db.blkio_config.as_ref().unwrap()["device_read_bps"][0]["rate"], "12mb");
+        let web = compose.services["web"].runtime_extras.as_ref().unwrap();
+        assert_eq!((web.isolation.as_deref(), web.userns_mode.as_deref()), (Some("process"), Some("host")));
+        assert_eq!(compose.services["cache"].runtime_extras, None);
+
+        // They're written among the service's fields, and read back from there
+        let json = serde_json::to_value(&compose).unwrap();
+        assert_eq!(json["services"]["db"]["oom_score_adj"], -500);
+        assert_eq!(json["services"]["db"]["blkio_config"]["weight"], 300);
+        assert_eq!(json["services"]["web"]["isolation"], "process");
+        assert!(json["services"]["web"].get("cpuset").is_none());
+        let reparsed: Compose = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(reparsed.services["db"].runtime_extras.as_ref(), Some(db));
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), json);
+
+        assert!(parse_str("services:\n  web:\n    image: nginx\n    oom_score_adj: lots\n").unwrap_err().to_string().contains("expected an integer"));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let compose = parse_composefile(Box::new(std::io::Cursor::new(get_yaml_elk()))).unwrap().value;
+        let json = serde_json::to_string(&compose).unwrap();
+        let parsed = parse_composefile_json(Box::new(std::io::Cursor::new(json.clone()))).unwrap().value;
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), serde_json::to_value(&compose).unwrap());
+        assert_eq!(parsed.services["logstash"].port_infos(), compose.services["logstash"].port_infos());
+
+        // JSON is detected without being asked for
+        let detected = parse_composefiles(vec![Box::new(std::io::Cursor::new(json))]).unwrap();
+        assert_eq!(serde_json::to_value(&detected).unwrap(), serde_json::to_value(&compose).unwrap());
+        assert!(parse_composefile_json(Box::new("services:\n  web:\n    image: nginx\n".as_bytes())).is_err());
+    }
+
+    #[test]
+    fn test_parse_warnings() {
+        let yaml = "services:\n  web:\n    image: nginx\n    restart: on-failure:3\n";
+        let result = parse_composefile(Box::new(yaml.as_bytes())).unwrap();
+        assert_eq!(result.warnings.len(), 1);
+        assert_eq!(result.warnings[0].service.as_deref(), Some("web"));
+        assert!(result.warnings[0].message.contains("deprecated restart value 'on-failure:3'"), "{}", result.warnings[0]);
+        assert!(result.value.validate().is_valid());
+
+        let result = parse_composefile(Box::new("version: '3.8'\nservices:\n  web:\n    image: nginx\n".as_bytes())).unwrap();
+        assert_eq!(result.warnings[0].to_string(), "The top-level 'version: 3.8' is obsolete and ignored by compose");
+        // Only a number of attempts makes the old syntax valid
+        assert!(!parse_str("services:\n  web:\n    image: nginx\n    restart: on-failure:x\n").unwrap().validate().is_valid());
+    }
+
+    #[test]
+    fn test_boolean_restart() {
+        // YAML 1.2 reads an unquoted no as the string it's meant as
+        let compose = parse_str("services:\n  web:\n    image: nginx\n    restart: no\n").unwrap();
+        assert_eq!(compose.services["web"].restart.as_deref(), Some("no"));
+        assert!(compose.validate().warnings.is_empty());
+
+        // but YAML 1.1 tools and JSON write it as false, which is taken as no with a warning
+        for (written, policy, diagnostic) in [
+            ("false", "no", "warning[unquoted-restart]: Service 'web' sets restart to the boolean false, taken as 'no'; quote it, as in restart: \"no\", since YAML 1.1 reads an unquoted no as false"),
+            ("true", "always", "warning[unquoted-restart]: Service 'web' sets restart to the boolean true, taken as 'always'; name the policy, as in restart: always, since yes, on and true aren't restart policies"),
+        ] {
+            let compose = parse_str(&format!("services:\n  web:\n    image: nginx\n    restart: {}\n", written)).unwrap();
+            assert_eq!(compose.services["web"].restart.as_deref(), Some(policy));
+            let report = compose.validate();
+            assert!(report.errors.is_empty());
+            assert_eq!(report.diagnostics(), vec![diagnostic]);
+        }
+        let json = parse_composefile_json(Box::new(r#"{"services": {"web": {"image": "nginx", "restart": false}}}"#.as_bytes())).unwrap().value;
+        assert_eq!(json.services["web"].restart.as_deref(), Some("no"));
+        assert!(parse_str("services:\n  web:\n    image: nginx\n    restart: [no]\n").is_err());
+    }
+
+    #[test]
+    fn test_interactive_replicas() {
+        let compose = parse_str(
+            "services:\n  shell:\n    image: alpine\n    stdin_open: true\n    tty: yes\n  \
+             workers:\n    image: alpine\n    tty: true\n    deploy:\n      replicas: 3\n  \
+             scaled:\n    image: alpine\n    stdin_open: false\n    scale: 2\n",
+        )
+        .unwrap();
+        assert_eq!(compose.services["shell"].interactive_flags(), vec!["stdin_open", "tty"]);
+        assert_eq!(compose.services["scaled"].interactive_flags(), Vec::<&str>::new());
+        let report = compose.validate();
+        assert_eq!(
+            report.diagnostics(),
+            vec!["warning[interactive-replicas]: Service 'workers' sets tty but has 3 replicas; interactive flags on scaled services are almost always a mistake"]
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let compose = parse_composefile(Box::new(include_str!("../tests/fixtures/elk.yml").as_bytes())).unwrap().value;
+        let summary = compose.to_string();
+        assert!(summary.starts_with("Compose[version=none, services=5, networks=2]\n"));
+        assert!(summary.contains("  - elasticsearch: image=elasticsearch:7.16.1, ports=2, depends_on=none, networks=elastic\n"));
+        assert!(summary.contains("  - logstash: image=logstash:7.16.1, ports=4, depends_on=elasticsearch:service_healthy, networks=elastic\n"));
+        assert!(summary.contains("  - filebeat: image=elastic/filebeat:7.16.1, ports=0, depends_on=logstash, networks=elastic\n"));
+        assert!(summary.contains("  network elastic: driver=bridge\n  network frontend: driver=default, internal=false\n"));
+        // Nothing is in its debug form
+        assert!(summary.lines().skip(1).all(|line| !line.contains(['{', '[', '(', '"']) && !line.contains("Some")));
+
+        let healthcheck = compose.services["elasticsearch"].healthcheck.as_ref().unwrap();
+        assert_eq!(
+            healthcheck.to_string(),
+            "test=CMD-SHELL curl --silent --fail localhost:9200/_cluster/health || exit 1, interval=10s, timeout=10s, retries=3"
+        );
+    }
+
+    #[test]
+    fn test_services_by_restart_policy() {
+        let yaml = "services:\n  web:\n    image: nginx\n    restart: always\n  worker:\n    image: worker\n    restart: on-failure\n  \
+                    cache:\n    image: redis\n    restart: unless-stopped\n  migrate:\n    image: migrate\n    restart: \"no\"\n  \
+                    seed:\n    image: seed\n  proxy:\n    image: nginx\n    restart: always\n";
+        let compose = parse_str(yaml).unwrap();
+        let policies = compose.get_services_by_restart_policy();
+        assert_eq!(policies.len(), 5);
+        assert_eq!(policies["always"], vec!["proxy", "web"]);
+        assert_eq!(policies["on-failure"], vec!["worker"]);
+        assert_eq!(policies["unless-stopped"], vec!["cache"]);
+        assert_eq!(policies["no"], vec!["migrate"]);
+        assert_eq!(policies["unset"], vec!["seed"]);
+        assert_eq!(compose.always_restart_services(), vec!["proxy", "web"]);
+        assert_eq!(compose.never_restart_services(), vec!["migrate", "seed"]);
+    }
+
+    #[test]
+    fn test_startup_wait_time_estimate() {
+        let compose = parse_composefile(Box::new(include_str!("../tests/fixtures/elk.yml").as_bytes())).unwrap().value;
+        let estimates = compose.get_service_startup_wait_time_estimate().unwrap();
+        assert_eq!(estimates.len(), 5);
+        assert_eq!(estimates["elasticsearch"], Duration::ZERO);
+        // Elasticsearch's healthcheck retries 3 times at 10s intervals
+        assert!(estimates["kibana"] >= Duration::from_secs(30));
+        // Through kibana, and through logstash which has no healthcheck of its own
+        assert_eq!(estimates["nginx"], Duration::from_secs(30));
+        assert_eq!(estimates["filebeat"], Duration::from_secs(30));
+
+        // Defaults, start periods and healthchecks along a chain add up
+        let yaml = "services:\n  db:\n    image: postgres\n    healthcheck:\n      test: [\"CMD\", \"pg_isready\"]\n      start_period: 5s\n  api:\n    image: api\n    depends_on: [db]\n    healthcheck:\n      test: [\"CMD\", \"true\"]\n      interval: 2s\n      retries: 5\n  web:\n    image: web\n    depends_on: [api]\n";
+        let compose = parse_str(yaml).unwrap();
+        let estimates = compose.get_service_startup_wait_time_estimate().unwrap();
+        assert_eq!(estimates["api"], Duration::from_secs(95));
+        assert_eq!(estimates["web"], Duration::from_secs(105));
+
+        let yaml = "services:\n  db:\n    image: postgres\n    healthcheck:\n      test: [\"CMD\", \"true\"]\n      interval: soon\n  web:\n    image: web\n    depends_on: [db]\n";
+        let err = parse_str(yaml).unwrap().get_service_startup_wait_time_estimate().unwrap_err();
+        assert_eq!(err.to_string(), "Invalid healthcheck.interval value 'soon': must be a duration such as '30s'");
+    }
+
+    #[test]
+    fn test_validate_image_or_build() {
+        let errors = |yaml: &str| parse_str(yaml).unwrap().validate().errors.into_iter().map(|e| e.message).collect::<Vec<_>>();
+        assert!(errors("services:\n  web:\n    image: nginx\n").is_empty());
+        assert!(errors("services:\n  web:\n    build: .\n").is_empty());
+        assert!(errors("services:\n  web:\n    image: web\n    build: .\n").is_empty());
+        // An image or build can come from the service extended
+        assert!(errors("services:\n  base:\n    image: nginx\n  web:\n    extends: base\n").is_empty());
+        assert!(errors("services:\n  base:\n    build: .\n  web:\n    extends:\n      service: base\n").is_empty());
+        assert_eq!(
+            errors("services:\n  base:\n    restart: always\n  web:\n    extends: base\n    ports: [\"80\"]\n"),
+            vec![
+                "Service 'base' has neither an image nor a build, so compose can't create its container",
+                "Service 'web' has neither an image nor a build, so compose can't create its container",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_subnet_overlap() {
+        let networks = |a: &str, b: &str| {
+            format!(
+                "services:\n  web:\n    image: nginx\nnetworks:\n  front:\n    ipam:\n      config:\n        - subnet: {}\n  back:\n    ipam:\n      config:\n        - subnet: {}\n",
+                a, b
+            )
+        };
+        let overlaps = |a: &str, b: &str| parse_str(&networks(a, b)).unwrap().validate_subnet_overlap().into_iter().map(|e| e.message).collect::<Vec<_>>();
+        assert_eq!(overlaps("172.28.0.0/16", "172.28.1.0/24"), vec!["Networks 'back' and 'front' have overlapping subnets 172.28.1.0/24 and 172.28.0.0/16"]);
+        assert!(overlaps("10.0.0.0/8", "192.168.0.0/16").is_empty());
+        assert_eq!(overlaps("10.1.0.0/16", "10.1.0.0/16").len(), 1);
+        // IPv6 subnets are kept but not compared
+        assert!(overlaps("fd00::/64", "fd00::/64").is_empty());
+
+        // The overlap is a validation error
+        let compose = parse_str(&networks("172.28.0.0/16", "172.28.1.0/24")).unwrap();
+        let report = compose.validate();
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].service.is_none());
+        let subnet = &compose.networks.as_ref().unwrap()["front"].ipam.as_ref().unwrap().config.as_ref().unwrap()[0].subnet;
+        assert_eq!(serde_yaml::to_string(subnet).unwrap(), "172.28.0.0/16\n");
+
+        let err = parse_str(&networks("172.28.0.0", "10.0.0.0/8")).unwrap_err();
+        assert!(err.to_string().contains("Invalid subnet '172.28.0.0'"), "{}", err);
+    }
+
+    #[test]
+    fn test_port_lookups() {
+        let yaml = "services:\n  web:\n    image: nginx\n    ports:\n      - \"8080:80\"\n      - \"5353:53/udp\"\n      - \"127.0.0.1:9443:443\"\n      - \"9000\"\n";
+        let compose = parse_str(yaml).unwrap();
+        let web = &compose.services["web"];
+        assert_eq!(web.get_host_port_for_container_port(80, Protocol::Tcp), Some(8080));
+        assert_eq!(web.get_host_port_for_container_port(53, Protocol::Udp), Some(5353));
+        assert_eq!(web.get_host_port_for_container_port(53, Protocol::Tcp), None);
+        // Only a later entry matches, one with an explicit host IP
+        assert_eq!(web.get_host_port_for_container_port(443, Protocol::Tcp), Some(9443));
+        assert_eq!(web.get_host_port_for_container_port(81, Protocol::Tcp), None);
+        // Published on an ephemeral host port
+        assert_eq!(web.get_host_port_for_container_port(9000, Protocol::Tcp), None);
+
+        assert_eq!(web.get_container_port_for_host_port(8080, Protocol::Tcp), Some(80));
+        assert_eq!(web.get_container_port_for_host_port(5353, Protocol::Udp), Some(53));
+        assert_eq!(web.get_container_port_for_host_port(9443, Protocol::Tcp), Some(443));
+        assert_eq!(web.get_container_port_for_host_port(80, Protocol::Tcp), None);
+    }
+
+    #[test]
+    fn test_count_volumes_by_type() {
+        let compose = parse_str(include_str!("../tests/fixtures/volumes.yml")).unwrap();
+        let counts = compose.count_volumes_by_type();
+        assert_eq!(counts.get(&VolumeType::Bind), Some(&3));
+        assert_eq!(counts.get(&VolumeType::Named), Some(&3));
+        assert_eq!(counts.get(&VolumeType::Tmpfs), Some(&3));
+        assert_eq!(counts.get(&VolumeType::Unknown), Some(&1));
+        assert_eq!(compose.total_volume_count(), 10);
+
+        let compose = parse_str("services:\n  web:\n    image: nginx\n").unwrap();
+        assert!(compose.count_volumes_by_type().is_empty());
+        assert_eq!(compose.total_volume_count(), 0);
+    }
+
+    #[test]
+    fn test_merge_container_with_service() {
+        let dockerfile = "FROM alpine\nLABEL version=\"1.0\" tier=\"web\"\nUSER root\nWORKDIR /app\n";
+        let container = crate::parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "web").unwrap();
+        let yaml = r#"
+services:
+  web:
+    image: web:latest
+    user: "1000:1000"
+    entrypoint: /bin/web --serve
+    labels:
+      - version=2.0
+      - owner=ops
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let merged = merge_container_with_service(container, compose.services.get("web").unwrap());
+
+        assert_eq!(merged.labels.get("version").unwrap(), "2.0");
+        assert_eq!(merged.labels.get("tier").unwrap(), "web");
+        assert_eq!(merged.labels.get("owner").unwrap(), "ops");
+        assert_eq!(merged.user.as_deref(), Some("1000:1000"));
+        assert_eq!(merged.working_dir.as_deref(), Some("/app"));
+        assert_eq!(merged.entrypoint, Some(CommandLine::Shell("/bin/web --serve".to_string())));
+    }
+
+    #[test]
+    fn test_labels_map_form() {
+        let yaml = r#"
+services:
+  web:
+    labels:
+      com.example.tier: frontend
+      com.example.replicas: 2
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let labels = compose.services.get("web").unwrap().labels.as_ref().unwrap();
+        assert_eq!(labels.get("com.example.tier").unwrap(), "frontend");
+        assert_eq!(labels.get("com.example.replicas").unwrap(), "2");
+    }
+
+    #[test]
+    fn test_validate_service_healthy_dependency() {
+        let compose: Compose = serde_yaml::from_str(&get_yaml_sample()).unwrap();
+        let report = compose.validate();
+        assert!(report.is_valid());
+        assert!(report.warnings.is_empty());
+
+        // The same fixture with db's healthcheck removed
+        let without_healthcheck = get_yaml_sample().replace(
+            "            healthcheck:\n              test: ['CMD-SHELL', 'mysqladmin ping -h 127.0.0.1 --password=\"$$(cat /run/secrets/db-password)\" --silent']\n              interval: 3s\n",
+            "",
+        );
+        let compose: Compose = serde_yaml::from_str(&without_healthcheck).unwrap();
+        assert!(compose.services.get("db").unwrap().healthcheck.is_none());
+        let report = compose.validate();
+        assert!(report.is_valid());
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].service.as_deref(), Some("backend"));
+        assert_eq!(
+            report.warnings[0].message,
+            "Service 'backend' depends on 'db' being healthy, but 'db' defines no healthcheck"
+        );
+    }
+
+    #[test]
+    fn test_validate_collects_all_errors() {
+        let yaml = r#"
+services:
+  web:
+    restart: sometimes
+    networks:
+      - missing
+    depends_on:
+      - nowhere
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let report = compose.validate();
+        assert!(!report.is_valid());
+        assert_eq!(report.errors.len(), 4);
+    }
+
+    #[test]
+    fn test_merge_compose_values() {
+        let mut base: serde_yaml::Value = serde_yaml::from_str(r#"
+services:
+  web:
+    image: web:1.0
+    healthcheck:
+      test: ["CMD", "curl", "-f", "http://localhost/debug"]
+    ports:
+      - "80:80"
+        "#).unwrap();
+        let overlay: serde_yaml::Value = serde_yaml::from_str(r#"
+services:
+  web:
+    image: web:2.0
+    healthcheck:
+      test: ["CMD", "curl", "-f", "http://localhost/"]
+    ports:
+      - "80:80"
+      - "443:443"
+  db:
+    image: postgres
+        "#).unwrap();
+        merge_compose_values(&mut base, overlay);
+        let compose: Compose = serde_yaml::from_value(base).unwrap();
+
+        let web = compose.services.get("web").unwrap();
+        assert_eq!(web.image.as_deref(), Some("web:2.0"));
+        assert_eq!(web.healthcheck.as_ref().unwrap().test, vec!["CMD", "curl", "-f", "http://localhost/"]);
+        assert_eq!(web.ports.as_ref().unwrap(), &vec!["80:80".to_string(), "443:443".to_string()]);
+        assert!(compose.services.contains_key("db"));
+    }
+
+    #[test]
+    fn test_discover_compose_files() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(discover_compose_files(dir.path()).is_empty());
+
+        std::fs::write(dir.path().join("docker-compose.yml"), "services: {}").unwrap();
+        let files = discover_compose_files(dir.path());
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with("docker-compose.yml"));
+
+        // compose.yaml is preferred, and its override file is merged on top
+        std::fs::write(dir.path().join("compose.yaml"), "services: {}").unwrap();
+        std::fs::write(dir.path().join("compose.override.yaml"), "services: {}").unwrap();
+        std::fs::write(dir.path().join("docker-compose.override.yml"), "services: {}").unwrap();
+        let files = discover_compose_files(dir.path());
+        assert_eq!(files.len(), 2);
+        assert!(files[0].ends_with("compose.yaml"));
+        assert!(files[1].ends_with("compose.override.yaml"));
+    }
+
+    #[test]
+    fn test_resolve_compose_files_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("compose.yml"), "services: {}").unwrap();
+
+        let explicit = vec!["a.yml".to_string()];
+        assert_eq!(resolve_compose_files(&explicit, false, true, dir.path()).1, ComposeFileSource::Explicit);
+        assert_eq!(resolve_compose_files(&[], false, true, dir.path()).1, ComposeFileSource::Discovered);
+        assert_eq!(resolve_compose_files(&[], false, false, dir.path()), (Vec::new(), ComposeFileSource::Stdin));
+    }
+
+    #[test]
+    fn test_service_networks_map_form() {
+        let yaml = r#"
+services:
+  elasticsearch:
+    image: elasticsearch:7.16.1
+    networks:
+      elastic:
+        aliases:
+          - es
+        ipv4_address: 172.16.238.10
+        priority: 100
+      monitoring:
+  kibana:
+    image: kibana:7.16.1
+    environment:
+      ELASTICSEARCH_HOSTS: http://es:9200
+    networks: [elastic]
+networks:
+  elastic:
+  monitoring:
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let elasticsearch = compose.services.get("elasticsearch").unwrap();
+        assert_eq!(elasticsearch.network_names(), vec!["elastic", "monitoring"]);
+
+        let networks = elasticsearch.networks.as_ref().unwrap();
+        let elastic = networks.get("elastic").unwrap().as_ref().unwrap();
+        assert_eq!(elastic.aliases.as_ref().unwrap(), &vec!["es".to_string()]);
+        assert_eq!(elastic.ipv4_address, Some(Ipv4Addr::new(172, 16, 238, 10)));
+        assert_eq!(elastic.priority, Some(100));
+        assert_eq!(networks.get("monitoring"), Some(&None));
+
+        let kibana = compose.services.get("kibana").unwrap();
+        assert_eq!(kibana.network_names(), vec!["elastic"]);
+
+        // Network aliases are reachable names for flow inference
+        assert!(compose.validate().is_valid());
+        assert_eq!(compose.infer_flows().len(), 1);
+        assert_eq!(compose.infer_flows()[0].target, "elasticsearch");
+    }
+
+    #[test]
+    fn test_validate_service_networks() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx
+    networks:
+      front:
+        aliases: [www]
+      default:
+      back:
+networks:
+  front:
+    driver: bridge
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let report = compose.validate();
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].message, "Referenced network 'back' not found for service 'web'");
+    }
+
+    #[test]
+    fn test_validate_unknown_references_suggest_names() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx
+    depends_on: [backnd, queue]
+    networks: [fronted]
+    volumes: ["cach:/var/cache/nginx", "logs:/var/log/nginx"]
+  backend:
+    image: api
+networks:
+  frontend: {}
+  frontend2: {}
+volumes:
+  cache: {}
+"#;
+        let compose = parse_composefiles_unvalidated(vec![Box::new(yaml.as_bytes())], &Default::default()).unwrap();
+        let mut messages: Vec<String> = compose.validate().errors.into_iter().map(|error| error.message).collect();
+        messages.sort();
+        assert_eq!(
+            messages,
+            vec![
+                "Referenced network 'fronted' not found for service 'web', did you mean 'frontend' or 'frontend2'?",
+                "Referenced service 'backnd' in depends_on not found for service 'web', did you mean 'backend'?",
+                "Referenced service 'queue' in depends_on not found for service 'web'",
+                "Referenced volume 'cach' not found for service 'web', did you mean 'cache'?",
+                "Referenced volume 'logs' not found for service 'web'",
+            ]
+        );
+        // Without the document parsed, which volumes are declared isn't known
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        assert!(compose.validate().errors.iter().all(|error| error.code != "unknown-volume"));
+    }
+
+    #[test]
+    fn test_get_service_exposed_ports() {
+        let dockerfile = "FROM nginx\nEXPOSE 8080\nEXPOSE 9090/udp\n";
+        let container = crate::parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "web").unwrap();
+        let yaml = r#"
+services:
+  web:
+    ports:
+      - "80:8080"
+      - "127.0.0.1:9443:443"
+      - "53"
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let ports = compose.get_service_exposed_ports("web", Some(&container));
+        assert_eq!(
+            ports,
+            vec![
+                PortInfo { container_port: 8080, host_port: Some(80), protocol: Protocol::Tcp, published: true, name: None },
+                PortInfo { container_port: 443, host_port: Some(9443), protocol: Protocol::Tcp, published: true, name: None },
+                PortInfo { container_port: 53, host_port: None, protocol: Protocol::Tcp, published: true, name: None },
+                PortInfo { container_port: 9090, host_port: None, protocol: Protocol::Udp, published: false, name: None },
+            ]
+        );
+
+        assert_eq!(compose.get_service_exposed_ports("web", None).len(), 3);
+        assert_eq!(compose.get_service_exposed_ports("missing", Some(&container)).len(), 0);
+    }
+
+    #[test]
+    fn test_depends_on_accessors() {
+        let compose = parse_str(
+            "services:\n  web:\n    depends_on: [db, cache]\n  api:\n    depends_on:\n      queue:\n        condition: service_started\n      db:\n        condition: service_healthy\n",
+        )
+        .unwrap();
+        let list = compose.services["web"].depends_on.as_ref().unwrap();
+        assert_eq!(list.all_dependencies(), vec!["db", "cache"]);
+        assert_eq!(list.condition_for("db"), None);
+
+        let map = compose.services["api"].depends_on.as_ref().unwrap();
+        assert_eq!(map.all_dependencies(), vec!["db", "queue"]);
+        assert_eq!(map.condition_for("db"), Some("service_healthy"));
+        assert_eq!(map.condition_for("queue"), Some("service_started"));
+        assert_eq!(map.condition_for("cache"), None);
+
+        // The service's accessors unify the list and map forms
+        let (web, api) = (compose.service("web").unwrap(), compose.service("api").unwrap());
+        assert_eq!(web.depends_on_names(), vec!["db", "cache"]);
+        assert_eq!(web.depends_on_condition("db"), Some("service_started"));
+        assert_eq!(api.depends_on_names(), vec!["db", "queue"]);
+        assert_eq!(api.depends_on_condition("db"), Some("service_healthy"));
+        assert_eq!(api.depends_on_condition("cache"), None);
+    }
+
+    #[test]
+    fn test_compose_and_service_accessors() {
+        let compose = parse_str(
+            "services:\n  web:\n    image: nginx\n    ports: ['8080:80', '443']\n    expose: ['9000']\n    environment:\n      MODE: prod\n  db:\n    image: postgres\n",
+        )
+        .unwrap();
+        assert_eq!(compose.services().map(|(name, _)| name).collect::<Vec<_>>(), vec!["db", "web"]);
+        assert!(compose.service("cache").is_none());
+        let web = compose.service("web").unwrap();
+        let published: Vec<(u16, Option<u16>)> = web.published_ports().iter().map(|port| (port.container_port, port.host_port)).collect();
+        assert_eq!(published, vec![(80, Some(8080)), (443, None)]);
+        assert_eq!(web.env("MODE"), Some("prod"));
+        assert_eq!(web.env("DEBUG"), None);
+        assert!(web.depends_on_names().is_empty());
+        assert_eq!(compose.service("db").unwrap().env("MODE"), None);
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let compose: Compose = serde_yaml::from_str(&get_yaml_sample()).unwrap();
+        assert_eq!(compose.dependency_order().unwrap(), vec!["db", "backend", "proxy"]);
+
+        let yaml = r#"
+services:
+  a:
+    depends_on: [b]
+  b:
+    depends_on: [a]
+  c:
+    depends_on: [missing]
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let err = compose.dependency_order().unwrap_err();
+        assert_eq!(err.to_string(), "Dependency cycle involving services: a, b");
+        let report = compose.validate();
+        assert!(report.errors.iter().any(|e| e.service.is_none() && e.message == err.to_string()));
+    }
+
+    #[test]
+    fn test_start_waves() {
+        let compose: Compose = serde_yaml::from_str(&get_yaml_sample()).unwrap();
+        let waves = compose.start_waves().unwrap();
+        let names: Vec<Vec<&str>> = waves.iter().map(|w| w.services.iter().map(|s| s.name.as_str()).collect()).collect();
+        assert_eq!(names, vec![vec!["db"], vec!["backend"], vec!["proxy"]]);
+        assert!(waves[0].services[0].healthcheck);
+        assert_eq!(waves[1].to_string(), "Wave 2:\n  backend (no healthcheck)\n");
+        assert_eq!(
+            serde_json::to_string(&waves[0]).unwrap(),
+            r#"{"wave":1,"services":[{"name":"db","healthcheck":true}]}"#
+        );
+
+        let compose: Compose = serde_yaml::from_str("services:\n  a:\n    depends_on: [b]\n  b:\n    depends_on: [c]\n  c:\n    depends_on: [b]\n").unwrap();
+        assert_eq!(compose.start_waves().unwrap_err().to_string(), "Dependency cycle: b -> c -> b");
+    }
+
+    fn healthcheck_errors(healthcheck: &str) -> Vec<String> {
+        let yaml = format!("services:\n  web:\n    image: nginx\n    healthcheck:\n      test: [\"CMD\", \"true\"]\n{}", healthcheck);
+        let compose: Compose = serde_yaml::from_str(&yaml).unwrap();
+        compose.validate().errors.into_iter().map(|e| e.message).collect()
+    }
+
+    #[test]
+    fn test_validate_healthcheck_values() {
+        assert!(healthcheck_errors("      retries: 3\n      interval: 30s\n      timeout: 1m30s\n      start_period: 5s\n").is_empty());
+        assert_eq!(
+            healthcheck_errors("      retries: 0\n"),
+            vec!["Invalid healthcheck.retries value 0 for service 'web': must be a positive integer"]
+        );
+        assert_eq!(
+            healthcheck_errors("      retries: -1\n"),
+            vec!["Invalid healthcheck.retries value -1 for service 'web': must be a positive integer"]
+        );
+        assert_eq!(
+            healthcheck_errors("      interval: 0s\n"),
+            vec!["Invalid healthcheck.interval value '0s' for service 'web': Docker requires at least 1s"]
+        );
+        assert_eq!(
+            healthcheck_errors("      timeout: abc\n"),
+            vec!["Invalid healthcheck.timeout value 'abc' for service 'web': must be a duration such as '30s'"]
+        );
+    }
+
+    #[test]
+    fn test_validate_healthcheck_minimums() {
+        assert_eq!(
+            healthcheck_errors("      interval: 500ms\n"),
+            vec!["Invalid healthcheck.interval value '500ms' for service 'web': Docker requires at least 1s"]
+        );
+        assert!(healthcheck_errors("      interval: 1s\n").is_empty());
+        assert!(healthcheck_errors("      interval: 30s\n").is_empty());
+        assert_eq!(
+            healthcheck_errors("      timeout: 100ms\n"),
+            vec!["Invalid healthcheck.timeout value '100ms' for service 'web': Docker requires at least 1s"]
+        );
+        assert!(healthcheck_errors("      start_period: 0s\n").is_empty());
+
+        let compose: Compose = serde_yaml::from_str("services:\n  web:\n    image: nginx\n    healthcheck:\n      test: []\n").unwrap();
+        let errors: Vec<String> = compose.validate().errors.into_iter().map(|e| e.message).collect();
+        assert_eq!(errors, vec!["Invalid healthcheck.test for service 'web': must not be empty"]);
+    }
+
+    #[test]
+    fn test_validate_volumes() {
+        let yaml = r#"
+services:
+  db:
+    image: mysql
+    volumes:
+      - dbdata:/var/lib/mysql
+      - ~/backups:/backups:ro
+      - ./conf:conf
+"#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let report = compose.validate();
+        let errors: Vec<&str> = report.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(errors, vec!["Invalid volume './conf:conf': the target 'conf' is not an absolute path in service 'db'"]);
+        assert!(report.warnings.iter().any(|w| w.message.contains("'~/backups:/backups:ro'")));
+        let mount_points: Vec<String> = compose.services["db"].mount_points().into_iter().map(|m| m.mount_point).collect();
+        assert_eq!(mount_points, vec!["/var/lib/mysql", "/backups"]);
+    }
+
+    #[test]
+    fn test_validate_volume_overlaps() {
+        let yaml = r#"
+services:
+  app:
+    image: app
+    volumes:
+      - data:/data
+      - ./cache:/data/cache
+      - logs:/var/log/
+      - ./more-logs:/var/log:ro
+      - ./nginx.conf:/etc/nginx/conf.d/
+      - ./conf.d:/etc/conf.d/
+      - ./app.conf:/etc/app.conf
+"#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let report = compose.validate();
+        assert_eq!(
+            report.diagnostics(),
+            vec![
+                "error[duplicate-volume-target]: Service 'app' mounts both 'logs:/var/log/' and './more-logs:/var/log:ro' at /var/log",
+                "warning[nested-volume-target]: Service 'app' mounts './cache:/data/cache' inside 'data:/data', so what's visible at /data/cache depends on the order they're mounted",
+                "note[file-on-directory-mount]: Service 'app' mounts './nginx.conf:/etc/nginx/conf.d/', whose source looks like a file but whose target looks like a directory",
+            ]
+        );
+        assert_eq!(report.errors[0].service.as_deref(), Some("app"));
+        assert_eq!(report.warnings[0].service.as_deref(), Some("app"));
+        assert_eq!(report.notes[0].service.as_deref(), Some("app"));
+
+        // Siblings sharing a prefix aren't nested
+        let siblings: Compose = serde_yaml::from_str("services:\n  app:\n    image: app\n    volumes: [/data, /data2, /datastore/x]\n").unwrap();
+        assert!(siblings.validate().diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_validate_windows_volume_targets() {
+        let yaml = r#"
+services:
+  win:
+    image: mcr.microsoft.com/windows/servercore:ltsc2022
+    platform: windows/amd64
+    volumes:
+      - C:\data:C:\app
+      - logs:c:/app/Logs
+      - cache:C:\APP\logs
+  linux:
+    image: app
+    volumes:
+      - C:\data:C:\app
+"#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        assert!(compose.services["win"].is_windows());
+        let report = compose.validate();
+        assert_eq!(
+            report.diagnostics(),
+            vec![
+                r"error[invalid-volume]: Invalid volume 'C:\data:C:\app' in service 'linux': the target 'C:\app' is not an absolute path in a Linux container",
+                r"error[duplicate-volume-target]: Service 'win' mounts both 'logs:c:/app/Logs' and 'cache:C:\APP\logs' at C:\APP\logs",
+                r"warning[nested-volume-target]: Service 'win' mounts 'logs:c:/app/Logs' inside 'C:\data:C:\app', so what's visible at c:/app/Logs depends on the order they're mounted",
+                r"warning[nested-volume-target]: Service 'win' mounts 'cache:C:\APP\logs' inside 'C:\data:C:\app', so what's visible at C:\APP\logs depends on the order they're mounted",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_hostnames() {
+        let errors = |yaml: &str| {
+            let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+            compose.validate().errors.into_iter().map(|e| e.message).collect::<Vec<_>>()
+        };
+        assert!(errors("services:\n  a:\n    image: app\n    hostname: api\n  b:\n    image: app\n    hostname: db.internal\n").is_empty());
+        assert_eq!(
+            errors("services:\n  b:\n    image: app\n    hostname: api\n  a:\n    image: app\n    hostname: api\n  c:\n    image: app\n    hostname: api\n"),
+            vec!["Services 'a' and 'b' both use hostname 'api'", "Services 'a' and 'c' both use hostname 'api'"]
+        );
+        assert_eq!(
+            errors("services:\n  a:\n    image: app\n    hostname: Api_1\n"),
+            vec!["Invalid hostname 'Api_1' for service 'a': 'Api_1' contains 'A', but DNS labels may only contain lowercase letters, digits and hyphens"]
+        );
+    }
+
+    #[test]
+    fn test_validate_stop_signal() {
+        let errors = |stop_signal: &str| {
+            let yaml = format!("services:\n  web:\n    image: nginx\n    stop_signal: \"{}\"\n", stop_signal);
+            let compose: Compose = serde_yaml::from_str(&yaml).unwrap();
+            compose.validate().errors.into_iter().map(|e| e.message).collect::<Vec<_>>()
+        };
+        assert!(errors("SIGTERM").is_empty());
+        assert!(errors("SIGKILL").is_empty());
+        assert!(errors("15").is_empty());
+        assert_eq!(errors("SIGFOO"), vec!["Invalid stop_signal value 'SIGFOO' for service 'web': not a known signal name"]);
+        assert_eq!(errors("0"), vec!["Invalid stop_signal value '0' for service 'web': signal numbers must be from 1 to 64"]);
+    }
+
+    #[test]
+    fn test_validate_replicas_published_ports() {
+        let yaml = r#"
+services:
+  web:
+    deploy:
+      replicas: 3
+    ports:
+      - "80"
+  api:
+    deploy:
+      replicas: 3
+    ports:
+      - "8080:80"
+  single:
+    ports:
+      - "8081:80"
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(compose.services["web"].replicas(), Some(3));
+        assert_eq!(compose.services["single"].replicas(), None);
+        let report = compose.validate();
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].service.as_deref(), Some("api"));
+        assert_eq!(
+            report.warnings[0].message,
+            "Service 'api' has 3 replicas but publishes fixed host port 8080, which would conflict at runtime"
+        );
+    }
+
+    #[test]
+    fn test_validate_invalid_ports() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx
+    ports: ["8080:80/bogus", "5000:5000/sctp", "9000-9010:9000"]
+    expose: ["70000"]
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let messages: Vec<String> = compose.validate().errors.into_iter().filter(|e| e.code == "invalid-port").map(|e| e.message).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Invalid ports entry '8080:80/bogus' for service 'web': unsupported protocol 'bogus', expected tcp, udp or sctp",
+                "Invalid expose entry '70000' for service 'web': '70000' is not a port number",
+            ]
+        );
+        let ports = compose.services["web"].published_ports();
+        assert_eq!(ports.len(), 1);
+        assert_eq!((ports[0].container_port, ports[0].protocol), (5000, Protocol::Sctp));
+    }
+
+    #[test]
+    fn test_validate_image_availability() {
+        use std::io::{BufRead, Write};
+        use std::net::TcpListener;
+
+        // A registry holding only app:1.0
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let registry = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(3) {
+                let mut stream = stream.unwrap();
+                let mut lines = std::io::BufReader::new(&stream).lines().map(Result::unwrap);
+                let request = lines.next().unwrap();
+                lines.take_while(|line| !line.is_empty()).for_each(drop);
+                let status = match request.split(' ').nth(1).unwrap() {
+                    "/v2/app/manifests/1.0" => "200 OK",
+                    "/v2/app/manifests/3.0" => "503 Service Unavailable",
+                    _ => "404 Not Found",
+                };
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+        });
+        let yaml = format!(
+            "services:\n  found:\n    image: {registry}/app:1.0\n  missing:\n    image: {registry}/app:2.0\n  overloaded:\n    image: {registry}/app:3.0\n  remote:\n    image: registry.invalid/app\n  web:\n    image: Web:1.0\n"
+        );
+        let compose = parse_str(&yaml).unwrap();
+        let warnings = compose.validate_image_availability(Duration::from_secs(5));
+        let messages: Vec<String> = warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(warnings.len(), 4);
+        assert_eq!(messages[0], format!("Image '{registry}/app:2.0' of service 'missing' was not found in registry '{registry}'"));
+        assert_eq!(warnings[0].service.as_deref(), Some("missing"));
+        // Only a success means the image exists
+        assert_eq!(messages[1], format!("Can't check image '{registry}/app:3.0' of service 'overloaded': registry '{registry}' answered with HTTP status 503"));
+        // A remote registry which can't be reached, over HTTPS or at all
+        assert_eq!((warnings[2].service.as_deref(), warnings[2].code), (Some("remote"), "image-unchecked"));
+        #[cfg(not(feature = "https"))]
+        assert_eq!(messages[2], "Can't check image 'registry.invalid/app' of service 'remote': registry 'registry.invalid' needs HTTPS, which this build doesn't support");
+        // A malformed reference is a warning for its service, and the others are still checked
+        assert_eq!(messages[3], "Can't check image 'Web:1.0' of service 'web': it isn't a valid image reference");
+        assert_eq!(warnings[3].code, "image-unchecked");
+    }
+
+    #[test]
+    fn test_services_matching_labels() {
+        let compose = parse_composefile(Box::new(include_str!("../tests/fixtures/labels.yml").as_bytes())).unwrap().value;
+        for (selector, expected) in [
+            ("tier=backend", vec!["api", "db"]),
+            ("tier==frontend", vec!["web"]),
+            ("env!=prod", vec!["api", "worker"]),
+            ("env in (staging,prod)", vec!["api", "db", "web"]),
+            ("env notin (prod)", vec!["api", "worker"]),
+            ("app.kubernetes.io/component", vec!["db"]),
+            ("!tier", vec!["worker"]),
+            ("tier=backend,env=prod", vec!["db"]),
+            ("", vec!["api", "db", "web", "worker"]),
+        ] {
+            let selector: LabelSelector = selector.parse().unwrap();
+            assert_eq!(compose.services_matching(&selector), expected, "{}", selector);
         }
-    
-        // Since no other properties are defined for the backend service in the provided YAML, 
-        // we'll check that they are set to their default values (i.e., None or empty).
-        assert!(service.image.is_none());
-        assert!(service.restart.is_none());
-        assert!(service.env_file.is_none());
-        assert!(service.logging.is_none());
-        assert!(service.ports.is_none());
-        assert!(service.networks.is_none());
-        assert!(service.volumes.is_none());
-        assert!(service.dns.is_none());
-        assert!(service.hostname.is_none());
-        assert!(service.environment.is_none());
-        assert!(service.extra_hosts.is_none());
-        assert!(service.healthcheck.is_none());
+
+        // Dependencies on the services left out go with them
+        let mut backend = parse_composefile(Box::new(include_str!("../tests/fixtures/labels.yml").as_bytes())).unwrap().value;
+        backend.retain_services_matching(&"tier in (backend)".parse().unwrap());
+        let mut names: Vec<&String> = backend.services.keys().collect();
+        names.sort();
+        assert_eq!(names, ["api", "db"]);
+        assert_eq!(backend.services["api"].depends_on.as_ref().unwrap().all_dependencies(), vec!["db"]);
+        let mut frontend = parse_composefile(Box::new(include_str!("../tests/fixtures/labels.yml").as_bytes())).unwrap().value;
+        frontend.retain_services_matching(&"tier=frontend".parse().unwrap());
+        assert!(frontend.services["web"].depends_on.as_ref().unwrap().all_dependencies().is_empty());
+        assert!(frontend.validate().is_valid());
     }
-    
-    fn check_proxy_service(service: &Service) {
-        let depends_on = service.depends_on.as_ref().unwrap();
-        match depends_on {
-            DependsOn::List(services) => {
-                assert_eq!(services.len(), 1);
-                assert_eq!(services[0], "backend");
+
+    #[test]
+    fn test_image_consumers() {
+        let compose = parse_str(
+            "services:\n  a:\n    image: nginx\n  b:\n    image: docker.io/library/nginx:latest\n  c:\n    image: nginx:1.25\n  d:\n    build: .\n",
+        )
+        .unwrap();
+        let consumers = compose.image_consumers(&HashMap::new(), true);
+        assert_eq!(consumers["docker.io/library/nginx:latest"], vec!["a", "b"]);
+        assert_eq!(consumers["docker.io/library/nginx:1.25"], vec!["c"]);
+        assert_eq!(consumers.len(), 2);
+
+        // Compared as written, each form of the reference is an image of its own
+        let consumers = compose.image_consumers(&HashMap::new(), false);
+        assert_eq!(consumers["nginx"], vec!["a"]);
+        assert_eq!(consumers["docker.io/library/nginx:latest"], vec!["b"]);
+        assert_eq!(consumers["nginx:1.25"], vec!["c"]);
+        assert_eq!(consumers.len(), 3);
+    }
+
+    #[test]
+    fn test_name_table() {
+        let yaml = r#"
+services:
+  api:
+    hostname: api-host
+    networks:
+      backend:
+        aliases: [service]
+      frontend:
+  worker:
+    networks:
+      backend:
+        aliases: [service]
+  web:
+    container_name: web-1
+    networks:
+      frontend:
+        aliases: [service]
+  batch:
+    environment:
+      API_URL: http://service:8080
+"#;
+        let compose = parse_str(yaml).unwrap();
+        let table = compose.name_table();
+        let services = |network: &str, name: &str| table[network][name].iter().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(services("backend", "service"), vec!["api", "worker"]);
+        assert_eq!(services("backend", "api-host"), vec!["api"]);
+        assert_eq!(services("frontend", "service"), vec!["web"]);
+        assert_eq!(services("frontend", "web-1"), vec!["web"]);
+        assert_eq!(services("default", "batch"), vec!["batch"]);
+        assert!(!table["frontend"].contains_key("worker"));
+
+        // The same alias on one network collides, on different networks it doesn't
+        let warnings: Vec<String> = compose.validate().warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(warnings, vec!["Name 'service' on network 'backend' is claimed by services 'api' and 'worker'"]);
+        // batch isn't attached to a network with a 'service' name
+        assert!(compose.infer_flows().is_empty());
+    }
+
+    #[test]
+    fn test_service_aliases() {
+        let yaml = r#"
+x-common: &common
+  restart: always
+services:
+  worker1: &worker1
+    image: worker:1.0
+    environment:
+      QUEUE: jobs
+  worker2: *worker1
+  "worker3":
+    <<: *worker1
+    image: worker:2.0
+  other:
+    <<: *common
+    image: other:1.0
+"#;
+        let compose = parse_str(yaml).unwrap();
+        let worker2 = &compose.services["worker2"];
+        assert_eq!(worker2.derived_from.as_deref(), Some("worker1"));
+        assert_eq!(worker2.image.as_deref(), Some("worker:1.0"));
+        // The merge key is applied, with the service's own fields overriding the alias
+        let worker3 = &compose.services["worker3"];
+        assert_eq!(worker3.derived_from.as_deref(), Some("worker1"));
+        assert_eq!(worker3.image.as_deref(), Some("worker:2.0"));
+        assert_eq!(worker3.environment.as_ref().unwrap()["QUEUE"], "jobs");
+        // Aliases of anything other than a service aren't derivations
+        assert_eq!(compose.services["other"].derived_from, None);
+        assert_eq!(compose.services["other"].restart.as_deref(), Some("always"));
+        assert_eq!(compose.services["worker1"].derived_from, None);
+    }
+
+    #[test]
+    fn test_scale() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx
+    scale: 3
+  worker:
+    image: worker
+    scale: 2
+    deploy:
+      replicas: 4
+  db:
+    image: postgres
+"#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(compose.total_container_count(), 8);
+        let report = compose.validate();
+        assert!(report.is_valid());
+        assert_eq!(
+            report.warnings[0].message,
+            "Service 'worker' sets both scale (2) and deploy.replicas (4); deploy.replicas takes precedence in Swarm mode"
+        );
+
+        let compose: Compose = serde_yaml::from_str("services:\n  web:\n    image: nginx\n    scale: 0\n").unwrap();
+        let errors: Vec<String> = compose.validate().errors.into_iter().map(|e| e.message).collect();
+        assert_eq!(errors, vec!["Invalid scale value 0 for service 'web': must be at least 1"]);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx
+  debug:
+    image: busybox
+    profiles: [dev]
+  tester:
+    image: tester
+    profiles: [test]
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(compose.profiles_used(), HashSet::from(["dev", "test"]));
+        assert_eq!(compose.services_for_profile("dev"), vec!["debug"]);
+        assert_eq!(compose.services_for_profile("test"), vec!["tester"]);
+        assert!(compose.services_for_profile("prod").is_empty());
+        assert_eq!(compose.always_active_services(), vec!["web"]);
+    }
+
+    #[test]
+    fn test_activate_profiles() {
+        let yaml = r#"
+services:
+  web:
+    image: nginx
+    depends_on:
+      api:
+        condition: service_healthy
+      metrics:
+        condition: service_started
+        required: false
+  api:
+    image: api
+    profiles: [backend]
+    depends_on: [db]
+  db:
+    image: postgres
+    profiles: [data]
+  metrics:
+    image: prometheus
+    profiles: [monitoring]
+  debug:
+    image: busybox
+    profiles: [dev]
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let names = |activation: &ProfileActivation| activation.services.iter().map(String::as_str).collect::<Vec<_>>().join(",");
+
+        // The required dependency is activated, and so is its own, a profile further on
+        let activation = compose.activate_profiles(&[], true).unwrap();
+        assert_eq!(names(&activation), "api,db,web");
+        assert_eq!(
+            activation.auto_activated.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "Service 'api' (profiles backend) is activated as 'web' requires it",
+                "Service 'db' (profiles data) is activated as 'api' requires it",
+            ]
+        );
+        // The optional dependency isn't
+        assert_eq!(
+            activation.skipped.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["Service 'web' depends on 'metrics', which isn't started as its profiles monitoring are inactive and the dependency isn't required"]
+        );
+        // Each is reported with a code, so can be allowed and denied
+        let report = activation.report();
+        assert_eq!(report.notes.iter().map(|note| note.code).collect::<Vec<_>>(), ["profile-auto-activated"; 2]);
+        assert_eq!(report.warnings[0].code, "inactive-optional-dependency");
+        assert_eq!(report.warnings[0].field.as_deref(), Some("services.web.depends_on.metrics"));
+
+        // Services already active aren't auto-activated
+        let activation = compose.activate_profiles(&["backend".to_string(), "monitoring".to_string()], true).unwrap();
+        assert_eq!(names(&activation), "api,db,metrics,web");
+        assert_eq!(activation.auto_activated.len(), 1);
+        assert!(activation.skipped.is_empty());
+
+        let err = compose.activate_profiles(&["backend".to_string()], false).unwrap_err();
+        assert_eq!(err.to_string(), "Service 'api' requires 'db', which is only in the inactive profiles data");
+        assert!(compose.activate_profiles(&["backend".to_string(), "data".to_string()], false).is_ok());
+
+        let mut retained: Compose = serde_yaml::from_str(yaml).unwrap();
+        retained.retain_active_services(&["dev".to_string()], true).unwrap();
+        assert_eq!(retained.services().map(|(name, _)| name).collect::<Vec<_>>(), vec!["api", "db", "debug", "web"]);
+        assert_eq!(retained.services["web"].depends_on_names(), vec!["api"]);
+        assert_eq!(retained.services["web"].depends_on_required("api"), Some(true));
+    }
+
+    #[test]
+    fn test_dns_servers() {
+        let yaml = r#"
+services:
+  web:
+    dns:
+      - 8.8.8.8
+      - 10.0.0.2:5353
+    networks: [backend]
+  worker:
+    dns: 1.1.1.1
+  db:
+    image: postgres
+networks:
+  backend:
+    internal: true
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let expected: HashSet<SocketAddr> = ["8.8.8.8:53", "10.0.0.2:5353", "1.1.1.1:53"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        assert_eq!(compose.get_dns_servers(), expected);
+        assert_eq!(compose.services_with_custom_dns(), vec!["web", "worker"]);
+
+        let report = compose.validate();
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(
+            report.warnings[0].message,
+            "Service 'web' uses public DNS server 8.8.8.8 but is only attached to internal networks"
+        );
+
+        assert!(serde_yaml::from_str::<Compose>("services:\n  web:\n    dns: not-an-address\n").is_err());
+    }
+
+    #[test]
+    fn test_validate_published_port_not_exposed() {
+        let yaml = r#"
+services:
+  web:
+    expose: ["8080"]
+    ports:
+      - "8000:8000"
+  api:
+    ports:
+      - "9000:9000"
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let dockerfile = "FROM nginx\nEXPOSE 8080\n";
+        let container = crate::parse_dockerfile::parse_containerfile(Box::new(dockerfile.as_bytes()), "web").unwrap();
+        let containers = HashMap::from([("web".to_string(), container)]);
+
+        let report = compose.validate_with_containers(&containers);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(
+            report.warnings[0].message,
+            "Service 'web' publishes port 8000 which is neither EXPOSEd by its Dockerfile nor listed in expose (8080); check for a typo"
+        );
+        // Without its Dockerfile the expose list alone is checked
+        assert_eq!(compose.validate().warnings, report.warnings);
+    }
+
+    #[test]
+    fn test_service_label_accessors() {
+        let yaml = r#"
+services:
+  web:
+    labels:
+      - "com.example.tier=frontend"
+  db:
+    image: postgres
+        "#;
+        let compose: Compose = serde_yaml::from_str(yaml).unwrap();
+        let web = &compose.services["web"];
+        assert_eq!(web.label_value("com.example.tier"), Some("frontend"));
+        assert_eq!(web.label_value("missing"), None);
+        assert!(web.has_label("com.example.tier"));
+        assert!(!web.has_label("missing"));
+        assert_eq!(web.label_keys().collect::<Vec<_>>(), vec!["com.example.tier"]);
+
+        let db = &compose.services["db"];
+        assert_eq!(db.label_value("com.example.tier"), None);
+        assert!(!db.has_label("com.example.tier"));
+        assert_eq!(db.label_keys().count(), 0);
+    }
+
+    fn parse_str(yaml: &str) -> Result<Compose> {
+        parse_composefile(Box::new(std::io::Cursor::new(yaml.to_string()))).map(|result| result.value)
+    }
+
+    #[test]
+    fn test_extends() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("common.yml"), "services:\n  base:\n    image: app:1.0\n    environment:\n      LOG: info\n").unwrap();
+        let yaml = r#"
+services:
+  web:
+    extends:
+      file: common.yml
+      service: base
+    ports: ["80:80"]
+  worker:
+    extends: web
+    environment:
+      ROLE: worker
+"#;
+        let options = ParseOptions { base_dir: Some(dir.path().to_path_buf()), ..Default::default() };
+        let compose = parse_composefiles_with_options(vec![Box::new(std::io::Cursor::new(yaml))], &options).unwrap();
+        assert_eq!(compose.included_files, vec![dir.path().join("common.yml").to_string_lossy()]);
+        let worker = &compose.services["worker"];
+        assert_eq!(worker.image.as_deref(), Some("app:1.0"));
+        assert_eq!(worker.ports, Some(vec!["80:80".to_string()]));
+        let environment = worker.environment.as_ref().unwrap();
+        assert_eq!((environment["LOG"].as_str(), environment["ROLE"].as_str()), ("info", "worker"));
+    }
+
+    #[test]
+    fn test_extends_cycle() {
+        let err = parse_str("services:\n  a:\n    extends: b\n  b:\n    extends:\n      service: a\n").unwrap_err();
+        let Some(CargotectureError::Validation(message)) = err.downcast_ref::<CargotectureError>() else {
+            panic!("expected a validation error, got {}", err);
+        };
+        assert_eq!(message, "Cyclic extends: a -> b -> a");
+
+        let err = parse_str("services:\n  a:\n    extends: a\n").unwrap_err();
+        assert_eq!(err.to_string(), "Cyclic extends: a -> a");
+    }
+
+    #[test]
+    fn test_extends_depth() {
+        let chain = |length: usize| {
+            let mut yaml = "services:\n  s0:\n    image: base\n".to_string();
+            for i in 1..=length {
+                yaml.push_str(&format!("  s{}:\n    extends: s{}\n", i, i - 1));
             }
-            _ => panic!("Unexpected DependsOn variant"),
-        }
-    
-        // Since no other properties are defined for the proxy service in the provided YAML,
-        // we'll check that they are set to their default values (i.e., None or empty).
-        assert!(service.image.is_none());
-        assert!(service.restart.is_none());
-        assert!(service.env_file.is_none());
-        assert!(service.logging.is_none());
-        assert!(service.ports.is_none());
-        assert!(service.networks.is_none());
-        assert!(service.volumes.is_none());
-        assert!(service.dns.is_none());
-        assert!(service.hostname.is_none());
-        assert!(service.environment.is_none());
-        assert!(service.extra_hosts.is_none());
-        assert!(service.healthcheck.is_none());
+            yaml
+        };
+        assert_eq!(parse_str(&chain(10)).unwrap().services["s10"].image.as_deref(), Some("base"));
+        let err = parse_str(&chain(11)).unwrap_err();
+        assert_eq!(err.to_string(), "The extends chain of service 's11' is longer than the maximum of 10");
+
+        let options = ParseOptions { max_extends_depth: 2, ..Default::default() };
+        assert!(parse_composefiles_with_options(vec![Box::new(std::io::Cursor::new(chain(3)))], &options).is_err());
+        assert!(parse_str("services:\n  a:\n    extends: missing\n").is_err());
     }
 
     #[test]
-    fn test_deserialization_sample() {
-        let yaml_str = get_yaml_sample();
-        let compose: Compose = serde_yaml::from_str(&yaml_str).unwrap();
-        let services = compose.services;
+    fn test_unknown_fields_and_extensions() {
+        let err = parse_str("services:\n  web:\n    image: nginx\n    restartt: always\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `restartt`, did you mean 'restart'?"), "{}", err);
+        let err = parse_str("services:\n  web:\n    image: nginx\n    colour: blue\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `colour`, expected one of"), "{}", err);
+        assert!(parse_str("servics:\n  web:\n    image: nginx\n").is_err());
+        assert!(parse_str("services: {}\nnetworks:\n  back:\n    drivr: bridge\n").is_err());
 
-        check_db_service(services.get("db").unwrap());
-        check_backend_service(services.get("backend").unwrap());
-        check_proxy_service(services.get("proxy").unwrap());
+        let yaml = r#"
+x-logging: &logging
+  driver: json-file
+services:
+  web:
+    image: nginx
+    x-custom: value
+    build: .
+networks:
+  back:
+    x-team: platform
+    external: true
+"#;
+        let compose = parse_str(yaml).unwrap();
+        assert_eq!(compose.extensions["x-logging"]["driver"].as_str(), Some("json-file"));
+        let web = &compose.services["web"];
+        assert_eq!(web.extensions.get("x-custom").and_then(|v| v.as_str()), Some("value"));
+        assert_eq!(web.image.as_deref(), Some("nginx"));
+        assert_eq!(compose.networks.as_ref().unwrap()["back"].extensions["x-team"].as_str(), Some("platform"));
     }
 
     #[test]
-    fn test_deserialization_elk() {
-        let yaml_str = get_yaml_elk();
-        let compose: Compose = serde_yaml::from_str(&yaml_str).unwrap();
-        let services = compose.services;
+    fn test_device_requests() {
+        let compose = parse_str(include_str!("../tests/fixtures/cuda.yml")).unwrap();
+        assert!(compose.validate().warnings.is_empty());
 
-        check_elasticsearch_service(services.get("elasticsearch").unwrap());
-        check_logstash_service(services.get("logstash").unwrap());
-        check_kibana_service(services.get("kibana").unwrap());
+        let trainer = compose.services["trainer"].device_requests();
+        assert_eq!(trainer, vec![DeviceRequest {
+            source: DeviceRequestSource::Reservation,
+            driver: Some("nvidia".to_string()),
+            count: Some(DeviceCount::Count(1)),
+            device_ids: Vec::new(),
+            capabilities: vec!["gpu".to_string()],
+        }]);
+        assert!(!trainer[0].all_devices());
+
+        let notebook = compose.services["notebook"].device_requests();
+        let legacy = compose.services["legacy"].device_requests();
+        assert_eq!(notebook.len(), 1);
+        assert_eq!((notebook[0].source, legacy[0].source), (DeviceRequestSource::Gpus, DeviceRequestSource::Runtime));
+        assert_eq!(DeviceRequest { source: DeviceRequestSource::Runtime, ..notebook[0].clone() }, legacy[0]);
+        assert!(legacy[0].all_devices());
+
+        let inference = &compose.services["inference"].device_requests()[0];
+        assert_eq!(inference.device_ids, vec!["0", "3"]);
+        assert_eq!(inference.count, None);
+        assert!(!inference.all_devices());
+    }
+
+    #[test]
+    fn test_validate_device_requests() {
+        let report = |devices: &str| {
+            let yaml = format!(
+                "services:\n  gpu:\n    image: pytorch/pytorch\n    runtime: nvidia\n    deploy:\n      resources:\n        reservations:\n          devices:\n{}",
+                devices
+            );
+            let compose: Compose = serde_yaml::from_str(&yaml).unwrap();
+            let report = compose.validate();
+            (
+                report.errors.into_iter().map(|e| e.message).collect::<Vec<_>>(),
+                report.warnings.into_iter().map(|w| w.message).collect::<Vec<_>>(),
+            )
+        };
+        let (errors, warnings) = report("            - driver: nvidia\n              count: all\n              capabilities: [gpu]\n");
+        assert!(errors.is_empty() && warnings.is_empty());
+        assert_eq!(
+            report("            - driver: nvidia\n              count: 0\n              capabilities: [gpu]\n").0,
+            vec!["Invalid device count 0 in deploy.resources.reservations.devices of service 'gpu': must be a positive integer or 'all'"]
+        );
+        assert_eq!(
+            report("            - driver: nvidia\n              count: -2\n              capabilities: [gpu]\n").0.len(),
+            1
+        );
+        assert_eq!(
+            report("            - driver: nvidia\n              count: all\n              capabilities: [gpu, warp]\n").1,
+            vec!["Service 'gpu' requests unknown device capability 'warp' in deploy.resources.reservations.devices"]
+        );
+        assert_eq!(
+            report("            - driver: nvidia\n              count: 2\n              capabilities: [gpu]\n").1,
+            vec!["Service 'gpu' requests devices with deploy.resources.reservations.devices and runtime, which disagree"]
+        );
+    }
+
+    #[test]
+    fn test_load_build_containers_with_target() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("app")).unwrap();
+        fs::write(
+            dir.path().join("app/Dockerfile"),
+            "FROM rust:1.75 AS builder\nEXPOSE 9000\nFROM debian:bookworm-slim AS runtime\nEXPOSE 8080\n",
+        ).unwrap();
+        let compose = |target: &str| parse_str(&format!(
+            "services:\n  app:\n    build:\n      context: app\n      target: {}\n  web:\n    image: nginx\n    build: app\n",
+            target
+        )).unwrap();
+
+        let containers = load_build_containers(&compose("builder"), Some(dir.path())).unwrap();
+        let ports: Vec<u16> = compose("builder").get_service_exposed_ports("app", containers.get("app")).iter().map(|p| p.container_port).collect();
+        assert_eq!(ports, vec![9000]);
+        // Without a target the last stage is built
+        assert_eq!(containers["web"].exposed_ports.iter().map(|p| p.port_number).collect::<Vec<_>>(), vec![8080]);
+
+        let err = load_build_containers(&compose("release"), Some(dir.path())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to model the build of service 'app': Build target 'release' not found, the stages are: builder, runtime"
+        );
     }
 }
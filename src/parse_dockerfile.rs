@@ -1,25 +1,24 @@
 // Copyright Andrew Mobbs 2023
-use dockerfile_parser::{Dockerfile, Instruction, Result};
-use crate::get_basename;
-use escape_string;
+use dockerfile_parser::{Dockerfile, Instruction, Result, ShellOrExecExpr, Stage, StageParent, Stages};
+use crate::{
+    error::CargotectureError,
+    util::{canonicalize_volume_path, expand_variables, get_basename, glob_match, parse_port_mapping, shell_quote, split_shell_words},
+};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     fmt::{self, Display, Formatter},
-    fs::File,
+    fs::{self, File},
     io::{BufReader, Read},
+    path::Path,
 };
 
-#[derive(Debug, Deserialize,Serialize,PartialEq)]
+#[derive(Debug, Default, Clone, Copy, Deserialize,Serialize,PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Protocol {
+    #[default]
     Tcp,
     Udp,
-}
-
-impl Default for Protocol {
-    fn default() -> Self {
-        Protocol::Tcp
-    }
+    Sctp,
 }
 
 impl Display for Protocol {
@@ -27,6 +26,7 @@ impl Display for Protocol {
         match self {
             Protocol::Tcp => write!(f, "TCP"),
             Protocol::Udp => write!(f, "udp"),
+            Protocol::Sctp => write!(f, "SCTP"),
         }
     }
 }
@@ -35,23 +35,396 @@ impl Display for Protocol {
 #[derive(Debug, Deserialize,Serialize,PartialEq)]
 pub struct ExposedPort {
     pub port_number: u16,
-    pub protocol: Protocol
+    pub protocol: Protocol,
+    /// The line of the EXPOSE instruction, when parsed from a Dockerfile
+    #[serde(default)]
+    pub line: Option<usize>,
+}
+/// A command as written in a Dockerfile CMD/ENTRYPOINT or a compose command/entrypoint.
+/// The shell form is run by `/bin/sh -c`, the exec form directly.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum CommandLine {
+    Shell(String),
+    Exec(Vec<String>),
+}
+
+impl CommandLine {
+    pub(crate) fn from_expr(expr: &ShellOrExecExpr) -> Self {
+        match expr {
+            ShellOrExecExpr::Shell(shell) => CommandLine::Shell(shell.to_string().trim().to_string()),
+            ShellOrExecExpr::Exec(exec) => CommandLine::Exec(exec.as_str_vec().iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    /// The arguments of the command, splitting a shell form command into words
+    pub fn as_argv(&self) -> Vec<String> {
+        match self {
+            CommandLine::Shell(command) => split_shell_words(command).unwrap_or_else(|| vec![command.clone()]),
+            CommandLine::Exec(args) => args.clone(),
+        }
+    }
+
+    /// The command as a single line, quoting exec form arguments where the shell would need it
+    pub fn display(&self) -> String {
+        match self {
+            CommandLine::Shell(command) => command.clone(),
+            CommandLine::Exec(args) => args.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" "),
+        }
+    }
 }
+
 #[derive(Debug, Deserialize,Serialize,PartialEq)]
 pub struct VolumeMount {
     pub mount_point: String
 }
-#[derive(Debug, Deserialize,PartialEq)]
+
+impl VolumeMount {
+    /// A mount point, canonicalised so that `/data/` and `/data` are the same mount point
+    pub fn new(mount_point: &str) -> std::result::Result<Self, CargotectureError> {
+        Ok(VolumeMount { mount_point: canonicalize_volume_path(mount_point)? })
+    }
+}
+/// Where a source of an ADD instruction is fetched from
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// A path in the build context, or in the stage or image named by `--from`
+    Local,
+    /// A file downloaded over HTTP
+    Url,
+    /// A git repository, cloned at the ref after any `#`
+    Git,
+}
+
+impl SourceKind {
+    /// The kind of an ADD source as written, by the prefixes and suffix Docker recognises
+    /// git repositories by
+    pub fn of(source: &str) -> Self {
+        let source = source.to_ascii_lowercase();
+        if ["git@", "git://", "ssh://"].iter().any(|prefix| source.starts_with(prefix)) {
+            return SourceKind::Git;
+        }
+        if !source.starts_with("http://") && !source.starts_with("https://") {
+            return SourceKind::Local;
+        }
+        match source.split('#').next().unwrap_or_default().ends_with(".git") {
+            true => SourceKind::Git,
+            false => SourceKind::Url,
+        }
+    }
+
+    pub fn is_remote(self) -> bool {
+        self != SourceKind::Local
+    }
+}
+
+impl Display for SourceKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SourceKind::Local => write!(f, "local"),
+            SourceKind::Url => write!(f, "url"),
+            SourceKind::Git => write!(f, "git"),
+        }
+    }
+}
+
+/// The extensions of the archives ADD extracts into the destination rather than copying
+const ARCHIVE_EXTENSIONS: &[&str] = &[".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz", ".tbz2", ".tar.xz", ".txz"];
+
+/// A COPY or ADD instruction, with its variables expanded
+#[derive(Debug, Serialize, PartialEq)]
+pub struct CopyInstruction {
+    /// `COPY` or `ADD`
+    pub instruction: String,
+    pub sources: Vec<String>,
+    pub destination: String,
+    /// The stage or image named by `--from`, when not copying from the build context
+    pub from: Option<String>,
+    /// The digest given by an ADD's `--checksum`, which the build verifies remote sources against
+    pub checksum: Option<String>,
+    /// The line of the instruction in the Dockerfile
+    pub line: usize,
+}
+
+impl CopyInstruction {
+    /// Whether the sources are read from the build context, and so subject to `.dockerignore`
+    pub fn from_context(&self) -> bool {
+        self.from.is_none()
+    }
+
+    /// Where a source is fetched from. COPY only ever copies local paths.
+    pub fn source_kind(&self, source: &str) -> SourceKind {
+        match self.instruction.as_str() {
+            "ADD" => SourceKind::of(source),
+            _ => SourceKind::Local,
+        }
+    }
+
+    /// The sources an ADD fetches from outside the build, with their kinds
+    pub fn remote_sources(&self) -> Vec<(&str, SourceKind)> {
+        self.sources
+            .iter()
+            .map(|source| (source.as_str(), self.source_kind(source)))
+            .filter(|(_, kind)| kind.is_remote())
+            .collect()
+    }
+
+    /// The local sources an ADD likely extracts into the destination rather than copying.
+    /// Docker goes by the content of the file, so this is a guess from its extension.
+    /// Downloaded archives aren't extracted.
+    pub fn extracted_archives(&self) -> Vec<&str> {
+        self.sources
+            .iter()
+            .filter(|source| self.instruction == "ADD" && self.source_kind(source) == SourceKind::Local)
+            .filter(|source| ARCHIVE_EXTENSIONS.iter().any(|extension| source.to_ascii_lowercase().ends_with(extension)))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// A path in the container filesystem that an instruction puts something at or declares
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PathEntry {
+    /// The absolute path, resolved against the WORKDIR of the instruction when relative
+    pub path: String,
+    /// The instruction, `COPY`, `ADD`, `WORKDIR` or `VOLUME`
+    pub instruction: String,
+    /// The index of the instruction in `ParsedContainer::containerfile`
+    pub index: usize,
+}
+
+/// Package managers, the commands installing packages with them, and what in the same RUN
+/// keeps their cache out of the layer
+const PACKAGE_MANAGERS: &[(&str, &[&str], &[&str])] = &[
+    ("apt-get", &["apt-get install", "apt install"], &["rm -rf /var/lib/apt/lists"]),
+    ("apk", &["apk add"], &["--no-cache", "rm -rf /var/cache/apk"]),
+    ("yum", &["yum install"], &["yum clean all", "rm -rf /var/cache/yum"]),
+    ("dnf", &["dnf install"], &["dnf clean all", "rm -rf /var/cache/dnf"]),
+];
+
+/// The package manager a RUN command installs packages with, if any, and whether the
+/// command also cleans its cache. A best effort guess from the text of the command.
+pub(crate) fn package_install(command: &str) -> Option<(&'static str, bool)> {
+    let command = command.split_whitespace().collect::<Vec<_>>().join(" ");
+    PACKAGE_MANAGERS
+        .iter()
+        .find(|(_, installs, _)| installs.iter().any(|install| command.contains(install)))
+        .map(|(manager, _, cleans)| (*manager, cleans.iter().any(|clean| command.contains(clean))))
+}
+
+/// Simple measures of how a stage builds its layers, for discussing image size
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BuildMetrics {
+    /// The name of the stage, or its index when unnamed
+    pub stage: String,
+    /// The RUN, COPY and ADD instructions of the stage, each of which creates a layer
+    pub layer_instructions: usize,
+    /// The RUN instructions directly after another RUN, which could be merged into it
+    pub mergeable_runs: usize,
+    /// Whether every RUN installing packages cleans the package manager's cache in the
+    /// same RUN. None when no RUN installs packages.
+    pub cache_cleaned: Option<bool>,
+}
+
+impl BuildMetrics {
+    fn of_stage(stage: &Stage) -> Self {
+        let mut metrics = BuildMetrics {
+            stage: stage.name.clone().unwrap_or_else(|| stage.index.to_string()),
+            layer_instructions: 0,
+            mergeable_runs: 0,
+            cache_cleaned: None,
+        };
+        let mut after_run = false;
+        for ins in &stage.instructions {
+            let is_run = matches!(ins, Instruction::Run(_));
+            let creates_layer = match ins {
+                Instruction::Run(_) | Instruction::Copy(_) => true,
+                Instruction::Misc(misc) => misc.instruction.content.eq_ignore_ascii_case("ADD"),
+                _ => false,
+            };
+            if creates_layer {
+                metrics.layer_instructions += 1;
+            }
+            if let Instruction::Run(run) = ins {
+                if after_run {
+                    metrics.mergeable_runs += 1;
+                }
+                if let Some((_, cleaned)) = package_install(&CommandLine::from_expr(&run.expr).display()) {
+                    metrics.cache_cleaned = Some(metrics.cache_cleaned.unwrap_or(true) && cleaned);
+                }
+            }
+            after_run = is_run;
+        }
+        metrics
+    }
+}
+
+/// Resolve a path against a working directory, normalising `.` and `..` components
+pub(crate) fn resolve_path(working_dir: &str, path: &str) -> String {
+    let joined = match path.starts_with('/') {
+        true => path.to_string(),
+        false => format!("{}/{}", working_dir, path),
+    };
+    let mut components: Vec<&str> = Vec::new();
+    for component in joined.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    format!("/{}", components.join("/"))
+}
+
+#[derive(Debug, Default, Deserialize,PartialEq)]
 enum Port {
     Network(ExposedPort),
     Volume(Vec<VolumeMount>),
+    #[default]
     None
 }
-impl Default for Port {
-    fn default() -> Self {
-        Port::None
+/// A stage of a containerfile as written
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StageSource {
+    /// The name given by `FROM ... AS name`, lowercased
+    pub name: Option<String>,
+    /// The indices of the earlier stages it's built `FROM` or copies files `--from`
+    pub dependencies: Vec<usize>,
+    /// Its instructions, `FROM` first, each with the comment lines written above it
+    pub instructions: Vec<String>,
+}
+
+/// The source of a containerfile, stage by stage, from which a Dockerfile building only
+/// some of its stages can be written
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedContainerfile {
+    /// What's written before the first `FROM` other than the comments above it: parser
+    /// directives, global ARGs and comments between them
+    pub preamble: String,
+    pub stages: Vec<StageSource>,
+}
+
+impl ParsedContainerfile {
+    fn from_dockerfile(dockerfile: &Dockerfile) -> Self {
+        let content = &dockerfile.content;
+        let stages = dockerfile.stages();
+        // Parser directives, such as `# syntax=...`, must stay at the top
+        let directives: usize = content
+            .split_inclusive('\n')
+            .take_while(|line| line.trim_start().strip_prefix('#').is_some_and(|directive| directive.contains('=')))
+            .map(str::len)
+            .sum();
+        let preamble_end = dockerfile.global_args.iter().map(|arg| arg.span.end).max().unwrap_or(0).max(directives);
+        let mut end_of_previous = preamble_end;
+        let stages = stages
+            .iter()
+            .map(|stage| {
+                let mut dependencies: Vec<usize> = Vec::new();
+                if let StageParent::Stage(parent) = stage.parent {
+                    dependencies.push(parent);
+                }
+                let copied_from = stage.instructions.iter().filter_map(|ins| match ins {
+                    Instruction::Copy(copy) => copy.flags.iter().find(|flag| flag.name.content == "from"),
+                    _ => None,
+                });
+                for from in copied_from {
+                    // Anything other than an earlier stage is an image
+                    match stages.get(&from.value.content) {
+                        Some(source) if source.index < stage.index => dependencies.push(source.index),
+                        _ => (),
+                    }
+                }
+                dependencies.sort();
+                dependencies.dedup();
+                let instructions = stage
+                    .instructions
+                    .iter()
+                    .map(|ins| {
+                        let span = ins.span();
+                        let comments = content[end_of_previous.min(span.start)..span.start].lines().map(str::trim).filter(|line| line.starts_with('#'));
+                        let mut lines: Vec<&str> = comments.collect();
+                        lines.push(&content[span.start..span.end]);
+                        end_of_previous = span.end;
+                        lines.join("\n")
+                    })
+                    .collect();
+                StageSource { name: stage.name.clone(), dependencies, instructions }
+            })
+            .collect();
+        ParsedContainerfile { preamble: content[..preamble_end].trim_end().to_string(), stages }
     }
+
+    /// A Dockerfile building only the stages named, and the stages they're built from or
+    /// copy from, in their original order. Stages may be named by index, as `--from` allows.
+    pub fn reconstruct(&self, stages: &[&str]) -> std::result::Result<String, CargotectureError> {
+        let mut selected = vec![false; self.stages.len()];
+        let mut pending = Vec::new();
+        for name in stages {
+            let index = match name.parse::<usize>() {
+                Ok(index) if index < self.stages.len() => Some(index),
+                _ => self.stages.iter().position(|stage| stage.name.as_deref() == Some(name.to_ascii_lowercase().as_str())),
+            };
+            pending.push(index.ok_or_else(|| CargotectureError::UnknownStage {
+                target: name.to_string(),
+                available: self.stages.iter().filter_map(|stage| stage.name.clone()).collect(),
+            })?);
+        }
+        while let Some(index) = pending.pop() {
+            if !std::mem::replace(&mut selected[index], true) {
+                pending.extend(&self.stages[index].dependencies);
+            }
+        }
+        // Stages copied from by index are renumbered, as the stages before them may be dropped
+        let mut renumbered = HashMap::new();
+        for (old, _) in selected.iter().enumerate().filter(|(_, selected)| **selected) {
+            renumbered.insert(old, renumbered.len());
+        }
+        let mut sections: Vec<String> = Vec::new();
+        if !self.preamble.is_empty() {
+            sections.push(self.preamble.clone());
+        }
+        for (stage, _) in self.stages.iter().zip(selected).filter(|(_, selected)| *selected) {
+            let instructions: Vec<String> = stage.instructions.iter().map(|ins| renumber_copy_from(ins, &renumbered)).collect();
+            sections.push(instructions.join("\n"));
+        }
+        Ok(format!("{}\n", sections.join("\n\n")))
+    }
+}
+
+/// A COPY instruction, with the comments above it, with each `--from` naming a stage by
+/// index changed to the index `renumbered` gives it. Other instructions are unchanged.
+fn renumber_copy_from(instruction: &str, renumbered: &HashMap<usize, usize>) -> String {
+    let is_copy = instruction
+        .lines()
+        .find(|line| !line.trim_start().starts_with('#'))
+        .and_then(|line| line.split_whitespace().next())
+        .is_some_and(|keyword| keyword.eq_ignore_ascii_case("COPY"));
+    if !is_copy {
+        return instruction.to_string();
+    }
+    let lines: Vec<String> = instruction
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with('#') {
+                return line.to_string();
+            }
+            let mut words = Vec::new();
+            for word in line.split(' ') {
+                let index = word.strip_prefix("--from=").and_then(|from| from.parse::<usize>().ok());
+                match index.and_then(|index| renumbered.get(&index)) {
+                    Some(index) => words.push(format!("--from={}", index)),
+                    None => words.push(word.to_string()),
+                }
+            }
+            words.join(" ")
+        })
+        .collect();
+    lines.join("\n")
 }
+
 /// A type representing a container as specified by a Docker-style Containerfile
 #[derive(Debug, Serialize)]
 pub struct ParsedContainer {
@@ -60,21 +433,171 @@ pub struct ParsedContainer {
     pub labels: HashMap<String, String>,
     pub exposed_ports: Vec<ExposedPort>,
     pub volumes: Vec<VolumeMount>,
+    pub user: Option<String>,
+    pub working_dir: Option<String>,
+    pub entrypoint: Option<CommandLine>,
+    pub cmd: Option<CommandLine>,
+    /// ARGs declared before the first FROM, with their defaults. Only these may be used in FROM.
+    pub global_args: HashMap<String, Option<String>>,
+    /// The commands of the RUN instructions of the stage, in order
+    pub run_commands: Vec<CommandLine>,
+    /// The COPY and ADD instructions of the stage, in order
+    pub copies: Vec<CopyInstruction>,
+    /// The paths the stage copies files to, works in and declares as volumes, sorted, each
+    /// with the first instruction naming it
+    pub filesystem: Vec<PathEntry>,
+    /// The build metrics of the stage and of each stage it's built `FROM`, root first
+    pub build_metrics: Vec<BuildMetrics>,
     pub containerfile: Vec<String>,
+    /// Why the file was scanned leniently rather than parsed, when the Dockerfile parser
+    /// failed on it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lenient_parse: Option<String>,
+    /// The lines of the instructions a lenient scan didn't recognise, which are kept as
+    /// written in `containerfile` but otherwise not modelled
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub raw_lines: Vec<usize>,
+    /// The containerfile as written, stage by stage. Empty when scanned leniently.
+    #[serde(skip)]
+    pub source: ParsedContainerfile,
+}
+
+impl ParsedContainer {
+    /// The value of a label, if set
+    pub fn label_value(&self, key: &str) -> Option<&str> {
+        self.labels.get(key).map(String::as_str)
+    }
+
+    /// The keys of the labels set, in no particular order
+    pub fn label_keys(&self) -> impl Iterator<Item = &str> {
+        self.labels.keys().map(String::as_str)
+    }
+
+    /// The labels set with their values, sorted by key
+    pub fn labels_sorted(&self) -> Vec<(&str, &str)> {
+        let mut labels: Vec<(&str, &str)> = self.labels.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+        labels.sort();
+        labels
+    }
+
+    /// The first port EXPOSEd with a number, whatever its protocol
+    pub fn port(&self, number: u16) -> Option<&ExposedPort> {
+        self.exposed_ports.iter().find(|port| port.port_number == number)
+    }
+
+    pub fn has_label(&self, key: &str) -> bool {
+        self.labels.contains_key(key)
+    }
+
+    /// Add or overwrite labels, e.g. with those applied by a compose service at deploy time.
+    /// Labels in `extra` take precedence over those from `LABEL` instructions.
+    pub fn merge_labels(&mut self, extra: &HashMap<String, String>) {
+        for (key, value) in extra {
+            self.labels.insert(key.clone(), value.clone());
+        }
+    }
+
+    /// The COPY and ADD instructions that copy anything once the `.dockerignore` patterns
+    /// in `ignored` are applied, dropping those from the build context whose every source
+    /// is excluded
+    pub fn effective_copies(&self, ignored: &[String]) -> Vec<&CopyInstruction> {
+        self.copies
+            .iter()
+            .filter(|copy| !copy.from_context() || copy.sources.iter().any(|source| copy.source_kind(source).is_remote() || !is_ignored(source, ignored)))
+            .collect()
+    }
+
+    /// The sources of COPY and ADD instructions from the build context that are excluded by
+    /// the `.dockerignore` patterns in `ignored`
+    pub fn ignored_sources(&self, ignored: &[String]) -> Vec<(&CopyInstruction, &str)> {
+        self.copies
+            .iter()
+            .filter(|copy| copy.from_context())
+            .flat_map(|copy| copy.sources.iter().map(move |source| (copy, source.as_str())))
+            .filter(|(copy, source)| copy.source_kind(source) == SourceKind::Local && is_ignored(source, ignored))
+            .collect()
+    }
+}
+
+/// Parse an ADD instruction, which dockerfile_parser leaves as a miscellaneous instruction
+pub(crate) fn parse_add_instruction(arguments: &str, line: usize) -> Option<CopyInstruction> {
+    let mut from = None;
+    let mut checksum = None;
+    let mut paths = Vec::new();
+    for word in split_shell_words(arguments)? {
+        match word.strip_prefix("--") {
+            Some(flag) => {
+                if let Some(value) = flag.strip_prefix("from=") {
+                    from = Some(value.to_string());
+                } else if let Some(value) = flag.strip_prefix("checksum=") {
+                    checksum = Some(value.to_string());
+                }
+            }
+            None => paths.push(word),
+        }
+    }
+    let destination = paths.pop()?;
+    Some(CopyInstruction { instruction: "ADD".to_string(), sources: paths, destination, from, checksum, line })
+}
+
+// .dockerignore
+
+/// Read the patterns of a `.dockerignore` file, skipping blank lines and comments
+pub fn parse_dockerignore(path: &Path) -> anyhow::Result<Vec<String>> {
+    let content = fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// A path or pattern relative to the root of the build context, as Docker cleans them
+fn context_path(path: &str) -> &str {
+    let mut path = path.trim().trim_end_matches('/');
+    loop {
+        match path.strip_prefix("./").or_else(|| path.strip_prefix('/')) {
+            Some(rest) => path = rest,
+            None => return path,
+        }
+    }
+}
+
+/// Whether `.dockerignore` patterns exclude a path of the build context. A pattern excludes
+/// the paths it matches and everything under them, and the last matching pattern wins, so a
+/// later `!` pattern can re-include what an earlier one excluded.
+pub fn is_ignored(path: &str, patterns: &[String]) -> bool {
+    let path = context_path(path);
+    if path.is_empty() || path == "." {
+        return false;
+    }
+    let ancestors: Vec<&str> = path.match_indices('/').map(|(i, _)| &path[..i]).chain(std::iter::once(path)).collect();
+    let mut ignored = false;
+    for pattern in patterns {
+        let (include, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, context_path(pattern)),
+            None => (false, context_path(pattern)),
+        };
+        if ancestors.iter().any(|ancestor| glob_match(pattern, ancestor)) {
+            ignored = !include;
+        }
+    }
+    ignored
 }
 
+/// An EXPOSE argument, `port[/protocol]`, where an unknown protocol is taken as TCP. Unlike
+/// a compose port it can't publish the port, so a host port or address makes it invalid.
 fn parse_exposed_port(input: &str) -> Port {
-    let parts: Vec<&str>= input.split('/').collect();
-    let port:u16=parts[0].trim().parse().unwrap_or(0);
-    let protocol = match parts.get(1) {
-        Some(s) if s.to_lowercase() == "tcp" => Protocol::Tcp,
-        Some(s) if s.to_lowercase() == "udp" => Protocol::Udp,
-        _ => Protocol::default(),
+    let input = match input.split_once('/') {
+        Some((port, protocol)) if !["tcp", "udp", "sctp"].contains(&protocol.to_ascii_lowercase().as_str()) => port,
+        _ => input,
     };
-    if port == 0 {
-        Port::None
-    } else {
-        Port::Network(ExposedPort{port_number: port,protocol,})
+    match parse_port_mapping(input) {
+        Ok(binding) if binding.host_port.is_none() && binding.host_ip.is_none() && !input.contains(':') => {
+            Port::Network(ExposedPort{port_number: binding.container_port,protocol: binding.protocol,line: None})
+        }
+        _ => Port::None,
     }
 }
 
@@ -84,7 +607,7 @@ fn parse_json_volume(json_str: &str) -> Port {
         Ok(paths) => {
             let volume_ports = paths
                 .into_iter()
-                .map(|mount_point| VolumeMount { mount_point })
+                .filter_map(|mount_point| VolumeMount::new(&mount_point).ok())
                 .collect();
             Port::Volume(volume_ports)
         },
@@ -100,9 +623,7 @@ fn parse_string_volume(input_str: &str) -> Port {
     
     let volume_ports: Vec<VolumeMount> = unix_paths
         .into_iter()
-        .map(|mount_point| VolumeMount {
-            mount_point: mount_point.into_owned(),
-        })
+        .filter_map(|mount_point| VolumeMount::new(&mount_point).ok())
         .collect();
     Port::Volume(volume_ports)
 
@@ -116,36 +637,138 @@ fn parse_volume(input: &str) -> Port {
     }
 }
 
-fn parse_misc_instruction(inst: &dockerfile_parser::MiscInstruction) -> Port {
-    let in_str = inst.instruction.to_string();
+/// The mount points declared by the arguments of a VOLUME instruction
+pub(crate) fn volume_mount_points(arguments: &str) -> Vec<String> {
+    match parse_volume(arguments) {
+        Port::Volume(volumes) => volumes.into_iter().map(|volume| volume.mount_point).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn parse_misc_instruction(inst: &dockerfile_parser::MiscInstruction, args: &HashMap<String, String>) -> Port {
+    // Instructions are case-insensitive: `expose 8080` is as valid as `EXPOSE 8080`
+    let in_str = inst.instruction.to_string().to_ascii_uppercase();
+    let arguments = expand_variables(&inst.arguments.to_string(), args);
     match in_str.as_str() {
         "EXPOSE" => {
-            parse_exposed_port(inst.arguments.to_string().as_str())
+            parse_exposed_port(&arguments)
         },
         "VOLUME" => {
-            parse_volume(inst.arguments.to_string().as_str())
+            parse_volume(&arguments)
         },
         _ => {Port::None}
     }
 }
 
-fn extract_dockerblock(dockerfile: &dockerfile_parser::Dockerfile) -> Result<ParsedContainer> {
+/// The value of an ARG: a `--build-arg` override, else its default. An ARG without a
+/// default inherits the default of the global ARG of the same name.
+fn arg_value(
+    arg: &dockerfile_parser::ArgInstruction,
+    build_args: &HashMap<String, String>,
+    global_args: &HashMap<String, Option<String>>,
+) -> Option<String> {
+    let name = &arg.name.content;
+    build_args
+        .get(name)
+        .cloned()
+        .or_else(|| arg.value.as_ref().map(|v| v.content.clone()))
+        .or_else(|| global_args.get(name).cloned().flatten())
+}
+
+/// The 1-based line number of a byte offset into the Dockerfile content
+pub(crate) fn line_number(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+/// The index of the stage to model: the stage named `target`, compared case-insensitively,
+/// or the last stage as `docker build` defaults to. None when there are no stages.
+fn select_stage(stages: &Stages, target: Option<&str>) -> std::result::Result<Option<usize>, CargotectureError> {
+    let Some(target) = target else {
+        return Ok(stages.stages.last().map(|stage| stage.index));
+    };
+    match stages.get_by_name(target) {
+        Some(stage) => Ok(Some(stage.index)),
+        None => Err(CargotectureError::UnknownStage {
+            target: target.to_string(),
+            available: stages.iter().filter_map(|stage| stage.name.clone()).collect(),
+        }),
+    }
+}
+
+/// The indices of a stage and the stages it's built `FROM`, root first
+fn stage_chain(stages: &Stages, index: usize) -> Vec<usize> {
+    let mut chain = vec![index];
+    while let StageParent::Stage(parent) = stages[chain[chain.len() - 1]].parent {
+        chain.push(parent);
+    }
+    chain.reverse();
+    chain
+}
+
+/// Model the effective configuration of a stage: that built up by the instructions of the
+/// stage and of each stage it's built `FROM`, in order
+fn extract_dockerblock(dockerfile: &dockerfile_parser::Dockerfile, build_args: &HashMap<String, String>, stage: Option<usize>) -> Result<ParsedContainer> {
+    let mut global_args = HashMap::new();
+    let mut global_values = HashMap::new();
+    for arg in &dockerfile.global_args {
+        if let Some(value) = arg_value(arg, build_args, &HashMap::new()) {
+            global_values.insert(arg.name.content.clone(), value);
+        }
+        global_args.insert(arg.name.content.clone(), arg.value.as_ref().map(|v| v.content.clone()));
+    }
+
     let mut name = String::new();
     let mut base_image = String::new();
     let mut labels = HashMap::new();
     let mut exposed_ports = Vec::new();
     let mut volumes = Vec::new();
-    let mut containerfile = Vec::new();
+    let mut user = None;
+    let mut working_dir = None;
+    let mut entrypoint = None;
+    let mut cmd = None;
+    let mut run_commands = Vec::new();
+    let mut copies = Vec::new();
+    let mut build_metrics = Vec::new();
+    let stages = dockerfile.stages();
+    let containerfile = stages
+        .iter()
+        .flat_map(|stage| stage.instructions.iter())
+        .map(|ins| dockerfile.content[ins.span().start..ins.span().end].to_string())
+        .collect();
 
-    for stage in dockerfile.iter_stages() {
-        name=stage.name.unwrap_or("".to_string());
-        for ins in stage.instructions {
-            let ins_str=format!("{}",&dockerfile.content[ins.span().start..ins.span().end]);
-            containerfile.push(ins_str);
+    // The index in containerfile of the first instruction of each stage
+    let stage_offsets: Vec<usize> = stages
+        .iter()
+        .scan(0, |offset, stage| {
+            let start = *offset;
+            *offset += stage.instructions.len();
+            Some(start)
+        })
+        .collect();
+    let mut filesystem: Vec<PathEntry> = Vec::new();
+    let mut current_dir = "/".to_string();
+
+    let chain = stage.map(|index| stage_chain(&stages, index)).unwrap_or_default();
+    for stage in chain.into_iter().map(|index| &stages[index]) {
+        name=stage.name.clone().unwrap_or_default();
+        build_metrics.push(BuildMetrics::of_stage(stage));
+        // ARG values are scoped to the stage that declares them
+        let mut stage_args: HashMap<String, String> = HashMap::new();
+        for (offset, ins) in stage.instructions.iter().enumerate() {
+            let index = stage_offsets[stage.index] + offset;
+            let mut add_path = |path: &str, instruction: &str| {
+                filesystem.push(PathEntry { path: resolve_path(&current_dir, path), instruction: instruction.to_string(), index });
+            };
             match ins {
-// TODO - Parse ARG (& ENV?) Instructions to provide expansion of others below
-                Instruction::From(from) => {
-                    base_image = from.image.clone().to_string();
+// TODO - Parse ENV Instructions to provide expansion of others below
+                // A stage built from another stage keeps the base image of that stage
+                Instruction::From(from) if !matches!(stage.parent, StageParent::Stage(_)) => {
+                    base_image = expand_variables(&from.image.content, &global_values);
+                }
+                Instruction::Arg(arg) => {
+                    if let Some(value) = arg_value(arg, build_args, &global_args) {
+                        stage_args.insert(arg.name.content.clone(), value);
+                    }
                 }
                 Instruction::Label(label) => {
 
@@ -153,13 +776,53 @@ fn extract_dockerblock(dockerfile: &dockerfile_parser::Dockerfile) -> Result<Par
                         labels.insert(item.name.to_string(), item.value.to_string());
                     }
                 }
+                Instruction::Entrypoint(ep) => {
+                    entrypoint = Some(CommandLine::from_expr(&ep.expr));
+                }
+                Instruction::Cmd(command) => {
+                    cmd = Some(CommandLine::from_expr(&command.expr));
+                }
+                Instruction::Run(run) => {
+                    run_commands.push(CommandLine::from_expr(&run.expr));
+                }
+                Instruction::Misc(misc) if misc.instruction.content.eq_ignore_ascii_case("USER") => {
+                    user = Some(expand_variables(misc.arguments.to_string().trim(), &stage_args));
+                }
+                Instruction::Misc(misc) if misc.instruction.content.eq_ignore_ascii_case("WORKDIR") => {
+                    let dir = expand_variables(misc.arguments.to_string().trim(), &stage_args);
+                    add_path(&dir, "WORKDIR");
+                    current_dir = resolve_path(&current_dir, &dir);
+                    working_dir = Some(dir);
+                }
+                Instruction::Copy(copy) => {
+                    add_path(&expand_variables(&copy.destination.content, &stage_args), "COPY");
+                    copies.push(CopyInstruction {
+                        instruction: "COPY".to_string(),
+                        sources: copy.sources.iter().map(|source| expand_variables(&source.content, &stage_args)).collect(),
+                        destination: expand_variables(&copy.destination.content, &stage_args),
+                        from: copy.flags.iter().find(|flag| flag.name.content == "from").map(|flag| flag.value.content.clone()),
+                        checksum: None,
+                        line: line_number(&dockerfile.content, ins.span().start),
+                    });
+                }
+                Instruction::Misc(misc) if misc.instruction.content.eq_ignore_ascii_case("ADD") => {
+                    let arguments = expand_variables(&misc.arguments.to_string(), &stage_args);
+                    let add = parse_add_instruction(&arguments, line_number(&dockerfile.content, ins.span().start));
+                    if let Some(add) = &add {
+                        add_path(&add.destination, "ADD");
+                    }
+                    copies.extend(add);
+                }
                 Instruction::Misc(misc) => {
-                    
-                    match parse_misc_instruction(misc) {
-                        Port::Network(exposed) => {
+                    match parse_misc_instruction(misc, &stage_args) {
+                        Port::Network(mut exposed) => {
+                            exposed.line = Some(line_number(&dockerfile.content, ins.span().start));
                             exposed_ports.push(exposed);
                         }
                         Port::Volume(mut vol) => {
+                            for volume in &vol {
+                                add_path(&volume.mount_point, "VOLUME");
+                            }
                             volumes.append(&mut vol);
                         }
                         Port::None => {
@@ -170,13 +833,28 @@ fn extract_dockerblock(dockerfile: &dockerfile_parser::Dockerfile) -> Result<Par
             }
         }
     }
+    // Sorting is stable, so the first instruction naming each path is kept
+    filesystem.sort_by(|a, b| a.path.cmp(&b.path));
+    filesystem.dedup_by(|later, first| later.path == first.path);
     let block = ParsedContainer {
         name,
         base_image,
         labels,
         exposed_ports,
         volumes,
-        containerfile
+        user,
+        working_dir,
+        entrypoint,
+        cmd,
+        global_args,
+        run_commands,
+        copies,
+        filesystem,
+        build_metrics,
+        containerfile,
+        lenient_parse: None,
+        raw_lines: Vec::new(),
+        source: ParsedContainerfile::from_dockerfile(dockerfile),
     };
 
     Ok(block)
@@ -195,16 +873,184 @@ fn debug_dockerfile_parse(dockerfile: &dockerfile_parser::Dockerfile) {
       }  
 }
 pub fn parse_containerfile(reader: Box<dyn Read>,name: &str) -> Result<ParsedContainer> {
-    
+    parse_containerfile_with_args(reader, name, &HashMap::new())
+}
+
+/// Parse a containerfile with `--build-arg` style overrides of the values of its ARGs
+pub fn parse_containerfile_with_args(reader: Box<dyn Read>, name: &str, build_args: &HashMap<String, String>) -> Result<ParsedContainer> {
     let dockerfile = Dockerfile::from_reader(reader)?;
     //debug_dockerfile_parse(&dockerfile);
-    let mut block=extract_dockerblock(&dockerfile)?;
-    if block.name == "" {
+    container_of(&dockerfile, name, build_args)
+}
+
+/// Model the last stage of a parsed Dockerfile, named `name` when the stage has no name
+fn container_of(dockerfile: &Dockerfile, name: &str, build_args: &HashMap<String, String>) -> Result<ParsedContainer> {
+    let last_stage = dockerfile.stages().stages.last().map(|stage| stage.index);
+    let mut block=extract_dockerblock(dockerfile, build_args, last_stage)?;
+    if block.name.is_empty() {
         block.name=name.to_string();
     }
     Ok(block)
 }
 
+/// Parse a containerfile as `parse_containerfile_with_args` does, but when the Dockerfile
+/// parser fails, scan it line by line instead, so that one bad instruction doesn't lose the
+/// whole file. The scan reads only FROM, LABEL, EXPOSE, VOLUME, ENV and ARG, and records why
+/// it was needed in `lenient_parse`.
+pub fn parse_containerfile_lenient(mut reader: Box<dyn Read>, name: &str, build_args: &HashMap<String, String>) -> anyhow::Result<ParsedContainer> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    match Dockerfile::parse(&content) {
+        Ok(dockerfile) => Ok(container_of(&dockerfile, name, build_args)?),
+        Err(err) => Ok(scan_containerfile(&content, name, build_args, &err.to_string())),
+    }
+}
+
+/// The instructions of a Dockerfile as the line each starts on and its text, with
+/// continuation lines joined and comments and blank lines dropped
+fn logical_lines(content: &str) -> Vec<(usize, String)> {
+    let mut instructions = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let (text, continued) = match trimmed.strip_suffix('\\') {
+            Some(text) => (text, true),
+            None => (trimmed, false),
+        };
+        let (_, instruction) = current.get_or_insert_with(|| (index + 1, String::new()));
+        if !instruction.is_empty() {
+            instruction.push(' ');
+        }
+        instruction.push_str(text.trim());
+        if !continued {
+            instructions.extend(current.take());
+        }
+    }
+    instructions.extend(current);
+    instructions
+}
+
+/// The variables an ENV or ARG instruction sets, from `KEY=value` pairs or the older
+/// `ENV KEY value` form. An ARG without a default sets nothing.
+fn scanned_variables(keyword: &str, arguments: &str) -> Vec<(String, String)> {
+    let words = split_shell_words(arguments).unwrap_or_default();
+    match words.first() {
+        Some(first) if keyword == "ENV" && !first.contains('=') => vec![(first.clone(), words[1..].join(" "))],
+        _ => words
+            .iter()
+            .filter_map(|word| word.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+/// Model a containerfile the Dockerfile parser failed on from the subset of instructions
+/// that can be recognised by their leading keyword. Only the last stage is modelled, and
+/// without the stages it's built from.
+fn scan_containerfile(content: &str, name: &str, build_args: &HashMap<String, String>, error: &str) -> ParsedContainer {
+    let mut container = ParsedContainer {
+        name: String::new(),
+        base_image: String::new(),
+        labels: HashMap::new(),
+        exposed_ports: Vec::new(),
+        volumes: Vec::new(),
+        user: None,
+        working_dir: None,
+        entrypoint: None,
+        cmd: None,
+        global_args: HashMap::new(),
+        run_commands: Vec::new(),
+        copies: Vec::new(),
+        filesystem: Vec::new(),
+        build_metrics: Vec::new(),
+        containerfile: Vec::new(),
+        lenient_parse: Some(format!(
+            "lenient parse: only FROM, LABEL, EXPOSE, VOLUME, ENV and ARG instructions were read, as the Dockerfile parser failed: {}",
+            error.trim()
+        )),
+        raw_lines: Vec::new(),
+        source: ParsedContainerfile::default(),
+    };
+    let mut global_values = HashMap::new();
+    let mut variables: HashMap<String, String> = HashMap::new();
+    let mut in_stage = false;
+    for (line, instruction) in logical_lines(content) {
+        let (keyword, arguments) = instruction.split_once(char::is_whitespace).unwrap_or((&instruction, ""));
+        let keyword = keyword.to_ascii_uppercase();
+        let arguments = arguments.trim();
+        container.containerfile.push(instruction.clone());
+        match keyword.as_str() {
+            "FROM" => {
+                let words: Vec<&str> = arguments.split_whitespace().filter(|word| !word.starts_with("--")).collect();
+                container.base_image = expand_variables(words.first().copied().unwrap_or_default(), &global_values);
+                container.name = match words.as_slice() {
+                    [_, as_keyword, stage] if as_keyword.eq_ignore_ascii_case("AS") => stage.to_string(),
+                    _ => String::new(),
+                };
+                container.labels.clear();
+                container.exposed_ports.clear();
+                container.volumes.clear();
+                variables.clear();
+                in_stage = true;
+            }
+            "ARG" | "ENV" => {
+                for (key, value) in scanned_variables(&keyword, arguments) {
+                    let value = build_args.get(&key).filter(|_| keyword == "ARG").cloned().unwrap_or(value);
+                    if in_stage {
+                        variables.insert(key, value);
+                    } else {
+                        container.global_args.insert(key.clone(), Some(value.clone()));
+                        global_values.insert(key, value);
+                    }
+                }
+            }
+            "LABEL" => {
+                for (key, value) in split_shell_words(arguments).unwrap_or_default().iter().filter_map(|word| word.split_once('=')) {
+                    container.labels.insert(key.to_string(), value.to_string());
+                }
+            }
+            "EXPOSE" => {
+                for port in expand_variables(arguments, &variables).split_whitespace() {
+                    if let Port::Network(mut exposed) = parse_exposed_port(port) {
+                        exposed.line = Some(line);
+                        container.exposed_ports.push(exposed);
+                    }
+                }
+            }
+            "VOLUME" => {
+                if let Port::Volume(mut volumes) = parse_volume(&expand_variables(arguments, &variables)) {
+                    container.volumes.append(&mut volumes);
+                }
+            }
+            _ => container.raw_lines.push(line),
+        }
+    }
+    if container.name.is_empty() {
+        container.name = name.to_string();
+    }
+    container
+}
+
+/// Parse a containerfile as `parse_containerfile_with_args` does, modelling the stage named
+/// by `target` as `docker build --target` would build it, or the last stage without one
+pub fn parse_containerfile_for_target(
+    reader: Box<dyn Read>,
+    name: &str,
+    build_args: &HashMap<String, String>,
+    target: Option<&str>,
+) -> anyhow::Result<ParsedContainer> {
+    let dockerfile = Dockerfile::from_reader(reader)?;
+    let stage = select_stage(&dockerfile.stages(), target)?;
+    let mut block = extract_dockerblock(&dockerfile, build_args, stage)?;
+    if block.name.is_empty() {
+        block.name = name.to_string();
+    }
+    Ok(block)
+}
+
 
 
 /// A function to parse a dockerfile into a DockerfileBlock structure
@@ -262,11 +1108,231 @@ mod tests {
         // Check if the parsed DockerfileBlock has the expected exposed_ports
         assert_eq!(dockerfile_block.exposed_ports.len(), 1);
         assert_eq!(dockerfile_block.exposed_ports[0].port_number, 8080);
+        assert_eq!(dockerfile_block.exposed_ports[0].line, Some(3));
 
         // Check if the parsed DockerfileBlock has the expected volumes
         assert_eq!(dockerfile_block.volumes.len(), 1);
         assert_eq!(dockerfile_block.volumes[0].mount_point, "/data");
     }
+
+    #[test]
+    fn test_parse_user_workdir_entrypoint() {
+        let content = "FROM alpine\nUSER app\nWORKDIR /srv\nENTRYPOINT [\"/bin/server\", \"--port\", \"80\"]\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "alpine").unwrap();
+        assert_eq!(container.user.as_deref(), Some("app"));
+        assert_eq!(container.working_dir.as_deref(), Some("/srv"));
+        assert_eq!(
+            container.entrypoint,
+            Some(CommandLine::Exec(vec!["/bin/server".to_string(), "--port".to_string(), "80".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_parse_cmd_shell_and_exec() {
+        let content = "FROM alpine\nCMD echo \"hello world\" > /tmp/out\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "alpine").unwrap();
+        let cmd = container.cmd.unwrap();
+        assert_eq!(cmd, CommandLine::Shell("echo \"hello world\" > /tmp/out".to_string()));
+        assert_eq!(cmd.as_argv(), vec!["echo", "hello world", ">", "/tmp/out"]);
+
+        let content = "FROM alpine\nCMD [\"sh\", \"-c\", \"echo 'it''s' $HOME\"]\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "alpine").unwrap();
+        let cmd = container.cmd.unwrap();
+        assert_eq!(cmd.as_argv(), vec!["sh", "-c", "echo 'it''s' $HOME"]);
+        assert_eq!(CommandLine::Exec(vec!["/bin/server".to_string(), "a b".to_string()]).display(), "/bin/server 'a b'");
+        // Displaying an exec form command and splitting it again recovers the arguments
+        assert_eq!(CommandLine::Shell(cmd.display()).as_argv(), cmd.as_argv());
+    }
+
+    #[test]
+    fn test_command_line_serde_round_trip() {
+        for yaml in ["echo \"a b\" 'c d'\n", "- /bin/server\n- --name\n- a b\n"] {
+            let command: CommandLine = serde_yaml::from_str(yaml).unwrap();
+            assert_eq!(serde_yaml::to_string(&command).unwrap().trim(), yaml.trim());
+        }
+        let exec: CommandLine = serde_yaml::from_str("[\"/bin/server\", \"--name\", \"a b\"]").unwrap();
+        assert_eq!(exec, CommandLine::Exec(vec!["/bin/server".to_string(), "--name".to_string(), "a b".to_string()]));
+    }
+
+    #[test]
+    fn test_parse_global_arg_in_from() {
+        let content = "ARG BASE=debian:bookworm\nARG PORT=8080\nFROM ${BASE}\nARG PORT\nEXPOSE $PORT\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "app").unwrap();
+        assert_eq!(container.base_image, "debian:bookworm");
+        assert_eq!(container.global_args.get("BASE"), Some(&Some("debian:bookworm".to_string())));
+        // PORT is re-declared in the stage without a default, so inherits the global default
+        assert_eq!(container.exposed_ports[0].port_number, 8080);
+
+        let build_args = HashMap::from([("BASE".to_string(), "alpine:3.19".to_string())]);
+        let container = parse_containerfile_with_args(Box::new(content.as_bytes()), "app", &build_args).unwrap();
+        assert_eq!(container.base_image, "alpine:3.19");
+        assert_eq!(container.global_args.get("BASE"), Some(&Some("debian:bookworm".to_string())));
+    }
+
+    #[test]
+    fn test_stage_arg_not_redeclared() {
+        // A global ARG is not visible inside a stage unless re-declared there
+        let content = "ARG PORT=8080\nFROM alpine\nEXPOSE 9000\nUSER ${PORT:-app}\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "app").unwrap();
+        assert_eq!(container.user.as_deref(), Some("app"));
+    }
+
+    const TWO_STAGE: &str = "FROM rust:1.75 AS builder\nEXPOSE 9000\nUSER builder\nFROM builder AS tester\nVOLUME /results\nFROM debian:bookworm-slim AS runtime\nEXPOSE 8080\n";
+
+    #[test]
+    fn test_parse_build_target() {
+        let parse = |target| parse_containerfile_for_target(Box::new(TWO_STAGE.as_bytes()), "app", &HashMap::new(), target);
+        let builder = parse(Some("Builder")).unwrap();
+        assert_eq!(builder.name, "builder");
+        assert_eq!(builder.exposed_ports.iter().map(|p| p.port_number).collect::<Vec<_>>(), vec![9000]);
+        assert_eq!(builder.user.as_deref(), Some("builder"));
+
+        // A stage built from another inherits its configuration and base image
+        let tester = parse(Some("tester")).unwrap();
+        assert_eq!(tester.base_image, "rust:1.75");
+        assert_eq!(tester.exposed_ports[0].port_number, 9000);
+        assert_eq!(tester.volumes[0].mount_point, "/results");
+
+        let runtime = parse(None).unwrap();
+        assert_eq!(runtime.base_image, "debian:bookworm-slim");
+        assert_eq!(runtime.exposed_ports.iter().map(|p| p.port_number).collect::<Vec<_>>(), vec![8080]);
+        assert_eq!(runtime.user, None);
+        assert_eq!(runtime.containerfile.len(), 7);
+        assert!(runtime.run_commands.is_empty());
+
+        let err = parse(Some("release")).unwrap_err();
+        assert_eq!(err.to_string(), "Build target 'release' not found, the stages are: builder, tester, runtime");
+    }
+
+    #[test]
+    fn test_dockerignore() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        writeln!(temp_file, "# Build output\n*.log\n\n/target\ndocs/**/*.md\n!docs/README.md").unwrap();
+        let patterns = parse_dockerignore(temp_file.path()).unwrap();
+        assert_eq!(patterns, vec!["*.log", "/target", "docs/**/*.md", "!docs/README.md"]);
+
+        assert!(is_ignored("app.log", &patterns));
+        assert!(is_ignored("./app.log", &patterns));
+        // * doesn't match across path elements
+        assert!(!is_ignored("logs/app.log", &patterns));
+        // A pattern excludes everything under the directories it matches
+        assert!(is_ignored("target/release/app", &patterns));
+        assert!(is_ignored("docs/guide.md", &patterns));
+        assert!(is_ignored("docs/api/v1/index.md", &patterns));
+        assert!(!is_ignored("docs/README.md", &patterns));
+        assert!(!is_ignored(".", &patterns));
+
+        let content = "FROM rust:1.75 AS builder\nCOPY . /src\nFROM debian\nCOPY --from=builder /src/app.log /app.log\nCOPY build.log Cargo.toml /app/\nCOPY debug.log /app/\nADD --chown=app app.tar.gz /app/\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "app").unwrap();
+        assert_eq!(container.copies.len(), 4);
+        assert_eq!(container.copies[0].from.as_deref(), Some("builder"));
+        assert_eq!(container.copies[3].instruction, "ADD");
+        assert_eq!(container.copies[3].sources, vec!["app.tar.gz"]);
+        assert_eq!(container.copies[3].line, 7);
+        let ignored: Vec<(usize, &str)> = container.ignored_sources(&patterns).into_iter().map(|(copy, source)| (copy.line, source)).collect();
+        assert_eq!(ignored, vec![(5, "build.log"), (6, "debug.log")]);
+        let effective: Vec<usize> = container.effective_copies(&patterns).into_iter().map(|copy| copy.line).collect();
+        assert_eq!(effective, vec![4, 5, 7]);
+    }
+
+    #[test]
+    fn test_add_sources() {
+        assert_eq!(SourceKind::of("https://example.com/tool.tar.gz"), SourceKind::Url);
+        assert_eq!(SourceKind::of("https://github.com/moby/buildkit.git#v0.12.0"), SourceKind::Git);
+        assert_eq!(SourceKind::of("git@github.com:moby/buildkit.git"), SourceKind::Git);
+        assert_eq!(SourceKind::of("vendor/tool.tar.gz"), SourceKind::Local);
+
+        let content = "FROM alpine\nADD --checksum=sha256:abc https://example.com/tool.tar.gz vendor.tgz notes.txt /opt/\nCOPY https://example.com/x.tar /x/\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "app").unwrap();
+        let add = &container.copies[0];
+        assert_eq!(add.checksum.as_deref(), Some("sha256:abc"));
+        assert_eq!(add.remote_sources(), vec![("https://example.com/tool.tar.gz", SourceKind::Url)]);
+        assert_eq!(add.extracted_archives(), vec!["vendor.tgz"]);
+        // COPY neither fetches nor extracts
+        assert!(container.copies[1].remote_sources().is_empty() && container.copies[1].extracted_archives().is_empty());
+        // Downloads aren't in the build context, so .dockerignore doesn't apply to them
+        let ignored: Vec<&str> = container.ignored_sources(&["*".to_string()]).into_iter().filter(|(copy, _)| copy.line == 2).map(|(_, source)| source).collect();
+        assert_eq!(ignored, vec!["vendor.tgz", "notes.txt"]);
+    }
+
+    #[test]
+    fn test_filesystem() {
+        let content = "FROM alpine AS build\nCOPY src/ /build/src/\nFROM alpine\nWORKDIR /srv\nCOPY config.toml conf/\nWORKDIR app\nCOPY --from=build /build/src ./src\nADD app.tar.gz ../lib\nVOLUME /srv/app/data\nCOPY Cargo.toml /srv/conf\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "app").unwrap();
+        let filesystem: Vec<(&str, &str, usize)> = container
+            .filesystem
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.instruction.as_str(), entry.index))
+            .collect();
+        // The build stage isn't part of the final image, and /srv/conf is named twice
+        assert_eq!(filesystem, vec![
+            ("/srv", "WORKDIR", 3),
+            ("/srv/app", "WORKDIR", 5),
+            ("/srv/app/data", "VOLUME", 8),
+            ("/srv/app/src", "COPY", 6),
+            ("/srv/conf", "COPY", 4),
+            ("/srv/lib", "ADD", 7),
+        ]);
+        assert_eq!(container.containerfile[6], "COPY --from=build /build/src ./src");
+    }
+
+    #[test]
+    fn test_build_metrics() {
+        let content = "FROM debian:12 AS base\nRUN apt-get update\nRUN apt-get install -y curl\nRUN useradd app\nFROM base\nCOPY app /app\nADD conf.tar /etc/app\nRUN apk add --no-cache tini\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "app").unwrap();
+        assert_eq!(container.build_metrics, vec![
+            BuildMetrics { stage: "base".to_string(), layer_instructions: 3, mergeable_runs: 2, cache_cleaned: Some(false) },
+            BuildMetrics { stage: "1".to_string(), layer_instructions: 3, mergeable_runs: 0, cache_cleaned: Some(true) },
+        ]);
+        let json = serde_json::to_value(&container).unwrap();
+        assert_eq!(json["build_metrics"][0]["mergeable_runs"], 2);
+
+        // Both are also lint warnings
+        let rules: Vec<&str> = crate::lint::lint_containerfile(content, &[]).unwrap().iter().map(|finding| finding.rule).collect();
+        assert_eq!(rules, vec!["consecutive-run", "package-cache", "consecutive-run"]);
+
+        assert_eq!(package_install("apt-get update && apt-get   install -y curl"), Some(("apt-get", false)));
+        assert_eq!(package_install("yum install -y git && yum clean all"), Some(("yum", true)));
+        assert_eq!(package_install("cargo install ripgrep"), None);
+    }
+
+    #[test]
+    fn test_label_accessors() {
+        let content = "FROM alpine\nLABEL openapi.title=\"Pets\" tier=\"web\"\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "alpine").unwrap();
+        assert_eq!(container.label_value("openapi.title"), Some("Pets"));
+        assert_eq!(container.label_value("missing"), None);
+        assert!(container.has_label("tier"));
+        assert!(!container.has_label("missing"));
+        let mut keys: Vec<&str> = container.label_keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["openapi.title", "tier"]);
+        assert_eq!(container.labels_sorted(), vec![("openapi.title", "Pets"), ("tier", "web")]);
+    }
+
+    #[test]
+    fn test_port_accessor() {
+        let content = "FROM alpine\nEXPOSE 53/udp\nEXPOSE 8080\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "alpine").unwrap();
+        assert_eq!(container.port(53).map(|port| port.protocol), Some(Protocol::Udp));
+        assert_eq!(container.port(8080).and_then(|port| port.line), Some(3));
+        assert!(container.port(80).is_none());
+    }
+
+    #[test]
+    fn test_merge_labels() {
+        let content = "FROM alpine\nLABEL version=\"1.0\" tier=\"web\"\n";
+        let mut container = parse_containerfile(Box::new(content.as_bytes()), "alpine").unwrap();
+        let extra = HashMap::from([
+            ("version".to_string(), "2.0".to_string()),
+            ("owner".to_string(), "ops".to_string()),
+        ]);
+        container.merge_labels(&extra);
+        assert_eq!(container.labels.get("version").unwrap(), "2.0");
+        assert_eq!(container.labels.get("tier").unwrap(), "web");
+        assert_eq!(container.labels.get("owner").unwrap(), "ops");
+    }
+
     #[test]
     fn test_parse_volume() {
         // Test with JSON input
@@ -290,6 +1356,11 @@ mod tests {
             panic!("Expected Port::Volume, got {:?}", parsed_string_volume);
         }
 
+        // Mount points are canonicalised
+        let mount_points = volume_mount_points(r#"["/data/", "/srv//app/../cache"]"#);
+        assert_eq!(mount_points, vec!["/data", "/srv/cache"]);
+        assert_eq!(volume_mount_points("/data/ /logs/"), vec!["/data", "/logs"]);
+
         // Test with invalid input
         let invalid_input = "invalid";
         let parsed_invalid_volume = parse_volume(invalid_input);
@@ -319,7 +1390,7 @@ mod tests {
             span: Span { start: 0, end: 0 },
             content: "8080/tcp".to_string(),
         })]);
-        let parsed_expose = parse_misc_instruction(&expose_instruction);
+        let parsed_expose = parse_misc_instruction(&expose_instruction, &HashMap::new());
         if let Port::Network(exposed_port) = parsed_expose {
             assert_eq!(exposed_port.port_number, 8080);
             assert_eq!(exposed_port.protocol, Protocol::Tcp);
@@ -332,7 +1403,7 @@ mod tests {
             span: Span { start: 0, end: 0 },
             content: "/data".to_string(),
         })]);
-        let parsed_volume_string = parse_misc_instruction(&volume_instruction_string);
+        let parsed_volume_string = parse_misc_instruction(&volume_instruction_string, &HashMap::new());
         if let Port::Volume(volume_mounts) = parsed_volume_string {
             assert_eq!(volume_mounts.len(), 1);
             assert_eq!(volume_mounts[0].mount_point, "/data");
@@ -345,7 +1416,7 @@ mod tests {
             span: Span { start: 0, end: 0 },
             content: r#"[ "/data" , "/app" ]"#.to_string(),
         })]);
-        let parsed_volume_json = parse_misc_instruction(&volume_instruction_json);
+        let parsed_volume_json = parse_misc_instruction(&volume_instruction_json, &HashMap::new());
         if let Port::Volume(volume_mounts) = parsed_volume_json {
             assert_eq!(volume_mounts.len(), 2);
             assert_eq!(volume_mounts[0].mount_point, "/data");
@@ -359,9 +1430,187 @@ mod tests {
             span: Span { start: 0, end: 0 },
             content: "John Doe <john@example.com>".to_string(),
         })]);
-        let parsed_unsupported = parse_misc_instruction(&unsupported_instruction);
+        let parsed_unsupported = parse_misc_instruction(&unsupported_instruction, &HashMap::new());
         assert_eq!(parsed_unsupported, Port::None);
     }
+    #[test]
+    fn test_lowercase_misc_instruction() {
+        let expose = create_misc_instruction("expose", vec![BreakableStringComponent::String(SpannedString {
+            span: Span { start: 0, end: 0 },
+            content: "8080".to_string(),
+        })]);
+        assert!(matches!(parse_misc_instruction(&expose, &HashMap::new()), Port::Network(port) if port.port_number == 8080));
+
+        let content = "FROM alpine\nexpose 8080/udp\nVolume /data\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "alpine").unwrap();
+        assert_eq!(container.exposed_ports.len(), 1);
+        assert_eq!(container.exposed_ports[0].protocol, Protocol::Udp);
+        assert_eq!(container.volumes[0].mount_point, "/data");
+    }
+
+    #[test]
+    fn test_reconstruct_stages() {
+        let content = r#"# syntax=docker/dockerfile:1
+ARG RUST=1.75
+
+# Shared toolchain and dependencies
+FROM rust:${RUST} AS deps
+WORKDIR /build
+COPY Cargo.toml Cargo.lock ./
+RUN cargo fetch
+
+FROM node:20 AS assets
+COPY web/ /web/
+# Bundle the static assets
+RUN npm ci && npm run build
+
+FROM deps AS builder
+COPY src/ src/
+COPY --from=assets /web/dist /build/static
+RUN cargo build --release
+
+FROM deps AS test
+RUN cargo test
+
+FROM debian:bookworm-slim AS runtime
+COPY --from=builder /build/target/release/app /usr/local/bin/app
+CMD ["app"]
+"#;
+        let container = parse_containerfile(Box::new(content.as_bytes()), "app").unwrap();
+        let source = &container.source;
+        assert_eq!(source.preamble, "# syntax=docker/dockerfile:1\nARG RUST=1.75");
+        let dependencies: Vec<&[usize]> = source.stages.iter().map(|stage| stage.dependencies.as_slice()).collect();
+        assert_eq!(dependencies, vec![&[][..], &[], &[0, 1], &[0], &[2]]);
+        assert_eq!(source.stages[0].instructions[0], "# Shared toolchain and dependencies\nFROM rust:${RUST} AS deps");
+
+        let builder = source.reconstruct(&["builder"]).unwrap();
+        assert_eq!(
+            builder,
+            r#"# syntax=docker/dockerfile:1
+ARG RUST=1.75
+
+# Shared toolchain and dependencies
+FROM rust:${RUST} AS deps
+WORKDIR /build
+COPY Cargo.toml Cargo.lock ./
+RUN cargo fetch
+
+FROM node:20 AS assets
+COPY web/ /web/
+# Bundle the static assets
+RUN npm ci && npm run build
+
+FROM deps AS builder
+COPY src/ src/
+COPY --from=assets /web/dist /build/static
+RUN cargo build --release
+"#
+        );
+        let reparsed = parse_containerfile(Box::new(std::io::Cursor::new(builder)), "app").unwrap();
+        assert_eq!(reparsed.source.stages.len(), 3);
+        assert_eq!(reparsed.name, "builder");
+        assert_eq!(reparsed.base_image, "rust:1.75");
+
+        // Stages can be named by index, and are kept in their original order
+        let test = source.reconstruct(&["TEST", "1"]).unwrap();
+        let names: Vec<Option<String>> = parse_containerfile(Box::new(std::io::Cursor::new(test)), "app").unwrap().source.stages.into_iter().map(|stage| stage.name).collect();
+        assert_eq!(names, vec![Some("deps".to_string()), Some("assets".to_string()), Some("test".to_string())]);
+
+        let all = source.reconstruct(&["runtime", "test"]).unwrap();
+        assert_eq!(parse_containerfile(Box::new(std::io::Cursor::new(all)), "app").unwrap().source, container.source);
+        assert_eq!(source.reconstruct(&["release"]).unwrap_err().to_string(), "Build target 'release' not found, the stages are: deps, assets, builder, test, runtime");
+    }
+
+    #[test]
+    fn test_reconstruct_renumbers_copy_from() {
+        let content = "FROM alpine AS unused\nRUN true\n\nFROM golang AS build\nRUN go build -o /app\n\nFROM scratch\n# The binary from build\nCOPY --from=1 /app /app\n";
+        let container = parse_containerfile(Box::new(content.as_bytes()), "app").unwrap();
+        assert_eq!(container.source.stages[2].dependencies, vec![1]);
+        let final_stage = container.source.reconstruct(&["2"]).unwrap();
+        assert_eq!(final_stage, "FROM golang AS build\nRUN go build -o /app\n\nFROM scratch\n# The binary from build\nCOPY --from=0 /app /app\n");
+        let reparsed = parse_containerfile(Box::new(std::io::Cursor::new(final_stage)), "app").unwrap();
+        assert_eq!(reparsed.source.stages[1].dependencies, vec![0]);
+
+        // Nothing is dropped, so nothing is renumbered
+        assert_eq!(container.source.reconstruct(&["2", "unused"]).unwrap(), content);
+    }
+
+    #[test]
+    fn test_lenient_parse() {
+        let content = "ARG NODE=20\nFROM --platform alpine\nFROM node:${NODE} AS app\nLABEL tier=web \\\n  team=\"a b\"\nENV PORT=3000\nCOPY\nEXPOSE ${PORT} 9229/udp\nVOLUME /data\n";
+        assert!(parse_containerfile(Box::new(content.as_bytes()), "app").is_err());
+
+        let container = parse_containerfile_lenient(Box::new(content.as_bytes()), "fallback", &HashMap::new()).unwrap();
+        assert!(container.lenient_parse.as_deref().unwrap().starts_with("lenient parse: "));
+        assert!(container.lenient_parse.as_deref().unwrap().contains("could not parse Dockerfile"));
+        assert_eq!((container.name.as_str(), container.base_image.as_str()), ("app", "node:20"));
+        assert_eq!(container.label_value("team"), Some("a b"));
+        let ports: Vec<(u16, Protocol, Option<usize>)> = container.exposed_ports.iter().map(|port| (port.port_number, port.protocol, port.line)).collect();
+        assert_eq!(ports, vec![(3000, Protocol::Tcp, Some(8)), (9229, Protocol::Udp, Some(8))]);
+        assert_eq!(container.volumes[0].mount_point, "/data");
+        assert_eq!(container.raw_lines, vec![7]);
+        assert_eq!(container.containerfile[3], "LABEL tier=web team=\"a b\"");
+
+        // Files the parser accepts are parsed as usual
+        let container = parse_containerfile_lenient(Box::new("FROM alpine\nEXPOSE 80\n".as_bytes()), "alpine", &HashMap::new()).unwrap();
+        assert!(container.lenient_parse.is_none() && container.raw_lines.is_empty());
+        assert_eq!(container.exposed_ports[0].port_number, 80);
+    }
+
+    /// A deterministic xorshift generator, so that failures are reproducible
+    struct Xorshift(u64);
+
+    impl Xorshift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[(self.next() % items.len() as u64) as usize]
+        }
+
+        /// A string of fragments that are significant to instruction arguments
+        fn arguments(&mut self) -> String {
+            const FRAGMENTS: &[&str] = &[
+                "", " ", "/", "[", "]", "\"", ",", ":", "-", "\\", "'", "$", "${", "}", "\n", "\t",
+                "0", "8080", "65535", "65536", "tcp", "udp", "/data", "[\"/a\"]", "::1", "é", "\u{0}",
+            ];
+            let length = self.next() % 12;
+            (0..length).map(|_| self.pick(FRAGMENTS)).collect()
+        }
+    }
+
+    #[test]
+    fn test_misc_instruction_fuzz() {
+        const INSTRUCTIONS: &[&str] = &["EXPOSE", "expose", "VOLUME", "volume", "Volume", "LABEL", "", "X"];
+        let mut rng = Xorshift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..5000 {
+            let arguments = rng.arguments();
+            if let Port::Network(port) = parse_exposed_port(&arguments) {
+                assert_ne!(port.port_number, 0, "EXPOSE {:?}", arguments);
+            }
+            assert!(matches!(parse_volume(&arguments), Port::Volume(_) | Port::None), "VOLUME {:?}", arguments);
+
+            let instruction = rng.pick(INSTRUCTIONS);
+            let misc = create_misc_instruction(instruction, vec![BreakableStringComponent::String(SpannedString {
+                span: Span { start: 0, end: 0 },
+                content: arguments.clone(),
+            })]);
+            match parse_misc_instruction(&misc, &HashMap::new()) {
+                Port::Network(port) => assert!(instruction.eq_ignore_ascii_case("EXPOSE") && port.port_number != 0),
+                Port::Volume(_) => assert!(instruction.eq_ignore_ascii_case("VOLUME")),
+                Port::None => {}
+            }
+
+            // Whole Dockerfiles may fail to parse, but mustn't panic
+            let content = format!("FROM alpine\n{} {}\n", instruction, arguments);
+            let _ = parse_containerfile(Box::new(std::io::Cursor::new(content)), "fuzz");
+        }
+    }
+
     #[test]
     fn test_parse_exposed_port() {
         // Test with a valid TCP port
@@ -398,5 +1647,14 @@ mod tests {
         } else {
             panic!("Expected Port::Network, got {:?}", parsed_unsupported_protocol);
         }
+        assert_eq!(
+            parse_exposed_port("8080/sctp"),
+            Port::Network(ExposedPort { port_number: 8080, protocol: Protocol::Sctp, line: None })
+        );
+
+        // EXPOSE can't publish a port, so host ports and addresses are invalid
+        assert_eq!(parse_exposed_port("8080:80"), Port::None);
+        assert_eq!(parse_exposed_port("127.0.0.1::80"), Port::None);
+        assert_eq!(parse_exposed_port(":80"), Port::None);
     }
 }
@@ -0,0 +1,855 @@
+//! Kubernetes manifests of several documents, such as `kubectl get -o yaml` writes for a
+//! namespace: the pods they run, directly or from the pod templates of workloads such as
+//! Deployments, the Services selecting those pods, the Ingresses routing traffic from
+//! outside the cluster to the Services, and the NetworkPolicies limiting what reaches the
+//! pods. Other kinds of object are skipped.
+use std::{collections::BTreeMap, fmt, io::Read};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use crate::{
+    parse_podfile::{pod_from_value, pod_from_workload, ParsedPod, PodContainer},
+    selector::{LabelOperator, LabelRequirement, LabelSelector},
+    validation::{SourceLocation, ValidationReport},
+};
+
+/// A port given by its number or by its name
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum PortReference {
+    Number(u16),
+    Name(String),
+}
+
+impl fmt::Display for PortReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortReference::Number(number) => write!(f, "{}", number),
+            PortReference::Name(name) => write!(f, "'{}'", name),
+        }
+    }
+}
+
+impl PortReference {
+    /// Whether the reference is to a port with this number and name
+    fn matches(&self, number: u16, name: Option<&str>) -> bool {
+        match self {
+            PortReference::Number(expected) => *expected == number,
+            PortReference::Name(expected) => name == Some(expected),
+        }
+    }
+}
+
+/// A port of a Service, and the port of the selected pods it forwards to
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServicePort {
+    pub port: u16,
+    /// The port of the pods, by number or by the name of a container port. The same number
+    /// as `port` when not given.
+    pub target_port: Option<PortReference>,
+    pub name: Option<String>,
+}
+
+impl ServicePort {
+    pub fn target_port(&self) -> PortReference {
+        self.target_port.clone().unwrap_or(PortReference::Number(self.port))
+    }
+}
+
+/// A Service, forwarding its ports to the pods its selector matches
+#[derive(Debug, Clone, PartialEq)]
+pub struct KubeService {
+    pub name: String,
+    pub selector: BTreeMap<String, String>,
+    pub ports: Vec<ServicePort>,
+}
+
+impl KubeService {
+    /// Whether the Service forwards to a pod. A Service without a selector forwards to none,
+    /// its endpoints being managed some other way.
+    pub fn selects(&self, pod: &ParsedPod) -> bool {
+        !self.selector.is_empty() && self.selector.iter().all(|(key, value)| pod.labels.get(key) == Some(value))
+    }
+}
+
+/// A route of an Ingress, from a host and path outside the cluster to a port of a Service
+#[derive(Debug, Clone, PartialEq)]
+pub struct IngressRoute {
+    /// The host routed, or None for any host
+    pub host: Option<String>,
+    /// The path routed, or None for any path, as for the default backend
+    pub path: Option<String>,
+    pub service: String,
+    pub port: PortReference,
+}
+
+impl IngressRoute {
+    /// The host and path routed, such as `shop.example.com/api`, with `*` for any host
+    pub fn address(&self) -> String {
+        format!("{}{}", self.host.as_deref().unwrap_or("*"), self.path.as_deref().unwrap_or("/*"))
+    }
+}
+
+/// An Ingress: its rules' paths, then its default backend, as routes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ingress {
+    pub name: String,
+    pub routes: Vec<IngressRoute>,
+    /// The document of its file the Ingress was read from, counting from 1
+    pub document: usize,
+}
+
+/// What a rule of a NetworkPolicy lets traffic come from, or go to
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyPeer {
+    /// The pods of the policy's namespace whose labels a selector matches
+    Pods(LabelSelector),
+    /// The pods of other namespaces, such as an ingress controller's
+    Namespaces,
+    /// Addresses in a CIDR block, outside the cluster
+    IpBlock(String),
+}
+
+impl PolicyPeer {
+    /// Whether the peer includes traffic from outside the cluster, which reaches pods through
+    /// an ingress controller in a namespace of its own. Which namespace, and which addresses,
+    /// isn't known, so any namespace or address block is taken to include it.
+    fn is_external(&self) -> bool {
+        !matches!(self, PolicyPeer::Pods(_))
+    }
+}
+
+/// A rule of a NetworkPolicy, allowing traffic from, or to, its peers on its ports. A rule
+/// without peers allows any, and one without ports every port.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyRule {
+    pub peers: Vec<PolicyPeer>,
+    pub ports: Vec<PortReference>,
+}
+
+impl PolicyRule {
+    fn allows_external(&self, port: u16, name: Option<&str>) -> bool {
+        (self.peers.is_empty() || self.peers.iter().any(PolicyPeer::is_external))
+            && (self.ports.is_empty() || self.ports.iter().any(|reference| reference.matches(port, name)))
+    }
+}
+
+/// A NetworkPolicy. The pods it selects only accept the traffic its ingress rules, or those
+/// of another policy selecting them, allow; likewise for egress when it isolates egress.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkPolicy {
+    pub name: String,
+    pub pod_selector: LabelSelector,
+    pub isolates_ingress: bool,
+    pub isolates_egress: bool,
+    pub ingress: Vec<PolicyRule>,
+    pub egress: Vec<PolicyRule>,
+}
+
+impl NetworkPolicy {
+    pub fn selects(&self, pod: &ParsedPod) -> bool {
+        self.pod_selector.matches(&pod.labels)
+    }
+}
+
+/// A route of an Ingress resolved to a container of a pod its Service selects
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedRoute<'a> {
+    pub ingress: &'a Ingress,
+    pub route: &'a IngressRoute,
+    pub service: &'a KubeService,
+    pub service_port: &'a ServicePort,
+    pub pod: &'a ParsedPod,
+    pub container: &'a PodContainer,
+    /// The port of the container
+    pub port: u16,
+    /// Whether the NetworkPolicies selecting the pod let traffic from outside the cluster in
+    pub allowed: bool,
+}
+
+/// The containers a route of an Ingress reaches, and why it doesn't reach the others
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteResolution<'a> {
+    pub resolved: Vec<ResolvedRoute<'a>>,
+    /// Why each pod the Service selects that the route doesn't reach isn't reached
+    pub unreached: Vec<String>,
+}
+
+/// An object of the manifests whose kind isn't modelled, such as a CronJob
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedObject {
+    pub kind: String,
+    pub name: Option<String>,
+    /// The document of its file the object was read from, counting from 1
+    pub document: usize,
+}
+
+/// The objects of a set of manifests that are modelled, and those that were skipped
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedManifests {
+    pub pods: Vec<ParsedPod>,
+    pub services: Vec<KubeService>,
+    pub ingresses: Vec<Ingress>,
+    pub network_policies: Vec<NetworkPolicy>,
+    pub skipped: Vec<SkippedObject>,
+}
+
+impl ParsedManifests {
+    /// Whether no object of the manifests is modelled
+    pub fn is_empty(&self) -> bool {
+        self.pods.is_empty() && self.services.is_empty() && self.ingresses.is_empty() && self.network_policies.is_empty()
+    }
+
+    /// The pod, when the manifests are of a single pod and nothing else
+    pub fn single_pod(&self) -> Option<&ParsedPod> {
+        match self.pods.as_slice() {
+            [pod] if self.services.is_empty() && self.ingresses.is_empty() && self.network_policies.is_empty() => Some(pod),
+            _ => None,
+        }
+    }
+
+    /// The NetworkPolicies selecting a pod
+    pub fn policies_selecting(&self, pod: &ParsedPod) -> Vec<&NetworkPolicy> {
+        self.network_policies.iter().filter(|policy| policy.selects(pod)).collect()
+    }
+
+    /// Whether traffic from outside the cluster may reach a port of a pod: the pod isn't
+    /// isolated for ingress, or a rule of a policy isolating it allows the traffic
+    pub fn allows_external(&self, pod: &ParsedPod, port: u16, name: Option<&str>) -> bool {
+        let isolating: Vec<&NetworkPolicy> = self.policies_selecting(pod).into_iter().filter(|policy| policy.isolates_ingress).collect();
+        isolating.is_empty() || isolating.iter().flat_map(|policy| &policy.ingress).any(|rule| rule.allows_external(port, name))
+    }
+
+    /// Resolve a route of an Ingress to the containers it reaches: through its Service to the
+    /// pods that selects, bare or run by workloads, and to the container of each listening on
+    /// the Service's target port. Err says why the route reaches nothing.
+    pub fn resolve_route<'a>(&'a self, ingress: &'a Ingress, route: &'a IngressRoute) -> Result<RouteResolution<'a>, String> {
+        let service = self.services.iter().find(|service| service.name == route.service).ok_or_else(|| format!("Service '{}' isn't defined", route.service))?;
+        let service_port = service
+            .ports
+            .iter()
+            .find(|port| route.port.matches(port.port, port.name.as_deref()))
+            .ok_or_else(|| format!("Service '{}' has no port {}", service.name, route.port))?;
+        let pods: Vec<&ParsedPod> = self.pods.iter().filter(|pod| service.selects(pod)).collect();
+        if pods.is_empty() {
+            return Err(format!("Service '{}' selects no pods", service.name));
+        }
+        let target = service_port.target_port();
+        let mut resolved = Vec::new();
+        let mut unreached = Vec::new();
+        for pod in pods {
+            let running: Vec<&PodContainer> = pod.containers.iter().filter(|container| !container.init).collect();
+            let listening = running.iter().find_map(|container| {
+                let port = container.ports.iter().find(|port| target.matches(port.container_port, port.name.as_deref()))?;
+                Some((*container, port.container_port, port.name.as_deref()))
+            });
+            // Containers needn't declare the ports they listen on, so a port given by number
+            // is taken to be that of a pod's only container
+            let listening = listening.or(match (&target, running.as_slice()) {
+                (PortReference::Number(number), [container]) => Some((*container, *number, None)),
+                _ => None,
+            });
+            let Some((container, port, name)) = listening else {
+                unreached.push(format!("no container of {} listens on port {}, the target of Service '{}'", pod.description(), target, service.name));
+                continue;
+            };
+            let allowed = self.allows_external(pod, port, name);
+            resolved.push(ResolvedRoute { ingress, route, service, service_port, pod, container, port, allowed });
+        }
+        match resolved.is_empty() {
+            true => Err(unreached.join("; ")),
+            false => Ok(RouteResolution { resolved, unreached }),
+        }
+    }
+
+    /// Every route of every Ingress which reaches a container, in the order of the Ingresses
+    pub fn resolved_routes(&self) -> Vec<ResolvedRoute<'_>> {
+        self.ingresses
+            .iter()
+            .flat_map(|ingress| ingress.routes.iter().map(move |route| (ingress, route)))
+            .filter_map(|(ingress, route)| self.resolve_route(ingress, route).ok())
+            .flat_map(|resolution| resolution.resolved)
+            .collect()
+    }
+
+    /// Validate each pod, check each route of each Ingress reaches a container of each pod its
+    /// Service selects and that one accepts traffic from outside the cluster, and warn about
+    /// each kind of object skipped, which routes can't be followed through
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        for pod in &self.pods {
+            let mut pod_report = pod.validate();
+            pod_report.locate(|_, _| vec![SourceLocation { document: Some(pod.document), ..Default::default() }]);
+            report.errors.extend(pod_report.errors);
+            report.warnings.extend(pod_report.warnings);
+            report.notes.extend(pod_report.notes);
+        }
+        for ingress in &self.ingresses {
+            let mut ingress_report = ValidationReport::default();
+            for route in &ingress.routes {
+                match self.resolve_route(ingress, route) {
+                    Err(reason) => ingress_report.warning(
+                        None,
+                        "unresolved-ingress-backend",
+                        format!("Ingress '{}' routes {} to port {} of Service '{}', which reaches nothing: {}", ingress.name, route.address(), route.port, route.service, reason),
+                    ),
+                    Ok(resolution) => {
+                        for reason in &resolution.unreached {
+                            ingress_report.warning(
+                                None,
+                                "unresolved-ingress-backend",
+                                format!("Ingress '{}' routes {} to Service '{}', but {}", ingress.name, route.address(), route.service, reason),
+                            );
+                        }
+                        if resolution.resolved.iter().all(|resolved| !resolved.allowed) {
+                            ingress_report.warning(
+                                None,
+                                "denied-ingress-route",
+                                format!(
+                                    "Ingress '{}' routes {} to Service '{}', but NetworkPolicies deny traffic from outside the cluster to every pod it selects",
+                                    ingress.name,
+                                    route.address(),
+                                    route.service
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+            ingress_report.locate(|_, _| vec![SourceLocation { document: Some(ingress.document), ..Default::default() }]);
+            report.warnings.extend(ingress_report.warnings);
+        }
+        let mut kinds: Vec<&str> = self.skipped.iter().map(|object| object.kind.as_str()).collect();
+        kinds.sort();
+        kinds.dedup();
+        for kind in kinds {
+            let objects: Vec<&SkippedObject> = self.skipped.iter().filter(|object| object.kind == kind).collect();
+            let names: Vec<String> = objects
+                .iter()
+                .map(|object| match &object.name {
+                    Some(name) => format!("'{}'", name),
+                    None => format!("(document {})", object.document),
+                })
+                .collect();
+            let mut kind_report = ValidationReport::default();
+            kind_report.warning(None, "unmodelled-object", format!("Skipped {} {}, as objects of that kind aren't modelled", kind, names.join(", ")));
+            kind_report.locate(|_, _| objects.iter().map(|object| SourceLocation { document: Some(object.document), ..Default::default() }).collect());
+            report.warnings.extend(kind_report.warnings);
+        }
+        report
+    }
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ServiceManifest {
+    metadata: Metadata,
+    #[serde(default)]
+    spec: ServiceSpec,
+}
+
+#[derive(Default, Deserialize)]
+struct ServiceSpec {
+    #[serde(default)]
+    selector: BTreeMap<String, String>,
+    #[serde(default)]
+    ports: Vec<ServicePort>,
+}
+
+#[derive(Deserialize)]
+struct IngressManifest {
+    metadata: Metadata,
+    #[serde(default)]
+    spec: IngressSpec,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IngressSpec {
+    default_backend: Option<IngressBackend>,
+    #[serde(default)]
+    rules: Vec<IngressRule>,
+}
+
+#[derive(Deserialize)]
+struct IngressRule {
+    host: Option<String>,
+    http: Option<IngressHttp>,
+}
+
+#[derive(Deserialize)]
+struct IngressHttp {
+    paths: Vec<IngressPath>,
+}
+
+#[derive(Deserialize)]
+struct IngressPath {
+    path: Option<String>,
+    backend: IngressBackend,
+}
+
+#[derive(Deserialize)]
+struct IngressBackend {
+    /// None for a backend which is a resource rather than a Service, which isn't modelled
+    service: Option<IngressServiceBackend>,
+}
+
+#[derive(Deserialize)]
+struct IngressServiceBackend {
+    name: String,
+    port: IngressServicePort,
+}
+
+#[derive(Deserialize)]
+struct IngressServicePort {
+    number: Option<u16>,
+    name: Option<String>,
+}
+
+impl IngressManifest {
+    fn into_ingress(self, document: usize) -> Result<Ingress> {
+        let name = self.metadata.name;
+        let paths = self.spec.rules.into_iter().flat_map(|rule| {
+            let host = rule.host;
+            rule.http.into_iter().flat_map(|http| http.paths).map(move |path| (host.clone(), path.path, path.backend))
+        });
+        let default = self.spec.default_backend.map(|backend| (None, None, backend));
+        let mut routes = Vec::new();
+        for (host, path, backend) in paths.chain(default) {
+            let Some(service) = backend.service else { continue };
+            let port = match (service.port.number, service.port.name) {
+                (Some(number), _) => PortReference::Number(number),
+                (None, Some(port)) => PortReference::Name(port),
+                (None, None) => return Err(anyhow!("The backend of Ingress {} for Service {} has no port", name, service.name)),
+            };
+            routes.push(IngressRoute { host, path, service: service.name, port });
+        }
+        Ok(Ingress { name, routes, document })
+    }
+}
+
+#[derive(Deserialize)]
+struct NetworkPolicyManifest {
+    metadata: Metadata,
+    spec: NetworkPolicySpec,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NetworkPolicySpec {
+    #[serde(default)]
+    pod_selector: SelectorManifest,
+    policy_types: Option<Vec<String>>,
+    #[serde(default)]
+    ingress: Vec<PolicyRuleManifest>,
+    egress: Option<Vec<PolicyRuleManifest>>,
+}
+
+#[derive(Default, Deserialize)]
+struct PolicyRuleManifest {
+    #[serde(default, alias = "to")]
+    from: Vec<PolicyPeerManifest>,
+    #[serde(default)]
+    ports: Vec<PolicyPortManifest>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PolicyPeerManifest {
+    pod_selector: Option<SelectorManifest>,
+    namespace_selector: Option<SelectorManifest>,
+    ip_block: Option<IpBlockManifest>,
+}
+
+#[derive(Deserialize)]
+struct IpBlockManifest {
+    cidr: String,
+}
+
+#[derive(Deserialize)]
+struct PolicyPortManifest {
+    port: Option<PortReference>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SelectorManifest {
+    #[serde(default)]
+    match_labels: BTreeMap<String, String>,
+    #[serde(default)]
+    match_expressions: Vec<SelectorExpression>,
+}
+
+#[derive(Deserialize)]
+struct SelectorExpression {
+    key: String,
+    operator: String,
+    #[serde(default)]
+    values: Vec<String>,
+}
+
+impl SelectorManifest {
+    fn into_selector(self) -> Result<LabelSelector> {
+        let equalities = self.match_labels.into_iter().map(|(key, value)| Ok(LabelRequirement { key, operator: LabelOperator::Equals(value) }));
+        let expressions = self.match_expressions.into_iter().map(|expression| {
+            let operator = match expression.operator.as_str() {
+                "In" => LabelOperator::In(expression.values),
+                "NotIn" => LabelOperator::NotIn(expression.values),
+                "Exists" => LabelOperator::Exists,
+                "DoesNotExist" => LabelOperator::DoesNotExist,
+                operator => return Err(anyhow!("Unknown label selector operator {}", operator)),
+            };
+            Ok(LabelRequirement { key: expression.key, operator })
+        });
+        Ok(LabelSelector { requirements: equalities.chain(expressions).collect::<Result<_>>()? })
+    }
+}
+
+impl PolicyRuleManifest {
+    fn into_rule(self) -> Result<PolicyRule> {
+        let mut peers = Vec::new();
+        for peer in self.from {
+            peers.push(match (peer.pod_selector, peer.namespace_selector, peer.ip_block) {
+                (_, _, Some(block)) => PolicyPeer::IpBlock(block.cidr),
+                (_, Some(_), None) => PolicyPeer::Namespaces,
+                (Some(selector), None, None) => PolicyPeer::Pods(selector.into_selector()?),
+                (None, None, None) => return Err(anyhow!("A NetworkPolicy peer has no podSelector, namespaceSelector or ipBlock")),
+            });
+        }
+        // A port without a number or name is every port of its protocol
+        let ports = if self.ports.iter().any(|port| port.port.is_none()) { Vec::new() } else { self.ports.into_iter().filter_map(|port| port.port).collect() };
+        Ok(PolicyRule { peers, ports })
+    }
+}
+
+impl NetworkPolicyManifest {
+    fn into_policy(self) -> Result<NetworkPolicy> {
+        let spec = self.spec;
+        // Policies isolate ingress unless they say otherwise, and egress when they have egress rules
+        let types = spec.policy_types.unwrap_or_else(|| {
+            let mut types = vec!["Ingress".to_string()];
+            types.extend(spec.egress.as_ref().map(|_| "Egress".to_string()));
+            types
+        });
+        Ok(NetworkPolicy {
+            name: self.metadata.name,
+            pod_selector: spec.pod_selector.into_selector()?,
+            isolates_ingress: types.iter().any(|kind| kind == "Ingress"),
+            isolates_egress: types.iter().any(|kind| kind == "Egress"),
+            ingress: spec.ingress.into_iter().map(PolicyRuleManifest::into_rule).collect::<Result<_>>()?,
+            egress: spec.egress.unwrap_or_default().into_iter().map(PolicyRuleManifest::into_rule).collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// The kinds of workload whose pod template is modelled as a pod with the workload's name
+pub const WORKLOAD_KINDS: &[&str] = &["Deployment", "StatefulSet", "DaemonSet", "ReplicaSet", "Job"];
+
+/// Parse Kubernetes manifests, documents separated by `---`. Pods, the workloads of
+/// `WORKLOAD_KINDS`, Services, Ingresses and NetworkPolicies are modelled, and other objects
+/// skipped. A document without a kind is taken to be a pod, as by `parse_podfile`.
+pub fn parse_manifests(reader: Box<dyn Read>) -> Result<ParsedManifests> {
+    let mut manifests = ParsedManifests::default();
+    for (index, document) in serde_yaml::Deserializer::from_reader(reader).enumerate() {
+        let value = serde_yaml::Value::deserialize(document)?;
+        // Such as the empty document after a trailing ---
+        if value.is_null() {
+            continue;
+        }
+        match value.get("kind").and_then(serde_yaml::Value::as_str).unwrap_or("Pod") {
+            "Pod" => manifests.pods.push(pod_from_value(value, index + 1)?),
+            kind if WORKLOAD_KINDS.contains(&kind) => manifests.pods.push(pod_from_workload(value, index + 1)?),
+            "Service" => {
+                let service: ServiceManifest = serde_yaml::from_value(value)?;
+                manifests.services.push(KubeService { name: service.metadata.name, selector: service.spec.selector, ports: service.spec.ports });
+            }
+            "Ingress" => manifests.ingresses.push(serde_yaml::from_value::<IngressManifest>(value)?.into_ingress(index + 1)?),
+            "NetworkPolicy" => manifests.network_policies.push(serde_yaml::from_value::<NetworkPolicyManifest>(value)?.into_policy()?),
+            kind => {
+                let name = value.get("metadata").and_then(|metadata| metadata.get("name")).and_then(serde_yaml::Value::as_str).map(str::to_string);
+                manifests.skipped.push(SkippedObject { kind: kind.to_string(), name, document: index + 1 });
+            }
+        }
+    }
+    if manifests.is_empty() {
+        let kinds: Vec<&str> = manifests.skipped.iter().map(|object| object.kind.as_str()).collect();
+        return Err(match kinds.is_empty() {
+            true => anyhow!("Expected Kubernetes manifests, found none"),
+            false => anyhow!("Expected a Pod, workload, Service, Ingress or NetworkPolicy manifest, found a {}", kinds.join(", ")),
+        });
+    }
+    Ok(manifests)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SHOP: &str = r#"
+apiVersion: networking.k8s.io/v1
+kind: Ingress
+metadata:
+  name: shop
+spec:
+  rules:
+    - host: shop.example.com
+      http:
+        paths:
+          - path: /
+            pathType: Prefix
+            backend:
+              service:
+                name: storefront
+                port:
+                  number: 80
+          - path: /api
+            pathType: Prefix
+            backend:
+              service:
+                name: api
+                port:
+                  name: http
+---
+apiVersion: v1
+kind: Service
+metadata:
+  name: storefront
+spec:
+  selector:
+    app: storefront
+  ports:
+    - port: 80
+      targetPort: 8080
+---
+apiVersion: v1
+kind: Service
+metadata:
+  name: api
+spec:
+  selector:
+    app: api
+  ports:
+    - name: http
+      port: 8000
+      targetPort: api
+---
+apiVersion: v1
+kind: Pod
+metadata:
+  name: storefront
+  labels:
+    app: storefront
+spec:
+  containers:
+    - name: nginx
+      image: nginx:1.25
+---
+apiVersion: v1
+kind: Pod
+metadata:
+  name: api
+  labels:
+    app: api
+spec:
+  containers:
+    - name: log-shipper
+      image: fluent-bit:3
+    - name: server
+      image: api:2.1
+      ports:
+        - name: api
+          containerPort: 9000
+---
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: worker
+spec:
+  replicas: 2
+  selector:
+    matchLabels:
+      app: worker
+  template:
+    metadata:
+      labels:
+        app: worker
+    spec:
+      containers:
+        - name: worker
+          image: shop/worker:2
+---
+apiVersion: batch/v1
+kind: CronJob
+metadata:
+  name: report
+spec: {}
+"#;
+
+    const POLICIES: &str = r#"
+---
+apiVersion: networking.k8s.io/v1
+kind: NetworkPolicy
+metadata:
+  name: default-deny
+spec:
+  podSelector: {}
+  policyTypes: [Ingress, Egress]
+---
+apiVersion: networking.k8s.io/v1
+kind: NetworkPolicy
+metadata:
+  name: allow-api
+spec:
+  podSelector:
+    matchExpressions:
+      - key: app
+        operator: In
+        values: [api]
+  ingress:
+    - from:
+        - namespaceSelector:
+            matchLabels:
+              kubernetes.io/metadata.name: ingress-nginx
+      ports:
+        - port: api
+    - from:
+        - podSelector:
+            matchLabels:
+              app: storefront
+"#;
+
+    fn parse(manifests: &str) -> ParsedManifests {
+        parse_manifests(Box::new(Cursor::new(manifests.to_string()))).unwrap()
+    }
+
+    /// Each resolved route as the address routed, the pod and container reached, the
+    /// container port, and whether it's allowed
+    fn routes(manifests: &ParsedManifests) -> Vec<(String, &str, &str, u16, bool)> {
+        manifests
+            .resolved_routes()
+            .iter()
+            .map(|resolved| (resolved.route.address(), resolved.pod.name.as_str(), resolved.container.name.as_str(), resolved.port, resolved.allowed))
+            .collect()
+    }
+
+    #[test]
+    fn test_ingress_routes() {
+        let manifests = parse(SHOP);
+        assert_eq!((manifests.pods.len(), manifests.services.len(), manifests.ingresses.len()), (3, 2, 1));
+        assert_eq!(manifests.ingresses[0].routes[1].port, PortReference::Name("http".to_string()));
+        assert_eq!(manifests.single_pod(), None);
+        // The storefront's only container is taken to listen on the target port it doesn't
+        // declare, and the api's named target port is found on the container declaring it
+        assert_eq!(
+            routes(&manifests),
+            vec![
+                ("shop.example.com/".to_string(), "storefront", "nginx", 8080, true),
+                ("shop.example.com/api".to_string(), "api", "server", 9000, true),
+            ]
+        );
+        // The CronJob is the one object not modelled
+        assert_eq!(manifests.validate().diagnostics(), vec!["warning[unmodelled-object]: Skipped CronJob 'report', as objects of that kind aren't modelled"]);
+        assert_eq!(manifests.validate().warnings[0].locations, vec![SourceLocation { document: Some(7), ..Default::default() }]);
+    }
+
+    #[test]
+    fn test_routes_through_workloads() {
+        // Ingress to Service to the pods of a Deployment, one of which doesn't listen on the
+        // target port: the route still reaches the other
+        let manifests = parse(
+            "kind: Ingress\nmetadata:\n  name: edge\nspec:\n  defaultBackend:\n    service:\n      name: web\n      port: {number: 80}\n\
+             ---\nkind: Service\nmetadata:\n  name: web\nspec:\n  selector: {app: web}\n  ports: [{port: 80, targetPort: http}]\n\
+             ---\nkind: Deployment\nmetadata:\n  name: web\nspec:\n  template:\n    metadata:\n      labels: {app: web}\n    spec:\n      containers:\n        - name: nginx\n          ports: [{name: http, containerPort: 8080}]\n\
+             ---\nkind: DaemonSet\nmetadata:\n  name: agent\nspec:\n  template:\n    metadata:\n      labels: {app: web}\n    spec:\n      containers: [{name: agent}, {name: proxy}]\n",
+        );
+        assert_eq!(routes(&manifests), vec![("*/*".to_string(), "web", "nginx", 8080, true)]);
+        assert_eq!(
+            manifests.validate().diagnostics(),
+            vec!["warning[unresolved-ingress-backend]: Ingress 'edge' routes */* to Service 'web', but no container of DaemonSet 'agent' listens on port 'http', the target of Service 'web'"]
+        );
+    }
+
+    #[test]
+    fn test_network_policies() {
+        let manifests = parse(&format!("{}{}", SHOP, POLICIES));
+        let policy = &manifests.network_policies[1];
+        assert_eq!(policy.pod_selector.to_string(), "app in (api)");
+        assert!(policy.isolates_ingress && !policy.isolates_egress);
+        assert_eq!(policy.ingress[0].peers, vec![PolicyPeer::Namespaces]);
+        assert_eq!(policy.ingress[1].peers, vec![PolicyPeer::Pods("app=storefront".parse().unwrap())]);
+        let api = &manifests.pods[1];
+        let names: Vec<&str> = manifests.policies_selecting(api).iter().map(|policy| policy.name.as_str()).collect();
+        assert_eq!(names, vec!["default-deny", "allow-api"]);
+
+        // The default deny isolates both pods, and only the api's port is let back in from the
+        // ingress controller's namespace; the storefront's pods don't count as outside
+        assert_eq!(
+            routes(&manifests),
+            vec![
+                ("shop.example.com/".to_string(), "storefront", "nginx", 8080, false),
+                ("shop.example.com/api".to_string(), "api", "server", 9000, true),
+            ]
+        );
+        assert!(!manifests.allows_external(api, 9001, None));
+        assert_eq!(
+            manifests.validate().diagnostics(),
+            vec![
+                "warning[denied-ingress-route]: Ingress 'shop' routes shop.example.com/ to Service 'storefront', but NetworkPolicies deny traffic from outside the cluster to every pod it selects",
+                "warning[unmodelled-object]: Skipped CronJob 'report', as objects of that kind aren't modelled",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unresolved_ingress_backends() {
+        let ingress = "kind: Ingress\nmetadata:\n  name: edge\nspec:\n  defaultBackend:\n    service:\n      name: web\n      port: {number: 80}\n  rules:\n    - http:\n        paths:\n          - path: /admin\n            backend:\n              service:\n                name: storefront\n                port: {number: 443}\n          - path: /api\n            backend:\n              service:\n                name: api\n                port: {name: http}\n";
+        let services = "---\nkind: Service\nmetadata:\n  name: storefront\nspec:\n  selector: {app: storefront}\n  ports: [{port: 80}]\n---\nkind: Service\nmetadata:\n  name: api\nspec:\n  selector: {app: api}\n  ports: [{name: http, port: 8000}]\n";
+        let manifests = parse(&format!("{}{}", ingress, services));
+        assert!(manifests.resolved_routes().is_empty());
+        assert_eq!(
+            manifests.validate().diagnostics(),
+            vec![
+                "warning[unresolved-ingress-backend]: Ingress 'edge' routes */admin to port 443 of Service 'storefront', which reaches nothing: Service 'storefront' has no port 443",
+                "warning[unresolved-ingress-backend]: Ingress 'edge' routes */api to port 'http' of Service 'api', which reaches nothing: Service 'api' selects no pods",
+                "warning[unresolved-ingress-backend]: Ingress 'edge' routes */* to port 80 of Service 'web', which reaches nothing: Service 'web' isn't defined",
+            ]
+        );
+        // Each is located at the Ingress's document
+        let report = parse(&format!("{}---\n{}", services, ingress)).validate();
+        let third = SourceLocation { document: Some(3), ..Default::default() };
+        assert!(report.warnings.iter().all(|warning| warning.locations == vec![third.clone()]));
+        assert_eq!(report.located_diagnostics()[0].lines().last(), Some("  --> defined in <input> (document 3)"));
+    }
+
+    #[test]
+    fn test_parse_manifests() {
+        let pod = "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: nginx\n---\n";
+        assert_eq!(parse(pod).single_pod().map(|pod| pod.name.as_str()), Some("web"));
+        let config = "kind: ConfigMap\nmetadata:\n  name: settings\n";
+        assert_eq!(
+            parse_manifests(Box::new(config.as_bytes())).unwrap_err().to_string(),
+            "Expected a Pod, workload, Service, Ingress or NetworkPolicy manifest, found a ConfigMap"
+        );
+
+        // A workload is its pod template, named for the workload
+        let worker = &parse(SHOP).pods[2];
+        assert_eq!((worker.name.as_str(), worker.kind.as_str(), worker.document), ("worker", "Deployment", 6));
+        assert_eq!(worker.labels["app"], "worker");
+        assert_eq!(worker.containers[0].image.as_deref(), Some("shop/worker:2"));
+        for kind in WORKLOAD_KINDS {
+            let workload = format!("kind: {}\nmetadata:\n  name: batch\nspec:\n  template:\n    spec:\n      containers: [{{name: run}}]\n", kind);
+            let manifests = parse(&workload);
+            assert_eq!(manifests.single_pod().map(|pod| (pod.name.as_str(), pod.kind.as_str())), Some(("batch", *kind)));
+        }
+        let untemplated = "kind: Deployment\nmetadata:\n  name: web\nspec: {}\n";
+        assert!(parse_manifests(Box::new(untemplated.as_bytes())).is_err());
+        let unknown = "kind: NetworkPolicy\nmetadata:\n  name: p\nspec:\n  podSelector:\n    matchExpressions: [{key: app, operator: Like}]\n";
+        assert_eq!(parse_manifests(Box::new(unknown.as_bytes())).unwrap_err().to_string(), "Unknown label selector operator Like");
+    }
+}
@@ -1,7 +1,497 @@
-use std::io::Read;
-use anyhow::Result;
-// TODO - not written
-pub fn parse_podfile(_reader: Box<dyn Read>) -> Result<()> {
-Ok(())
+//! Kubernetes pod manifests, modelled as the containers they run and the volumes they share
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer};
+use crate::{
+    util::{is_absolute_container_path, overlapping_mounts, MountOverlap},
+    validation::ValidationReport,
+};
+
+/// A port a container of a pod listens on
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PodPort {
+    pub container_port: u16,
+    pub host_port: Option<u16>,
+    /// `TCP`, `UDP` or `SCTP`, TCP when not given
+    pub protocol: Option<String>,
+    pub name: Option<String>,
+}
+
+impl PodPort {
+    pub fn protocol(&self) -> &str {
+        self.protocol.as_deref().unwrap_or("TCP")
+    }
+}
+
+/// A volume mounted into a container of a pod
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PodVolumeMount {
+    /// The pod volume mounted
+    pub name: String,
+    pub mount_path: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// An environment variable set on a container of a pod
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PodEnvVar {
+    pub name: String,
+    /// None when the value is taken from elsewhere with `valueFrom`, such as a Secret
+    pub value: Option<String>,
+}
+
+/// The compute resources a container of a pod is limited to
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct PodResources {
+    /// Kubernetes quantities by resource, such as `cpu: 500m` and `memory: 256Mi`
+    #[serde(default, deserialize_with = "deserialize_quantities")]
+    pub limits: BTreeMap<String, String>,
+}
+
+/// Deserialize quantities written as strings or numbers, such as `cpu: 1`, as strings
+fn deserialize_quantities<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    let quantities: BTreeMap<String, serde_yaml::Value> = BTreeMap::deserialize(deserializer)?;
+    quantities
+        .into_iter()
+        .map(|(resource, quantity)| match quantity {
+            serde_yaml::Value::String(quantity) => Ok((resource, quantity)),
+            serde_yaml::Value::Number(quantity) => Ok((resource, quantity.to_string())),
+            _ => Err(D::Error::custom(format!("the {} limit should be a quantity such as 500m or 256Mi", resource))),
+        })
+        .collect()
+}
+
+/// A container of a pod
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PodContainer {
+    pub name: String,
+    pub image: Option<String>,
+    #[serde(default)]
+    pub ports: Vec<PodPort>,
+    #[serde(default)]
+    pub volume_mounts: Vec<PodVolumeMount>,
+    #[serde(default)]
+    pub env: Vec<PodEnvVar>,
+    #[serde(default)]
+    pub resources: PodResources,
+    /// Whether it's an init container, run to completion before the others start
+    #[serde(skip)]
+    pub init: bool,
+}
+
+/// A volume of a pod, which its containers may mount
+#[derive(Debug, Clone, PartialEq)]
+pub struct PodVolume {
+    pub name: String,
+    /// The kind of volume, such as `emptyDir` or `configMap`, if the manifest gives one
+    pub source: Option<String>,
+}
+
+/// A parsed pod: its init containers, in the order they run, then its other containers
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedPod {
+    pub name: String,
+    /// `Pod`, or the kind of workload, such as `Deployment`, whose pod template it was read
+    /// from, in which case it has the workload's name
+    pub kind: String,
+    pub containers: Vec<PodContainer>,
+    pub volumes: Vec<PodVolume>,
+    /// The operating system the pod's containers run on, `linux` or `windows`, if given
+    pub os: Option<String>,
+    /// The labels of the pod, from its metadata
+    pub labels: HashMap<String, String>,
+    /// The document of its file the pod was read from, counting from 1
+    pub document: usize,
+}
+
+/// A pod or workload as messages name it, such as `pod 'web'` or `Deployment 'web'`
+fn describe(kind: &str, name: &str) -> String {
+    match kind {
+        "Pod" => format!("pod '{}'", name),
+        kind => format!("{} '{}'", kind, name),
+    }
+}
+
+impl ParsedPod {
+    /// The pod as messages name it: `pod 'web'`, or for a workload's pod `Deployment 'web'`
+    pub fn description(&self) -> String {
+        describe(&self.kind, &self.name)
+    }
+
+    /// The containers mounting each volume of the pod that more than one of them mounts,
+    /// in the order the volumes are declared
+    pub fn shared_volumes(&self) -> Vec<(&PodVolume, Vec<&PodContainer>)> {
+        self.volumes
+            .iter()
+            .filter_map(|volume| {
+                let containers: Vec<&PodContainer> =
+                    self.containers.iter().filter(|container| container.volume_mounts.iter().any(|mount| mount.name == volume.name)).collect();
+                (containers.len() > 1).then_some((volume, containers))
+            })
+            .collect()
+    }
+
+    /// Check the pod's containers can run together. They share a network namespace, so no
+    /// two of them may listen on the same port, nor claim the same host port. Init containers
+    /// run before the others, so can't collide with them. Volumes shared by containers, the
+    /// usual way for a sidecar to exchange data, are noted. Each container must mount its
+    /// volumes at absolute paths, none at the same path as another.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        for container in &self.containers {
+            self.validate_volume_mounts(container, &mut report);
+        }
+        let ports: Vec<(&PodContainer, &PodPort)> =
+            self.containers.iter().filter(|container| !container.init).flat_map(|container| container.ports.iter().map(move |port| (container, port))).collect();
+        for (index, (container, port)) in ports.iter().enumerate() {
+            let others = ports[..index].iter().filter(|(other, other_port)| other.name != container.name && other_port.protocol() == port.protocol());
+            for (other, other_port) in others {
+                if other_port.container_port == port.container_port {
+                    report.error(
+                        Some(&container.name),
+                        "duplicate-container-port",
+                        format!(
+                            "Containers '{}' and '{}' of pod '{}' both listen on port {}/{}",
+                            other.name, container.name, self.name, port.container_port, port.protocol()
+                        ),
+                    );
+                }
+                if let Some(host_port) = port.host_port.filter(|host_port| other_port.host_port == Some(*host_port)) {
+                    report.error(
+                        Some(&container.name),
+                        "duplicate-host-port",
+                        format!("Containers '{}' and '{}' of pod '{}' both claim host port {}/{}", other.name, container.name, self.name, host_port, port.protocol()),
+                    );
+                }
+            }
+        }
+        for (volume, containers) in self.shared_volumes() {
+            let names: Vec<String> = containers.iter().map(|container| format!("'{}'", container.name)).collect();
+            report.note(None, "shared-volume", format!("Volume '{}' of pod '{}' is shared by containers {}", volume.name, self.name, names.join(", ")));
+        }
+        report
+    }
+
+    fn validate_volume_mounts(&self, container: &PodContainer, report: &mut ValidationReport) {
+        let windows = self.os.as_deref() == Some("windows");
+        let describe = |mount: &PodVolumeMount| format!("volume '{}' at '{}'", mount.name, mount.mount_path);
+        for mount in container.volume_mounts.iter().filter(|mount| !is_absolute_container_path(&mount.mount_path, windows)) {
+            report.error(
+                Some(&container.name),
+                "invalid-volume",
+                format!("Container '{}' of pod '{}' mounts {}, which is not an absolute path", container.name, self.name, describe(mount)),
+            );
+        }
+        let targets: Vec<&str> = container.volume_mounts.iter().map(|mount| mount.mount_path.as_str()).collect();
+        for (first, second, overlap) in overlapping_mounts(&targets) {
+            let (outer, inner) = (&container.volume_mounts[first], &container.volume_mounts[second]);
+            match overlap {
+                MountOverlap::Duplicate => report.error(
+                    Some(&container.name),
+                    "duplicate-volume-target",
+                    format!("Container '{}' of pod '{}' mounts both {} and {}", container.name, self.name, describe(outer), describe(inner)),
+                ),
+                MountOverlap::Nested => report.warning(
+                    Some(&container.name),
+                    "nested-volume-target",
+                    format!(
+                        "Container '{}' of pod '{}' mounts {} inside {}, so what's visible there depends on the order they're mounted",
+                        container.name, self.name, describe(inner), describe(outer)
+                    ),
+                ),
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    kind: Option<String>,
+    metadata: Metadata,
+    spec: Spec,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    name: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Spec {
+    #[serde(default)]
+    init_containers: Vec<PodContainer>,
+    #[serde(default)]
+    containers: Vec<PodContainer>,
+    #[serde(default)]
+    volumes: Vec<serde_yaml::Mapping>,
+    os: Option<PodOs>,
 }
 
+#[derive(Deserialize)]
+struct PodOs {
+    name: String,
+}
+
+/// A workload running pods from a template, such as a Deployment
+#[derive(Deserialize)]
+struct WorkloadManifest {
+    kind: String,
+    metadata: WorkloadMetadata,
+    spec: WorkloadSpec,
+}
+
+#[derive(Deserialize)]
+struct WorkloadMetadata {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct WorkloadSpec {
+    template: PodTemplate,
+}
+
+#[derive(Deserialize)]
+struct PodTemplate {
+    #[serde(default)]
+    metadata: TemplateMetadata,
+    spec: Spec,
+}
+
+#[derive(Default, Deserialize)]
+struct TemplateMetadata {
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+/// Parse a pod manifest, such as `kubectl get pod -o yaml` writes
+pub fn parse_podfile(reader: Box<dyn Read>) -> Result<ParsedPod> {
+    pod_from_manifest(serde_yaml::from_reader(reader)?, 1)
+}
+
+/// Parse a pod manifest already read as one document of several
+pub(crate) fn pod_from_value(value: serde_yaml::Value, document: usize) -> Result<ParsedPod> {
+    pod_from_manifest(serde_yaml::from_value(value)?, document)
+}
+
+/// Parse the pod template of a workload, such as a Deployment, already read as one document
+/// of several. The pod has the workload's name and the template's labels.
+pub(crate) fn pod_from_workload(value: serde_yaml::Value, document: usize) -> Result<ParsedPod> {
+    let workload: WorkloadManifest = serde_yaml::from_value(value)?;
+    let template = workload.spec.template;
+    pod_from_spec(workload.metadata.name, workload.kind, template.metadata.labels, template.spec, document)
+}
+
+fn pod_from_manifest(manifest: Manifest, document: usize) -> Result<ParsedPod> {
+    if let Some(kind) = manifest.kind.filter(|kind| kind != "Pod") {
+        return Err(anyhow!("Expected a Pod manifest, found a {}", kind));
+    }
+    pod_from_spec(manifest.metadata.name, "Pod".to_string(), manifest.metadata.labels, manifest.spec, document)
+}
+
+fn pod_from_spec(name: String, kind: String, labels: HashMap<String, String>, spec: Spec, document: usize) -> Result<ParsedPod> {
+    let mut containers = spec.init_containers;
+    for container in &mut containers {
+        container.init = true;
+    }
+    containers.extend(spec.containers);
+    let described = describe(&kind, &name);
+    let volumes = spec
+        .volumes
+        .iter()
+        .map(|volume| {
+            let volume_name = volume.get("name").and_then(serde_yaml::Value::as_str).ok_or_else(|| anyhow!("A volume of {} has no name", described))?;
+            let source = volume.keys().filter_map(serde_yaml::Value::as_str).find(|key| *key != "name").map(str::to_string);
+            Ok(PodVolume { name: volume_name.to_string(), source })
+        })
+        .collect::<Result<_>>()?;
+    Ok(ParsedPod {
+        name,
+        kind,
+        containers,
+        volumes,
+        os: spec.os.map(|os| os.name),
+        labels,
+        document,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_podfile() {
+        let manifest = r#"
+apiVersion: v1
+kind: Pod
+metadata:
+  name: web
+  labels:
+    tier: frontend
+    app.kubernetes.io/part-of: shop
+spec:
+  initContainers:
+    - name: fetch
+      image: busybox:1.36
+      volumeMounts:
+        - name: content
+          mountPath: /work
+  containers:
+    - name: nginx
+      image: nginx:1.25
+      volumeMounts:
+        - name: content
+          mountPath: /usr/share/nginx/html
+          readOnly: true
+    - name: metrics
+      image: exporter:0.11
+  volumes:
+    - name: content
+      emptyDir: {}
+    - name: config
+      configMap:
+        name: web-config
+"#;
+        let pod = parse_podfile(Box::new(manifest.as_bytes())).unwrap();
+        assert_eq!(pod.name, "web");
+        assert_eq!(pod.labels["app.kubernetes.io/part-of"], "shop");
+        assert!("tier in (frontend,edge),!canary".parse::<crate::selector::LabelSelector>().unwrap().matches(&pod.labels));
+        assert!(!"tier!=frontend".parse::<crate::selector::LabelSelector>().unwrap().matches(&pod.labels));
+        let names: Vec<(&str, bool)> = pod.containers.iter().map(|container| (container.name.as_str(), container.init)).collect();
+        assert_eq!(names, vec![("fetch", true), ("nginx", false), ("metrics", false)]);
+        assert!(pod.containers[1].volume_mounts[0].read_only);
+        assert_eq!(pod.volumes[1], PodVolume { name: "config".to_string(), source: Some("configMap".to_string()) });
+
+        let shared = pod.shared_volumes();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].0.name, "content");
+        assert_eq!(shared[0].1.iter().map(|container| container.name.as_str()).collect::<Vec<_>>(), vec!["fetch", "nginx"]);
+
+        let deployment = "kind: Deployment\nmetadata:\n  name: web\nspec: {}\n";
+        assert!(parse_podfile(Box::new(deployment.as_bytes())).is_err());
+    }
+
+    #[test]
+    fn test_validate_sidecar() {
+        let manifest = r#"
+kind: Pod
+metadata:
+  name: web
+spec:
+  containers:
+    - name: nginx
+      image: nginx:1.25
+      ports:
+        - containerPort: 80
+          hostPort: 8080
+      volumeMounts:
+        - name: logs
+          mountPath: /var/log/nginx
+    - name: shipper
+      image: fluent-bit:3
+      ports:
+        - containerPort: 2020
+      volumeMounts:
+        - name: logs
+          mountPath: /logs
+  volumes:
+    - name: logs
+      emptyDir: {}
+"#;
+        let pod = parse_podfile(Box::new(manifest.as_bytes())).unwrap();
+        let report = pod.validate();
+        assert!(report.is_valid());
+        assert!(report.warnings.is_empty());
+        assert_eq!(report.diagnostics(), vec!["note[shared-volume]: Volume 'logs' of pod 'web' is shared by containers 'nginx', 'shipper'"]);
+        #[cfg(feature = "sysml")]
+        assert!(crate::gen_sysml::sysml_part_hierarchy(&pod, &Default::default()).contains("    bind nginx.logs = shipper.logs;\n"));
+    }
+
+    #[test]
+    fn test_validate_port_collisions() {
+        let manifest = r#"
+kind: Pod
+metadata:
+  name: web
+spec:
+  initContainers:
+    - name: warmup
+      ports:
+        - containerPort: 80
+  containers:
+    - name: nginx
+      ports:
+        - containerPort: 80
+          hostPort: 8080
+        - containerPort: 53
+          protocol: UDP
+    - name: envoy
+      ports:
+        - containerPort: 80
+        - containerPort: 9901
+          hostPort: 8080
+        - containerPort: 53
+"#;
+        let report = parse_podfile(Box::new(manifest.as_bytes())).unwrap().validate();
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.diagnostics(),
+            vec![
+                "error[duplicate-container-port]: Containers 'nginx' and 'envoy' of pod 'web' both listen on port 80/TCP",
+                "error[duplicate-host-port]: Containers 'nginx' and 'envoy' of pod 'web' both claim host port 8080/TCP",
+            ]
+        );
+        assert_eq!(report.errors[0].service.as_deref(), Some("envoy"));
+    }
+
+    #[test]
+    fn test_validate_volume_mounts() {
+        let manifest = r#"
+kind: Pod
+metadata:
+  name: web
+spec:
+  containers:
+    - name: app
+      volumeMounts:
+        - name: data
+          mountPath: /data
+        - name: cache
+          mountPath: /data/cache
+        - name: config
+          mountPath: etc/app
+        - name: secrets
+          mountPath: /data/
+"#;
+        let report = parse_podfile(Box::new(manifest.as_bytes())).unwrap().validate();
+        assert_eq!(
+            report.diagnostics(),
+            vec![
+                "error[invalid-volume]: Container 'app' of pod 'web' mounts volume 'config' at 'etc/app', which is not an absolute path",
+                "error[duplicate-volume-target]: Container 'app' of pod 'web' mounts both volume 'data' at '/data' and volume 'secrets' at '/data/'",
+                "warning[nested-volume-target]: Container 'app' of pod 'web' mounts volume 'cache' at '/data/cache' inside volume 'data' at '/data', so what's visible there depends on the order they're mounted",
+                "warning[nested-volume-target]: Container 'app' of pod 'web' mounts volume 'cache' at '/data/cache' inside volume 'secrets' at '/data/', so what's visible there depends on the order they're mounted",
+            ]
+        );
+        assert!(report.errors.iter().all(|error| error.service.as_deref() == Some("app")));
+
+        let windows = "kind: Pod\nmetadata:\n  name: win\nspec:\n  os:\n    name: windows\n  containers:\n    - name: iis\n      volumeMounts:\n        - name: logs\n          mountPath: C:\\inetpub\\logs\n";
+        let pod = parse_podfile(Box::new(windows.as_bytes())).unwrap();
+        assert_eq!(pod.os.as_deref(), Some("windows"));
+        assert!(pod.validate().diagnostics().is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+//! A single call generating an artifact from the content of a containerfile, compose file or
+//! pod manifest, for embedding where there's no filesystem, environment or terminal, such as
+//! WebAssembly in a browser. Nothing is read but the input and nothing is printed: problems
+//! are returned as diagnostics alongside the artifact.
+use std::io::{Cursor, Read};
+use serde::Serialize;
+use crate::{
+    gen_sysml::{sysml_cargotecture_package, sysml_compose_package, SysmlOptions},
+    parse_compose::{parse_composefiles_unvalidated, ParseOptions},
+    parse_dockerfile::parse_containerfile,
+    validation::{validate_compose_readers, validate_containerfile_reader, ValidateOptions, ValidationReport},
+};
+#[cfg(feature = "diagrams")]
+use crate::{
+    gen_csv::{compose_to_csv_with_extensions, CSV_HEADER},
+    gen_markdown::{compose_to_markdown, container_to_markdown},
+};
+#[cfg(feature = "pod")]
+use crate::{gen_sysml::sysml_kubernetes, parse_manifests::parse_manifests, validation::validate_pod_reader};
+
+/// What the input is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Containerfile,
+    Compose,
+    #[cfg(feature = "pod")]
+    Pod,
+}
+
+/// What to generate from the input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Sysml,
+    #[cfg(feature = "diagrams")]
+    Markdown,
+    #[cfg(feature = "diagrams")]
+    Csv,
+}
+
+/// Options for `process`
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    /// The name of the container, or of the package a compose file is modelled in
+    pub name: String,
+    pub format: OutputFormat,
+    pub sysml: SysmlOptions,
+    /// Lint containerfiles, reporting findings as diagnostics
+    pub lint: bool,
+    /// Drop unknown compose fields with a warning rather than failing the parse
+    pub lenient: bool,
+    /// Model the containers built for compose services from their Dockerfiles. This needs
+    /// the Dockerfiles to be read, so is unavailable, which is reported as a warning.
+    pub follow_builds: bool,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        ProcessOptions {
+            name: "model".to_string(),
+            format: OutputFormat::default(),
+            sysml: SysmlOptions::default(),
+            lint: false,
+            lenient: false,
+            follow_builds: false,
+        }
+    }
+}
+
+/// The result of `process`
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ProcessOutput {
+    /// The artifact generated, None when the input couldn't be parsed or the format doesn't
+    /// apply to it
+    pub artifact: Option<String>,
+    pub diagnostics: ValidationReport,
+}
+
+impl ProcessOutput {
+    /// The artifact and diagnostics as a JSON object, for callers across an FFI boundary
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a process output is always serialisable")
+    }
+}
+
+fn reader(input: &str) -> Box<dyn Read> {
+    Box::new(Cursor::new(input.to_string()))
+}
+
+/// Validate `input` and generate `options.format` from it, without touching the filesystem,
+/// environment or standard streams. Features which need files, such as following compose
+/// builds or `extends.file`, are reported as unavailable.
+pub fn process(input: &str, kind: InputKind, options: &ProcessOptions) -> ProcessOutput {
+    let parse = ParseOptions { lenient: options.lenient, read_files: false, ..Default::default() };
+    let validate = ValidateOptions { parse: parse.clone(), lint: options.lint, ..Default::default() };
+    // Reading from memory can't fail, so only the diagnostics of the input remain
+    let (mut diagnostics, artifact) = match kind {
+        InputKind::Containerfile => {
+            let diagnostics = validate_containerfile_reader(reader(input), &validate).unwrap_or_default();
+            let artifact = parse_containerfile(reader(input), &options.name).ok().map(|container| match options.format {
+                OutputFormat::Sysml => sysml_cargotecture_package(&container, &options.sysml),
+                #[cfg(feature = "diagrams")]
+                OutputFormat::Markdown => container_to_markdown(&container),
+                #[cfg(feature = "diagrams")]
+                OutputFormat::Csv => format!("{}\n{}", CSV_HEADER, container.to_csv_row()),
+            });
+            (diagnostics, artifact)
+        }
+        InputKind::Compose => {
+            let diagnostics = validate_compose_readers(vec![reader(input)], &validate).unwrap_or_default();
+            let artifact = parse_composefiles_unvalidated(vec![reader(input)], &parse).ok().map(|compose| match options.format {
+                OutputFormat::Sysml => sysml_compose_package(&compose, &options.name, &options.sysml),
+                #[cfg(feature = "diagrams")]
+                OutputFormat::Markdown => compose_to_markdown(&compose),
+                #[cfg(feature = "diagrams")]
+                OutputFormat::Csv => compose_to_csv_with_extensions(&compose, &options.sysml.promote_extensions),
+            });
+            (diagnostics, artifact)
+        }
+        #[cfg(feature = "pod")]
+        InputKind::Pod => {
+            let diagnostics = validate_pod_reader(reader(input), &validate).unwrap_or_default();
+            let artifact = parse_manifests(reader(input))
+                .ok()
+                .filter(|_| options.format == OutputFormat::Sysml)
+                .map(|manifests| sysml_kubernetes(&manifests, &options.name, &options.sysml));
+            (diagnostics, artifact)
+        }
+    };
+    #[cfg(feature = "pod")]
+    if kind == InputKind::Pod && options.format != OutputFormat::Sysml {
+        diagnostics.warning(None, "unavailable", "Only SysML can be generated from pod manifests".to_string());
+    }
+    if options.follow_builds && kind == InputKind::Compose {
+        diagnostics.warning(
+            None,
+            "unavailable",
+            "Following builds is unavailable in this mode, which can't read files, so services are modelled from the compose file alone".to_string(),
+        );
+    }
+    ProcessOutput { artifact, diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_compose::parse_composefile;
+
+    const ELK: &str = include_str!("../tests/fixtures/elk.yml");
+
+    #[test]
+    fn test_process_compose() {
+        let options = ProcessOptions { name: "elk".to_string(), ..Default::default() };
+        let output = process(ELK, InputKind::Compose, &options);
+        let compose = parse_composefile(Box::new(ELK.as_bytes())).unwrap().value;
+        assert_eq!(output.artifact, Some(sysml_compose_package(&compose, "elk", &SysmlOptions::default())));
+        assert_eq!(output.diagnostics, compose.validate());
+
+        let json: serde_json::Value = serde_json::from_str(&output.to_json()).unwrap();
+        assert!(json["artifact"].as_str().unwrap().starts_with("package elk"));
+        assert!(json["diagnostics"]["errors"].as_array().unwrap().is_empty());
+
+        let followed = process(ELK, InputKind::Compose, &ProcessOptions { follow_builds: true, ..options });
+        assert_eq!(followed.artifact, output.artifact);
+        assert_eq!(followed.diagnostics.warnings.last().unwrap().code, "unavailable");
+    }
+
+    #[test]
+    fn test_process_without_files() {
+        let yaml = "services:\n  web:\n    extends:\n      file: common.yml\n      service: base\n";
+        let output = process(yaml, InputKind::Compose, &ProcessOptions::default());
+        assert_eq!(output.artifact, None);
+        assert_eq!(
+            output.diagnostics.diagnostics(),
+            vec!["error[parse-failed]: Parse failed: extends.file common.yml is unavailable in this mode, which can't read files"]
+        );
+    }
+
+    #[test]
+    fn test_process_containerfile() {
+        let dockerfile = "FROM rust:1.75\nRUN apt-get install -y curl\nEXPOSE 8080\n";
+        let output = process(dockerfile, InputKind::Containerfile, &ProcessOptions { name: "app".to_string(), lint: true, ..Default::default() });
+        assert!(output.artifact.unwrap().contains("part app"));
+        assert!(!output.diagnostics.warnings.is_empty());
+
+        let broken = process("FROM alpine\nCOPY\n", InputKind::Containerfile, &ProcessOptions::default());
+        assert_eq!(broken.artifact, None);
+        assert_eq!(broken.diagnostics.errors[0].code, "parse-failed");
+    }
+
+    #[cfg(feature = "pod")]
+    #[test]
+    fn test_process_pod() {
+        let manifest = "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: nginx\n      image: nginx:1.25\n";
+        let output = process(manifest, InputKind::Pod, &ProcessOptions::default());
+        assert!(output.artifact.unwrap().contains("part def webPod"));
+        assert!(output.diagnostics.is_valid());
+    }
+}
@@ -0,0 +1,126 @@
+//! Provenance of generated models: the tool that generated them, the inputs it read and
+//! the options it used, so a committed model can be traced back to what produced it
+use std::{path::Path, time::SystemTime};
+use serde::{Deserialize, Serialize};
+use crate::util::sha256_hex;
+
+/// An input file and the SHA-256 digest of its content
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputHash {
+    pub path: String,
+    pub sha256: String,
+}
+
+impl InputHash {
+    pub fn new(path: &str, content: &[u8]) -> Self {
+        InputHash { path: path.to_string(), sha256: sha256_hex(content) }
+    }
+
+    /// Hash a file as read from disk now
+    pub fn of_file(path: &Path) -> std::io::Result<Self> {
+        Ok(InputHash::new(&path.to_string_lossy(), &std::fs::read(path)?))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Provenance {
+    pub tool: String,
+    pub version: String,
+    /// When the model was generated, as an RFC 3339 UTC timestamp. Omitted for reproducible
+    /// output, so that the same inputs always generate the same bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generated_at: Option<String>,
+    pub inputs: Vec<InputHash>,
+    /// The effective generation options, as `(name, value)` pairs
+    pub options: Vec<(String, String)>,
+}
+
+/// The first line of the SysML comment holding provenance, by which it's recognised
+const SYSML_MARKER: &str = "/* Provenance";
+
+impl Provenance {
+    pub fn new(version: &str, reproducible: bool) -> Self {
+        Provenance {
+            tool: "cargotecture".to_string(),
+            version: version.to_string(),
+            generated_at: if reproducible { None } else { Some(rfc3339_utc(SystemTime::now())) },
+            ..Default::default()
+        }
+    }
+
+    /// The provenance of a model generated from other inputs, but otherwise as this one
+    pub fn with_inputs(&self, inputs: Vec<InputHash>) -> Self {
+        Provenance { inputs, ..self.clone() }
+    }
+
+    /// Render as a SysML comment block, one fact per line
+    pub fn sysml_comment(&self) -> String {
+        let mut comment = format!("{}\n * tool: {} {}\n", SYSML_MARKER, self.tool, self.version);
+        if let Some(generated_at) = &self.generated_at {
+            comment.push_str(&format!(" * generated: {}\n", generated_at));
+        }
+        for input in &self.inputs {
+            comment.push_str(&format!(" * input: {} sha256:{}\n", input.path.replace("*/", "*\\/"), input.sha256));
+        }
+        for (name, value) in &self.options {
+            comment.push_str(&format!(" * option: {}={}\n", name, value.replace("*/", "*\\/")));
+        }
+        comment.push_str(" */\n\n");
+        comment
+    }
+}
+
+/// Remove provenance comments from generated SysML, e.g. to compare models generated at
+/// different times
+pub fn strip_sysml_provenance(sysml: &str) -> String {
+    let mut stripped = String::new();
+    let mut lines = sysml.split_inclusive('\n');
+    while let Some(line) = lines.next() {
+        if line.trim() != SYSML_MARKER {
+            stripped.push_str(line);
+            continue;
+        }
+        for line in lines.by_ref() {
+            if line.trim() == "*/" {
+                break;
+            }
+        }
+        // The blank line separating the comment from what follows
+        if let Some(line) = lines.next().filter(|line| !line.trim().is_empty()) {
+            stripped.push_str(line);
+        }
+    }
+    stripped
+}
+
+/// Format a time as an RFC 3339 UTC timestamp to the second, e.g. `2024-03-01T12:00:00Z`
+fn rfc3339_utc(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_rfc3339_utc() {
+        assert_eq!(rfc3339_utc(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339_utc(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339_utc(UNIX_EPOCH + Duration::from_secs(1_709_294_400)), "2024-03-01T12:00:00Z");
+    }
+
+    #[test]
+    fn test_sysml_comment_round_trip() {
+        let mut provenance = Provenance::new("1.0", true).with_inputs(vec![InputHash::new("compose.yml", b"abc")]);
+        provenance.options.push(("group_by".to_string(), "none".to_string()));
+        let comment = provenance.sysml_comment();
+        assert_eq!(
+            comment,
+            "/* Provenance\n * tool: cargotecture 1.0\n * input: compose.yml sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\n * option: group_by=none\n */\n\n"
+        );
+        let sysml = format!("package a {{\n{}    part b;\n}}\n", comment.lines().map(|l| format!("    {}\n", l)).collect::<String>());
+        assert_eq!(strip_sysml_provenance(&sysml), "package a {\n    part b;\n}\n");
+        assert!(Provenance::new("1.0", false).sysml_comment().contains(" * generated: "));
+    }
+}
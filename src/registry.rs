@@ -0,0 +1,321 @@
+//! Checks that images exist in their registries, using the registry HTTP API's manifest
+//! endpoint. Registries on the local host, which Docker contacts over plain HTTP, can always
+//! be checked. Others, such as Docker Hub and GHCR, are reached over HTTPS with an anonymous
+//! pull token, which needs the `https` feature.
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// The media types of the manifests and indexes an image tag may name
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+/// An image reference split into the registry holding it, its repository and its tag or digest
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImageReference {
+    pub registry: String,
+    pub repository: String,
+    pub reference: String,
+}
+
+impl ImageReference {
+    /// Parse an image reference, normalised as Docker does: images without a registry are
+    /// on Docker Hub, official images are in its `library` namespace, and the default tag is
+    /// `latest`. None when the reference is malformed.
+    pub fn parse(image: &str) -> Option<Self> {
+        let (name, digest) = match image.split_once('@') {
+            Some((name, digest)) => (name, Some(digest)),
+            None => (image, None),
+        };
+        // A colon after the last '/' separates the tag, one before it a registry port
+        let (name, tag) = match name.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => (repository, Some(tag)),
+            _ => (name, None),
+        };
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, repository)) if host.contains(['.', ':']) || host == "localhost" => (host, repository.to_string()),
+            Some(_) => ("docker.io", name.to_string()),
+            None => ("docker.io", format!("library/{}", name)),
+        };
+        let valid = |part: &str| !part.is_empty() && !part.contains(char::is_whitespace);
+        if !valid(registry) || !repository.split('/').all(valid) || repository != repository.to_lowercase() {
+            return None;
+        }
+        let reference = digest.or(tag).unwrap_or("latest");
+        valid(reference).then(|| ImageReference {
+            registry: registry.to_string(),
+            repository,
+            reference: reference.to_string(),
+        })
+    }
+
+    /// The fully qualified reference, such as `docker.io/library/nginx:latest` for `nginx`
+    pub fn normalized(&self) -> String {
+        self.to_string()
+    }
+
+    /// Whether the registry is on the local host, which Docker contacts over plain HTTP
+    pub fn is_local(&self) -> bool {
+        let host = match self.registry.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => host,
+            _ => self.registry.as_str(),
+        };
+        matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+    }
+
+    /// The host serving the registry API, which for Docker Hub isn't `docker.io` itself
+    pub fn api_host(&self) -> &str {
+        match self.registry.as_str() {
+            "docker.io" | "index.docker.io" => "registry-1.docker.io",
+            registry => registry,
+        }
+    }
+}
+
+/// The fully qualified reference, such as `docker.io/library/nginx:latest`, so that
+/// references to the same image compare equal however they're written
+impl Display for ImageReference {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Digests contain a colon of their own, e.g. sha256:abc
+        let separator = if self.reference.contains(':') { '@' } else { ':' };
+        write!(f, "{}/{}{}{}", self.registry, self.repository, separator, self.reference)
+    }
+}
+
+/// The form of an image reference it's compared in: fully qualified when `normalize` is on,
+/// so that `nginx`, `nginx:latest` and `docker.io/library/nginx:latest` are the same image,
+/// or as written when it's off, so that pinning a tag is a change. References which don't
+/// parse, such as those with interpolations, are always compared as written.
+pub fn image_key(image: &str, normalize: bool) -> String {
+    match ImageReference::parse(image) {
+        Some(reference) if normalize => reference.normalized(),
+        _ => image.to_string(),
+    }
+}
+
+/// Whether two image references name the same image, as `image_key` compares them
+pub fn same_image(a: &str, b: &str, normalize: bool) -> bool {
+    image_key(a, normalize) == image_key(b, normalize)
+}
+
+/// The HTTP status of a HEAD request for an image's manifest, which is 200 when the image
+/// exists and 404 when it doesn't. Remote registries answer 401 when they refuse anonymous
+/// access, as they do for private images and, on Docker Hub, for missing repositories.
+pub fn manifest_status(image: &ImageReference, timeout: Duration) -> io::Result<u16> {
+    if image.is_local() {
+        plain_manifest_status(image, timeout)
+    } else {
+        https_manifest_status(image, timeout)
+    }
+}
+
+fn plain_manifest_status(image: &ImageReference, timeout: Duration) -> io::Result<u16> {
+    let address = match image.registry.rsplit_once(':') {
+        Some((_, port)) if !port.contains(']') => image.registry.clone(),
+        _ => format!("{}:80", image.registry),
+    };
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", image.registry)))?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let request = format!(
+        "HEAD /v2/{}/manifests/{} HTTP/1.1\r\nHost: {}\r\nAccept: {}\r\nConnection: close\r\n\r\n",
+        image.repository, image.reference, image.registry, MANIFEST_TYPES
+    );
+    stream.write_all(request.as_bytes())?;
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected response '{}'", status_line.trim())))
+}
+
+/// Public registries answer an anonymous request with a 401 whose challenge names the
+/// service to ask for a pull token, so the request is repeated with that token
+#[cfg(feature = "https")]
+fn https_manifest_status(image: &ImageReference, timeout: Duration) -> io::Result<u16> {
+    token_manifest_status(&format!("https://{}", image.api_host()), image, timeout)
+}
+
+/// The manifest status from the registry API at `base`, asking for a pull token if the
+/// registry challenges the anonymous request
+#[cfg(feature = "https")]
+fn token_manifest_status(base: &str, image: &ImageReference, timeout: Duration) -> io::Result<u16> {
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+    let url = format!("{}/v2/{}/manifests/{}", base, image.repository, image.reference);
+    let head = |token: Option<&str>| {
+        let mut request = agent.head(&url).set("Accept", MANIFEST_TYPES);
+        if let Some(token) = token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        match request.call() {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => Ok(response),
+            Err(err) => Err(io::Error::other(err.to_string())),
+        }
+    };
+    let response = head(None)?;
+    if response.status() != 401 {
+        return Ok(response.status());
+    }
+    let challenge = response
+        .header("www-authenticate")
+        .and_then(bearer_challenge)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the registry asked for credentials other than a bearer token"))?;
+    let realm = challenge.get("realm").ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the registry's challenge has no realm"))?;
+    let scope = challenge.get("scope").cloned().unwrap_or_else(|| format!("repository:{}:pull", image.repository));
+    let mut request = agent.get(realm).query("scope", &scope);
+    if let Some(service) = challenge.get("service") {
+        request = request.query("service", service);
+    }
+    let token = match request.call() {
+        Ok(response) => serde_json::from_reader::<_, serde_json::Value>(response.into_reader())?,
+        // The token service refusing a token is the registry refusing access
+        Err(ureq::Error::Status(status, _)) => return Ok(status),
+        Err(err) => return Err(io::Error::other(err.to_string())),
+    };
+    let token = ["token", "access_token"]
+        .iter()
+        .find_map(|key| token[key].as_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the registry's token service returned no token"))?;
+    Ok(head(Some(token))?.status())
+}
+
+#[cfg(not(feature = "https"))]
+fn https_manifest_status(image: &ImageReference, _timeout: Duration) -> io::Result<u16> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("registry '{}' needs HTTPS, which this build doesn't support", image.registry),
+    ))
+}
+
+/// The parameters of a `Bearer` WWW-Authenticate challenge, such as
+/// `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`.
+/// None for other schemes.
+pub fn bearer_challenge(header: &str) -> Option<HashMap<String, String>> {
+    let (scheme, mut rest) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let mut parameters = HashMap::new();
+    loop {
+        rest = rest.trim_start_matches([' ', ',']);
+        if rest.is_empty() {
+            return Some(parameters);
+        }
+        let (key, after) = rest.split_once('=')?;
+        // Values are quoted strings, which may themselves hold commas, or bare tokens
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"')?,
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        parameters.insert(key.trim().to_ascii_lowercase(), value.to_string());
+        rest = after;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_image_reference() {
+        let parse = |image| ImageReference::parse(image).map(|i| (i.registry, i.repository, i.reference));
+        let expected = |registry: &str, repository: &str, reference: &str| Some((registry.to_string(), repository.to_string(), reference.to_string()));
+        assert_eq!(parse("nginx"), expected("docker.io", "library/nginx", "latest"));
+        assert_eq!(parse("bitnami/redis:7.2"), expected("docker.io", "bitnami/redis", "7.2"));
+        assert_eq!(parse("ghcr.io/org/app:v1"), expected("ghcr.io", "org/app", "v1"));
+        assert_eq!(parse("localhost:5000/app"), expected("localhost:5000", "app", "latest"));
+        assert_eq!(parse("app@sha256:abc"), expected("docker.io", "library/app", "sha256:abc"));
+        assert_eq!(parse("App:1.0"), None);
+        assert_eq!(parse(""), None);
+
+        assert!(ImageReference::parse("localhost:5000/app").unwrap().is_local());
+        assert!(ImageReference::parse("127.0.0.1/app").unwrap().is_local());
+        assert!(!ImageReference::parse("ghcr.io/org/app").unwrap().is_local());
+        assert_eq!(ImageReference::parse("nginx").unwrap().api_host(), "registry-1.docker.io");
+        assert_eq!(ImageReference::parse("ghcr.io/org/app").unwrap().api_host(), "ghcr.io");
+
+        let normalised = |image| ImageReference::parse(image).unwrap().to_string();
+        assert_eq!(normalised("nginx"), "docker.io/library/nginx:latest");
+        assert_eq!(normalised("docker.io/library/nginx:latest"), "docker.io/library/nginx:latest");
+        assert_eq!(normalised("app@sha256:abc"), "docker.io/library/app@sha256:abc");
+    }
+
+    #[test]
+    fn test_bearer_challenge() {
+        let challenge = bearer_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull,push""#,
+        )
+        .unwrap();
+        assert_eq!(challenge["realm"], "https://auth.docker.io/token");
+        assert_eq!(challenge["service"], "registry.docker.io");
+        assert_eq!(challenge["scope"], "repository:library/nginx:pull,push");
+
+        let challenge = bearer_challenge("bearer realm=https://ghcr.io/token, service=ghcr.io").unwrap();
+        assert_eq!(challenge["realm"], "https://ghcr.io/token");
+        assert_eq!(challenge["service"], "ghcr.io");
+
+        assert_eq!(bearer_challenge(r#"Basic realm="registry""#), None);
+        assert_eq!(bearer_challenge(r#"Bearer realm="unterminated"#), None);
+    }
+
+    #[cfg(feature = "https")]
+    #[test]
+    fn test_token_manifest_status() {
+        use std::net::TcpListener;
+
+        // A registry holding only app:1.0, which only answers requests bearing a pull token
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let realm = format!("{}/token", base);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(6) {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines().map(Result::unwrap);
+                let request = lines.next().unwrap();
+                let authorized = lines.by_ref().take_while(|line| !line.is_empty()).any(|line| line.eq_ignore_ascii_case("authorization: bearer secret"));
+                let (status, headers, body) = if request.starts_with("GET /token?scope=repository%3Alibrary%2Fapp%3Apull&service=test ") {
+                    ("200 OK", String::new(), r#"{"token":"secret"}"#)
+                } else if !authorized {
+                    ("401 Unauthorized", format!("WWW-Authenticate: Bearer realm=\"{}\",service=\"test\"\r\n", realm), "")
+                } else if request.starts_with("HEAD /v2/library/app/manifests/1.0 ") {
+                    ("200 OK", String::new(), "")
+                } else {
+                    ("404 Not Found", String::new(), "")
+                };
+                write!(stream, "HTTP/1.1 {}\r\n{}Content-Length: {}\r\n\r\n{}", status, headers, body.len(), body).unwrap();
+            }
+        });
+        let status = |image| token_manifest_status(&base, &ImageReference::parse(image).unwrap(), Duration::from_secs(5)).unwrap();
+        assert_eq!(status("app:1.0"), 200);
+        assert_eq!(status("app:2.0"), 404);
+    }
+
+    #[test]
+    fn test_same_image() {
+        let forms = ["nginx", "nginx:latest", "docker.io/library/nginx:latest"];
+        for a in forms {
+            for b in forms {
+                assert!(same_image(a, b, true), "{} and {} are the same image once normalised", a, b);
+                assert_eq!(same_image(a, b, false), a == b, "{} and {} compared as written", a, b);
+            }
+        }
+        assert_eq!(ImageReference::parse("nginx:latest").unwrap().normalized(), "docker.io/library/nginx:latest");
+        assert!(!same_image("nginx", "nginx:1.25", true));
+        assert!(!same_image("nginx", "ghcr.io/library/nginx", true));
+        // References which don't parse are compared as written either way
+        assert_eq!(image_key("${REGISTRY}/app", true), "${REGISTRY}/app");
+        assert!(!same_image("${IMAGE}", "nginx", true));
+    }
+}
@@ -0,0 +1,255 @@
+//! Discovery of the containerfiles and compose files in a directory tree, and generation
+//! of a model package for each of them
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+};
+use crate::{
+    cache::{with_provenance, CacheEntry, ScanCache},
+    gen_sysml::{sysml_cargotecture_package, sysml_compose_package, sysml_index_package, sysml_name, SysmlOptions},
+    sysml_ast::RenderStyle,
+    parse_compose::{parse_composefiles_unvalidated, ParseOptions, DEFAULT_COMPOSE_FILES},
+    provenance::InputHash,
+    parse_dockerfile::parse_containerfile,
+    util::{dedup_name, sanitize_file_name},
+};
+
+/// Directories never descended into when scanning
+const SKIPPED_DIRS: [&str; 2] = ["target", "node_modules"];
+
+/// The file name of the index package written with the split output
+pub const INDEX_FILE: &str = "index.sysml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    Containerfile,
+    Compose,
+}
+
+/// A containerfile or compose file found by a scan
+#[derive(Debug, PartialEq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub kind: SourceKind,
+}
+
+/// A generated model package and the file it was generated from
+#[derive(Debug)]
+pub struct GeneratedPackage {
+    /// The SysML name of the package
+    pub package: String,
+    pub source: PathBuf,
+    pub sysml: String,
+}
+
+/// The kind of source a file is, judged by its name
+pub fn source_kind(file_name: &str) -> Option<SourceKind> {
+    let lower = file_name.to_ascii_lowercase();
+    if DEFAULT_COMPOSE_FILES.contains(&file_name)
+        || ((lower.starts_with("compose.") || lower.starts_with("docker-compose."))
+            && (lower.ends_with(".yml") || lower.ends_with(".yaml")))
+    {
+        Some(SourceKind::Compose)
+    } else if lower == "dockerfile"
+        || lower == "containerfile"
+        || lower.starts_with("dockerfile.")
+        || lower.starts_with("containerfile.")
+        || lower.ends_with(".dockerfile")
+        || lower.ends_with(".containerfile")
+    {
+        Some(SourceKind::Containerfile)
+    } else {
+        None
+    }
+}
+
+/// Find the containerfiles and compose files under a directory, in path order. Hidden
+/// directories and build output directories are skipped.
+pub fn scan_directory(root: &Path) -> io::Result<Vec<SourceFile>> {
+    let mut sources = Vec::new();
+    let mut entries: Vec<fs::DirEntry> = fs::read_dir(root)?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_dir() {
+            if !file_name.starts_with('.') && !SKIPPED_DIRS.contains(&file_name.as_str()) {
+                sources.extend(scan_directory(&path)?);
+            }
+        } else if let Some(kind) = source_kind(&file_name) {
+            sources.push(SourceFile { path, kind });
+        }
+    }
+    Ok(sources)
+}
+
+/// The name of the model for a source file: the name of its directory, as compose names a
+/// project, or the file's own name for an override such as `compose.prod.yaml`
+fn model_name(root: &Path, source: &SourceFile) -> String {
+    let file_name = source.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let parent = source
+        .path
+        .parent()
+        .filter(|parent| *parent != root)
+        .map(Path::to_path_buf)
+        .or_else(|| root.canonicalize().ok());
+    let directory = parent
+        .as_deref()
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "root".to_string());
+    let simple = match source.kind {
+        SourceKind::Compose => DEFAULT_COMPOSE_FILES.contains(&file_name.as_str()),
+        SourceKind::Containerfile => ["dockerfile", "containerfile"].contains(&file_name.to_ascii_lowercase().as_str()),
+    };
+    if simple {
+        directory
+    } else {
+        format!("{}_{}", directory, file_name.replace('.', "_"))
+    }
+}
+
+/// Parse a source file and generate its package without provenance, recording what was
+/// reported along the way
+fn generate_entry(source: &SourceFile, name: &str, content: Vec<u8>, options: &SysmlOptions) -> CacheEntry {
+    let file: Box<dyn Read> = Box::new(Cursor::new(content));
+    let options = SysmlOptions { provenance: None, ..options.clone() };
+    let skipped = |err: String| vec![format!("warning: skipping {}: {}", source.path.display(), err)];
+    match source.kind {
+        SourceKind::Containerfile => match parse_containerfile(file, name) {
+            Ok(mut container) => {
+                container.name = name.to_string();
+                CacheEntry::new(Some(sysml_cargotecture_package(&container, &options)), Vec::new(), &[])
+            }
+            Err(err) => CacheEntry::new(None, skipped(err.to_string()), &[]),
+        },
+        SourceKind::Compose => {
+            let parse_options = ParseOptions {
+                base_dir: source.path.parent().map(Path::to_path_buf),
+                file_names: vec![source.path.to_string_lossy().into_owned()],
+                ..Default::default()
+            };
+            match parse_composefiles_unvalidated(vec![file], &parse_options) {
+                Ok(compose) => CacheEntry::new(
+                    Some(sysml_compose_package(&compose, name, &options)),
+                    compose.validate().diagnostics(),
+                    &compose.included_files,
+                ),
+                Err(err) => CacheEntry::new(None, skipped(err.to_string()), &[]),
+            }
+        }
+    }
+}
+
+/// Generate a package per source file, with distinct names. Files which fail to parse are
+/// reported and skipped.
+pub fn generate_packages(root: &Path, sources: &[SourceFile], options: &SysmlOptions) -> Vec<GeneratedPackage> {
+    generate_packages_cached(root, sources, options, None)
+}
+
+/// Generate packages as `generate_packages` does, reusing those cached for unchanged files
+/// and caching the others. What parsing reported is reported again for cached files.
+pub fn generate_packages_cached(
+    root: &Path,
+    sources: &[SourceFile],
+    options: &SysmlOptions,
+    mut cache: Option<&mut ScanCache>,
+) -> Vec<GeneratedPackage> {
+    let mut used = HashSet::new();
+    let mut packages = Vec::new();
+    for source in sources {
+        let name = dedup_name(&mut used, &model_name(root, source));
+        let content = match fs::read(&source.path) {
+            Ok(content) => content,
+            Err(err) => {
+                eprintln!("warning: skipping {}: {}", source.path.display(), err);
+                continue;
+            }
+        };
+        let entry = match cache.as_deref_mut() {
+            Some(cache) => {
+                let key = ScanCache::key(&source.path, &name, options, &content);
+                cache.get_or_insert_with(&key, || generate_entry(source, &name, content, options))
+            }
+            None => generate_entry(source, &name, content, options),
+        };
+        entry.diagnostics.iter().for_each(|diagnostic| eprintln!("{}", diagnostic));
+        let Some(sysml) = entry.sysml else {
+            continue;
+        };
+        // Each package's provenance names just the files it was generated from
+        let sysml = match &options.provenance {
+            Some(provenance) => {
+                let included = entry.included.iter().map(|input| PathBuf::from(&input.path));
+                let files = std::iter::once(source.path.clone()).chain(included);
+                let inputs = files.filter_map(|file| InputHash::of_file(&file).ok()).collect();
+                with_provenance(&sysml, &provenance.with_inputs(inputs), &options.style)
+            }
+            None => sysml,
+        };
+        packages.push(GeneratedPackage {
+            package: sysml_name(&format!("{}Model", name)),
+            source: source.path.clone(),
+            sysml,
+        });
+    }
+    packages
+}
+
+/// Write each package to a file of its own in `dir`, with an index package importing them
+/// all, laid out in `style`. Returns the paths written, the index last.
+pub fn write_split_output(dir: &Path, index_name: &str, packages: &[GeneratedPackage], style: &RenderStyle) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut used = HashSet::from([INDEX_FILE.trim_end_matches(".sysml").to_string()]);
+    let mut written = Vec::new();
+    let mut entries = Vec::new();
+    for package in packages {
+        let file_name = format!("{}.sysml", dedup_name(&mut used, &sanitize_file_name(&package.package)));
+        let path = dir.join(&file_name);
+        fs::write(&path, &package.sysml)?;
+        written.push(path);
+        entries.push((package.package.clone(), file_name));
+    }
+    let index = dir.join(INDEX_FILE);
+    fs::write(&index, sysml_index_package(index_name, &entries, style))?;
+    written.push(index);
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_kind() {
+        assert_eq!(source_kind("compose.yaml"), Some(SourceKind::Compose));
+        assert_eq!(source_kind("docker-compose.prod.yml"), Some(SourceKind::Compose));
+        assert_eq!(source_kind("Dockerfile"), Some(SourceKind::Containerfile));
+        assert_eq!(source_kind("api.Dockerfile"), Some(SourceKind::Containerfile));
+        assert_eq!(source_kind("Containerfile.dev"), Some(SourceKind::Containerfile));
+        assert_eq!(source_kind("compose.json"), None);
+        assert_eq!(source_kind("README.md"), None);
+    }
+
+    #[test]
+    fn test_scan_directory_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for sub in ["api", "web", ".git", "target"] {
+            fs::create_dir(root.join(sub)).unwrap();
+            fs::write(root.join(sub).join("Dockerfile"), "FROM alpine\n").unwrap();
+        }
+        fs::write(root.join("api").join("compose.yaml"), "services:\n  api:\n    image: api\n").unwrap();
+
+        let sources = scan_directory(root).unwrap();
+        let paths: Vec<PathBuf> = sources.iter().map(|s| s.path.strip_prefix(root).unwrap().to_path_buf()).collect();
+        assert_eq!(paths, vec![PathBuf::from("api/Dockerfile"), PathBuf::from("api/compose.yaml"), PathBuf::from("web/Dockerfile")]);
+
+        let packages = generate_packages(root, &sources, &SysmlOptions::default());
+        let names: Vec<&str> = packages.iter().map(|p| p.package.as_str()).collect();
+        assert_eq!(names, vec!["apiModel", "api_2Model", "webModel"]);
+        assert!(packages[1].sysml.starts_with("package api_2Model {"));
+    }
+}
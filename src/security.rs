@@ -0,0 +1,509 @@
+//! A report of the security-relevant configuration of a compose file, for security teams
+//! reviewing many services at once
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+};
+use serde::Serialize;
+use crate::{
+    output::Table,
+    parse_compose::{Compose, Service},
+    parse_dockerfile::ParsedContainer,
+    util::{image_tag, parse_volume_spec, redact_url_credentials, VolumeSource},
+};
+
+/// How much a finding weakens the isolation of a container or exposes its data, most
+/// severe first so that findings sort by it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Severity {
+    Critical,
+    High,
+    Medium,
+    Low,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Severity::Critical => write!(f, "critical"),
+            Severity::High => write!(f, "high"),
+            Severity::Medium => write!(f, "medium"),
+            Severity::Low => write!(f, "low"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SecurityFinding {
+    pub severity: Severity,
+    pub service: Option<String>,
+    pub description: String,
+    pub recommendation: String,
+}
+
+impl Display for SecurityFinding {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "[{}] {}", self.severity, self.description)?;
+        write!(f, "    Recommendation: {}", self.recommendation)
+    }
+}
+
+/// The security findings of a compose file, most severe first
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct SecurityReport {
+    pub findings: Vec<SecurityFinding>,
+}
+
+impl Display for SecurityReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+/// Capabilities that break the isolation of a container, with the severity of adding them
+const DANGEROUS_CAPABILITIES: &[(&str, Severity)] = &[
+    ("ALL", Severity::Critical),
+    ("SYS_ADMIN", Severity::Critical),
+    ("NET_ADMIN", Severity::High),
+];
+
+/// Host paths which give a container control of the host when bind mounted, with the
+/// severity of mounting them or anything under them. The host's root only counts as itself.
+const SENSITIVE_PATHS: &[(&str, Severity)] = &[
+    ("/", Severity::Critical),
+    ("/var/run/docker.sock", Severity::Critical),
+    ("/run/docker.sock", Severity::Critical),
+    ("/etc", Severity::High),
+    ("/proc", Severity::High),
+    ("/sys", Severity::High),
+    ("/dev", Severity::High),
+    ("/boot", Severity::High),
+    ("/root", Severity::High),
+    ("/var/lib/docker", Severity::High),
+];
+
+/// Ports of services that shouldn't usually be reachable from outside the host
+const SENSITIVE_PORTS: &[(u16, &str)] = &[(22, "SSH"), (3306, "MySQL"), (5432, "PostgreSQL"), (27017, "MongoDB")];
+
+/// Words in environment variable names that suggest their values are secrets
+const SECRET_NAMES: &[&str] = &["PASSWORD", "PASSWD", "SECRET", "TOKEN", "API_KEY", "APIKEY", "PRIVATE_KEY", "CREDENTIALS"];
+
+/// Whether the name of an environment variable suggests its value is a secret, rather than
+/// the path of a file holding one
+pub(crate) fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_NAMES.iter().any(|word| name.contains(word)) && !name.ends_with("_FILE")
+}
+
+/// Whether an environment variable holds a secret written into the compose file, rather
+/// than one interpolated from the environment or read from a file
+fn is_literal_secret(name: &str, value: &str) -> bool {
+    let secret_name = is_secret_name(name);
+    let literal = !value.is_empty() && !value.starts_with('$');
+    (secret_name && literal) || redact_url_credentials(value) != value
+}
+
+fn is_root(user: &str) -> bool {
+    matches!(user.split(':').next(), Some("root" | "0"))
+}
+
+/// The severity of bind mounting a host path, None when it isn't sensitive
+fn sensitive_path_severity(path: &str) -> Option<Severity> {
+    SENSITIVE_PATHS
+        .iter()
+        .find(|(sensitive, _)| path == *sensitive || (*sensitive != "/" && path.strip_prefix(sensitive).is_some_and(|rest| rest.starts_with('/'))))
+        .map(|(_, severity)| *severity)
+}
+
+/// The host paths a service bind mounts which give it control of the host, with their severity
+fn sensitive_mounts(service: &Service) -> Vec<(String, Severity)> {
+    service
+        .volumes
+        .iter()
+        .flatten()
+        .filter_map(|volume| match parse_volume_spec(volume).ok()?.source {
+            VolumeSource::Bind(path) => sensitive_path_severity(&path).map(|severity| (path, severity)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The host namespaces a service shares
+fn host_namespaces(service: &Service) -> Vec<&'static str> {
+    [("network", &service.network_mode), ("pid", &service.pid), ("ipc", &service.ipc)]
+        .into_iter()
+        .filter(|(_, mode)| mode.as_deref() == Some("host"))
+        .map(|(namespace, _)| namespace)
+        .collect()
+}
+
+fn service_findings(name: &str, service: &Service, container: Option<&ParsedContainer>) -> Vec<SecurityFinding> {
+    let mut findings = Vec::new();
+    let mut finding = |severity, description: String, recommendation: &str| {
+        findings.push(SecurityFinding {
+            severity,
+            service: Some(name.to_string()),
+            description,
+            recommendation: recommendation.to_string(),
+        });
+    };
+
+    if service.privileged == Some(true) {
+        finding(
+            Severity::Critical,
+            format!("Service '{}' runs privileged, with full access to the host's devices", name),
+            "Remove privileged and add only the capabilities and devices the service needs",
+        );
+    }
+    if service.network_mode.as_deref() == Some("host") {
+        finding(
+            Severity::High,
+            format!("Service '{}' uses the host's network stack", name),
+            "Attach the service to networks and publish only the ports it serves",
+        );
+    }
+    for (namespace, mode) in [("PID", &service.pid), ("IPC", &service.ipc)] {
+        if mode.as_deref() == Some("host") {
+            finding(
+                Severity::High,
+                format!("Service '{}' shares the host's {} namespace", name, namespace),
+                "Use the container's own namespace, or share another service's",
+            );
+        }
+    }
+    for capability in service.cap_add.iter().flatten() {
+        let normalised = capability.to_ascii_uppercase();
+        let normalised = normalised.strip_prefix("CAP_").unwrap_or(&normalised);
+        if let Some((_, severity)) = DANGEROUS_CAPABILITIES.iter().find(|(dangerous, _)| *dangerous == normalised) {
+            finding(
+                *severity,
+                format!("Service '{}' adds the {} capability", name, capability),
+                "Add only the narrower capabilities the service needs",
+            );
+        }
+    }
+    for (path, severity) in sensitive_mounts(service) {
+        finding(
+            severity,
+            format!("Service '{}' mounts the host path {}", name, path),
+            "Mount only the files the service needs, or give it access through an API",
+        );
+    }
+    match (service.user.as_deref(), container) {
+        (Some(user), _) if is_root(user) => finding(
+            Severity::Medium,
+            format!("Service '{}' runs as root (user '{}')", name, user),
+            "Run the service as an unprivileged user",
+        ),
+        (None, Some(container)) => match container.user.as_deref() {
+            Some(user) if is_root(user) => finding(
+                Severity::Medium,
+                format!("Service '{}' runs as root (user '{}' from its Dockerfile)", name, user),
+                "Run the service as an unprivileged user",
+            ),
+            Some(_) => (),
+            None => finding(
+                Severity::Low,
+                format!("Dockerfile of service '{}' sets no USER, so it runs as root unless its base image sets one", name),
+                "Add a USER instruction for an unprivileged user",
+            ),
+        },
+        _ => (),
+    }
+    if let Some(image) = service.image.as_deref().filter(|image| image_tag(image) == "latest") {
+        finding(
+            Severity::Low,
+            format!("Image '{}' of service '{}' isn't pinned to a version", image, name),
+            "Use a version tag or digest, so deployments get the image that was reviewed",
+        );
+    }
+    let mut environment: Vec<(&String, &String)> = service.environment.iter().flatten().collect();
+    environment.sort();
+    for (variable, value) in environment {
+        if is_literal_secret(variable, value) {
+            finding(
+                Severity::High,
+                format!("Environment variable {} of service '{}' holds a secret", variable, name),
+                "Use compose secrets, or interpolate the value from the environment",
+            );
+        }
+    }
+    for port in service.port_infos().iter().filter(|port| port.published) {
+        if let Some((_, protocol)) = SENSITIVE_PORTS.iter().find(|(sensitive, _)| *sensitive == port.container_port) {
+            finding(
+                Severity::Medium,
+                format!("Service '{}' publishes the {} port {} on the host", name, protocol, port.container_port),
+                "Reach the service over a network rather than publishing its port",
+            );
+        }
+    }
+    findings
+}
+
+/// The security-relevant configuration of a service, with its findings
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServicePosture {
+    pub service: String,
+    /// The user the service runs as, set in the compose file or else by the Dockerfile it's
+    /// built from. None when neither sets one, leaving it to the image.
+    pub user: Option<String>,
+    pub privileged: bool,
+    pub cap_add: Vec<String>,
+    /// The host namespaces shared: `network`, `pid` or `ipc`
+    pub host_namespaces: Vec<String>,
+    /// Sensitive host paths bind mounted
+    pub sensitive_mounts: Vec<String>,
+    /// Whether the root filesystem is mounted read-only
+    pub read_only: bool,
+    /// The severity of the most severe finding, None when there are none
+    pub severity: Option<Severity>,
+    pub findings: Vec<SecurityFinding>,
+}
+
+/// The security posture of every service of a compose file, riskiest first
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct PostureReport {
+    pub services: Vec<ServicePosture>,
+}
+
+impl PostureReport {
+    /// A table with a row per service
+    pub fn to_table(&self) -> Table {
+        let list = |values: &[String]| if values.is_empty() { "-".to_string() } else { values.join(",") };
+        let yes_no = |value: bool| if value { "yes" } else { "no" }.to_string();
+        let mut table = Table::new(&["SEVERITY", "SERVICE", "USER", "PRIVILEGED", "CAP_ADD", "HOST_NAMESPACES", "SENSITIVE_MOUNTS", "READ_ONLY", "FINDINGS"]);
+        for posture in &self.services {
+            table.push(vec![
+                posture.severity.map_or_else(|| "none".to_string(), |severity| severity.to_string()),
+                posture.service.clone(),
+                posture.user.clone().unwrap_or_else(|| "-".to_string()),
+                yes_no(posture.privileged),
+                list(&posture.cap_add),
+                list(&posture.host_namespaces),
+                list(&posture.sensitive_mounts),
+                yes_no(posture.read_only),
+                posture.findings.len().to_string(),
+            ]);
+        }
+        table
+    }
+}
+
+impl Display for PostureReport {
+    /// The table of `to_table`, however wide
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_table().render())
+    }
+}
+
+impl Compose {
+    /// Aggregate the security findings for every service, most severe first and then by
+    /// service name
+    pub fn security_report(&self) -> SecurityReport {
+        self.security_report_with_containers(&HashMap::new())
+    }
+
+    /// Aggregate the security findings for every service, taking the user of services built
+    /// from Dockerfiles from the containers built for them, by service name
+    pub fn security_report_with_containers(&self, containers: &HashMap<String, ParsedContainer>) -> SecurityReport {
+        let mut findings: Vec<SecurityFinding> = self
+            .services()
+            .flat_map(|(name, service)| service_findings(name, service, containers.get(name)))
+            .collect();
+        // A stable sort keeps each service's findings in the order they're checked
+        findings.sort_by_key(|finding| finding.severity);
+        SecurityReport { findings }
+    }
+
+    /// The security posture of every service, taking the user of services built from
+    /// Dockerfiles from the containers built for them. Services are ranked by their most
+    /// severe finding, then by how many findings they have, then by name.
+    pub fn security_posture(&self, containers: &HashMap<String, ParsedContainer>) -> PostureReport {
+        let mut services: Vec<ServicePosture> = self
+            .services
+            .iter()
+            .map(|(name, service)| {
+                let container = containers.get(name);
+                let findings = service_findings(name, service, container);
+                ServicePosture {
+                    service: name.clone(),
+                    user: service.user.clone().or_else(|| container.and_then(|container| container.user.clone())),
+                    privileged: service.privileged == Some(true),
+                    cap_add: service.cap_add.clone().unwrap_or_default(),
+                    host_namespaces: host_namespaces(service).into_iter().map(String::from).collect(),
+                    sensitive_mounts: sensitive_mounts(service).into_iter().map(|(path, _)| path).collect(),
+                    read_only: service.read_only == Some(true),
+                    severity: findings.iter().map(|finding| finding.severity).min(),
+                    findings,
+                }
+            })
+            .collect();
+        services.sort_by(|a, b| {
+            (a.severity.is_none(), a.severity, Reverse(a.findings.len()), &a.service)
+                .cmp(&(b.severity.is_none(), b.severity, Reverse(b.findings.len()), &b.service))
+        });
+        PostureReport { services }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_compose::parse_composefile;
+
+    /// The findings for a single service `web` with the given fields
+    fn findings(fields: &str) -> Vec<(Severity, String)> {
+        let yaml = format!("services:\n  web:\n    image: nginx:1.25\n{}", fields);
+        let compose = parse_composefile(Box::new(std::io::Cursor::new(yaml))).unwrap().value;
+        compose.security_report().findings.into_iter().map(|f| (f.severity, f.description)).collect()
+    }
+
+    fn finding(severity: Severity, description: &str) -> Vec<(Severity, String)> {
+        vec![(severity, description.to_string())]
+    }
+
+    #[test]
+    fn test_no_findings() {
+        assert!(findings("    user: \"1000:1000\"\n    ports: [\"127.0.0.1:8080:80\"]\n").is_empty());
+    }
+
+    #[test]
+    fn test_privileged() {
+        assert_eq!(findings("    privileged: true\n"), finding(Severity::Critical, "Service 'web' runs privileged, with full access to the host's devices"));
+        assert!(findings("    privileged: false\n").is_empty());
+    }
+
+    #[test]
+    fn test_host_namespaces() {
+        assert_eq!(findings("    network_mode: host\n"), finding(Severity::High, "Service 'web' uses the host's network stack"));
+        assert_eq!(findings("    pid: host\n"), finding(Severity::High, "Service 'web' shares the host's PID namespace"));
+        assert_eq!(findings("    ipc: host\n"), finding(Severity::High, "Service 'web' shares the host's IPC namespace"));
+        assert!(findings("    network_mode: \"service:db\"\n    ipc: shareable\n").is_empty());
+    }
+
+    #[test]
+    fn test_dangerous_capabilities() {
+        assert_eq!(
+            findings("    cap_add: [CHOWN, NET_ADMIN, CAP_SYS_ADMIN]\n"),
+            vec![
+                (Severity::Critical, "Service 'web' adds the CAP_SYS_ADMIN capability".to_string()),
+                (Severity::High, "Service 'web' adds the NET_ADMIN capability".to_string()),
+            ]
+        );
+        assert_eq!(findings("    cap_add: [all]\n"), finding(Severity::Critical, "Service 'web' adds the all capability"));
+    }
+
+    #[test]
+    fn test_root_user() {
+        assert_eq!(findings("    user: root\n"), finding(Severity::Medium, "Service 'web' runs as root (user 'root')"));
+        assert_eq!(findings("    user: \"0:0\"\n"), finding(Severity::Medium, "Service 'web' runs as root (user '0:0')"));
+        assert!(findings("    user: app\n").is_empty());
+    }
+
+    #[test]
+    fn test_untagged_images() {
+        let compose = parse_composefile(Box::new("services:\n  a:\n    image: nginx\n  b:\n    image: localhost:5000/app:latest\n  c:\n    image: app@sha256:abc\n".as_bytes())).unwrap().value;
+        let descriptions: Vec<String> = compose.security_report().findings.into_iter().map(|f| f.description).collect();
+        assert_eq!(
+            descriptions,
+            vec!["Image 'nginx' of service 'a' isn't pinned to a version", "Image 'localhost:5000/app:latest' of service 'b' isn't pinned to a version"]
+        );
+    }
+
+    #[test]
+    fn test_secrets_in_environment() {
+        let environment = "    environment:\n      DB_PASSWORD: hunter2\n      API_TOKEN: ${API_TOKEN}\n      PASSWORD_FILE: /run/secrets/db\n      DATABASE_URL: postgres://app:secret@db/app\n      LOG_LEVEL: debug\n";
+        assert_eq!(
+            findings(environment),
+            vec![
+                (Severity::High, "Environment variable DATABASE_URL of service 'web' holds a secret".to_string()),
+                (Severity::High, "Environment variable DB_PASSWORD of service 'web' holds a secret".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sensitive_ports() {
+        assert_eq!(findings("    ports: [\"5432:5432\"]\n"), finding(Severity::Medium, "Service 'web' publishes the PostgreSQL port 5432 on the host"));
+        assert_eq!(findings("    ports: [\"2222:22\"]\n"), finding(Severity::Medium, "Service 'web' publishes the SSH port 22 on the host"));
+        assert!(findings("    expose: [\"3306\"]\n").is_empty());
+    }
+
+    #[test]
+    fn test_sensitive_mounts() {
+        assert_eq!(
+            findings("    volumes:\n      - /var/run/docker.sock:/var/run/docker.sock\n      - /etc/nginx:/etc/nginx:ro\n      - ./html:/usr/share/nginx/html\n      - /etcetera:/data\n"),
+            vec![
+                (Severity::Critical, "Service 'web' mounts the host path /var/run/docker.sock".to_string()),
+                (Severity::High, "Service 'web' mounts the host path /etc/nginx".to_string()),
+            ]
+        );
+        assert_eq!(findings("    volumes: [\"/:/host\"]\n"), finding(Severity::Critical, "Service 'web' mounts the host path /"));
+        assert!(findings("    volumes: [\"/srv/data:/data\", \"etc:/etc\"]\n").is_empty());
+    }
+
+    #[test]
+    fn test_dockerfile_user() {
+        let compose = parse_composefile(Box::new("services:\n  a:\n    build: a\n  b:\n    build: b\n  c:\n    build: c\n    user: app\n".as_bytes())).unwrap().value;
+        let container = |user: &str| crate::parse_dockerfile::parse_containerfile(Box::new(std::io::Cursor::new(format!("FROM alpine\n{}", user))), "x").unwrap();
+        let containers = HashMap::from([
+            ("a".to_string(), container("USER root\n")),
+            ("b".to_string(), container("")),
+            ("c".to_string(), container("USER 0\n")),
+        ]);
+        let descriptions: Vec<String> = compose.security_report_with_containers(&containers).findings.into_iter().map(|f| f.description).collect();
+        assert_eq!(
+            descriptions,
+            vec![
+                "Service 'a' runs as root (user 'root' from its Dockerfile)",
+                "Dockerfile of service 'b' sets no USER, so it runs as root unless its base image sets one",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_security_posture() {
+        let yaml = "services:\n  locked:\n    image: app:1.0\n    user: \"1000\"\n    read_only: true\n  admin:\n    image: tool:1.0\n    privileged: true\n    network_mode: host\n    cap_add: [NET_ADMIN]\n  worker:\n    build: worker\n";
+        let compose = parse_composefile(Box::new(yaml.as_bytes())).unwrap().value;
+        let worker = crate::parse_dockerfile::parse_containerfile(Box::new("FROM alpine\nUSER root\n".as_bytes()), "worker").unwrap();
+        let report = compose.security_posture(&HashMap::from([("worker".to_string(), worker)]));
+
+        let ranked: Vec<(&str, Option<Severity>)> = report.services.iter().map(|p| (p.service.as_str(), p.severity)).collect();
+        assert_eq!(ranked, vec![("admin", Some(Severity::Critical)), ("worker", Some(Severity::Medium)), ("locked", None)]);
+        assert_eq!(report.services[0].host_namespaces, vec!["network"]);
+        assert_eq!(report.services[1].user.as_deref(), Some("root"));
+        assert!(report.services[2].read_only);
+
+        let table = report.to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "SEVERITY  SERVICE  USER  PRIVILEGED  CAP_ADD    HOST_NAMESPACES  SENSITIVE_MOUNTS  READ_ONLY  FINDINGS");
+        assert_eq!(lines[1], "critical  admin    -     yes         NET_ADMIN  network          -                 no         3");
+        assert_eq!(lines[3], "none      locked   1000  no          -          -                -                 yes        0");
+
+        // Narrowed to fit a terminal, the widest columns give way first
+        let narrow = report.to_table().max_width(Some(80)).render();
+        assert!(narrow.lines().all(|line| crate::output::display_width(line) <= 80));
+        assert_eq!(narrow.lines().next(), Some("SEVERI…  SERVICE  USER  PRIVIL…  CAP_ADD  HOST_NA…  SENSITI…  READ_ON…  FINDINGS"));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["services"][0]["severity"], "Critical");
+        assert_eq!(json["services"][1]["findings"][0]["description"], "Service 'worker' runs as root (user 'root' from its Dockerfile)");
+    }
+
+    #[test]
+    fn test_report_order_and_display() {
+        let report = parse_composefile(Box::new("services:\n  a:\n    image: nginx\n  b:\n    image: redis:7\n    privileged: true\n".as_bytes()))
+            .unwrap()
+            .value
+            .security_report();
+        assert_eq!(report.findings[0].service.as_deref(), Some("b"));
+        assert_eq!(report.findings[1].severity, Severity::Low);
+        assert!(report.to_string().starts_with(
+            "[critical] Service 'b' runs privileged, with full access to the host's devices\n    Recommendation: Remove privileged"
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+//! Kubernetes-style label selectors, such as `tier=backend,env!=prod` or
+//! `env in (staging,prod)`, for picking the components of a model by their labels
+use std::{collections::HashMap, fmt, str::FromStr};
+use crate::error::CargotectureError;
+
+/// The test a requirement of a selector makes of the value of its label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelOperator {
+    /// `key=value` or `key==value`
+    Equals(String),
+    /// `key!=value`, which labels without the key match too
+    NotEquals(String),
+    /// `key in (a,b)`
+    In(Vec<String>),
+    /// `key notin (a,b)`, which labels without the key match too
+    NotIn(Vec<String>),
+    /// `key`
+    Exists,
+    /// `!key`
+    DoesNotExist,
+}
+
+/// A requirement of a selector on a single label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelRequirement {
+    pub key: String,
+    pub operator: LabelOperator,
+}
+
+impl LabelRequirement {
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        let value = labels.get(&self.key);
+        match &self.operator {
+            LabelOperator::Equals(expected) => value == Some(expected),
+            LabelOperator::NotEquals(expected) => value != Some(expected),
+            LabelOperator::In(values) => value.is_some_and(|value| values.contains(value)),
+            LabelOperator::NotIn(values) => value.is_none_or(|value| !values.contains(value)),
+            LabelOperator::Exists => value.is_some(),
+            LabelOperator::DoesNotExist => value.is_none(),
+        }
+    }
+}
+
+impl fmt::Display for LabelRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.operator {
+            LabelOperator::Equals(value) => write!(f, "{}={}", self.key, value),
+            LabelOperator::NotEquals(value) => write!(f, "{}!={}", self.key, value),
+            LabelOperator::In(values) => write!(f, "{} in ({})", self.key, values.join(",")),
+            LabelOperator::NotIn(values) => write!(f, "{} notin ({})", self.key, values.join(",")),
+            LabelOperator::Exists => write!(f, "{}", self.key),
+            LabelOperator::DoesNotExist => write!(f, "!{}", self.key),
+        }
+    }
+}
+
+/// A label selector: requirements separated by commas, all of which a component's labels
+/// must meet for it to be selected. An empty selector selects everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSelector {
+    pub requirements: Vec<LabelRequirement>,
+}
+
+impl LabelSelector {
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        self.requirements.iter().all(|requirement| requirement.matches(labels))
+    }
+}
+
+impl fmt::Display for LabelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let requirements: Vec<String> = self.requirements.iter().map(ToString::to_string).collect();
+        write!(f, "{}", requirements.join(","))
+    }
+}
+
+/// Whether a label key is valid: a name of at most 63 letters, digits, `-`, `_` and `.`,
+/// starting and ending with a letter or digit, optionally after a DNS subdomain prefix and
+/// a `/`
+fn is_label_key(key: &str) -> bool {
+    let (prefix, name) = key.rsplit_once('/').map_or((None, key), |(prefix, name)| (Some(prefix), name));
+    !name.is_empty() && is_label_value(name) && prefix.is_none_or(|prefix| !prefix.is_empty() && prefix.len() <= 253 && is_label_value_chars(prefix, &['-', '.']))
+}
+
+/// Whether a label value is valid: empty, or at most 63 letters, digits, `-`, `_` and `.`,
+/// starting and ending with a letter or digit
+fn is_label_value(value: &str) -> bool {
+    value.len() <= 63 && is_label_value_chars(value, &['-', '_', '.'])
+}
+
+fn is_label_value_chars(value: &str, punctuation: &[char]) -> bool {
+    let alphanumeric = |c: Option<char>| c.is_none_or(|c| c.is_ascii_alphanumeric());
+    value.chars().all(|c| c.is_ascii_alphanumeric() || punctuation.contains(&c)) && alphanumeric(value.chars().next()) && alphanumeric(value.chars().last())
+}
+
+/// Split a selector into its requirements at the commas outside parentheses
+fn split_requirements(selector: &str) -> Result<Vec<&str>, String> {
+    let mut requirements = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (index, c) in selector.char_indices() {
+        match c {
+            '(' if depth == 0 => depth = 1,
+            '(' => return Err("sets can't be nested".to_string()),
+            ')' if depth == 0 => return Err("')' closes no '('".to_string()),
+            ')' => depth = 0,
+            ',' if depth == 0 => {
+                requirements.push(&selector[start..index]);
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    if depth > 0 {
+        return Err("'(' is never closed".to_string());
+    }
+    requirements.push(&selector[start..]);
+    Ok(requirements)
+}
+
+/// Parse the values of a set-based requirement, such as `(staging, prod)`
+fn parse_values(set: &str) -> Result<Vec<String>, String> {
+    let inner = set
+        .trim()
+        .strip_prefix('(')
+        .and_then(|set| set.strip_suffix(')'))
+        .ok_or_else(|| format!("expected a set of values in parentheses, found '{}'", set.trim()))?;
+    let values: Vec<String> = inner.split(',').map(|value| value.trim().to_string()).collect();
+    match values.iter().find(|value| value.is_empty() || !is_label_value(value)) {
+        Some(value) => Err(format!("'{}' isn't a valid label value", value)),
+        None => Ok(values),
+    }
+}
+
+fn parse_requirement(requirement: &str) -> Result<LabelRequirement, String> {
+    let requirement = requirement.trim();
+    let (negated, requirement) = match requirement.strip_prefix('!') {
+        Some(key) => (true, key.trim_start()),
+        None => (false, requirement),
+    };
+    let key_end = requirement.find(|c: char| !(c.is_ascii_alphanumeric() || "-_./".contains(c))).unwrap_or(requirement.len());
+    let (key, rest) = (&requirement[..key_end], requirement[key_end..].trim_start());
+    if key.is_empty() {
+        return Err(format!("expected a label key, found '{}'", requirement));
+    }
+    if !is_label_key(key) {
+        return Err(format!("'{}' isn't a valid label key", key));
+    }
+    let value = |value: &str| {
+        let value = value.trim();
+        is_label_value(value).then(|| value.to_string()).ok_or_else(|| format!("'{}' isn't a valid label value", value))
+    };
+    let word = |word: &str| rest.strip_prefix(word).filter(|set| set.starts_with(|c: char| c == '(' || c.is_whitespace()));
+    let operator = match rest {
+        "" if negated => LabelOperator::DoesNotExist,
+        "" => LabelOperator::Exists,
+        _ if negated => return Err(format!("'!{}' can only test whether a label exists", key)),
+        _ => match (rest.strip_prefix("!="), rest.strip_prefix("=="), rest.strip_prefix('='), word("in"), word("notin")) {
+            (Some(expected), ..) | (_, Some(expected), ..) | (_, _, Some(expected), ..) => {
+                let expected = value(expected)?;
+                if rest.starts_with("!=") {
+                    LabelOperator::NotEquals(expected)
+                } else {
+                    LabelOperator::Equals(expected)
+                }
+            }
+            (_, _, _, Some(set), _) => LabelOperator::In(parse_values(set)?),
+            (.., Some(set)) => LabelOperator::NotIn(parse_values(set)?),
+            _ => return Err(format!("expected =, ==, !=, in or notin after '{}', found '{}'", key, rest)),
+        },
+    };
+    Ok(LabelRequirement { key: key.to_string(), operator })
+}
+
+impl FromStr for LabelSelector {
+    type Err = CargotectureError;
+
+    /// Parse a selector as `kubectl --selector` takes it: requirements separated by commas,
+    /// each `key=value`, `key==value`, `key!=value`, `key in (a,b)`, `key notin (a,b)`,
+    /// `key` or `!key`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Ok(LabelSelector::default());
+        }
+        let invalid = |reason: String| CargotectureError::invalid_selector(s, &reason);
+        let requirements = split_requirements(s).map_err(invalid)?;
+        let requirements = requirements.into_iter().map(parse_requirement).collect::<Result<_, _>>().map_err(invalid)?;
+        Ok(LabelSelector { requirements })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse_selector() {
+        let selector: LabelSelector = "tier=backend, env!=prod,app.kubernetes.io/part-of == shop,env in (staging, prod),zone notin (eu),canary,!legacy".parse().unwrap();
+        let operators: Vec<(&str, &LabelOperator)> = selector.requirements.iter().map(|r| (r.key.as_str(), &r.operator)).collect();
+        assert_eq!(
+            operators,
+            vec![
+                ("tier", &LabelOperator::Equals("backend".to_string())),
+                ("env", &LabelOperator::NotEquals("prod".to_string())),
+                ("app.kubernetes.io/part-of", &LabelOperator::Equals("shop".to_string())),
+                ("env", &LabelOperator::In(vec!["staging".to_string(), "prod".to_string()])),
+                ("zone", &LabelOperator::NotIn(vec!["eu".to_string()])),
+                ("canary", &LabelOperator::Exists),
+                ("legacy", &LabelOperator::DoesNotExist),
+            ]
+        );
+        assert_eq!(selector.to_string(), "tier=backend,env!=prod,app.kubernetes.io/part-of=shop,env in (staging,prod),zone notin (eu),canary,!legacy");
+        assert_eq!("".parse::<LabelSelector>().unwrap(), LabelSelector::default());
+        // A value may be empty, as a label's may
+        assert_eq!("tier=".parse::<LabelSelector>().unwrap().requirements[0].operator, LabelOperator::Equals(String::new()));
+    }
+
+    #[test]
+    fn test_invalid_selector() {
+        for (selector, reason) in [
+            ("tier=back end", "'back end' isn't a valid label value"),
+            ("=backend", "expected a label key, found '=backend'"),
+            ("-tier=backend", "'-tier' isn't a valid label key"),
+            ("tier>1", "expected =, ==, !=, in or notin after 'tier', found '>1'"),
+            ("env in staging", "expected a set of values in parentheses, found 'staging'"),
+            ("env in (staging", "'(' is never closed"),
+            ("env in (staging,)", "'' isn't a valid label value"),
+            ("!tier=backend", "'!tier' can only test whether a label exists"),
+            ("tier=backend,", "expected a label key, found ''"),
+        ] {
+            assert_eq!(
+                selector.parse::<LabelSelector>().unwrap_err(),
+                CargotectureError::invalid_selector(selector, reason),
+                "{}",
+                selector
+            );
+        }
+        assert_eq!(
+            "tier>1".parse::<LabelSelector>().unwrap_err().to_string(),
+            "Invalid label selector 'tier>1': expected =, ==, !=, in or notin after 'tier', found '>1'"
+        );
+    }
+
+    #[test]
+    fn test_selector_matches() {
+        let backend = labels(&[("tier", "backend"), ("env", "staging")]);
+        let frontend = labels(&[("tier", "frontend"), ("env", "prod"), ("canary", "")]);
+        let unlabelled = labels(&[]);
+        for (selector, expected) in [
+            ("tier=backend", [true, false, false]),
+            ("tier==frontend", [false, true, false]),
+            ("env!=prod", [true, false, true]),
+            ("env in (staging,prod)", [true, true, false]),
+            ("env notin (prod)", [true, false, true]),
+            ("canary", [false, true, false]),
+            ("!canary", [true, false, true]),
+            ("tier=backend,env=prod", [false, false, false]),
+            ("", [true, true, true]),
+        ] {
+            let selector: LabelSelector = selector.parse().unwrap();
+            let matches = [&backend, &frontend, &unlabelled].map(|labels| selector.matches(labels));
+            assert_eq!(matches, expected, "{}", selector);
+        }
+    }
+}
@@ -0,0 +1,485 @@
+//! A small abstract syntax tree for the SysML v2 textual notation we generate, so that
+//! generators can reorder and regroup elements before rendering them as text.
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+use crate::output::display_width;
+
+/// An element of a generated SysML model
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    /// `package <name> { ... }`
+    Package { name: String, members: Vec<Element> },
+    /// A usage such as `part web: Container { ... }` or `attribute image redefines image = "x";`
+    Usage(Usage),
+    /// A single line such as an annotation or assertion, rendered at the current indentation
+    Line(String),
+    /// Preformatted text, each line rendered at the current indentation
+    Block(String),
+}
+
+/// The relationship a connection models, carried by every connection as its `kind` so that
+/// queries of a model can filter connections by relationship
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionKind {
+    /// A service depending on another, from `depends_on`
+    DependsOn,
+    /// A service attached to a network
+    NetworkMembership,
+    /// Traffic between the network ports of two containers
+    NetworkFlow,
+    /// A volume shared through the volume ports of two containers
+    VolumeSharing,
+    /// Communication inferred from configuration values rather than declared
+    InferredFlow,
+    /// Traffic from outside a Kubernetes cluster, routed by an Ingress through a Service
+    IngressRoute,
+    /// Content an ADD fetches into a container's image from outside the build
+    ExternalFetch,
+}
+
+impl ConnectionKind {
+    pub const ALL: [ConnectionKind; 7] = [
+        ConnectionKind::DependsOn,
+        ConnectionKind::NetworkMembership,
+        ConnectionKind::NetworkFlow,
+        ConnectionKind::VolumeSharing,
+        ConnectionKind::InferredFlow,
+        ConnectionKind::IngressRoute,
+        ConnectionKind::ExternalFetch,
+    ];
+
+    /// The connection def connections of this kind are typed by
+    pub fn definition(self) -> &'static str {
+        match self {
+            ConnectionKind::DependsOn => "Dependency",
+            ConnectionKind::NetworkMembership => "NetworkMembership",
+            ConnectionKind::NetworkFlow => "NetworkConnection",
+            ConnectionKind::VolumeSharing => "VolumeConnection",
+            ConnectionKind::InferredFlow => "InferredFlow",
+            ConnectionKind::IngressRoute => "IngressRoute",
+            ConnectionKind::ExternalFetch => "ExternalFetch",
+        }
+    }
+
+    /// The literal of the `ConnectionKind` enum def for this kind
+    pub fn literal(self) -> &'static str {
+        match self {
+            ConnectionKind::DependsOn => "dependsOn",
+            ConnectionKind::NetworkMembership => "networkMembership",
+            ConnectionKind::NetworkFlow => "networkFlow",
+            ConnectionKind::VolumeSharing => "volumeSharing",
+            ConnectionKind::InferredFlow => "inferredFlow",
+            ConnectionKind::IngressRoute => "ingressRoute",
+            ConnectionKind::ExternalFetch => "externalFetch",
+        }
+    }
+}
+
+/// A usage (feature) of a definition, with its optional body of member elements
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Usage {
+    /// `part`, `port`, `attribute`, `connection` etc, or empty for a bare redefinition
+    pub keyword: String,
+    /// The (already quoted if necessary) name of the usage
+    pub name: String,
+    pub type_name: Option<String>,
+    pub multiplicity: Option<String>,
+    pub redefines: Option<String>,
+    /// The ends of a connection usage
+    pub connect: Option<(String, String)>,
+    /// The kind of a connection usage, rendered as its first member
+    pub kind: Option<ConnectionKind>,
+    /// The rendered value expression
+    pub value: Option<String>,
+    pub members: Vec<Element>,
+}
+
+impl Usage {
+    pub fn new(keyword: &str, name: &str) -> Self {
+        Usage { keyword: keyword.to_string(), name: name.to_string(), ..Default::default() }
+    }
+
+    /// A connection of `kind` from `source` to `target`, typed by the connection def of
+    /// the kind
+    pub fn connection(name: &str, kind: ConnectionKind, source: &str, target: &str) -> Self {
+        let mut connection = Usage::new("connection", name).typed(kind.definition()).connecting(source, target);
+        connection.kind = Some(kind);
+        connection
+    }
+
+    pub fn typed(mut self, type_name: &str) -> Self {
+        self.type_name = Some(type_name.to_string());
+        self
+    }
+
+    pub fn redefining(mut self, redefines: &str) -> Self {
+        self.redefines = Some(redefines.to_string());
+        self
+    }
+
+    pub fn connecting(mut self, source: &str, target: &str) -> Self {
+        self.connect = Some((source.to_string(), target.to_string()));
+        self
+    }
+
+    pub fn valued(mut self, value: &str) -> Self {
+        self.value = Some(value.to_string());
+        self
+    }
+
+    pub fn push(&mut self, member: Element) {
+        self.members.push(member);
+    }
+}
+
+impl From<Usage> for Element {
+    fn from(usage: Usage) -> Self {
+        Element::Usage(usage)
+    }
+}
+
+/// An attribute redefining the attribute of the same name, e.g.
+/// `attribute image redefines image = "nginx";`
+pub fn attribute_redefinition(name: &str, value: &str) -> Element {
+    Usage::new("attribute", name).redefining(name).valued(value).into()
+}
+
+/// How far each level of nesting is indented
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(usize),
+    Tab,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Indent::Spaces(4)
+    }
+}
+
+impl FromStr for Indent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tab" => Ok(Indent::Tab),
+            _ => match s.parse() {
+                Ok(spaces @ 1..=8) => Ok(Indent::Spaces(spaces)),
+                _ => Err(format!("unknown indent '{}', expected a number of spaces from 1 to 8, or tab", s)),
+            },
+        }
+    }
+}
+
+impl Display for Indent {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Indent::Spaces(spaces) => write!(f, "{}", spaces),
+            Indent::Tab => write!(f, "tab"),
+        }
+    }
+}
+
+/// Where the opening brace of a body goes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BraceStyle {
+    /// At the end of the declaration, `part web: Container {`
+    #[default]
+    SameLine,
+    /// On a line of its own, indented as the declaration
+    NextLine,
+}
+
+impl FromStr for BraceStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "same-line" => Ok(BraceStyle::SameLine),
+            "next-line" => Ok(BraceStyle::NextLine),
+            _ => Err(format!("unknown brace style '{}', expected same-line or next-line", s)),
+        }
+    }
+}
+
+impl Display for BraceStyle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            BraceStyle::SameLine => write!(f, "same-line"),
+            BraceStyle::NextLine => write!(f, "next-line"),
+        }
+    }
+}
+
+/// How SysML text is laid out. The default is four space indents, braces on the line of
+/// their declaration and no wrapping.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RenderStyle {
+    pub indent: Indent,
+    /// The width to wrap the string values of usages at, where they'd make a longer line
+    pub max_width: Option<usize>,
+    pub braces: BraceStyle,
+}
+
+impl Display for RenderStyle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let max_width = self.max_width.map_or_else(|| "none".to_string(), |width| width.to_string());
+        write!(f, "indent={},max-width={},braces={}", self.indent, max_width, self.braces)
+    }
+}
+
+/// The width a tab is counted as when wrapping
+const TAB_WIDTH: usize = 4;
+
+impl RenderStyle {
+    /// The indentation of a line `depth` levels deep
+    pub fn indentation(&self, depth: usize) -> String {
+        match self.indent {
+            Indent::Spaces(spaces) => " ".repeat(spaces * depth),
+            Indent::Tab => "\t".repeat(depth),
+        }
+    }
+
+    fn width(&self, line: &str) -> usize {
+        display_width(line) + line.matches('\t').count() * (TAB_WIDTH - 1)
+    }
+}
+
+/// Render elements to SysML text in the default style
+pub fn render(elements: &[Element]) -> String {
+    render_with(elements, &RenderStyle::default())
+}
+
+/// Render elements to SysML text in `style`
+pub fn render_with(elements: &[Element], style: &RenderStyle) -> String {
+    let mut out = String::new();
+    for element in elements {
+        render_element(element, 0, style, &mut out);
+    }
+    out
+}
+
+/// Write the line opening a body, and the brace on a line of its own when the style has it
+fn open_body(head: &str, indent: &str, style: &RenderStyle, out: &mut String) {
+    match style.braces {
+        BraceStyle::SameLine => out.push_str(&format!("{}{} {{\n", indent, head)),
+        BraceStyle::NextLine => out.push_str(&format!("{}{}\n{}{{\n", indent, head, indent)),
+    }
+}
+
+fn render_element(element: &Element, depth: usize, style: &RenderStyle, out: &mut String) {
+    let indent = style.indentation(depth);
+    match element {
+        Element::Package { name, members } => {
+            open_body(&format!("package {}", name), &indent, style, out);
+            for member in members {
+                render_element(member, depth + 1, style, out);
+            }
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        Element::Usage(usage) => {
+            let kind = usage.kind.map(|kind| attribute_redefinition("kind", &format!("ConnectionKind::{}", kind.literal())));
+            if kind.is_none() && usage.members.is_empty() {
+                for line in declaration_lines(usage, depth, style, ";") {
+                    out.push_str(&format!("{}\n", line));
+                }
+            } else {
+                let end = match style.braces {
+                    BraceStyle::SameLine => " {",
+                    BraceStyle::NextLine => "",
+                };
+                for line in declaration_lines(usage, depth, style, end) {
+                    out.push_str(&format!("{}\n", line));
+                }
+                if style.braces == BraceStyle::NextLine {
+                    out.push_str(&format!("{}{{\n", indent));
+                }
+                for member in kind.iter().chain(&usage.members) {
+                    render_element(member, depth + 1, style, out);
+                }
+                out.push_str(&format!("{}}}\n", indent));
+            }
+        }
+        Element::Line(line) => {
+            out.push_str(&format!("{}{}\n", indent, line));
+        }
+        Element::Block(text) => render_block(text, depth, style, out),
+    }
+}
+
+/// Render preformatted text, which is written with four space indents and braces on the
+/// line of their declaration, in `style`
+fn render_block(text: &str, depth: usize, style: &RenderStyle, out: &mut String) {
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            out.push('\n');
+            continue;
+        }
+        let content = line.trim_start_matches(' ');
+        let spaces = line.len() - content.len();
+        let indent = format!("{}{}", style.indentation(depth + spaces / 4), " ".repeat(spaces % 4));
+        match content.strip_suffix(" {") {
+            Some(head) if !content.starts_with("//") && !head.contains('}') => open_body(head, &indent, style, out),
+            _ => out.push_str(&format!("{}{}\n", indent, content)),
+        }
+    }
+}
+
+/// The lines of the declaration of a usage at `depth`, ending with `end`. When the style
+/// has a maximum width the declaration is longer than, a string value is moved to the
+/// lines beneath it, indented, and split into literals joined by `+` where it's too long
+/// for one, each line as long as the width allows. Escapes are never split.
+fn declaration_lines(usage: &Usage, depth: usize, style: &RenderStyle, end: &str) -> Vec<String> {
+    let indent = style.indentation(depth);
+    let line = format!("{}{}{}", indent, usage_declaration(usage), end);
+    let (Some(max_width), Some(value)) = (style.max_width, &usage.value) else {
+        return vec![line];
+    };
+    let literal = value.strip_prefix('"').and_then(|value| value.strip_suffix('"'));
+    let Some(literal) = literal.filter(|literal| !literal.is_empty() && style.width(&line) > max_width) else {
+        return vec![line];
+    };
+    // Each character of the literal, with an escape and the character it escapes together
+    let mut units: Vec<String> = Vec::new();
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => units.push(chars.next().map_or_else(|| c.to_string(), |escaped| format!("{}{}", c, escaped))),
+            _ => units.push(c.to_string()),
+        }
+    }
+    let declaration = usage_declaration(&Usage { value: None, ..usage.clone() });
+    let mut lines = vec![format!("{}{} =", indent, declaration)];
+    let continuation = style.indentation(depth + 1);
+    // The columns left for a literal's content on a line ending with `suffix`
+    let room = |suffix: &str| max_width.saturating_sub(style.width(&continuation) + 2 + suffix.len());
+    let mut rest = &units[..];
+    loop {
+        let remaining: usize = rest.iter().map(|unit| display_width(unit)).sum();
+        // At least one unit goes on each line, however narrow the width
+        let mut taken = 1;
+        let mut used = display_width(&rest[0]);
+        while taken < rest.len() && used + display_width(&rest[taken]) <= room(" +") {
+            used += display_width(&rest[taken]);
+            taken += 1;
+        }
+        if remaining <= room(end) || taken == rest.len() {
+            lines.push(format!("{}\"{}\"{}", continuation, rest.concat(), end));
+            return lines;
+        }
+        lines.push(format!("{}\"{}\" +", continuation, rest[..taken].concat()));
+        rest = &rest[taken..];
+    }
+}
+
+fn usage_declaration(usage: &Usage) -> String {
+    let mut declaration = String::new();
+    if !usage.keyword.is_empty() {
+        declaration.push_str(&usage.keyword);
+        declaration.push(' ');
+    }
+    declaration.push_str(&usage.name);
+    if let Some(type_name) = &usage.type_name {
+        declaration.push_str(&format!(": {}", type_name));
+    }
+    if let Some(multiplicity) = &usage.multiplicity {
+        declaration.push_str(&format!("[{}]", multiplicity));
+    }
+    if let Some(redefines) = &usage.redefines {
+        declaration.push_str(&format!(" redefines {}", redefines));
+    }
+    if let Some((source, target)) = &usage.connect {
+        declaration.push_str(&format!(" connect {} to {}", source, target));
+    }
+    if let Some(value) = &usage.value {
+        declaration.push_str(&format!(" = {}", value));
+    }
+    declaration
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_nesting() {
+        let mut web = Usage::new("part", "web").typed("Container");
+        web.push(attribute_redefinition("image", "\"nginx\""));
+        web.push(Usage::new("port", "port0").typed("NetworkPort").into());
+        let package = Element::Package {
+            name: "appModel".to_string(),
+            members: vec![Element::Block("part def A {\n    attribute a;\n}\n\n".to_string()), web.into()],
+        };
+        assert_eq!(
+            render(&[package]),
+            "package appModel {\n    part def A {\n        attribute a;\n    }\n\n    part web: Container {\n        attribute image redefines image = \"nginx\";\n        port port0: NetworkPort;\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_render_styles() {
+        let mut web = Usage::new("part", "web").typed("Container");
+        web.push(attribute_redefinition("image", "\"nginx\""));
+        let package = [Element::Package { name: "appModel".to_string(), members: vec![Element::Block("part def A {\n    attribute a;\n}\n\n".to_string()), web.into()] }];
+        let style = RenderStyle { indent: Indent::Tab, braces: BraceStyle::NextLine, ..Default::default() };
+        assert_eq!(
+            render_with(&package, &style),
+            "package appModel\n{\n\tpart def A\n\t{\n\t\tattribute a;\n\t}\n\n\tpart web: Container\n\t{\n\t\tattribute image redefines image = \"nginx\";\n\t}\n}\n"
+        );
+        let style = RenderStyle { indent: Indent::Spaces(2), ..Default::default() };
+        assert_eq!(
+            render_with(&package, &style),
+            "package appModel {\n  part def A {\n    attribute a;\n  }\n\n  part web: Container {\n    attribute image redefines image = \"nginx\";\n  }\n}\n"
+        );
+        assert_eq!("tab".parse(), Ok(Indent::Tab));
+        assert_eq!("2".parse(), Ok(Indent::Spaces(2)));
+        assert!("0".parse::<Indent>().is_err() && "tabs".parse::<Indent>().is_err());
+        assert_eq!(RenderStyle::default().to_string(), "indent=4,max-width=none,braces=same-line");
+    }
+
+    #[test]
+    fn test_render_wrapped_values() {
+        let attribute = |value: &str| Element::from(Usage::new("attribute", "command").typed("String").valued(value));
+        let style = RenderStyle { max_width: Some(40), ..Default::default() };
+        // A line exactly at the width isn't wrapped
+        let fits = attribute("\"012345678\"");
+        assert_eq!(render_with(&[fits], &style), "attribute command: String = \"012345678\";\n");
+
+        let long = [attribute("\"sh -c 'exec nginx -g \\\"daemon off;\\\"' && echo done\"")];
+        let wrapped = render_with(&long, &style);
+        assert_eq!(wrapped, "attribute command: String =\n    \"sh -c 'exec nginx -g \\\"daemon of\" +\n    \"f;\\\"' && echo done\";\n");
+        // Joining the literals gives the value back, every escape whole, at any width
+        for width in 30..60 {
+            let style = RenderStyle { max_width: Some(width), ..Default::default() };
+            let wrapped = render_with(&long, &style);
+            assert!(wrapped.lines().all(|line| line.len() <= width), "{}", wrapped);
+            let contents: Vec<&str> = wrapped.lines().skip(1).map(|line| line.trim().trim_end_matches(" +").trim_end_matches(';')).map(|literal| &literal[1..literal.len() - 1]).collect();
+            for content in &contents {
+                let backslashes = content.len() - content.trim_end_matches('\\').len();
+                assert!(backslashes % 2 == 0, "an escape is split in {}", wrapped);
+            }
+            assert_eq!(contents.concat(), "sh -c 'exec nginx -g \\\"daemon off;\\\"' && echo done");
+        }
+        // Values which aren't strings are left as they are
+        let number = attribute(&"1".repeat(50));
+        assert_eq!(render_with(&[number], &style).lines().count(), 1);
+    }
+
+    #[test]
+    fn test_render_connection() {
+        let mut connection = Usage::connection("dependency0", ConnectionKind::DependsOn, "backend", "db");
+        connection.push(Element::Line("assert constraint healthy: Healthy { in healthcheck = db.healthcheck; }".to_string()));
+        assert_eq!(
+            render(&[connection.into()]),
+            "connection dependency0: Dependency connect backend to db {\n    attribute kind redefines kind = ConnectionKind::dependsOn;\n    assert constraint healthy: Healthy { in healthcheck = db.healthcheck; }\n}\n"
+        );
+        // A connection with nothing but its kind still has a body
+        let flow = Usage::connection("networkConnection0", ConnectionKind::NetworkFlow, "web.http", "api.http");
+        assert_eq!(
+            render(&[flow.into()]),
+            "connection networkConnection0: NetworkConnection connect web.http to api.http {\n    attribute kind redefines kind = ConnectionKind::networkFlow;\n}\n"
+        );
+    }
+}
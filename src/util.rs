@@ -1,8 +1,14 @@
 use std::{
+    collections::{HashMap, HashSet},
     path::Path,
     ffi::OsStr,
-
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+    time::Duration,
 };
+use sha2::{Digest, Sha256};
+use crate::{error::CargotectureError, parse_dockerfile::Protocol};
 
 pub fn get_basename(file: &str) -> String {
     Path::new(file)
@@ -11,3 +17,1509 @@ pub fn get_basename(file: &str) -> String {
         .unwrap_or("")
         .to_owned()
 }
+
+pub fn get_stem(file: &str) -> String {
+    Path::new(file)
+        .file_stem()
+        .and_then(OsStr::to_str)
+        .unwrap_or("")
+        .to_owned()
+}
+
+/// The tag or digest of an image reference, defaulting to `latest` as Docker does
+pub fn image_tag(image: &str) -> String {
+    if let Some((_, digest)) = image.split_once('@') {
+        return digest.to_string();
+    }
+    // A colon before the last '/' belongs to a registry host:port, not a tag
+    let name = image.rsplit('/').next().unwrap_or(image);
+    match name.split_once(':') {
+        Some((_, tag)) => tag.to_string(),
+        None => "latest".to_string(),
+    }
+}
+
+/// A network endpoint referenced by a configuration value, e.g. `postgres://db:5432/app`
+#[derive(Debug, PartialEq)]
+pub struct Endpoint<'a> {
+    pub scheme: Option<&'a str>,
+    pub host: &'a str,
+    pub port: Option<u16>,
+}
+
+/// Well-known default ports for URL schemes commonly seen in service configuration
+pub fn default_port_for_scheme(scheme: &str) -> Option<u16> {
+    match scheme.to_lowercase().as_str() {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        "postgres" | "postgresql" => Some(5432),
+        "mysql" | "mariadb" => Some(3306),
+        "redis" => Some(6379),
+        "rediss" => Some(6380),
+        "mongodb" => Some(27017),
+        "amqp" => Some(5672),
+        "amqps" => Some(5671),
+        "nats" => Some(4222),
+        "memcached" => Some(11211),
+        _ => None,
+    }
+}
+
+/// Parse either a URL (`scheme://[user[:password]@]host[:port][/path]`) or a bare
+/// `host:port` pair. Returns None for anything else, including bare words.
+pub fn parse_endpoint(value: &str) -> Option<Endpoint<'_>> {
+    let value = value.trim();
+    let (scheme, rest) = match value.split_once("://") {
+        Some((scheme, rest)) => (Some(scheme), rest),
+        None => (None, value),
+    };
+    if let Some(s) = scheme {
+        if s.is_empty() || !s.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+            return None;
+        }
+    }
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, a)| a);
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port.parse::<u16>().ok()?)),
+        None => (authority, None),
+    };
+    let valid_host = !host.is_empty()
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if !valid_host || (scheme.is_none() && port.is_none()) {
+        return None;
+    }
+    Some(Endpoint { scheme, host, port })
+}
+
+/// Replace any password embedded in a URL with `***`
+pub fn redact_url_credentials(value: &str) -> String {
+    let Some((scheme, rest)) = value.split_once("://") else {
+        return value.to_string();
+    };
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, tail) = rest.split_at(authority_end);
+    match authority.rsplit_once('@') {
+        Some((userinfo, host)) if userinfo.contains(':') => {
+            let user = userinfo.split(':').next().unwrap_or("");
+            format!("{}://{}:***@{}{}", scheme, user, host, tail)
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// Parse a compose duration such as `10s`, `1m30s` or `1.5h`: a sequence of numbers,
+/// each followed by one of the units `ns`, `us`, `ms`, `s`, `m` or `h`
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len());
+        let seconds_per_unit = match &rest[..unit_end] {
+            "ns" => 1e-9,
+            "us" | "\u{b5}s" => 1e-6,
+            "ms" => 1e-3,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return None,
+        };
+        rest = &rest[unit_end..];
+        total += Duration::try_from_secs_f64(number * seconds_per_unit).ok()?;
+    }
+    Some(total)
+}
+
+/// Parse a boolean as YAML 1.1 writes it, which compose files still use: `true`, `yes`,
+/// `on` or `y` and their opposites, in any case
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "y" => Some(true),
+        "false" | "no" | "off" | "n" => Some(false),
+        _ => None,
+    }
+}
+
+/// A number in plain decimal, as every output writes numbers: integral values without a
+/// fractional part and never in scientific notation, whatever the magnitude
+pub fn format_number(value: f64) -> String {
+    // Display for floats writes every digit rather than an exponent, and no fractional
+    // part for integral values, but keeps the sign of negative zero
+    if value == 0.0 {
+        "0".to_string()
+    } else {
+        format!("{}", value)
+    }
+}
+
+/// Parse a file mode such as `0440`, `440` or `0o440`, which are always octal. YAML reads
+/// an unquoted `0440` as a string, but `0o440` as the number it means.
+pub fn parse_file_mode(value: &str) -> Option<u32> {
+    let value = value.trim();
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    if digits.is_empty() || digits.starts_with('+') {
+        return None;
+    }
+    u32::from_str_radix(digits, 8).ok().filter(|mode| *mode <= 0o7777)
+}
+
+/// A file mode as four octal digits, such as `0440`
+pub fn format_file_mode(mode: u32) -> String {
+    format!("{:04o}", mode)
+}
+
+/// Parse a compose byte value such as `64m`, `1gb` or `1_073_741_824`: a whole number with
+/// an optional unit of `b`, `k`, `m` or `g`, optionally followed by `b`, in powers of 1024
+pub fn parse_bytes(value: &str) -> Option<u64> {
+    let value = value.trim().replace('_', "").to_ascii_lowercase();
+    let number_end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let number: u64 = value[..number_end].parse().ok()?;
+    let multiplier: u64 = match &value[number_end..] {
+        "" | "b" => 1,
+        "k" | "kb" => 1 << 10,
+        "m" | "mb" => 1 << 20,
+        "g" | "gb" => 1 << 30,
+        _ => return None,
+    };
+    number.checked_mul(multiplier)
+}
+
+/// Parse a Kubernetes memory quantity such as `256Mi`, `1G` or `1e3`: a number with an
+/// optional binary suffix `Ki` to `Ti`, in powers of 1024, or decimal suffix `k` to `T`,
+/// in powers of 1000. Fractional bytes are rounded up.
+pub fn parse_quantity(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let number_end = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let number: f64 = value[..number_end].parse().ok()?;
+    let multiplier = match &value[number_end..] {
+        "" => 1.0,
+        "Ki" => 1024.0,
+        "Mi" => 1024.0 * 1024.0,
+        "Gi" => 1024.0 * 1024.0 * 1024.0,
+        "Ti" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        "k" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        exponent => 10f64.powi(exponent.strip_prefix(['e', 'E'])?.parse().ok()?),
+    };
+    let bytes = (number * multiplier).ceil();
+    (bytes.is_finite() && bytes <= u64::MAX as f64).then_some(bytes as u64)
+}
+
+/// Parse a CPU count into thousandths of a CPU: a compose `cpus` value such as `0.5`, or a
+/// Kubernetes quantity such as `500m` or `2`
+pub fn parse_cpus(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let millicpus = match value.strip_suffix('m') {
+        Some(millicpus) => millicpus.parse::<f64>().ok()?,
+        None => value.parse::<f64>().ok()? * 1000.0,
+    };
+    (millicpus.is_finite() && millicpus >= 0.0).then(|| millicpus.round() as u64)
+}
+
+/// Expand `$NAME`, `${NAME}`, `${NAME:-default}` and `${NAME:+alternative}` references as
+/// a Dockerfile does. Unset variables expand to the empty string and `\$` is a literal `$`.
+pub fn expand_variables(value: &str, variables: &HashMap<String, String>) -> String {
+    let lookup = |name: &str| variables.get(name).filter(|v| !v.is_empty());
+    let mut expanded = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => {
+                expanded.push('$');
+                chars.next();
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let reference: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                match reference.split_once(":-") {
+                    Some((name, default)) => expanded.push_str(lookup(name).map_or(default, String::as_str)),
+                    None => match reference.split_once(":+") {
+                        Some((name, alternative)) => expanded.push_str(lookup(name).map_or("", |_| alternative)),
+                        None => expanded.push_str(variables.get(&reference).map_or("", String::as_str)),
+                    },
+                }
+            }
+            '$' if chars.peek().is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                expanded.push_str(variables.get(&name).map_or("", String::as_str));
+            }
+            _ => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+/// A port binding as written in the compose short syntax, `[[host_ip:]host_port:]container_port[/protocol]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PortBinding {
+    pub host_ip: Option<IpAddr>,
+    /// The published host port, or None when Docker will choose an ephemeral one
+    pub host_port: Option<u16>,
+    pub container_port: u16,
+    pub protocol: Protocol,
+}
+
+/// Parse a compose short-syntax port such as `80`, `8080:80`, `127.0.0.1:8080:80/udp` or
+/// `[::1]:8080:80`. The protocol may be tcp, the default, udp or sctp, as for Docker. Port
+/// ranges and other protocols are invalid.
+pub fn parse_port_mapping(s: &str) -> Result<PortBinding, CargotectureError> {
+    let (mapping, protocol) = match s.trim().split_once('/') {
+        Some((mapping, proto)) if proto.eq_ignore_ascii_case("tcp") => (mapping, Protocol::Tcp),
+        Some((mapping, proto)) if proto.eq_ignore_ascii_case("udp") => (mapping, Protocol::Udp),
+        Some((mapping, proto)) if proto.eq_ignore_ascii_case("sctp") => (mapping, Protocol::Sctp),
+        Some((_, proto)) => {
+            return Err(CargotectureError::invalid_port(s, &format!("unsupported protocol '{}', expected tcp, udp or sctp", proto)))
+        }
+        None => (s.trim(), Protocol::Tcp),
+    };
+    let parse_port = |port: &str| -> Result<u16, CargotectureError> {
+        if port.contains('-') {
+            return Err(CargotectureError::invalid_port(s, "port ranges are not supported"));
+        }
+        match port.parse::<u16>() {
+            Ok(0) | Err(_) => Err(CargotectureError::invalid_port(s, &format!("'{}' is not a port number", port))),
+            Ok(port) => Ok(port),
+        }
+    };
+    let parse_ip = |ip: &str| -> Result<IpAddr, CargotectureError> {
+        ip.parse().map_err(|_| CargotectureError::invalid_port(s, &format!("'{}' is not an IP address", ip)))
+    };
+
+    let (host_ip, host_and_container) = match mapping.strip_prefix('[') {
+        Some(rest) => {
+            let (ip, rest) = rest
+                .split_once("]:")
+                .ok_or_else(|| CargotectureError::invalid_port(s, "unterminated IPv6 address"))?;
+            (Some(parse_ip(ip)?), rest)
+        }
+        None => (None, mapping),
+    };
+    let parts: Vec<&str> = host_and_container.split(':').collect();
+    let (host_ip, host_port, container_port) = match (host_ip, parts.as_slice()) {
+        (None, [container]) => (None, None, *container),
+        (ip, [host, container]) => (ip, Some(*host), *container),
+        (None, [ip, host, container]) => (Some(parse_ip(ip)?), Some(*host), *container),
+        _ => return Err(CargotectureError::invalid_port(s, "expected [[host_ip:]host_port:]container_port[/protocol]")),
+    };
+    let host_port = match host_port {
+        Some(host) if !host.is_empty() => Some(parse_port(host)?),
+        _ => None,
+    };
+    Ok(PortBinding { host_ip, host_port, container_port: parse_port(container_port)?, protocol })
+}
+
+/// Where the data of a volume comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum VolumeSource {
+    /// A volume created for the container alone, as for `/var/lib/mysql`
+    Anonymous,
+    /// A named volume, as for `dbdata:/var/lib/mysql`
+    Named(String),
+    /// A host path, as for `./data:/var/lib/mysql` or `C:\data:/data`
+    Bind(String),
+}
+
+/// A volume as written in the compose short syntax, `[source:]target[:mode]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeSpec {
+    pub source: VolumeSource,
+    pub target: String,
+    pub read_only: bool,
+    /// Mode options other than `ro` and `rw`, such as `z` or `cached`
+    pub options: Vec<String>,
+    /// The bind source starts with `~`, which compose expands to the home directory of
+    /// whoever runs it, so the host path can't be known from the file alone
+    pub home_relative: bool,
+    /// The target was written with a trailing slash, so is meant to be a directory
+    pub directory_target: bool,
+}
+
+const VOLUME_OPTIONS: [&str; 12] = [
+    "ro", "rw", "z", "Z", "nocopy", "cached", "delegated", "consistent", "shared", "rshared", "slave", "rslave",
+];
+const VOLUME_PROPAGATIONS: [&str; 2] = ["private", "rprivate"];
+
+/// True for a Windows path beginning with a drive letter, such as `C:\data` or `c:/data`
+fn is_windows_drive_path(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && (bytes[2] == b'\\' || bytes[2] == b'/')
+}
+
+/// True for a path a container may mount a volume at: absolute, and in a Windows container
+/// either beginning with a drive letter or rooted on the current drive
+pub fn is_absolute_container_path(path: &str, windows: bool) -> bool {
+    if windows {
+        is_windows_drive_path(path) || path.starts_with(['/', '\\'])
+    } else {
+        path.starts_with('/')
+    }
+}
+
+/// Whether a path is a directory or somewhere under it
+pub fn is_within(path: &str, dir: &str) -> bool {
+    path == dir || dir == "/" || path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// How two volumes mounted in the same container overlap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MountOverlap {
+    /// Both are mounted at the same path, so one hides the other
+    Duplicate,
+    /// The second is mounted somewhere under the first, so what's visible there depends on
+    /// the order they're mounted in
+    Nested,
+}
+
+/// The pairs of mount targets that overlap, by index, the outer of nested targets first.
+/// Paths are compared canonically, and Windows paths without regard to case or the kind of
+/// slash.
+pub fn overlapping_mounts(targets: &[&str]) -> Vec<(usize, usize, MountOverlap)> {
+    let comparable = |target: &str| {
+        if is_windows_drive_path(target) || target.starts_with('\\') {
+            target.replace('\\', "/").trim_end_matches('/').to_lowercase()
+        } else {
+            canonicalize_volume_path(target).unwrap_or_else(|_| target.to_string())
+        }
+    };
+    let targets: Vec<String> = targets.iter().map(|target| comparable(target)).collect();
+    let mut overlaps = Vec::new();
+    for (second, target) in targets.iter().enumerate() {
+        for (first, other) in targets[..second].iter().enumerate() {
+            if target == other {
+                overlaps.push((first, second, MountOverlap::Duplicate));
+            } else if is_within(target, other) {
+                overlaps.push((first, second, MountOverlap::Nested));
+            } else if is_within(other, target) {
+                overlaps.push((second, first, MountOverlap::Nested));
+            }
+        }
+    }
+    overlaps
+}
+
+/// True when a host path obviously names a file rather than a directory: its last segment
+/// has an extension, such as `./nginx.conf`. Dotfiles such as `.env`, and names such as
+/// `conf.d` with a one letter extension, are as often directories.
+pub fn looks_like_file(path: &str) -> bool {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((stem, extension)) => {
+            !stem.is_empty() && (2..=5).contains(&extension.len()) && extension.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+        None => false,
+    }
+}
+
+/// True when a volume source names a host path rather than a named volume
+fn is_host_path(source: &str) -> bool {
+    source.starts_with(['.', '/', '~', '\\']) || is_windows_drive_path(source)
+}
+
+/// Split a short-syntax volume on the colons separating its fields, keeping the colon of a
+/// Windows drive letter at the start of any field as part of that field
+fn split_volume_fields(s: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if c == ':' && !(i == start + 1 && is_windows_drive_path(&s[start..])) {
+            fields.push(&s[start..i]);
+            start = i + 1;
+        }
+    }
+    fields.push(&s[start..]);
+    fields
+}
+
+/// Normalise a relative or absolute host path lexically: drop `.` segments, empty segments and
+/// trailing separators, and resolve `..` against preceding segments where there are some.
+/// Relative paths keep a leading `./` so they stay recognisable as bind mounts.
+fn normalise_host_path(path: &str) -> String {
+    if is_windows_drive_path(path) || path.starts_with('\\') {
+        return path.to_string();
+    }
+    let (root, rest) = match path.strip_prefix('/') {
+        Some(rest) => ("/", rest),
+        None => match path.strip_prefix('~') {
+            Some(rest) => ("~/", rest.trim_start_matches('/')),
+            None => ("", path),
+        },
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in rest.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." if segments.last().is_some_and(|last| *last != "..") => {
+                segments.pop();
+            }
+            ".." if root == "/" => (),
+            _ => segments.push(segment),
+        }
+    }
+    let joined = segments.join("/");
+    match root {
+        "" if segments.first() == Some(&"..") => joined,
+        "" if joined.is_empty() => ".".to_string(),
+        "" => format!("./{}", joined),
+        "~/" if joined.is_empty() => "~".to_string(),
+        _ => format!("{}{}", root, joined),
+    }
+}
+
+/// Canonicalise a mount path lexically, so that `/data/`, `//data` and `/srv/../data` are all
+/// `/data`: drop `.` segments, empty segments and trailing slashes other than the root's,
+/// and resolve `..` against preceding segments. `..` can't climb above the root, while
+/// relative paths keep the ones they can't resolve. Windows paths are kept as they are.
+/// Paths containing null bytes are rejected, as no filesystem allows them.
+pub fn canonicalize_volume_path(path: &str) -> Result<String, CargotectureError> {
+    if path.contains('\0') {
+        return Err(CargotectureError::invalid_volume(path, "the path contains a null byte"));
+    }
+    if is_windows_drive_path(path) || path.starts_with('\\') {
+        return Ok(path.to_string());
+    }
+    let absolute = path.starts_with('/');
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." if segments.last().is_some_and(|last| *last != "..") => {
+                segments.pop();
+            }
+            ".." if absolute => (),
+            _ => segments.push(segment),
+        }
+    }
+    let joined = segments.join("/");
+    Ok(match (absolute, joined.is_empty()) {
+        (true, _) => format!("/{}", joined),
+        (false, true) => ".".to_string(),
+        (false, false) => joined,
+    })
+}
+
+/// Parse a compose short-syntax volume such as `/data`, `dbdata:/data`, `./data:/data:ro,z`
+/// or `C:\data:/data`, telling named volumes from bind mounts as compose does: a source
+/// starting with `.`, `/`, `~` or a drive letter is a host path, anything else a volume name.
+pub fn parse_volume_spec(s: &str) -> Result<VolumeSpec, CargotectureError> {
+    let invalid = |reason: &str| CargotectureError::invalid_volume(s, reason);
+    let fields = split_volume_fields(s.trim());
+    let (source, target, mode) = match fields.as_slice() {
+        [target] => (None, *target, None),
+        [source, target] => (Some(*source), *target, None),
+        [source, target, mode] => (Some(*source), *target, Some(*mode)),
+        _ => return Err(invalid("expected [source:]target[:mode]")),
+    };
+    if target.is_empty() {
+        return Err(invalid("the target path is empty"));
+    }
+    if !(target.starts_with('/') || is_windows_drive_path(target)) {
+        return Err(invalid(&format!("the target '{}' is not an absolute path", target)));
+    }
+    let directory_target = target.len() > 1 && target.ends_with(['/', '\\']);
+    let target = canonicalize_volume_path(target).map_err(|_| invalid("the target contains a null byte"))?;
+
+    let mut read_only = false;
+    let mut read_write = false;
+    let mut options = Vec::new();
+    for option in mode.into_iter().flat_map(|mode| mode.split(',')) {
+        match option {
+            "ro" => read_only = true,
+            "rw" => read_write = true,
+            _ if VOLUME_OPTIONS.contains(&option) || VOLUME_PROPAGATIONS.contains(&option) => {
+                options.push(option.to_string())
+            }
+            _ => return Err(invalid(&format!("unknown mode '{}'", option))),
+        }
+    }
+    if read_only && read_write {
+        return Err(invalid("mode can't be both ro and rw"));
+    }
+
+    let source = match source {
+        None => VolumeSource::Anonymous,
+        Some("") => return Err(invalid("the source is empty")),
+        Some(source) if is_host_path(source) => VolumeSource::Bind(normalise_host_path(source)),
+        Some(name) => VolumeSource::Named(name.to_string()),
+    };
+    if matches!(source, VolumeSource::Anonymous | VolumeSource::Named(_)) && options.iter().any(|o| o == "z" || o == "Z") {
+        // SELinux relabelling only applies to host paths
+        return Err(invalid("the z and Z modes only apply to bind mounts"));
+    }
+    let home_relative = matches!(&source, VolumeSource::Bind(path) if path.starts_with('~'));
+    Ok(VolumeSpec { source, target, read_only, options, home_relative, directory_target })
+}
+
+/// Check that a name is a valid RFC 1123 DNS label: 1 to 63 lowercase letters, digits and
+/// hyphens, neither starting nor ending with a hyphen
+pub fn validate_dns_label(s: &str) -> Result<(), String> {
+    if s.is_empty() {
+        return Err("a DNS label can't be empty".to_string());
+    }
+    if s.len() > 63 {
+        return Err(format!("'{}' is longer than the 63 characters allowed in a DNS label", s));
+    }
+    if let Some(c) = s.chars().find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-')) {
+        return Err(format!("'{}' contains '{}', but DNS labels may only contain lowercase letters, digits and hyphens", s, c));
+    }
+    if s.starts_with('-') || s.ends_with('-') {
+        return Err(format!("'{}' starts or ends with a hyphen", s));
+    }
+    Ok(())
+}
+
+/// The standard signals by number, as numbered on Linux
+const SIGNAL_NAMES: [&str; 31] = [
+    "HUP", "INT", "QUIT", "ILL", "TRAP", "ABRT", "BUS", "FPE", "KILL", "USR1", "SEGV", "USR2", "PIPE", "ALRM",
+    "TERM", "STKFLT", "CHLD", "CONT", "STOP", "TSTP", "TTIN", "TTOU", "URG", "XCPU", "XFSZ", "VTALRM", "PROF",
+    "WINCH", "IO", "PWR", "SYS",
+];
+const SIGRTMIN: u8 = 34;
+const SIGRTMAX: u8 = 64;
+
+/// Parse a signal as Docker's `stop_signal` accepts it: a name such as `SIGTERM` or `TERM`
+/// in any case, a real-time signal such as `SIGRTMIN+3`, or a number from 1 to 64
+pub fn parse_signal(s: &str) -> Result<u8, CargotectureError> {
+    let value = s.trim();
+    if let Ok(number) = value.parse::<u32>() {
+        return match u8::try_from(number) {
+            Ok(number @ 1..=SIGRTMAX) => Ok(number),
+            _ => Err(CargotectureError::invalid_signal(s, "signal numbers must be from 1 to 64")),
+        };
+    }
+    let upper = value.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    let unknown = || CargotectureError::invalid_signal(s, "not a known signal name");
+    if let Some(index) = SIGNAL_NAMES.iter().position(|known| *known == name) {
+        return Ok(index as u8 + 1);
+    }
+    let realtime = |base: u8, offset: Option<&str>, sign: i16| -> Result<u8, CargotectureError> {
+        let offset: i16 = offset.map_or(Ok(0), str::parse).map_err(|_| unknown())?;
+        let number = base as i16 + sign * offset;
+        if (SIGRTMIN as i16..=SIGRTMAX as i16).contains(&number) { Ok(number as u8) } else { Err(unknown()) }
+    };
+    match name.split_once(['+', '-']) {
+        Some(("RTMIN", offset)) if name.contains('+') => realtime(SIGRTMIN, Some(offset), 1),
+        Some(("RTMAX", offset)) if name.contains('-') => realtime(SIGRTMAX, Some(offset), -1),
+        None if name == "RTMIN" => realtime(SIGRTMIN, None, 1),
+        None if name == "RTMAX" => realtime(SIGRTMAX, None, -1),
+        _ => Err(unknown()),
+    }
+}
+
+/// Split a shell command line into words as `/bin/sh` would, honouring single quotes,
+/// double quotes and backslash escapes but not expanding anything. Returns None when a
+/// quote is left unterminated.
+pub fn split_shell_words(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            '\n' => {}
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    if c != '\n' {
+                        word.get_or_insert_with(String::new).push(c);
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+/// Quote a word for the shell when it contains anything but safe characters
+pub fn shell_quote(word: &str) -> String {
+    let safe = !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+    if safe {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Make a name distinct from those already used by appending `_2`, `_3`, ... and record it.
+/// Names are compared case-insensitively, as they may become file names.
+pub fn dedup_name(used: &mut HashSet<String>, name: &str) -> String {
+    let mut candidate = name.to_string();
+    let mut suffix = 2;
+    while used.contains(&candidate.to_lowercase()) {
+        candidate = format!("{}_{}", name, suffix);
+        suffix += 1;
+    }
+    used.insert(candidate.to_lowercase());
+    candidate
+}
+
+/// Replace anything but ASCII letters, digits, `-` and `_` so a name can be used as a file name
+pub fn sanitize_file_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let sanitized = sanitized.trim_matches('_');
+    if sanitized.is_empty() { "model".to_string() } else { sanitized.to_string() }
+}
+
+/// True for addresses which aren't routed on the public internet: RFC 1918 private ranges,
+/// shared address space, loopback and link-local addresses
+pub fn is_private_ipv4(ip: &Ipv4Addr) -> bool {
+    let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+    ip.is_private() || shared || ip.is_loopback() || ip.is_link_local()
+}
+
+/// An IPv4 network in CIDR notation, such as `172.28.0.0/16`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CidrV4 {
+    pub address: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+impl CidrV4 {
+    /// The network mask, with the top `prefix_len` bits set
+    pub fn mask(&self) -> u32 {
+        u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0)
+    }
+}
+
+impl FromStr for CidrV4 {
+    type Err = CargotectureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = s
+            .split_once('/')
+            .ok_or_else(|| CargotectureError::invalid_subnet(s, "expected an address and prefix length such as 172.28.0.0/16"))?;
+        let address = address.parse().map_err(|_| CargotectureError::invalid_subnet(s, "not an IPv4 address"))?;
+        let prefix_len = prefix_len
+            .parse()
+            .ok()
+            .filter(|prefix_len| *prefix_len <= 32)
+            .ok_or_else(|| CargotectureError::invalid_subnet(s, "the prefix length must be from 0 to 32"))?;
+        Ok(CidrV4 { address, prefix_len })
+    }
+}
+
+impl fmt::Display for CidrV4 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// Whether two networks share any address: their addresses agree under the mask of the
+/// larger network
+pub fn cidrs_overlap(a: CidrV4, b: CidrV4) -> bool {
+    let mask = a.mask() & b.mask();
+    u32::from(a.address) & mask == u32::from(b.address) & mask
+}
+
+/// The SHA-256 digest of some data as lowercase hex
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DiffOp<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// The line edits turning `old` into `new`, by longest common subsequence of the lines
+/// left once the common prefix and suffix are trimmed
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffOp<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    // lengths[i][j] is the length of the LCS of a[i..] and b[j..]
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] { lengths[i + 1][j + 1] + 1 } else { lengths[i + 1][j].max(lengths[i][j + 1]) };
+        }
+    }
+
+    let mut ops: Vec<DiffOp> = old[..prefix].iter().map(|line| DiffOp::Equal(line)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push(DiffOp::Equal(a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            ops.push(DiffOp::Delete(a[i]));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Insert(b[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[old.len() - suffix..].iter().map(|line| DiffOp::Equal(line)));
+    ops
+}
+
+/// A unified diff of two texts with three lines of context, or an empty string when they're equal
+pub fn unified_diff(old_name: &str, new_name: &str, old: &str, new: &str) -> String {
+    const CONTEXT: usize = 3;
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&old_lines, &new_lines);
+    let changed: Vec<usize> = (0..ops.len()).filter(|&i| !matches!(ops[i], DiffOp::Equal(_))).collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // Group changes whose contexts would touch or overlap into hunks of op indices
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    // Line numbers in the old and new texts of each op
+    let mut positions = Vec::with_capacity(ops.len());
+    let (mut old_line, mut new_line) = (0, 0);
+    for op in &ops {
+        positions.push((old_line, new_line));
+        match op {
+            DiffOp::Equal(_) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            DiffOp::Delete(_) => old_line += 1,
+            DiffOp::Insert(_) => new_line += 1,
+        }
+    }
+    for (start, end) in hunks {
+        let hunk = &ops[start..end];
+        let old_count = hunk.iter().filter(|op| !matches!(op, DiffOp::Insert(_))).count();
+        let new_count = hunk.iter().filter(|op| !matches!(op, DiffOp::Delete(_))).count();
+        let (old_start, new_start) = positions[start];
+        // Empty ranges are numbered from the line before them
+        let number = |start: usize, count: usize| if count == 0 { start } else { start + 1 };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            number(old_start, old_count), old_count, number(new_start, new_count), new_count
+        ));
+        for op in hunk {
+            match op {
+                DiffOp::Equal(line) => out.push_str(&format!(" {}\n", line)),
+                DiffOp::Delete(line) => out.push_str(&format!("-{}\n", line)),
+                DiffOp::Insert(line) => out.push_str(&format!("+{}\n", line)),
+            }
+        }
+    }
+    out
+}
+
+/// The format a compose file is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentFormat {
+    Yaml,
+    Json,
+}
+
+/// The format of a compose file, judged by its content. A document that is a single JSON
+/// object is JSON; anything else is YAML, of which JSON is mostly a subset anyway.
+pub fn detect_format(content: &str) -> DocumentFormat {
+    let content = content.trim_start_matches('\u{feff}').trim_start();
+    if content.starts_with('{') && serde_json::from_str::<serde_json::Value>(content).is_ok_and(|value| value.is_object()) {
+        DocumentFormat::Json
+    } else {
+        DocumentFormat::Yaml
+    }
+}
+
+/// Match a path against a glob, as in `.dockerignore`: `*` and `?` match within a path element,
+/// `**` any number of path elements, and `\` escapes the character after it
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    if let Some(rest) = pattern.strip_prefix("**") {
+        let rest = rest.strip_prefix('/').unwrap_or(rest);
+        let starts = std::iter::once(0).chain(path.match_indices('/').map(|(i, _)| i + 1)).chain(std::iter::once(path.len()));
+        return starts.into_iter().any(|i| glob_match(rest, &path[i..]));
+    }
+    let mut chars = pattern.chars();
+    match chars.next() {
+        None => path.is_empty(),
+        Some('*') => path
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(path.len()))
+            .take_while(|&i| !path[..i].contains('/'))
+            .any(|i| glob_match(chars.as_str(), &path[i..])),
+        Some('?') => {
+            let mut rest = path.chars();
+            matches!(rest.next(), Some(c) if c != '/') && glob_match(chars.as_str(), rest.as_str())
+        }
+        Some(c) => {
+            let c = if c == '\\' { chars.next().unwrap_or('\\') } else { c };
+            path.strip_prefix(c).is_some_and(|rest| glob_match(chars.as_str(), rest))
+        }
+    }
+}
+
+/// A variable set by a line of an env file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvFileEntry {
+    pub key: String,
+    /// The value as written, unquoted. None for a line naming a variable without a value,
+    /// which takes its value from the environment compose runs in.
+    pub value: Option<String>,
+    /// The value was single-quoted, so isn't interpolated
+    pub literal: bool,
+    /// The line, counting from 1
+    pub line: usize,
+}
+
+/// Parse an env file as compose reads `env_file`: `KEY=VALUE` lines, optionally after
+/// `export`, with blank lines and `#` comments ignored. Values may be single-quoted, taken
+/// literally, or double-quoted, with `\n`, `\"` and `\\` escapes. Unquoted values end at a
+/// ` #` comment.
+pub fn parse_env_file(content: &str) -> Vec<EnvFileEntry> {
+    let mut entries = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim())),
+            None => (line, None),
+        };
+        let literal = value.is_some_and(|value| value.len() > 1 && value.starts_with('\'') && value.ends_with('\''));
+        let value = value.map(|value| match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+            Some(quoted) => quoted.replace("\\n", "\n").replace("\\\"", "\"").replace("\\\\", "\\"),
+            None if literal => value[1..value.len() - 1].to_string(),
+            None => value.split(" #").next().unwrap_or_default().trim_end().to_string(),
+        });
+        entries.push(EnvFileEntry { key: key.to_string(), value, literal, line: index + 1 });
+    }
+    entries
+}
+
+/// A value with its `${VAR}` interpolations substituted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Interpolation {
+    pub value: String,
+    /// The variables the value refers to, in the order they're first referred to
+    pub variables: Vec<String>,
+    /// Variables substituted without being set, which give an empty string
+    pub unset: Vec<String>,
+}
+
+/// Substitute variables into a value as compose interpolates its files: `$VAR`, `${VAR}`,
+/// `${VAR:-default}` and `${VAR-default}`, `${VAR:?error}` and `${VAR?error}`, which fail
+/// when the variable isn't set, and `${VAR:+replacement}` and `${VAR+replacement}`. The
+/// forms with a colon treat an empty variable as unset. `$$` is a literal `$`.
+pub fn interpolate(value: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Interpolation, String> {
+    let mut interpolation = Interpolation::default();
+    interpolate_into(value, lookup, &mut interpolation)?;
+    Ok(interpolation)
+}
+
+fn is_variable_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn interpolate_into(value: &str, lookup: &dyn Fn(&str) -> Option<String>, interpolation: &mut Interpolation) -> Result<(), String> {
+    let mut rest = value;
+    while let Some(index) = rest.find('$') {
+        interpolation.value.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            interpolation.value.push('$');
+            rest = after;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            let mut depth = 1;
+            let end = braced
+                .char_indices()
+                .find(|(_, c)| {
+                    depth += match c {
+                        '{' => 1,
+                        '}' => -1,
+                        _ => 0,
+                    };
+                    depth == 0
+                })
+                .map(|(end, _)| end)
+                .ok_or_else(|| format!("'${{' is never closed in '{}'", value))?;
+            substitute(&braced[..end], lookup, interpolation)?;
+            rest = &braced[end + 1..];
+        } else {
+            let length = after.find(|c| !is_variable_char(c)).unwrap_or(after.len());
+            if length == 0 || after.starts_with(|c: char| c.is_ascii_digit()) {
+                interpolation.value.push('$');
+                rest = after;
+            } else {
+                substitute(&after[..length], lookup, interpolation)?;
+                rest = &after[length..];
+            }
+        }
+    }
+    interpolation.value.push_str(rest);
+    Ok(())
+}
+
+/// Substitute the expression between the braces of `${...}`
+fn substitute(expression: &str, lookup: &dyn Fn(&str) -> Option<String>, interpolation: &mut Interpolation) -> Result<(), String> {
+    let length = expression.find(|c| !is_variable_char(c)).unwrap_or(expression.len());
+    let (name, modifier) = expression.split_at(length);
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(format!("invalid interpolation '${{{}}}'", expression));
+    }
+    if !interpolation.variables.iter().any(|variable| variable == name) {
+        interpolation.variables.push(name.to_string());
+    }
+    let value = lookup(name);
+    let (colon, modifier) = match modifier.strip_prefix(':') {
+        Some(modifier) => (true, modifier),
+        None => (false, modifier),
+    };
+    // Whether the variable counts as set: with a colon, only when it's also not empty
+    let set = value.as_deref().is_some_and(|value| !colon || !value.is_empty());
+    let mut operator = modifier.chars();
+    let operand = operator.as_str().get(1..).unwrap_or_default();
+    match (operator.next(), set) {
+        (None, _) if colon => Err(format!("invalid interpolation '${{{}}}'", expression)),
+        (None, _) => {
+            if value.is_none() && !interpolation.unset.iter().any(|unset| unset == name) {
+                interpolation.unset.push(name.to_string());
+            }
+            interpolation.value.push_str(&value.unwrap_or_default());
+            Ok(())
+        }
+        (Some('-'), false) | (Some('+'), true) => interpolate_into(operand, lookup, interpolation),
+        (Some('-' | '?'), true) => {
+            interpolation.value.push_str(&value.unwrap_or_default());
+            Ok(())
+        }
+        (Some('+'), false) => Ok(()),
+        (Some('?'), false) if operand.is_empty() => Err(format!("required variable {} is missing a value", name)),
+        (Some('?'), false) => Err(format!("required variable {} is missing a value: {}", name, operand)),
+        _ => Err(format!("invalid interpolation '${{{}}}'", expression)),
+    }
+}
+
+/// The Levenshtein distance between two strings: the fewest characters inserted, deleted or
+/// substituted to turn one into the other
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            current.push((previous[j] + usize::from(ca != *cb)).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The names a misspelt name is likely meant to be, closest first: those within an edit
+/// distance of 2 of it, fewer than its length so that short names don't suggest any other
+/// short name, and those it's a prefix of, or which are a prefix of it, of at least 3
+/// characters
+pub fn suggestions<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let max_distance = 2.min(name.chars().count().saturating_sub(1));
+    let prefixed = |a: &str, b: &str| a.len() >= 3 && b.starts_with(a);
+    let mut similar: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= max_distance || prefixed(name, candidate) || prefixed(candidate, name))
+        .collect();
+    similar.sort();
+    similar.dedup();
+    similar.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// A hint naming suggestions to end a message with, such as `, did you mean 'backend'?`,
+/// or nothing when there are none
+pub fn did_you_mean(suggestions: &[&str]) -> String {
+    let quoted: Vec<String> = suggestions.iter().map(|suggestion| format!("'{}'", suggestion)).collect();
+    match quoted.split_last() {
+        None => String::new(),
+        Some((last, [])) => format!(", did you mean {}?", last),
+        Some((last, rest)) => format!(", did you mean {} or {}?", rest.join(", "), last),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            parse_endpoint("postgres://user:secret@db:5432/app"),
+            Some(Endpoint { scheme: Some("postgres"), host: "db", port: Some(5432) })
+        );
+        assert_eq!(
+            parse_endpoint("http://elasticsearch"),
+            Some(Endpoint { scheme: Some("http"), host: "elasticsearch", port: None })
+        );
+        assert_eq!(
+            parse_endpoint("kafka:9092"),
+            Some(Endpoint { scheme: None, host: "kafka", port: Some(9092) })
+        );
+        assert_eq!(parse_endpoint("redis"), None);
+        assert_eq!(parse_endpoint("-Xms512m -Xmx512m"), None);
+        assert_eq!(parse_endpoint("db:notaport"), None);
+    }
+
+    #[test]
+    fn test_image_tag() {
+        assert_eq!(image_tag("elasticsearch:7.16.1"), "7.16.1");
+        assert_eq!(image_tag("nginx"), "latest");
+        assert_eq!(image_tag("registry.local:5000/team/app"), "latest");
+        assert_eq!(image_tag("registry.local:5000/team/app:v2"), "v2");
+        assert_eq!(image_tag("alpine@sha256:abcd"), "sha256:abcd");
+    }
+
+    #[test]
+    fn test_redact_url_credentials() {
+        assert_eq!(
+            redact_url_credentials("postgres://user:secret@db:5432/app"),
+            "postgres://user:***@db:5432/app"
+        );
+        assert_eq!(redact_url_credentials("http://db:80/x"), "http://db:80/x");
+        assert_eq!(redact_url_credentials("plain"), "plain");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("10s"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("1m30s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5h"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("0s"), Some(Duration::ZERO));
+        assert_eq!(parse_duration("abc"), None);
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn test_canonical_scalars() {
+        for (value, expected) in [("yes", Some(true)), ("On", Some(true)), ("off", Some(false)), ("N", Some(false)), ("maybe", None)] {
+            assert_eq!(parse_bool(value), expected, "{}", value);
+        }
+        assert_eq!(format_number(8080.0), "8080");
+        assert_eq!(format_number(1.5), "1.5");
+        assert_eq!(format_number(1e21), "1000000000000000000000");
+        assert_eq!(format_number(0.0000005), "0.0000005");
+        assert_eq!(format_number(-0.0), "0");
+
+        assert_eq!(parse_file_mode("0440"), Some(0o440));
+        assert_eq!(parse_file_mode("440"), Some(0o440));
+        assert_eq!(parse_file_mode("0o440"), Some(0o440));
+        assert_eq!(parse_file_mode("0448"), None);
+        assert_eq!(parse_file_mode("17777"), None);
+        assert_eq!(format_file_mode(0o440), "0440");
+        assert_eq!(format_file_mode(0o4755), "4755");
+
+        assert_eq!(parse_bytes("64m"), Some(64 << 20));
+        assert_eq!(parse_bytes("1GB"), Some(1 << 30));
+        assert_eq!(parse_bytes("1_099_511_627_776"), Some(1 << 40));
+        assert_eq!(parse_bytes("1.5g"), None);
+        assert_eq!(parse_bytes("99999999999g"), None);
+
+        assert_eq!(parse_quantity("256Mi"), Some(256 << 20));
+        assert_eq!(parse_quantity("1G"), Some(1_000_000_000));
+        assert_eq!(parse_quantity("1.5Ki"), Some(1536));
+        assert_eq!(parse_quantity("1e3"), Some(1000));
+        assert_eq!(parse_quantity("128"), Some(128));
+        assert_eq!(parse_quantity("1Zi"), None);
+        assert_eq!(parse_cpus("500m"), Some(500));
+        assert_eq!(parse_cpus("0.5"), Some(500));
+        assert_eq!(parse_cpus("2"), Some(2000));
+        assert_eq!(parse_cpus("half"), None);
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        let variables = HashMap::from([
+            ("BASE".to_string(), "debian:bookworm".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+        assert_eq!(expand_variables("${BASE}", &variables), "debian:bookworm");
+        assert_eq!(expand_variables("$BASE-slim", &variables), "debian:bookworm-slim");
+        assert_eq!(expand_variables("${MISSING:-alpine}", &variables), "alpine");
+        assert_eq!(expand_variables("${EMPTY:-alpine}", &variables), "alpine");
+        assert_eq!(expand_variables("${BASE:+set}", &variables), "set");
+        assert_eq!(expand_variables("a${MISSING}b", &variables), "ab");
+        assert_eq!(expand_variables("\\$BASE", &variables), "$BASE");
+        assert_eq!(expand_variables("no variables", &variables), "no variables");
+    }
+
+    #[test]
+    fn test_parse_port_mapping() {
+        let binding = |host_ip: Option<&str>, host_port, container_port, protocol| PortBinding {
+            host_ip: host_ip.map(|ip| ip.parse().unwrap()),
+            host_port,
+            container_port,
+            protocol,
+        };
+        assert_eq!(parse_port_mapping("80").unwrap(), binding(None, None, 80, Protocol::Tcp));
+        assert_eq!(parse_port_mapping("80:90").unwrap(), binding(None, Some(80), 90, Protocol::Tcp));
+        assert_eq!(parse_port_mapping("127.0.0.1:80:90").unwrap(), binding(Some("127.0.0.1"), Some(80), 90, Protocol::Tcp));
+        assert_eq!(parse_port_mapping("80:90/udp").unwrap(), binding(None, Some(80), 90, Protocol::Udp));
+        assert_eq!(parse_port_mapping("127.0.0.1:80:90/tcp").unwrap(), binding(Some("127.0.0.1"), Some(80), 90, Protocol::Tcp));
+        assert_eq!(parse_port_mapping("127.0.0.1::90").unwrap(), binding(Some("127.0.0.1"), None, 90, Protocol::Tcp));
+        assert_eq!(parse_port_mapping("[::1]:80:90").unwrap(), binding(Some("::1"), Some(80), 90, Protocol::Tcp));
+
+        assert_eq!(
+            parse_port_mapping("abc").unwrap_err().to_string(),
+            "Invalid port 'abc': 'abc' is not a port number"
+        );
+        assert!(parse_port_mapping("").is_err());
+        assert!(parse_port_mapping("70000").is_err());
+        assert!(parse_port_mapping("0").is_err());
+        assert!(parse_port_mapping("localhost:80:90").is_err());
+        assert!(parse_port_mapping("1:2:3:4").is_err());
+        assert_eq!(parse_port_mapping("80/sctp").unwrap(), binding(None, None, 80, Protocol::Sctp));
+        assert_eq!(parse_port_mapping("80:90/SCTP").unwrap(), binding(None, Some(80), 90, Protocol::Sctp));
+        assert_eq!(
+            parse_port_mapping("80:90/bogus").unwrap_err(),
+            CargotectureError::invalid_port("80:90/bogus", "unsupported protocol 'bogus', expected tcp, udp or sctp")
+        );
+        assert!(parse_port_mapping("80/").is_err());
+        assert_eq!(
+            parse_port_mapping("8000-8010:80").unwrap_err(),
+            CargotectureError::invalid_port("8000-8010:80", "port ranges are not supported")
+        );
+    }
+
+    #[test]
+    fn test_split_shell_words() {
+        assert_eq!(split_shell_words("logstash -f /etc/app.conf").unwrap(), vec!["logstash", "-f", "/etc/app.conf"]);
+        assert_eq!(
+            split_shell_words(r#"sh -c "echo \"quoted\" \$HOME" 'single '"double""#).unwrap(),
+            vec!["sh", "-c", "echo \"quoted\" $HOME", "single double"]
+        );
+        assert_eq!(split_shell_words(r"one\ word '' x").unwrap(), vec!["one word", "", "x"]);
+        assert_eq!(split_shell_words("  ").unwrap(), Vec::<String>::new());
+        assert_eq!(split_shell_words("echo 'unterminated"), None);
+        let word = "it's a \"test\" $x";
+        assert_eq!(split_shell_words(&shell_quote(word)).unwrap(), vec![word]);
+        assert_eq!(shell_quote("/bin/server"), "/bin/server");
+    }
+
+    #[test]
+    fn test_dedup_and_sanitize_names() {
+        let mut used = HashSet::new();
+        assert_eq!(dedup_name(&mut used, "api"), "api");
+        assert_eq!(dedup_name(&mut used, "API"), "API_2");
+        assert_eq!(dedup_name(&mut used, "api"), "api_3");
+        assert_eq!(dedup_name(&mut used, "web"), "web");
+        assert_eq!(sanitize_file_name("'my app.v2Model'"), "my_app_v2Model");
+        assert_eq!(sanitize_file_name("'...'"), "model");
+    }
+
+    #[test]
+    fn test_cidrs_overlap() {
+        let cidr = |s: &str| s.parse::<CidrV4>().unwrap();
+        assert!(cidrs_overlap(cidr("172.28.0.0/16"), cidr("172.28.1.0/24")));
+        assert!(cidrs_overlap(cidr("172.28.1.0/24"), cidr("172.28.0.0/16")));
+        assert!(cidrs_overlap(cidr("10.1.0.0/16"), cidr("10.1.0.0/16")));
+        assert!(!cidrs_overlap(cidr("10.0.0.0/8"), cidr("192.168.0.0/16")));
+        assert!(!cidrs_overlap(cidr("172.28.0.0/24"), cidr("172.28.1.0/24")));
+        // Every network is within 0.0.0.0/0
+        assert!(cidrs_overlap(cidr("0.0.0.0/0"), cidr("192.168.0.0/16")));
+
+        assert_eq!(cidr("172.28.0.0/16").to_string(), "172.28.0.0/16");
+        assert_eq!(cidr("10.0.0.0/8").mask(), 0xff00_0000);
+        assert_eq!(
+            "172.28.0.0".parse::<CidrV4>().unwrap_err().to_string(),
+            "Invalid subnet '172.28.0.0': expected an address and prefix length such as 172.28.0.0/16"
+        );
+        assert!("172.28.0.0/33".parse::<CidrV4>().is_err());
+        assert!("fd00::/64".parse::<CidrV4>().is_err());
+    }
+
+    #[test]
+    fn test_is_private_ipv4() {
+        for private in ["10.1.2.3", "172.16.0.1", "192.168.1.1", "100.64.0.1", "127.0.0.11", "169.254.1.1"] {
+            assert!(is_private_ipv4(&private.parse().unwrap()), "{}", private);
+        }
+        for public in ["8.8.8.8", "1.1.1.1", "172.32.0.1", "100.128.0.1"] {
+            assert!(!is_private_ipv4(&public.parse().unwrap()), "{}", public);
+        }
+    }
+
+    #[test]
+    fn test_overlapping_mounts() {
+        use MountOverlap::*;
+        assert_eq!(overlapping_mounts(&["/data/sub", "/logs", "/data", "/logs"]), vec![(2, 0, Nested), (1, 3, Duplicate)]);
+        assert_eq!(overlapping_mounts(&["/", "/data"]), vec![(0, 1, Nested)]);
+        assert!(overlapping_mounts(&["/data", "/data2"]).is_empty());
+        assert_eq!(overlapping_mounts(&[r"C:\App", "c:/app/"]), vec![(0, 1, Duplicate)]);
+
+        assert!(looks_like_file("./nginx.conf"));
+        assert!(looks_like_file(r"C:\config\app.json"));
+        assert!(!looks_like_file("./conf.d"));
+        assert!(!looks_like_file("./.env"));
+        assert!(!looks_like_file("./data"));
+
+        assert!(is_absolute_container_path("/data", false));
+        assert!(!is_absolute_container_path(r"C:\data", false));
+        assert!(is_absolute_container_path(r"C:\data", true));
+        assert!(!is_absolute_container_path("data", true));
+    }
+
+    #[test]
+    fn test_parse_volume_spec() {
+        let spec = |source, target: &str, read_only, options: &[&str], home_relative| VolumeSpec {
+            source,
+            target: target.to_string(),
+            read_only,
+            options: options.iter().map(|o| o.to_string()).collect(),
+            home_relative,
+            directory_target: false,
+        };
+        let named = |name: &str| VolumeSource::Named(name.to_string());
+        let bind = |path: &str| VolumeSource::Bind(path.to_string());
+        let cases = [
+            ("/var/lib/mysql", spec(VolumeSource::Anonymous, "/var/lib/mysql", false, &[], false)),
+            ("/var/lib/mysql/", VolumeSpec { directory_target: true, ..spec(VolumeSource::Anonymous, "/var/lib/mysql", false, &[], false) }),
+            ("dbdata://var/lib/../lib/mysql", spec(named("dbdata"), "/var/lib/mysql", false, &[], false)),
+            ("dbdata:/var/lib/mysql", spec(named("dbdata"), "/var/lib/mysql", false, &[], false)),
+            ("dbdata:/var/lib/mysql:ro", spec(named("dbdata"), "/var/lib/mysql", true, &[], false)),
+            ("dbdata:/data:nocopy", spec(named("dbdata"), "/data", false, &["nocopy"], false)),
+            ("./data:/var/lib/mysql", spec(bind("./data"), "/var/lib/mysql", false, &[], false)),
+            ("./data/./x/../y/:/y:rw", spec(bind("./data/y"), "/y", false, &[], false)),
+            ("../shared:/shared:ro,z", spec(bind("../shared"), "/shared", true, &["z"], false)),
+            ("/opt/data:/data:Z", spec(bind("/opt/data"), "/data", false, &["Z"], false)),
+            ("/etc/conf:/conf:ro,cached", spec(bind("/etc/conf"), "/conf", true, &["cached"], false)),
+            ("./src:/app:delegated,rshared", spec(bind("./src"), "/app", false, &["delegated", "rshared"], false)),
+            ("~/data:/x", spec(bind("~/data"), "/x", false, &[], true)),
+            ("~:/home/user:ro", spec(bind("~"), "/home/user", true, &[], true)),
+            (r"C:\data:/x", spec(bind(r"C:\data"), "/x", false, &[], false)),
+            (r"c:/data:/x:ro", spec(bind("c:/data"), "/x", true, &[], false)),
+            (r"C:\data:C:\app", spec(bind(r"C:\data"), r"C:\app", false, &[], false)),
+            (r"\\server\share:/mnt:ro", spec(bind(r"\\server\share"), "/mnt", true, &[], false)),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_volume_spec(value).unwrap(), expected, "{}", value);
+        }
+
+        assert_eq!(
+            parse_volume_spec("data:relative").unwrap_err().to_string(),
+            "Invalid volume 'data:relative': the target 'relative' is not an absolute path"
+        );
+        assert!(parse_volume_spec("/a:/b:bogus").is_err());
+        assert!(parse_volume_spec("/a:/b:ro,rw").is_err());
+        assert!(parse_volume_spec("data:/b:z").is_err());
+        assert!(parse_volume_spec(":/b").is_err());
+        assert!(parse_volume_spec("/a:/b:ro:extra").is_err());
+        assert!(parse_volume_spec("").is_err());
+        assert_eq!(
+            parse_volume_spec("data:/da\0ta").unwrap_err().to_string(),
+            "Invalid volume 'data:/da\0ta': the target contains a null byte"
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_volume_path() {
+        let cases = [
+            ("/data", "/data"),
+            ("/data/", "/data"),
+            ("/data///", "/data"),
+            ("/", "/"),
+            ("//", "/"),
+            ("//var//lib/./mysql", "/var/lib/mysql"),
+            ("/var/lib/../log", "/var/log"),
+            ("/../../data", "/data"),
+            ("/data/..", "/"),
+            ("data/", "data"),
+            ("./data/../cache", "cache"),
+            ("../data", "../data"),
+            ("data/../..", ".."),
+            (".", "."),
+            (r"C:\data\", r"C:\data\"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(canonicalize_volume_path(path).unwrap(), expected, "{}", path);
+        }
+        assert_eq!(
+            canonicalize_volume_path("/da\0ta").unwrap_err().to_string(),
+            "Invalid volume '/da\0ta': the path contains a null byte"
+        );
+    }
+
+    #[test]
+    fn test_validate_dns_label() {
+        assert!(validate_dns_label("web").is_ok());
+        assert!(validate_dns_label("web-1").is_ok());
+        assert!(validate_dns_label(&"a".repeat(63)).is_ok());
+        assert!(validate_dns_label(&"a".repeat(64)).is_err());
+        assert!(validate_dns_label("").is_err());
+        assert!(validate_dns_label("-web").is_err());
+        assert!(validate_dns_label("web-").is_err());
+        assert_eq!(
+            validate_dns_label("Web").unwrap_err(),
+            "'Web' contains 'W', but DNS labels may only contain lowercase letters, digits and hyphens"
+        );
+        assert!(validate_dns_label("web_1").is_err());
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGTERM").unwrap(), 15);
+        assert_eq!(parse_signal("SIGKILL").unwrap(), 9);
+        assert_eq!(parse_signal("15").unwrap(), 15);
+        assert_eq!(parse_signal("usr1").unwrap(), 10);
+        assert_eq!(parse_signal("SIGRTMIN+2").unwrap(), 36);
+        assert_eq!(parse_signal("SIGRTMAX").unwrap(), 64);
+        assert_eq!(parse_signal("SIGFOO").unwrap_err().to_string(), "Invalid signal 'SIGFOO': not a known signal name");
+        assert_eq!(parse_signal("0").unwrap_err().to_string(), "Invalid signal '0': signal numbers must be from 1 to 64");
+        assert!(parse_signal("65").is_err());
+        assert!(parse_signal("SIGRTMIN+40").is_err());
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256_hex(&[b'a'; 1000]),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\n";
+        assert_eq!(unified_diff("old", "new", old, old), "");
+        assert_eq!(
+            unified_diff("old", "new", old, new),
+            "--- old\n+++ new\n@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n@@ -8,3 +8,4 @@\n h\n i\n j\n+k\n"
+        );
+        assert_eq!(unified_diff("old", "new", "", "x\n"), "--- old\n+++ new\n@@ -0,0 +1,1 @@\n+x\n");
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect_format("services:\n  web:\n    image: nginx\n"), DocumentFormat::Yaml);
+        assert_eq!(detect_format("\u{feff}\n  {\"services\": {\"web\": {\"image\": \"nginx\"}}}\n"), DocumentFormat::Json);
+        // YAML flow mappings aren't JSON
+        assert_eq!(detect_format("{services: {web: {image: nginx}}}\n"), DocumentFormat::Yaml);
+        assert_eq!(detect_format("[]"), DocumentFormat::Yaml);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("no-netwrok", "no-network"), 2);
+        assert_eq!(edit_distance("dependency-cycle", "dependency-cycle"), 0);
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let content = "# database\nDB_HOST=db\n\nexport DB_PORT = 5432 # default\nGREETING=\"hello\\nworld\"\nPATTERN='$literal'\nFROM_SHELL\nEMPTY=\n";
+        let parsed = parse_env_file(content);
+        let entries: Vec<(&str, Option<&str>, bool, usize)> = parsed.iter().map(|e| (e.key.as_str(), e.value.as_deref(), e.literal, e.line)).collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("DB_HOST", Some("db"), false, 2),
+                ("DB_PORT", Some("5432"), false, 4),
+                ("GREETING", Some("hello\nworld"), false, 5),
+                ("PATTERN", Some("$literal"), true, 6),
+                ("FROM_SHELL", None, false, 7),
+                ("EMPTY", Some(""), false, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let lookup = |name: &str| match name {
+            "HOST" => Some("db".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let value = |value: &str| interpolate(value, &lookup).map(|interpolation| interpolation.value);
+        assert_eq!(value("postgres://${HOST}:5432/$HOST"), Ok("postgres://db:5432/db".to_string()));
+        assert_eq!(value("${MISSING:-${HOST}-fallback}"), Ok("db-fallback".to_string()));
+        assert_eq!(value("${EMPTY:-default}|${EMPTY-default}"), Ok("default|".to_string()));
+        assert_eq!(value("${HOST:+set}|${MISSING+set}"), Ok("set|".to_string()));
+        assert_eq!(value("$$HOST costs $5"), Ok("$HOST costs $5".to_string()));
+        assert_eq!(value("${MISSING:?set it}"), Err("required variable MISSING is missing a value: set it".to_string()));
+        assert_eq!(value("${EMPTY?}"), Ok(String::new()));
+        assert_eq!(value("${HOST"), Err("'${' is never closed in '${HOST'".to_string()));
+        assert_eq!(value("${HO ST}"), Err("invalid interpolation '${HO ST}'".to_string()));
+        let interpolation = interpolate("$MISSING/${HOST}/${MISSING}", &lookup).unwrap();
+        assert_eq!(interpolation.variables, ["MISSING", "HOST"]);
+        assert_eq!(interpolation.unset, ["MISSING"]);
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let services = ["backend", "frontend", "db", "cache"];
+        assert_eq!(suggestions("backnd", services), ["backend"]);
+        assert_eq!(suggestions("back", services), ["backend"]);
+        assert_eq!(suggestions("backend-v2", services), ["backend"]);
+        assert_eq!(suggestions("dbs", services), ["db"]);
+        assert!(suggestions("queue", services).is_empty());
+        assert!(suggestions("db", services).is_empty());
+        assert!(suggestions("db", ["web"]).is_empty());
+        assert_eq!(suggestions("ab", ["web", "db"]), ["db"]);
+        assert_eq!(did_you_mean(&[]), "");
+        assert_eq!(did_you_mean(&["backend"]), ", did you mean 'backend'?");
+        assert_eq!(did_you_mean(&["a", "b", "c"]), ", did you mean 'a', 'b' or 'c'?");
+    }
+}
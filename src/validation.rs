@@ -0,0 +1,1174 @@
+use serde::Serialize;
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{Cursor, Read},
+};
+use crate::{
+    error::CargotectureError,
+    lint::{lint_containerfile, Severity, EXPLANATIONS as LINT_EXPLANATIONS, RULES},
+    parse_dockerfile::parse_containerfile,
+    util::{edit_distance, glob_match},
+};
+#[cfg(feature = "compose")]
+use crate::parse_compose::{parse_composefiles_unvalidated, ParseOptions};
+#[cfg(feature = "pod")]
+use crate::parse_manifests::parse_manifests;
+
+/// The codes of the problems validation reports, other than lint findings, with the
+/// severity each is reported with unless its level is changed
+pub const CODES: &[(&str, Severity)] = &[
+    ("parse-failed", Severity::Error),
+    ("unknown-field", Severity::Warning),
+    ("no-services", Severity::Error),
+    ("unconfigured-service", Severity::Warning),
+    ("no-image", Severity::Error),
+    ("invalid-hostname", Severity::Error),
+    ("duplicate-hostname", Severity::Error),
+    ("invalid-restart", Severity::Error),
+    ("unquoted-restart", Severity::Warning),
+    ("invalid-watch", Severity::Error),
+    ("invalid-stop-signal", Severity::Error),
+    ("home-relative-volume", Severity::Warning),
+    ("invalid-volume", Severity::Error),
+    ("unknown-volume", Severity::Error),
+    ("duplicate-volume-target", Severity::Error),
+    ("nested-volume-target", Severity::Warning),
+    ("file-on-directory-mount", Severity::Note),
+    ("invalid-healthcheck", Severity::Error),
+    ("invalid-device-request", Severity::Error),
+    ("unknown-device-capability", Severity::Warning),
+    ("device-request-conflict", Severity::Warning),
+    ("invalid-scale", Severity::Error),
+    ("scale-and-replicas", Severity::Warning),
+    ("no-network", Severity::Warning),
+    ("interactive-replicas", Severity::Warning),
+    ("invalid-port", Severity::Error),
+    ("replica-port-conflict", Severity::Warning),
+    ("unexposed-port", Severity::Warning),
+    ("public-dns", Severity::Warning),
+    ("unknown-network", Severity::Error),
+    ("unknown-dependency", Severity::Error),
+    ("unhealthy-dependency", Severity::Warning),
+    ("dependency-cycle", Severity::Error),
+    ("profile-auto-activated", Severity::Note),
+    ("inactive-optional-dependency", Severity::Warning),
+    ("subnet-overlap", Severity::Error),
+    ("ambiguous-name", Severity::Warning),
+    ("image-not-found", Severity::Warning),
+    ("image-unchecked", Severity::Warning),
+    ("shared-volume", Severity::Note),
+    ("duplicate-container-port", Severity::Error),
+    ("duplicate-host-port", Severity::Error),
+    ("unresolved-ingress-backend", Severity::Warning),
+    ("denied-ingress-route", Severity::Warning),
+    ("unmodelled-object", Severity::Warning),
+    ("unavailable", Severity::Warning),
+];
+
+/// The kind of problem a diagnostic code reports, by which `explain --list` groups codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiagnosticCategory {
+    Input,
+    Services,
+    Dependencies,
+    Networking,
+    Volumes,
+    Devices,
+    Images,
+    Containerfiles,
+}
+
+impl Display for DiagnosticCategory {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            DiagnosticCategory::Input => write!(f, "input"),
+            DiagnosticCategory::Services => write!(f, "services"),
+            DiagnosticCategory::Dependencies => write!(f, "dependencies"),
+            DiagnosticCategory::Networking => write!(f, "networking"),
+            DiagnosticCategory::Volumes => write!(f, "volumes"),
+            DiagnosticCategory::Devices => write!(f, "devices"),
+            DiagnosticCategory::Images => write!(f, "images"),
+            DiagnosticCategory::Containerfiles => write!(f, "containerfiles"),
+        }
+    }
+}
+
+/// What a diagnostic code means, at more length than its messages, for `explain`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiagnosticInfo {
+    pub code: &'static str,
+    pub category: DiagnosticCategory,
+    /// What the code reports, in a sentence
+    pub summary: &'static str,
+    /// Why it's worth reporting
+    pub rationale: &'static str,
+    /// Input reported with the code
+    pub failing: &'static str,
+    /// The same input, fixed
+    pub passing: &'static str,
+    pub remediation: &'static str,
+}
+
+impl DiagnosticInfo {
+    /// The severity the code is reported with unless its level is changed
+    pub fn severity(&self) -> Severity {
+        all_codes().into_iter().find(|(code, _)| *code == self.code).map_or(Severity::Warning, |(_, severity)| severity)
+    }
+}
+
+impl Display for DiagnosticInfo {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let indented = |text: &str| text.lines().map(|line| format!("    {}", line)).collect::<Vec<_>>().join("\n");
+        writeln!(f, "{} ({}, {})\n", self.code, self.severity(), self.category)?;
+        writeln!(f, "{}\n", self.summary)?;
+        writeln!(f, "{}\n", self.rationale)?;
+        writeln!(f, "Reported for:\n\n{}\n", indented(self.failing))?;
+        writeln!(f, "Not reported for:\n\n{}\n", indented(self.passing))?;
+        writeln!(f, "To fix it: {}", self.remediation)
+    }
+}
+
+/// The explanations of the codes validation reports, other than lint findings, in the
+/// order of `CODES`
+pub const EXPLANATIONS: &[DiagnosticInfo] = &[
+    DiagnosticInfo {
+        code: "parse-failed",
+        category: DiagnosticCategory::Input,
+        summary: "The input couldn't be parsed, so nothing else about it was checked.",
+        rationale: "Syntax errors and values of the wrong type stop the parse, and every other check needs the parsed model.",
+        failing: "services:\n  web:\n    image: nginx\n    privileged: maybe\n",
+        passing: "services:\n  web:\n    image: nginx\n    privileged: false\n",
+        remediation: "fix the problem the message names. Unknown compose fields also fail the parse unless --lenient is given.",
+    },
+    DiagnosticInfo {
+        code: "unknown-field",
+        category: DiagnosticCategory::Input,
+        summary: "A compose field that isn't in the compose specification was dropped when parsing leniently.",
+        rationale: "An unknown field is usually a misspelt one, whose setting compose would silently ignore too.",
+        failing: "services:\n  web:\n    image: nginx\n    restrat: always\n",
+        passing: "services:\n  web:\n    image: nginx\n    restart: always\n",
+        remediation: "correct the field's name, or prefix it with x- if it's an extension meant for other tools.",
+    },
+    DiagnosticInfo {
+        code: "no-services",
+        category: DiagnosticCategory::Input,
+        summary: "The compose file defines no services.",
+        rationale: "Compose has nothing to run, and there's nothing to model.",
+        failing: "services: {}\n",
+        passing: "services:\n  web:\n    image: nginx\n",
+        remediation: "define at least one service, or check that the right file, or files, were given.",
+    },
+    DiagnosticInfo {
+        code: "unconfigured-service",
+        category: DiagnosticCategory::Services,
+        summary: "A service is named with nothing under it.",
+        rationale: "A service written as `web:` alone is likely unfinished, or its settings were indented under the wrong key.",
+        failing: "services:\n  web:\n",
+        passing: "services:\n  web:\n    image: nginx\n",
+        remediation: "give the service an image or build, and check the indentation of the lines after it.",
+    },
+    DiagnosticInfo {
+        code: "no-image",
+        category: DiagnosticCategory::Services,
+        summary: "A service has neither an image nor a build.",
+        rationale: "Compose can't create a container without an image to run, so `up` fails for the service.",
+        failing: "services:\n  web:\n    restart: always\n",
+        passing: "services:\n  web:\n    image: nginx\n    restart: always\n",
+        remediation: "add an image, or a build naming the context of a Dockerfile.",
+    },
+    DiagnosticInfo {
+        code: "invalid-hostname",
+        category: DiagnosticCategory::Networking,
+        summary: "A service's hostname isn't a valid DNS name.",
+        rationale: "Other containers couldn't resolve the name, and some runtimes refuse to start the container.",
+        failing: "services:\n  web:\n    image: nginx\n    hostname: -web\n",
+        passing: "services:\n  web:\n    image: nginx\n    hostname: web\n",
+        remediation: "use labels of letters, digits and hyphens which neither start nor end with a hyphen.",
+    },
+    DiagnosticInfo {
+        code: "duplicate-hostname",
+        category: DiagnosticCategory::Networking,
+        summary: "Two services use the same hostname.",
+        rationale: "Either could answer to the name, so which one other services reach is left to chance.",
+        failing: "services:\n  web:\n    image: nginx\n    hostname: app\n  api:\n    image: api\n    hostname: app\n",
+        passing: "services:\n  web:\n    image: nginx\n    hostname: web\n  api:\n    image: api\n    hostname: api\n",
+        remediation: "give each service a hostname of its own, or drop the hostnames and use the service names.",
+    },
+    DiagnosticInfo {
+        code: "invalid-restart",
+        category: DiagnosticCategory::Services,
+        summary: "A service's restart policy isn't one compose knows.",
+        rationale: "Compose refuses to create the service.",
+        failing: "services:\n  web:\n    image: nginx\n    restart: sometimes\n",
+        passing: "services:\n  web:\n    image: nginx\n    restart: on-failure\n",
+        remediation: "use no, always, on-failure or unless-stopped, limiting retries with deploy.restart_policy.max_attempts.",
+    },
+    DiagnosticInfo {
+        code: "unquoted-restart",
+        category: DiagnosticCategory::Services,
+        summary: "A service's restart policy is written as a boolean, which is taken as the policy meant.",
+        rationale: "YAML 1.1 reads an unquoted `restart: no` as false, and yes or on as true, which compose and other tools reject as restart policies.",
+        failing: "services:\n  web:\n    image: nginx\n    restart: false\n",
+        passing: "services:\n  web:\n    image: nginx\n    restart: \"no\"\n",
+        remediation: "quote the policy, as in restart: \"no\".",
+    },
+    DiagnosticInfo {
+        code: "invalid-watch",
+        category: DiagnosticCategory::Services,
+        summary: "A develop.watch rule has an unknown action, or a sync without a target.",
+        rationale: "`docker compose watch` refuses to start with the rule.",
+        failing: "services:\n  web:\n    build: .\n    develop:\n      watch:\n        - path: ./src\n          action: copy\n          target: /app/src\n",
+        passing: "services:\n  web:\n    build: .\n    develop:\n      watch:\n        - path: ./src\n          action: sync\n          target: /app/src\n",
+        remediation: "use sync, rebuild, restart, sync+restart or sync+exec, giving syncs the container path to sync to.",
+    },
+    DiagnosticInfo {
+        code: "invalid-stop-signal",
+        category: DiagnosticCategory::Services,
+        summary: "A service's stop_signal isn't a known signal.",
+        rationale: "Compose can't stop the container with it, so stopping the service fails or falls back to killing it.",
+        failing: "services:\n  web:\n    image: nginx\n    stop_signal: SIGSTOPIT\n",
+        passing: "services:\n  web:\n    image: nginx\n    stop_signal: SIGQUIT\n",
+        remediation: "name a POSIX signal, such as SIGTERM, or give its number.",
+    },
+    DiagnosticInfo {
+        code: "home-relative-volume",
+        category: DiagnosticCategory::Volumes,
+        summary: "A bind mount's host path starts with ~.",
+        rationale: "The path depends on the home directory of whoever runs compose, so the stack behaves differently for each user and in CI.",
+        failing: "services:\n  web:\n    image: nginx\n    volumes: [\"~/site:/usr/share/nginx/html\"]\n",
+        passing: "services:\n  web:\n    image: nginx\n    volumes: [\"./site:/usr/share/nginx/html\"]\n",
+        remediation: "use a path relative to the compose file, or a variable such as ${SITE_DIR}.",
+    },
+    DiagnosticInfo {
+        code: "invalid-volume",
+        category: DiagnosticCategory::Volumes,
+        summary: "A volume can't be parsed, or is mounted at a relative path.",
+        rationale: "Containers can only mount volumes at absolute paths, so the container fails to start.",
+        failing: "services:\n  web:\n    image: nginx\n    volumes: [\"./site:html\"]\n",
+        passing: "services:\n  web:\n    image: nginx\n    volumes: [\"./site:/usr/share/nginx/html\"]\n",
+        remediation: "mount the volume at an absolute path in the container.",
+    },
+    DiagnosticInfo {
+        code: "unknown-volume",
+        category: DiagnosticCategory::Volumes,
+        summary: "A service mounts a named volume the compose file doesn't define.",
+        rationale: "Compose refuses to create the service.",
+        failing: "services:\n  db:\n    image: postgres\n    volumes: [\"data:/var/lib/postgresql/data\"]\n",
+        passing: "services:\n  db:\n    image: postgres\n    volumes: [\"data:/var/lib/postgresql/data\"]\nvolumes:\n  data: {}\n",
+        remediation: "define the volume under the top-level volumes, correct the name, or mount a host path starting with ./ instead.",
+    },
+    DiagnosticInfo {
+        code: "duplicate-volume-target",
+        category: DiagnosticCategory::Volumes,
+        summary: "Two volumes of a service are mounted at the same path.",
+        rationale: "Only one of them can be visible there, and Docker refuses to create the container.",
+        failing: "services:\n  db:\n    image: postgres\n    volumes: [\"data:/var/lib/postgresql/data\", \"./backup:/var/lib/postgresql/data\"]\nvolumes:\n  data: {}\n",
+        passing: "services:\n  db:\n    image: postgres\n    volumes: [\"data:/var/lib/postgresql/data\", \"./backup:/backup\"]\nvolumes:\n  data: {}\n",
+        remediation: "mount each volume at a path of its own.",
+    },
+    DiagnosticInfo {
+        code: "nested-volume-target",
+        category: DiagnosticCategory::Volumes,
+        summary: "A volume of a service is mounted inside another of its volumes.",
+        rationale: "What's visible at the inner path depends on the order the volumes are mounted in, which isn't guaranteed.",
+        failing: "services:\n  web:\n    image: nginx\n    volumes: [\"./site:/srv\", \"cache:/srv/cache\"]\nvolumes:\n  cache: {}\n",
+        passing: "services:\n  web:\n    image: nginx\n    volumes: [\"./site:/srv\", \"cache:/var/cache/site\"]\nvolumes:\n  cache: {}\n",
+        remediation: "mount the volumes side by side, or make the inner one part of the outer.",
+    },
+    DiagnosticInfo {
+        code: "file-on-directory-mount",
+        category: DiagnosticCategory::Volumes,
+        summary: "A bind mount's source looks like a file, having an extension, but its target ends with a slash.",
+        rationale: "Mounting a file on a directory fails, or hides what the image put there; the file was likely meant to go in the directory.",
+        failing: "services:\n  web:\n    image: nginx\n    volumes: [\"./nginx.conf:/etc/nginx/conf.d/\"]\n",
+        passing: "services:\n  web:\n    image: nginx\n    volumes: [\"./nginx.conf:/etc/nginx/conf.d/default.conf\"]\n",
+        remediation: "give the file's full path in the container as the target.",
+    },
+    DiagnosticInfo {
+        code: "invalid-healthcheck",
+        category: DiagnosticCategory::Services,
+        summary: "A healthcheck has an empty test or a retries count that isn't positive.",
+        rationale: "Docker rejects the healthcheck, so the container fails to start.",
+        failing: "services:\n  web:\n    image: nginx\n    healthcheck:\n      test: []\n",
+        passing: "services:\n  web:\n    image: nginx\n    healthcheck:\n      test: [CMD, curl, -f, http://localhost]\n",
+        remediation: "give the command to run as the test, or disable the image's healthcheck with test: [NONE].",
+    },
+    DiagnosticInfo {
+        code: "invalid-device-request",
+        category: DiagnosticCategory::Devices,
+        summary: "A request for devices such as GPUs has an invalid count, or gpus isn't all or a list.",
+        rationale: "Docker can't reserve the devices, so the container fails to start.",
+        failing: "services:\n  train:\n    image: pytorch\n    gpus: some\n",
+        passing: "services:\n  train:\n    image: pytorch\n    gpus: all\n",
+        remediation: "request a positive number of devices, all of them, or a list of device IDs.",
+    },
+    DiagnosticInfo {
+        code: "unknown-device-capability",
+        category: DiagnosticCategory::Devices,
+        summary: "A device request names a capability Docker doesn't know.",
+        rationale: "No device would match the request, so the container fails to start on hosts with the devices it needs.",
+        failing: "services:\n  train:\n    image: pytorch\n    deploy:\n      resources:\n        reservations:\n          devices:\n            - capabilities: [gpus]\n",
+        passing: "services:\n  train:\n    image: pytorch\n    deploy:\n      resources:\n        reservations:\n          devices:\n            - capabilities: [gpu]\n",
+        remediation: "use the capabilities Docker knows, such as gpu, compute or utility.",
+    },
+    DiagnosticInfo {
+        code: "device-request-conflict",
+        category: DiagnosticCategory::Devices,
+        summary: "A service requests devices both with gpus and under deploy, and the requests disagree.",
+        rationale: "Docker applies both, so the service gets more devices than either asks for; this is usually a half-finished migration between the two forms.",
+        failing: "services:\n  train:\n    image: pytorch\n    gpus: all\n    deploy:\n      resources:\n        reservations:\n          devices:\n            - count: 1\n              capabilities: [gpu]\n",
+        passing: "services:\n  train:\n    image: pytorch\n    deploy:\n      resources:\n        reservations:\n          devices:\n            - count: 1\n              capabilities: [gpu]\n",
+        remediation: "request the devices in one form only.",
+    },
+    DiagnosticInfo {
+        code: "invalid-scale",
+        category: DiagnosticCategory::Services,
+        summary: "A service is scaled to no instances.",
+        rationale: "Compose creates no container for it, which is better said by leaving the service out or giving it a profile.",
+        failing: "services:\n  worker:\n    image: worker\n    scale: 0\n",
+        passing: "services:\n  worker:\n    image: worker\n    scale: 1\n",
+        remediation: "scale the service to at least one instance, or put it in a profile that isn't started by default.",
+    },
+    DiagnosticInfo {
+        code: "scale-and-replicas",
+        category: DiagnosticCategory::Services,
+        summary: "A service sets both scale and deploy.replicas.",
+        rationale: "Which wins depends on whether it's run with compose or in Swarm mode, so the number of instances differs between them.",
+        failing: "services:\n  worker:\n    image: worker\n    scale: 2\n    deploy:\n      replicas: 3\n",
+        passing: "services:\n  worker:\n    image: worker\n    deploy:\n      replicas: 3\n",
+        remediation: "set the number of instances in one place, preferably deploy.replicas.",
+    },
+    DiagnosticInfo {
+        code: "no-network",
+        category: DiagnosticCategory::Networking,
+        summary: "A service depends on another but shares no network with it.",
+        rationale: "The service can only reach its dependency over a network they're both attached to, where a service listing no networks is on the default network.",
+        failing: "services:\n  web:\n    image: nginx\n    depends_on: [api]\n  api:\n    image: api\n    networks: [backend]\nnetworks:\n  backend: {}\n",
+        passing: "services:\n  web:\n    image: nginx\n    depends_on: [api]\n    networks: [backend]\n  api:\n    image: api\n    networks: [backend]\nnetworks:\n  backend: {}\n",
+        remediation: "attach the service to a network its dependency is on.",
+    },
+    DiagnosticInfo {
+        code: "interactive-replicas",
+        category: DiagnosticCategory::Services,
+        summary: "A service with more than one replica sets stdin_open or tty.",
+        rationale: "Only one replica could be attached to, so interactive flags on scaled services are almost always a mistake.",
+        failing: "services:\n  worker:\n    image: worker\n    tty: true\n    deploy:\n      replicas: 3\n",
+        passing: "services:\n  worker:\n    image: worker\n    deploy:\n      replicas: 3\n",
+        remediation: "drop stdin_open and tty, or run the interactive tool as a service of its own.",
+    },
+    DiagnosticInfo {
+        code: "invalid-port",
+        category: DiagnosticCategory::Networking,
+        summary: "A ports or expose entry of a service isn't a valid port.",
+        rationale: "Compose rejects the file, and the port is left out of the model, so whatever it was meant to reach isn't shown as reachable.",
+        failing: "services:\n  web:\n    image: nginx\n    ports: [\"8080:80/tpc\"]\n",
+        passing: "services:\n  web:\n    image: nginx\n    ports: [\"8080:80/tcp\"]\n",
+        remediation: "write the port as [[host_ip:]host_port:]container_port[/protocol], with a protocol of tcp, udp or sctp.",
+    },
+    DiagnosticInfo {
+        code: "replica-port-conflict",
+        category: DiagnosticCategory::Networking,
+        summary: "A service with more than one replica publishes a fixed host port.",
+        rationale: "Only one replica can bind the port, so the others fail to start.",
+        failing: "services:\n  web:\n    image: nginx\n    ports: [\"8080:80\"]\n    deploy:\n      replicas: 2\n",
+        passing: "services:\n  web:\n    image: nginx\n    ports: [\"80\"]\n    deploy:\n      replicas: 2\n",
+        remediation: "publish the port without a host port, or a range of host ports, or put a load balancer in front of the replicas.",
+    },
+    DiagnosticInfo {
+        code: "unexposed-port",
+        category: DiagnosticCategory::Networking,
+        summary: "A service publishes a port that neither its Dockerfile EXPOSEs nor its expose lists.",
+        rationale: "Nothing is known to listen on the port, which is often a typo, such as 8000 for 8080.",
+        failing: "services:\n  api:\n    image: api\n    expose: [\"8080\"]\n    ports: [\"8000:8000\"]\n",
+        passing: "services:\n  api:\n    image: api\n    expose: [\"8080\"]\n    ports: [\"8000:8080\"]\n",
+        remediation: "publish the port the container listens on, or expose the port if it's right.",
+    },
+    DiagnosticInfo {
+        code: "public-dns",
+        category: DiagnosticCategory::Networking,
+        summary: "A service attached only to internal networks uses a public DNS server.",
+        rationale: "Internal networks have no route out, so the server can't be reached and name lookups time out.",
+        failing: "services:\n  api:\n    image: api\n    dns: 8.8.8.8\n    networks: [private]\nnetworks:\n  private:\n    internal: true\n",
+        passing: "services:\n  api:\n    image: api\n    dns: 10.0.0.2\n    networks: [private]\nnetworks:\n  private:\n    internal: true\n",
+        remediation: "use a resolver on the internal network, or drop dns to use Docker's embedded resolver.",
+    },
+    DiagnosticInfo {
+        code: "unknown-network",
+        category: DiagnosticCategory::Networking,
+        summary: "A service is attached to a network the compose file doesn't define.",
+        rationale: "Compose refuses to create the service.",
+        failing: "services:\n  api:\n    image: api\n    networks: [backend]\n",
+        passing: "services:\n  api:\n    image: api\n    networks: [backend]\nnetworks:\n  backend: {}\n",
+        remediation: "define the network under the top-level networks, or correct the name.",
+    },
+    DiagnosticInfo {
+        code: "unknown-dependency",
+        category: DiagnosticCategory::Dependencies,
+        summary: "A service depends on a service the compose file doesn't define.",
+        rationale: "Compose refuses to start the service.",
+        failing: "services:\n  web:\n    image: nginx\n    depends_on: [api]\n",
+        passing: "services:\n  web:\n    image: nginx\n    depends_on: [api]\n  api:\n    image: api\n",
+        remediation: "define the service, correct the name, or include the file that defines it.",
+    },
+    DiagnosticInfo {
+        code: "unhealthy-dependency",
+        category: DiagnosticCategory::Dependencies,
+        summary: "A service waits for a dependency to be healthy, but the dependency has no healthcheck.",
+        rationale: "Unless the image defines a healthcheck, the dependency never becomes healthy and the service never starts.",
+        failing: "services:\n  api:\n    image: api\n    depends_on:\n      db:\n        condition: service_healthy\n  db:\n    image: postgres\n",
+        passing: "services:\n  api:\n    image: api\n    depends_on:\n      db:\n        condition: service_healthy\n  db:\n    image: postgres\n    healthcheck:\n      test: [CMD, pg_isready]\n",
+        remediation: "give the dependency a healthcheck, or wait for service_started instead.",
+    },
+    DiagnosticInfo {
+        code: "dependency-cycle",
+        category: DiagnosticCategory::Dependencies,
+        summary: "Services depend on each other in a cycle.",
+        rationale: "None of them can start first, so compose refuses to start any.",
+        failing: "services:\n  api:\n    image: api\n    depends_on: [db]\n  db:\n    image: postgres\n    depends_on: [api]\n",
+        passing: "services:\n  api:\n    image: api\n    depends_on: [db]\n  db:\n    image: postgres\n",
+        remediation: "remove a dependency from the cycle, having the service retry its connections instead.",
+    },
+    DiagnosticInfo {
+        code: "profile-auto-activated",
+        category: DiagnosticCategory::Dependencies,
+        summary: "A service outside the active profiles is started anyway, as an active service requires it. Reported when validating with --profile, as --profile debug for these examples.",
+        rationale: "Compose starts required dependencies whatever their profiles, so the service's profiles don't keep it out as they seem to.",
+        failing: "services:\n  web:\n    image: web\n    profiles: [debug]\n    depends_on: [db]\n  db:\n    image: postgres\n    profiles: [data]\n",
+        passing: "services:\n  web:\n    image: web\n    profiles: [debug]\n    depends_on: [db]\n  db:\n    image: postgres\n    profiles: [data, debug]\n",
+        remediation: "add the active profile to the dependency's, or make the dependency optional with required: false.",
+    },
+    DiagnosticInfo {
+        code: "inactive-optional-dependency",
+        category: DiagnosticCategory::Dependencies,
+        summary: "An optional dependency of an active service isn't started, as its profiles are inactive. Reported when validating with --profile, as --profile debug for these examples.",
+        rationale: "The service starts without it, so whatever it relies on the dependency for isn't there.",
+        failing: "services:\n  web:\n    image: web\n    profiles: [debug]\n    depends_on:\n      metrics:\n        condition: service_started\n        required: false\n  metrics:\n    image: prometheus\n    profiles: [monitoring]\n",
+        passing: "services:\n  web:\n    image: web\n    profiles: [debug]\n    depends_on:\n      metrics:\n        condition: service_started\n        required: false\n  metrics:\n    image: prometheus\n    profiles: [monitoring, debug]\n",
+        remediation: "activate one of the dependency's profiles too, or drop the dependency if the service can do without it.",
+    },
+    DiagnosticInfo {
+        code: "subnet-overlap",
+        category: DiagnosticCategory::Networking,
+        summary: "Two networks have overlapping subnets.",
+        rationale: "Docker can't route between the networks, and refuses to create the second.",
+        failing: "services:\n  api:\n    image: api\nnetworks:\n  front:\n    ipam:\n      config:\n        - subnet: 172.20.0.0/16\n  back:\n    ipam:\n      config:\n        - subnet: 172.20.1.0/24\n",
+        passing: "services:\n  api:\n    image: api\nnetworks:\n  front:\n    ipam:\n      config:\n        - subnet: 172.20.0.0/16\n  back:\n    ipam:\n      config:\n        - subnet: 172.21.0.0/24\n",
+        remediation: "give each network a subnet of its own.",
+    },
+    DiagnosticInfo {
+        code: "ambiguous-name",
+        category: DiagnosticCategory::Networking,
+        summary: "A name on a network is claimed by more than one service, as a service name, hostname, container name or alias.",
+        rationale: "Docker's DNS resolves the name to any of the services, so connections reach one at random.",
+        failing: "services:\n  api:\n    image: api\n  web:\n    image: nginx\n    hostname: api\n",
+        passing: "services:\n  api:\n    image: api\n  web:\n    image: nginx\n    hostname: web\n",
+        remediation: "give each service names of its own on each network.",
+    },
+    DiagnosticInfo {
+        code: "image-not-found",
+        category: DiagnosticCategory::Images,
+        summary: "A service's image wasn't found in its registry when checking images.",
+        rationale: "Compose would fail to pull it.",
+        failing: "services:\n  web:\n    image: localhost:5000/web:1.2-typo\n",
+        passing: "services:\n  web:\n    image: localhost:5000/web:1.2\n",
+        remediation: "correct the image's name or tag, or push the image.",
+    },
+    DiagnosticInfo {
+        code: "image-unchecked",
+        category: DiagnosticCategory::Images,
+        summary: "A service's image couldn't be checked against its registry.",
+        rationale: "Whether it can be pulled is unknown, as the reference is malformed, or the registry is unreachable, refused anonymous access or answered with an error.",
+        failing: "services:\n  web:\n    image: registry.example.com/web:1.2\n",
+        passing: "services:\n  web:\n    image: localhost:5000/web:1.2\n",
+        remediation: "fix the image reference, or start the registry or raise --image-timeout; private images can't be checked.",
+    },
+    DiagnosticInfo {
+        code: "shared-volume",
+        category: DiagnosticCategory::Volumes,
+        summary: "A volume of a pod is mounted by more than one of its containers.",
+        rationale: "This is how the containers of a pod share files, so it's noted as part of the pod's design rather than reported as a problem.",
+        failing: "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: nginx\n      volumeMounts: [{name: site, mountPath: /usr/share/nginx/html}]\n    - name: sync\n      volumeMounts: [{name: site, mountPath: /site}]\n  volumes:\n    - name: site\n      emptyDir: {}\n",
+        passing: "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: nginx\n      volumeMounts: [{name: site, mountPath: /usr/share/nginx/html}]\n  volumes:\n    - name: site\n      emptyDir: {}\n",
+        remediation: "nothing, unless the volume was meant to be private to one container.",
+    },
+    DiagnosticInfo {
+        code: "duplicate-container-port",
+        category: DiagnosticCategory::Networking,
+        summary: "Two containers of a pod listen on the same port.",
+        rationale: "The containers of a pod share a network namespace, so only one of them can bind the port.",
+        failing: "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: nginx\n      ports: [{containerPort: 8080}]\n    - name: metrics\n      ports: [{containerPort: 8080}]\n",
+        passing: "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: nginx\n      ports: [{containerPort: 8080}]\n    - name: metrics\n      ports: [{containerPort: 9100}]\n",
+        remediation: "have each container listen on a port of its own.",
+    },
+    DiagnosticInfo {
+        code: "duplicate-host-port",
+        category: DiagnosticCategory::Networking,
+        summary: "Two containers of a pod claim the same host port.",
+        rationale: "Only one of them can bind it on the node, so the pod can't be scheduled.",
+        failing: "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: nginx\n      ports: [{containerPort: 80, hostPort: 8080}]\n    - name: admin\n      ports: [{containerPort: 81, hostPort: 8080}]\n",
+        passing: "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: nginx\n      ports: [{containerPort: 80, hostPort: 8080}]\n    - name: admin\n      ports: [{containerPort: 81, hostPort: 8081}]\n",
+        remediation: "give each container a host port of its own, or none and use a Service.",
+    },
+    DiagnosticInfo {
+        code: "unresolved-ingress-backend",
+        category: DiagnosticCategory::Networking,
+        summary: "A route of an Ingress reaches no container: its Service isn't defined, hasn't the port, selects no pods, or no container listens on its target port.",
+        rationale: "Traffic routed there from outside the cluster gets no answer, which is usually a Service or port misnamed, or a Service whose selector doesn't match its pods' labels.",
+        failing: "kind: Ingress\nmetadata:\n  name: shop\nspec:\n  defaultBackend:\n    service:\n      name: web\n      port: {number: 80}\n",
+        passing: "kind: Ingress\nmetadata:\n  name: shop\nspec:\n  defaultBackend:\n    service:\n      name: web\n      port: {number: 80}\n---\nkind: Service\nmetadata:\n  name: web\nspec:\n  selector: {app: web}\n  ports: [{port: 80, targetPort: 8080}]\n---\nkind: Pod\nmetadata:\n  name: web\n  labels: {app: web}\nspec:\n  containers:\n    - name: nginx\n      ports: [{containerPort: 8080}]\n",
+        remediation: "check the names and ports of the Ingress backend, the Service's ports and selector, and the labels and ports of the pods.",
+    },
+    DiagnosticInfo {
+        code: "denied-ingress-route",
+        category: DiagnosticCategory::Networking,
+        summary: "A route of an Ingress reaches only pods whose NetworkPolicies deny traffic from outside the cluster.",
+        rationale: "The route looks open but every request through it is dropped. Traffic arrives through the ingress controller, from another namespace, so only rules allowing any source, a namespaceSelector or an ipBlock are taken to let it in.",
+        failing: "kind: Ingress\nmetadata:\n  name: shop\nspec:\n  defaultBackend:\n    service:\n      name: web\n      port: {number: 80}\n---\nkind: Service\nmetadata:\n  name: web\nspec:\n  selector: {app: web}\n  ports: [{port: 80, targetPort: 8080}]\n---\nkind: Pod\nmetadata:\n  name: web\n  labels: {app: web}\nspec:\n  containers:\n    - name: nginx\n      ports: [{containerPort: 8080}]\n---\nkind: NetworkPolicy\nmetadata:\n  name: default-deny\nspec:\n  podSelector: {}\n",
+        passing: "kind: Ingress\nmetadata:\n  name: shop\nspec:\n  defaultBackend:\n    service:\n      name: web\n      port: {number: 80}\n---\nkind: Service\nmetadata:\n  name: web\nspec:\n  selector: {app: web}\n  ports: [{port: 80, targetPort: 8080}]\n---\nkind: Pod\nmetadata:\n  name: web\n  labels: {app: web}\nspec:\n  containers:\n    - name: nginx\n      ports: [{containerPort: 8080}]\n---\nkind: NetworkPolicy\nmetadata:\n  name: default-deny\nspec:\n  podSelector: {}\n---\nkind: NetworkPolicy\nmetadata:\n  name: allow-web\nspec:\n  podSelector:\n    matchLabels: {app: web}\n  ingress:\n    - from: [{namespaceSelector: {}}]\n      ports: [{port: 8080}]\n",
+        remediation: "add a NetworkPolicy allowing traffic from the ingress controller's namespace to the pods' port.",
+    },
+    DiagnosticInfo {
+        code: "unmodelled-object",
+        category: DiagnosticCategory::Input,
+        summary: "Kubernetes manifests hold an object of a kind that isn't modelled, such as a CronJob or ConfigMap.",
+        rationale: "It's left out of the model, so pods it runs aren't there for Services to select or Ingress routes to reach.",
+        failing: "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers: [{name: nginx}]\n---\nkind: CronJob\nmetadata:\n  name: report\nspec: {}\n",
+        passing: "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers: [{name: nginx}]\n",
+        remediation: "run the pods from a Pod or a Deployment, StatefulSet, DaemonSet, ReplicaSet or Job, or ignore the warning if the object runs no pods.",
+    },
+    DiagnosticInfo {
+        code: "unavailable",
+        category: DiagnosticCategory::Input,
+        summary: "Something asked for can't be done where cargotecture is running, such as following builds without a filesystem.",
+        rationale: "The output was generated without it, so it's less complete than asked for.",
+        failing: "services:\n  web:\n    build: .\n",
+        passing: "services:\n  web:\n    image: nginx\n",
+        remediation: "run cargotecture where the files it needs can be read, or don't ask for what needs them.",
+    },
+];
+
+/// The explanations of every code a problem may be reported with, lint rules included
+pub fn explanations() -> impl Iterator<Item = &'static DiagnosticInfo> {
+    EXPLANATIONS.iter().chain(LINT_EXPLANATIONS)
+}
+
+/// The explanation of a code, or an error listing the codes it might be a misspelling of
+pub fn explain(code: &str) -> Result<&'static DiagnosticInfo, CargotectureError> {
+    if let Some(info) = explanations().find(|info| info.code == code) {
+        return Ok(info);
+    }
+    let mut similar: Vec<(usize, &str)> = explanations()
+        .map(|info| (edit_distance(code, info.code), info.code))
+        .filter(|(distance, known)| *distance <= (code.len() / 3).max(2) || (code.len() > 2 && known.contains(code)))
+        .collect();
+    similar.sort();
+    Err(CargotectureError::UnknownCode { code: code.to_string(), similar: similar.into_iter().take(5).map(|(_, known)| known.to_string()).collect() })
+}
+
+/// Every code a problem may be reported with and its default severity, lint rules included
+pub fn all_codes() -> Vec<(&'static str, Severity)> {
+    // Every lint rule currently reports warnings
+    CODES.iter().copied().chain(RULES.iter().map(|rule| (*rule, Severity::Warning))).collect()
+}
+
+/// How a problem is treated, as set with `--allow` and `--deny`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Not reported
+    Allow,
+    /// Reported as an error
+    Deny,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Level::Allow => write!(f, "allow"),
+            Level::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// Where in the inputs a value was written
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SourceLocation {
+    /// The file, when the input was read with its name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// The document of a multi-document YAML file, counting from 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<usize>,
+    /// The line, counting from 1, when it could be found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.file.as_deref().unwrap_or("<input>"))?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(document) = self.document {
+            write!(f, " (document {})", document)?;
+        }
+        Ok(())
+    }
+}
+
+/// Describe where a value was defined and then overridden, as in
+/// `defined in base.yml:14, overridden in prod.yml:9`
+pub fn describe_locations(locations: &[SourceLocation]) -> String {
+    let mut described: Vec<String> = Vec::new();
+    for (index, location) in locations.iter().enumerate() {
+        described.push(format!("{} {}", if index == 0 { "defined in" } else { "overridden in" }, location));
+    }
+    described.join(", ")
+}
+
+/// A problem which makes a configuration invalid
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationError {
+    pub service: Option<String>,
+    /// What kind of problem it is, such as `unknown-network`, by which its level can be set
+    pub code: &'static str,
+    pub message: String,
+    /// The path to the value at fault, such as `services.web.healthcheck.interval`, by
+    /// which it's located in the files merged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Where the value at fault was defined, then each file which overrode it, in the order
+    /// the files were merged
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<SourceLocation>,
+}
+
+/// A likely mistake which doesn't stop the configuration being used
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationWarning {
+    pub service: Option<String>,
+    /// What kind of problem it is, such as `no-network`, by which its level can be set
+    pub code: &'static str,
+    pub message: String,
+    /// The path to the value at fault, as for errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Where the value at fault was defined and overridden, as for errors
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<SourceLocation>,
+}
+
+/// Something worth knowing about a configuration which isn't a problem
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationNote {
+    pub service: Option<String>,
+    /// What kind of note it is, such as `shared-volume`, by which its level can be set
+    pub code: &'static str,
+    pub message: String,
+    /// The path to the value noted, as for errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Where the value noted was defined and overridden, as for errors
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<SourceLocation>,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Display for ValidationWarning {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Display for ValidationNote {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// All the errors, warnings and notes found while validating a configuration
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ValidationReport {
+    pub errors: Vec<ValidationError>,
+    pub warnings: Vec<ValidationWarning>,
+    pub notes: Vec<ValidationNote>,
+}
+
+impl ValidationReport {
+    pub fn error(&mut self, service: Option<&str>, code: &'static str, message: String) {
+        self.errors.push(ValidationError { service: service.map(str::to_string), code, message, field: None, locations: Vec::new() });
+    }
+
+    pub fn warning(&mut self, service: Option<&str>, code: &'static str, message: String) {
+        self.warnings.push(ValidationWarning { service: service.map(str::to_string), code, message, field: None, locations: Vec::new() });
+    }
+
+    pub fn note(&mut self, service: Option<&str>, code: &'static str, message: String) {
+        self.notes.push(ValidationNote { service: service.map(str::to_string), code, message, field: None, locations: Vec::new() });
+    }
+
+    /// Add an error about the value at a path, such as `services.web.restart`
+    pub fn error_at(&mut self, service: Option<&str>, field: String, code: &'static str, message: String) {
+        self.error(service, code, message);
+        self.errors.last_mut().unwrap().field = Some(field);
+    }
+
+    /// Add a warning about the value at a path, as for errors
+    pub fn warning_at(&mut self, service: Option<&str>, field: String, code: &'static str, message: String) {
+        self.warning(service, code, message);
+        self.warnings.last_mut().unwrap().field = Some(field);
+    }
+
+    /// Add a note about the value at a path, as for errors
+    pub fn note_at(&mut self, service: Option<&str>, field: String, code: &'static str, message: String) {
+        self.note(service, code, message);
+        self.notes.last_mut().unwrap().field = Some(field);
+    }
+
+    /// True when no errors were found. Warnings and notes don't affect validity.
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// The errors, warnings and notes as printed, errors first
+    pub fn diagnostics(&self) -> Vec<String> {
+        let errors = self.errors.iter().map(|error| format!("error[{}]: {}", error.code, error));
+        let warnings = self.warnings.iter().map(|warning| format!("warning[{}]: {}", warning.code, warning));
+        errors.chain(warnings).chain(self.notes.iter().map(|note| format!("note[{}]: {}", note.code, note))).collect()
+    }
+
+    /// The errors, warnings and notes as `diagnostics` gives them, each followed by where
+    /// its value was defined and overridden when that's known
+    pub fn located_diagnostics(&self) -> Vec<String> {
+        let errors = self.errors.iter().map(|e| &e.locations);
+        let locations = errors.chain(self.warnings.iter().map(|w| &w.locations)).chain(self.notes.iter().map(|n| &n.locations));
+        self.diagnostics()
+            .into_iter()
+            .zip(locations)
+            .map(|(diagnostic, locations)| match locations.is_empty() {
+                true => diagnostic,
+                false => format!("{}\n  --> {}", diagnostic, describe_locations(locations)),
+            })
+            .collect()
+    }
+
+    /// Set where each problem was found, from its service and the path to the value at
+    /// fault, for those whose location isn't already known
+    pub fn locate(&mut self, locate: impl Fn(Option<&str>, Option<&str>) -> Vec<SourceLocation>) {
+        let errors = self.errors.iter_mut().map(|e| (e.service.as_deref(), e.field.as_deref(), &mut e.locations));
+        let warnings = self.warnings.iter_mut().map(|w| (w.service.as_deref(), w.field.as_deref(), &mut w.locations));
+        let notes = self.notes.iter_mut().map(|n| (n.service.as_deref(), n.field.as_deref(), &mut n.locations));
+        for (service, field, locations) in errors.chain(warnings).chain(notes) {
+            if locations.is_empty() {
+                *locations = locate(service, field);
+            }
+        }
+    }
+
+    /// Name the file every problem was found in, for a report of a single input read
+    /// without its name
+    pub fn in_file(&mut self, file: &str) {
+        self.locate(|_, _| vec![SourceLocation::default()]);
+        let errors = self.errors.iter_mut().flat_map(|e| e.locations.iter_mut());
+        let warnings = self.warnings.iter_mut().flat_map(|w| w.locations.iter_mut());
+        for location in errors.chain(warnings).chain(self.notes.iter_mut().flat_map(|n| n.locations.iter_mut())) {
+            location.file.get_or_insert_with(|| file.to_string());
+        }
+    }
+
+    /// Print errors, warnings and notes to stderr
+    pub fn print(&self) {
+        for diagnostic in self.diagnostics() {
+            eprintln!("{}", diagnostic);
+        }
+    }
+
+    /// Print errors, warnings and notes to stderr with where each was found
+    pub fn print_located(&self) {
+        for diagnostic in self.located_diagnostics() {
+            eprintln!("{}", diagnostic);
+        }
+    }
+}
+
+/// Options for validating a configuration without generating anything from it
+#[derive(Debug, Clone, Default)]
+pub struct ValidateOptions {
+    /// How compose files are parsed, including whether unknown fields are dropped with a
+    /// warning rather than failing the parse
+    #[cfg(feature = "compose")]
+    pub parse: ParseOptions,
+    /// The profiles active, with which the services auto-activated and the optional
+    /// dependencies left out are reported. Empty to report neither.
+    #[cfg(feature = "compose")]
+    pub profiles: Vec<String>,
+    /// Treat warnings as errors
+    pub strict: bool,
+    /// Lint containerfiles, reporting findings with the lint rule's severity
+    pub lint: bool,
+    /// The levels of the problems whose codes match each glob, such as `invalid-*`, in the
+    /// order given. The last glob matching a code sets its level, after `strict`.
+    pub levels: Vec<(String, Level)>,
+}
+
+impl ValidateOptions {
+    /// The level set for a code, if any
+    pub fn level(&self, code: &str) -> Option<Level> {
+        let set = self.levels.iter().rev().find(|(pattern, _)| glob_match(pattern, code)).map(|(_, level)| *level);
+        set.or(self.strict.then_some(Level::Deny).filter(|_| all_codes().contains(&(code, Severity::Warning))))
+    }
+
+    /// The severity every code is reported with, or None when it isn't, sorted by code
+    pub fn effective_levels(&self) -> Vec<(&'static str, Option<Severity>)> {
+        let mut levels: Vec<(&str, Option<Severity>)> = all_codes()
+            .into_iter()
+            .map(|(code, severity)| match self.level(code) {
+                Some(Level::Allow) => (code, None),
+                Some(Level::Deny) => (code, Some(Severity::Error)),
+                None => (code, Some(severity)),
+            })
+            .collect();
+        levels.sort_by_key(|(code, _)| *code);
+        levels
+    }
+}
+
+impl ValidationReport {
+    /// Drop the problems allowed and make those denied errors, including warnings when
+    /// validating strictly
+    fn apply_levels(mut self, options: &ValidateOptions) -> Self {
+        let (denied, warnings) = std::mem::take(&mut self.warnings).into_iter().partition(|w| options.level(w.code) == Some(Level::Deny));
+        self.warnings = warnings;
+        self.warnings.retain(|w| options.level(w.code) != Some(Level::Allow));
+        let (denied_notes, notes) = std::mem::take(&mut self.notes).into_iter().partition(|n| options.level(n.code) == Some(Level::Deny));
+        self.notes = notes;
+        self.notes.retain(|n| options.level(n.code) != Some(Level::Allow));
+        self.errors.retain(|e| options.level(e.code) != Some(Level::Allow));
+        self.errors.extend(denied.into_iter().map(|w: ValidationWarning| ValidationError { service: w.service, code: w.code, message: w.message, field: w.field, locations: w.locations }));
+        self.errors.extend(denied_notes.into_iter().map(|n: ValidationNote| ValidationError { service: n.service, code: n.code, message: n.message, field: n.field, locations: n.locations }));
+        self
+    }
+}
+
+/// Validate one or more compose files, later files merged over earlier ones as when
+/// parsing. Failing to parse them is reported as an error; only failing to read them is
+/// an Err.
+#[cfg(feature = "compose")]
+pub fn validate_compose_readers(readers: Vec<Box<dyn Read>>, options: &ValidateOptions) -> anyhow::Result<ValidationReport> {
+    let mut contents = Vec::new();
+    for mut reader in readers {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        contents.push(Box::new(Cursor::new(content)) as Box<dyn Read>);
+    }
+    let report = match parse_composefiles_unvalidated(contents, &options.parse) {
+        Ok(compose) => {
+            let mut report = compose.validate();
+            if !options.profiles.is_empty() {
+                let mut activation = compose.activate_profiles(&options.profiles, true)?.report();
+                compose.locate_diagnostics(&mut activation);
+                report.warnings.extend(activation.warnings);
+                report.notes.extend(activation.notes);
+            }
+            report
+        }
+        Err(err) => {
+            let mut report = ValidationReport::default();
+            report.error(None, "parse-failed", format!("Parse failed: {}", err));
+            report
+        }
+    };
+    Ok(report.apply_levels(options))
+}
+
+/// Validate a compose file
+#[cfg(feature = "compose")]
+pub fn validate_compose_reader(reader: Box<dyn Read>, options: &ValidateOptions) -> anyhow::Result<ValidationReport> {
+    validate_compose_readers(vec![reader], options)
+}
+
+/// Validate Kubernetes manifests, of a pod or of several objects. Failing to parse them is
+/// reported as an error; only failing to read them is an Err.
+#[cfg(feature = "pod")]
+pub fn validate_pod_reader(mut reader: Box<dyn Read>, options: &ValidateOptions) -> anyhow::Result<ValidationReport> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    let report = match parse_manifests(Box::new(Cursor::new(content))) {
+        Ok(manifests) => manifests.validate(),
+        Err(err) => {
+            let mut report = ValidationReport::default();
+            report.error(None, "parse-failed", format!("Parse failed: {}", err));
+            report
+        }
+    };
+    Ok(report.apply_levels(options))
+}
+
+/// Validate a containerfile, linting it when the options say to. Failing to parse it is
+/// reported as an error; only failing to read it is an Err.
+pub fn validate_containerfile_reader(mut reader: Box<dyn Read>, options: &ValidateOptions) -> anyhow::Result<ValidationReport> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    let mut report = ValidationReport::default();
+    if let Err(err) = parse_containerfile(Box::new(Cursor::new(content.clone())), "Containerfile") {
+        report.error(None, "parse-failed", format!("Parse failed: {}", err));
+        return Ok(report.apply_levels(options));
+    }
+    if options.lint {
+        for finding in lint_containerfile(&content, &[])? {
+            let message = format!("line {}: {}", finding.line, finding.message);
+            match finding.severity {
+                Severity::Error => report.error(None, finding.rule, message),
+                Severity::Warning => report.warning(None, finding.rule, message),
+                Severity::Note => report.note(None, finding.rule, message),
+            }
+        }
+    }
+    Ok(report.apply_levels(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_validity() {
+        let mut report = ValidationReport::default();
+        assert!(report.is_valid());
+        report.warning(Some("web"), "no-network", "a warning".to_string());
+        assert!(report.is_valid());
+        report.error(None, "no-services", "an error".to_string());
+        assert!(!report.is_valid());
+        assert_eq!(report.errors[0].to_string(), "an error");
+        assert_eq!(report.warnings[0].service.as_deref(), Some("web"));
+        assert_eq!(report.diagnostics(), vec!["error[no-services]: an error", "warning[no-network]: a warning"]);
+    }
+
+    #[cfg(feature = "compose")]
+    fn compose_report(yaml: &str, options: &ValidateOptions) -> ValidationReport {
+        validate_compose_reader(Box::new(Cursor::new(yaml.to_string())), options).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "compose")]
+    fn test_validate_compose_reader() {
+        let options = ValidateOptions::default();
+        let report = compose_report("services:\n  web:\n    image: nginx\n    depends_on: [db]\n", &options);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].message, "Referenced service 'db' in depends_on not found for service 'web'");
+
+        let report = compose_report("services:\n  web:\n    image: [\n", &options);
+        assert!(report.errors[0].message.starts_with("Parse failed: "));
+
+        // Unknown fields fail the parse unless parsing leniently
+        let yaml = "services:\n  web:\n    image: nginx\n    colour: blue\n";
+        assert!(compose_report(yaml, &options).errors[0].message.contains("unknown field `colour`"));
+        let lenient = ValidateOptions { parse: ParseOptions { lenient: true, ..Default::default() }, ..Default::default() };
+        let report = compose_report(yaml, &lenient);
+        assert!(report.is_valid());
+        assert_eq!(report.warnings[0].message, "Unknown field 'services.web.colour' was ignored");
+        assert_eq!(report.warnings[0].service.as_deref(), Some("web"));
+
+        // Strict validation fails on warnings
+        let strict = ValidateOptions { strict: true, ..lenient };
+        let report = compose_report(yaml, &strict);
+        assert!(!report.is_valid());
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "compose")]
+    fn test_validate_overridden_value_located() {
+        let base = "services:\n  web:\n    image: nginx\n    restart: always\n    networks: [front]\nnetworks:\n  front: {}\n";
+        let prod = "# Production overrides\nservices:\n  web:\n    restart: sometimes\n";
+        let readers: Vec<Box<dyn Read>> = vec![Box::new(Cursor::new(base)), Box::new(Cursor::new(prod))];
+        let options = ValidateOptions { parse: ParseOptions { file_names: vec!["base.yml".to_string(), "prod.yml".to_string()], ..Default::default() }, ..Default::default() };
+        let report = validate_compose_readers(readers, &options).unwrap();
+        assert_eq!(report.errors.iter().map(|e| e.code).collect::<Vec<_>>(), vec!["invalid-restart"]);
+        let location = |file: &str, line| SourceLocation { file: Some(file.to_string()), document: None, line: Some(line) };
+        assert_eq!(report.errors[0].locations, vec![location("base.yml", 4), location("prod.yml", 4)]);
+        assert_eq!(
+            report.located_diagnostics(),
+            vec!["error[invalid-restart]: Invalid restart value 'sometimes' for service 'web'\n  --> defined in base.yml:4, overridden in prod.yml:4"]
+        );
+
+        // A problem with a field the service doesn't set is located at the service
+        let report = compose_report("services:\n  web:\n    hostname: web\n", &ValidateOptions::default());
+        assert_eq!(report.errors[0].code, "no-image");
+        assert_eq!(report.errors[0].locations, vec![SourceLocation { line: Some(2), ..Default::default() }]);
+    }
+
+    #[test]
+    #[cfg(feature = "compose")]
+    fn test_validate_located_at_field() {
+        let validate = |base: &str, prod: &str| {
+            let readers: Vec<Box<dyn Read>> = vec![Box::new(Cursor::new(base.to_string())), Box::new(Cursor::new(prod.to_string()))];
+            let file_names = vec!["compose.yaml".to_string(), "compose.prod.yaml".to_string()];
+            let options = ValidateOptions { parse: ParseOptions { file_names, ..Default::default() }, ..Default::default() };
+            validate_compose_readers(readers, &options).unwrap()
+        };
+        let location = |file: &str, line| SourceLocation { file: Some(file.to_string()), document: None, line: Some(line) };
+        let base = "services:\n  web:\n    image: nginx\n    restart: always\n    healthcheck:\n      test: [CMD, 'true']\n      interval: INTERVAL\n";
+
+        // The scalar at fault is located, rather than the block holding it
+        let report = validate(&base.replace("INTERVAL", "10s"), "services:\n  web:\n    healthcheck:\n      interval: 100ms\n");
+        assert_eq!(report.errors.iter().map(|e| e.field.as_deref()).collect::<Vec<_>>(), vec![Some("services.web.healthcheck.interval")]);
+        assert_eq!(report.errors[0].locations, vec![location("compose.yaml", 7), location("compose.prod.yaml", 4)]);
+
+        // An override of a neighbouring field didn't override the value at fault
+        let report = validate(&base.replace("INTERVAL", "100ms"), "services:\n  web:\n    healthcheck:\n      retries: 3\n");
+        assert_eq!(report.errors[0].locations, vec![location("compose.yaml", 7)]);
+
+        // A problem with the service as a whole is located where it was defined
+        let report = validate("services:\n  web:\n    hostname: web\n", "services:\n  web:\n    restart: always\n");
+        assert_eq!(report.errors[0].code, "no-image");
+        assert_eq!(report.located_diagnostics()[0].lines().last(), Some("  --> defined in compose.yaml:2"));
+    }
+
+    #[test]
+    fn test_validate_containerfile_reader() {
+        let content = "FROM alpine\nVOLUME /data\nCOPY seed/ /data/\n";
+        let validate = |options: &ValidateOptions| validate_containerfile_reader(Box::new(Cursor::new(content)), options).unwrap();
+        assert_eq!(validate(&ValidateOptions::default()), ValidationReport::default());
+
+        let lint = ValidateOptions { lint: true, ..Default::default() };
+        let report = validate(&lint);
+        assert!(report.is_valid());
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].code, "volume-shadow");
+        assert!(report.warnings[0].message.starts_with("line 3: COPY writes to /data"));
+
+        let allowed = ValidateOptions { levels: vec![("volume-shadow".to_string(), Level::Allow)], ..lint.clone() };
+        assert!(validate(&allowed).warnings.is_empty());
+        assert!(!validate(&ValidateOptions { strict: true, ..lint }).is_valid());
+    }
+
+    #[test]
+    #[cfg(feature = "compose")]
+    fn test_code_levels() {
+        let yaml = "services:\n  web:\n    image: nginx\n    depends_on: [db]\n    restart: sometimes\n  db:\n    image: postgres\n    networks: [back]\nnetworks:\n  back: {}\n";
+        let levels = |levels: &[(&str, Level)]| ValidateOptions {
+            levels: levels.iter().map(|(pattern, level)| (pattern.to_string(), *level)).collect(),
+            ..Default::default()
+        };
+        let report = compose_report(yaml, &ValidateOptions::default());
+        assert_eq!(report.errors.iter().map(|e| e.code).collect::<Vec<_>>(), vec!["invalid-restart"]);
+        assert_eq!(report.warnings.iter().map(|w| w.code).collect::<Vec<_>>(), vec!["no-network"]);
+
+        // Denying a warning makes it an error, and allowing an error drops it
+        let report = compose_report(yaml, &levels(&[("no-network", Level::Deny), ("invalid-restart", Level::Allow)]));
+        assert_eq!(report.errors.iter().map(|e| e.code).collect::<Vec<_>>(), vec!["no-network"]);
+        assert!(report.warnings.is_empty());
+
+        // Globs match several codes, and the last level given for a code wins
+        let options = levels(&[("invalid-*", Level::Allow), ("no-*", Level::Allow), ("no-network", Level::Deny)]);
+        assert_eq!(compose_report(yaml, &options).errors.iter().map(|e| e.code).collect::<Vec<_>>(), vec!["no-network"]);
+        assert_eq!(options.level("invalid-healthcheck"), Some(Level::Allow));
+        assert_eq!(options.level("no-image"), Some(Level::Allow));
+        assert_eq!(options.level("public-dns"), None);
+
+        // Strict promotes the warnings not otherwise set
+        let options = ValidateOptions { strict: true, ..levels(&[("no-network", Level::Allow)]) };
+        assert!(compose_report(yaml, &options).warnings.is_empty());
+        let effective = options.effective_levels();
+        assert!(effective.contains(&("no-network", None)));
+        assert!(effective.contains(&("public-dns", Some(Severity::Error))));
+        assert!(effective.contains(&("volume-shadow", Some(Severity::Error))));
+    }
+
+    #[test]
+    #[cfg(feature = "pod")]
+    fn test_validate_pod_reader() {
+        let pod = "kind: Pod\nmetadata:\n  name: web\nspec:\n  containers:\n    - name: a\n      volumeMounts: [{name: data, mountPath: /a}]\n    - name: b\n      volumeMounts: [{name: data, mountPath: /b}]\n  volumes:\n    - name: data\n      emptyDir: {}\n";
+        let report = validate_pod_reader(Box::new(Cursor::new(pod)), &ValidateOptions::default()).unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.notes[0].code, "shared-volume");
+
+        // Notes can be denied like any other problem, and aren't affected by strict
+        let strict = ValidateOptions { strict: true, ..Default::default() };
+        assert_eq!(validate_pod_reader(Box::new(Cursor::new(pod)), &strict).unwrap().notes.len(), 1);
+        let denied = ValidateOptions { levels: vec![("shared-volume".to_string(), Level::Deny)], ..Default::default() };
+        let report = validate_pod_reader(Box::new(Cursor::new(pod)), &denied).unwrap();
+        assert!(report.notes.is_empty());
+        assert_eq!(report.errors[0].code, "shared-volume");
+
+        let report = validate_pod_reader(Box::new(Cursor::new("kind: Service\n")), &ValidateOptions::default()).unwrap();
+        assert_eq!(report.errors[0].code, "parse-failed");
+    }
+
+    #[test]
+    fn test_every_code_explained() {
+        for (code, _) in all_codes() {
+            let explained: Vec<&DiagnosticInfo> = explanations().filter(|info| info.code == code).collect();
+            assert_eq!(explained.len(), 1, "{} should be explained once", code);
+            let info = explained[0];
+            for field in [info.summary, info.rationale, info.failing, info.passing, info.remediation] {
+                assert!(!field.trim().is_empty(), "{} has an empty explanation", code);
+            }
+            assert_ne!(info.failing, info.passing, "{}", code);
+        }
+        assert_eq!(explanations().count(), all_codes().len());
+        let info = explain("dependency-cycle").unwrap();
+        assert!(info.to_string().starts_with("dependency-cycle (error, dependencies)\n\nServices depend on each other in a cycle.\n"));
+        assert!(info.to_string().contains("\n    services:\n      api:\n"));
+    }
+
+    #[test]
+    fn test_explain_unknown_code() {
+        assert_eq!(
+            explain("no-netwrok").unwrap_err(),
+            CargotectureError::UnknownCode { code: "no-netwrok".to_string(), similar: vec!["no-network".to_string()] }
+        );
+        assert_eq!(
+            explain("cycle").unwrap_err().to_string(),
+            "Unknown diagnostic code 'cycle', did you mean: dependency-cycle"
+        );
+        assert_eq!(explain("quantum-flux").unwrap_err().to_string(), "Unknown diagnostic code 'quantum-flux'");
+    }
+
+    /// The codes reported for an explanation's example, linting containerfiles and parsing
+    /// compose files leniently, so that unknown fields are reported rather than failing, with
+    /// the profile the examples of profile codes are written for active
+    fn example_codes(example: &str) -> Option<Vec<&'static str>> {
+        let reader = Box::new(Cursor::new(example.to_string()));
+        let report = if example.starts_with("FROM ") {
+            validate_containerfile_reader(reader, &ValidateOptions { lint: true, ..Default::default() }).unwrap()
+        } else if example.starts_with("kind: ") {
+            #[cfg(not(feature = "pod"))]
+            return None;
+            #[cfg(feature = "pod")]
+            validate_pod_reader(reader, &ValidateOptions::default()).unwrap()
+        } else {
+            #[cfg(not(feature = "compose"))]
+            return None;
+            #[cfg(feature = "compose")]
+            validate_compose_reader(
+                reader,
+                &ValidateOptions { parse: ParseOptions { lenient: true, ..Default::default() }, profiles: vec!["debug".to_string()], ..Default::default() },
+            )
+            .unwrap()
+        };
+        let errors = report.errors.iter().map(|error| error.code);
+        let warnings = report.warnings.iter().map(|warning| warning.code);
+        Some(errors.chain(warnings).chain(report.notes.iter().map(|note| note.code)).collect())
+    }
+
+    #[test]
+    fn test_explanation_examples() {
+        // These need a registry, or a mode without files, to be reported
+        let untestable = ["image-not-found", "image-unchecked", "unavailable"];
+        for info in explanations().filter(|info| !untestable.contains(&info.code)) {
+            if let Some(codes) = example_codes(info.failing) {
+                assert!(codes.contains(&info.code), "the failing example of {} reports {:?}", info.code, codes);
+            }
+            if let Some(codes) = example_codes(info.passing) {
+                assert!(!codes.contains(&info.code), "the passing example of {} reports {:?}", info.code, codes);
+            }
+        }
+    }
+}
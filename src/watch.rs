@@ -0,0 +1,107 @@
+//! Watch compose files for changes, re-parsing them and reporting what changed in the model
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::PathBuf,
+};
+use anyhow::{anyhow, Result};
+use crate::{
+    arch_model::{ArchModel, ModelChange},
+    parse_compose::{parse_composefiles_with_options, Compose, ParseOptions},
+};
+
+/// A regeneration of the model after the watched files changed
+pub struct Regeneration {
+    pub compose: Compose,
+    /// Changes from the previously parsed model, empty on the first parse
+    pub changes: Vec<ModelChange>,
+}
+
+/// Polls compose files, caching their contents and the last model parsed from them
+pub struct ComposeWatcher {
+    files: Vec<PathBuf>,
+    options: ParseOptions,
+    contents: Option<Vec<String>>,
+    model: Option<ArchModel>,
+}
+
+impl ComposeWatcher {
+    pub fn new(files: Vec<PathBuf>, options: ParseOptions) -> Self {
+        ComposeWatcher { files, options, contents: None, model: None }
+    }
+
+    /// Re-read the watched files, re-parsing them if they changed since the last poll.
+    /// A parse failure is returned once per change and leaves the cached model as it was,
+    /// so the next successful parse is compared against the last good model.
+    pub fn poll(&mut self) -> Result<Option<Regeneration>> {
+        let contents = self
+            .files
+            .iter()
+            .map(|file| fs::read_to_string(file).map_err(|e| anyhow!("Failed to read {}: {}", file.display(), e)))
+            .collect::<Result<Vec<String>>>()?;
+        if self.contents.as_ref() == Some(&contents) {
+            return Ok(None);
+        }
+        self.contents = Some(contents);
+
+        let readers = self
+            .files
+            .iter()
+            .map(|file| Ok(Box::new(BufReader::new(File::open(file)?)) as Box<dyn Read>))
+            .collect::<Result<Vec<_>>>()?;
+        let compose = parse_composefiles_with_options(readers, &self.options)?;
+        let model = ArchModel::from_compose(&compose);
+        let changes = self.model.as_ref().map_or_else(Vec::new, |previous| previous.diff(&model));
+        self.model = Some(model);
+        Ok(Some(Regeneration { compose, changes }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch_model::change_summary;
+
+    #[test]
+    fn test_poll_reports_each_edit() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("compose.yml");
+        fs::write(&path, "services:\n  kibana:\n    image: kibana:7.16.1\n    ports: [\"5601:5601\"]\n")?;
+        let mut watcher = ComposeWatcher::new(vec![path.clone()], ParseOptions::default());
+
+        let first = watcher.poll()?.expect("the first poll parses the files");
+        assert!(first.changes.is_empty());
+        assert!(watcher.poll()?.is_none());
+
+        fs::write(&path, "services:\n  kibana:\n    image: kibana:7.17.0\n")?;
+        let second = watcher.poll()?.expect("an edit is noticed");
+        assert_eq!(
+            change_summary(&second.changes),
+            "service kibana: image changed kibana:7.16.1 \u{2192} kibana:7.17.0; port 5601 removed\n"
+        );
+
+        fs::write(&path, "services:\n  kibana:\n    image: kibana:7.17.0\n  logstash:\n    image: logstash:7.17.0\n")?;
+        let third = watcher.poll()?.expect("an edit is noticed");
+        assert_eq!(change_summary(&third.changes), "service logstash: added\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_poll_keeps_last_good_model() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("compose.yml");
+        fs::write(&path, "services:\n  web:\n    image: nginx:1.24\n")?;
+        let mut watcher = ComposeWatcher::new(vec![path.clone()], ParseOptions::default());
+        watcher.poll()?;
+
+        fs::write(&path, "services:\n  web: [\n")?;
+        assert!(watcher.poll().is_err());
+        assert!(watcher.poll()?.is_none());
+
+        fs::write(&path, "services:\n  web:\n    image: nginx:1.25\n")?;
+        let changes = watcher.poll()?.expect("an edit is noticed").changes;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].to_string(), "service web: image changed nginx:1.24 \u{2192} nginx:1.25");
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+use tempfile::TempDir;
+
+const BASE: &str = r#"
+services:
+  web:
+    image: web:1.0
+    ports:
+      - "80:80"
+"#;
+
+const OVERRIDE: &str = r#"
+services:
+  web:
+    image: web:2.0
+  db:
+    image: postgres:15
+"#;
+
+const OTHER: &str = r#"
+services:
+  other:
+    image: other:1.0
+"#;
+
+fn fixture_dir() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("base.yml"), BASE).unwrap();
+    fs::write(dir.path().join("override.yml"), OVERRIDE).unwrap();
+    fs::write(dir.path().join("compose.yaml"), OTHER).unwrap();
+    dir
+}
+
+fn cargotecture(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_cargotecture"));
+    command
+        .current_dir(dir)
+        .env_remove("COMPOSE_FILE")
+        .env_remove("COMPOSE_PATH_SEPARATOR")
+        .env_remove("COMPOSE_PROJECT_NAME")
+        .stdin(Stdio::null());
+    command
+}
+
+fn stdout(command: &mut Command) -> String {
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn compose_file_env_merges_files_in_order() {
+    let dir = fixture_dir();
+    let out = stdout(
+        cargotecture(dir.path())
+            .args(["compose", "--use-env"])
+            .env("COMPOSE_FILE", "base.yml:override.yml"),
+    );
+    assert!(out.contains("attribute image redefines image = \"web:2.0\";"));
+    assert!(out.contains("part db: Container {"));
+    assert!(out.contains("portNumber redefines portNumber = 80;"));
+    assert!(!out.contains("part other: Container {"));
+}
+
+#[test]
+fn compose_path_separator_is_respected() {
+    let dir = fixture_dir();
+    let out = stdout(
+        cargotecture(dir.path())
+            .args(["compose", "--use-env"])
+            .env("COMPOSE_FILE", "base.yml;override.yml")
+            .env("COMPOSE_PATH_SEPARATOR", ";")
+            .env("COMPOSE_PROJECT_NAME", "shop"),
+    );
+    assert!(out.starts_with("package shopModel {"));
+    assert!(out.contains("part db: Container {"));
+}
+
+#[test]
+fn explicit_files_take_precedence_over_env() {
+    let dir = fixture_dir();
+    let out = stdout(
+        cargotecture(dir.path())
+            .args(["compose", "--use-env", "base.yml"])
+            .env("COMPOSE_FILE", "override.yml"),
+    );
+    assert!(out.contains("attribute image redefines image = \"web:1.0\";"));
+    assert!(!out.contains("part db: Container {"));
+}
+
+#[test]
+fn default_file_used_without_env() {
+    let dir = fixture_dir();
+    let out = stdout(cargotecture(dir.path()).args(["compose", "--use-env"]));
+    assert!(out.contains("part other: Container {"));
+}
+
+#[test]
+fn env_ignored_without_use_env() {
+    let dir = fixture_dir();
+    let out = stdout(
+        cargotecture(dir.path())
+            .arg("compose")
+            .env("COMPOSE_FILE", "base.yml")
+            .stdin(fs::File::open(dir.path().join("override.yml")).unwrap()),
+    );
+    assert!(out.contains("part db: Container {"));
+    assert!(!out.contains("web:1.0"));
+}
+
+#[test]
+fn discovered_override_file_is_merged() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("docker-compose.yaml"), BASE).unwrap();
+    fs::write(dir.path().join("docker-compose.override.yaml"), OVERRIDE).unwrap();
+    let output = cargotecture(dir.path()).args(["compose", "--use-env"]).output().unwrap();
+    assert!(output.status.success());
+
+    let out = String::from_utf8(output.stdout).unwrap();
+    assert!(out.contains("attribute image redefines image = \"web:2.0\";"));
+    assert!(out.contains("part db: Container {"));
+    assert!(out.contains("portNumber redefines portNumber = 80;"));
+
+    let err = String::from_utf8(output.stderr).unwrap();
+    assert!(err.contains("Using compose files:"));
+    assert!(err.contains("docker-compose.override.yaml"));
+}
+
+#[test]
+fn piped_stdin_is_read_instead_of_discovered_files() {
+    let dir = fixture_dir();
+    let out = stdout(
+        cargotecture(dir.path())
+            .arg("compose")
+            .stdin(fs::File::open(dir.path().join("base.yml")).unwrap()),
+    );
+    assert!(out.contains("part web: Container {"));
+    assert!(!out.contains("part other: Container {"));
+}
+
+#[test]
+fn fmt_check_and_write() {
+    let dir = fixture_dir();
+    let output = cargotecture(dir.path()).args(["fmt", "--check", "base.yml"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "base.yml isn't formatted\n");
+
+    let printed = stdout(cargotecture(dir.path()).args(["fmt", "base.yml"]));
+    assert_eq!(fs::read_to_string(dir.path().join("base.yml")).unwrap(), BASE);
+    stdout(cargotecture(dir.path()).args(["fmt", "--write", "base.yml"]));
+    assert_eq!(fs::read_to_string(dir.path().join("base.yml")).unwrap(), printed);
+    assert!(printed.contains("    - protocol: tcp\n      published: 80\n      target: 80\n"));
+
+    let output = cargotecture(dir.path()).args(["fmt", "--check", "base.yml"]).output().unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+}
+
+#[test]
+fn validate_allow_and_deny_set_levels() {
+    let dir = fixture_dir();
+    fs::write(
+        dir.path().join("compose.invalid.yml"),
+        "services:\n  web:\n    image: nginx\n    depends_on: [db]\n    restart: sometimes\n  db:\n    image: postgres\n    networks: [back]\nnetworks:\n  back: {}\n",
+    )
+    .unwrap();
+    let validate = |args: &[&str]| cargotecture(dir.path()).arg("validate").args(args).arg("compose.invalid.yml").output().unwrap();
+
+    let output = validate(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("error[invalid-restart]: Invalid restart value 'sometimes' for service 'web'\n"), "{}", stderr);
+    assert!(stderr.contains("warning[no-network]: "), "{}", stderr);
+
+    // Allowing the error leaves the warning
+    let output = validate(&["--allow", "invalid-restart"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("warning[no-network]: "));
+
+    // Denying the warning fails again, and the last level given for a code wins
+    let output = validate(&["--allow", "invalid-*", "--deny", "no-network"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("error[no-network]: "));
+    let output = validate(&["--deny", "no-network", "--allow", "invalid-*", "--allow", "no-*"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+
+    let output = validate(&["--deny", "bogus-*"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn anonymize_writes_a_map_inverting_the_model() {
+    let dir = fixture_dir();
+    fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/elk.yml"), dir.path().join("elk.yml")).unwrap();
+    let anonymize = |seed: &str| {
+        stdout(cargotecture(dir.path()).args(["compose", "elk.yml", "--anonymize", "--anonymize-map", "map.json", "--anonymize-seed", seed]))
+    };
+    let anonymized = anonymize("vendor");
+    for original in ["elasticsearch", "logstash", "kibana", "filebeat", "nginx", "elk"] {
+        assert!(!anonymized.contains(original), "{} in {}", original, anonymized);
+    }
+    assert!(anonymized.contains("portNumber redefines portNumber = 5601;"));
+    assert_eq!(anonymize("vendor"), anonymized);
+
+    let map: cargotecture::anonymize::AnonymizationMap = serde_json::from_str(&fs::read_to_string(dir.path().join("map.json")).unwrap()).unwrap();
+    assert_eq!(map.seed, "vendor");
+    // Names which were basic become quoted, as pseudonyms aren't
+    let restored = map.deanonymize(&anonymized);
+    for part in ["part 'elasticsearch': Container", "part 'elasticNetwork': Network", "connect 'logstash' to 'elasticsearch'", "\"elasticsearch:7.16.1\""] {
+        assert!(restored.contains(part), "{} in {}", part, restored);
+    }
+
+    // Nothing original is left in any format, including labels and runtime tuning kept as
+    // written. Running privileged gives the security report something to say.
+    fs::write(
+        dir.path().join("corp.yml"),
+        "services:\n  kibana:\n    privileged: true\n    labels:\n      com.corp.team: payments\n    blkio_config:\n      device_read_bps:\n        - path: /dev/corp-secret-disk\n          rate: 12mb\n",
+    )
+    .unwrap();
+    let formats = [
+        "sysml", "markdown", "start-order", "start-order-json", "csv", "security", "security-report", "security-report-json", "env-report", "env-report-json",
+    ];
+    for format in formats {
+        let anonymized = stdout(cargotecture(dir.path()).args(["compose", "elk.yml", "corp.yml", "--anonymize", "--format", format]));
+        assert!(!anonymized.is_empty(), "{}", format);
+        for original in ["elasticsearch", "logstash", "kibana", "filebeat", "nginx", "elk", "corp", "payments", "12mb"] {
+            assert!(!anonymized.contains(original), "{} in --format {}: {}", original, format, anonymized);
+        }
+    }
+}
+
+#[test]
+fn env_report_interpolates_from_the_dotenv_file() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("compose.yaml"), "services:\n  web:\n    image: web\n    env_file: web.env\n    environment:\n      LOG_LEVEL: ${LOG_LEVEL:-info}\n").unwrap();
+    fs::write(dir.path().join("web.env"), "LOG_LEVEL=warn\nAPI_KEY=abc123\n").unwrap();
+    fs::write(dir.path().join(".env"), "LOG_LEVEL=debug\n").unwrap();
+    let report = stdout(cargotecture(dir.path()).env_remove("LOG_LEVEL").args(["compose", "compose.yaml", "--format", "env-report"]));
+    assert_eq!(report, "web\n  API_KEY=***  (web.env:2)\n! LOG_LEVEL=debug  (inline at compose.yaml:6; interpolated LOG_LEVEL; overrides 'warn' from web.env:1)\n");
+
+    let json: serde_json::Value = serde_json::from_str(&stdout(cargotecture(dir.path()).env_remove("LOG_LEVEL").args(["compose", "compose.yaml", "--format", "env-report-json"]))).unwrap();
+    assert_eq!(json["services"][0]["variables"][1]["value"], "debug");
+}
+
+#[test]
+fn sysml_style_flags_lay_out_the_model() {
+    let dir = fixture_dir();
+    let sysml = stdout(cargotecture(dir.path()).args(["compose", "base.yml", "--indent", "tab", "--brace-style", "next-line", "--no-provenance"]));
+    assert!(sysml.starts_with("package baseModel\n{\n\t/* SysML v2.0 generated by cargotecture */\n"), "{}", sysml);
+    assert!(sysml.contains("\n\tpart def Container\n\t{\n\t\tattribute image: String;\n"), "{}", sysml);
+    let output = cargotecture(dir.path()).args(["compose", "base.yml", "--indent", "3x"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn sysml_style_flags_apply_to_pod_reconcile_and_scan() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("compose.yaml"), "services:\n  web:\n    image: nginx:1.25\n").unwrap();
+    fs::write(
+        dir.path().join("deploy.yaml"),
+        "kind: Deployment\nmetadata:\n  name: web\nspec:\n  template:\n    spec:\n      containers:\n        - {name: nginx, image: nginx:1.25}\n",
+    )
+    .unwrap();
+
+    let pod = stdout(cargotecture(dir.path()).args(["pod", "deploy.yaml", "--indent", "2", "--reproducible"]));
+    assert!(pod.contains(" * input: deploy.yaml sha256:"), "{}", pod);
+    assert!(pod.contains(" * option: style=indent=2,max-width=none,braces=same-line\n"), "{}", pod);
+    assert!(pod.contains("part def webPod {\n  part nginx: Container[1] {\n"), "{}", pod);
+
+    let reconciled = stdout(cargotecture(dir.path()).args(["reconcile", "compose.yaml", "deploy.yaml", "--indent", "tab", "--brace-style", "next-line", "--reproducible"]));
+    assert!(reconciled.starts_with("package composeReconciled\n{\n\t/* Provenance\n"), "{}", reconciled);
+    assert!(reconciled.contains("\t * option: style=indent=tab,max-width=none,braces=next-line\n"), "{}", reconciled);
+
+    let scanned = stdout(cargotecture(dir.path()).args(["scan", ".", "--indent", "2", "--reproducible"]));
+    assert!(scanned.contains("\n  /* Provenance\n"), "{}", scanned);
+    assert!(scanned.contains("   * option: style=indent=2,max-width=none,braces=same-line\n"), "{}", scanned);
+}
+
+#[test]
+fn follow_builds_checks_published_ports_against_expose() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("compose.yaml"), "services:\n  web:\n    build: .\n    ports: [\"8000:8000\"]\n").unwrap();
+    fs::write(dir.path().join("Dockerfile"), "FROM nginx\nEXPOSE 8080\n").unwrap();
+    let output = cargotecture(dir.path()).args(["compose", "compose.yaml", "--follow-builds"]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "warning[unexposed-port]: Service 'web' publishes port 8000 which is neither EXPOSEd by its Dockerfile nor listed in expose (8080); check for a typo\n"
+    );
+
+    // Without the Dockerfile there's nothing to check the port against
+    let output = cargotecture(dir.path()).args(["compose", "compose.yaml"]).output().unwrap();
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn profile_activates_required_dependencies() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("compose.yaml"),
+        "services:\n  web:\n    image: nginx\n    depends_on: [api]\n  api:\n    image: api\n    profiles: [backend]\n  debug:\n    image: busybox\n    profiles: [dev]\n",
+    )
+    .unwrap();
+    let output = cargotecture(dir.path()).args(["compose", "compose.yaml", "--profile", "dev", "--format", "start-order"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Wave 1:\n  api (no healthcheck)\n  debug (no healthcheck)\nWave 2:\n  web (no healthcheck)\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("note[profile-auto-activated]: Service 'api' (profiles backend) is activated as 'web' requires it\n"));
+
+    let output = cargotecture(dir.path()).args(["compose", "compose.yaml", "--profile", "dev", "--no-auto-activate", "--format", "start-order"]).output().unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Service 'web' requires 'api', which is only in the inactive profiles backend"));
+
+    // Validating with the profile reports the activation, which can be denied
+    let output = cargotecture(dir.path()).args(["validate", "--profile", "dev", "-v", "compose.yaml"]).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("note[profile-auto-activated]: Service 'api' (profiles backend) is activated as 'web' requires it\n  --> defined in compose.yaml:7\n"), "{}", stderr);
+    let output = cargotecture(dir.path()).args(["validate", "--profile", "dev", "--deny", "profile-auto-activated", "compose.yaml"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn reconcile_maps_services_to_deployments() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("compose.yaml"), "services:\n  web:\n    image: nginx:1.25\n  api:\n    image: shop/api:2\n").unwrap();
+    fs::write(
+        dir.path().join("deploy.yaml"),
+        "kind: Deployment\nmetadata:\n  name: storefront\nspec:\n  template:\n    metadata:\n      labels: {app: storefront}\n    spec:\n      containers:\n        - {name: nginx, image: nginx:1.25}\n---\nkind: StatefulSet\nmetadata:\n  name: backend\nspec:\n  template:\n    spec:\n      containers:\n        - {name: api, image: shop/api:3}\n",
+    )
+    .unwrap();
+    let output = cargotecture(dir.path()).args(["reconcile", "compose.yaml", "deploy.yaml", "--map", "api=backend"]).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "api = backend: image shop/api:2 in compose, shop/api:3 in kubernetes\nweb = storefront: no divergences\n"
+    );
+    assert!(String::from_utf8(output.stdout).unwrap().contains(r#"attribute name redefines name = "backend";"#));
+}
@@ -0,0 +1,55 @@
+use std::{path::Path, process::Command};
+
+/// The features which can be turned on independently, besides `dockerfile`, which is
+/// always built, and `cli`, which turns on all of them
+const FEATURES: &[&str] = &["compose", "pod", "sysml", "diagrams"];
+
+fn cargo(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO"));
+    command.current_dir(env!("CARGO_MANIFEST_DIR")).args(args);
+    command
+}
+
+#[test]
+fn dockerfile_feature_depends_on_neither_yaml_nor_clap() {
+    let output = cargo(&["tree", "--offline", "--edges", "normal", "--prefix", "none", "--no-default-features", "--features", "dockerfile"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let tree = String::from_utf8(output.stdout).unwrap();
+    for dependency in ["serde_yaml ", "clap "] {
+        assert!(!tree.lines().any(|line| line.starts_with(dependency)), "{}", tree);
+    }
+}
+
+/// Check the library and its tests build with every combination of features. This builds
+/// the crate many times, so it's left to CI: `cargo test --test features -- --ignored`
+#[test]
+#[ignore]
+fn every_feature_combination_builds() {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("features");
+    for combination in 0..1 << FEATURES.len() {
+        let features: Vec<&str> = FEATURES.iter().enumerate().filter(|(index, _)| combination & 1 << index != 0).map(|(_, feature)| *feature).collect();
+        let features = features.join(",");
+        let status = cargo(&["check", "--lib", "--tests", "--no-default-features", "--features", &features])
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "failed to build with features [{}]", features);
+    }
+}
+
+/// Check the library builds for WebAssembly with everything but the CLI, as embedding it
+/// through `process::process` needs. This needs the `wasm32-unknown-unknown` target
+/// installed, so is left to CI: `cargo test --test features -- --ignored`
+#[test]
+#[ignore]
+fn library_builds_for_wasm() {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("wasm");
+    let features = FEATURES.join(",");
+    let status = cargo(&["check", "--lib", "--target", "wasm32-unknown-unknown", "--no-default-features", "--features", &features])
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed to build for wasm32-unknown-unknown");
+}
@@ -0,0 +1,155 @@
+//! Golden tests of the generated SysML for the ELK fixture in each grouping mode.
+//! Run with `UPDATE_GOLDEN=1` to regenerate the expected output after an intended change.
+
+use cargotecture::{
+    gen_sysml::{check_sysml, sysml_compose_package, GroupBy, SysmlOptions},
+    parse_compose::{parse_composefile, Compose},
+    sysml_ast::{BraceStyle, Indent, RenderStyle},
+};
+use std::{fs, path::PathBuf, process::{Command, Output, Stdio}};
+
+fn tests_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn elk() -> Compose {
+    let fixture = fs::File::open(tests_dir().join("fixtures/elk.yml")).unwrap();
+    parse_composefile(Box::new(fixture)).unwrap().value
+}
+
+fn check_golden_text(golden: &str, actual: &str) {
+    let path = tests_dir().join("golden").join(golden);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+    }
+    let expected = fs::read_to_string(&path).unwrap();
+    assert_eq!(actual, expected, "generated output differs from {}", path.display());
+}
+
+fn check_golden(golden: &str, options: &SysmlOptions) {
+    check_golden_text(golden, &sysml_compose_package(&elk(), "elk", options));
+}
+
+#[test]
+fn golden_group_by_none() {
+    check_golden("elk_group_by_none.sysml", &SysmlOptions::default());
+}
+
+#[test]
+fn golden_group_by_network() {
+    check_golden("elk_group_by_network.sysml", &SysmlOptions { group_by: GroupBy::Network, ..Default::default() });
+}
+
+#[test]
+fn golden_group_by_dependency() {
+    check_golden("elk_group_by_dependency.sysml", &SysmlOptions { group_by: GroupBy::Dependency, ..Default::default() });
+}
+
+#[test]
+fn golden_ports_sorted() {
+    check_golden("elk_ports_sorted.sysml", &SysmlOptions { ports_sorted: true, ..Default::default() });
+}
+
+/// Check the output in a style against its golden file, and that it's as well formed as
+/// in the default style
+fn check_golden_style(golden: &str, style: RenderStyle) {
+    let options = SysmlOptions { include_environment: true, style, ..Default::default() };
+    let sysml = sysml_compose_package(&elk(), "elk", &options);
+    assert_eq!(check_sysml(&sysml), vec![]);
+    check_golden_text(golden, &sysml);
+}
+
+#[test]
+fn golden_style_two_space_indent() {
+    check_golden_style("elk_style_indent_2.sysml", RenderStyle { indent: Indent::Spaces(2), ..Default::default() });
+}
+
+#[test]
+fn golden_style_tab_indent() {
+    check_golden_style("elk_style_indent_tab.sysml", RenderStyle { indent: Indent::Tab, ..Default::default() });
+}
+
+#[test]
+fn golden_style_next_line_braces() {
+    check_golden_style("elk_style_next_line_braces.sysml", RenderStyle { braces: BraceStyle::NextLine, ..Default::default() });
+}
+
+#[test]
+fn golden_style_max_width() {
+    let style = RenderStyle { max_width: Some(60), ..Default::default() };
+    check_golden_style("elk_style_max_width_60.sysml", style);
+    let sysml = sysml_compose_package(&elk(), "elk", &SysmlOptions { include_environment: true, style, ..Default::default() });
+    assert!(sysml.contains(" +\n"));
+}
+
+#[test]
+fn golden_per_service_packages() {
+    let options = SysmlOptions { per_service_packages: true, ..Default::default() };
+    check_golden("elk_per_service_packages.sysml", &options);
+    let sysml = sysml_compose_package(&elk(), "elk", &options);
+    assert!(sysml.contains("\n    package elasticsearch {\n        import elkModel::*;\n"));
+    assert!(sysml.contains("\n    package logstash {\n        import elkModel::*;\n"));
+}
+
+#[test]
+fn golden_security_report_table() {
+    let output = Command::new(env!("CARGO_BIN_EXE_cargotecture"))
+        .args(["compose", "--format", "security-report"])
+        .arg(tests_dir().join("fixtures/elk.yml"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Output to a pipe isn't truncated
+    check_golden_text("elk_security_report.txt", &String::from_utf8(output.stdout).unwrap());
+
+    let narrow = elk().security_posture(&Default::default()).to_table().max_width(Some(80)).render();
+    check_golden_text("elk_security_report_80.txt", &narrow);
+}
+
+#[test]
+fn grouping_never_changes_connections() {
+    let compose = elk();
+    // Compare the connections with the package qualification of their ends removed
+    let connections = |group_by| {
+        let sysml = sysml_compose_package(&compose, "elk", &SysmlOptions { group_by, ..Default::default() });
+        sysml
+            .lines()
+            .map(str::trim)
+            .filter(|line| (line.starts_with("connection ") && !line.starts_with("connection def ")) || line.starts_with("assert "))
+            .map(|line| line.replace("elasticServices::", "").replace("defaultServices::", ""))
+            .collect::<Vec<_>>()
+    };
+    let flat = connections(GroupBy::None);
+    assert_eq!(flat.iter().filter(|line| line.starts_with("connection ")).count(), 10);
+    assert_eq!(connections(GroupBy::Network), flat);
+    assert_eq!(connections(GroupBy::Dependency), flat);
+}
+
+fn check(fixture: &std::path::Path, golden: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cargotecture"))
+        .arg("compose")
+        .arg("--check")
+        .arg(tests_dir().join("golden").join(golden))
+        .arg(fixture)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn check_reports_diff_and_changes() {
+    let output = check(&tests_dir().join("fixtures/elk.yml"), "elk_group_by_none.sysml");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty());
+
+    let dir = tempfile::tempdir().unwrap();
+    let fixture = dir.path().join("elk.yml");
+    let edited = fs::read_to_string(tests_dir().join("fixtures/elk.yml")).unwrap().replace("kibana:7.16.1", "kibana:7.17.0");
+    fs::write(&fixture, edited).unwrap();
+    let output = check(&fixture, "elk_group_by_none.sysml");
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("-            attribute image redefines image = \"kibana:7.16.1\";\n+            attribute image redefines image = \"kibana:7.17.0\";\n"), "{}", stdout);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("service kibana: image changed kibana:7.16.1 \u{2192} kibana:7.17.0\n"), "{}", stderr);
+}
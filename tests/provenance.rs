@@ -0,0 +1,45 @@
+//! Provenance recorded in generated models by the command line tool
+
+use std::{path::PathBuf, process::{Command, Stdio}};
+
+/// The SHA-256 of tests/fixtures/elk.yml, as computed by `sha256sum`
+const ELK_SHA256: &str = "38ad117fa869c564e016bc8587b5d6497a756de109e82da786f1609d17f70633";
+
+fn compose_elk(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_cargotecture"))
+        .current_dir(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests"))
+        .arg("compose")
+        .args(args)
+        .arg("fixtures/elk.yml")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn provenance_records_input_hash_and_options() {
+    let sysml = compose_elk(&["--group-by", "network"]);
+    assert!(sysml.starts_with("package elkModel {\n    /* Provenance\n     * tool: cargotecture "));
+    assert!(sysml.contains(&format!("     * input: fixtures/elk.yml sha256:{}\n", ELK_SHA256)));
+    assert!(sysml.contains("     * option: group_by=network\n"));
+    assert!(sysml.contains("     * generated: "));
+}
+
+#[test]
+fn reproducible_output_is_byte_stable() {
+    let first = compose_elk(&["--reproducible"]);
+    assert!(!first.contains("generated: "));
+    assert!(first.contains(ELK_SHA256));
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert_eq!(compose_elk(&["--reproducible"]), first);
+}
+
+#[test]
+fn no_provenance_drops_it() {
+    let sysml = compose_elk(&["--no-provenance"]);
+    assert!(!sysml.contains("Provenance"));
+    let golden = std::fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/elk_group_by_none.sysml")).unwrap();
+    assert_eq!(sysml, golden);
+}
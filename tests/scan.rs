@@ -0,0 +1,119 @@
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+fn fixture_tree() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for (path, content) in [
+        ("api/Dockerfile", "FROM rust:1.75\nEXPOSE 8080\n"),
+        ("api/compose.yaml", "services:\n  api:\n    image: api:1.0\n"),
+        ("web/Dockerfile", "FROM nginx:1.25\nEXPOSE 80\n"),
+        ("web/compose.prod.yaml", "services:\n  web:\n    image: web:1.0\n"),
+        ("Web/Containerfile", "FROM alpine\n"),
+        (".git/Dockerfile", "FROM ignored\n"),
+    ] {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+fn scan(dir: &Path, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_cargotecture"))
+        .current_dir(dir)
+        .arg("scan")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+#[test]
+fn split_output_index_imports_resolve_to_emitted_files() {
+    let dir = fixture_tree();
+    scan(dir.path(), &["--split-output", "model"]);
+
+    let out = dir.path().join("model");
+    let index = fs::read_to_string(out.join("index.sysml")).unwrap();
+    let imports: Vec<(&str, &str)> = index
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("import "))
+        .map(|import| {
+            let (package, file) = import.split_once("::*; // ").unwrap();
+            (package, file)
+        })
+        .collect();
+    assert_eq!(imports.len(), 5);
+
+    for (package, file) in &imports {
+        let sysml = fs::read_to_string(out.join(file)).unwrap();
+        assert!(sysml.starts_with(&format!("package {} {{", package)), "{} doesn't define {}", file, package);
+    }
+    // Web/ and web/ differ only in case, so must not be written to the same file
+    let mut files: Vec<String> = imports.iter().map(|(_, file)| file.to_lowercase()).collect();
+    files.dedup();
+    assert_eq!(files.len(), imports.len());
+    assert_eq!(fs::read_dir(&out).unwrap().count(), imports.len() + 1);
+}
+
+#[test]
+fn size_warning_names_oversized_files() {
+    let dir = fixture_tree();
+    let output = scan(dir.path(), &["--split-output", "model", "--size-warning", "100"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("apiModel.sysml is"), "{}", stderr);
+    assert!(stderr.contains("larger than the 100 byte threshold"));
+
+    let output = scan(dir.path(), &[]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("package apiModel {"));
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn cache_skips_unchanged_files() {
+    let dir = fixture_tree();
+    let cache = dir.path().join("cache");
+    let cache_arg = cache.to_str().unwrap();
+    let first = scan(dir.path(), &["--cache-dir", cache_arg, "--reproducible"]);
+    assert_eq!(String::from_utf8_lossy(&first.stderr), "cache: 0 unchanged, 5 parsed\n");
+
+    let second = scan(dir.path(), &["--cache-dir", cache_arg, "--reproducible"]);
+    assert_eq!(String::from_utf8_lossy(&second.stderr), "cache: 5 unchanged, 0 parsed\n");
+    assert_eq!(second.stdout, first.stdout);
+
+    fs::write(dir.path().join("api/Dockerfile"), "FROM rust:1.76\nEXPOSE 8080\n").unwrap();
+    let third = scan(dir.path(), &["--cache-dir", cache_arg, "--reproducible"]);
+    assert_eq!(String::from_utf8_lossy(&third.stderr), "cache: 4 unchanged, 1 parsed\n");
+    assert!(String::from_utf8_lossy(&third.stdout).contains("rust:1.76"));
+
+    let refreshed = scan(dir.path(), &["--cache-dir", cache_arg, "--reproducible", "--no-cache"]);
+    assert_eq!(String::from_utf8_lossy(&refreshed.stderr), "cache: 0 unchanged, 5 parsed\n");
+    assert_eq!(refreshed.stdout, third.stdout);
+}
+
+#[test]
+fn cache_replays_diagnostics() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("compose.yaml"), "services:\n  web:\n    image: nginx:1.25\n    depends_on: [db]\n").unwrap();
+    let cache = dir.path().join(".cache");
+    let cache_arg = cache.to_str().unwrap();
+    for hits in [0, 1] {
+        let output = scan(dir.path(), &["--cache-dir", cache_arg]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("error[unknown-dependency]: Referenced service 'db' in depends_on not found for service 'web'"), "{}", stderr);
+        assert!(stderr.contains(&format!("cache: {} unchanged", hits)), "{}", stderr);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargotecture"))
+        .args(["cache", "clear", "--cache-dir", cache_arg])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(fs::read_dir(&cache).unwrap().count(), 0);
+}